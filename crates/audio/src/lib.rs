@@ -1,8 +1,12 @@
 mod consumer;
+mod pacing;
 mod producer;
+mod ring;
 
 pub use consumer::AudioConsumer;
+pub use pacing::AudioBufferSizer;
 pub use producer::AudioStreamProducer;
+pub use ring::{AudioRingWriter, AudioRingReader, audio_ring};
 
 #[derive(Clone, PartialEq)]
 pub struct AudioStreamProperties {
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of recent frame intervals considered when sizing the buffer
+const FRAME_HISTORY_LENGTH: usize = 120;
+
+/// Extra buffered time added for each underrun
+const UNDERRUN_HEADROOM: Duration = Duration::from_millis(5);
+
+/// Rate at which headroom added by underruns is given back, per frame without one
+const HEADROOM_DECAY_PER_FRAME: Duration = Duration::from_micros(20);
+
+/// Shortest buffer recommended, regardless of how fast frames are
+const MIN_BUFFER_TIME: Duration = Duration::from_millis(10);
+
+/// Longest buffer recommended, regardless of how slow frames are
+const MAX_BUFFER_TIME: Duration = Duration::from_millis(250);
+
+/// AudioBufferSizer struct
+/// Recommends how long an audio ring should be, given how often the render loop tops it up.
+/// The ring must last through the longest recent gap between frames and the frame spent
+/// refilling it, plus headroom that grows on underruns and drains away while playback is clean.
+/// Growth is applied straight away; shrinking waits until the recommendation is well below the
+/// current length, so that the size does not flap. Each frame, feed it the frame's interval and
+/// the ring writer's underruns, then apply any new length:
+///
+/// ```ignore
+/// sizer.note_frame(frame_interval);
+/// sizer.note_underruns(writer.take_underruns());
+/// if let Some(frames) = sizer.adjust() {
+///     writer.set_capacity_frames(frames);
+/// }
+/// ```
+pub struct AudioBufferSizer {
+    sample_rate: u32,
+    recent_intervals: VecDeque<Duration>,
+    headroom: Duration,
+    current_frames: usize
+}
+
+impl AudioBufferSizer {
+
+    /// Create a new instance for a stream of the given sample rate, starting from the ring's
+    /// current length in frames
+    pub fn new(sample_rate: u32, current_frames: usize) -> Self {
+        Self {
+            sample_rate,
+            recent_intervals: VecDeque::with_capacity(FRAME_HISTORY_LENGTH),
+            headroom: Duration::ZERO,
+            current_frames
+        }
+    }
+
+    /// Note the time between the last two frames of the render loop
+    pub fn note_frame(&mut self, frame_interval: Duration) {
+        if self.recent_intervals.len() == FRAME_HISTORY_LENGTH {
+            self.recent_intervals.pop_front();
+        }
+        self.recent_intervals.push_back(frame_interval);
+        self.headroom = self.headroom.saturating_sub(HEADROOM_DECAY_PER_FRAME);
+    }
+
+    /// Note underruns reported by the ring since the last frame
+    pub fn note_underruns(&mut self, underruns: u32) {
+        self.headroom = (self.headroom + UNDERRUN_HEADROOM * underruns).min(MAX_BUFFER_TIME);
+    }
+
+    /// Get the buffered time currently recommended
+    pub fn recommended_time(&self) -> Duration {
+        let longest_interval = self.recent_intervals.iter().max().copied()
+            .unwrap_or(Duration::ZERO);
+        (longest_interval * 2 + self.headroom).clamp(MIN_BUFFER_TIME, MAX_BUFFER_TIME)
+    }
+
+    /// Get the ring length currently recommended, in frames
+    pub fn recommended_frames(&self) -> usize {
+        let nanos = self.recommended_time().as_nanos() * self.sample_rate as u128;
+        nanos.div_ceil(1_000_000_000) as usize
+    }
+
+    pub fn current_frames(&self) -> usize {
+        self.current_frames
+    }
+
+    /// Decide whether the ring should change length, returning the new length in frames if so
+    pub fn adjust(&mut self) -> Option<usize> {
+        let recommended = self.recommended_frames();
+        let should_grow = recommended > self.current_frames;
+        let should_shrink = recommended < self.current_frames * 3 / 4;
+        if !should_grow && !should_shrink {
+            return None;
+        }
+        self.current_frames = recommended;
+        Some(recommended)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{AudioStreamProducer, AudioStreamProperties};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// RingState struct
+/// The queued samples and bookkeeping shared between the two ends of a ring
+struct RingState<S> {
+    samples: VecDeque<S>,
+    capacity_frames: usize,
+    underruns: u32
+}
+
+/// AudioRingWriter struct
+/// The end of an audio ring used by the thread producing audio, such as the game loop, which
+/// tops it up each frame. Cloning gives another handle to the same ring.
+#[derive(Clone)]
+pub struct AudioRingWriter<S> {
+    state: Arc<Mutex<RingState<S>>>,
+    channels: usize
+}
+
+/// AudioRingReader struct
+/// The end of an audio ring given to an AudioConsumer, which drains it from the audio callback.
+/// When too few samples are queued, the rest of the buffer is filled with silence and an
+/// underrun is counted.
+pub struct AudioRingReader<S> {
+    state: Arc<Mutex<RingState<S>>>,
+    properties: AudioStreamProperties
+}
+
+/// Create an audio ring holding up to the given number of frames, returning its two ends
+pub fn audio_ring<S>(
+    properties: AudioStreamProperties,
+    capacity_frames: usize
+) -> (AudioRingWriter<S>, AudioRingReader<S>) {
+    let channels = properties.channels as usize;
+    let state = Arc::new(Mutex::new(RingState {
+        samples: VecDeque::with_capacity(capacity_frames * channels),
+        capacity_frames,
+        underruns: 0
+    }));
+    let writer = AudioRingWriter { state: state.clone(), channels };
+    let reader = AudioRingReader { state, properties };
+    (writer, reader)
+}
+
+impl<S> AudioRingWriter<S> where S: Copy {
+
+    /// Queue interleaved samples, as many whole frames as fit; returns the number of samples
+    /// accepted
+    pub fn push(&self, samples: &[S]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let free_samples = state.capacity_frames * self.channels - state.samples.len();
+        let accepted = free_samples.min(samples.len()) / self.channels * self.channels;
+        state.samples.extend(&samples[..accepted]);
+        accepted
+    }
+
+    /// Number of frames that can be queued right now
+    pub fn free_frames(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.capacity_frames - state.samples.len() / self.channels
+    }
+
+    /// Number of frames queued and not yet played
+    pub fn queued_frames(&self) -> usize {
+        self.state.lock().unwrap().samples.len() / self.channels
+    }
+
+    pub fn capacity_frames(&self) -> usize {
+        self.state.lock().unwrap().capacity_frames
+    }
+
+    /// Change how many frames the ring can hold, which bounds the latency of queued audio.
+    /// When shrinking, the oldest queued frames beyond the new capacity are dropped.
+    pub fn set_capacity_frames(&self, capacity_frames: usize) {
+        let mut state = self.state.lock().unwrap();
+        let max_samples = capacity_frames * self.channels;
+        if state.samples.len() > max_samples {
+            let excess = state.samples.len() - max_samples;
+            state.samples.drain(..excess);
+        }
+        state.capacity_frames = capacity_frames;
+    }
+
+    /// Get the number of underruns since this was last called, and reset the count
+    pub fn take_underruns(&self) -> u32 {
+        std::mem::take(&mut self.state.lock().unwrap().underruns)
+    }
+}
+
+impl<S> AudioStreamProducer for AudioRingReader<S> where S: Copy + Default {
+    type Sample = S;
+
+    unsafe fn fill_buffer(&mut self, data: &mut [S], _size_bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let available = state.samples.len().min(data.len());
+        for (dst, src) in data.iter_mut().zip(state.samples.drain(..available)) {
+            *dst = src;
+        }
+        if available < data.len() {
+            data[available..].fill(S::default());
+            state.underruns += 1;
+        }
+    }
+
+    fn get_properties(&self) -> AudioStreamProperties {
+        self.properties.clone()
+    }
+}
@@ -1,8 +1,9 @@
 
 use crate::{
     consumer::AudioConsumer,
-    AudioStreamProducer, AudioStreamProperties, AudioSampleFormat
+    AudioStreamProducer, AudioStreamProperties, AudioSampleFormat, AudioBufferSizer, audio_ring
 };
+use std::time::Duration;
 
 const FRAMES_PER_VALUE: usize = 64;
 const MAX_VOLUME: i16 = 0x2000;
@@ -79,3 +80,49 @@ fn plays_back_for_five_seconds() {
     std::thread::sleep(std::time::Duration::from_millis(5000));
     consumer.stop();
 }
+
+#[test]
+fn ring_plays_silence_and_counts_underruns_when_starved() {
+    let properties = NoiseTest::new().get_properties();
+    let (writer, mut reader) = audio_ring::<i16>(properties, 4);
+    assert_eq!(writer.push(&[1, 1, 2, 2, 3, 3, 4, 4, 5, 5]), 8);
+    assert_eq!(writer.free_frames(), 0);
+
+    let mut data = [9i16; 12];
+    unsafe { reader.fill_buffer(&mut data, 12); }
+    assert_eq!(data, [1, 1, 2, 2, 3, 3, 4, 4, 0, 0, 0, 0]);
+    assert_eq!(writer.take_underruns(), 1);
+    assert_eq!(writer.take_underruns(), 0);
+
+    // Shrinking drops the oldest frames
+    writer.push(&[6, 6, 7, 7, 8, 8]);
+    writer.set_capacity_frames(1);
+    assert_eq!(writer.queued_frames(), 1);
+    unsafe { reader.fill_buffer(&mut data[..2], 2); }
+    assert_eq!(data[..2], [8, 8]);
+}
+
+#[test]
+fn buffer_sizing_follows_frame_pacing_and_underruns() {
+    let mut sizer = AudioBufferSizer::new(48000, 480);
+    for _ in 0..10 {
+        sizer.note_frame(Duration::from_millis(16));
+    }
+
+    // Covers two of the longest frames
+    assert_eq!(sizer.adjust(), Some(1536));
+    assert_eq!(sizer.adjust(), None);
+
+    // Underruns add headroom straight away
+    sizer.note_underruns(2);
+    assert_eq!(sizer.adjust(), Some(2016));
+
+    // A single slow frame grows the buffer, which only shrinks again once it
+    // has left the history and the headroom has drained
+    sizer.note_frame(Duration::from_millis(50));
+    assert!(sizer.adjust().unwrap() > 4800);
+    for _ in 0..600 {
+        sizer.note_frame(Duration::from_millis(16));
+    }
+    assert_eq!(sizer.adjust(), Some(1536));
+}
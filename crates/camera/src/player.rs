@@ -12,6 +12,7 @@ pub struct PlayerCamera {
     position_x: f32,
     position_y: f32,
     position_z: f32,
+    reversed_z: bool,
     perspective_projection: Matrix4<f32>
 }
 
@@ -31,30 +32,65 @@ impl PlayerCamera {
             position_x: x,
             position_y: y,
             position_z: z,
+            reversed_z: false,
             perspective_projection: Self::make_vulkan_perspective_matrix(
                 aspect_ratio,
                 Self::NEAR_PLANE,
-                Self::FAR_PLANE)
+                Self::FAR_PLANE,
+                false)
         }
     }
 
+    /// Choose whether the projection maps the near plane to depth 1.0 and the far plane to 0.0,
+    /// for rendering with reversed-Z depth, rather than the other way around
+    pub fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.reversed_z = reversed_z;
+        self.perspective_projection = Self::make_vulkan_perspective_matrix(
+            1.0,
+            Self::NEAR_PLANE,
+            Self::FAR_PLANE,
+            reversed_z);
+    }
+
+    /// Query whether the projection is for reversed-Z depth
+    pub fn is_reversed_z(&self) -> bool {
+        self.reversed_z
+    }
+
     /// Creates a projection matrix suitable for Vulkan. Note that OpenGL, DirectX, etc may need
-    /// alternate implementations due to differing up/down coordinates or clip volumes.
+    /// alternate implementations due to differing up/down coordinates or clip volumes. With
+    /// reversed Z, depth runs from 1.0 at the near plane to 0.0 at the far plane.
     fn make_vulkan_perspective_matrix(
         aspect_ratio: f32,
         near_plane: f32,
-        far_plane: f32
+        far_plane: f32,
+        reversed_z: bool
     ) -> Matrix4<f32> {
         let half_width = aspect_ratio;
         let half_height = 1.0;
+        let (depth_scale, depth_offset) = match reversed_z {
+            false => (
+                far_plane / (far_plane - near_plane),
+                (-far_plane * near_plane) / (far_plane - near_plane)
+            ),
+            true => (
+                -near_plane / (far_plane - near_plane),
+                (far_plane * near_plane) / (far_plane - near_plane)
+            )
+        };
         Matrix4::<f32>::new(
             near_plane / half_width, 0.0, 0.0, 0.0,
             0.0, near_plane / half_height, 0.0, 0.0,
-            0.0, 0.0, far_plane / (far_plane - near_plane), 1.0,
-            0.0, 0.0, (-far_plane * near_plane) / (far_plane - near_plane), 0.0
+            0.0, 0.0, depth_scale, 1.0,
+            0.0, 0.0, depth_offset, 0.0
         )
     }
 
+    /// Get the camera's position in world space
+    pub fn get_position(&self) -> Vector3<f32> {
+        Vector3::new(self.position_x, self.position_y, self.position_z)
+    }
+
     /// Get the view matrix, based on the camera's position and orientation
     pub fn get_view_matrix(&self) -> Matrix4<f32> {
         let rotation = Matrix4::from_angle_y(Rad(self.rotation));
@@ -69,6 +105,21 @@ impl PlayerCamera {
         self.perspective_projection
     }
 
+    /// Get the distances to the near and far planes of the perspective projection
+    pub fn get_depth_range(&self) -> (f32, f32) {
+        (Self::NEAR_PLANE, Self::FAR_PLANE)
+    }
+
+    /// Get a perspective projection matrix for rendering into an area of the given aspect ratio,
+    /// width over height, such as the oriented extent of a pre-rotated swapchain
+    pub fn get_projection_matrix_for_aspect(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        Self::make_vulkan_perspective_matrix(
+            aspect_ratio,
+            Self::NEAR_PLANE,
+            Self::FAR_PLANE,
+            self.reversed_z)
+    }
+
     /// Move the camera as per the up/down/left/right inputs in the supplied controller
     pub fn update(&mut self, time_step_millis: u64, dx: f32, dy: f32) {
 
@@ -0,0 +1,182 @@
+use error::EngineError;
+
+/// Color struct
+/// An RGBA color with components stored in linear space, which is what shaders and blending
+/// operate on. Conversions are provided to and from sRGB-encoded values, which is what hex codes
+/// and most color pickers produce.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32
+}
+
+impl Color {
+
+    pub const TRANSPARENT: Color = Color::new(0.0, 0.0, 0.0, 0.0);
+    pub const BLACK: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+    pub const RED: Color = Color::new(1.0, 0.0, 0.0, 1.0);
+    pub const GREEN: Color = Color::new(0.0, 1.0, 0.0, 1.0);
+    pub const BLUE: Color = Color::new(0.0, 0.0, 1.0, 1.0);
+
+    /// Create a new instance from linear components
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Create a new instance from sRGB-encoded components; alpha is always linear
+    pub fn from_srgb(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self {
+            r: srgb_to_linear(r),
+            g: srgb_to_linear(g),
+            b: srgb_to_linear(b),
+            a
+        }
+    }
+
+    /// Parse an sRGB hex code in the form RGB, RGBA, RRGGBB or RRGGBBAA, with or without a
+    /// leading '#'
+    pub fn from_hex(hex: &str) -> Result<Self, EngineError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let invalid = || EngineError::UserError(format!("Invalid hex color: {}", hex));
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let values: Vec<u8> = match digits.len() {
+            3 | 4 => digits.chars()
+                .map(|c| c.to_digit(16).unwrap() as u8 * 17)
+                .collect(),
+            6 | 8 => (0..digits.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+                .collect(),
+            _ => return Err(invalid())
+        };
+        let alpha = values.get(3).copied().unwrap_or(255);
+        Ok(Self::from_srgb(
+            values[0] as f32 / 255.0,
+            values[1] as f32 / 255.0,
+            values[2] as f32 / 255.0,
+            alpha as f32 / 255.0))
+    }
+
+    /// Create a new instance from hue (degrees), saturation and value, in sRGB space
+    pub fn from_hsv(hue_degrees: f32, saturation: f32, value: f32, alpha: f32) -> Self {
+        let hue = hue_degrees.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x)
+        };
+        let m = value - chroma;
+        Self::from_srgb(r + m, g + m, b + m, alpha)
+    }
+
+    /// Return the sRGB-encoded components; alpha is unchanged
+    pub fn to_srgb(&self) -> [f32; 4] {
+        [linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), self.a]
+    }
+
+    /// Return the sRGB hex code in the form #RRGGBBAA
+    pub fn to_hex(&self) -> String {
+        let [r, g, b, a] = self.to_srgb();
+        let to_byte = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        format!("#{:02X}{:02X}{:02X}{:02X}", to_byte(r), to_byte(g), to_byte(b), to_byte(a))
+    }
+
+    /// Return hue (degrees), saturation and value, in sRGB space
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let [r, g, b, _] = self.to_srgb();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { delta / max };
+        (hue, saturation, max)
+    }
+
+    /// Return the linear components as an array, as expected by shaders and clear values
+    pub fn to_array(&self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    /// Return a copy with the color components multiplied by alpha
+    pub fn premultiplied(&self) -> Self {
+        Self::new(self.r * self.a, self.g * self.a, self.b * self.a, self.a)
+    }
+
+    /// Reverse premultiplication; fully transparent colors become transparent black
+    pub fn unpremultiplied(&self) -> Self {
+        if self.a == 0.0 {
+            return Self::TRANSPARENT;
+        }
+        Self::new(self.r / self.a, self.g / self.a, self.b / self.a, self.a)
+    }
+
+    /// Return a copy with a different alpha
+    pub fn with_alpha(&self, a: f32) -> Self {
+        Self::new(self.r, self.g, self.b, a)
+    }
+
+    /// Linearly interpolate towards another color
+    pub fn lerp(&self, other: &Color, t: f32) -> Self {
+        Self::new(
+            self.r + (other.r - self.r) * t,
+            self.g + (other.g - self.g) * t,
+            self.b + (other.b - self.b) * t,
+            self.a + (other.a - self.a) * t)
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Self::BLACK
+    }
+}
+
+impl From<[f32; 4]> for Color {
+    fn from(value: [f32; 4]) -> Self {
+        Self::new(value[0], value[1], value[2], value[3])
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(value: Color) -> Self {
+        value.to_array()
+    }
+}
+
+/// Convert a single sRGB-encoded component to linear
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a single linear component to sRGB encoding
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,49 @@
+
+use crate::{Color, srgb_to_linear, linear_to_srgb};
+
+fn approx_eq(a: f32, b: f32) -> bool {
+    (a - b).abs() < 0.001
+}
+
+#[test]
+fn srgb_conversion_round_trips() {
+    for i in 0..=10 {
+        let value = i as f32 / 10.0;
+        assert!(approx_eq(linear_to_srgb(srgb_to_linear(value)), value));
+    }
+}
+
+#[test]
+fn hex_parses_in_all_forms() {
+    assert_eq!(Color::from_hex("#FFFFFF").unwrap(), Color::WHITE);
+    assert_eq!(Color::from_hex("fff").unwrap(), Color::WHITE);
+    assert_eq!(Color::from_hex("#00000000").unwrap(), Color::TRANSPARENT);
+    assert_eq!(Color::from_hex("#0F08").unwrap().to_hex(), "#00FF0088");
+    assert!(Color::from_hex("#12345").is_err());
+    assert!(Color::from_hex("#GG0000").is_err());
+}
+
+#[test]
+fn hex_round_trips() {
+    let color = Color::from_hex("#336699CC").unwrap();
+    assert_eq!(color.to_hex(), "#336699CC");
+}
+
+#[test]
+fn hsv_round_trips() {
+    let color = Color::from_hsv(210.0, 0.5, 0.8, 1.0);
+    let (h, s, v) = color.to_hsv();
+    assert!(approx_eq(h, 210.0));
+    assert!(approx_eq(s, 0.5));
+    assert!(approx_eq(v, 0.8));
+    assert_eq!(Color::from_hsv(120.0, 1.0, 1.0, 1.0), Color::GREEN);
+}
+
+#[test]
+fn premultiplication_reverses() {
+    let color = Color::new(0.8, 0.4, 0.2, 0.5);
+    let premultiplied = color.premultiplied();
+    assert_eq!(premultiplied.to_array(), [0.4, 0.2, 0.1, 0.5]);
+    assert_eq!(premultiplied.unpremultiplied(), color);
+    assert_eq!(Color::TRANSPARENT.unpremultiplied(), Color::TRANSPARENT);
+}
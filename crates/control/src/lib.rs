@@ -1,4 +0,0 @@
-mod io;
-mod user;
-
-pub use {io::ControlIo, user::UserControl};
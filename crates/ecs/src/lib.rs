@@ -1,10 +1,12 @@
 mod handle;
 mod manager;
+mod registry;
 mod resource_types;
 mod table;
 
 pub use handle::Handle;
 pub use manager::EcsManager;
+pub use registry::{ResourceRegistry, RegistryEntry};
 pub use table::{HandleTable, DynamicTable};
 
 pub mod resource {
@@ -1,16 +1,23 @@
 
-use crate::{Handle, DynamicTable, HandleTable, resource::Resource};
+use crate::{
+    Handle, DynamicTable, HandleTable, ResourceRegistry, RegistryEntry, resource::Resource
+};
 use error::EngineError;
+use std::collections::HashMap;
 
 pub struct EcsManager<L> {
-    tables: Vec<Box<dyn DynamicTable<L>>>
+    tables: Vec<Box<dyn DynamicTable<L>>>,
+    tracking_usage: bool,
+    namespaces: HashMap<u32, Vec<(usize, u32)>>
 }
 
 impl<L> EcsManager<L> {
 
     pub fn new() -> Self {
         Self {
-            tables: vec![]
+            tables: vec![],
+            tracking_usage: false,
+            namespaces: HashMap::new()
         }
     }
 
@@ -36,11 +43,44 @@ impl<L> EcsManager<L> {
         }
 
         let mut table = HandleTable::new();
+        if self.tracking_usage {
+            DynamicTable::<L>::begin_usage_tracking(&mut table);
+        }
         let handle = table.push_new_resource(item);
         self.tables.push(Box::new(table));
         handle
     }
 
+    /// Add an item that belongs to a namespace, such as a cell of a streamed world, so that it
+    /// can be released along with everything else in the namespace by release_namespace
+    pub fn add_item_in_namespace<T: Resource<L>>(&mut self, namespace: u32, item: T) -> Handle {
+        let handle = self.add_item(item);
+        let table_position = self.tables.iter()
+            .position(|table| table.as_any().is::<HandleTable<T>>())
+            .unwrap();
+        self.namespaces.entry(namespace)
+            .or_default()
+            .push((table_position, handle.table_index()));
+        handle
+    }
+
+    /// Release every item added to the namespace that has not since been removed, returning the
+    /// number released
+    pub fn release_namespace(&mut self, namespace: u32, loader: &L) -> usize {
+        let Some(items) = self.namespaces.remove(&namespace) else {
+            return 0;
+        };
+        items.into_iter()
+            .filter(|(table_position, table_index)|
+                self.tables[*table_position].release_item(*table_index, loader))
+            .count()
+    }
+
+    /// Number of items held in the namespace
+    pub fn namespace_size(&self, namespace: u32) -> usize {
+        self.namespaces.get(&namespace).map(|items| items.len()).unwrap_or(0)
+    }
+
     pub fn push_new_with_handle<T: Resource<L>>(&mut self, handle: Handle, item: T) {
 
         for table in self.tables.iter_mut() {
@@ -51,10 +91,57 @@ impl<L> EcsManager<L> {
         }
 
         let mut table = HandleTable::new();
+        if self.tracking_usage {
+            DynamicTable::<L>::begin_usage_tracking(&mut table);
+        }
         table.push_new_with_handle(handle, item);
         self.tables.push(Box::new(table));
     }
 
+    /// Create an item with the given handle unless one created for the same identity is there
+    /// already, such as for resources that may be shared with a previously loaded scene. The
+    /// identity names what the item is created from, such as an asset path, so that a different
+    /// resource loaded at the same handle by another scene is never mistaken for it; any such
+    /// item is displaced and released along with other unused items. Either way, the item
+    /// counts as used if usage is being tracked.
+    pub fn ensure_with_handle<T, F>(
+        &mut self,
+        handle: Handle,
+        identity: &str,
+        create: F
+    ) -> Result<(), EngineError>
+        where T: Resource<L>, F: FnOnce(&Self) -> Result<T, EngineError>
+    {
+        for table in self.tables.iter_mut() {
+            if let Some(table) = table.as_any_mut().downcast_mut::<HandleTable<T>>() {
+                let held = table.query_handle(handle).is_some();
+                if held && table.identity_of(handle.table_index()) == Some(identity) {
+                    table.mark_used(handle);
+                    return Ok(());
+                }
+                break;
+            }
+        }
+        let item = create(self)?;
+        for table in self.tables.iter_mut() {
+            if let Some(table) = table.as_any_mut().downcast_mut::<HandleTable<T>>() {
+                table.replace_with_handle(handle, identity, item);
+                return Ok(());
+            }
+        }
+        let mut table = HandleTable::new();
+        if self.tracking_usage {
+            DynamicTable::<L>::begin_usage_tracking(&mut table);
+        }
+        table.replace_with_handle(handle, identity, item);
+        self.tables.push(Box::new(table));
+        Ok(())
+    }
+
+    pub fn contains_item<T: Resource<L>>(&self, handle: Handle) -> bool {
+        self.get_item::<T>(handle).is_some()
+    }
+
     pub fn get_item<T: Resource<L>>(&self, handle: Handle) -> Option<&T> {
         for table in self.tables.iter() {
             if let Some(table) = table.as_any().downcast_ref::<HandleTable<T>>() {
@@ -64,18 +151,159 @@ impl<L> EcsManager<L> {
         None
     }
 
+    pub fn get_item_mut<T: Resource<L>>(&mut self, handle: Handle) -> Option<&mut T> {
+        for table in self.tables.iter_mut() {
+            if let Some(table) = table.as_any_mut().downcast_mut::<HandleTable<T>>() {
+                return table.query_handle_mut(handle);
+            }
+        }
+        None
+    }
+
+    /// Visit every item of a given type
+    pub fn for_each_item<T, F>(&self, mut f: F)
+        where T: Resource<L>, F: FnMut(Handle, &T)
+    {
+        for table in self.tables.iter() {
+            if let Some(table) = table.as_any().downcast_ref::<HandleTable<T>>() {
+                table.for_each(&mut f);
+                return;
+            }
+        }
+    }
+
+    /// Visit every item of a given type mutably, stopping at the first error returned
+    pub fn try_for_each_item_mut<T, F>(&mut self, mut f: F) -> Result<(), EngineError>
+        where T: Resource<L>, F: FnMut(Handle, &mut T) -> Result<(), EngineError>
+    {
+        for table in self.tables.iter_mut() {
+            if let Some(table) = table.as_any_mut().downcast_mut::<HandleTable<T>>() {
+                return table.try_for_each_mut(&mut f);
+            }
+        }
+        Ok(())
+    }
+
     pub fn remove_item<T: Resource<L>>(
         &mut self,
         handle: Handle
     ) -> Option<T> {
-        for table in self.tables.iter_mut() {
+        for (table_position, table) in self.tables.iter_mut().enumerate() {
             if let Some(table) = table.as_any_mut().downcast_mut::<HandleTable<T>>() {
-                return table.remove(handle);
+                let item = table.remove(handle);
+                if item.is_some() {
+                    self.forget_namespace_entry(table_position, handle.table_index());
+                }
+                return item;
             }
         }
         None
     }
 
+    /// The slot may be reused, so it must no longer be released with a namespace
+    fn forget_namespace_entry(&mut self, table_position: usize, table_index: u32) {
+        let entry = (table_position, table_index);
+        for items in self.namespaces.values_mut() {
+            items.retain(|item| *item != entry);
+        }
+    }
+
+    /// Number of items held of each resource type, by type name, for diagnostics
+    pub fn resource_counts(&self) -> Vec<(&'static str, usize)> {
+        self.tables.iter()
+            .map(|table| (table.resource_type_name(), table.item_count()))
+            .collect()
+    }
+
+    /// Every item held, by resource type, table index and identity
+    pub fn registry(&self) -> ResourceRegistry {
+        self.collect_registry(false)
+    }
+
+    /// The items noted as wanted since usage tracking began, or every item held if usage is not
+    /// being tracked
+    pub fn wanted_registry(&self) -> ResourceRegistry {
+        self.collect_registry(self.tracking_usage)
+    }
+
+    fn collect_registry(&self, wanted_only: bool) -> ResourceRegistry {
+        let mut registry = ResourceRegistry::new();
+        for table in self.tables.iter() {
+            for entry in table.registry_entries(wanted_only) {
+                registry.insert(entry);
+            }
+        }
+        registry
+    }
+
+    /// Note the items recorded in a registry as wanted, if usage is being tracked and they are
+    /// still held with the same identities, such as those that a scene asked for before usage
+    /// tracking was suspended
+    pub fn keep_registered(&mut self, registry: &ResourceRegistry) {
+        for entry in registry.iter() {
+            let table = self.tables.iter_mut()
+                .find(|table| table.resource_type_name() == entry.type_name);
+            if let Some(table) = table {
+                table.keep_identified_item(entry.table_index, entry.identity.as_deref());
+            }
+        }
+    }
+
+    /// Release the items with the given entries, such as those orphaned by switching scenes,
+    /// returning the number released. Entries for items no longer held with the same identity
+    /// are skipped.
+    pub fn release_entries(&mut self, entries: &[RegistryEntry], loader: &L) -> usize {
+        let held = self.registry();
+        let mut released = 0;
+        for entry in entries.iter().filter(|entry| held.contains(entry)) {
+            let table_position = self.tables.iter()
+                .position(|table| table.resource_type_name() == entry.type_name)
+                .unwrap();
+            if self.tables[table_position].release_item(entry.table_index, loader) {
+                self.forget_namespace_entry(table_position, entry.table_index);
+                released += 1;
+            }
+        }
+        released
+    }
+
+    /// Start noting which items are wanted. Items are noted as they are pushed, added or ensured;
+    /// pushing to a handle whose item has not yet been noted replaces that item rather than
+    /// panicking. Used when switching scenes, to keep what the next scene shares with the last.
+    /// Items in a namespace always count as wanted.
+    pub fn begin_usage_tracking(&mut self) {
+        self.tracking_usage = true;
+        for table in self.tables.iter_mut() {
+            table.begin_usage_tracking();
+        }
+
+        // Items in namespaces are only released along with their namespace
+        for (table_position, table_index) in self.namespaces.values().flatten() {
+            self.tables[*table_position].keep_item(*table_index);
+        }
+    }
+
+    /// Stop noting which items are wanted without releasing anything. Items displaced while
+    /// tracking are kept until release_unused, since the GPU may still be using them.
+    pub fn suspend_usage_tracking(&mut self) {
+        self.tracking_usage = false;
+        for table in self.tables.iter_mut() {
+            table.suspend_usage_tracking();
+        }
+    }
+
+    /// Release every item orphaned since usage tracking began, being those held but not noted
+    /// as wanted, along with any that were displaced, and stop tracking. Returns the number of
+    /// items released.
+    pub fn release_unused(&mut self, loader: &L) -> usize {
+        let orphans = self.registry().orphaned_by(&self.wanted_registry());
+        let released = self.release_entries(&orphans, loader);
+        self.suspend_usage_tracking();
+        released + self.tables.iter_mut()
+            .map(|table| table.release_displaced(loader))
+            .sum::<usize>()
+    }
+
     pub fn free_all_resources(&mut self, loader: &L) -> Result<(), EngineError> {
 
         for table in self.tables.iter_mut() {
@@ -83,6 +311,8 @@ impl<L> EcsManager<L> {
         }
 
         self.tables.clear();
+        self.tracking_usage = false;
+        self.namespaces.clear();
 
         Ok(())
     }
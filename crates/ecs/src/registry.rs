@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+
+/// RegistryEntry struct
+/// One item held by an EcsManager: the name of its resource type, its index within that type's
+/// table and, for items created through EcsManager::ensure_with_handle, the identity of the
+/// resource it was created from
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RegistryEntry {
+    pub type_name: &'static str,
+    pub table_index: u32,
+    pub identity: Option<String>
+}
+
+/// ResourceRegistry struct
+/// A record of items held by an EcsManager, or of those that a scene asked for, so that two can
+/// be compared to find what switching from one to the other leaves unused
+#[derive(Clone, Default, Debug)]
+pub struct ResourceRegistry {
+    entries: HashSet<RegistryEntry>
+}
+
+impl ResourceRegistry {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, entry: RegistryEntry) {
+        self.entries.insert(entry);
+    }
+
+    pub fn contains(&self, entry: &RegistryEntry) -> bool {
+        self.entries.contains(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegistryEntry> {
+        self.entries.iter()
+    }
+
+    /// Entries of this registry that the next one lacks, including those it has at the same
+    /// index but for a different identity; these are the items orphaned by switching to it
+    pub fn orphaned_by(&self, next: &ResourceRegistry) -> Vec<RegistryEntry> {
+        self.entries.iter()
+            .filter(|entry| !next.contains(entry))
+            .cloned()
+            .collect()
+    }
+}
@@ -16,4 +16,17 @@ pub trait RawResourceBearer<L> {
         loader: &mut L,
         swapchain_image_count: usize
     ) -> Result<(), EngineError>;
+
+    /// Reload only the dynamic resources that depend on the swapchain, such as renderpasses,
+    /// framebuffers and pipelines with a static viewport, after it has been rebuilt with the same
+    /// number of images. Those that don't, such as descriptor set and pipeline layouts, may be
+    /// kept. By default, every dynamic resource is reloaded.
+    fn reload_swapchain_resources(
+        &self,
+        ecs: &mut EcsManager<L>,
+        loader: &mut L,
+        swapchain_image_count: usize
+    ) -> Result<(), EngineError> {
+        self.reload_dynamic_resources(ecs, loader, swapchain_image_count)
+    }
 }
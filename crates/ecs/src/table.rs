@@ -1,17 +1,31 @@
 
-use crate::{Handle, resource::Resource};
+use crate::{Handle, RegistryEntry, resource::Resource};
+use error::EngineError;
 use std::any::Any;
+use std::collections::{HashMap, HashSet};
 
 pub trait DynamicTable<L> {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
     fn free_all_resources(&mut self, loader: &L);
+    fn resource_type_name(&self) -> &'static str;
+    fn item_count(&self) -> usize;
+    fn begin_usage_tracking(&mut self);
+    fn suspend_usage_tracking(&mut self);
+    fn release_displaced(&mut self, loader: &L) -> usize;
+    fn registry_entries(&self, wanted_only: bool) -> Vec<RegistryEntry>;
+    fn release_item(&mut self, table_index: u32, loader: &L) -> bool;
+    fn keep_item(&mut self, table_index: u32);
+    fn keep_identified_item(&mut self, table_index: u32, identity: Option<&str>);
 }
 
 pub struct HandleTable<T: 'static> {
     pub(crate) next_index_guess: u32,
     next_unique_id: u32,
-    items: Vec<Option<T>>
+    items: Vec<Option<T>>,
+    identities: HashMap<u32, String>,
+    used: Option<HashSet<u32>>,
+    displaced: Vec<T>
 }
 
 impl<L, T: Resource<L> + 'static> DynamicTable<L> for HandleTable<T> {
@@ -31,6 +45,72 @@ impl<L, T: Resource<L> + 'static> DynamicTable<L> for HandleTable<T> {
             }
         }
         self.items.clear();
+        self.identities.clear();
+        for item in self.displaced.drain(..) {
+            item.release(loader);
+        }
+        self.used = None;
+    }
+
+    fn resource_type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
+    fn item_count(&self) -> usize {
+        self.items.iter().filter(|item| item.is_some()).count()
+    }
+
+    fn begin_usage_tracking(&mut self) {
+        self.used = Some(HashSet::new());
+    }
+
+    fn suspend_usage_tracking(&mut self) {
+        self.used = None;
+    }
+
+    fn release_displaced(&mut self, loader: &L) -> usize {
+        let released = self.displaced.len();
+        for item in self.displaced.drain(..) {
+            item.release(loader);
+        }
+        released
+    }
+
+    fn registry_entries(&self, wanted_only: bool) -> Vec<RegistryEntry> {
+        self.items.iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_some())
+            .map(|(index, _)| index as u32)
+            .filter(|index| match (wanted_only, &self.used) {
+                (true, Some(used)) => used.contains(index),
+                _ => true
+            })
+            .map(|index| RegistryEntry {
+                type_name: std::any::type_name::<T>(),
+                table_index: index,
+                identity: self.identities.get(&index).cloned()
+            })
+            .collect()
+    }
+
+    fn release_item(&mut self, table_index: u32, loader: &L) -> bool {
+        match self.remove(Handle::for_resource(table_index)) {
+            Some(item) => {
+                item.release(loader);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn keep_item(&mut self, table_index: u32) {
+        self.mark_used(Handle::for_resource(table_index));
+    }
+
+    fn keep_identified_item(&mut self, table_index: u32, identity: Option<&str>) {
+        if self.identity_of(table_index) == identity {
+            self.mark_used(Handle::for_resource(table_index));
+        }
     }
 }
 
@@ -40,19 +120,61 @@ impl<T: 'static> HandleTable<T> {
         Self {
             next_index_guess: 0,
             next_unique_id: 1,
-            items: vec![]
+            items: vec![],
+            identities: HashMap::new(),
+            used: None,
+            displaced: vec![]
         }
     }
 
+    /// Note that the item at the handle is still wanted, if usage is being tracked. Returns
+    /// whether there is an item there.
+    pub(crate) fn mark_used(&mut self, handle: Handle) -> bool {
+        let table_index = handle.table_index();
+        if let Some(used) = self.used.as_mut() {
+            used.insert(table_index);
+        }
+        matches!(self.items.get(table_index as usize), Some(Some(_)))
+    }
+
+    /// The identity that the item at the index was created for, if it was ensured with one
+    pub(crate) fn identity_of(&self, table_index: u32) -> Option<&str> {
+        self.identities.get(&table_index).map(|identity| identity.as_str())
+    }
+
+    /// Put an item created for the given identity at the handle. Any item already there that
+    /// has not been noted as wanted is displaced, to be released with the other unused items.
+    pub(crate) fn replace_with_handle(&mut self, handle: Handle, identity: &str, item: T) {
+        let table_index = handle.table_index();
+        let claimed = self.used.as_ref()
+            .map(|used| used.contains(&table_index))
+            .unwrap_or(false);
+        if claimed && self.query_handle(handle).is_some() {
+            panic!("Tried to push a new handle which was already taken!");
+        }
+        if let Some(displaced) = self.remove(handle) {
+            self.displaced.push(displaced);
+        }
+        self.push_new_with_handle(handle, item);
+        self.identities.insert(table_index, identity.to_owned());
+    }
+
     pub(crate) fn push_new_resource(&mut self, item: T) -> Handle {
         let table_index = self.obtain_next_index();
         self.items[table_index as usize] = Some(item);
-        Handle::for_resource(table_index)
+        let handle = Handle::for_resource(table_index);
+        self.mark_used(handle);
+        handle
     }
 
     pub(crate) fn push_new_with_handle(&mut self, handle: Handle, item: T) {
 
         let table_index = handle.table_index() as usize;
+        let claimed = self.used.as_ref()
+            .map(|used| used.contains(&handle.table_index()))
+            .unwrap_or(true);
+        self.mark_used(handle);
+        self.identities.remove(&handle.table_index());
 
         // If vector doesn't yet have the index
         if table_index >= self.items.len() {
@@ -70,6 +192,15 @@ impl<T: 'static> HandleTable<T> {
             return;
         }
 
+        // While usage is tracked, an item not yet claimed is replaced, and the old one released
+        // along with the other unused items
+        if !claimed {
+            if let Some(displaced) = self.items[table_index].replace(item) {
+                self.displaced.push(displaced);
+            }
+            return;
+        }
+
         panic!("Tried to push a new handle which was already taken!");
     }
 
@@ -81,14 +212,35 @@ impl<T: 'static> HandleTable<T> {
         if self.items[table_index].is_some() {
             self.next_index_guess = table_index as u32;
         }
+        self.identities.remove(&handle.table_index());
         self.items[table_index].take()
     }
 
     pub fn query_handle(&self, handle: Handle) -> Option<&T> {
-        if let Some(item) = &self.items[handle.table_index() as usize] {
-            return Some(item);
+        self.items.get(handle.table_index() as usize).and_then(|item| item.as_ref())
+    }
+
+    pub fn query_handle_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.items.get_mut(handle.table_index() as usize).and_then(|item| item.as_mut())
+    }
+
+    pub(crate) fn for_each<F>(&self, f: &mut F) where F: FnMut(Handle, &T) {
+        for (index, item) in self.items.iter().enumerate() {
+            if let Some(item) = item {
+                f(Handle::for_resource(index as u32), item);
+            }
+        }
+    }
+
+    pub(crate) fn try_for_each_mut<F>(&mut self, f: &mut F) -> Result<(), EngineError>
+        where F: FnMut(Handle, &mut T) -> Result<(), EngineError>
+    {
+        for (index, item) in self.items.iter_mut().enumerate() {
+            if let Some(item) = item {
+                f(Handle::for_resource(index as u32), item)?;
+            }
         }
-        None
+        Ok(())
     }
 
     fn obtain_next_index(&mut self) -> u32 {
@@ -6,6 +6,10 @@ pub struct NullResourceLoader;
 
 struct SomeResource;
 
+struct CountedResource {
+    visits: u32
+}
+
 impl Resource<NullResourceLoader> for SomeResource {
     type CreationData = ();
 
@@ -20,6 +24,20 @@ impl Resource<NullResourceLoader> for SomeResource {
     fn release(&self, _loader: &NullResourceLoader) {}
 }
 
+impl Resource<NullResourceLoader> for CountedResource {
+    type CreationData = ();
+
+    fn create(
+        _loader: &NullResourceLoader,
+        _ecs: &EcsManager<NullResourceLoader>,
+        _data: &()
+    ) -> Result<Self, EngineError> {
+        Ok(CountedResource { visits: 0 })
+    }
+
+    fn release(&self, _loader: &NullResourceLoader) {}
+}
+
 #[test]
 fn explicit_handles_can_read_back() {
     let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
@@ -72,3 +90,182 @@ fn unused_handles_read_back_as_none() {
         .remove_item::<SomeResource>(Handle::for_resource(5));
     assert!(item_back.is_none());
 }
+
+#[test]
+fn items_can_be_visited_mutably() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let handle_0 = ecs.add_item(CountedResource { visits: 0 });
+    let handle_1 = ecs.add_item(CountedResource { visits: 0 });
+    ecs.add_item(SomeResource);
+
+    ecs.try_for_each_item_mut::<CountedResource, _>(|_, item| {
+        item.visits += 1;
+        Ok(())
+    }).unwrap();
+    assert_eq!(ecs.get_item::<CountedResource>(handle_0).unwrap().visits, 1);
+    assert_eq!(ecs.get_item::<CountedResource>(handle_1).unwrap().visits, 1);
+
+    let result = ecs.try_for_each_item_mut::<CountedResource, _>(|_, _| {
+        Err(EngineError::OpFailed(String::from("Stop")))
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn resource_counts_are_reported_per_type() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let handle = ecs.add_item(CountedResource { visits: 0 });
+    ecs.add_item(CountedResource { visits: 0 });
+    ecs.add_item(SomeResource);
+    ecs.remove_item::<CountedResource>(handle);
+
+    let counts = ecs.resource_counts();
+    assert_eq!(counts.len(), 2);
+    assert!(counts[0].0.ends_with("CountedResource"));
+    assert_eq!(counts[0].1, 1);
+    assert!(counts[1].0.ends_with("SomeResource"));
+    assert_eq!(counts[1].1, 1);
+}
+
+#[test]
+fn items_can_be_visited_and_changed_by_handle() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    ecs.add_item(CountedResource { visits: 0 });
+    let handle = ecs.add_item(CountedResource { visits: 0 });
+    ecs.get_item_mut::<CountedResource>(handle).unwrap().visits = 3;
+    assert!(ecs.get_item_mut::<SomeResource>(handle).is_none());
+
+    let mut total_visits = 0;
+    ecs.for_each_item::<CountedResource, _>(|_, item| total_visits += item.visits);
+    assert_eq!(total_visits, 3);
+}
+
+#[test]
+fn usage_tracking_keeps_shared_items_and_releases_the_rest() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let shared = Handle::for_resource(0);
+    let orphan = Handle::for_resource(1);
+    let replaced = Handle::for_resource(2);
+    ecs.ensure_with_handle(shared, "shared", |_| Ok(CountedResource { visits: 1 })).unwrap();
+    ecs.push_new_with_handle(orphan, CountedResource { visits: 1 });
+    ecs.push_new_with_handle(replaced, CountedResource { visits: 1 });
+
+    ecs.begin_usage_tracking();
+    ecs.ensure_with_handle(shared, "shared", |_| Ok(CountedResource { visits: 2 })).unwrap();
+    ecs.push_new_with_handle(replaced, CountedResource { visits: 2 });
+    ecs.push_new_with_handle(Handle::for_resource(0), SomeResource);
+    let released = ecs.release_unused(&NullResourceLoader);
+
+    assert_eq!(released, 2);
+    assert_eq!(ecs.get_item::<CountedResource>(shared).unwrap().visits, 1);
+    assert_eq!(ecs.get_item::<CountedResource>(replaced).unwrap().visits, 2);
+    assert!(!ecs.contains_item::<CountedResource>(orphan));
+    assert!(ecs.contains_item::<SomeResource>(Handle::for_resource(0)));
+}
+
+#[test]
+fn ensured_items_are_created_only_when_missing() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let handle = Handle::for_resource(3);
+    ecs.ensure_with_handle(handle, "counted", |_| Ok(CountedResource { visits: 1 })).unwrap();
+    ecs.ensure_with_handle(handle, "counted", |_| -> Result<CountedResource, EngineError> {
+        panic!("Item should not be created twice")
+    }).unwrap();
+    assert_eq!(ecs.get_item::<CountedResource>(handle).unwrap().visits, 1);
+    assert!(!ecs.contains_item::<CountedResource>(Handle::for_resource(9)));
+}
+
+#[test]
+fn ensured_items_are_replaced_when_the_identity_differs() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let handle = Handle::for_resource(1);
+    ecs.ensure_with_handle(handle, "first", |_| Ok(CountedResource { visits: 1 })).unwrap();
+
+    ecs.begin_usage_tracking();
+    ecs.ensure_with_handle(handle, "second", |_| Ok(CountedResource { visits: 2 })).unwrap();
+    assert_eq!(ecs.get_item::<CountedResource>(handle).unwrap().visits, 2);
+    assert_eq!(ecs.release_unused(&NullResourceLoader), 1);
+    assert_eq!(ecs.get_item::<CountedResource>(handle).unwrap().visits, 2);
+}
+
+#[test]
+fn registries_differ_by_identity_and_orphan_what_the_next_lacks() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    ecs.ensure_with_handle(Handle::for_resource(0), "kept", |_| Ok(SomeResource)).unwrap();
+    ecs.ensure_with_handle(Handle::for_resource(1), "dropped", |_| Ok(SomeResource)).unwrap();
+    let previous = ecs.registry();
+    assert_eq!(previous.len(), 2);
+
+    ecs.begin_usage_tracking();
+    ecs.ensure_with_handle(Handle::for_resource(0), "kept", |_| Ok(SomeResource)).unwrap();
+    let next = ecs.wanted_registry();
+    let orphans = previous.orphaned_by(&next);
+    assert_eq!(orphans.len(), 1);
+    assert_eq!(orphans[0].table_index, 1);
+    assert_eq!(orphans[0].identity.as_deref(), Some("dropped"));
+    assert_eq!(ecs.release_unused(&NullResourceLoader), 1);
+    assert!(ecs.contains_item::<SomeResource>(Handle::for_resource(0)));
+    assert!(!ecs.contains_item::<SomeResource>(Handle::for_resource(1)));
+}
+
+#[test]
+fn registered_items_are_kept_after_tracking_is_suspended() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let shared = Handle::for_resource(0);
+    let orphan = Handle::for_resource(1);
+    let replaced = Handle::for_resource(2);
+    ecs.ensure_with_handle(shared, "shared", |_| Ok(CountedResource { visits: 1 })).unwrap();
+    ecs.ensure_with_handle(orphan, "orphan", |_| Ok(CountedResource { visits: 1 })).unwrap();
+    ecs.ensure_with_handle(replaced, "old", |_| Ok(CountedResource { visits: 1 })).unwrap();
+
+    ecs.begin_usage_tracking();
+    ecs.ensure_with_handle(shared, "shared", |_| Ok(CountedResource { visits: 2 })).unwrap();
+    ecs.ensure_with_handle(replaced, "new", |_| Ok(CountedResource { visits: 2 })).unwrap();
+    let wanted = ecs.wanted_registry();
+    ecs.suspend_usage_tracking();
+
+    // Without tracking, only the displaced item is released; the orphan is found once tracking
+    // resumes and the wanted items are noted again
+    assert_eq!(ecs.release_unused(&NullResourceLoader), 1);
+    assert!(ecs.contains_item::<CountedResource>(orphan));
+    ecs.begin_usage_tracking();
+    ecs.keep_registered(&wanted);
+    assert_eq!(ecs.release_unused(&NullResourceLoader), 1);
+    assert_eq!(ecs.get_item::<CountedResource>(shared).unwrap().visits, 1);
+    assert_eq!(ecs.get_item::<CountedResource>(replaced).unwrap().visits, 2);
+    assert!(!ecs.contains_item::<CountedResource>(orphan));
+}
+
+#[test]
+fn namespaces_release_only_their_own_items() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let global = ecs.add_item(CountedResource { visits: 0 });
+    let first = ecs.add_item_in_namespace(1, CountedResource { visits: 1 });
+    ecs.add_item_in_namespace(1, SomeResource);
+    let second = ecs.add_item_in_namespace(2, CountedResource { visits: 2 });
+    let removed = ecs.add_item_in_namespace(2, CountedResource { visits: 2 });
+    ecs.remove_item::<CountedResource>(removed);
+    let reused = ecs.add_item(CountedResource { visits: 0 });
+    assert_eq!(ecs.namespace_size(1), 2);
+    assert_eq!(ecs.namespace_size(2), 1);
+
+    assert_eq!(ecs.release_namespace(1, &NullResourceLoader), 2);
+    assert!(!ecs.contains_item::<CountedResource>(first));
+    assert!(ecs.contains_item::<CountedResource>(global));
+    assert!(ecs.contains_item::<CountedResource>(second));
+    assert_eq!(ecs.release_namespace(1, &NullResourceLoader), 0);
+    assert_eq!(ecs.release_namespace(2, &NullResourceLoader), 1);
+    assert!(!ecs.contains_item::<CountedResource>(second));
+    assert!(ecs.contains_item::<CountedResource>(reused));
+}
+
+#[test]
+fn namespaced_items_survive_usage_tracking() {
+    let mut ecs: EcsManager<NullResourceLoader> = EcsManager::new();
+    let namespaced = ecs.add_item_in_namespace(5, CountedResource { visits: 0 });
+    let global = ecs.add_item(CountedResource { visits: 0 });
+    ecs.begin_usage_tracking();
+    assert_eq!(ecs.release_unused(&NullResourceLoader), 1);
+    assert!(ecs.contains_item::<CountedResource>(namespaced));
+    assert!(!ecs.contains_item::<CountedResource>(global));
+}
@@ -1,31 +1,242 @@
 
-use crate::{internals::EngineInternals, SceneFactory};
+use crate::{
+    internals::{EngineInternals, RenderSuspension}, viewport::Viewport, crash::{self, CrashContext},
+    RandomService, Scheduler, Sequence, TaskId, SceneFactory, Timer, StockTimer,
+    InputLatencyTracker, PresentTiming, StreamsWorld, ShaderReloader, InputMap, Scene,
+    input::TextInputFilter
+};
 use window::{
-    Window, WindowCommand, WindowStateEvent,
-    RenderCycleEvent, KeyCode, KeyState, MessageProxy, WindowEventLooper,
-    Event, WindowEvent, KeyboardInput, ControlFlow,
-    RenderEventHandler, WindowEventHandler
+    Window, WindowOptions, WindowConfig, WindowCommand, WindowStateEvent,
+    RenderCycleEvent, LoadingEvent, FrameTiming, KeyCode, KeyState, MessageProxy, WindowEventLooper,
+    Event, WindowEvent, DeviceEvent, KeyboardInput, ControlFlow, CursorMode, MonitorInfo,
+    RenderEventHandler, WindowEventHandler, CommandSender
+};
+use vk_renderer::{
+    PresentResult, VkContext, TextureQuality, TextureStreamingConfig, DepthConvention
 };
-use control::{ControlIo, UserControl};
-use vk_renderer::{PresentResult, VkContext};
+use error::EngineError;
+use std::cell::{Cell, RefCell};
 use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub struct Engine<M: 'static + Send + Debug> {
     app_title: &'static str,
     looper: Option<WindowEventLooper<M>>,
-    control: UserControl
+    input: Rc<RefCell<InputMap>>,
+    timer: Option<Box<dyn Timer>>,
+    window_config: WindowConfig,
+    srgb_output: bool,
+    dynamic_rendering: bool,
+    depth_convention: DepthConvention,
+    stencil_buffer: bool,
+    pipeline_creation_threads: usize,
+    render_scale: Option<f32>,
+    texture_quality: Rc<Cell<TextureQuality>>,
+    texture_streaming: Option<TextureStreamingConfig>,
+    random: Rc<RefCell<RandomService>>,
+    scheduler: Rc<RefCell<Scheduler>>,
+    input_latency: Option<Rc<RefCell<InputLatencyTracker>>>,
+    world_streamer: Option<Box<dyn StreamsWorld<VkContext>>>,
+    shader_reloader: Option<ShaderReloader>,
+    crash_context: Arc<Mutex<CrashContext>>,
+    secondary_windows: Vec<(&'static str, WindowConfig, Box<dyn SceneFactory<VkContext>>)>
 }
 
 impl<M: 'static + Send + Debug> Engine<M> {
 
     pub fn new(app_title: &'static str) -> Self {
+        Self::new_with_timer(app_title, Box::new(StockTimer::new()))
+    }
+
+    /// Create a new instance whose window is created with the given attributes
+    pub fn new_with_config(app_title: &'static str, config: WindowConfig) -> Self {
+        let mut engine = Self::new(app_title);
+        engine.window_config = config;
+        engine
+    }
+
+    /// Create a new instance with a custom source of time, such as a TestTimer to step time
+    /// manually
+    pub fn new_with_timer(app_title: &'static str, timer: Box<dyn Timer>) -> Self {
         Self {
             app_title,
             looper: Some(WindowEventLooper::new()),
-            control: UserControl::new()
+            input: Rc::new(RefCell::new(InputMap::default())),
+            timer: Some(timer),
+            window_config: WindowConfig::default(),
+            srgb_output: false,
+            dynamic_rendering: false,
+            depth_convention: DepthConvention::Standard,
+            stencil_buffer: false,
+            pipeline_creation_threads: 1,
+            render_scale: None,
+            texture_quality: Rc::new(Cell::new(TextureQuality::default())),
+            texture_streaming: None,
+            random: Rc::new(RefCell::new(RandomService::new(time_seed()))),
+            scheduler: Rc::new(RefCell::new(Scheduler::new())),
+            input_latency: None,
+            world_streamer: None,
+            shader_reloader: None,
+            crash_context: Arc::new(Mutex::new(CrashContext::default())),
+            secondary_windows: vec![]
         }
     }
 
+    /// Open another window when the engine runs, such as a preview window in an editor, drawing
+    /// a scene from the given factory with a render context of its own and the same settings as
+    /// the main window. It's updated along with the main scene, sharing its input and timing, and
+    /// closing it closes only that window. Returns a number identifying it in the
+    /// ViewportClosed event. Windows can only be added before run is called; there is no command
+    /// to open one while the engine is running.
+    pub fn add_window(
+        &mut self,
+        title: &'static str,
+        config: WindowConfig,
+        scene_factory: Box<dyn SceneFactory<VkContext>>
+    ) -> usize {
+        self.secondary_windows.push((title, config, scene_factory));
+        self.secondary_windows.len() - 1
+    }
+
+    /// Set limits on the size and shape of the window, applied when it is created by run
+    pub fn set_window_options(&mut self, options: WindowOptions) {
+        self.window_config.options = options;
+    }
+
+    /// Set all of the attributes the window is created with by run
+    pub fn set_window_config(&mut self, config: WindowConfig) {
+        self.window_config = config;
+    }
+
+    /// Choose whether to present through an sRGB swapchain where the display supports one, in
+    /// which case shaders should output linear color, and color textures should use the Srgba
+    /// format so that sampling them gives linear values
+    pub fn set_srgb_output(&mut self, srgb_output: bool) {
+        self.srgb_output = srgb_output;
+    }
+
+    /// Choose whether to render without renderpass and framebuffer objects where the device
+    /// supports Vulkan 1.3 dynamic rendering; scenes decide whether to make use of it
+    pub fn set_dynamic_rendering(&mut self, dynamic_rendering: bool) {
+        self.dynamic_rendering = dynamic_rendering;
+    }
+
+    /// Choose whether to render with reversed-Z depth, which keeps precision for distant
+    /// surfaces in large scenes. Scenes are told through Scene::attach_depth_convention.
+    pub fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.depth_convention = match reversed_z {
+            true => DepthConvention::ReversedZ,
+            false => DepthConvention::Standard
+        };
+    }
+
+    /// Choose whether the depth buffer has a stencil aspect, for effects such as outlines,
+    /// mirrors and portals; see StencilState. Where the device can't create one, a warning is
+    /// printed and depth is used alone.
+    pub fn set_stencil_buffer(&mut self, stencil_buffer: bool) {
+        self.stencil_buffer = stencil_buffer;
+    }
+
+    /// Set the most threads that batches of pipelines, such as those rebuilt after resizing, are
+    /// created on; one, the default, creates them on the main thread
+    pub fn set_pipeline_creation_threads(&mut self, thread_count: usize) {
+        self.pipeline_creation_threads = thread_count.max(1);
+    }
+
+    /// Render the main pass at the render area's size multiplied by the given factor, then scale
+    /// it into the window, or set None, the default, to render at the window's resolution;
+    /// see VkContext::set_render_scale. Scenes that support it, such as the stock scene, create
+    /// their targets accordingly. Like the other settings here, this must be set before running
+    /// the engine, as the render contexts are created with it.
+    pub fn set_render_scale(&mut self, scale: Option<f32>) {
+        self.render_scale = scale;
+    }
+
+    /// Set the global texture quality, which lowers the detail sampled from textures without
+    /// reloading them. May also be changed while running through get_texture_quality_setting.
+    pub fn set_texture_quality(&mut self, quality: TextureQuality) {
+        self.texture_quality.set(quality);
+    }
+
+    /// Get the shared texture quality setting; when it changes, samplers are rebuilt before the
+    /// next frame
+    pub fn get_texture_quality_setting(&self) -> Rc<Cell<TextureQuality>> {
+        self.texture_quality.clone()
+    }
+
+    /// Enable streaming of StreamedTexture resources, which each frame loads and evicts their mip
+    /// levels within a budget, according to the detail that the scene requests for them
+    pub fn set_texture_streaming(&mut self, config: Option<TextureStreamingConfig>) {
+        self.texture_streaming = config;
+    }
+
+    /// Stream a world in cells around the focus that the scene gives through
+    /// Scene::get_streaming_focus, such as a WorldStreamer, rather than loading it up front
+    pub fn set_world_streamer(&mut self, streamer: Box<dyn StreamsWorld<VkContext>>) {
+        self.world_streamer = Some(streamer);
+    }
+
+    /// Recompile shaders from their GLSL files when they change while running, rebuilding the
+    /// scene's dynamic resources around them; intended for development, with the runtime-shaders
+    /// feature enabled
+    pub fn set_shader_reloader(&mut self, reloader: ShaderReloader) {
+        self.shader_reloader = Some(reloader);
+    }
+
+    /// Seed the random number service; unless this is called, the seed is taken from the clock
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random.borrow_mut().reseed(seed);
+    }
+
+    /// Get the random number service shared with the scene, such as to save or restore its state
+    pub fn get_random_service(&self) -> Rc<RefCell<RandomService>> {
+        self.random.clone()
+    }
+
+    /// Run a callback once, after the given delay from the start of the main loop
+    pub fn after<F: 'static + FnMut(&mut Scheduler)>(&self, delay: Duration, f: F) -> TaskId {
+        self.scheduler.borrow_mut().after(delay, f)
+    }
+
+    /// Run a callback repeatedly at the given interval once the main loop starts
+    pub fn every<F: 'static + FnMut(&mut Scheduler)>(&self, interval: Duration, f: F) -> TaskId {
+        self.scheduler.borrow_mut().every(interval, f)
+    }
+
+    /// Run a sequence of steps, starting with the main loop
+    pub fn run_sequence(&self, sequence: Sequence) -> TaskId {
+        self.scheduler.borrow_mut().run_sequence(sequence)
+    }
+
+    /// Get the scheduler shared with the scene
+    pub fn get_scheduler(&self) -> Rc<RefCell<Scheduler>> {
+        self.scheduler.clone()
+    }
+
+    /// Get the input map, to change its bindings or to pass in gamepad state
+    pub fn get_input_map(&self) -> Rc<RefCell<InputMap>> {
+        self.input.clone()
+    }
+
+    /// Choose whether to measure the latency from keyboard input arriving to the scene update
+    /// that consumes it, and to the present of the resulting frame. Present timing comes from
+    /// waiting on presents where the device supports it. The report is printed on exit, and may
+    /// be read while running through get_input_latency_tracker.
+    pub fn set_input_latency_tracking(&mut self, enabled: bool) {
+        self.input_latency = match enabled {
+            true => Some(Rc::new(RefCell::new(
+                InputLatencyTracker::new(PresentTiming::QueuePresentReturn)))),
+            false => None
+        };
+    }
+
+    /// Get the input latency tracker, if tracking is enabled
+    pub fn get_input_latency_tracker(&self) -> Option<Rc<RefCell<InputLatencyTracker>>> {
+        self.input_latency.clone()
+    }
+
     pub fn new_message_proxy(&self) -> MessageProxy<WindowCommand<M>> {
         let Some(looper) = &self.looper else {
             panic!("Internal error");
@@ -33,35 +244,142 @@ impl<M: 'static + Send + Debug> Engine<M> {
         looper.create_proxy()
     }
 
-    pub fn run<A>(self, app: A) where
+    /// List the monitors connected to the system, such as to pick one to open the window on;
+    /// once running, send WindowCommand::ListMonitors instead
+    pub fn get_monitors(&self) -> Vec<MonitorInfo> {
+        let Some(looper) = &self.looper else {
+            panic!("Internal error");
+        };
+        looper.get_monitors()
+    }
+
+    /// Create a sender that supports batching and non-panicking sends, for use from other threads
+    pub fn new_command_sender(&self) -> CommandSender<M> {
+        CommandSender::new(self.new_message_proxy())
+    }
+
+    pub fn run<A>(mut self, app: A) where
         A: 'static + WindowEventHandler<M> + RenderEventHandler + SceneFactory<VkContext>
     {
-        // Create the window
+        // Write a crash report for any panic from here on, including while creating the device
+        // and loading resources
+        crash::install_panic_hook(self.app_title, self.crash_context.clone());
+
+        // Create the windows
         let Some(looper) = &self.looper else {
             panic!("Internal error");
         };
-        let window = Window::new(self.app_title, looper);
+        let window = Window::new_with_config(self.app_title, looper, self.window_config.clone())
+            .unwrap();
+        let secondary_windows = std::mem::take(&mut self.secondary_windows)
+            .into_iter()
+            .map(|(title, config, scene_factory)| {
+                (Window::new_with_config(title, looper, config).unwrap(), scene_factory)
+            })
+            .collect();
 
         // Run main loop until completion
-        self.run_main_loop(window, app);
+        self.run_main_loop(window, secondary_windows, app);
+    }
+
+    /// Create the render context and scene for a window added by add_window
+    fn create_viewport(
+        &self,
+        number: usize,
+        window: Window,
+        scene_factory: Box<dyn SceneFactory<VkContext>>
+    ) -> Result<Viewport, EngineError> {
+        let mut scene = scene_factory.get_scene();
+        scene.attach_random_service(self.random.clone());
+        scene.attach_scheduler(self.scheduler.clone());
+        scene.attach_depth_convention(self.depth_convention);
+        let internals = EngineInternals::new(
+            &window,
+            &scene.get_resource_bearer(),
+            Box::new(StockTimer::new()),
+            self.srgb_output,
+            self.dynamic_rendering,
+            self.depth_convention,
+            self.stencil_buffer,
+            self.pipeline_creation_threads,
+            self.render_scale,
+            false,
+            self.texture_quality.get(),
+            None,
+            Arc::new(Mutex::new(CrashContext::default())))?;
+        Viewport::new(number, window, internals, scene)
     }
 
-    fn run_main_loop<A>(mut self, window: Window, mut app: A) where
+    fn run_main_loop<A>(
+        mut self,
+        window: Window,
+        secondary_windows: Vec<(Window, Box<dyn SceneFactory<VkContext>>)>,
+        mut app: A
+    ) where
         A: 'static + WindowEventHandler<M> + RenderEventHandler + SceneFactory<VkContext>
     {
         let Some(looper) = self.looper.take() else {
             panic!("Internal error");
         };
+        let Some(timer) = self.timer.take() else {
+            panic!("Internal error");
+        };
+        let loading_start = Instant::now();
         let mut internals = {
-            let scene = app.get_scene();
+            let mut scene = app.get_scene();
+            scene.attach_depth_convention(self.depth_convention);
             let resource_bearer = scene.get_resource_bearer();
-            let internals = EngineInternals::new(&window, &resource_bearer).unwrap();
+            let internals = EngineInternals::new(
+                &window,
+                &resource_bearer,
+                timer,
+                self.srgb_output,
+                self.dynamic_rendering,
+                self.depth_convention,
+                self.stencil_buffer,
+                self.pipeline_creation_threads,
+                self.render_scale,
+                self.input_latency.is_some(),
+                self.texture_quality.get(),
+                self.texture_streaming,
+                self.crash_context.clone()).unwrap();
+            scene.attach_debug_labels(internals.get_debug_labels());
+            scene.attach_pre_rotation(internals.get_pre_rotation());
+            internals.prewarm_pipelines(&scene, |completed, total| app.on_render_cycle_event(
+                RenderCycleEvent::Loading(LoadingEvent::PrewarmingPipelines { completed, total })))
+                .unwrap();
             internals.record_graphics_commands(&scene).unwrap();
             internals
         };
+        app.on_render_cycle_event(
+            RenderCycleEvent::Loading(LoadingEvent::Finished(loading_start.elapsed())));
+        if let Some(tracker) = &self.input_latency {
+            if internals.supports_present_wait() {
+                tracker.borrow_mut().set_present_timing(PresentTiming::PresentWait);
+            }
+        }
+        let input_latency = self.input_latency.clone();
         let running_window_id = window.get_window_id();
         app.on_window_state_event(WindowStateEvent::Starting);
         let mut scene = app.get_scene();
+        scene.attach_random_service(self.random.clone());
+        scene.attach_scheduler(self.scheduler.clone());
+        scene.attach_depth_convention(self.depth_convention);
+        scene.attach_debug_labels(internals.get_debug_labels());
+        scene.attach_pre_rotation(internals.get_pre_rotation());
+        let mut viewports = secondary_windows.into_iter()
+            .enumerate()
+            .map(|(number, (window, scene_factory))| {
+                self.create_viewport(number, window, scene_factory)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let open_viewports = &mut viewports;
+        let mut suspension = RenderSuspension::default();
+        let mut frame_timing =
+            FrameTiming::new(0, 0, Duration::ZERO, window.get_refresh_interval());
+        let mut pending_scene: Option<(Box<dyn Scene<VkContext>>, Instant)> = None;
+        let mut text_input = TextInputFilter::new();
         let code = looper.run_loop(move |event, _, control_flow| {
             *control_flow = match *control_flow {
                 ControlFlow::ExitWithCode(_) => return,
@@ -69,17 +387,97 @@ impl<M: 'static + Send + Debug> Engine<M> {
             };
             match event {
                 Event::UserEvent(command) => {
-                    match command {
-                        WindowCommand::RequestClose => {
-                            internals.engine_teardown();
-                            *control_flow = ControlFlow::Exit
-                        },
-                        WindowCommand::RequestRedraw => {
-                            window.request_redraw();
-                        },
-                        WindowCommand::Custom(e) => {
-                            app.on_window_custom_event(e);
-                            ()
+                    for command in command.into_commands() {
+                        if *control_flow == ControlFlow::Exit {
+                            break;
+                        }
+                        match command {
+                            WindowCommand::RequestClose => {
+                                internals.engine_teardown();
+                                *control_flow = ControlFlow::Exit
+                            },
+                            WindowCommand::RequestRedraw => {
+                                window.request_redraw();
+                            },
+                            WindowCommand::ToggleFullscreen => {
+                                // Any resize event that follows restarts the settle period, so
+                                // the surface is rebuilt once at the final size
+                                if let Some(mode) = window.toggle_fullscreen() {
+                                    internals.queue_surface_rebuild(window.get_client_size());
+                                    app.on_window_state_event(
+                                        WindowStateEvent::DisplayModeChanged(mode));
+                                }
+                            },
+                            WindowCommand::SetFullscreen(mode) => {
+                                if let Some(mode) = window.set_fullscreen_mode(mode) {
+                                    internals.queue_surface_rebuild(window.get_client_size());
+                                    app.on_window_state_event(
+                                        WindowStateEvent::DisplayModeChanged(mode));
+                                }
+                            },
+                            WindowCommand::SetFullscreenOnMonitor {
+                                mode,
+                                monitor_index,
+                                display_mode
+                            } => {
+                                let mode = window.set_fullscreen_on_monitor(
+                                    mode,
+                                    monitor_index,
+                                    display_mode);
+                                match mode {
+                                    Ok(mode) => {
+                                        internals.queue_surface_rebuild(window.get_client_size());
+                                        app.on_window_state_event(
+                                            WindowStateEvent::DisplayModeChanged(mode));
+                                    },
+                                    Err(e) => app.on_window_state_event(
+                                        WindowStateEvent::CommandFailed(format!("{:?}", e)))
+                                }
+                            },
+                            WindowCommand::MoveToMonitor(monitor_index) => {
+                                // A resize event follows if the monitor's scale factor differs
+                                if let Err(e) = window.move_to_monitor(monitor_index) {
+                                    app.on_window_state_event(
+                                        WindowStateEvent::CommandFailed(format!("{:?}", e)));
+                                }
+                            },
+                            WindowCommand::ListMonitors => {
+                                app.on_window_state_event(
+                                    WindowStateEvent::MonitorsListed(window.get_monitors()));
+                            },
+                            WindowCommand::SetCursorMode(mode) => {
+                                if let Err(e) = window.set_cursor_mode(mode) {
+                                    app.on_window_state_event(
+                                        WindowStateEvent::CommandFailed(format!("{:?}", e)));
+                                }
+                            },
+                            WindowCommand::SetTextInput { enabled, position } => {
+                                window.set_text_input(enabled, position);
+                            },
+                            WindowCommand::RequestScreenshot => {
+                                // Captured as part of the next frame, then sent back once drawn
+                                if let Err(e) = internals.request_frame_capture() {
+                                    app.on_window_state_event(
+                                        WindowStateEvent::ScreenshotFailed(format!("{:?}", e)));
+                                }
+                            },
+                            WindowCommand::DefragmentMemory => {
+                                let event = match internals.defragment_memory(&scene) {
+                                    Ok(report) => WindowStateEvent::MemoryDefragmented {
+                                        relocated: report.relocated_count,
+                                        blocks_freed: report.blocks_freed
+                                    },
+                                    Err(e) => WindowStateEvent::CommandFailed(format!("{:?}", e))
+                                };
+                                app.on_window_state_event(event);
+                            },
+                            WindowCommand::Custom(e) => {
+                                app.on_window_custom_event(e);
+                                ()
+                            },
+                            WindowCommand::Batch(_) => {
+                                // Batches were already flattened
+                            }
                         }
                     }
                 },
@@ -87,6 +485,11 @@ impl<M: 'static + Send + Debug> Engine<M> {
                 if window_id == running_window_id => {
                     match event {
                         WindowEvent::KeyboardInput { input, .. } => {
+                            // Events carry no timestamp from the OS, so the time of delivery
+                            // from the event loop is the closest available to receipt
+                            if let Some(tracker) = &self.input_latency {
+                                tracker.borrow_mut().on_input_received(Instant::now());
+                            }
                             let KeyboardInput { virtual_keycode, state, .. } = input;
                             match (virtual_keycode, state) {
                                 (Some(KeyCode::Escape), KeyState::Pressed) => {
@@ -98,15 +501,50 @@ impl<M: 'static + Send + Debug> Engine<M> {
                                         WindowStateEvent::KeyEvent(
                                             keycode,
                                             state));
-                                    self.control.process_keyboard_event(keycode, state);
+                                    self.input.borrow_mut().process_key(keycode, state);
                                 },
                                 _ => {}
                             };
                         },
+                        WindowEvent::ReceivedCharacter(character) => {
+                            if let Some(event) = text_input.on_character(character) {
+                                app.on_window_state_event(event);
+                            }
+                        },
+                        WindowEvent::Ime(ime) => {
+                            if let Some(event) = text_input.on_ime(ime) {
+                                app.on_window_state_event(event);
+                            }
+                        },
+                        WindowEvent::MouseInput { state, button, .. } => {
+                            self.input.borrow_mut().process_mouse_button(button, state);
+                        },
+                        WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                            // Such as on moving to a monitor of different DPI. The suggested size
+                            // keeps the window's logical size; a resize event follows if it isn't
+                            // what the window is already, and rebuilds the surface once settled.
+                            internals.queue_resize(*new_inner_size);
+                            app.on_window_state_event(
+                                WindowStateEvent::ScaleFactorChanged(scale_factor));
+                        },
                         WindowEvent::Focused(focused) => {
                             match focused {
-                                true => app.on_window_state_event(WindowStateEvent::FocusGained),
-                                false => app.on_window_state_event(WindowStateEvent::FocusLost)
+                                true => {
+                                    // Some platforms release the cursor grab on losing focus
+                                    if window.get_cursor_mode() != CursorMode::Normal {
+                                        if let Err(e) =
+                                            window.set_cursor_mode(window.get_cursor_mode()) {
+                                            app.on_window_state_event(
+                                                WindowStateEvent::CommandFailed(
+                                                    format!("{:?}", e)));
+                                        }
+                                    }
+                                    app.on_window_state_event(WindowStateEvent::FocusGained)
+                                },
+                                false => {
+                                    self.input.borrow_mut().release_all();
+                                    app.on_window_state_event(WindowStateEvent::FocusLost)
+                                }
                             };
                         },
                         WindowEvent::CloseRequested => {
@@ -116,42 +554,261 @@ impl<M: 'static + Send + Debug> Engine<M> {
                         },
                         WindowEvent::Resized(client_area_dimensions) => {
                             // TODO - this recreates swapchain after first init; is it safe to not init swapchain until this?
-                            let last_known_size = internals.get_last_known_size();
-                            if last_known_size != client_area_dimensions {
-                                let aspect_ratio = client_area_dimensions.width as f32 /
-                                    client_area_dimensions.height as f32;
-                                app.on_render_cycle_event(
-                                    RenderCycleEvent::RecreatingSurface(aspect_ratio));
-                                internals.recreate_surface(&window, client_area_dimensions, &scene)
-                                    .unwrap();
+                            // Live resizing sends many of these; the surface is rebuilt once the
+                            // size settles, and meanwhile the previous swapchain keeps presenting,
+                            // or the last frame is stretched over the window once it can't.
+                            // Sizes off a constrained aspect ratio are snapped back first, which
+                            // produces another of these events. Minimizing reports a zero size.
+                            let resumed = suspension.on_resized(client_area_dimensions);
+                            if resumed {
+                                internals.resume_rendering(client_area_dimensions);
+                            } else if !suspension.is_suspended() {
+                                let previous_size = internals.get_last_known_size();
+                                if !window.enforce_aspect_ratio(
+                                    client_area_dimensions,
+                                    previous_size
+                                ) {
+                                    internals.queue_resize(client_area_dimensions);
+                                }
+                            }
+                        },
+                        WindowEvent::Occluded(occluded) => {
+                            if suspension.on_occluded(occluded) {
+                                internals.resume_rendering(window.get_client_size());
+                            }
+                        },
+                        _ => {}
+                    };
+                },
+                Event::WindowEvent { event, window_id } => {
+                    // Events for windows added by add_window; input from any window drives
+                    // the input map
+                    let index = open_viewports.iter()
+                        .position(|viewport| viewport.get_window_id() == window_id);
+                    match (index, event) {
+                        (Some(index), WindowEvent::Resized(client_area_dimensions)) => {
+                            open_viewports[index].on_resized(client_area_dimensions);
+                        },
+                        (Some(index), WindowEvent::ScaleFactorChanged { new_inner_size, .. }) => {
+                            open_viewports[index].on_resized(*new_inner_size);
+                        },
+                        (Some(index), WindowEvent::Occluded(occluded)) => {
+                            open_viewports[index].on_occluded(occluded);
+                        },
+                        (Some(index), WindowEvent::CloseRequested) => {
+                            let mut viewport = open_viewports.remove(index);
+                            viewport.teardown();
+                            app.on_window_state_event(
+                                WindowStateEvent::ViewportClosed(viewport.get_number()));
+                        },
+                        (Some(_), WindowEvent::KeyboardInput { input, .. }) => {
+                            if let Some(keycode) = input.virtual_keycode {
+                                self.input.borrow_mut().process_key(keycode, input.state);
                             }
                         },
+                        (Some(_), WindowEvent::MouseInput { state, button, .. }) => {
+                            self.input.borrow_mut().process_mouse_button(button, state);
+                        },
                         _ => {}
                     };
                 },
+                Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. }
+                if window.get_cursor_mode() == CursorMode::Locked => {
+                    let (dx, dy) = delta;
+                    self.input.borrow_mut().process_mouse_motion(dx, dy);
+                    app.on_window_state_event(WindowStateEvent::MouseMotion { dx, dy });
+                },
+                Event::MainEventsCleared | Event::RedrawRequested(_)
+                if suspension.is_suspended() => {
+                    // Nothing is updated or drawn while the window can't be seen, and without
+                    // redraw requests the loop waits for the next event
+                },
                 Event::MainEventsCleared => {
+                    if let Some(client_area_dimensions) = internals.take_settled_resize() {
+                        let aspect_ratio = window.get_render_aspect_ratio(client_area_dimensions);
+                        app.on_render_cycle_event(
+                            RenderCycleEvent::RecreatingSurface {
+                                aspect_ratio,
+                                scale_factor: window.get_scale_factor()
+                            });
+                        internals.recreate_surface(&window, client_area_dimensions, &scene)
+                            .unwrap();
+                        scene.attach_pre_rotation(internals.get_pre_rotation());
+                    }
+                    let texture_quality = self.texture_quality.get();
+                    if texture_quality != internals.get_texture_quality() {
+                        internals.apply_texture_quality(texture_quality, &scene).unwrap();
+                    }
+                    internals.update_texture_streaming(&scene).unwrap();
+                    if let Some(reloader) = self.shader_reloader.as_mut() {
+                        let reloads = internals.update_shader_reloading(&scene, reloader).unwrap();
+                        for reload in reloads.into_iter() {
+                            app.on_window_state_event(match reload.result {
+                                Ok(()) => WindowStateEvent::ShaderReloaded(reload.path),
+                                Err(e) => WindowStateEvent::ShaderReloadFailed {
+                                    path: reload.path,
+                                    error: format!("{:?}", e)
+                                }
+                            });
+                        }
+                    }
+                    if let Some(tracker) = &self.input_latency {
+                        internals.observe_presents(&mut tracker.borrow_mut()).unwrap();
+                    }
+
                     // TODO: v-sync?
-                    let time_passed_millis = internals.pull_time_step_millis();
-                    app.on_render_cycle_event(
-                        RenderCycleEvent::PrepareUpdate(time_passed_millis));
-                    scene.update(
-                        time_passed_millis,
-                        self.control.get_dx(),
-                        self.control.get_dy());
+                    // The refresh interval is queried each frame since the window may have moved
+                    // to a different monitor
+                    frame_timing = internals.pull_frame_timing(window.get_refresh_interval());
+                    app.on_render_cycle_event(RenderCycleEvent::PrepareUpdate(frame_timing));
+                    self.scheduler.borrow_mut()
+                        .advance(Duration::from_millis(frame_timing.elapsed_millis));
+                    let input = self.input.borrow_mut().snapshot();
+                    scene.update(frame_timing.elapsed_millis, &input);
+                    let mut index = 0;
+                    while index < open_viewports.len() {
+                        match open_viewports[index].update(frame_timing.elapsed_millis, &input) {
+                            Ok(()) => index += 1,
+                            Err(e) => {
+                                // Only this window is lost; the main window carries on
+                                let viewport = open_viewports.remove(index);
+                                close_failed_viewport(viewport, e, &mut app);
+                            }
+                        }
+                    }
+                    if let Some(tracker) = &self.input_latency {
+                        tracker.borrow_mut().on_update(Instant::now());
+                    }
+                    // The next scene loads while the current one keeps running, and takes over
+                    // once its uploads have completed
+                    if pending_scene.is_none() {
+                        if let Some(mut next_scene) = scene.take_next_scene() {
+                            next_scene.attach_random_service(self.random.clone());
+                            next_scene.attach_scheduler(self.scheduler.clone());
+                            next_scene.attach_depth_convention(self.depth_convention);
+                            next_scene.attach_debug_labels(internals.get_debug_labels());
+                            next_scene.attach_pre_rotation(internals.get_pre_rotation());
+                            let loading_start = Instant::now();
+                            internals.begin_scene_swap(&next_scene).unwrap();
+                            pending_scene = Some((next_scene, loading_start));
+                        }
+                    }
+                    if pending_scene.is_some() && internals.is_scene_swap_ready().unwrap() {
+                        let (mut next_scene, loading_start) = pending_scene.take().unwrap();
+                        next_scene.attach_debug_labels(internals.get_debug_labels());
+                        next_scene.attach_pre_rotation(internals.get_pre_rotation());
+                        internals.finish_scene_swap(&next_scene).unwrap();
+                        internals.prewarm_pipelines(&next_scene, |completed, total| {
+                            app.on_render_cycle_event(RenderCycleEvent::Loading(
+                                LoadingEvent::PrewarmingPipelines { completed, total }))
+                        }).unwrap();
+                        app.on_render_cycle_event(RenderCycleEvent::Loading(
+                            LoadingEvent::Finished(loading_start.elapsed())));
+                        scene = next_scene;
+                    }
+                    if let Some(streamer) = self.world_streamer.as_mut() {
+                        internals.update_world_streaming(&scene, streamer.as_mut()).unwrap();
+                    }
                     window.request_redraw();
                 },
+                Event::RedrawRequested(window_id) if window_id != running_window_id => {
+                    let index = open_viewports.iter()
+                        .position(|viewport| viewport.get_window_id() == window_id);
+                    if let Some(index) = index {
+                        match open_viewports[index].render() {
+                            Ok(false) => {},
+                            Ok(true) => {
+                                let number = open_viewports[index].get_number();
+                                app.on_window_state_event(
+                                    WindowStateEvent::ViewportDeviceRestored(number));
+                            },
+                            Err(e) => {
+                                // Only this window is lost; the main window carries on
+                                let viewport = open_viewports.remove(index);
+                                close_failed_viewport(viewport, e, &mut app);
+                            }
+                        }
+                    }
+                },
                 Event::RedrawRequested(_) => {
-                    app.on_render_cycle_event(RenderCycleEvent::RenderingFrame);
-                    match internals.render_frame(&scene) {
-                        Ok(PresentResult::Ok) => {},
+                    app.on_render_cycle_event(RenderCycleEvent::RenderingFrame(frame_timing));
+                    let presented = match internals.is_stretching_frames() {
+                        true => internals.present_stretched_frame(),
+                        false => internals.render_frame(&scene)
+                    };
+                    match presented {
+                        Ok(PresentResult::Ok) => {
+                            if let Some(tracker) = &self.input_latency {
+                                tracker.borrow_mut().on_frame_submitted(
+                                    internals.get_last_present_id(),
+                                    Instant::now());
+                            }
+                        },
+                        Ok(PresentResult::Suboptimal) => {
+                            if let Some(tracker) = &self.input_latency {
+                                tracker.borrow_mut().on_frame_submitted(
+                                    internals.get_last_present_id(),
+                                    Instant::now());
+                            }
+
+                            // The frame was shown; a settling resize rebuilds the surface anyway
+                            if !internals.has_pending_resize() {
+                                internals.recreate_swapchain(&scene).unwrap();
+                                scene.attach_pre_rotation(internals.get_pre_rotation());
+                            }
+                        },
+                        Ok(PresentResult::FrameSkipped) => {
+                            // No image was available in time; try again next frame
+                        },
+                        Ok(PresentResult::SwapchainOutOfDate) if internals.has_pending_resize() => {
+                            // Until the resize settles and the surface is rebuilt, the last frame
+                            // is stretched over a swapchain of the new size
+                            internals.stretch_last_frame().unwrap();
+                        },
                         Ok(PresentResult::SwapchainOutOfDate) => {
                             let last_known_size = internals.get_last_known_size();
-                            let aspect_ratio = last_known_size.width as f32 /
-                                last_known_size.height as f32;
+                            let aspect_ratio = window.get_render_aspect_ratio(last_known_size);
                             app.on_render_cycle_event(
-                                RenderCycleEvent::RecreatingSurface(aspect_ratio));
+                                RenderCycleEvent::RecreatingSurface {
+                                    aspect_ratio,
+                                    scale_factor: window.get_scale_factor()
+                                });
                             internals.recreate_surface(&window, last_known_size, &scene)
                                 .unwrap();
+                            scene.attach_pre_rotation(internals.get_pre_rotation());
+                        },
+                        Err(EngineError::Timeout(e)) => {
+                            // Images stopped becoming available; rebuilding the swapchain is
+                            // the best chance of getting them back
+                            println!("Rendering stalled, recreating surface: {}", e);
+                            let last_known_size = internals.get_last_known_size();
+                            let aspect_ratio = window.get_render_aspect_ratio(last_known_size);
+                            app.on_render_cycle_event(
+                                RenderCycleEvent::RecreatingSurface {
+                                    aspect_ratio,
+                                    scale_factor: window.get_scale_factor()
+                                });
+                            internals.recreate_surface(&window, last_known_size, &scene)
+                                .unwrap();
+                            scene.attach_pre_rotation(internals.get_pre_rotation());
+                        },
+                        Err(EngineError::DeviceLost(e)) => {
+                            // Such as after a driver reset; everything on the device is rebuilt,
+                            // and only if that fails too does the application exit
+                            println!("Device lost, recreating it: {}", e);
+                            match internals.recover_from_device_loss(&window, &scene) {
+                                Ok(()) => {
+                                    scene.attach_debug_labels(internals.get_debug_labels());
+                                    scene.attach_pre_rotation(internals.get_pre_rotation());
+                                    scene.on_device_restored();
+                                },
+                                Err(e) => {
+                                    // The old context may already be torn down, so nothing is
+                                    // torn down again
+                                    println!("Device recovery failed: {:?}", e);
+                                    *control_flow = ControlFlow::Exit
+                                }
+                            }
                         },
                         Err(e) => {
                             println!("Rendering error: {:?}", e);
@@ -159,10 +816,47 @@ impl<M: 'static + Send + Debug> Engine<M> {
                             *control_flow = ControlFlow::Exit
                         }
                     }
+                    if *control_flow != ControlFlow::Exit {
+                        match internals.take_frame_capture() {
+                            Ok(Some((width, height, pixels))) => app.on_window_state_event(
+                                WindowStateEvent::ScreenshotCaptured { width, height, pixels }),
+                            Ok(None) => {},
+                            Err(e) => app.on_window_state_event(
+                                WindowStateEvent::ScreenshotFailed(format!("{:?}", e)))
+                        }
+                    }
                 },
                 _ => ()
             }
         });
         println!("Window exited with code {}", code);
+        for viewport in viewports.iter_mut() {
+            viewport.teardown();
+        }
+        if let Some(tracker) = input_latency {
+            println!("{}", tracker.borrow().report());
+        }
     }
 }
+
+/// Tear down a window added by Engine::add_window that failed to update or render, telling the
+/// app why before reporting it closed
+fn close_failed_viewport<M: 'static, A: WindowEventHandler<M>>(
+    mut viewport: Viewport,
+    error: EngineError,
+    app: &mut A
+) {
+    viewport.teardown();
+    app.on_window_state_event(WindowStateEvent::ViewportFailed {
+        number: viewport.get_number(),
+        error: format!("{:?}", error)
+    });
+    app.on_window_state_event(WindowStateEvent::ViewportClosed(viewport.get_number()));
+}
+
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,83 @@
+use crate::persistence::dirs;
+use std::backtrace::Backtrace;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// CrashContext struct
+/// What the engine was last doing, kept up to date while running so that a crash report can say
+/// more than where the panic happened
+#[derive(Default)]
+pub struct CrashContext {
+    pub frame_number: u64,
+    pub last_recorded_passes: Option<String>,
+    pub resource_counts: Vec<(&'static str, usize)>,
+    pub device_description: String
+}
+
+impl CrashContext {
+
+    /// Build the text of a crash report for a panic, given its message and location
+    pub fn report(&self, panic_description: &str, backtrace: &Backtrace) -> String {
+        let mut report = String::new();
+        let thread = std::thread::current();
+        let _ = writeln!(
+            report,
+            "Panic on thread {}: {}",
+            thread.name().unwrap_or("<unnamed>"),
+            panic_description);
+        let _ = writeln!(report);
+        let _ = writeln!(report, "Frame number: {}", self.frame_number);
+        let _ = writeln!(
+            report,
+            "Last recorded passes: {}",
+            self.last_recorded_passes.as_deref().unwrap_or("<none>"));
+        let _ = writeln!(report, "Device: {}", self.device_description);
+        let _ = writeln!(report, "Resources:");
+        for (type_name, count) in self.resource_counts.iter() {
+            let _ = writeln!(report, "    {}: {}", type_name, count);
+        }
+        let _ = writeln!(report);
+        let _ = writeln!(report, "Backtrace:");
+        let _ = writeln!(report, "{}", backtrace);
+        report
+    }
+}
+
+/// Install a panic hook that writes a crash report to the app's log directory before the panic
+/// unwinds, then runs the previously installed hook. If the context is locked at the time of
+/// the panic, such as by another thread updating it, the report is written without it.
+pub fn install_panic_hook(app_name: &str, context: Arc<Mutex<CrashContext>>) {
+    let report_dir = dirs::log_dir(app_name)
+        .unwrap_or_else(|_| std::env::temp_dir())
+        .join("crash-reports");
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        let backtrace = Backtrace::force_capture();
+        let panic_description = panic_info.to_string();
+        let report = match context.try_lock() {
+            Ok(context) => context.report(&panic_description, &backtrace),
+            Err(TryLockError::Poisoned(context)) =>
+                context.into_inner().report(&panic_description, &backtrace),
+            Err(TryLockError::WouldBlock) =>
+                CrashContext::default().report(&panic_description, &backtrace)
+        };
+        match write_report(&report_dir, &report) {
+            Ok(path) => eprintln!("Crash report written to {:?}", path),
+            Err(e) => eprintln!("Failed to write crash report: {:?}", e)
+        }
+        previous_hook(panic_info);
+    }));
+}
+
+fn write_report(report_dir: &Path, report: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(report_dir)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or_default();
+    let path = report_dir.join(format!("crash-{}.txt", timestamp));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
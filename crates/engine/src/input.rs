@@ -0,0 +1,375 @@
+use window::{KeyCode, KeyState, MouseButton, Ime, WindowStateEvent};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Name of the axis bound by default to the left and right arrow keys
+pub const MOVE_X_AXIS: &str = "move_x";
+
+/// Name of the axis bound by default to the down and up arrow keys
+pub const MOVE_Y_AXIS: &str = "move_y";
+
+/// GamepadButton enum
+/// Buttons of a gamepad, named by their position in the standard layout. The engine reads no
+/// gamepads itself; apps pass their state in through InputMap::process_gamepad_button.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight
+}
+
+/// GamepadAxis enum
+/// Analog inputs of a gamepad, passed in through InputMap::process_gamepad_axis
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger
+}
+
+/// InputSource enum
+/// A single button-like input that may be bound to an action, or to either end of an axis
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum InputSource {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton)
+}
+
+/// AxisBinding enum
+/// One input contributing to a named axis; either a pair of buttons pushing it toward -1 and +1,
+/// or a gamepad axis, scaled and with small values around its rest position ignored
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AxisBinding {
+    Buttons { negative: InputSource, positive: InputSource },
+    Analog { axis: GamepadAxis, scale: f32, dead_zone: f32 }
+}
+
+/// InputBindings struct
+/// The rebindable configuration of an InputMap: the inputs bound to each named action and axis.
+/// Several inputs may be bound to the same name, and any of them triggers it.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct InputBindings {
+    pub actions: HashMap<String, Vec<InputSource>>,
+    pub axes: HashMap<String, Vec<AxisBinding>>
+}
+
+impl InputBindings {
+
+    /// Bindings with the arrow keys on the two movement axes, as apps had before actions could
+    /// be configured
+    pub fn stock() -> Self {
+        let mut bindings = Self::default();
+        bindings.bind_axis(MOVE_X_AXIS, AxisBinding::Buttons {
+            negative: InputSource::Key(KeyCode::Left),
+            positive: InputSource::Key(KeyCode::Right)
+        });
+        bindings.bind_axis(MOVE_Y_AXIS, AxisBinding::Buttons {
+            negative: InputSource::Key(KeyCode::Down),
+            positive: InputSource::Key(KeyCode::Up)
+        });
+        bindings
+    }
+
+    /// Add an input that triggers the named action, alongside any already bound to it
+    pub fn bind_action(&mut self, action: &str, source: InputSource) {
+        let sources = self.actions.entry(action.to_owned()).or_default();
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+
+    /// Add an input that moves the named axis, alongside any already bound to it
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        let bindings = self.axes.entry(axis.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Replace whatever inputs trigger the named action with a single one, such as when the
+    /// player picks a new key in an options menu
+    pub fn rebind_action(&mut self, action: &str, source: InputSource) {
+        self.actions.insert(action.to_owned(), vec![source]);
+    }
+
+    /// Replace whatever inputs move the named axis with a single one
+    pub fn rebind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        self.axes.insert(axis.to_owned(), vec![binding]);
+    }
+
+    /// Remove the named action and everything bound to it
+    pub fn unbind_action(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    /// Remove the named axis and everything bound to it
+    pub fn unbind_axis(&mut self, axis: &str) {
+        self.axes.remove(axis);
+    }
+}
+
+/// ActionState struct
+/// The state of one named action for an update
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+struct ActionState {
+    pressed: bool,
+    just_pressed: bool,
+    just_released: bool
+}
+
+/// InputState struct
+/// The state of every named action and axis, as passed to Scene::update. Names with nothing
+/// bound read as released and at rest.
+#[derive(Clone, Default, Debug)]
+pub struct InputState {
+    actions: HashMap<String, ActionState>,
+    axes: HashMap<String, f32>,
+    mouse_delta: (f32, f32)
+}
+
+impl InputState {
+
+    /// Query whether any input bound to the action is held
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.actions.get(action).map_or(false, |state| state.pressed)
+    }
+
+    /// Query whether the action was pressed since the previous update, even if already released
+    pub fn was_just_pressed(&self, action: &str) -> bool {
+        self.actions.get(action).map_or(false, |state| state.just_pressed)
+    }
+
+    /// Query whether the action was held at the previous update and no longer is
+    pub fn was_just_released(&self, action: &str) -> bool {
+        self.actions.get(action).map_or(false, |state| state.just_released)
+    }
+
+    /// The value of the named axis, between -1 and 1
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.axes.get(axis).copied().unwrap_or(0.0)
+    }
+
+    /// Relative mouse movement since the previous update, in pixels; only reported while the
+    /// cursor is locked
+    pub fn get_mouse_delta(&self) -> (f32, f32) {
+        self.mouse_delta
+    }
+
+    /// The left/right movement input, from the stock horizontal axis
+    pub fn get_dx(&self) -> f32 {
+        self.axis(MOVE_X_AXIS)
+    }
+
+    /// The up/down movement input, from the stock vertical axis
+    pub fn get_dy(&self) -> f32 {
+        self.axis(MOVE_Y_AXIS)
+    }
+}
+
+/// InputMap struct
+/// Tracks keys, mouse buttons and gamepad inputs as they arrive, and maps them to named actions
+/// and axes through its bindings. The engine feeds it window events and takes a snapshot for
+/// each scene update; apps may change its bindings at any time, and pass in gamepad state from
+/// whatever library they read it with.
+pub struct InputMap {
+    bindings: InputBindings,
+    held: HashSet<InputSource>,
+    pressed_since_snapshot: HashSet<InputSource>,
+    analog: HashMap<GamepadAxis, f32>,
+    mouse_delta: (f32, f32),
+    previously_pressed: HashSet<String>
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self::new(InputBindings::stock())
+    }
+}
+
+impl InputMap {
+
+    pub fn new(bindings: InputBindings) -> Self {
+        Self {
+            bindings,
+            held: HashSet::new(),
+            pressed_since_snapshot: HashSet::new(),
+            analog: HashMap::new(),
+            mouse_delta: (0.0, 0.0),
+            previously_pressed: HashSet::new()
+        }
+    }
+
+    /// Getter for the bindings, such as to save them
+    pub fn get_bindings(&self) -> &InputBindings {
+        &self.bindings
+    }
+
+    /// Mutable getter for the bindings, to bind and rebind inputs
+    pub fn get_bindings_mut(&mut self) -> &mut InputBindings {
+        &mut self.bindings
+    }
+
+    /// Replace all of the bindings, such as with ones loaded from a config file
+    pub fn set_bindings(&mut self, bindings: InputBindings) {
+        self.bindings = bindings;
+    }
+
+    /// Process a keyboard event
+    pub fn process_key(&mut self, keycode: KeyCode, state: KeyState) {
+        self.process_source(InputSource::Key(keycode), state == KeyState::Pressed);
+    }
+
+    /// Process a mouse button event
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: KeyState) {
+        self.process_source(InputSource::Mouse(button), state == KeyState::Pressed);
+    }
+
+    /// Process a gamepad button changing state
+    pub fn process_gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        self.process_source(InputSource::Gamepad(button), pressed);
+    }
+
+    /// Process a new value of a gamepad axis, between -1 and 1
+    pub fn process_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.analog.insert(axis, value.clamp(-1.0, 1.0));
+    }
+
+    /// Process relative mouse movement, which accumulates until the next snapshot
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        self.mouse_delta.0 += dx as f32;
+        self.mouse_delta.1 += dy as f32;
+    }
+
+    /// Release everything held, such as when the window loses focus and will not be told about
+    /// keys released elsewhere
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.analog.clear();
+    }
+
+    /// Map the inputs received so far to the state of each action and axis, for one update
+    pub fn snapshot(&mut self) -> InputState {
+        let mut state = InputState::default();
+        for (action, sources) in self.bindings.actions.iter() {
+            let pressed = sources.iter().any(|source| self.held.contains(source));
+            let was_pressed = self.previously_pressed.contains(action);
+            let pressed_between = sources.iter()
+                .any(|source| self.pressed_since_snapshot.contains(source));
+            state.actions.insert(action.clone(), ActionState {
+                pressed,
+                just_pressed: (pressed && !was_pressed) || pressed_between,
+                just_released: !pressed && was_pressed
+            });
+        }
+        for (axis, bindings) in self.bindings.axes.iter() {
+            let value: f32 = bindings.iter()
+                .map(|binding| self.axis_binding_value(binding))
+                .sum();
+            state.axes.insert(axis.clone(), value.clamp(-1.0, 1.0));
+        }
+        self.previously_pressed = state.actions.iter()
+            .filter(|(_, action_state)| action_state.pressed)
+            .map(|(action, _)| action.clone())
+            .collect();
+        self.pressed_since_snapshot.clear();
+        state.mouse_delta = std::mem::take(&mut self.mouse_delta);
+        state
+    }
+
+    fn process_source(&mut self, source: InputSource, pressed: bool) {
+        match pressed {
+            true => {
+                if self.held.insert(source) {
+                    self.pressed_since_snapshot.insert(source);
+                }
+            },
+            false => {
+                self.held.remove(&source);
+            }
+        }
+    }
+
+    fn axis_binding_value(&self, binding: &AxisBinding) -> f32 {
+        match binding {
+            AxisBinding::Buttons { negative, positive } => {
+                let negative = if self.held.contains(negative) { -1.0 } else { 0.0 };
+                let positive = if self.held.contains(positive) { 1.0 } else { 0.0 };
+                negative + positive
+            },
+            AxisBinding::Analog { axis, scale, dead_zone } => {
+                let value = self.analog.get(axis).copied().unwrap_or(0.0);
+                match value.abs() <= *dead_zone {
+                    true => 0.0,
+                    false => value * scale
+                }
+            }
+        }
+    }
+}
+
+/// TextInputFilter struct
+/// Maps the typed characters and input method events of a window to the text events given to
+/// the app. Some platforms also report text from an input method as typed characters, which
+/// would have it inserted twice, so characters are dropped while a composition is under way and
+/// where they repeat the text just committed.
+#[derive(Default)]
+pub(crate) struct TextInputFilter {
+    composing: bool,
+    committed: VecDeque<char>
+}
+
+impl TextInputFilter {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The event for a typed character, if it should be reported; control characters are only
+    /// reported as key events
+    pub fn on_character(&mut self, character: char) -> Option<WindowStateEvent> {
+        if self.composing || character.is_control() {
+            return None;
+        }
+        if self.committed.pop_front() == Some(character) {
+            return None;
+        }
+        self.committed.clear();
+        Some(WindowStateEvent::CharacterReceived(character))
+    }
+
+    /// The event for an input method event, if there is one
+    pub fn on_ime(&mut self, ime: Ime) -> Option<WindowStateEvent> {
+        match ime {
+            Ime::Preedit(text, cursor) => {
+                self.composing = !text.is_empty();
+                Some(WindowStateEvent::TextComposition { text, cursor })
+            },
+            Ime::Commit(text) => {
+                self.composing = false;
+                self.committed = text.chars().collect();
+                Some(WindowStateEvent::TextCommitted(text))
+            },
+            Ime::Enabled | Ime::Disabled => {
+                self.composing = false;
+                self.committed.clear();
+                None
+            }
+        }
+    }
+}
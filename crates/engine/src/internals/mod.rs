@@ -1,28 +1,92 @@
 
-use crate::{StockTimer, Timer, Scene};
-use vk_renderer::{VkCore, VkContext, PresentResult};
-use window::{Window, PhysicalSize};
-use ecs::{EcsManager, resource::RawResourceBearer};
+mod resize;
+mod secondary;
+
+use crate::{
+    Timer, Scene, RecordingStrategy, InputLatencyTracker, StreamsWorld, ShaderReloader,
+    crash::CrashContext, shader_reload::ShaderReload
+};
+pub(crate) use resize::{ResizeDebouncer, SwapchainReload, RenderSuspension};
+use vk_renderer::{
+    VkCore, VkContext, FeatureDeclaration, PresentResult, PresentStatus, AcquireResult,
+    SamplerWrapper, TextureQuality, TextureStreamer, TextureStreamingConfig, prewarm_pipelines,
+    DepthConvention, TexturePixelFormat, DebugLabels, PreRotation, DefragmentReport
+};
+use window::{Window, PhysicalSize, FrameTiming};
+use ecs::{EcsManager, Handle, ResourceRegistry, resource::{Resource, RawResourceBearer}};
+use ash::vk;
 use error::EngineError;
 use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time that the window size must remain unchanged before the surface is rebuilt after resizing
+const RESIZE_SETTLE_TIME_MILLIS: u64 = 100;
+
+/// PendingSceneSwap struct
+/// A switch to another scene that has begun: the items that the next scene's static resources
+/// are held as, and the transfer timeline value that signals once they have been uploaded
+struct PendingSceneSwap {
+    wanted: ResourceRegistry,
+    upload_value: u64
+}
+
+/// ContextSettings struct
+/// Options that the render context is created with, kept so that an identical context can be
+/// created again after the device is lost
+struct ContextSettings {
+    srgb_output: bool,
+    depth_convention: DepthConvention,
+    stencil_buffer: bool,
+    pipeline_creation_threads: usize,
+    render_scale: Option<f32>
+}
 
 pub struct EngineInternals {
-    timer: StockTimer,
+    timer: Box<dyn Timer>,
     last_known_client_area_size: PhysicalSize<u32>,
     render_core: RefCell<VkCore>,
     render_context: RefCell<VkContext>,
-    ecs: RefCell<EcsManager<VkContext>>
+    context_settings: ContextSettings,
+    ecs: RefCell<EcsManager<VkContext>>,
+    resize_debouncer: ResizeDebouncer,
+    stretched_image_count: Option<usize>,
+    texture_streamer: Option<TextureStreamer>,
+    pending_scene_swap: Option<PendingSceneSwap>,
+    crash_context: Arc<Mutex<CrashContext>>
 }
 
 impl EngineInternals {
 
     pub fn new(
         window: &Window,
-        resource_bearer: &Box<dyn RawResourceBearer<VkContext>>
+        resource_bearer: &Box<dyn RawResourceBearer<VkContext>>,
+        timer: Box<dyn Timer>,
+        srgb_output: bool,
+        dynamic_rendering: bool,
+        depth_convention: DepthConvention,
+        stencil_buffer: bool,
+        pipeline_creation_threads: usize,
+        render_scale: Option<f32>,
+        present_wait: bool,
+        texture_quality: TextureQuality,
+        texture_streaming: Option<TextureStreamingConfig>,
+        crash_context: Arc<Mutex<CrashContext>>
     ) -> Result<Self, EngineError> {
         // Creation of required components
-        let core = unsafe { VkCore::new(&window, vec![]).unwrap() };
-        let mut context = VkContext::new(&core, &window).unwrap();
+        let features = declared_features(dynamic_rendering, present_wait);
+        let core = unsafe {
+            VkCore::new(&window, features).unwrap()
+        };
+        crash_context.lock().unwrap().device_description = core.describe_device();
+        let context_settings = ContextSettings {
+            srgb_output,
+            depth_convention,
+            stencil_buffer,
+            pipeline_creation_threads,
+            render_scale
+        };
+        let mut context = Self::create_context(&core, window, &context_settings, texture_quality)?;
         let mut ecs = EcsManager::new();
 
         // Load needed resources
@@ -33,16 +97,64 @@ impl EngineInternals {
             &mut context,
             swapchain_image_count)?;
 
+        crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+
         // Initialisation
         Ok(Self {
-            timer: StockTimer::new(),
+            timer,
             last_known_client_area_size: PhysicalSize::default(),
             render_core: RefCell::new(core),
             render_context: RefCell::new(context),
-            ecs: RefCell::new(ecs)
+            context_settings,
+            ecs: RefCell::new(ecs),
+            resize_debouncer: ResizeDebouncer::new(
+                Duration::from_millis(RESIZE_SETTLE_TIME_MILLIS)),
+            stretched_image_count: None,
+            texture_streamer: texture_streaming.map(TextureStreamer::new),
+            pending_scene_swap: None,
+            crash_context
         })
     }
 
+    /// Create a render context on the core's device with the given settings
+    fn create_context(
+        core: &VkCore,
+        window: &Window,
+        settings: &ContextSettings,
+        texture_quality: TextureQuality
+    ) -> Result<VkContext, EngineError> {
+        let mut context = VkContext::new(core, window)?;
+        let mut rebuild_swapchain = false;
+        if settings.srgb_output {
+            context.set_srgb_swapchain(true);
+            rebuild_swapchain = true;
+        }
+        if window.is_transparent() {
+            context.set_transparent_swapchain(true);
+            rebuild_swapchain = true;
+        }
+        if settings.stencil_buffer {
+            match context.set_depth_format(TexturePixelFormat::Depth24Stencil8) {
+                Ok(()) => rebuild_swapchain = true,
+                Err(e) => println!("Stencil buffer unavailable, using depth only: {:?}", e)
+            }
+        }
+        if rebuild_swapchain {
+            // The swapchain and depth formats and composite alpha are chosen on creation, so
+            // rebuild them before anything uses them
+            unsafe {
+                context.recreate_surface(core, window)?;
+                context.regenerate_graphics_command_buffers()?;
+            }
+        }
+        context.set_viewport_aspect_ratio(window.get_letterbox_aspect_ratio());
+        context.set_texture_quality(texture_quality);
+        context.set_depth_convention(settings.depth_convention);
+        context.set_pipeline_creation_threads(settings.pipeline_creation_threads);
+        context.set_render_scale(settings.render_scale)?;
+        Ok(context)
+    }
+
     pub fn engine_teardown(&mut self) {
 
         unsafe {
@@ -70,27 +182,166 @@ impl EngineInternals {
         let context = self.render_context.borrow();
         let ecs = self.ecs.borrow();
         for image_index in 0..context.get_swapchain_image_count() {
-            let command_buffer = context.get_graphics_command_buffer(image_index);
             unsafe {
-                scene.record_commands(
-                    &context.device,
-                    command_buffer,
-                    context.get_extent()?,
-                    &ecs,
-                    image_index)?;
+                Self::record_image_commands(scene, &context, &ecs, image_index)?;
             }
         }
+        self.note_recorded_passes(scene.as_ref(), None);
         Ok(())
     }
 
-    pub fn pull_time_step_millis(&mut self) -> u64 {
-        self.timer.pull_time_step_millis()
+    /// Record the scene's commands for one swapchain image, recording any secondary command
+    /// buffers it wants on worker threads first
+    unsafe fn record_image_commands(
+        scene: &Box<dyn Scene<VkContext>>,
+        context: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        image_index: usize
+    ) -> Result<(), EngineError> {
+        let render_extent = context.get_extent()?;
+        let secondary_command_buffers =
+            match scene.get_secondary_recordings(render_extent, ecs, image_index)? {
+                Some(recordings) => {
+                    let command_buffers = context.get_secondary_command_buffers(
+                        image_index,
+                        recordings.jobs.len())?;
+                    secondary::record_secondary_command_buffers(
+                        &context.device,
+                        &command_buffers,
+                        recordings)?;
+                    command_buffers
+                },
+                None => vec![]
+            };
+        scene.record_commands(
+            &context.device,
+            context.get_graphics_command_buffer(image_index),
+            render_extent,
+            ecs,
+            image_index,
+            &secondary_command_buffers)
+    }
+
+    /// Labels for scenes to mark the passes they record with; see Scene::attach_debug_labels
+    pub fn get_debug_labels(&self) -> DebugLabels {
+        self.render_context.borrow().get_debug_labels()
+    }
+
+    /// Rotation that the current swapchain presents with; see Scene::attach_pre_rotation
+    pub fn get_pre_rotation(&self) -> PreRotation {
+        self.render_context.borrow().get_pre_rotation()
+    }
+
+    /// Note the passes just recorded, for every swapchain image or only the given one
+    fn note_recorded_passes(&self, scene: &dyn Scene<VkContext>, image_index: Option<usize>) {
+        let images = match image_index {
+            Some(image_index) => format!("image {}", image_index),
+            None => "all images".to_owned()
+        };
+        let mut crash_context = self.crash_context.lock().unwrap();
+        crash_context.last_recorded_passes = Some(format!(
+            "[{}] for {} in frame {}",
+            scene.get_pass_names().join(", "),
+            images,
+            self.timer.get_frame_number()));
+    }
+
+    /// Pull the time step for a new frame from the timer, along with timing details for it
+    pub fn pull_frame_timing(&mut self, refresh_interval: Option<Duration>) -> FrameTiming {
+        let elapsed_millis = self.timer.pull_time_step_millis();
+        self.crash_context.lock().unwrap().frame_number = self.timer.get_frame_number();
+        FrameTiming::new(
+            elapsed_millis,
+            self.timer.get_frame_number().saturating_sub(1),
+            self.timer.get_elapsed_time(),
+            refresh_interval)
     }
 
     pub fn get_last_known_size(&self) -> PhysicalSize<u32> {
         self.last_known_client_area_size
     }
 
+    /// Note that the window has been resized; the surface is not rebuilt until the size settles.
+    /// Meanwhile each frame is retained where the surface allows, so that the last of them can be
+    /// stretched over the window should the swapchain go out of date; see stretch_last_frame.
+    pub fn queue_resize(&mut self, new_client_area_size: PhysicalSize<u32>) {
+        self.resize_debouncer.on_resized(
+            new_client_area_size,
+            self.last_known_client_area_size,
+            Instant::now());
+        if self.resize_debouncer.is_pending() {
+            // Without retention, frames are skipped until the resize settles instead
+            let _ = self.render_context.borrow_mut().set_frame_retention(true);
+        }
+    }
+
+    /// Rebuild the surface once the size settles, even if it stays the same, since a change of
+    /// display mode may leave the swapchain unsuitable without the window being resized
+    pub fn queue_surface_rebuild(&mut self, client_area_size: PhysicalSize<u32>) {
+        self.resize_debouncer.force(client_area_size, Instant::now());
+    }
+
+    /// Resume after rendering was suspended, rebuilding the surface at the restored size since
+    /// the swapchain may have been invalidated meanwhile, and leaving the time passed out of the
+    /// next frame's time step
+    pub fn resume_rendering(&mut self, client_area_size: PhysicalSize<u32>) {
+        self.timer.skip_time_step();
+        self.queue_surface_rebuild(client_area_size);
+    }
+
+    /// Whether the window has been resized but the surface has not yet been rebuilt
+    pub fn has_pending_resize(&self) -> bool {
+        self.resize_debouncer.is_pending()
+    }
+
+    /// Return the size to rebuild the surface at, if a resize has settled
+    pub fn take_settled_resize(&mut self) -> Option<PhysicalSize<u32>> {
+        self.resize_debouncer.take_settled(Instant::now())
+    }
+
+    /// Whether frames are presented by stretching the last one rendered, rather than by
+    /// rendering the scene, until the surface is rebuilt
+    pub fn is_stretching_frames(&self) -> bool {
+        self.stretched_image_count.is_some()
+    }
+
+    /// Rebuild just the swapchain at the surface's new size, once it has gone out of date during
+    /// a resize, and present the last frame rendered stretched over it until the resize settles
+    /// and the surface is rebuilt. The scene's resources are left as they are, since they would
+    /// only be rebuilt again when it settles. Does nothing where no frame was retained, in which
+    /// case frames are skipped until then.
+    pub fn stretch_last_frame(&mut self) -> Result<(), EngineError> {
+        let mut context = self.render_context.borrow_mut();
+        if !context.has_retained_frame() {
+            return Ok(());
+        }
+        // The scene's resources stay loaded for the image count of the swapchain stretched from
+        let core = self.render_core.borrow();
+        let image_count = context.get_swapchain_image_count();
+        unsafe {
+            context.wait_until_device_idle()?;
+            context.recreate_swapchain(&core)?;
+            context.regenerate_graphics_command_buffers()?;
+        }
+        self.stretched_image_count.get_or_insert(image_count);
+        Ok(())
+    }
+
+    /// Present the last frame rendered stretched over the next swapchain image, in place of
+    /// render_frame while stretching frames
+    pub fn present_stretched_frame(&mut self) -> Result<PresentResult, EngineError> {
+        let mut context = self.render_context.borrow_mut();
+        unsafe {
+            match context.acquire_next_image()? {
+                AcquireResult::Acquired(_) => {},
+                AcquireResult::SwapchainOutOfDate => return Ok(PresentResult::SwapchainOutOfDate),
+                AcquireResult::TimedOut => return Ok(PresentResult::FrameSkipped)
+            };
+            context.acquire_transferred_resources()?;
+            context.present_retained_frame()
+        }
+    }
+
     pub fn recreate_surface(
         &mut self,
         window: &Window,
@@ -110,30 +361,491 @@ impl EngineInternals {
         unsafe {
             let mut context = self.render_context.borrow_mut();
             let mut ecs = self.ecs.borrow_mut();
-            let swapchain_image_count = context.get_swapchain_image_count();
+            let previous_image_count = self.stretched_image_count
+                .unwrap_or(context.get_swapchain_image_count());
+            context.set_frame_retention(false)?;
             context.recreate_surface(&core, window)?;
             context.regenerate_graphics_command_buffers()?;
-            resource_bearer.reload_dynamic_resources(
+            context.set_viewport_aspect_ratio(window.get_letterbox_aspect_ratio());
+            Self::reload_after_swapchain_rebuild(
+                &resource_bearer,
                 &mut ecs,
                 &mut context,
-                swapchain_image_count)?;
+                previous_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
         }
         self.record_graphics_commands(scene)?;
         self.last_known_client_area_size = new_client_area_size;
+        self.stretched_image_count = None;
+        Ok(())
+    }
+
+    /// Rebuild the swapchain for the existing surface, such as after a present reported it
+    /// suboptimal, reloading only the scene's resources that depend on it
+    pub fn recreate_swapchain(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<(), EngineError> {
+        unsafe {
+            self.render_context.borrow().wait_until_device_idle()?;
+        }
+
+        let core = self.render_core.borrow();
+        let resource_bearer = scene.get_resource_bearer();
+        unsafe {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            let previous_image_count = context.get_swapchain_image_count();
+            context.recreate_swapchain(&core)?;
+            context.regenerate_graphics_command_buffers()?;
+            Self::reload_after_swapchain_rebuild(
+                &resource_bearer,
+                &mut ecs,
+                &mut context,
+                previous_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+        }
+        self.record_graphics_commands(scene)
+    }
+
+    fn reload_after_swapchain_rebuild(
+        resource_bearer: &Box<dyn RawResourceBearer<VkContext>>,
+        ecs: &mut EcsManager<VkContext>,
+        context: &mut VkContext,
+        previous_image_count: usize
+    ) -> Result<(), EngineError> {
+        let swapchain_image_count = context.get_swapchain_image_count();
+        match SwapchainReload::after_rebuild(previous_image_count, swapchain_image_count) {
+            SwapchainReload::SwapchainResources => resource_bearer.reload_swapchain_resources(
+                ecs,
+                context,
+                swapchain_image_count),
+            SwapchainReload::AllDynamicResources => resource_bearer.reload_dynamic_resources(
+                ecs,
+                context,
+                swapchain_image_count)
+        }
+    }
+
+    /// Recover from the device being lost, such as by a driver reset. Everything created on the
+    /// old device is released and the context recreated with the same settings, then the scene's
+    /// static and dynamic resources are loaded again through its bearer and commands recorded.
+    /// Resources added other than through the bearer, such as cells of a streamed world, are not
+    /// restored. The scene should be told afterwards, since any state it holds from the old
+    /// device is now invalid; see Scene::on_device_restored.
+    pub fn recover_from_device_loss(
+        &mut self,
+        window: &Window,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<(), EngineError> {
+        // Waiting fails on a lost device, but nothing more will execute on it either
+        unsafe {
+            if let Err(e) = self.render_context.borrow().wait_until_device_idle() {
+                println!("Tearing down lost device: {:?}", e);
+            }
+        }
+        let texture_quality = self.render_context.borrow().get_texture_quality();
+        {
+            let mut context = self.render_context.borrow_mut();
+            context.release_command_buffers()?;
+            self.ecs.borrow_mut().free_all_resources(&context)?;
+            context.teardown();
+        }
+
+        // Recreate the context and reload everything into a fresh ECS; any scene swap in
+        // progress starts again when it is finished
+        self.pending_scene_swap = None;
+        let mut context = Self::create_context(
+            &self.render_core.borrow(),
+            window,
+            &self.context_settings,
+            texture_quality)?;
+        let mut ecs = EcsManager::new();
+        let resource_bearer = scene.get_resource_bearer();
+        let swapchain_image_count = context.get_swapchain_image_count();
+        resource_bearer.initialise_static_resources(&mut ecs, &context)?;
+        resource_bearer.reload_dynamic_resources(
+            &mut ecs,
+            &mut context,
+            swapchain_image_count)?;
+        self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+        *self.render_context.borrow_mut() = context;
+        *self.ecs.borrow_mut() = ecs;
+        self.stretched_image_count = None;
+        self.record_graphics_commands(scene)
+    }
+
+    /// Start switching to another scene without stopping the current one. The next scene's
+    /// static resources are loaded while usage is tracked, so that those it shares with the
+    /// current scene, ensured with the same identities, are kept. Nothing waits for the GPU:
+    /// uploads carry on through the transfer queue while the current scene keeps rendering with
+    /// the resources its commands were recorded with, which stay alive until the swap finishes.
+    /// Until then, the current scene should not look up static resources by handle, since the
+    /// next scene may have replaced them. Once is_scene_swap_ready, call finish_scene_swap.
+    pub fn begin_scene_swap(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<(), EngineError> {
+        let context = self.render_context.borrow();
+        let mut ecs = self.ecs.borrow_mut();
+        ecs.begin_usage_tracking();
+        let loaded = scene.get_resource_bearer().initialise_static_resources(&mut ecs, &context);
+        let wanted = ecs.wanted_registry();
+        ecs.suspend_usage_tracking();
+        loaded?;
+        self.pending_scene_swap = Some(PendingSceneSwap {
+            wanted,
+            upload_value: context.get_transfer_timeline().last_submitted_value()
+        });
         Ok(())
     }
 
+    /// Query, without waiting, whether the uploads started by begin_scene_swap have completed,
+    /// so that the swap can be finished without stalling
+    pub fn is_scene_swap_ready(&self) -> Result<bool, EngineError> {
+        let Some(pending) = &self.pending_scene_swap else {
+            return Ok(true);
+        };
+        let context = self.render_context.borrow();
+        unsafe {
+            context.get_transfer_timeline().is_complete(&context.device, pending.upload_value)
+        }
+    }
+
+    /// Finish switching to the scene passed to begin_scene_swap, beginning the swap first if it
+    /// was abandoned, such as by recovering from device loss. Once the frames in flight are done
+    /// with the current scene's resources, the next scene's dynamic resources are reloaded and
+    /// its commands recorded. Only the graphics queue is waited for, not the whole device. The
+    /// registry of items held is then diffed against that of the items the next scene asked
+    /// for, and the orphans released. Returns the number of items released.
+    pub fn finish_scene_swap(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<usize, EngineError> {
+        let pending = match self.pending_scene_swap.take() {
+            Some(pending) => pending,
+            None => {
+                self.begin_scene_swap(scene)?;
+                self.pending_scene_swap.take().unwrap()
+            }
+        };
+        let released = {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            unsafe {
+                context.get_graphics_timeline().wait_all(&context.device)?;
+            }
+            let resource_bearer = scene.get_resource_bearer();
+            let swapchain_image_count = context.get_swapchain_image_count();
+            ecs.begin_usage_tracking();
+            ecs.keep_registered(&pending.wanted);
+            if let Err(e) = resource_bearer.reload_dynamic_resources(
+                &mut ecs,
+                &mut context,
+                swapchain_image_count
+            ) {
+                ecs.suspend_usage_tracking();
+                return Err(e);
+            }
+            let released = ecs.release_unused(&context);
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+            released
+        };
+        self.record_graphics_commands(scene)?;
+        Ok(released)
+    }
+
+    /// Create each pipeline permutation that the scene declares, so that its first use hits the
+    /// pipeline cache, reporting the number done and the total after each one
+    pub fn prewarm_pipelines<F>(
+        &self,
+        scene: &Box<dyn Scene<VkContext>>,
+        on_progress: F
+    ) -> Result<(), EngineError>
+        where F: FnMut(usize, usize)
+    {
+        let permutations = scene.get_pipeline_permutations();
+        if permutations.is_empty() {
+            return Ok(());
+        }
+        let context = self.render_context.borrow();
+        let ecs = self.ecs.borrow();
+        unsafe {
+            prewarm_pipelines(&context, &ecs, &permutations, on_progress)
+        }
+    }
+
+    /// Whether presents are identified, so that their arrival at the display can be observed
+    pub fn supports_present_wait(&self) -> bool {
+        self.render_context.borrow().supports_present_wait()
+    }
+
+    /// The identifier given to the most recently submitted frame's present, if any
+    pub fn get_last_present_id(&self) -> Option<u64> {
+        self.render_context.borrow().get_last_present_id()
+    }
+
+    /// Capture the next frame rendered, for collecting with take_frame_capture
+    pub fn request_frame_capture(&self) -> Result<(), EngineError> {
+        self.render_context.borrow_mut().request_frame_capture()
+    }
+
+    /// Collect the width, height and RGBA pixels of the frame last captured, if one has been
+    /// rendered since it was requested, waiting for it to finish rendering
+    pub fn take_frame_capture(&self) -> Result<Option<(u32, u32, Vec<u8>)>, EngineError> {
+        let capture = unsafe {
+            self.render_context.borrow_mut().take_frame_capture()?
+        };
+        Ok(capture.map(|(extent, pixels)| (extent.width, extent.height, pixels)))
+    }
+
+    /// Poll the presents that the latency tracker is waiting on, oldest first, stopping at the
+    /// first that has not yet reached the display
+    pub fn observe_presents(&self, tracker: &mut InputLatencyTracker) -> Result<(), EngineError> {
+        let context = self.render_context.borrow();
+        while let Some(present_id) = tracker.oldest_pending_present() {
+            match unsafe { context.present_status(present_id)? } {
+                PresentStatus::Presented => tracker.on_present_observed(present_id, Instant::now()),
+                PresentStatus::Lost => tracker.on_present_lost(present_id),
+                PresentStatus::Pending => break
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_texture_quality(&self) -> TextureQuality {
+        self.render_context.borrow().get_texture_quality()
+    }
+
+    /// Rebuild every sampler with new texture quality settings, then reload the dynamic
+    /// resources, since their descriptor sets refer to the old samplers. Resources created
+    /// statically that refer to samplers are not rebuilt.
+    pub fn apply_texture_quality(
+        &mut self,
+        quality: TextureQuality,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<(), EngineError> {
+        unsafe {
+            self.render_context.borrow().wait_until_device_idle()?;
+        }
+        {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            context.set_texture_quality(quality);
+            ecs.try_for_each_item_mut::<SamplerWrapper, _>(
+                |_, sampler| sampler.recreate(&context))?;
+            let swapchain_image_count = context.get_swapchain_image_count();
+            scene.get_resource_bearer().reload_dynamic_resources(
+                &mut ecs,
+                &mut context,
+                swapchain_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+        }
+        self.record_graphics_commands(scene)
+    }
+
+    /// Compact the device memory that resources are sub-allocated from, once the device is idle.
+    /// If anything was moved, the dynamic resources are reloaded, since their descriptor sets
+    /// refer to the old buffers and images, and commands are recorded again. Resources created
+    /// statically that refer to movable buffers or images are not rebuilt. Not allowed while a
+    /// scene swap is pending, as the incoming scene's resources are still being loaded.
+    pub fn defragment_memory(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<DefragmentReport, EngineError> {
+        if self.pending_scene_swap.is_some() {
+            return Err(EngineError::UserError(
+                String::from("Memory cannot be defragmented during a scene swap")));
+        }
+        unsafe {
+            self.render_context.borrow().wait_until_device_idle()?;
+        }
+        let report = {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            let report = unsafe { context.defragment_memory(&mut ecs)? };
+            if report.relocated_count == 0 {
+                return Ok(report);
+            }
+            let swapchain_image_count = context.get_swapchain_image_count();
+            scene.get_resource_bearer().reload_dynamic_resources(
+                &mut ecs,
+                &mut context,
+                swapchain_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+            report
+        };
+        self.record_graphics_commands(scene)?;
+        Ok(report)
+    }
+
+    /// Load and evict mip levels of streamed textures, if streaming is enabled. New images are
+    /// staged on the transfer queue without waiting, and swapped in on a later frame once their
+    /// uploads have signalled. Swapping waits for the frames in flight, then reloads dynamic
+    /// resources so that descriptor sets refer to the new images.
+    pub fn update_texture_streaming(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>
+    ) -> Result<(), EngineError> {
+        let Some(streamer) = &self.texture_streamer else {
+            return Ok(());
+        };
+        {
+            let context = self.render_context.borrow();
+            let mut ecs = self.ecs.borrow_mut();
+            let plan = streamer.plan(&context, &ecs);
+            if !plan.is_empty() {
+                unsafe {
+                    streamer.stage(&plan, &context, &mut ecs)?;
+                }
+            }
+            let ready = unsafe { streamer.has_completed_uploads(&context, &ecs)? };
+            if !ready {
+                return Ok(());
+            }
+        }
+
+        // Uploads have completed, so only the frames in flight must finish with the images and
+        // descriptor sets being replaced
+        {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            unsafe {
+                context.get_graphics_timeline().wait_all(&context.device)?;
+                streamer.swap_in_completed(&context, &mut ecs)?;
+            }
+            let swapchain_image_count = context.get_swapchain_image_count();
+            scene.get_resource_bearer().reload_dynamic_resources(
+                &mut ecs,
+                &mut context,
+                swapchain_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+        }
+        self.record_graphics_commands(scene)
+    }
+
+    /// Recompile watched shaders whose files have changed, if it is time to check them, returning
+    /// the outcome for each. When any compile, the device is waited on, their modules replaced
+    /// and dynamic resources reloaded so that pipelines are rebuilt from the new modules, as with
+    /// changes to texture quality. Errors returned are for the reload as a whole, not for shaders
+    /// that failed to compile.
+    pub fn update_shader_reloading(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>,
+        reloader: &mut ShaderReloader
+    ) -> Result<Vec<ShaderReload>, EngineError> {
+        let changed = reloader.poll_changed(Instant::now());
+        if changed.is_empty() {
+            return Ok(vec![]);
+        }
+        let (modules, reloads) = reloader.compile(
+            &changed,
+            &self.render_context.borrow(),
+            &self.ecs.borrow());
+        if modules.is_empty() {
+            return Ok(reloads);
+        }
+        unsafe {
+            self.render_context.borrow().wait_until_device_idle()?;
+        }
+        {
+            let mut context = self.render_context.borrow_mut();
+            let mut ecs = self.ecs.borrow_mut();
+            for (shader_index, module) in modules.into_iter() {
+                let handle = Handle::for_resource(shader_index);
+                if let Some(previous) = ecs.remove_item::<vk::ShaderModule>(handle) {
+                    previous.release(&context);
+                }
+                ecs.push_new_with_handle(handle, module);
+            }
+            let swapchain_image_count = context.get_swapchain_image_count();
+            scene.get_resource_bearer().reload_dynamic_resources(
+                &mut ecs,
+                &mut context,
+                swapchain_image_count)?;
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+        }
+        self.record_graphics_commands(scene)?;
+        Ok(reloads)
+    }
+
+    /// Move a streamed world's focus to where the scene wants it, then, if any cells are ready
+    /// to upload or out of range, wait for the device to be idle and upload or release them
+    pub fn update_world_streaming(
+        &mut self,
+        scene: &Box<dyn Scene<VkContext>>,
+        streamer: &mut dyn StreamsWorld<VkContext>
+    ) -> Result<(), EngineError> {
+        let Some((focus_x, focus_z)) = scene.get_streaming_focus() else {
+            return Ok(());
+        };
+        if !streamer.poll(focus_x, focus_z)? {
+            return Ok(());
+        }
+        unsafe {
+            self.render_context.borrow().wait_until_device_idle()?;
+        }
+        let changed = {
+            let context = self.render_context.borrow();
+            let mut ecs = self.ecs.borrow_mut();
+            let changed = unsafe { streamer.apply(&mut ecs, &context)? };
+            self.crash_context.lock().unwrap().resource_counts = ecs.resource_counts();
+            changed
+        };
+        match changed {
+            true => self.record_graphics_commands(scene),
+            false => Ok(())
+        }
+    }
+
     pub fn render_frame(&mut self, scene: &Box<dyn Scene<VkContext>>) -> Result<PresentResult, EngineError> {
         let mut context = self.render_context.borrow_mut();
         let ecs = self.ecs.borrow();
         unsafe {
-            let (image_index, up_to_date) = context.acquire_next_image()?;
-            if !up_to_date {
-                return Ok(PresentResult::SwapchainOutOfDate);
+            let image_index = match context.acquire_next_image()? {
+                AcquireResult::Acquired(image_index) => image_index,
+                AcquireResult::SwapchainOutOfDate => return Ok(PresentResult::SwapchainOutOfDate),
+                AcquireResult::TimedOut => return Ok(PresentResult::FrameSkipped)
+            };
+
+            // The fence for this image has been waited on, so its command buffer is free to
+            // re-record if the scene wants that
+            if scene.get_recording_strategy() == RecordingStrategy::PerFrame {
+                Self::record_image_commands(scene, &context, &ecs, image_index)?;
+                self.note_recorded_passes(scene.as_ref(), Some(image_index));
             }
 
+            // Uniform updates made while preparing the frame are staged in the transient buffer
+            // of its frame slot, and copied into place when it is submitted
             scene.prepare_frame_render(&context, image_index, &ecs)?;
+
+            // Resources uploaded on a separate transfer queue family must be acquired before use,
+            // including any that the scene updated while preparing the frame
+            context.acquire_transferred_resources()?;
             context.submit_and_present()
         }
     }
 }
+
+/// The optional device features that the engine declares, each enabled only where the device
+/// supports it. Descriptor indexing is always declared, so that scenes may use bindless texture
+/// arrays wherever they are available.
+pub(crate) fn declared_features(
+    dynamic_rendering: bool,
+    present_wait: bool
+) -> Vec<FeatureDeclaration> {
+    let mut features = vec![
+        FeatureDeclaration::CompressedTextures,
+        FeatureDeclaration::SamplerAnisotropy,
+        FeatureDeclaration::FillModeNonSolid,
+        FeatureDeclaration::MultiDrawIndirect,
+        FeatureDeclaration::DescriptorIndexing
+    ];
+    if dynamic_rendering {
+        features.push(FeatureDeclaration::DynamicRendering);
+    }
+    if present_wait {
+        features.push(FeatureDeclaration::PresentWait);
+    }
+    features
+}
@@ -0,0 +1,112 @@
+use window::PhysicalSize;
+use std::time::{Duration, Instant};
+
+/// ResizeDebouncer struct
+/// Coalesces the stream of resize events produced while a window is being live-resized, so that
+/// the surface is only rebuilt once the size has been stable for a while
+pub struct ResizeDebouncer {
+    settle_time: Duration,
+    pending: Option<(PhysicalSize<u32>, Instant)>,
+    forced: bool
+}
+
+impl ResizeDebouncer {
+
+    pub fn new(settle_time: Duration) -> Self {
+        Self {
+            settle_time,
+            pending: None,
+            forced: false
+        }
+    }
+
+    /// Note a new size reported at the given time, restarting the settle period. A size equal to
+    /// the current surface size cancels any pending resize, unless one was forced.
+    pub fn on_resized(
+        &mut self,
+        size: PhysicalSize<u32>,
+        current_size: PhysicalSize<u32>,
+        now: Instant
+    ) {
+        self.pending = match size == current_size && !self.forced {
+            true => None,
+            false => Some((size, now))
+        };
+    }
+
+    /// Rebuild the surface once the settle period passes even if the size ends up unchanged, such
+    /// as after the display mode changes
+    pub fn force(&mut self, size: PhysicalSize<u32>, now: Instant) {
+        self.pending = Some((size, now));
+        self.forced = true;
+    }
+
+    /// Whether a resize has been reported but not yet acted upon
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Return the new size if one has been stable for long enough, clearing it
+    pub fn take_settled(&mut self, now: Instant) -> Option<PhysicalSize<u32>> {
+        match self.pending {
+            Some((size, reported_at)) if now.duration_since(reported_at) >= self.settle_time => {
+                self.pending = None;
+                self.forced = false;
+                Some(size)
+            },
+            _ => None
+        }
+    }
+}
+
+/// RenderSuspension struct
+/// Tracks whether the window can be seen. Minimized windows report a zero-size client area, and
+/// occluded ones are hidden behind others or on a locked screen; nothing is updated or rendered
+/// in either case, until the window is restored.
+#[derive(Default)]
+pub struct RenderSuspension {
+    minimized: bool,
+    occluded: bool
+}
+
+impl RenderSuspension {
+
+    pub fn is_suspended(&self) -> bool {
+        self.minimized || self.occluded
+    }
+
+    /// Note a new client area size, returning true if rendering should resume
+    pub fn on_resized(&mut self, size: PhysicalSize<u32>) -> bool {
+        let was_suspended = self.is_suspended();
+        self.minimized = size.width == 0 || size.height == 0;
+        was_suspended && !self.is_suspended()
+    }
+
+    /// Note whether the window is occluded, returning true if rendering should resume
+    pub fn on_occluded(&mut self, occluded: bool) -> bool {
+        let was_suspended = self.is_suspended();
+        self.occluded = occluded;
+        was_suspended && !self.is_suspended()
+    }
+}
+
+/// SwapchainReload enum
+/// Which of a scene's dynamic resources need reloading after the swapchain has been rebuilt. Only
+/// those that depend on the swapchain need to be, unless its image count has changed, in which
+/// case everything per-image is reloaded.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SwapchainReload {
+    SwapchainResources,
+    AllDynamicResources
+}
+
+impl SwapchainReload {
+
+    /// Choose what to reload given the image counts before and after rebuilding
+    pub fn after_rebuild(previous_image_count: usize, new_image_count: usize) -> Self {
+        match previous_image_count == new_image_count {
+            true => SwapchainReload::SwapchainResources,
+            false => SwapchainReload::AllDynamicResources
+        }
+    }
+}
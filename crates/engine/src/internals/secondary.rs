@@ -0,0 +1,45 @@
+use crate::SecondaryRecordings;
+use ash::{Device, vk};
+use error::EngineError;
+
+/// Record a scene's secondary command buffers, one job into each buffer, splitting the jobs
+/// between as many threads as there are cores. If any job fails, the first error is returned.
+pub unsafe fn record_secondary_command_buffers(
+    device: &Device,
+    command_buffers: &[vk::CommandBuffer],
+    recordings: SecondaryRecordings
+) -> Result<(), EngineError> {
+    let SecondaryRecordings { inheritance, jobs } = recordings;
+    let mut work: Vec<_> = command_buffers.iter().copied().zip(jobs.into_iter()).collect();
+    if work.is_empty() {
+        return Ok(());
+    }
+    let available_threads = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1);
+    let chunk_size = work.len().div_ceil(available_threads.clamp(1, work.len()));
+
+    // Each buffer is in a pool of its own, so threads may record into them at the same time
+    let inheritance = &inheritance;
+    let results: Vec<Result<(), EngineError>> = std::thread::scope(|scope| {
+        let mut threads = vec![];
+        while !work.is_empty() {
+            let chunk: Vec<_> = work.drain(..chunk_size.min(work.len())).collect();
+            threads.push(scope.spawn(move || {
+                for (command_buffer, job) in chunk.into_iter() {
+                    inheritance.begin(device, command_buffer)?;
+                    job(device, command_buffer)?;
+                    device.end_command_buffer(command_buffer)
+                        .map_err(|e| EngineError::OpFailed(
+                            format!("Error ending secondary command buffer: {:?}", e)))?;
+                }
+                Ok(())
+            }));
+        }
+        threads.into_iter()
+            .map(|thread| thread.join().unwrap_or_else(|_| Err(EngineError::OpFailed(
+                String::from("Secondary command recording thread panicked")))))
+            .collect()
+    });
+    results.into_iter().collect()
+}
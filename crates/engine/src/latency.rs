@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Number of most recent samples kept for each measurement
+const MAX_SAMPLES: usize = 4096;
+
+/// PresentTiming enum
+/// How the time at which a frame was presented is found. Where presents cannot be waited on,
+/// the time that queueing the present returned is used, which is earlier than the frame reaching
+/// the display by however much work is still queued ahead of it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PresentTiming {
+    PresentWait,
+    QueuePresentReturn
+}
+
+/// LatencyStats struct
+/// Summary of a set of latency samples
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub p95: Duration,
+    pub max: Duration
+}
+
+impl LatencyStats {
+
+    /// Summarise the samples, or return None if there are none
+    pub fn from_samples(samples: &VecDeque<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+        sorted.sort();
+        let count = sorted.len();
+        let total: Duration = sorted.iter().sum();
+        let p95_index = (count * 95).div_ceil(100).max(1) - 1;
+        Some(Self {
+            count,
+            min: sorted[0],
+            mean: total / count as u32,
+            p95: sorted[p95_index],
+            max: sorted[count - 1]
+        })
+    }
+}
+
+impl Display for LatencyStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} samples, min {:.2} ms, mean {:.2} ms, p95 {:.2} ms, max {:.2} ms",
+            self.count,
+            self.min.as_secs_f64() * 1000.0,
+            self.mean.as_secs_f64() * 1000.0,
+            self.p95.as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0)
+    }
+}
+
+/// LatencyReport struct
+/// End-to-end input latency, from receipt of input events to the scene update that consumed
+/// them, and to the present of the frame that update produced
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LatencyReport {
+    pub receipt_to_update: Option<LatencyStats>,
+    pub receipt_to_present: Option<LatencyStats>,
+    pub present_timing: PresentTiming,
+    pub lost_presents: usize
+}
+
+impl Display for LatencyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Input latency (present timing from {:?}):", self.present_timing)?;
+        match &self.receipt_to_update {
+            Some(stats) => writeln!(f, "  receipt to update: {}", stats)?,
+            None => writeln!(f, "  receipt to update: no samples")?
+        };
+        match &self.receipt_to_present {
+            Some(stats) => write!(f, "  receipt to present: {}", stats)?,
+            None => write!(f, "  receipt to present: no samples")?
+        };
+        if self.lost_presents > 0 {
+            write!(f, "\n  {} presents lost to swapchain recreation", self.lost_presents)?;
+        }
+        Ok(())
+    }
+}
+
+/// InputLatencyTracker struct
+/// Timestamps input events as they arrive from the window system, as the scene update consumes
+/// them, and as the frame rendered after that update is presented. Input consumed by an update
+/// is carried over to the next frame actually submitted, since frames may be skipped. Where the
+/// present is identified, the frame waits in a queue until the present is observed; observation
+/// happens by polling once per frame, so is late by up to a frame.
+pub struct InputLatencyTracker {
+    present_timing: PresentTiming,
+    received: Vec<Instant>,
+    awaiting_submit: Vec<Instant>,
+    awaiting_present: VecDeque<(u64, Vec<Instant>)>,
+    receipt_to_update: VecDeque<Duration>,
+    receipt_to_present: VecDeque<Duration>,
+    lost_presents: usize
+}
+
+impl InputLatencyTracker {
+
+    pub fn new(present_timing: PresentTiming) -> Self {
+        Self {
+            present_timing,
+            received: vec![],
+            awaiting_submit: vec![],
+            awaiting_present: VecDeque::new(),
+            receipt_to_update: VecDeque::new(),
+            receipt_to_present: VecDeque::new(),
+            lost_presents: 0
+        }
+    }
+
+    pub fn get_present_timing(&self) -> PresentTiming {
+        self.present_timing
+    }
+
+    /// Change how presents are timed, such as once the device's support is known; anything
+    /// awaiting a present is discarded
+    pub fn set_present_timing(&mut self, present_timing: PresentTiming) {
+        self.present_timing = present_timing;
+        self.awaiting_present.clear();
+    }
+
+    /// Record an input event arriving from the window system
+    pub fn on_input_received(&mut self, at: Instant) {
+        self.received.push(at);
+    }
+
+    /// Record that the scene update has consumed all input received so far
+    pub fn on_update(&mut self, at: Instant) {
+        for received in self.received.drain(..) {
+            push_sample(&mut self.receipt_to_update, at.saturating_duration_since(received));
+            self.awaiting_submit.push(received);
+        }
+    }
+
+    /// Record that a frame was submitted for presentation. With no present ID, the frame counts
+    /// as presented at the given time.
+    pub fn on_frame_submitted(&mut self, present_id: Option<u64>, at: Instant) {
+        if self.awaiting_submit.is_empty() {
+            return;
+        }
+        let inputs = std::mem::take(&mut self.awaiting_submit);
+        match (self.present_timing, present_id) {
+            (PresentTiming::PresentWait, Some(present_id)) =>
+                self.awaiting_present.push_back((present_id, inputs)),
+            _ => self.complete(&inputs, at)
+        }
+    }
+
+    /// The oldest present that input is waiting on
+    pub fn oldest_pending_present(&self) -> Option<u64> {
+        self.awaiting_present.front().map(|(present_id, _)| *present_id)
+    }
+
+    /// Record that the present with the given ID has been observed to reach the display, which
+    /// implies all earlier presents have too
+    pub fn on_present_observed(&mut self, present_id: u64, at: Instant) {
+        while let Some((pending_id, _)) = self.awaiting_present.front() {
+            if *pending_id > present_id {
+                break;
+            }
+            let (_, inputs) = self.awaiting_present.pop_front().unwrap();
+            self.complete(&inputs, at);
+        }
+    }
+
+    /// Record that the present with the given ID will never be observed, dropping its input
+    pub fn on_present_lost(&mut self, present_id: u64) {
+        while let Some((pending_id, _)) = self.awaiting_present.front() {
+            if *pending_id > present_id {
+                break;
+            }
+            self.awaiting_present.pop_front();
+            self.lost_presents += 1;
+        }
+    }
+
+    /// Summarise the samples collected so far
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            receipt_to_update: LatencyStats::from_samples(&self.receipt_to_update),
+            receipt_to_present: LatencyStats::from_samples(&self.receipt_to_present),
+            present_timing: self.present_timing,
+            lost_presents: self.lost_presents
+        }
+    }
+
+    /// Discard all samples, such as after changing frame pacing settings
+    pub fn reset(&mut self) {
+        self.receipt_to_update.clear();
+        self.receipt_to_present.clear();
+        self.lost_presents = 0;
+    }
+
+    fn complete(&mut self, inputs: &[Instant], at: Instant) {
+        for received in inputs.iter() {
+            push_sample(&mut self.receipt_to_present, at.saturating_duration_since(*received));
+        }
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() == MAX_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
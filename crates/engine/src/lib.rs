@@ -1,15 +1,51 @@
 mod internals;
+mod crash;
+mod input;
+mod latency;
+mod persistence;
+mod random;
+mod scheduler;
 mod core;
 mod scene;
+mod shader_reload;
+mod simple;
+mod streaming;
 mod timer;
+mod viewport;
 
 pub use crate::core::Engine;
+pub use simple::{run_simple, SimpleConfig, SimpleFrame};
 pub use scene::{
     Scene,
     SceneFactory,
+    RecordingStrategy,
+    SecondaryRecording,
+    SecondaryRecordings,
     stock::{StockScene, StockResourceBearer},
     null::NullScene
 };
 pub use error::EngineError;
-pub use timer::{Timer, stock::StockTimer};
+pub use color::Color;
+pub use persistence::{
+    write_atomic,
+    backup_path,
+    dirs::{DataDirKind, config_dir, save_dir, log_dir, platform_dir},
+    slots::{SaveSlots, LoadedSave}
+};
+pub use input::{
+    InputMap, InputState, InputBindings, InputSource, AxisBinding, GamepadButton, GamepadAxis,
+    MOVE_X_AXIS, MOVE_Y_AXIS
+};
+pub use random::{RandomService, RandomStream};
+pub use scheduler::{Scheduler, Sequence, TaskId};
+pub use streaming::{
+    CellId, CellReader, CellUploader, StreamsWorld, WorldStreamer, WorldStreamingConfig,
+    ColladaCellReader, StaticModelUploader
+};
+pub use shader_reload::ShaderReloader;
+pub use latency::{InputLatencyTracker, LatencyReport, LatencyStats, PresentTiming};
+pub use timer::{Timer, stock::StockTimer, test::TestTimer};
 pub use vk_renderer::VkContext;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,89 @@
+use error::EngineError;
+use std::path::PathBuf;
+
+/// DataDirKind enum
+/// The kinds of per-user directory that an app may keep files in
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DataDirKind {
+    Config,
+    Saves,
+    Logs
+}
+
+/// Directory for the app's settings, following the conventions of the current platform:
+/// %APPDATA% on Windows, ~/Library/Application Support on macOS and $XDG_CONFIG_HOME elsewhere
+pub fn config_dir(app_name: &str) -> Result<PathBuf, EngineError> {
+    platform_dir(DataDirKind::Config, app_name, |key| std::env::var(key).ok())
+}
+
+/// Directory for the app's save files, following the conventions of the current platform:
+/// %APPDATA% on Windows, ~/Library/Application Support on macOS and $XDG_DATA_HOME elsewhere
+pub fn save_dir(app_name: &str) -> Result<PathBuf, EngineError> {
+    platform_dir(DataDirKind::Saves, app_name, |key| std::env::var(key).ok())
+}
+
+/// Directory for the app's logs and crash reports, following the conventions of the current
+/// platform: %LOCALAPPDATA% on Windows, ~/Library/Logs on macOS and $XDG_STATE_HOME elsewhere
+pub fn log_dir(app_name: &str) -> Result<PathBuf, EngineError> {
+    platform_dir(DataDirKind::Logs, app_name, |key| std::env::var(key).ok())
+}
+
+/// Resolve a directory of the given kind for the current platform, reading environment variables
+/// through the supplied lookup
+pub fn platform_dir<F>(kind: DataDirKind, app_name: &str, env: F) -> Result<PathBuf, EngineError>
+    where F: Fn(&str) -> Option<String>
+{
+    if app_name.is_empty() || app_name.contains(['/', '\\']) {
+        return Err(EngineError::UserError(
+            format!("Invalid app name for directory: {:?}", app_name)));
+    }
+    let base = base_dir(kind, &env)
+        .ok_or_else(|| EngineError::MissingResource(
+            "Could not determine the user's data directory".to_owned()))?;
+    let dir = base.join(app_name);
+    Ok(match kind {
+        DataDirKind::Config => dir,
+        DataDirKind::Saves if cfg!(any(target_os = "windows", target_os = "macos")) =>
+            dir.join("saves"),
+        DataDirKind::Saves => dir,
+        DataDirKind::Logs if cfg!(target_os = "windows") => dir.join("logs"),
+        DataDirKind::Logs => dir
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn base_dir<F: Fn(&str) -> Option<String>>(kind: DataDirKind, env: &F) -> Option<PathBuf> {
+    let key = match kind {
+        DataDirKind::Logs => "LOCALAPPDATA",
+        _ => "APPDATA"
+    };
+    non_empty(env(key)).map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn base_dir<F: Fn(&str) -> Option<String>>(kind: DataDirKind, env: &F) -> Option<PathBuf> {
+    let library = non_empty(env("HOME")).map(|home| PathBuf::from(home).join("Library"))?;
+    Some(match kind {
+        DataDirKind::Logs => library.join("Logs"),
+        _ => library.join("Application Support")
+    })
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn base_dir<F: Fn(&str) -> Option<String>>(kind: DataDirKind, env: &F) -> Option<PathBuf> {
+    let (xdg_key, home_relative) = match kind {
+        DataDirKind::Config => ("XDG_CONFIG_HOME", ".config"),
+        DataDirKind::Saves => ("XDG_DATA_HOME", ".local/share"),
+        DataDirKind::Logs => ("XDG_STATE_HOME", ".local/state")
+    };
+
+    // The XDG spec requires these to be absolute, and says to ignore them otherwise
+    non_empty(env(xdg_key))
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| non_empty(env("HOME")).map(|home| PathBuf::from(home).join(home_relative)))
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
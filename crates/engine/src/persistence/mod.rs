@@ -0,0 +1,48 @@
+pub mod dirs;
+pub mod slots;
+
+use error::EngineError;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Path of the backup kept alongside a file written by write_atomic
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling_with_suffix(path, ".bak")
+}
+
+/// Replace the contents of a file without ever leaving it partially written. The data goes to a
+/// temporary file beside the target, which is synced and then renamed over it, so that the file
+/// exists throughout. The previous contents, if any, are first copied to a backup (see
+/// backup_path) so that a later corrupt write can be recovered from.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), EngineError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating directory {:?}: {:?}", parent, e)))?;
+    }
+    let temp_path = sibling_with_suffix(path, ".tmp");
+    {
+        let mut file = fs::File::create(&temp_path)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating file {:?}: {:?}", temp_path, e)))?;
+        file.write_all(data)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error writing file {:?}: {:?}", temp_path, e)))?;
+    }
+    if path.exists() {
+        fs::copy(path, backup_path(path))
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error backing up file {:?}: {:?}", path, e)))?;
+    }
+    fs::rename(&temp_path, path)
+        .map_err(|e| EngineError::OpFailed(
+            format!("Error replacing file {:?}: {:?}", path, e)))
+}
+
+fn sibling_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
@@ -0,0 +1,192 @@
+use crate::persistence::{dirs, write_atomic, backup_path, sibling_with_suffix};
+use error::EngineError;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Extension given to save slot files
+const SAVE_FILE_EXTENSION: &str = "toml";
+
+/// SaveFile struct
+/// On-disk layout of a save slot; the checksum covers the serialized data table
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+    version: u32,
+    checksum: String,
+    data: toml::Value
+}
+
+/// LoadedSave struct
+/// Data read back from a save slot, along with the version of the app that wrote it, so that
+/// older saves can be migrated, and whether the slot had to be recovered from its backup
+#[derive(Debug)]
+pub struct LoadedSave<T> {
+    pub data: T,
+    pub version: u32,
+    pub restored_from_backup: bool
+}
+
+/// SaveSlots struct
+/// Named save slots stored as files in a directory. Each write is atomic and keeps the previous
+/// contents as a backup; loading a slot that fails its checksum restores the backup instead.
+/// Save data may be any type that serializes to a table, such as a struct or map.
+pub struct SaveSlots {
+    dir: PathBuf,
+    version: u32
+}
+
+impl SaveSlots {
+
+    /// Create a new instance using the platform's save directory for the app. Saves are stamped
+    /// with the given version, and saves from later versions are refused.
+    pub fn new(app_name: &str, version: u32) -> Result<Self, EngineError> {
+        Ok(Self::in_dir(dirs::save_dir(app_name)?, version))
+    }
+
+    /// Create a new instance using the given directory, which is created on first save
+    pub fn in_dir(dir: PathBuf, version: u32) -> Self {
+        Self { dir, version }
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write data to the named slot, replacing what was there
+    pub fn save<T: Serialize>(&self, slot: &str, data: &T) -> Result<(), EngineError> {
+        let path = self.slot_path(slot)?;
+        let data = toml::Value::try_from(data)
+            .map_err(|e| EngineError::UserError(format!("Error serializing save: {:?}", e)))?;
+        if !data.is_table() {
+            return Err(EngineError::UserError(
+                "Save data must serialize to a table, such as a struct or map".to_owned()));
+        }
+        let save_file = SaveFile {
+            version: self.version,
+            checksum: checksum(&data)?,
+            data
+        };
+        let contents = toml::to_string(&save_file)
+            .map_err(|e| EngineError::OpFailed(format!("Error serializing save: {:?}", e)))?;
+        write_atomic(&path, contents.as_bytes())
+    }
+
+    /// Read the named slot, or None if it has never been saved. If the slot is corrupt but its
+    /// backup is intact, the backup is restored in its place and returned.
+    pub fn load<T: DeserializeOwned>(
+        &self,
+        slot: &str
+    ) -> Result<Option<LoadedSave<T>>, EngineError> {
+        let path = self.slot_path(slot)?;
+        let (save_file, restored_from_backup) = match read_save_file(&path) {
+            Ok(Some(save_file)) => (save_file, false),
+            main_result => {
+                let backup = backup_path(&path);
+                match read_save_file(&backup) {
+                    Ok(Some(save_file)) => {
+                        restore_backup(&backup, &path)?;
+                        (save_file, true)
+                    },
+                    Ok(None) => return main_result.map(|_| None),
+                    Err(e) => return Err(main_result.err().unwrap_or(e))
+                }
+            }
+        };
+        if save_file.version > self.version {
+            return Err(EngineError::Compatibility(format!(
+                "Save slot {} was written by version {}, newer than this version {}",
+                slot,
+                save_file.version,
+                self.version)));
+        }
+        let data = save_file.data.try_into::<T>()
+            .map_err(|e| EngineError::Compatibility(format!(
+                "Save slot {} from version {} does not match the expected data: {:?}",
+                slot,
+                save_file.version,
+                e)))?;
+        Ok(Some(LoadedSave {
+            data,
+            version: save_file.version,
+            restored_from_backup
+        }))
+    }
+
+    /// Names of the slots that have been saved, in no particular order
+    pub fn list_slots(&self) -> Result<Vec<String>, EngineError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(EngineError::OpFailed(
+                format!("Error reading directory {:?}: {:?}", self.dir, e)))
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == SAVE_FILE_EXTENSION))
+            .filter_map(|path| path.file_stem()?.to_str().map(str::to_owned))
+            .collect())
+    }
+
+    /// Remove the named slot and its backup
+    pub fn delete(&self, slot: &str) -> Result<(), EngineError> {
+        let path = self.slot_path(slot)?;
+        for path in [backup_path(&path), path] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(EngineError::OpFailed(
+                    format!("Error deleting file {:?}: {:?}", path, e))),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of a slot's file; names are limited to letters, digits, '-' and '_' so that they
+    /// can't escape the save directory
+    fn slot_path(&self, slot: &str) -> Result<PathBuf, EngineError> {
+        let valid = !slot.is_empty() &&
+            slot.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(EngineError::UserError(format!("Invalid save slot name: {:?}", slot)));
+        }
+        Ok(self.dir.join(format!("{}.{}", slot, SAVE_FILE_EXTENSION)))
+    }
+}
+
+/// Read and verify a save file, or None if it doesn't exist
+fn read_save_file(path: &Path) -> Result<Option<SaveFile>, EngineError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(EngineError::OpFailed(
+            format!("Error reading file {:?}: {:?}", path, e)))
+    };
+    let save_file: SaveFile = toml::from_str(&contents)
+        .map_err(|e| EngineError::OpFailed(format!("Corrupt save file {:?}: {:?}", path, e)))?;
+    if checksum(&save_file.data)? != save_file.checksum {
+        return Err(EngineError::OpFailed(format!("Corrupt save file {:?}: bad checksum", path)));
+    }
+    Ok(Some(save_file))
+}
+
+/// Put a backup back in place of its corrupt or missing original, leaving the backup intact
+fn restore_backup(backup: &Path, path: &Path) -> Result<(), EngineError> {
+    let temp_path = sibling_with_suffix(path, ".tmp");
+    fs::copy(backup, &temp_path)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map(|_| ())
+        .map_err(|e| EngineError::OpFailed(
+            format!("Error restoring backup {:?}: {:?}", backup, e)))
+}
+
+/// FNV-1a hash of the data table as serialized, in hex; this detects damaged files rather than
+/// deliberate tampering
+fn checksum(data: &toml::Value) -> Result<String, EngineError> {
+    let serialized = toml::to_string(data)
+        .map_err(|e| EngineError::OpFailed(format!("Error serializing save: {:?}", e)))?;
+    let hash = serialized.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    Ok(format!("{:016x}", hash))
+}
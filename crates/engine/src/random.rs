@@ -0,0 +1,162 @@
+use serde::{Serialize, Deserialize, Serializer, Deserializer, de::Error};
+use std::collections::BTreeMap;
+
+/// RandomStream struct
+/// A deterministic xoshiro256** generator. The same seed always produces the same sequence on
+/// every platform, and the state can be serialized to resume the sequence later.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RandomStream {
+    #[serde(serialize_with = "serialize_state", deserialize_with = "deserialize_state")]
+    state: [u64; 4]
+}
+
+impl RandomStream {
+
+    /// Create a new stream, expanding the seed into the full state with SplitMix64
+    pub fn new(seed: u64) -> Self {
+        let mut splitmix_state = seed;
+        let mut state = [0u64; 4];
+        for word in state.iter_mut() {
+            splitmix_state = splitmix_state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = splitmix_state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *word = z ^ (z >> 31);
+        }
+        Self { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Uniformly distributed value in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniformly distributed value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniformly distributed integer in [min, max); returns min if the range is empty
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+
+        // Multiply-shift maps a 32-bit value into the range; rejecting the few values that would
+        // fall into the uneven remainder keeps the result unbiased
+        let span = max - min;
+        let threshold = span.wrapping_neg() % span;
+        loop {
+            let product = self.next_u32() as u64 * span as u64;
+            if (product as u32) >= threshold {
+                return min + (product >> 32) as u32;
+            }
+        }
+    }
+
+    /// Uniformly distributed value in [min, max)
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Return true with the given probability
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
+
+/// RandomService struct
+/// Shared source of randomness for gameplay. Each system draws from its own named stream, derived
+/// from the service's seed and the name, so that systems don't disturb each other's sequences
+/// and a replay with the same seed and inputs plays out identically. The whole service can be
+/// serialized, such as into a save slot, to resume every stream where it left off.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RandomService {
+    #[serde(serialize_with = "serialize_seed", deserialize_with = "deserialize_seed")]
+    seed: u64,
+    streams: BTreeMap<String, RandomStream>
+}
+
+impl RandomService {
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            streams: BTreeMap::new()
+        }
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Start over with a new seed, discarding the state of every stream
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.streams.clear();
+    }
+
+    /// Get the named stream, creating it on first use; its sequence depends only on the seed and
+    /// the name, not on when it was first used
+    pub fn stream(&mut self, name: &str) -> &mut RandomStream {
+        let seed = self.seed;
+        self.streams.entry(name.to_owned())
+            .or_insert_with(|| RandomStream::new(seed ^ name_hash(name)))
+    }
+}
+
+/// FNV-1a hash of a stream name, mixed into the service seed to derive each stream's seed
+fn name_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// 64-bit values are stored as hex strings, since some formats (such as TOML, used by save slots)
+// can't hold integers above i64::MAX
+
+fn serialize_seed<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016x}", seed))
+}
+
+fn deserialize_seed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    u64::from_str_radix(&hex, 16).map_err(D::Error::custom)
+}
+
+fn serialize_state<S: Serializer>(state: &[u64; 4], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = state.iter().map(|word| format!("{:016x}", word)).collect();
+    serializer.serialize_str(&hex)
+}
+
+fn deserialize_state<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u64; 4], D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(D::Error::custom("Random stream state must be 64 hex digits"));
+    }
+    let mut state = [0u64; 4];
+    for (index, word) in state.iter_mut().enumerate() {
+        *word = u64::from_str_radix(&hex[index * 16..(index + 1) * 16], 16)
+            .map_err(D::Error::custom)?;
+    }
+    if state == [0; 4] {
+        return Err(D::Error::custom("Random stream state must not be all zero"));
+    }
+    Ok(state)
+}
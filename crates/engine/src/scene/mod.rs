@@ -1,10 +1,41 @@
 pub mod null;
 pub mod stock;
 
-use vk_renderer::VkContext;
+use crate::{RandomService, Scheduler, InputState};
+use vk_renderer::{
+    VkContext, PipelinePermutation, DepthConvention, SecondaryInheritance, DebugLabels,
+    PreRotation
+};
 use ecs::{EcsManager, resource::RawResourceBearer};
 use error::EngineError;
 use ash::{Device, vk};
+use std::{cell::RefCell, rc::Rc};
+
+/// RecordingStrategy enum
+/// How a scene's graphics commands are recorded. Prerecorded commands are recorded once per
+/// swapchain image and reused every frame, which suits static scenes; per-frame recording suits
+/// scenes whose draw calls change every frame.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum RecordingStrategy {
+    #[default]
+    Prerecorded,
+    PerFrame
+}
+
+/// SecondaryRecording type
+/// A job recording commands into one secondary command buffer, which has already been begun and
+/// is ended after the job returns. Jobs run on worker threads, so capture only what they need,
+/// such as pipeline and buffer handles, rather than references into the scene.
+pub type SecondaryRecording =
+    Box<dyn FnOnce(&Device, vk::CommandBuffer) -> Result<(), EngineError> + Send>;
+
+/// SecondaryRecordings struct
+/// The secondary command buffers that a scene wants recorded for one swapchain image, such as one
+/// per object, all continuing the same rendering
+pub struct SecondaryRecordings {
+    pub inheritance: SecondaryInheritance,
+    pub jobs: Vec<SecondaryRecording>
+}
 
 pub trait SceneFactory<L> {
     fn get_scene(&self) -> Box<dyn Scene<L>>;
@@ -15,18 +46,95 @@ pub trait Scene<L> {
     /// Build an object that bears resources
     fn get_resource_bearer(&self) -> Box<dyn RawResourceBearer<L>>;
 
-    /// Record commands once such that they can be executed later once per frame
+    /// Decide how commands are recorded; this is checked every frame, so may change at run-time
+    fn get_recording_strategy(&self) -> RecordingStrategy {
+        RecordingStrategy::Prerecorded
+    }
+
+    /// Jobs recording secondary command buffers for a swapchain image, for scenes with enough draw
+    /// calls that recording them all on one thread is slow. Called just before record_commands
+    /// whenever it is, with the jobs run on worker threads, each into a buffer of its own begun
+    /// with the given inheritance.
+    unsafe fn get_secondary_recordings(
+        &self,
+        _render_extent: vk::Extent2D,
+        _ecs: &EcsManager<L>,
+        _swapchain_image_index: usize
+    ) -> Result<Option<SecondaryRecordings>, EngineError> {
+        Ok(None)
+    }
+
+    /// Record commands such that they can be executed later, either once per frame or for only
+    /// the next frame, depending on the recording strategy. Any secondary command buffers
+    /// recorded from get_secondary_recordings are given in the order of their jobs, to be executed
+    /// within rendering begun for secondary buffers; otherwise the slice is empty.
     unsafe fn record_commands(
         &self,
         device: &Device,
         command_buffer: vk::CommandBuffer,
         render_extent: vk::Extent2D,
         ecs: &EcsManager<L>,
-        swapchain_image_index: usize
+        swapchain_image_index: usize,
+        secondary_command_buffers: &[vk::CommandBuffer]
     ) -> Result<(), EngineError>;
 
-    /// Perform per-frame state updates
-    fn update(&mut self, time_step_millis: u64, control_dx: f32, control_dy: f32);
+    /// Names of the passes recorded by record_commands, in order, for diagnostics such as crash
+    /// reports
+    fn get_pass_names(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Receive the engine's random number service before the first update; scenes needing
+    /// reproducible randomness should keep it and draw from their own named streams
+    fn attach_random_service(&mut self, _random: Rc<RefCell<RandomService>>) {}
+
+    /// Receive the engine's scheduler before the first update, for running delayed and
+    /// repeating callbacks and sequences; it advances just before each update
+    fn attach_scheduler(&mut self, _scheduler: Rc<RefCell<Scheduler>>) {}
+
+    /// Receive the depth convention that the engine's pipelines use before commands are first
+    /// recorded; scenes should clear depth to its clear value and project to match it
+    fn attach_depth_convention(&mut self, _convention: DepthConvention) {}
+
+    /// Receive labels for marking the passes that record_commands records, before commands are
+    /// first recorded, so that debugging tools such as RenderDoc show them grouped by pass; they
+    /// only have an effect in debug builds
+    fn attach_debug_labels(&mut self, _labels: DebugLabels) {}
+
+    /// Receive the rotation that swapchain images are presented with, before commands are first
+    /// recorded and again whenever the swapchain is rebuilt; scenes should apply its clip-space
+    /// matrix after their projection, so that the compositor needn't rotate each frame
+    fn attach_pre_rotation(&mut self, _rotation: PreRotation) {}
+
+    /// Called after the device was lost, such as by a driver reset, and everything recreated,
+    /// with the bearer's resources loaded again under the same handles and commands re-recorded.
+    /// Anything the scene created on the device itself, or cached from resources it looked up,
+    /// must be rebuilt; the debug labels and pre-rotation are attached again just before this.
+    fn on_device_restored(&mut self) {}
+
+    /// Hand over to another scene, checked after every update. Resources that the next scene's
+    /// bearer ensures rather than pushes are kept if already loaded for the same identity;
+    /// anything the next scene does not ask for is released once it has loaded. This scene keeps
+    /// updating and rendering until the next one's uploads have completed.
+    fn take_next_scene(&mut self) -> Option<Box<dyn Scene<L>>> {
+        None
+    }
+
+    /// The horizontal position, as x and z, around which a streamed world keeps its cells
+    /// loaded; see Engine::set_world_streamer. Checked after every update.
+    fn get_streaming_focus(&self) -> Option<(f32, f32)> {
+        None
+    }
+
+    /// Pipeline permutations that this scene may create after loading, such as for materials
+    /// that only appear later. Each is created once during loading, after the scene's resources,
+    /// so that creating it again on first use finds it in the pipeline cache.
+    fn get_pipeline_permutations(&self) -> Vec<PipelinePermutation> {
+        vec![]
+    }
+
+    /// Perform per-frame state updates, given the state of the input map's actions and axes
+    fn update(&mut self, time_step_millis: u64, input: &InputState);
 
     /// Prepare for rendering a frame
     unsafe fn prepare_frame_render(
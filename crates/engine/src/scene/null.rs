@@ -3,7 +3,7 @@ use ecs::{EcsManager, resource::RawResourceBearer};
 use error::EngineError;
 use vk_renderer::VkContext;
 use ash::{Device, vk};
-use crate::Scene;
+use crate::{Scene, InputState};
 
 pub struct NullScene {}
 
@@ -27,12 +27,13 @@ impl Scene<VkContext> for NullScene {
         _command_buffer: vk::CommandBuffer,
         _render_extent: vk::Extent2D,
         _ecs: &EcsManager<VkContext>,
-        _swapchain_image_index: usize
+        _swapchain_image_index: usize,
+        _secondary_command_buffers: &[vk::CommandBuffer]
     ) -> Result<(), EngineError> {
         Ok(())
     }
 
-    fn update(&mut self, _time_step_millis: u64, _control_dx: f32, _control_dy: f32) {}
+    fn update(&mut self, _time_step_millis: u64, _input: &InputState) {}
 
     unsafe fn prepare_frame_render(
         &self,
@@ -1,18 +1,25 @@
 
-use crate::Scene;
+use crate::{Scene, InputState};
 use camera::PlayerCamera;
+use color::Color;
 use ecs::{EcsManager, Handle, resource::{RawResourceBearer, Resource}};
 use error::EngineError;
 use model::{StaticVertex, COLLADA, Config};
 use vk_renderer::{
     VkContext, TextureCodec, ResourceUtilities, RenderpassWrapper, PipelineWrapper,
-    BufferWrapper, BufferUsage, ImageUsage, VboCreationData, ShaderCreationData, ShaderStage,
-    RenderpassCreationData, DescriptorSetLayoutCreationData, PipelineLayoutCreationData,
-    PipelineCreationData, RenderpassTarget, UboUsage, ImageWrapper
+    BufferWrapper, BufferUsage, ImageUsage, VboCreationData, ShaderCreationData, ShaderSource,
+    ShaderStage, RenderpassCreationData, DescriptorSetLayoutCreationData,
+    PipelineLayoutCreationData, PipelineCreationData, RenderpassTarget, UboUsage, ImageWrapper,
+    RenderQueue, VertexLayout,
+    SamplerCreationData, SamplerWrapper, DynamicRenderTarget, DynamicRenderTargetData,
+    DepthConvention, BlendMode, DepthState, StencilState, RasterizerState, DebugLabels,
+    OffscreenFramebufferWrapper, OffscreenFramebufferData, TexturePixelFormat, UpscaleTarget,
+    UpscaleTargetData, PreRotation, ClusteredLights, ClusteredLightsCreationData, ClusterGrid,
+    PointLight, LIGHT_CLUSTER_SHADER
 };
 use vk_shader_macros::include_glsl;
 use ash::{Device, vk};
-use cgmath::{Matrix4, SquareMatrix, Rad};
+use cgmath::{Matrix4, SquareMatrix, Rad, Vector4};
 use std::borrow::Borrow;
 
 const VBO_INDEX_SCENE: u32 = 0;
@@ -23,13 +30,22 @@ const TEXTURE_INDEX_TERRAIN: u32 = 0;
 const TERRAIN_TEXTURE_BYTES: &[u8] =
     include_bytes!("../../../../resources/test/textures/simple_outdoor_texture.jpg");
 
+const SAMPLER_INDEX_MAIN: u32 = 0;
+
 const SHADER_INDEX_VERTEX: u32 = 0;
-const VERTEX_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock.vert");
+const VERTEX_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock_lit.vert");
 
 const SHADER_INDEX_FRAGMENT: u32 = 1;
-const FRAGMENT_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock.frag");
+const FRAGMENT_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock_lit.frag");
+
+const SHADER_INDEX_LIGHT_CLUSTER: u32 = 2;
 
 const RENDERPASS_INDEX_MAIN: u32 = 0;
+const MSAA_SAMPLES_MAIN: u32 = 4;
+
+const FRAMEBUFFER_INDEX_INTERNAL: u32 = 0;
+
+const UPSCALE_TARGET_INDEX_MAIN: u32 = 0;
 
 const DESCRIPTOR_SET_LAYOUT_INDEX_MAIN: u32 = 0;
 
@@ -37,32 +53,105 @@ const PIPELINE_LAYOUT_INDEX_MAIN: u32 = 0;
 
 const PIPELINE_INDEX_MAIN: u32 = 0;
 
+const LIGHT_CLUSTERS_INDEX_MAIN: u32 = 0;
+const LIGHT_CLUSTER_DIMENSIONS: [u32; 3] = [16, 9, 24];
+const DEMO_LIGHT_COUNT: usize = 64;
+
 #[repr(C)]
 pub struct StockUbo {
-    pub mvp_matrix: Matrix4<f32>
+    pub mvp_matrix: Matrix4<f32>,
+    pub view_model_matrix: Matrix4<f32>
 }
 
 /// TODO - Replace this type with derived implementations of Renderable using macros or some such.
 /// For now, this implementation will assume a basic rendering style that draws a textured model
-/// without any explicit lighting.
+/// lit by rings of orbiting point lights, which are binned into clusters by a compute pass each
+/// frame so that each fragment only considers the lights near it.
 pub struct StockScene {
     total_time: f64,
     camera: PlayerCamera,
-    ubo: StockUbo
+    depth_convention: DepthConvention,
+    debug_labels: DebugLabels,
+    pre_rotation: PreRotation,
+    view_model_matrix: Matrix4<f32>
 }
 
 pub struct StockResourceBearer {}
 
 impl StockScene {
     pub fn new() -> Self {
+        Self::new_with_camera(PlayerCamera::new(0.0, 1.5, -5.0, 0.0))
+    }
+
+    /// Create a new instance viewed through the supplied camera
+    pub fn new_with_camera(camera: PlayerCamera) -> Self {
         Self {
             total_time: 0.0,
-            camera: PlayerCamera::new(0.0, 1.5, -5.0, 0.0),
-            ubo: StockUbo {
-                mvp_matrix: Matrix4::identity()
-            }
+            camera,
+            depth_convention: DepthConvention::Standard,
+            debug_labels: DebugLabels::disabled(),
+            pre_rotation: PreRotation::Identity,
+            view_model_matrix: Matrix4::identity()
         }
     }
+
+    /// Getter for the total time this scene has been updated for, in seconds
+    pub fn get_total_time(&self) -> f64 {
+        self.total_time
+    }
+
+    /// Getter for the depth convention attached to this scene
+    pub fn get_depth_convention(&self) -> DepthConvention {
+        self.depth_convention
+    }
+
+    /// Advance time and the camera, then place the model using the supplied matrix rather than
+    /// the default spinning animation
+    pub fn update_with_model_matrix(
+        &mut self,
+        time_step_millis: u64,
+        control_dx: f32,
+        control_dy: f32,
+        model_matrix: Matrix4<f32>
+    ) {
+        let time_step_seconds = (time_step_millis as f64) * 0.001;
+        self.total_time = self.total_time + time_step_seconds;
+        self.camera.update(time_step_millis, control_dx, control_dy);
+
+        self.view_model_matrix = self.camera.get_view_matrix() * model_matrix;
+    }
+
+    /// The demo lights as they stand at the current time, in view space
+    fn demo_lights(&self) -> Vec<PointLight> {
+        let view_matrix = self.camera.get_view_matrix();
+        let time = self.total_time as f32;
+        (0..DEMO_LIGHT_COUNT)
+            .map(|i| {
+                let ring = (i % 4) as f32;
+                let direction = match i % 2 == 0 {
+                    true => 1.0,
+                    false => -1.0
+                };
+                let angle = (i as f32 / DEMO_LIGHT_COUNT as f32) * std::f32::consts::TAU +
+                    direction * time * 0.5;
+                let orbit_radius = 2.0 + ring * 1.5;
+                let world_position = Vector4::new(
+                    orbit_radius * angle.cos(),
+                    0.25 + (i % 3) as f32,
+                    orbit_radius * angle.sin(),
+                    1.0);
+                let position = view_matrix * world_position;
+                let hue = i as f32 * 360.0 / DEMO_LIGHT_COUNT as f32;
+                let color = Color::from_hsv(hue, 0.8, 1.0, 1.0).to_array();
+                PointLight {
+                    position: [position.x, position.y, position.z],
+                    radius: 2.5,
+                    color: [color[0], color[1], color[2]],
+                    intensity: 1.5
+                }
+            })
+            .collect()
+    }
 }
 
 impl Scene<VkContext> for StockScene {
@@ -71,7 +160,12 @@ impl Scene<VkContext> for StockScene {
         Box::new(StockResourceBearer::new())
     }
 
-    /// Stock rendering operation renders directly to the swapchain framebuffer
+    fn get_pass_names(&self) -> Vec<&'static str> {
+        vec!["Main"]
+    }
+
+    /// Stock rendering operation renders directly to the swapchain framebuffer, unless rendering
+    /// at an internal resolution, in which case it renders off-screen and scales the result in
     /// TODO - Fetch renderpass, framebuffer from the resource manager. Evidently we also need the pipeline, the pipeline layout, and the descriptor set.
     unsafe fn record_commands(
         &self,
@@ -79,14 +173,17 @@ impl Scene<VkContext> for StockScene {
         command_buffer: vk::CommandBuffer,
         render_extent: vk::Extent2D,
         ecs: &EcsManager<VkContext>,
-        swapchain_image_index: usize
+        swapchain_image_index: usize,
+        _secondary_command_buffers: &[vk::CommandBuffer]
     ) -> Result<(), EngineError> {
 
-        let renderpass  = ecs
-            .get_item::<RenderpassWrapper>(
-                Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, swapchain_image_index as u32)
-                    .unwrap())
-            .unwrap();
+        let renderpass_handle =
+            Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, swapchain_image_index as u32)
+                .unwrap();
+        let dynamic_render_target = ecs.get_item::<DynamicRenderTarget>(renderpass_handle);
+        let internal_framebuffer = ecs.get_item::<OffscreenFramebufferWrapper>(
+            Handle::for_resource_variation(FRAMEBUFFER_INDEX_INTERNAL, swapchain_image_index as u32)
+                .unwrap());
         let pipeline  = ecs
             .get_item::<PipelineWrapper>(
                 Handle::for_resource_variation(PIPELINE_INDEX_MAIN, swapchain_image_index as u32)
@@ -101,31 +198,52 @@ impl Scene<VkContext> for StockScene {
         let begin_info = vk::CommandBufferBeginInfo::builder();
         device.begin_command_buffer(command_buffer, &begin_info)
             .map_err(|e| EngineError::OpFailed(format!("{:?}", e)))?;
-
-        // Begin the renderpass
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.3, 0.0, 1.0]
-                }
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0
-                }
+        self.debug_labels.begin(command_buffer, "Main");
+
+        // Bin this frame's lights into clusters before rendering reads them
+        let clustered_lights = ecs
+            .get_item::<ClusteredLights>(
+                Handle::for_resource_variation(
+                    LIGHT_CLUSTERS_INDEX_MAIN,
+                    swapchain_image_index as u32)
+                    .unwrap())
+            .unwrap();
+        clustered_lights.record_binning(device, command_buffer)?;
+
+        // Begin the renderpass, or dynamic rendering if that's in use
+        let clear_color = Color::new(0.0, 0.3, 0.0, 1.0);
+        let clear_depth = self.depth_convention.clear_depth();
+        match dynamic_render_target {
+            Some(target) => target.begin(
+                device,
+                command_buffer,
+                RenderpassWrapper::color_clear_value(clear_color),
+                RenderpassWrapper::depth_clear_value(clear_depth)),
+            None => {
+                let renderpass  = ecs
+                    .get_item::<RenderpassWrapper>(renderpass_handle)
+                    .unwrap();
+                let clear_values = renderpass.clear_values(clear_color, clear_depth);
+                let extent = match internal_framebuffer {
+                    Some(framebuffer) => vk::Extent2D {
+                        width: framebuffer.width,
+                        height: framebuffer.height
+                    },
+                    None => render_extent
+                };
+                let renderpass_begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(renderpass.renderpass)
+                    .framebuffer(
+                        renderpass.custom_framebuffer.unwrap_or(renderpass.swapchain_framebuffer))
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent
+                    })
+                    .clear_values(&clear_values);
+                device.cmd_begin_render_pass(
+                    command_buffer, &renderpass_begin_info, vk::SubpassContents::INLINE);
             }
-        ];
-        let renderpass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(renderpass.renderpass)
-            .framebuffer(renderpass.swapchain_framebuffer)
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: render_extent
-            })
-            .clear_values(&clear_values);
-        device.cmd_begin_render_pass(
-            command_buffer, &renderpass_begin_info, vk::SubpassContents::INLINE);
+        }
 
         // Bind the pipeline and do rendering work
         let vertex_buffer  = ecs
@@ -148,15 +266,49 @@ impl Scene<VkContext> for StockScene {
             0,
             &[pipeline.get_descriptor_set()],
             &[]);
-        device.cmd_draw(
-            command_buffer,
-            vertex_buffer.element_count as u32,
-            1,
-            0,
-            0);
+        match vertex_buffer.index_buffer() {
+            Some((index_buffer, index_count)) => {
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer,
+                    0,
+                    vk::IndexType::UINT16);
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    index_count as u32,
+                    1,
+                    0,
+                    0,
+                    0);
+            },
+            None => device.cmd_draw(
+                command_buffer,
+                vertex_buffer.element_count as u32,
+                1,
+                0,
+                0)
+        }
 
         // End the renderpass
-        device.cmd_end_render_pass(command_buffer);
+        match dynamic_render_target {
+            Some(target) => target.end(device, command_buffer),
+            None => device.cmd_end_render_pass(command_buffer)
+        }
+
+        // Scale the off-screen result into the swapchain image, if rendering at an internal
+        // resolution
+        if let Some(framebuffer) = internal_framebuffer {
+            framebuffer.end_rendering();
+            let upscale_target = ecs
+                .get_item::<UpscaleTarget>(
+                    Handle::for_resource_variation(
+                        UPSCALE_TARGET_INDEX_MAIN,
+                        swapchain_image_index as u32)
+                        .unwrap())
+                .unwrap();
+            upscale_target.record_upscale(device, command_buffer, framebuffer.color_texture());
+        }
+        self.debug_labels.end(command_buffer);
 
         // End recording
         device.end_command_buffer(command_buffer)
@@ -164,15 +316,27 @@ impl Scene<VkContext> for StockScene {
         Ok(())
     }
 
-    fn update(&mut self, time_step_millis: u64, control_dx: f32, control_dy: f32) {
-        let time_step_seconds = (time_step_millis as f64) * 0.001;
-        self.total_time = self.total_time + time_step_seconds;
-        self.camera.update(time_step_millis, control_dx, control_dy);
+    fn attach_depth_convention(&mut self, convention: DepthConvention) {
+        self.depth_convention = convention;
+        self.camera.set_reversed_z(convention == DepthConvention::ReversedZ);
+    }
 
-        let model_matrix = Matrix4::from_angle_y(Rad(self.total_time as f32));
-        let view_matrix = self.camera.get_view_matrix();
-        let projection_matrix = self.camera.get_projection_matrix();
-        self.ubo.mvp_matrix = projection_matrix * view_matrix * model_matrix;
+    fn attach_debug_labels(&mut self, labels: DebugLabels) {
+        self.debug_labels = labels;
+    }
+
+    fn attach_pre_rotation(&mut self, rotation: PreRotation) {
+        self.pre_rotation = rotation;
+    }
+
+    fn update(&mut self, time_step_millis: u64, input: &InputState) {
+        let time_step_seconds = (time_step_millis as f64) * 0.001;
+        let model_matrix = Matrix4::from_angle_y(Rad((self.total_time + time_step_seconds) as f32));
+        self.update_with_model_matrix(
+            time_step_millis,
+            input.get_dx(),
+            input.get_dy(),
+            model_matrix);
     }
 
     unsafe fn prepare_frame_render(
@@ -186,11 +350,36 @@ impl Scene<VkContext> for StockScene {
                 Handle::for_resource_variation(PIPELINE_INDEX_MAIN, swapchain_image_index as u32)
                     .unwrap())
             .unwrap();
+
+        // Project with the aspect ratio that the user sees, then rotate into the swapchain's
+        let extent = self.pre_rotation.oriented_extent(context.get_render_area()?.extent);
+        let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+        let projection_matrix = self.camera.get_projection_matrix_for_aspect(aspect_ratio);
+        let pre_rotation_matrix = Matrix4::from(self.pre_rotation.clip_space_matrix());
+        let ubo = StockUbo {
+            mvp_matrix: pre_rotation_matrix * projection_matrix * self.view_model_matrix,
+            view_model_matrix: self.view_model_matrix
+        };
         pipeline.update_uniform_buffer(
             context,
-            self.ubo.borrow() as *const StockUbo as *const u8,
+            ubo.borrow() as *const StockUbo as *const u8,
             std::mem::size_of::<StockUbo>())?;
-        Ok(())
+
+        // Lights are binned in view space, for the projection without pre-rotation
+        let (near, far) = self.camera.get_depth_range();
+        let grid = ClusterGrid::for_projection(
+            LIGHT_CLUSTER_DIMENSIONS,
+            near,
+            far,
+            projection_matrix.into());
+        let clustered_lights = ecs
+            .get_item::<ClusteredLights>(
+                Handle::for_resource_variation(
+                    LIGHT_CLUSTERS_INDEX_MAIN,
+                    swapchain_image_index as u32)
+                    .unwrap())
+            .unwrap();
+        clustered_lights.write_lights(context, &grid, &self.demo_lights())
     }
 }
 
@@ -198,6 +387,210 @@ impl StockResourceBearer {
     pub fn new() -> Self {
         Self {}
     }
+
+    /// Release the renderpasses, or dynamic render targets, pipelines and light clusters of every
+    /// swapchain image, along with any targets for rendering at an internal resolution
+    fn release_swapchain_resources(
+        &self,
+        ecs: &mut EcsManager<VkContext>,
+        loader: &VkContext,
+        swapchain_image_count: usize
+    ) {
+        for i in 0..swapchain_image_count {
+            if let Some(item)  = ecs.remove_item::<PipelineWrapper>(
+                Handle::for_resource_variation(PIPELINE_INDEX_MAIN, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+        }
+
+        for i in 0..swapchain_image_count {
+            if let Some(item)  = ecs.remove_item::<ClusteredLights>(
+                Handle::for_resource_variation(LIGHT_CLUSTERS_INDEX_MAIN, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+        }
+
+        for i in 0..swapchain_image_count {
+            if let Some(item)  = ecs.remove_item::<RenderpassWrapper>(
+                Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+        }
+
+        for i in 0..swapchain_image_count {
+            if let Some(item)  = ecs.remove_item::<DynamicRenderTarget>(
+                Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+        }
+
+        for i in 0..swapchain_image_count {
+            if let Some(item)  = ecs.remove_item::<OffscreenFramebufferWrapper>(
+                Handle::for_resource_variation(FRAMEBUFFER_INDEX_INTERNAL, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+            if let Some(item)  = ecs.remove_item::<UpscaleTarget>(
+                Handle::for_resource_variation(UPSCALE_TARGET_INDEX_MAIN, i as u32).unwrap()
+            ) {
+                item.release(&loader);
+            }
+        }
+    }
+
+    /// Create the renderpasses, or dynamic render targets, pipelines and light clusters for every
+    /// swapchain image; the layouts must already be loaded. When rendering at an internal
+    /// resolution, each renderpass instead renders into an off-screen framebuffer of that size,
+    /// which is then scaled into the swapchain image.
+    fn create_swapchain_resources(
+        &self,
+        ecs: &mut EcsManager<VkContext>,
+        loader: &mut VkContext,
+        swapchain_image_count: usize
+    ) -> Result<(), EngineError> {
+
+        // Render without renderpass objects where possible, since then nothing needs to be
+        // created on the device here
+        let use_internal_resolution = loader.get_render_scale().is_some();
+        let use_dynamic_rendering =
+            loader.supports_dynamic_rendering() && !use_internal_resolution;
+        let samples = loader.select_msaa_samples(MSAA_SAMPLES_MAIN);
+        for i in 0..swapchain_image_count {
+            let handle = Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, i as u32).unwrap();
+            if use_internal_resolution {
+                let color_format = match loader.is_swapchain_srgb() {
+                    true => TexturePixelFormat::Srgba,
+                    false => TexturePixelFormat::Rgba
+                };
+                let creation_data =
+                    OffscreenFramebufferData::at_internal_resolution(loader, vec![color_format])?;
+                let framebuffer =
+                    OffscreenFramebufferWrapper::create(loader, &ecs, &creation_data)?;
+                let renderpass =
+                    match RenderpassWrapper::new_with_offscreen_target(loader, &framebuffer, samples) {
+                        Ok(renderpass) => renderpass,
+                        Err(e) => {
+                            framebuffer.release(loader);
+                            return Err(e);
+                        }
+                    };
+                let framebuffer_handle =
+                    Handle::for_resource_variation(FRAMEBUFFER_INDEX_INTERNAL, i as u32).unwrap();
+                unsafe {
+                    loader.set_debug_name(
+                        framebuffer.color_texture().image,
+                        &format!("Stock internal color image {}", i));
+                }
+                ecs.push_new_with_handle(framebuffer_handle, framebuffer);
+                unsafe {
+                    loader.set_debug_name(
+                        renderpass.renderpass,
+                        &format!("Stock internal renderpass {}", i));
+                }
+                ecs.push_new_with_handle(handle, renderpass);
+
+                let creation_data = UpscaleTargetData {
+                    swapchain_image_index: i as usize,
+                    source: framebuffer_handle,
+                    filter: vk::Filter::LINEAR
+                };
+                let upscale_target = UpscaleTarget::create(loader, &ecs, &creation_data)?;
+                ecs.push_new_with_handle(
+                    Handle::for_resource_variation(UPSCALE_TARGET_INDEX_MAIN, i as u32).unwrap(),
+                    upscale_target);
+            } else if use_dynamic_rendering {
+                let creation_data = DynamicRenderTargetData {
+                    swapchain_image_index: i as usize,
+                    samples
+                };
+                let target = DynamicRenderTarget::create(loader, &ecs, &creation_data)?;
+                ecs.push_new_with_handle(handle, target);
+            } else {
+                let creation_data = RenderpassCreationData {
+                    target: RenderpassTarget::SwapchainImageWithDepth,
+                    swapchain_image_index: i as usize,
+                    samples
+                };
+                let renderpass = RenderpassWrapper::create(loader, &ecs, &creation_data)?;
+                unsafe {
+                    loader.set_debug_name(
+                        renderpass.renderpass,
+                        &format!("Stock renderpass {}", i));
+                    loader.set_debug_name(
+                        renderpass.swapchain_framebuffer,
+                        &format!("Stock framebuffer {}", i));
+                }
+                ecs.push_new_with_handle(handle, renderpass);
+            }
+        }
+
+        for i in 0..swapchain_image_count {
+            let creation_data = ClusteredLightsCreationData {
+                shader_index: SHADER_INDEX_LIGHT_CLUSTER,
+                dimensions: LIGHT_CLUSTER_DIMENSIONS
+            };
+            let clustered_lights = ClusteredLights::create(loader, &ecs, &creation_data)?;
+            ecs.push_new_with_handle(
+                Handle::for_resource_variation(LIGHT_CLUSTERS_INDEX_MAIN, i as u32).unwrap(),
+                clustered_lights);
+        }
+
+        // Pipelines for every swapchain image are created together, as derivatives of the first
+        let creation_data: Vec<PipelineCreationData> = (0..swapchain_image_count)
+            .map(|i| PipelineCreationData {
+                pipeline_layout_index: PIPELINE_LAYOUT_INDEX_MAIN,
+                renderpass_index: RENDERPASS_INDEX_MAIN,
+                subpass: 0,
+                use_dynamic_rendering,
+                descriptor_set_layout_id: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
+                vertex_shader_index: SHADER_INDEX_VERTEX,
+                fragment_shader_index: SHADER_INDEX_FRAGMENT,
+                vbo_index: VBO_INDEX_SCENE,
+                texture_index: TEXTURE_INDEX_TERRAIN,
+                sampler_index: SAMPLER_INDEX_MAIN,
+                bindless_material: None,
+                draw_metadata: None,
+                vertex_layout: None,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                ubo_size_bytes: std::mem::size_of::<StockUbo>(),
+                ubo_object_count: None,
+                render_queue: RenderQueue::Opaque,
+                blend_mode: BlendMode::Opaque,
+                depth_state: DepthState::for_render_queue(RenderQueue::Opaque),
+                stencil_state: StencilState::disabled(),
+                rasterizer_state: RasterizerState::default(),
+                specialization_constants: vec![],
+                swapchain_image_index: i as usize
+            })
+            .collect();
+        let pipelines = unsafe { PipelineWrapper::create_batch(loader, &ecs, &creation_data)? };
+        for (i, pipeline) in pipelines.into_iter().enumerate() {
+            unsafe {
+                loader.set_debug_name(pipeline.get_pipeline(), &format!("Stock pipeline {}", i));
+                loader.set_debug_name(
+                    pipeline.get_descriptor_set(),
+                    &format!("Stock descriptor set {}", i));
+                let clustered_lights = ecs
+                    .get_item::<ClusteredLights>(
+                        Handle::for_resource_variation(LIGHT_CLUSTERS_INDEX_MAIN, i as u32)
+                            .unwrap())
+                    .unwrap();
+                pipeline.rewrite_descriptor_bindings(
+                    loader,
+                    &clustered_lights.descriptor_bindings())?;
+            }
+            ecs.push_new_with_handle(
+                Handle::for_resource_variation(PIPELINE_INDEX_MAIN, i as u32)
+                    .unwrap(),
+                pipeline);
+        }
+
+        Ok(())
+    }
 }
 
 impl RawResourceBearer<VkContext> for StockResourceBearer {
@@ -208,51 +601,76 @@ impl RawResourceBearer<VkContext> for StockResourceBearer {
         loader: &VkContext
     ) -> Result<(), EngineError> {
 
-        let scene_model = {
-            let collada = COLLADA::new(&SCENE_MODEL_BYTES);
-            let mut models = collada.extract_models(Config::default());
-            models.remove(0)
-        };
-        let creation_data = VboCreationData {
-            vertex_data: Some(scene_model.vertices.as_ptr() as *const u8),
-            vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
-            vertex_count: scene_model.vertices.len(),
-            draw_indexed: false,
-            index_data: None,
-            usage: BufferUsage::InitialiseOnceVertexBuffer
-        };
-        let model = BufferWrapper::create(loader, &ecs, &creation_data)?;
-        ecs.push_new_with_handle(
-            Handle::for_resource(VBO_INDEX_SCENE),
-            model);
-
-        let creation_data = ResourceUtilities::decode_texture(
-            TERRAIN_TEXTURE_BYTES,
-            TextureCodec::Jpeg,
-            ImageUsage::TextureSampleOnly)
-            .unwrap();
-        let texture = ImageWrapper::create(loader, &ecs, &creation_data)?;
-        ecs.push_new_with_handle(
-            Handle::for_resource(TEXTURE_INDEX_TERRAIN),
-            texture);
-
-        let creation_data = ShaderCreationData {
-            data: VERTEX_SHADER,
-            stage: ShaderStage::Vertex
-        };
-        let vertex_shader = vk::ShaderModule::create(loader, &ecs, &creation_data)?;
-        ecs.push_new_with_handle(
-            Handle::for_resource(SHADER_INDEX_VERTEX),
-            vertex_shader);
-
-        let creation_data = ShaderCreationData {
-            data: FRAGMENT_SHADER,
-            stage: ShaderStage::Fragment
-        };
-        let fragment_shader = vk::ShaderModule::create(loader, &ecs, &creation_data)?;
-        ecs.push_new_with_handle(
-            Handle::for_resource(SHADER_INDEX_FRAGMENT),
-            fragment_shader);
+        // Everything here is ensured rather than pushed, so that it is kept when switching
+        // between stock scenes; each is identified by what it is created from
+        let identity = "resources/test/models/Cubes.dae";
+        ecs.ensure_with_handle(Handle::for_resource(VBO_INDEX_SCENE), identity, |ecs| {
+            let scene_model = {
+                let collada = COLLADA::new(&SCENE_MODEL_BYTES);
+                let mut models = collada.extract_models(Config::default());
+                models.remove(0)
+            };
+            let creation_data = VboCreationData {
+                vertex_data: Some(scene_model.vertices.as_ptr() as *const u8),
+                vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
+                vertex_count: scene_model.vertices.len(),
+                vertex_layout: Some(VertexLayout::static_vertex()),
+                draw_indexed: false,
+                index_data: None,
+                usage: BufferUsage::InitialiseOnceVertexBuffer
+            };
+            let vertex_buffer = BufferWrapper::create(loader, ecs, &creation_data)?;
+            unsafe { loader.set_debug_name(vertex_buffer.buffer, "Stock scene vertices"); }
+            Ok(vertex_buffer)
+        })?;
+
+        let identity = "resources/test/textures/simple_outdoor_texture.jpg";
+        ecs.ensure_with_handle(Handle::for_resource(TEXTURE_INDEX_TERRAIN), identity, |ecs| {
+            let creation_data = ResourceUtilities::decode_texture(
+                TERRAIN_TEXTURE_BYTES,
+                TextureCodec::Jpeg,
+                ImageUsage::TextureSampleOnly)
+                .unwrap();
+            let texture = ImageWrapper::create(loader, ecs, &creation_data)?;
+            unsafe {
+                loader.set_debug_name(texture.image, "Stock terrain texture");
+                loader.set_debug_name(texture.image_view, "Stock terrain texture view");
+            }
+            Ok(texture)
+        })?;
+
+        let identity = "stock sampler with 16x anisotropy";
+        ecs.ensure_with_handle(Handle::for_resource(SAMPLER_INDEX_MAIN), identity, |ecs| {
+            let creation_data = SamplerCreationData::default().with_anisotropy(16.0);
+            SamplerWrapper::create(loader, ecs, &creation_data)
+        })?;
+
+        let identity = "resources/test/shaders/stock_lit.vert";
+        ecs.ensure_with_handle(Handle::for_resource(SHADER_INDEX_VERTEX), identity, |ecs| {
+            let creation_data = ShaderCreationData {
+                source: ShaderSource::SpirV(VERTEX_SHADER),
+                stage: ShaderStage::Vertex
+            };
+            vk::ShaderModule::create(loader, ecs, &creation_data)
+        })?;
+
+        let identity = "resources/test/shaders/stock_lit.frag";
+        ecs.ensure_with_handle(Handle::for_resource(SHADER_INDEX_FRAGMENT), identity, |ecs| {
+            let creation_data = ShaderCreationData {
+                source: ShaderSource::SpirV(FRAGMENT_SHADER),
+                stage: ShaderStage::Fragment
+            };
+            vk::ShaderModule::create(loader, ecs, &creation_data)
+        })?;
+
+        let identity = "resources/shaders/light_cluster.comp";
+        ecs.ensure_with_handle(Handle::for_resource(SHADER_INDEX_LIGHT_CLUSTER), identity, |ecs| {
+            let creation_data = ShaderCreationData {
+                source: ShaderSource::SpirV(LIGHT_CLUSTER_SHADER),
+                stage: ShaderStage::Compute
+            };
+            vk::ShaderModule::create(loader, ecs, &creation_data)
+        })?;
 
         Ok(())
     }
@@ -264,13 +682,8 @@ impl RawResourceBearer<VkContext> for StockResourceBearer {
         swapchain_image_count: usize
     ) -> Result<(), EngineError> {
 
-        for i in 0..swapchain_image_count {
-            if let Some(item)  = ecs.remove_item::<RenderpassWrapper>(
-                Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, i as u32).unwrap()
-            ) {
-                item.release(&loader);
-            }
-        }
+        // Pipelines go first, returning their descriptor sets before the layout is destroyed
+        self.release_swapchain_resources(ecs, loader, swapchain_image_count);
 
         if let Some(item)  = ecs.remove_item::<vk::DescriptorSetLayout>(
             Handle::for_resource(DESCRIPTOR_SET_LAYOUT_INDEX_MAIN)
@@ -284,62 +697,40 @@ impl RawResourceBearer<VkContext> for StockResourceBearer {
             item.release(&loader);
         }
 
-        for i in 0..swapchain_image_count {
-            if let Some(item)  = ecs.remove_item::<PipelineWrapper>(
-                Handle::for_resource_variation(PIPELINE_INDEX_MAIN, i as u32).unwrap()
-            ) {
-                item.release(&loader);
-            }
-        }
-
-        for i in 0..swapchain_image_count {
-            let creation_data = RenderpassCreationData {
-                target: RenderpassTarget::SwapchainImageWithDepth,
-                swapchain_image_index: i as usize
-            };
-            let renderpass = RenderpassWrapper::create(loader, &ecs, &creation_data)?;
-            ecs.push_new_with_handle(
-                Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, i as u32)
-                    .unwrap(),
-                renderpass);
-        }
-
         let creation_data = DescriptorSetLayoutCreationData {
-            ubo_usage: UboUsage::VertexShaderRead
+            ubo_usage: UboUsage::VertexShaderRead,
+            dynamic_ubo: false,
+            draw_metadata: false,
+            input_attachment_count: 0,
+            light_clusters: true
         };
         let descriptor_set_layout = vk::DescriptorSetLayout::create(loader, &ecs, &creation_data)?;
+        unsafe { loader.set_debug_name(descriptor_set_layout, "Stock descriptor set layout"); }
         ecs.push_new_with_handle(
             Handle::for_resource(DESCRIPTOR_SET_LAYOUT_INDEX_MAIN),
             descriptor_set_layout);
 
         let creation_data = PipelineLayoutCreationData {
-            descriptor_set_layout_index: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN
+            descriptor_set_layout_index: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
+            bindless_texture_set_index: None
         };
         let pipeline_layout = vk::PipelineLayout::create(loader, &ecs, &creation_data)?;
+        unsafe { loader.set_debug_name(pipeline_layout, "Stock pipeline layout"); }
         ecs.push_new_with_handle(
             Handle::for_resource(PIPELINE_LAYOUT_INDEX_MAIN),
             pipeline_layout);
 
-        for i in 0..swapchain_image_count {
-            let creation_data = PipelineCreationData {
-                pipeline_layout_index: PIPELINE_LAYOUT_INDEX_MAIN,
-                renderpass_index: RENDERPASS_INDEX_MAIN,
-                descriptor_set_layout_id: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
-                vertex_shader_index: SHADER_INDEX_VERTEX,
-                fragment_shader_index: SHADER_INDEX_FRAGMENT,
-                vbo_index: VBO_INDEX_SCENE,
-                texture_index: TEXTURE_INDEX_TERRAIN,
-                vbo_stride_bytes: std::mem::size_of::<StaticVertex>() as u32,
-                ubo_size_bytes: std::mem::size_of::<StockUbo>(),
-                swapchain_image_index: i as usize
-            };
-            let pipeline = PipelineWrapper::create(loader, &ecs, &creation_data)?;
-            ecs.push_new_with_handle(
-                Handle::for_resource_variation(PIPELINE_INDEX_MAIN, i as u32)
-                    .unwrap(),
-                pipeline);
-        }
+        self.create_swapchain_resources(ecs, loader, swapchain_image_count)
+    }
 
-        Ok(())
+    /// The layouts don't depend on the swapchain, so are kept
+    fn reload_swapchain_resources(
+        &self,
+        ecs: &mut EcsManager<VkContext>,
+        loader: &mut VkContext,
+        swapchain_image_count: usize
+    ) -> Result<(), EngineError> {
+        self.release_swapchain_resources(ecs, loader, swapchain_image_count);
+        self.create_swapchain_resources(ecs, loader, swapchain_image_count)
     }
 }
@@ -0,0 +1,206 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+type Callback = Box<dyn FnMut(&mut Scheduler)>;
+
+/// TaskId struct
+/// Identifies a scheduled callback or sequence, so that it can be cancelled
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TaskId(u64);
+
+/// Step enum
+/// One step of a sequence
+enum Step {
+    Wait(Duration),
+    WaitUntil(Box<dyn FnMut() -> bool>),
+    Call(Callback)
+}
+
+/// Sequence struct
+/// A list of steps run one after another as time passes, such as the stages of a cutscene or
+/// the waves of an attack. Built up by chaining calls, then started with Scheduler::run_sequence.
+#[derive(Default)]
+pub struct Sequence {
+    steps: VecDeque<Step>
+}
+
+impl Sequence {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pause for the given time before the next step
+    pub fn wait(mut self, delay: Duration) -> Self {
+        self.steps.push_back(Step::Wait(delay));
+        self
+    }
+
+    /// Pause until the condition returns true; it is checked once per update
+    pub fn wait_until<F: 'static + FnMut() -> bool>(mut self, condition: F) -> Self {
+        self.steps.push_back(Step::WaitUntil(Box::new(condition)));
+        self
+    }
+
+    /// Run a callback, then move straight on to the next step
+    pub fn then<F: 'static + FnMut(&mut Scheduler)>(mut self, callback: F) -> Self {
+        self.steps.push_back(Step::Call(Box::new(callback)));
+        self
+    }
+}
+
+/// TaskKind enum
+/// The kinds of work that can be scheduled
+enum TaskKind {
+    Once(Callback),
+    Repeating(Duration, Callback),
+    Sequence(Sequence)
+}
+
+/// Task struct
+/// Scheduled work, with the time on the scheduler's clock at which it is next due
+struct Task {
+    id: TaskId,
+    due: Duration,
+    kind: TaskKind
+}
+
+/// Scheduler struct
+/// Runs delayed and repeating callbacks and sequences as the engine's update loop advances time.
+/// Callbacks are given the scheduler, so that they may schedule or cancel further work. Tasks
+/// due in the same update run in the order they were scheduled, and timing is measured on the
+/// scheduler's own clock, so the same time steps always produce the same results.
+#[derive(Default)]
+pub struct Scheduler {
+    now: Duration,
+    next_id: u64,
+    tasks: Vec<Task>,
+    running_ids: HashSet<TaskId>,
+    cancelled_ids: HashSet<TaskId>
+}
+
+impl Scheduler {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time passed on the scheduler's clock, which is the sum of all time steps so far
+    pub fn get_time(&self) -> Duration {
+        self.now
+    }
+
+    /// Run a callback once, after the given delay
+    pub fn after<F: 'static + FnMut(&mut Scheduler)>(
+        &mut self,
+        delay: Duration,
+        callback: F
+    ) -> TaskId {
+        self.schedule(self.now + delay, TaskKind::Once(Box::new(callback)))
+    }
+
+    /// Run a callback repeatedly, first after one interval. If an update covers several
+    /// intervals, the callback runs once for each; a zero interval runs it once every update.
+    pub fn every<F: 'static + FnMut(&mut Scheduler)>(
+        &mut self,
+        interval: Duration,
+        callback: F
+    ) -> TaskId {
+        self.schedule(self.now + interval, TaskKind::Repeating(interval, Box::new(callback)))
+    }
+
+    /// Start running a sequence; steps that are due run during the next update
+    pub fn run_sequence(&mut self, sequence: Sequence) -> TaskId {
+        self.schedule(self.now, TaskKind::Sequence(sequence))
+    }
+
+    /// Stop a task from running again. Returns false if it had already finished or been
+    /// cancelled.
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        if let Some(index) = self.tasks.iter().position(|task| task.id == id) {
+            self.tasks.remove(index);
+            return true;
+        }
+        self.running_ids.contains(&id) && self.cancelled_ids.insert(id)
+    }
+
+    /// Query whether a task is still waiting to run
+    pub fn is_scheduled(&self, id: TaskId) -> bool {
+        self.tasks.iter().any(|task| task.id == id) ||
+            (self.running_ids.contains(&id) && !self.cancelled_ids.contains(&id))
+    }
+
+    /// Advance the clock by a time step, running everything that falls due
+    pub fn advance(&mut self, time_step: Duration) {
+        self.now += time_step;
+
+        // Tasks are taken out while they run, so that callbacks can freely schedule more work;
+        // anything scheduled during this update waits until the next one
+        let running = std::mem::take(&mut self.tasks);
+        self.running_ids = running.iter().map(|task| task.id).collect();
+        let mut remaining = Vec::with_capacity(running.len());
+        for task in running {
+            if self.cancelled_ids.contains(&task.id) {
+                continue;
+            }
+            if let Some(task) = self.run_task(task) {
+                remaining.push(task);
+            }
+        }
+        remaining.retain(|task| !self.cancelled_ids.contains(&task.id));
+        remaining.append(&mut self.tasks);
+        self.tasks = remaining;
+        self.running_ids.clear();
+        self.cancelled_ids.clear();
+    }
+
+    fn schedule(&mut self, due: Duration, kind: TaskKind) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(Task { id, due, kind });
+        id
+    }
+
+    /// Run a task as far as the current time allows, returning it if it has more to do
+    fn run_task(&mut self, mut task: Task) -> Option<Task> {
+        match &mut task.kind {
+            TaskKind::Once(callback) => {
+                if task.due > self.now {
+                    return Some(task);
+                }
+                callback(self);
+                None
+            },
+            TaskKind::Repeating(interval, callback) => {
+                while task.due <= self.now && !self.cancelled_ids.contains(&task.id) {
+                    callback(self);
+                    task.due = match interval.is_zero() {
+                        true => self.now + Duration::from_nanos(1),
+                        false => task.due + *interval
+                    };
+                }
+                Some(task)
+            },
+            TaskKind::Sequence(sequence) => {
+                while task.due <= self.now && !self.cancelled_ids.contains(&task.id) {
+                    match sequence.steps.front_mut() {
+                        Some(Step::Wait(delay)) => task.due += *delay,
+                        Some(Step::WaitUntil(condition)) => {
+                            if !condition() {
+                                return Some(task);
+                            }
+                            task.due = self.now;
+                        },
+                        Some(Step::Call(callback)) => callback(self),
+                        None => return None
+                    }
+                    sequence.steps.pop_front();
+                }
+                match sequence.steps.is_empty() {
+                    true => None,
+                    false => Some(task)
+                }
+            }
+        }
+    }
+}
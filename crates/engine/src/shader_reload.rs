@@ -0,0 +1,132 @@
+use vk_renderer::{VkContext, ShaderStage, ShaderSource, ShaderCreationData};
+use ecs::{EcsManager, resource::Resource};
+use error::EngineError;
+use ash::vk;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default time between checks of the watched files
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// WatchedShader struct
+/// A GLSL file on disk and the shader module that is compiled from it
+struct WatchedShader {
+    path: PathBuf,
+    stage: ShaderStage,
+    shader_index: u32,
+    last_modified: Option<SystemTime>
+}
+
+/// ShaderReload struct
+/// The outcome of recompiling a watched shader whose file changed; where it couldn't be read or
+/// compiled, the error, in which case the previous module is kept
+pub(crate) struct ShaderReload {
+    pub path: PathBuf,
+    pub result: Result<(), EngineError>
+}
+
+/// ShaderReloader struct
+/// Watches GLSL files during development by polling their modification times. When one changes,
+/// it is recompiled and the shader module at its resource index replaced between frames, after
+/// which the scene's dynamic resources are reloaded so that its pipelines use the new module; see
+/// Engine::set_shader_reloader. Compiling needs the runtime-shaders feature. Each reload is
+/// reported to the app as a window state event; a shader that fails to compile is reported with
+/// its error and the previous module kept, so that mistakes can be fixed live.
+pub struct ShaderReloader {
+    shaders: Vec<WatchedShader>,
+    poll_interval: Duration,
+    last_poll: Option<Instant>
+}
+
+impl Default for ShaderReloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderReloader {
+
+    pub fn new() -> Self {
+        Self {
+            shaders: vec![],
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            last_poll: None
+        }
+    }
+
+    /// Set the time between checks of the watched files
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Watch a GLSL file, which the shader module at the given resource index was built from.
+    /// Only changes made after this call cause a reload.
+    pub fn watch<P: Into<PathBuf>>(&mut self, path: P, stage: ShaderStage, shader_index: u32) {
+        let path = path.into();
+        let last_modified = modified_time(&path);
+        self.shaders.push(WatchedShader { path, stage, shader_index, last_modified });
+    }
+
+    /// Check the watched files, if the poll interval has passed since the last check, returning
+    /// the positions within the watch list of those modified since they were last seen. Files
+    /// that can't be read are skipped until they can.
+    pub(crate) fn poll_changed(&mut self, now: Instant) -> Vec<usize> {
+        if let Some(last_poll) = self.last_poll {
+            if now.duration_since(last_poll) < self.poll_interval {
+                return vec![];
+            }
+        }
+        self.last_poll = Some(now);
+        let mut changed = vec![];
+        for (position, shader) in self.shaders.iter_mut().enumerate() {
+            let Some(modified) = modified_time(&shader.path) else {
+                continue;
+            };
+            if shader.last_modified != Some(modified) {
+                shader.last_modified = Some(modified);
+                changed.push(position);
+            }
+        }
+        changed
+    }
+
+    /// Compile the changed shaders into new modules, paired with the resource index that each
+    /// replaces, along with the outcome for each changed shader. Shaders that can't be read or
+    /// compiled are left out of the modules.
+    pub(crate) fn compile(
+        &self,
+        changed: &[usize],
+        context: &VkContext,
+        ecs: &EcsManager<VkContext>
+    ) -> (Vec<(u32, vk::ShaderModule)>, Vec<ShaderReload>) {
+        let mut modules = vec![];
+        let mut reloads = vec![];
+        for shader in changed.iter().filter_map(|position| self.shaders.get(*position)) {
+            let module = std::fs::read_to_string(&shader.path)
+                .map_err(|e| EngineError::OpFailed(format!("Error reading shader: {}", e)))
+                .and_then(|source| {
+                    let creation_data = ShaderCreationData {
+                        source: ShaderSource::Glsl(source),
+                        stage: shader.stage
+                    };
+                    vk::ShaderModule::create(context, ecs, &creation_data)
+                });
+            let result = match module {
+                Ok(module) => {
+                    modules.push((shader.shader_index, module));
+                    Ok(())
+                },
+                Err(e) => Err(e)
+            };
+            reloads.push(ShaderReload { path: shader.path.clone(), result });
+        }
+        (modules, reloads)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,219 @@
+
+use crate::{
+    Engine, Scene, SceneFactory, StockScene, InputState, RandomService, Scheduler,
+    RecordingStrategy, SecondaryRecordings
+};
+use camera::PlayerCamera;
+use ecs::{EcsManager, resource::RawResourceBearer};
+use error::EngineError;
+use vk_renderer::{VkContext, DebugLabels, PreRotation, DepthConvention, PipelinePermutation};
+use window::{RenderCycleEvent, RenderEventHandler, WindowEventHandler, WindowStateEvent};
+use ash::{Device, vk};
+use cgmath::{Matrix4, Rad};
+use std::{cell::RefCell, rc::Rc};
+
+/// SimpleConfig struct
+/// Options for an app started through run_simple
+pub struct SimpleConfig {
+    pub app_title: &'static str,
+    pub camera_position: (f32, f32, f32),
+    pub camera_angle_rad: f32,
+    /// Whether to use a reversed-Z depth buffer; see Engine::set_reversed_z
+    pub reversed_z: bool
+}
+
+impl Default for SimpleConfig {
+    fn default() -> Self {
+        Self {
+            app_title: "Shining Engine",
+            camera_position: (0.0, 1.5, -5.0),
+            camera_angle_rad: 0.0,
+            reversed_z: false
+        }
+    }
+}
+
+/// SimpleFrame struct
+/// State passed to the per-frame callback of an app started through run_simple. The callback may
+/// modify the model matrix to place the stock model in the world; by default it spins in place.
+pub struct SimpleFrame {
+    pub time_step_millis: u64,
+    pub total_time_secs: f64,
+    pub input: InputState,
+    pub model_matrix: Matrix4<f32>
+}
+
+/// Open a window and render the stock textured model, calling the supplied callback once per
+/// update. This avoids any need to implement the event handler and scene traits or to touch any
+/// Vulkan objects directly:
+///
+/// ```ignore
+/// engine::run_simple(SimpleConfig::default(), |frame| {
+///     frame.model_matrix = Matrix4::from_angle_y(Rad(2.0 * frame.total_time_secs as f32));
+/// });
+/// ```
+pub fn run_simple<F>(config: SimpleConfig, update: F)
+    where F: FnMut(&mut SimpleFrame) + 'static
+{
+    let mut engine = Engine::<()>::new(config.app_title);
+    engine.set_reversed_z(config.reversed_z);
+    engine.run(SimpleApp::new(config, update));
+}
+
+/// App implementation used by run_simple; ignores all events
+pub(crate) struct SimpleApp<F: FnMut(&mut SimpleFrame) + 'static> {
+    config: SimpleConfig,
+    update: Rc<RefCell<F>>
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> SimpleApp<F> {
+    pub fn new(config: SimpleConfig, update: F) -> Self {
+        Self {
+            config,
+            update: Rc::new(RefCell::new(update))
+        }
+    }
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> WindowEventHandler<()> for SimpleApp<F> {
+    fn on_window_state_event(&mut self, _event: WindowStateEvent) {}
+    fn on_window_custom_event(&mut self, _event: ()) {}
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> RenderEventHandler for SimpleApp<F> {
+    fn on_render_cycle_event(&self, _event: RenderCycleEvent) {}
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> SceneFactory<VkContext> for SimpleApp<F> {
+    fn get_scene(&self) -> Box<dyn Scene<VkContext>> {
+        Box::new(self.new_scene())
+    }
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> SimpleApp<F> {
+    pub fn new_scene(&self) -> SimpleScene<F> {
+        let (x, y, z) = self.config.camera_position;
+        let camera = PlayerCamera::new(x, y, z, self.config.camera_angle_rad);
+        SimpleScene {
+            inner: StockScene::new_with_camera(camera),
+            update: self.update.clone()
+        }
+    }
+}
+
+/// Scene used by run_simple; renders as the stock scene does, but defers model placement to the
+/// user-supplied callback. Everything else is forwarded to the stock scene.
+pub(crate) struct SimpleScene<F: FnMut(&mut SimpleFrame) + 'static> {
+    inner: StockScene,
+    update: Rc<RefCell<F>>
+}
+
+#[cfg(test)]
+impl<F: FnMut(&mut SimpleFrame) + 'static> SimpleScene<F> {
+    pub fn get_stock_scene(&self) -> &StockScene {
+        &self.inner
+    }
+}
+
+impl<F: FnMut(&mut SimpleFrame) + 'static> Scene<VkContext> for SimpleScene<F> {
+
+    fn get_resource_bearer(&self) -> Box<dyn RawResourceBearer<VkContext>> {
+        self.inner.get_resource_bearer()
+    }
+
+    fn get_recording_strategy(&self) -> RecordingStrategy {
+        self.inner.get_recording_strategy()
+    }
+
+    unsafe fn get_secondary_recordings(
+        &self,
+        render_extent: vk::Extent2D,
+        ecs: &EcsManager<VkContext>,
+        swapchain_image_index: usize
+    ) -> Result<Option<SecondaryRecordings>, EngineError> {
+        self.inner.get_secondary_recordings(render_extent, ecs, swapchain_image_index)
+    }
+
+    unsafe fn record_commands(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        render_extent: vk::Extent2D,
+        ecs: &EcsManager<VkContext>,
+        swapchain_image_index: usize,
+        secondary_command_buffers: &[vk::CommandBuffer]
+    ) -> Result<(), EngineError> {
+        self.inner.record_commands(
+            device,
+            command_buffer,
+            render_extent,
+            ecs,
+            swapchain_image_index,
+            secondary_command_buffers)
+    }
+
+    fn get_pass_names(&self) -> Vec<&'static str> {
+        self.inner.get_pass_names()
+    }
+
+    fn attach_random_service(&mut self, random: Rc<RefCell<RandomService>>) {
+        self.inner.attach_random_service(random);
+    }
+
+    fn attach_scheduler(&mut self, scheduler: Rc<RefCell<Scheduler>>) {
+        self.inner.attach_scheduler(scheduler);
+    }
+
+    fn attach_depth_convention(&mut self, convention: DepthConvention) {
+        self.inner.attach_depth_convention(convention);
+    }
+
+    fn attach_debug_labels(&mut self, labels: DebugLabels) {
+        self.inner.attach_debug_labels(labels);
+    }
+
+    fn attach_pre_rotation(&mut self, rotation: PreRotation) {
+        self.inner.attach_pre_rotation(rotation);
+    }
+
+    fn on_device_restored(&mut self) {
+        self.inner.on_device_restored();
+    }
+
+    fn take_next_scene(&mut self) -> Option<Box<dyn Scene<VkContext>>> {
+        self.inner.take_next_scene()
+    }
+
+    fn get_streaming_focus(&self) -> Option<(f32, f32)> {
+        self.inner.get_streaming_focus()
+    }
+
+    fn get_pipeline_permutations(&self) -> Vec<PipelinePermutation> {
+        self.inner.get_pipeline_permutations()
+    }
+
+    fn update(&mut self, time_step_millis: u64, input: &InputState) {
+        let total_time_secs = self.inner.get_total_time() + time_step_millis as f64 * 0.001;
+        let mut frame = SimpleFrame {
+            time_step_millis,
+            total_time_secs,
+            input: input.clone(),
+            model_matrix: Matrix4::from_angle_y(Rad(total_time_secs as f32))
+        };
+        (self.update.borrow_mut())(&mut frame);
+        self.inner.update_with_model_matrix(
+            time_step_millis,
+            input.get_dx(),
+            input.get_dy(),
+            frame.model_matrix);
+    }
+
+    unsafe fn prepare_frame_render(
+        &self,
+        context: &VkContext,
+        swapchain_image_index: usize,
+        ecs: &EcsManager<VkContext>
+    ) -> Result<(), EngineError> {
+        self.inner.prepare_frame_render(context, swapchain_image_index, ecs)
+    }
+}
@@ -0,0 +1,393 @@
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use model::{Model, StaticVertex, COLLADA, Config};
+use vk_renderer::{VkContext, BufferWrapper, BufferUsage, VboCreationData, VertexLayout};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::thread::JoinHandle;
+
+/// CellId struct
+/// Identifies one square cell of a world laid out on a horizontal grid
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CellId {
+    pub x: i32,
+    pub z: i32
+}
+
+impl CellId {
+
+    pub fn new(x: i32, z: i32) -> Self {
+        Self { x, z }
+    }
+
+    /// The cell containing a horizontal position, for cells of the given size
+    pub fn containing(x: f32, z: f32, cell_size: f32) -> Self {
+        Self {
+            x: (x / cell_size).floor() as i32,
+            z: (z / cell_size).floor() as i32
+        }
+    }
+
+    /// Distance to another cell in whole cells, counting diagonal steps as one
+    pub fn distance_to(&self, other: CellId) -> u32 {
+        self.x.abs_diff(other.x).max(self.z.abs_diff(other.z))
+    }
+
+    /// Every cell within the given distance of this one, nearest first
+    pub fn cells_within(&self, radius: u32) -> Vec<CellId> {
+        let radius = radius as i32;
+        let mut cells: Vec<CellId> = (-radius..=radius)
+            .flat_map(|dz| (-radius..=radius).map(move |dx| (dx, dz)))
+            .map(|(dx, dz)| CellId::new(self.x + dx, self.z + dz))
+            .collect();
+        cells.sort_by_key(|cell| {
+            let (dx, dz) = ((cell.x - self.x) as i64, (cell.z - self.z) as i64);
+            dx * dx + dz * dz
+        });
+        cells
+    }
+}
+
+/// CellReader trait
+/// Reads everything a cell needs from storage, and decodes it into a form ready to upload. Runs
+/// on the streamer's worker thread, so must not touch the renderer. Returning None means the
+/// cell is empty.
+pub trait CellReader: Send + 'static {
+    type Data: Send + 'static;
+    fn read_cell(&mut self, cell: CellId) -> Result<Option<Self::Data>, EngineError>;
+}
+
+/// CellUploader trait
+/// Creates a cell's resources from the data read for it, on the main thread. Every resource
+/// should be added to the ECS in the given namespace, so that it is released when the cell is
+/// unloaded.
+pub trait CellUploader<L, D> {
+    fn upload_cell(
+        &mut self,
+        cell: CellId,
+        data: D,
+        ecs: &mut EcsManager<L>,
+        loader: &L,
+        namespace: u32
+    ) -> Result<(), EngineError>;
+
+    /// Forget a cell whose resources have just been released
+    fn cell_unloaded(&mut self, _cell: CellId) {}
+}
+
+/// StreamsWorld trait
+/// The parts of a world streamer the engine drives each frame, independent of the data read
+/// for its cells
+pub trait StreamsWorld<L> {
+
+    /// Move the focus to a horizontal position, requesting reads for cells that have come into
+    /// range. Returns whether there is work for apply, which must not be done while the GPU
+    /// may still be using resources.
+    fn poll(&mut self, focus_x: f32, focus_z: f32) -> Result<bool, EngineError>;
+
+    /// Upload cells whose reads have finished and release cells that have gone out of range.
+    /// Returns whether anything changed, in which case commands need re-recording.
+    unsafe fn apply(&mut self, ecs: &mut EcsManager<L>, loader: &L) -> Result<bool, EngineError>;
+}
+
+/// WorldStreamingConfig struct
+/// How far around the focus cells are kept loaded. Cells are read once within load_radius and
+/// released once beyond unload_radius, which should be larger to avoid thrashing at the edges.
+#[derive(Copy, Clone, Debug)]
+pub struct WorldStreamingConfig {
+    pub cell_size: f32,
+    pub load_radius: u32,
+    pub unload_radius: u32,
+    pub max_uploads_per_frame: usize,
+    pub first_namespace: u32
+}
+
+impl Default for WorldStreamingConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 64.0,
+            load_radius: 2,
+            unload_radius: 3,
+            max_uploads_per_frame: 2,
+            first_namespace: 0x10000
+        }
+    }
+}
+
+/// CellState enum
+/// Progress of a cell through reading and uploading
+enum CellState<D> {
+    Reading,
+    Read(Option<D>),
+    Loaded(u32),
+    Empty
+}
+
+/// WorldStreamer struct
+/// Streams a world too large to load up front, by splitting it into cells and keeping only
+/// those near a focus point loaded. Cells are read on a worker thread, then uploaded within a
+/// per-frame limit on the main thread, each into its own ECS namespace.
+pub struct WorldStreamer<D: Send + 'static, U> {
+    config: WorldStreamingConfig,
+    uploader: U,
+    cells: HashMap<CellId, CellState<D>>,
+    focus: Option<CellId>,
+    next_namespace: u32,
+    requests: Option<Sender<CellId>>,
+    results: Receiver<(CellId, Result<Option<D>, EngineError>)>,
+    worker: Option<JoinHandle<()>>
+}
+
+impl<D: Send + 'static, U> WorldStreamer<D, U> {
+
+    /// Create a new instance, starting a worker thread that reads cells with the given reader
+    pub fn new<R>(
+        reader: R,
+        uploader: U,
+        config: WorldStreamingConfig
+    ) -> Result<Self, EngineError> where R: CellReader<Data = D> {
+        let (request_sender, request_receiver) = channel::<CellId>();
+        let (result_sender, result_receiver) = channel();
+        let mut reader = reader;
+        let worker = std::thread::Builder::new()
+            .name(String::from("world-streamer"))
+            .spawn(move || {
+                while let Ok(cell) = request_receiver.recv() {
+                    let result = reader.read_cell(cell);
+                    if result_sender.send((cell, result)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error starting world streaming thread: {}", e)))?;
+        Ok(Self {
+            next_namespace: config.first_namespace,
+            config,
+            uploader,
+            cells: HashMap::new(),
+            focus: None,
+            requests: Some(request_sender),
+            results: result_receiver,
+            worker: Some(worker)
+        })
+    }
+
+    pub fn get_uploader(&self) -> &U {
+        &self.uploader
+    }
+
+    /// Cells whose resources are currently loaded
+    pub fn loaded_cells(&self) -> Vec<CellId> {
+        self.cells.iter()
+            .filter(|(_, state)| matches!(state, CellState::Loaded(_)))
+            .map(|(cell, _)| *cell)
+            .collect()
+    }
+
+    /// Cells waiting to be read or uploaded
+    pub fn pending_cell_count(&self) -> usize {
+        self.cells.values()
+            .filter(|state| matches!(state, CellState::Reading | CellState::Read(_)))
+            .count()
+    }
+
+    fn receive_results(&mut self) -> Result<(), EngineError> {
+        loop {
+            match self.results.try_recv() {
+                Ok((cell, result)) => {
+                    // Cells that went out of range while being read were already forgotten. A
+                    // cell that fails to read is left empty rather than stopping the world.
+                    let Some(state) = self.cells.get_mut(&cell) else {
+                        continue;
+                    };
+                    *state = match result {
+                        Ok(data) => CellState::Read(data),
+                        Err(e) => {
+                            println!("Error reading world cell {:?}: {:?}", cell, e);
+                            CellState::Empty
+                        }
+                    };
+                },
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(EngineError::OpFailed(
+                    String::from("World streaming thread stopped unexpectedly")))
+            }
+        }
+    }
+}
+
+impl<L, D: Send + 'static, U: CellUploader<L, D>> StreamsWorld<L> for WorldStreamer<D, U> {
+
+    fn poll(&mut self, focus_x: f32, focus_z: f32) -> Result<bool, EngineError> {
+        let focus = CellId::containing(focus_x, focus_z, self.config.cell_size);
+        if self.focus != Some(focus) {
+            self.focus = Some(focus);
+            let requests = self.requests.as_ref().unwrap();
+            for cell in focus.cells_within(self.config.load_radius) {
+                if self.cells.contains_key(&cell) {
+                    continue;
+                }
+                requests.send(cell)
+                    .map_err(|_| EngineError::OpFailed(
+                        String::from("World streaming thread stopped unexpectedly")))?;
+                self.cells.insert(cell, CellState::Reading);
+            }
+        }
+        self.receive_results()?;
+
+        let unload_radius = self.config.unload_radius.max(self.config.load_radius);
+        let work_pending = self.cells.iter().any(|(cell, state)| match state {
+            CellState::Read(_) => true,
+            _ => focus.distance_to(*cell) > unload_radius
+        });
+        Ok(work_pending)
+    }
+
+    unsafe fn apply(&mut self, ecs: &mut EcsManager<L>, loader: &L) -> Result<bool, EngineError> {
+        let Some(focus) = self.focus else {
+            return Ok(false);
+        };
+        let mut changed = false;
+
+        // Release cells that have gone out of range, or forget them if not yet uploaded
+        let unload_radius = self.config.unload_radius.max(self.config.load_radius);
+        let out_of_range: Vec<CellId> = self.cells.keys()
+            .filter(|cell| focus.distance_to(**cell) > unload_radius)
+            .copied()
+            .collect();
+        for cell in out_of_range {
+            if let Some(CellState::Loaded(namespace)) = self.cells.remove(&cell) {
+                ecs.release_namespace(namespace, loader);
+                self.uploader.cell_unloaded(cell);
+                changed = true;
+            }
+        }
+
+        // Upload the nearest cells that have been read
+        let mut ready: Vec<CellId> = self.cells.iter()
+            .filter(|(_, state)| matches!(state, CellState::Read(_)))
+            .map(|(cell, _)| *cell)
+            .collect();
+        ready.sort_by_key(|cell| focus.distance_to(*cell));
+        for cell in ready.into_iter().take(self.config.max_uploads_per_frame) {
+            let Some(CellState::Read(data)) = self.cells.remove(&cell) else {
+                continue;
+            };
+            let Some(data) = data else {
+                self.cells.insert(cell, CellState::Empty);
+                continue;
+            };
+            let namespace = self.next_namespace;
+            self.next_namespace += 1;
+            if let Err(e) = self.uploader.upload_cell(cell, data, ecs, loader, namespace) {
+                ecs.release_namespace(namespace, loader);
+                return Err(e);
+            }
+            self.cells.insert(cell, CellState::Loaded(namespace));
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+impl<D: Send + 'static, U> Drop for WorldStreamer<D, U> {
+    fn drop(&mut self) {
+        // Closing the request channel stops the worker once it finishes its current read
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// ColladaCellReader struct
+/// Reads each cell's models from a Collada file named after the cell, such as cell_3_-2.dae,
+/// in a directory. Cells without a file are empty.
+pub struct ColladaCellReader {
+    directory: PathBuf
+}
+
+impl ColladaCellReader {
+
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    pub fn path_for(&self, cell: CellId) -> PathBuf {
+        self.directory.join(format!("cell_{}_{}.dae", cell.x, cell.z))
+    }
+}
+
+impl CellReader for ColladaCellReader {
+    type Data = Vec<Model<StaticVertex>>;
+
+    fn read_cell(&mut self, cell: CellId) -> Result<Option<Self::Data>, EngineError> {
+        let path = self.path_for(cell);
+        let file_data = match std::fs::read(&path) {
+            Ok(file_data) => file_data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(EngineError::OpFailed(
+                format!("Error reading {}: {}", path.display(), e)))
+        };
+        let collada = COLLADA::try_new(&file_data)
+            .map_err(|e| EngineError::OpFailed(format!("{}: {}", path.display(), e)))?;
+        Ok(Some(collada.extract_models(Config::default())))
+    }
+}
+
+/// StaticModelUploader struct
+/// Uploads each of a cell's models as a vertex buffer. The handles of the buffers for each
+/// loaded cell are shared, so that the scene can draw them.
+#[derive(Default)]
+pub struct StaticModelUploader {
+    loaded: Rc<RefCell<HashMap<CellId, Vec<Handle>>>>
+}
+
+impl StaticModelUploader {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the vertex buffers of each loaded cell, shared with the scene
+    pub fn get_loaded_buffers(&self) -> Rc<RefCell<HashMap<CellId, Vec<Handle>>>> {
+        self.loaded.clone()
+    }
+}
+
+impl CellUploader<VkContext, Vec<Model<StaticVertex>>> for StaticModelUploader {
+
+    fn upload_cell(
+        &mut self,
+        cell: CellId,
+        data: Vec<Model<StaticVertex>>,
+        ecs: &mut EcsManager<VkContext>,
+        loader: &VkContext,
+        namespace: u32
+    ) -> Result<(), EngineError> {
+        let mut handles = vec![];
+        for model in data.iter().filter(|model| !model.vertices.is_empty()) {
+            let creation_data = VboCreationData {
+                vertex_data: Some(model.vertices.as_ptr() as *const u8),
+                vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
+                vertex_count: model.vertices.len(),
+                vertex_layout: Some(VertexLayout::static_vertex()),
+                draw_indexed: false,
+                index_data: None,
+                usage: BufferUsage::InitialiseOnceVertexBuffer
+            };
+            let buffer = BufferWrapper::create(loader, ecs, &creation_data)?;
+            handles.push(ecs.add_item_in_namespace(namespace, buffer));
+        }
+        self.loaded.borrow_mut().insert(cell, handles);
+        Ok(())
+    }
+
+    fn cell_unloaded(&mut self, cell: CellId) {
+        self.loaded.borrow_mut().remove(&cell);
+    }
+}
@@ -0,0 +1,622 @@
+
+use crate::{
+    Timer, StockTimer, TestTimer, SaveSlots, DataDirKind, RandomService, Scheduler, Sequence,
+    platform_dir, backup_path, write_atomic, InputLatencyTracker, PresentTiming, CellId, CellReader,
+    CellUploader, StreamsWorld, WorldStreamer, WorldStreamingConfig, ShaderReloader, EngineError,
+    InputMap, InputBindings, InputSource, AxisBinding, GamepadButton, GamepadAxis, Scene,
+    StockScene, SimpleConfig, SimpleFrame, RecordingStrategy
+};
+use ecs::{EcsManager, resource::Resource};
+use crate::internals::{ResizeDebouncer, SwapchainReload, RenderSuspension, declared_features};
+use crate::crash::CrashContext;
+use crate::input::TextInputFilter;
+use crate::simple::SimpleApp;
+use vk_renderer::{ShaderStage, FeatureDeclaration, DepthConvention};
+use window::{PhysicalSize, KeyCode, KeyState, MouseButton, Ime, WindowStateEvent};
+use serde::{Serialize, Deserialize};
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[test]
+fn test_timer_steps_deterministically() {
+    let mut timer = TestTimer::new(16);
+    assert_eq!(timer.pull_time_step_millis(), 16);
+    timer.advance(100);
+    assert_eq!(timer.pull_time_step_millis(), 116);
+    assert_eq!(timer.pull_time_step_millis(), 16);
+    assert_eq!(timer.get_frame_number(), 3);
+    assert_eq!(timer.get_elapsed_time(), Duration::from_millis(148));
+}
+
+#[test]
+fn test_timer_wall_clock_follows_elapsed_time() {
+    let mut timer = TestTimer::new(0);
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    timer.set_wall_clock_time(start);
+    timer.advance(500);
+    timer.pull_time_step_millis();
+    assert_eq!(timer.get_wall_clock_time(), start + Duration::from_millis(500));
+}
+
+#[test]
+fn stock_timer_counts_frames() {
+    let mut timer = StockTimer::new();
+    assert_eq!(timer.get_frame_number(), 0);
+    timer.pull_time_step_millis();
+    timer.pull_time_step_millis();
+    assert_eq!(timer.get_frame_number(), 2);
+}
+
+#[test]
+fn resize_debouncer_waits_for_size_to_settle() {
+    let mut debouncer = ResizeDebouncer::new(Duration::from_millis(100));
+    let current_size = PhysicalSize::new(800, 600);
+    let start = Instant::now();
+    debouncer.on_resized(PhysicalSize::new(810, 600), current_size, start);
+    let later = start + Duration::from_millis(50);
+    debouncer.on_resized(PhysicalSize::new(820, 600), current_size, later);
+    assert_eq!(debouncer.take_settled(start + Duration::from_millis(120)), None);
+    assert_eq!(
+        debouncer.take_settled(start + Duration::from_millis(150)),
+        Some(PhysicalSize::new(820, 600)));
+    assert!(!debouncer.is_pending());
+}
+
+#[test]
+fn resize_debouncer_cancels_when_size_returns() {
+    let mut debouncer = ResizeDebouncer::new(Duration::from_millis(100));
+    let current_size = PhysicalSize::new(800, 600);
+    let start = Instant::now();
+    debouncer.on_resized(PhysicalSize::new(810, 600), current_size, start);
+    debouncer.on_resized(current_size, current_size, start + Duration::from_millis(10));
+    assert!(!debouncer.is_pending());
+    assert_eq!(debouncer.take_settled(start + Duration::from_millis(500)), None);
+}
+
+#[test]
+fn rendering_resumes_once_neither_minimized_nor_occluded() {
+    let mut suspension = RenderSuspension::default();
+    assert!(!suspension.is_suspended());
+    assert!(!suspension.on_resized(PhysicalSize::new(0, 0)));
+    assert!(suspension.is_suspended());
+    assert!(!suspension.on_occluded(true));
+    assert!(!suspension.on_resized(PhysicalSize::new(800, 600)));
+    assert!(suspension.is_suspended());
+    assert!(suspension.on_occluded(false));
+    assert!(!suspension.is_suspended());
+    assert!(!suspension.on_resized(PhysicalSize::new(640, 480)));
+}
+
+#[test]
+fn forced_rebuild_survives_resize_to_same_size() {
+    let mut debouncer = ResizeDebouncer::new(Duration::from_millis(100));
+    let current_size = PhysicalSize::new(800, 600);
+    let start = Instant::now();
+    debouncer.force(current_size, start);
+    debouncer.on_resized(current_size, current_size, start + Duration::from_millis(10));
+    assert!(debouncer.is_pending());
+    assert_eq!(debouncer.take_settled(start + Duration::from_millis(120)), Some(current_size));
+    debouncer.on_resized(current_size, current_size, start + Duration::from_millis(130));
+    assert!(!debouncer.is_pending());
+}
+
+#[test]
+fn swapchain_reload_keeps_layouts_unless_image_count_changes() {
+    assert_eq!(SwapchainReload::after_rebuild(3, 3), SwapchainReload::SwapchainResources);
+    assert_eq!(SwapchainReload::after_rebuild(3, 2), SwapchainReload::AllDynamicResources);
+    assert_eq!(SwapchainReload::after_rebuild(2, 3), SwapchainReload::AllDynamicResources);
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct TestSave {
+    level: u32,
+    player_name: String
+}
+
+fn empty_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("engine-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn save_slots_round_trip() {
+    let slots = SaveSlots::in_dir(empty_temp_dir("round-trip"), 2);
+    assert!(slots.load::<TestSave>("slot1").unwrap().is_none());
+    let save = TestSave { level: 3, player_name: "Player".to_owned() };
+    slots.save("slot1", &save).unwrap();
+    let loaded = slots.load::<TestSave>("slot1").unwrap().unwrap();
+    assert_eq!(loaded.data, save);
+    assert_eq!(loaded.version, 2);
+    assert!(!loaded.restored_from_backup);
+    assert_eq!(slots.list_slots().unwrap(), vec!["slot1".to_owned()]);
+    assert!(slots.save("../escape", &save).is_err());
+    std::fs::remove_dir_all(slots.get_dir()).unwrap();
+}
+
+#[test]
+fn corrupt_save_restores_backup() {
+    let slots = SaveSlots::in_dir(empty_temp_dir("corrupt"), 1);
+    slots.save("slot", &TestSave { level: 1, player_name: "Old".to_owned() }).unwrap();
+    slots.save("slot", &TestSave { level: 2, player_name: "New".to_owned() }).unwrap();
+    let path = slots.get_dir().join("slot.toml");
+    let contents = std::fs::read_to_string(&path).unwrap().replace("level = 2", "level = 9");
+    std::fs::write(&path, contents).unwrap();
+
+    let loaded = slots.load::<TestSave>("slot").unwrap().unwrap();
+    assert_eq!(loaded.data.level, 1);
+    assert!(loaded.restored_from_backup);
+    assert!(backup_path(&path).exists());
+    assert!(!slots.load::<TestSave>("slot").unwrap().unwrap().restored_from_backup);
+    std::fs::remove_dir_all(slots.get_dir()).unwrap();
+}
+
+#[test]
+fn write_atomic_replaces_the_file_and_keeps_a_backup() {
+    let dir = empty_temp_dir("atomic");
+    let path = dir.join("settings.toml");
+    write_atomic(&path, b"first").unwrap();
+    assert!(!backup_path(&path).exists());
+    write_atomic(&path, b"second").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"first");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn saves_from_newer_versions_are_refused() {
+    let dir = empty_temp_dir("newer");
+    SaveSlots::in_dir(dir.clone(), 5)
+        .save("slot", &TestSave { level: 1, player_name: "Future".to_owned() })
+        .unwrap();
+    assert!(SaveSlots::in_dir(dir.clone(), 4).load::<TestSave>("slot").is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+#[test]
+fn data_dirs_follow_xdg_with_home_fallback() {
+    let env = |key: &str| match key {
+        "HOME" => Some("/home/user".to_owned()),
+        "XDG_DATA_HOME" => Some("relative/ignored".to_owned()),
+        _ => None
+    };
+    assert_eq!(
+        platform_dir(DataDirKind::Config, "game", env).unwrap(),
+        PathBuf::from("/home/user/.config/game"));
+    assert_eq!(
+        platform_dir(DataDirKind::Saves, "game", env).unwrap(),
+        PathBuf::from("/home/user/.local/share/game"));
+    assert!(platform_dir(DataDirKind::Saves, "game", |_| None).is_err());
+}
+
+#[test]
+fn crash_report_includes_engine_state() {
+    let context = CrashContext {
+        frame_number: 42,
+        last_recorded_passes: Some("[Main] for image 1 in frame 42".to_owned()),
+        resource_counts: vec![("BufferWrapper", 3)],
+        device_description: "Test GPU".to_owned()
+    };
+    let report = context.report("explicit panic at src/main.rs:1:1", &Backtrace::disabled());
+    assert!(report.contains("explicit panic at src/main.rs:1:1"));
+    assert!(report.contains("Frame number: 42"));
+    assert!(report.contains("Last recorded passes: [Main] for image 1 in frame 42"));
+    assert!(report.contains("Device: Test GPU"));
+    assert!(report.contains("    BufferWrapper: 3"));
+}
+
+#[test]
+fn random_streams_are_reproducible_and_independent() {
+    let mut first = RandomService::new(1234);
+    let mut second = RandomService::new(1234);
+    let sequence: Vec<u32> = (0..8).map(|_| first.stream("ai").next_u32()).collect();
+
+    // Drawing from another stream first must not change the "ai" sequence
+    second.stream("particles").next_u64();
+    let repeated: Vec<u32> = (0..8).map(|_| second.stream("ai").next_u32()).collect();
+    assert_eq!(sequence, repeated);
+    assert_ne!(first.stream("loot").next_u32(), RandomService::new(1234).stream("ai").next_u32());
+
+    for _ in 0..1000 {
+        let value = first.stream("ai").range_u32(5, 10);
+        assert!((5..10).contains(&value));
+        let value = first.stream("ai").next_f32();
+        assert!((0.0..1.0).contains(&value));
+    }
+}
+
+#[test]
+fn random_state_resumes_after_save_and_load() {
+    let slots = SaveSlots::in_dir(empty_temp_dir("random"), 1);
+    let mut random = RandomService::new(u64::MAX);
+    random.stream("ai").next_u64();
+    slots.save("random", &random).unwrap();
+    let expected: Vec<u64> = (0..4).map(|_| random.stream("ai").next_u64()).collect();
+
+    let mut loaded = slots.load::<RandomService>("random").unwrap().unwrap().data;
+    assert_eq!(loaded.get_seed(), u64::MAX);
+    let resumed: Vec<u64> = (0..4).map(|_| loaded.stream("ai").next_u64()).collect();
+    assert_eq!(expected, resumed);
+}
+
+#[test]
+fn scheduler_runs_delayed_and_repeating_callbacks() {
+    let mut scheduler = Scheduler::new();
+    let log = Rc::new(RefCell::new(vec![]));
+    let once_log = log.clone();
+    scheduler.after(Duration::from_millis(100), move |_| once_log.borrow_mut().push("once"));
+    let repeat_log = log.clone();
+    let repeating = scheduler.every(Duration::from_millis(40), move |_| {
+        repeat_log.borrow_mut().push("every")
+    });
+
+    scheduler.advance(Duration::from_millis(50));
+    assert_eq!(*log.borrow(), vec!["every"]);
+
+    // A long step catches up on every interval it covers
+    scheduler.advance(Duration::from_millis(70));
+    assert_eq!(*log.borrow(), vec!["every", "once", "every", "every"]);
+
+    assert!(scheduler.cancel(repeating));
+    assert!(!scheduler.is_scheduled(repeating));
+    scheduler.advance(Duration::from_millis(100));
+    assert_eq!(log.borrow().len(), 4);
+}
+
+#[test]
+fn scheduler_sequences_wait_for_time_and_conditions() {
+    let mut scheduler = Scheduler::new();
+    let step = Rc::new(Cell::new(0));
+    let ready = Rc::new(Cell::new(false));
+    let (first, second, condition) = (step.clone(), step.clone(), ready.clone());
+    let sequence = Sequence::new()
+        .then(move |_| first.set(1))
+        .wait(Duration::from_millis(30))
+        .wait_until(move || condition.get())
+        .then(move |scheduler| {
+            second.set(2);
+
+            // Work scheduled from a callback waits for a later update
+            let third = second.clone();
+            scheduler.after(Duration::ZERO, move |_| third.set(3));
+        });
+    let id = scheduler.run_sequence(sequence);
+
+    scheduler.advance(Duration::from_millis(10));
+    assert_eq!(step.get(), 1);
+    scheduler.advance(Duration::from_millis(30));
+    assert_eq!(step.get(), 1);
+    ready.set(true);
+    scheduler.advance(Duration::from_millis(10));
+    assert_eq!(step.get(), 2);
+    assert!(!scheduler.is_scheduled(id));
+    scheduler.advance(Duration::from_millis(10));
+    assert_eq!(step.get(), 3);
+}
+
+#[test]
+fn latency_tracker_measures_from_receipt_to_update_and_present() {
+    let mut tracker = InputLatencyTracker::new(PresentTiming::QueuePresentReturn);
+    let start = Instant::now();
+    tracker.on_input_received(start);
+    tracker.on_input_received(start + Duration::from_millis(4));
+    tracker.on_update(start + Duration::from_millis(10));
+
+    // A skipped frame leaves the input waiting for the next submitted one
+    tracker.on_frame_submitted(None, start + Duration::from_millis(20));
+    tracker.on_frame_submitted(None, start + Duration::from_millis(36));
+
+    let report = tracker.report();
+    let to_update = report.receipt_to_update.unwrap();
+    assert_eq!(to_update.count, 2);
+    assert_eq!(to_update.min, Duration::from_millis(6));
+    assert_eq!(to_update.max, Duration::from_millis(10));
+    assert_eq!(to_update.mean, Duration::from_millis(8));
+    let to_present = report.receipt_to_present.unwrap();
+    assert_eq!(to_present.count, 2);
+    assert_eq!(to_present.min, Duration::from_millis(16));
+    assert_eq!(to_present.max, Duration::from_millis(20));
+}
+
+#[test]
+fn latency_tracker_waits_for_identified_presents() {
+    let mut tracker = InputLatencyTracker::new(PresentTiming::PresentWait);
+    let start = Instant::now();
+    tracker.on_input_received(start);
+    tracker.on_update(start + Duration::from_millis(1));
+    tracker.on_frame_submitted(Some(7), start + Duration::from_millis(2));
+    tracker.on_input_received(start + Duration::from_millis(3));
+    tracker.on_update(start + Duration::from_millis(4));
+    tracker.on_frame_submitted(Some(8), start + Duration::from_millis(5));
+    assert!(tracker.report().receipt_to_present.is_none());
+    assert_eq!(tracker.oldest_pending_present(), Some(7));
+
+    tracker.on_present_observed(7, start + Duration::from_millis(30));
+    assert_eq!(tracker.oldest_pending_present(), Some(8));
+    tracker.on_present_lost(8);
+    assert_eq!(tracker.oldest_pending_present(), None);
+
+    let report = tracker.report();
+    assert_eq!(report.receipt_to_present.unwrap().count, 1);
+    assert_eq!(report.receipt_to_present.unwrap().max, Duration::from_millis(30));
+    assert_eq!(report.lost_presents, 1);
+}
+
+#[test]
+fn latency_stats_report_95th_percentile() {
+    let mut tracker = InputLatencyTracker::new(PresentTiming::QueuePresentReturn);
+    let start = Instant::now();
+    for millis in 1..=100 {
+        tracker.on_input_received(start);
+        tracker.on_update(start + Duration::from_millis(millis));
+    }
+    let stats = tracker.report().receipt_to_update.unwrap();
+    assert_eq!(stats.count, 100);
+    assert_eq!(stats.p95, Duration::from_millis(95));
+    assert_eq!(stats.max, Duration::from_millis(100));
+}
+
+struct NullLoader;
+
+struct CellMarker;
+
+impl Resource<NullLoader> for CellMarker {
+    type CreationData = ();
+
+    fn create(
+        _loader: &NullLoader,
+        _ecs: &EcsManager<NullLoader>,
+        _data: &()
+    ) -> Result<Self, EngineError> {
+        Ok(CellMarker)
+    }
+
+    fn release(&self, _loader: &NullLoader) {}
+}
+
+/// Cells with negative x are empty
+struct GridReader;
+
+impl CellReader for GridReader {
+    type Data = CellId;
+
+    fn read_cell(&mut self, cell: CellId) -> Result<Option<CellId>, EngineError> {
+        Ok(if cell.x < 0 { None } else { Some(cell) })
+    }
+}
+
+#[derive(Default)]
+struct MarkerUploader {
+    uploaded: Vec<CellId>,
+    unloaded: Vec<CellId>
+}
+
+impl CellUploader<NullLoader, CellId> for MarkerUploader {
+    fn upload_cell(
+        &mut self,
+        cell: CellId,
+        data: CellId,
+        ecs: &mut EcsManager<NullLoader>,
+        _loader: &NullLoader,
+        namespace: u32
+    ) -> Result<(), EngineError> {
+        assert_eq!(cell, data);
+        ecs.add_item_in_namespace(namespace, CellMarker);
+        self.uploaded.push(cell);
+        Ok(())
+    }
+
+    fn cell_unloaded(&mut self, cell: CellId) {
+        self.unloaded.push(cell);
+    }
+}
+
+/// Poll until the worker has read everything requested, then apply
+fn settle_streamer(
+    streamer: &mut WorldStreamer<CellId, MarkerUploader>,
+    ecs: &mut EcsManager<NullLoader>,
+    focus: (f32, f32)
+) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        StreamsWorld::<NullLoader>::poll(streamer, focus.0, focus.1).unwrap();
+        unsafe {
+            streamer.apply(ecs, &NullLoader).unwrap();
+        }
+        if streamer.pending_cell_count() == 0 {
+            return;
+        }
+        assert!(Instant::now() < deadline, "Cells were never read");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn cells_are_listed_nearest_first() {
+    assert_eq!(CellId::containing(-0.5, 130.0, 64.0), CellId::new(-1, 2));
+    let cells = CellId::new(0, 0).cells_within(1);
+    assert_eq!(cells.len(), 9);
+    assert_eq!(cells[0], CellId::new(0, 0));
+    assert!(cells[1..5].iter().all(|cell| cell.x == 0 || cell.z == 0));
+    assert_eq!(CellId::new(2, -1).distance_to(CellId::new(-1, 0)), 3);
+}
+
+#[test]
+fn world_streamer_loads_and_releases_cells_around_focus() {
+    let config = WorldStreamingConfig {
+        cell_size: 10.0,
+        load_radius: 1,
+        unload_radius: 1,
+        max_uploads_per_frame: 4,
+        first_namespace: 100
+    };
+    let mut streamer =
+        WorldStreamer::new(GridReader, MarkerUploader::default(), config).unwrap();
+    let mut ecs: EcsManager<NullLoader> = EcsManager::new();
+
+    // Around the origin, the three cells with negative x are empty
+    settle_streamer(&mut streamer, &mut ecs, (5.0, 5.0));
+    assert_eq!(streamer.loaded_cells().len(), 6);
+    assert_eq!(streamer.get_uploader().uploaded[0], CellId::new(0, 0));
+    assert_eq!(ecs.resource_counts()[0].1, 6);
+
+    // Moving two cells along x leaves only the x = 1 column shared
+    settle_streamer(&mut streamer, &mut ecs, (25.0, 5.0));
+    assert_eq!(streamer.loaded_cells().len(), 9);
+    assert_eq!(streamer.get_uploader().unloaded.len(), 3);
+    assert!(streamer.get_uploader().unloaded.iter().all(|cell| cell.x == 0));
+    assert_eq!(ecs.resource_counts()[0].1, 9);
+}
+
+#[test]
+fn shader_reloader_reports_files_modified_after_watching() {
+    let dir = empty_temp_dir("shaders");
+    let path = dir.join("stock.frag");
+    std::fs::write(&path, "void main() {}").unwrap();
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+    let mut reloader = ShaderReloader::new().with_poll_interval(Duration::from_millis(100));
+    reloader.watch(&path, ShaderStage::Fragment, 3);
+    reloader.watch(dir.join("missing.vert"), ShaderStage::Vertex, 4);
+
+    let start = Instant::now();
+    assert!(reloader.poll_changed(start).is_empty());
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(2000)).unwrap();
+    assert!(reloader.poll_changed(start + Duration::from_millis(50)).is_empty());
+    assert_eq!(reloader.poll_changed(start + Duration::from_millis(100)), vec![0]);
+    assert!(reloader.poll_changed(start + Duration::from_millis(200)).is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stock_bindings_move_on_arrow_keys() {
+    let mut input = InputMap::default();
+    input.process_key(KeyCode::Left, KeyState::Pressed);
+    input.process_key(KeyCode::Up, KeyState::Pressed);
+    let state = input.snapshot();
+    assert_eq!(state.get_dx(), -1.0);
+    assert_eq!(state.get_dy(), 1.0);
+    input.process_key(KeyCode::Right, KeyState::Pressed);
+    input.process_key(KeyCode::Up, KeyState::Released);
+    let state = input.snapshot();
+    assert_eq!(state.get_dx(), 0.0);
+    assert_eq!(state.get_dy(), 0.0);
+}
+
+#[test]
+fn actions_report_presses_and_releases_once() {
+    let mut bindings = InputBindings::default();
+    bindings.bind_action("jump", InputSource::Key(KeyCode::Space));
+    bindings.bind_action("jump", InputSource::Gamepad(GamepadButton::South));
+    let mut input = InputMap::new(bindings);
+
+    input.process_gamepad_button(GamepadButton::South, true);
+    let state = input.snapshot();
+    assert!(state.is_pressed("jump") && state.was_just_pressed("jump"));
+    let state = input.snapshot();
+    assert!(state.is_pressed("jump") && !state.was_just_pressed("jump"));
+    input.process_gamepad_button(GamepadButton::South, false);
+    let state = input.snapshot();
+    assert!(!state.is_pressed("jump") && state.was_just_released("jump"));
+
+    // A tap between updates still counts as a press
+    input.process_key(KeyCode::Space, KeyState::Pressed);
+    input.process_key(KeyCode::Space, KeyState::Released);
+    let state = input.snapshot();
+    assert!(!state.is_pressed("jump") && state.was_just_pressed("jump"));
+    assert!(!state.is_pressed("unbound"));
+}
+
+#[test]
+fn rebinding_replaces_previous_inputs() {
+    let mut input = InputMap::default();
+    input.get_bindings_mut().bind_action("fire", InputSource::Key(KeyCode::LControl));
+    input.get_bindings_mut().rebind_action("fire", InputSource::Mouse(MouseButton::Left));
+    input.process_key(KeyCode::LControl, KeyState::Pressed);
+    assert!(!input.snapshot().is_pressed("fire"));
+    input.process_mouse_button(MouseButton::Left, KeyState::Pressed);
+    assert!(input.snapshot().is_pressed("fire"));
+    input.release_all();
+    assert!(!input.snapshot().is_pressed("fire"));
+}
+
+#[test]
+fn analog_axes_ignore_dead_zone_and_clamp() {
+    let mut input = InputMap::default();
+    input.get_bindings_mut().bind_axis("move_x", AxisBinding::Analog {
+        axis: GamepadAxis::LeftStickX,
+        scale: 1.0,
+        dead_zone: 0.2
+    });
+    input.process_gamepad_axis(GamepadAxis::LeftStickX, 0.1);
+    assert_eq!(input.snapshot().get_dx(), 0.0);
+    input.process_gamepad_axis(GamepadAxis::LeftStickX, 0.5);
+    assert_eq!(input.snapshot().get_dx(), 0.5);
+    input.process_key(KeyCode::Right, KeyState::Pressed);
+    assert_eq!(input.snapshot().get_dx(), 1.0);
+}
+
+#[test]
+fn mouse_motion_accumulates_until_snapshot() {
+    let mut input = InputMap::default();
+    input.process_mouse_motion(3.0, -1.0);
+    input.process_mouse_motion(2.0, -1.5);
+    assert_eq!(input.snapshot().get_mouse_delta(), (5.0, -2.5));
+    assert_eq!(input.snapshot().get_mouse_delta(), (0.0, 0.0));
+}
+
+#[test]
+fn typed_characters_are_not_repeated_around_ime_composition() {
+    let mut text_input = TextInputFilter::new();
+    assert!(text_input.on_character('a') == Some(WindowStateEvent::CharacterReceived('a')));
+    assert!(text_input.on_character('\u{8}').is_none());
+    assert!(text_input.on_ime(Ime::Enabled).is_none());
+
+    let composing = text_input.on_ime(Ime::Preedit(String::from("ni"), Some((2, 2))));
+    assert!(composing == Some(WindowStateEvent::TextComposition {
+        text: String::from("ni"),
+        cursor: Some((2, 2))
+    }));
+    assert!(text_input.on_character('n').is_none());
+    assert!(text_input.on_ime(Ime::Preedit(String::new(), None)).is_some());
+    let committed = text_input.on_ime(Ime::Commit(String::from("你")));
+    assert!(committed == Some(WindowStateEvent::TextCommitted(String::from("你"))));
+
+    // Platforms that repeat the committed text as characters have them dropped, once
+    assert!(text_input.on_character('你').is_none());
+    assert!(text_input.on_character('你') == Some(WindowStateEvent::CharacterReceived('你')));
+
+    // Cancelling a composition with empty text lets typing through again
+    assert!(text_input.on_ime(Ime::Preedit(String::from("h"), None)).is_some());
+    assert!(text_input.on_character('h').is_none());
+    assert!(text_input.on_ime(Ime::Preedit(String::new(), None)).is_some());
+    assert!(text_input.on_character('b') == Some(WindowStateEvent::CharacterReceived('b')));
+}
+
+#[test]
+fn simple_scenes_forward_to_the_stock_scene() {
+    let config = SimpleConfig { reversed_z: true, ..SimpleConfig::default() };
+    let app = SimpleApp::new(config, |_: &mut SimpleFrame| {});
+    let mut scene = app.new_scene();
+    scene.attach_depth_convention(DepthConvention::ReversedZ);
+    assert_eq!(scene.get_stock_scene().get_depth_convention(), DepthConvention::ReversedZ);
+    assert_eq!(scene.get_pass_names(), StockScene::new().get_pass_names());
+    assert_eq!(scene.get_recording_strategy(), RecordingStrategy::Prerecorded);
+    assert!(scene.take_next_scene().is_none());
+}
+
+#[test]
+fn descriptor_indexing_is_declared_whatever_else_is_requested() {
+    for (dynamic_rendering, present_wait) in [(false, false), (true, false), (false, true)] {
+        let features = declared_features(dynamic_rendering, present_wait);
+        assert!(features.contains(&FeatureDeclaration::DescriptorIndexing));
+        assert_eq!(features.contains(&FeatureDeclaration::DynamicRendering), dynamic_rendering);
+        assert_eq!(features.contains(&FeatureDeclaration::PresentWait), present_wait);
+    }
+}
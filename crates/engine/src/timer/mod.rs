@@ -1,5 +1,29 @@
 pub mod stock;
+pub mod test;
 
+use std::time::{Duration, SystemTime};
+
+/// Timer trait
+/// Source of time for the engine. The main loop pulls one time step per frame, so the number of
+/// steps pulled doubles as the frame number.
 pub trait Timer {
+
+    /// Return the time passed since the previous call, and advance the frame number
     fn pull_time_step_millis(&mut self) -> u64;
+
+    /// Number of time steps pulled so far, which is the number of frames begun
+    fn get_frame_number(&self) -> u64;
+
+    /// Monotonic time passed since the timer was created, unaffected by changes to the system
+    /// clock
+    fn get_elapsed_time(&self) -> Duration;
+
+    /// Discard the time passed since the previous step without beginning a frame, such as when
+    /// rendering resumes after being suspended, so the next step doesn't include the pause
+    fn skip_time_step(&mut self) {}
+
+    /// Current wall-clock time, which is suitable for display but may jump backward or forward
+    fn get_wall_clock_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
 }
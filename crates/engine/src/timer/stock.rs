@@ -1,15 +1,22 @@
 
 use crate::Timer;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+/// StockTimer struct
+/// Timer driven by the system's monotonic clock
 pub struct StockTimer {
-    last_update_time: Instant
+    start_time: Instant,
+    last_update_time: Instant,
+    frame_number: u64
 }
 
 impl StockTimer {
     pub fn new() -> Self {
+        let now = Instant::now();
         Self {
-            last_update_time: Instant::now()
+            start_time: now,
+            last_update_time: now,
+            frame_number: 0
         }
     }
 }
@@ -19,6 +26,19 @@ impl Timer for StockTimer {
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_update_time);
         self.last_update_time = now;
+        self.frame_number += 1;
         elapsed.as_millis() as u64
     }
+
+    fn skip_time_step(&mut self) {
+        self.last_update_time = Instant::now();
+    }
+
+    fn get_frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    fn get_elapsed_time(&self) -> Duration {
+        self.start_time.elapsed()
+    }
 }
@@ -0,0 +1,61 @@
+
+use crate::Timer;
+use std::time::{Duration, SystemTime};
+
+/// TestTimer struct
+/// Timer that only advances when told to, for deterministic testing of scene update logic. Each
+/// pulled time step is the fixed step plus any time added with advance since the previous pull.
+pub struct TestTimer {
+    fixed_step_millis: u64,
+    pending_millis: u64,
+    elapsed_millis: u64,
+    frame_number: u64,
+    wall_clock_start: SystemTime
+}
+
+impl TestTimer {
+
+    /// Create a new instance which reports the given step for each frame, starting with a wall
+    /// clock at the Unix epoch
+    pub fn new(fixed_step_millis: u64) -> Self {
+        Self {
+            fixed_step_millis,
+            pending_millis: 0,
+            elapsed_millis: 0,
+            frame_number: 0,
+            wall_clock_start: SystemTime::UNIX_EPOCH
+        }
+    }
+
+    /// Add time to be reported by the next pulled time step
+    pub fn advance(&mut self, millis: u64) {
+        self.pending_millis += millis;
+    }
+
+    /// Set the wall-clock time corresponding to the current elapsed time
+    pub fn set_wall_clock_time(&mut self, time: SystemTime) {
+        self.wall_clock_start = time - Duration::from_millis(self.elapsed_millis);
+    }
+}
+
+impl Timer for TestTimer {
+    fn pull_time_step_millis(&mut self) -> u64 {
+        let step = self.fixed_step_millis + self.pending_millis;
+        self.pending_millis = 0;
+        self.elapsed_millis += step;
+        self.frame_number += 1;
+        step
+    }
+
+    fn get_frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    fn get_elapsed_time(&self) -> Duration {
+        Duration::from_millis(self.elapsed_millis)
+    }
+
+    fn get_wall_clock_time(&self) -> SystemTime {
+        self.wall_clock_start + self.get_elapsed_time()
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Scene, InputState, internals::{EngineInternals, RenderSuspension}};
+use vk_renderer::{VkContext, PresentResult};
+use window::{Window, WindowId, PhysicalSize};
+use error::EngineError;
+
+/// Viewport struct
+/// A window opened alongside the main one through Engine::add_window, such as a preview window
+/// in an editor, with its own render context and scene. The engine's main loop routes events to
+/// it by window ID, updates it with the main scene and draws it after the main window. While it
+/// is minimized or occluded, it is neither updated nor drawn, independently of the main window.
+pub(crate) struct Viewport {
+    number: usize,
+    window: Window,
+    internals: EngineInternals,
+    scene: Box<dyn Scene<VkContext>>,
+    suspension: RenderSuspension,
+    torn_down: bool
+}
+
+impl Viewport {
+
+    /// Wrap a window and the internals created for it, recording the scene's commands
+    pub fn new(
+        number: usize,
+        window: Window,
+        internals: EngineInternals,
+        mut scene: Box<dyn Scene<VkContext>>
+    ) -> Result<Self, EngineError> {
+        scene.attach_debug_labels(internals.get_debug_labels());
+        scene.attach_pre_rotation(internals.get_pre_rotation());
+        internals.prewarm_pipelines(&scene, |_, _| {})?;
+        internals.record_graphics_commands(&scene)?;
+        Ok(Self {
+            number,
+            window,
+            internals,
+            scene,
+            suspension: RenderSuspension::default(),
+            torn_down: false
+        })
+    }
+
+    /// The number that Engine::add_window returned for this window
+    pub fn get_number(&self) -> usize {
+        self.number
+    }
+
+    pub fn get_window_id(&self) -> WindowId {
+        self.window.get_window_id()
+    }
+
+    /// Note that the window has been resized, unless it was snapped back to its locked aspect
+    /// ratio, in which case another resize follows. Minimizing reports a zero size, which
+    /// suspends the viewport rather than rebuilding its surface.
+    pub fn on_resized(&mut self, size: PhysicalSize<u32>) {
+        if self.suspension.on_resized(size) {
+            self.internals.resume_rendering(size);
+        } else if !self.suspension.is_suspended() {
+            let previous_size = self.internals.get_last_known_size();
+            if !self.window.enforce_aspect_ratio(size, previous_size) {
+                self.internals.queue_resize(size);
+            }
+        }
+    }
+
+    /// Note whether the window is occluded, suspending the viewport while it is
+    pub fn on_occluded(&mut self, occluded: bool) {
+        if self.suspension.on_occluded(occluded) {
+            self.internals.resume_rendering(self.window.get_client_size());
+        }
+    }
+
+    /// Rebuild the surface if a resize has settled, then update the scene and ask for a redraw;
+    /// does nothing while the viewport is suspended. Errors returned can't be recovered from.
+    pub fn update(&mut self, time_step_millis: u64, input: &InputState) -> Result<(), EngineError> {
+        if self.suspension.is_suspended() {
+            return Ok(());
+        }
+        if let Some(size) = self.internals.take_settled_resize() {
+            self.internals.recreate_surface(&self.window, size, &self.scene)?;
+            self.scene.attach_pre_rotation(self.internals.get_pre_rotation());
+        }
+        self.scene.update(time_step_millis, input);
+        self.window.request_redraw();
+        Ok(())
+    }
+
+    /// Render and present a frame, rebuilding the swapchain or surface or recovering from device
+    /// loss the same way as for the main window. Returns whether the device was lost and has been
+    /// recreated. Errors returned can't be recovered from.
+    pub fn render(&mut self) -> Result<bool, EngineError> {
+        if self.suspension.is_suspended() {
+            return Ok(false);
+        }
+        match self.internals.render_frame(&self.scene) {
+            Ok(PresentResult::Ok) | Ok(PresentResult::FrameSkipped) => {},
+            Ok(PresentResult::Suboptimal) | Ok(PresentResult::SwapchainOutOfDate)
+            if self.internals.has_pending_resize() => {
+                // The surface is rebuilt once the resize settles
+            },
+            Ok(PresentResult::Suboptimal) => {
+                self.internals.recreate_swapchain(&self.scene)?;
+                self.scene.attach_pre_rotation(self.internals.get_pre_rotation());
+            },
+            Ok(PresentResult::SwapchainOutOfDate) | Err(EngineError::Timeout(_)) => {
+                let last_known_size = self.internals.get_last_known_size();
+                self.internals.recreate_surface(&self.window, last_known_size, &self.scene)?;
+                self.scene.attach_pre_rotation(self.internals.get_pre_rotation());
+            },
+            Err(EngineError::DeviceLost(_)) => {
+                if let Err(e) = self.internals.recover_from_device_loss(&self.window, &self.scene) {
+                    // The old context may already be torn down, so it isn't torn down again
+                    self.torn_down = true;
+                    return Err(e);
+                }
+                self.scene.attach_debug_labels(self.internals.get_debug_labels());
+                self.scene.attach_pre_rotation(self.internals.get_pre_rotation());
+                self.scene.on_device_restored();
+                return Ok(true);
+            },
+            Err(e) => return Err(e)
+        };
+        Ok(false)
+    }
+
+    pub fn teardown(&mut self) {
+        if !self.torn_down {
+            self.internals.engine_teardown();
+            self.torn_down = true;
+        }
+    }
+}
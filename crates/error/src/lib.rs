@@ -5,5 +5,7 @@ pub enum EngineError {
     MissingResource(String),
     Compatibility(String),
     EngineError(String),
+    Timeout(String),
+    DeviceLost(String),
     UserError(String)
 }
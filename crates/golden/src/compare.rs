@@ -0,0 +1,168 @@
+use error::EngineError;
+use image::{Rgba, RgbaImage};
+use std::fmt::{Display, Formatter};
+
+/// Size of the square windows that structural similarity is measured over
+const SSIM_WINDOW_SIZE: u32 = 8;
+
+/// Stabilising constants for structural similarity, for 8-bit luminance
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Tolerance struct
+/// How different a rendered image may be from its reference and still pass. A pixel counts as
+/// different if any channel differs by more than the channel delta; a small fraction of those
+/// is allowed, such as for rasterisation differences along edges between drivers. The structural
+/// similarity (SSIM) of the two images' luminance must also be at least the given minimum, which
+/// catches broad changes that stay within the per-channel delta.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Tolerance {
+    pub max_channel_delta: u8,
+    pub max_differing_fraction: f32,
+    pub min_ssim: f32
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            max_channel_delta: 2,
+            max_differing_fraction: 0.001,
+            min_ssim: 0.98
+        }
+    }
+}
+
+/// Comparison struct
+/// Measurements of how a rendered image differs from its reference
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Comparison {
+    pub max_channel_delta: u8,
+    pub differing_pixels: usize,
+    pub total_pixels: usize,
+    pub ssim: f32
+}
+
+impl Comparison {
+
+    pub fn differing_fraction(&self) -> f32 {
+        match self.total_pixels {
+            0 => 0.0,
+            total => self.differing_pixels as f32 / total as f32
+        }
+    }
+
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.differing_fraction() <= tolerance.max_differing_fraction &&
+            self.ssim >= tolerance.min_ssim
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} pixels differ (largest channel delta {}), SSIM {:.4}",
+            self.differing_pixels,
+            self.total_pixels,
+            self.max_channel_delta,
+            self.ssim)
+    }
+}
+
+/// Compare a rendered image against its reference, which must be the same size
+pub fn compare_images(
+    actual: &RgbaImage,
+    reference: &RgbaImage,
+    tolerance: &Tolerance
+) -> Result<Comparison, EngineError> {
+    if actual.dimensions() != reference.dimensions() {
+        return Err(EngineError::UserError(format!(
+            "Image is {:?} but its reference is {:?}",
+            actual.dimensions(),
+            reference.dimensions())));
+    }
+    let mut max_channel_delta = 0;
+    let mut differing_pixels = 0;
+    for (actual_pixel, reference_pixel) in actual.pixels().zip(reference.pixels()) {
+        let delta = channel_delta(actual_pixel, reference_pixel);
+        max_channel_delta = max_channel_delta.max(delta);
+        if delta > tolerance.max_channel_delta {
+            differing_pixels += 1;
+        }
+    }
+    Ok(Comparison {
+        max_channel_delta,
+        differing_pixels,
+        total_pixels: (actual.width() * actual.height()) as usize,
+        ssim: structural_similarity(actual, reference) as f32
+    })
+}
+
+/// Make an image showing where a rendered image differs from its reference: the reference
+/// faded to grey, with differing pixels in red at a brightness following the size of the delta
+pub fn diff_image(actual: &RgbaImage, reference: &RgbaImage, tolerance: &Tolerance) -> RgbaImage {
+    RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let reference_pixel = reference.get_pixel(x, y);
+        let delta = match actual.get_pixel_checked(x, y) {
+            Some(actual_pixel) => channel_delta(actual_pixel, reference_pixel),
+            None => u8::MAX
+        };
+        if delta > tolerance.max_channel_delta {
+            Rgba([128u8.saturating_add(delta / 2), 0, 0, 255])
+        } else {
+            let grey = (luminance(reference_pixel) / 4.0) as u8;
+            Rgba([grey, grey, grey, 255])
+        }
+    })
+}
+
+fn channel_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> u8 {
+    a.0.iter().zip(b.0.iter())
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0)
+}
+
+fn luminance(pixel: &Rgba<u8>) -> f64 {
+    0.299 * pixel.0[0] as f64 + 0.587 * pixel.0[1] as f64 + 0.114 * pixel.0[2] as f64
+}
+
+/// Mean structural similarity of two same-sized images' luminance, over square windows; windows
+/// at the right and bottom edges may be partial
+fn structural_similarity(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let mut total = 0.0;
+    let mut window_count = 0;
+    for window_y in (0..a.height()).step_by(SSIM_WINDOW_SIZE as usize) {
+        for window_x in (0..a.width()).step_by(SSIM_WINDOW_SIZE as usize) {
+            let x_end = (window_x + SSIM_WINDOW_SIZE).min(a.width());
+            let y_end = (window_y + SSIM_WINDOW_SIZE).min(a.height());
+            let samples: Vec<(f64, f64)> = (window_y..y_end)
+                .flat_map(|y| (window_x..x_end).map(move |x| (x, y)))
+                .map(|(x, y)| (luminance(a.get_pixel(x, y)), luminance(b.get_pixel(x, y))))
+                .collect();
+            total += window_similarity(&samples);
+            window_count += 1;
+        }
+    }
+    match window_count {
+        0 => 1.0,
+        count => total / count as f64
+    }
+}
+
+fn window_similarity(samples: &[(f64, f64)]) -> f64 {
+    let count = samples.len() as f64;
+    let mean_a = samples.iter().map(|(a, _)| a).sum::<f64>() / count;
+    let mean_b = samples.iter().map(|(_, b)| b).sum::<f64>() / count;
+    let (mut variance_a, mut variance_b, mut covariance) = (0.0, 0.0, 0.0);
+    for (a, b) in samples.iter() {
+        variance_a += (a - mean_a) * (a - mean_a);
+        variance_b += (b - mean_b) * (b - mean_b);
+        covariance += (a - mean_a) * (b - mean_b);
+    }
+    variance_a /= count;
+    variance_b /= count;
+    covariance /= count;
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2)) /
+        ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (variance_a + variance_b + SSIM_C2))
+}
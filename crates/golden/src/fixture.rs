@@ -0,0 +1,97 @@
+use crate::{Tolerance, Comparison, compare_images, diff_image};
+use error::EngineError;
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+
+/// Environment variable which, when set, makes checks overwrite reference images with what was
+/// rendered instead of comparing against them
+pub const UPDATE_REFERENCES_VARIABLE: &str = "GOLDEN_UPDATE";
+
+/// GoldenFixture struct
+/// Checks rendered images against reference PNG files in a directory, named after each check.
+/// When a check fails, the rendered image and a diff image are written to the failure directory
+/// for inspection. Reference images are created or replaced by running with the
+/// GOLDEN_UPDATE environment variable set.
+pub struct GoldenFixture {
+    reference_dir: PathBuf,
+    failure_dir: PathBuf,
+    update_references: bool
+}
+
+impl GoldenFixture {
+
+    /// Create a new instance using the given directory of reference images, writing failures to
+    /// a "failures" directory inside it
+    pub fn new<P: AsRef<Path>>(reference_dir: P) -> Self {
+        let reference_dir = reference_dir.as_ref().to_path_buf();
+        Self {
+            failure_dir: reference_dir.join("failures"),
+            reference_dir,
+            update_references: std::env::var_os(UPDATE_REFERENCES_VARIABLE).is_some()
+        }
+    }
+
+    /// Write failure images somewhere other than inside the reference directory
+    pub fn with_failure_dir<P: AsRef<Path>>(self, failure_dir: P) -> Self {
+        Self {
+            failure_dir: failure_dir.as_ref().to_path_buf(),
+            ..self
+        }
+    }
+
+    pub fn reference_path(&self, name: &str) -> PathBuf {
+        self.reference_dir.join(format!("{}.png", name))
+    }
+
+    /// Compare a rendered image against the reference of the given name, returning an error
+    /// describing the differences if it is not within tolerance
+    pub fn check(
+        &self,
+        name: &str,
+        actual: &RgbaImage,
+        tolerance: &Tolerance
+    ) -> Result<Comparison, EngineError> {
+        let reference_path = self.reference_path(name);
+        if self.update_references {
+            Self::save(actual, &reference_path)?;
+            return compare_images(actual, actual, tolerance);
+        }
+        if !reference_path.exists() {
+            return Err(EngineError::MissingResource(format!(
+                "No reference image at {}; run with {} set to create it",
+                reference_path.display(),
+                UPDATE_REFERENCES_VARIABLE)));
+        }
+        let reference = image::open(&reference_path)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error reading {}: {:?}", reference_path.display(), e)))?
+            .to_rgba8();
+
+        let comparison = match compare_images(actual, &reference, tolerance) {
+            Ok(comparison) if comparison.passes(tolerance) => return Ok(comparison),
+            Ok(comparison) => comparison.to_string(),
+            Err(EngineError::UserError(message)) => message,
+            Err(e) => return Err(e)
+        };
+        let actual_path = self.failure_dir.join(format!("{}.actual.png", name));
+        let diff_path = self.failure_dir.join(format!("{}.diff.png", name));
+        Self::save(actual, &actual_path)?;
+        Self::save(&diff_image(actual, &reference, tolerance), &diff_path)?;
+        Err(EngineError::OpFailed(format!(
+            "{} does not match its reference: {}; see {}",
+            name,
+            comparison,
+            diff_path.display())))
+    }
+
+    fn save(image: &RgbaImage, path: &Path) -> Result<(), EngineError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| EngineError::OpFailed(
+                    format!("Error creating {}: {:?}", dir.display(), e)))?;
+        }
+        image.save(path)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error writing {}: {:?}", path.display(), e)))
+    }
+}
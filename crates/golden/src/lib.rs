@@ -0,0 +1,11 @@
+mod compare;
+mod fixture;
+mod render;
+
+#[cfg(test)]
+mod tests;
+
+pub use compare::{Tolerance, Comparison, compare_images, diff_image};
+pub use fixture::{GoldenFixture, UPDATE_REFERENCES_VARIABLE};
+pub use render::HeadlessRenderer;
+pub use image::RgbaImage;
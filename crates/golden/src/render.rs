@@ -0,0 +1,141 @@
+use vk_renderer::{
+    VkCore, VkContext, OffscreenFramebufferWrapper, OffscreenFramebufferData, TexturePixelFormat,
+    ImageAccess
+};
+use window::{Window, WindowOptions, WindowEventLooper};
+use ecs::{EcsManager, Handle, resource::{RawResourceBearer, Resource}};
+use error::EngineError;
+use image::RgbaImage;
+use ash::{Device, vk};
+use std::fmt::Debug;
+
+/// HeadlessRenderer struct
+/// Renders into an off-screen framebuffer and reads each frame back, without showing a window.
+/// A hidden window is still created, since the Vulkan context needs a surface. The framebuffer is
+/// added to the ECS under the given resource index before the resource bearer's dynamic resources
+/// are loaded, so renderpasses can target it with RenderpassTarget::OffscreenImageWithDepth.
+/// Dynamic resources are loaded as though there were a single swapchain image.
+pub struct HeadlessRenderer {
+    window: Window,
+    core: VkCore,
+    context: VkContext,
+    ecs: EcsManager<VkContext>,
+    framebuffer_index: u32,
+    width: u32,
+    height: u32
+}
+
+impl HeadlessRenderer {
+
+    /// Create a new instance rendering frames of the given size, loading all resources from the
+    /// resource bearer. The event loop is only needed to create the window, and is never run.
+    pub unsafe fn new<M: 'static + Send + Debug>(
+        looper: &WindowEventLooper<M>,
+        width: u32,
+        height: u32,
+        framebuffer_index: u32,
+        resource_bearer: &dyn RawResourceBearer<VkContext>
+    ) -> Result<Self, EngineError> {
+        let options = WindowOptions {
+            hidden: true,
+            ..WindowOptions::default()
+        };
+        let window = Window::new_with_options("Golden Image Renderer", looper, options);
+        let core = VkCore::new(&window, vec![])?;
+        let mut context = VkContext::new(&core, &window)?;
+        let mut ecs = EcsManager::new();
+        resource_bearer.initialise_static_resources(&mut ecs, &context)?;
+
+        let creation_data = OffscreenFramebufferData {
+            width,
+            height,
+            color_formats: vec![TexturePixelFormat::Rgba],
+            depth_format: TexturePixelFormat::Unorm16
+        };
+        let framebuffer = OffscreenFramebufferWrapper::create(&context, &ecs, &creation_data)?;
+        ecs.push_new_with_handle(Handle::for_resource(framebuffer_index), framebuffer);
+        resource_bearer.reload_dynamic_resources(&mut ecs, &mut context, 1)?;
+
+        Ok(Self { window, core, context, ecs, framebuffer_index, width, height })
+    }
+
+    pub fn get_window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn get_context(&self) -> &VkContext {
+        &self.context
+    }
+
+    pub fn get_ecs(&self) -> &EcsManager<VkContext> {
+        &self.ecs
+    }
+
+    /// Record a frame with the given function, which should render into the off-screen
+    /// framebuffer through a renderpass leaving it as a color attachment, then submit it, wait
+    /// for it to finish, and read back the result
+    pub unsafe fn render<F>(&self, record: F) -> Result<RgbaImage, EngineError>
+        where F: FnOnce(&Device, vk::CommandBuffer, vk::Extent2D, &EcsManager<VkContext>) ->
+            Result<(), EngineError>
+    {
+        let device = &self.context.device;
+        let command_buffer = self.context.graphics_queue.allocate_command_buffer(device)?;
+        let result = self.record_and_submit(command_buffer, record);
+        self.context.graphics_queue.free_command_buffer(device, command_buffer);
+        result?;
+
+        let framebuffer = self.ecs
+            .get_item::<OffscreenFramebufferWrapper>(Handle::for_resource(self.framebuffer_index))
+            .ok_or(EngineError::MissingResource(String::from("Off-screen framebuffer")))?;
+        framebuffer.color_texture().assume_access(ImageAccess::ColorAttachment);
+        let pixels = framebuffer.color_texture().read_to_host(
+            &self.context,
+            self.width,
+            self.height)?;
+        RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or(EngineError::OpFailed(String::from("Read back an incomplete frame")))
+    }
+
+    unsafe fn record_and_submit<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        record: F
+    ) -> Result<(), EngineError>
+        where F: FnOnce(&Device, vk::CommandBuffer, vk::Extent2D, &EcsManager<VkContext>) ->
+            Result<(), EngineError>
+    {
+        let device = &self.context.device;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error beginning command buffer: {:?}", e)))?;
+        let extent = vk::Extent2D { width: self.width, height: self.height };
+        record(device, command_buffer, extent, &self.ecs)?;
+        device.end_command_buffer(command_buffer)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error ending command buffer: {:?}", e)))?;
+
+        let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)
+            .map_err(|e| EngineError::OpFailed(format!("Error creating fence: {:?}", e)))?;
+        let result = self.context.graphics_queue
+            .submit_transfer_command_buffer(device, &command_buffer, &fence)
+            .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX)
+                .map_err(|e| EngineError::OpFailed(
+                    format!("Error waiting for frame: {:?}", e))));
+        device.destroy_fence(fence, None);
+        result
+    }
+
+    /// Release all resources; the instance must not be used afterwards
+    pub unsafe fn teardown(&mut self) {
+        if let Err(e) = self.context.wait_until_device_idle() {
+            println!("Error waiting before teardown: {:?}", e);
+        }
+        if let Err(e) = self.ecs.free_all_resources(&self.context) {
+            println!("Error freeing resources: {:?}", e);
+        }
+        self.context.teardown();
+        self.core.teardown();
+    }
+}
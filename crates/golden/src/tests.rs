@@ -0,0 +1,83 @@
+
+use crate::{Tolerance, GoldenFixture, compare_images, diff_image};
+use image::{Rgba, RgbaImage};
+use error::EngineError;
+
+fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])
+    })
+}
+
+#[test]
+fn identical_images_pass() {
+    let image = gradient(32, 24);
+    let comparison = compare_images(&image, &image, &Tolerance::default()).unwrap();
+    assert_eq!(comparison.differing_pixels, 0);
+    assert_eq!(comparison.max_channel_delta, 0);
+    assert!((comparison.ssim - 1.0).abs() < 0.0001);
+    assert!(comparison.passes(&Tolerance::default()));
+}
+
+#[test]
+fn small_deltas_are_tolerated() {
+    let reference = gradient(32, 24);
+    let mut actual = reference.clone();
+    for pixel in actual.pixels_mut() {
+        pixel.0[2] += 1;
+    }
+    let comparison = compare_images(&actual, &reference, &Tolerance::default()).unwrap();
+    assert_eq!(comparison.max_channel_delta, 1);
+    assert_eq!(comparison.differing_pixels, 0);
+    assert!(comparison.passes(&Tolerance::default()));
+}
+
+#[test]
+fn changed_region_fails() {
+    let reference = gradient(32, 24);
+    let mut actual = reference.clone();
+    for y in 0..8 {
+        for x in 0..8 {
+            actual.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+        }
+    }
+    let tolerance = Tolerance::default();
+    let comparison = compare_images(&actual, &reference, &tolerance).unwrap();
+    assert_eq!(comparison.differing_pixels, 64);
+    assert!(!comparison.passes(&tolerance));
+
+    let diff = diff_image(&actual, &reference, &tolerance);
+    assert!(diff.get_pixel(0, 0).0[0] >= 128);
+    assert_eq!(diff.get_pixel(0, 0).0[1], 0);
+    let unchanged = diff.get_pixel(20, 20).0;
+    assert_eq!(unchanged[0], unchanged[1]);
+}
+
+#[test]
+fn size_mismatch_is_an_error() {
+    let result = compare_images(&gradient(32, 24), &gradient(24, 32), &Tolerance::default());
+    assert!(matches!(result, Err(EngineError::UserError(_))));
+}
+
+#[test]
+fn fixture_writes_diff_on_failure() {
+    let dir = std::env::temp_dir().join(format!("golden_test_{}", std::process::id()));
+    let fixture = GoldenFixture::new(&dir);
+    let reference = gradient(16, 16);
+    std::fs::create_dir_all(&dir).unwrap();
+    reference.save(fixture.reference_path("gradient")).unwrap();
+
+    assert!(fixture.check("gradient", &reference, &Tolerance::default()).is_ok());
+    let inverted = RgbaImage::from_fn(16, 16, |x, y| {
+        let pixel = reference.get_pixel(x, y).0;
+        Rgba([255 - pixel[0], 255 - pixel[1], pixel[2], 255])
+    });
+    assert!(fixture.check("gradient", &inverted, &Tolerance::default()).is_err());
+    assert!(dir.join("failures").join("gradient.diff.png").exists());
+    assert!(dir.join("failures").join("gradient.actual.png").exists());
+    assert!(matches!(
+        fixture.check("missing", &reference, &Tolerance::default()),
+        Err(EngineError::MissingResource(_))));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
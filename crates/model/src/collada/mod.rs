@@ -22,7 +22,13 @@ impl COLLADA {
 
     /// Create new instance from file data
     pub fn new(file_data: &[u8]) -> COLLADA {
-        from_reader(file_data).unwrap()
+        Self::try_new(file_data).unwrap()
+    }
+
+    /// Create new instance from file data, returning an error rather than panicking if it is not
+    /// valid Collada XML
+    pub fn try_new(file_data: &[u8]) -> Result<COLLADA, String> {
+        from_reader(file_data).map_err(|e| format!("Error parsing Collada data: {}", e))
     }
 
     /// Translate the data held by this instance into instances of model::types::Model.
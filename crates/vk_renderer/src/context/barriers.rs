@@ -0,0 +1,59 @@
+use ash::{Device, vk};
+
+/// Record image memory barriers, all between the same stages, with vkCmdPipelineBarrier2 where
+/// synchronization2 is enabled, or vkCmdPipelineBarrier otherwise. The barriers are described in
+/// the older form either way, so that callers need only one code path.
+pub(crate) unsafe fn record_image_barriers(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    synchronization2: bool,
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+    barriers: &[vk::ImageMemoryBarrier]
+) {
+    if !synchronization2 {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            barriers);
+        return;
+    }
+    let src_stage_mask = stage_flags2(src_stage_mask, vk::PipelineStageFlags::TOP_OF_PIPE);
+    let dst_stage_mask = stage_flags2(dst_stage_mask, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+    let barriers: Vec<vk::ImageMemoryBarrier2> = barriers.iter()
+        .map(|barrier| vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src_stage_mask)
+            .src_access_mask(access_flags2(barrier.src_access_mask))
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(access_flags2(barrier.dst_access_mask))
+            .old_layout(barrier.old_layout)
+            .new_layout(barrier.new_layout)
+            .src_queue_family_index(barrier.src_queue_family_index)
+            .dst_queue_family_index(barrier.dst_queue_family_index)
+            .image(barrier.image)
+            .subresource_range(barrier.subresource_range)
+            .build())
+        .collect();
+    let dependency_info = vk::DependencyInfo::builder()
+        .image_memory_barriers(&barriers);
+    device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+}
+
+/// The synchronization2 form of stage flags, whose bits have the same values. The given stage,
+/// being top of pipe for sources or bottom of pipe for destinations, only orders execution, so it
+/// becomes no stage at all, as synchronization2 expresses it.
+pub(crate) fn stage_flags2(
+    stages: vk::PipelineStageFlags,
+    execution_only_stage: vk::PipelineStageFlags
+) -> vk::PipelineStageFlags2 {
+    vk::PipelineStageFlags2::from_raw((stages & !execution_only_stage).as_raw() as u64)
+}
+
+/// The synchronization2 form of access flags, whose bits have the same values
+pub(crate) fn access_flags2(access: vk::AccessFlags) -> vk::AccessFlags2 {
+    vk::AccessFlags2::from_raw(access.as_raw() as u64)
+}
@@ -0,0 +1,205 @@
+use crate::{VkContext, BufferWrapper, CommandQueue, OneTimeCommands};
+use ecs::resource::Resource;
+use error::EngineError;
+use ash::vk;
+
+/// FrameCapture struct
+/// A copy of a swapchain image into a readback buffer, recorded into its own command buffer for
+/// the graphics queue. For a frame being presented, it is submitted along with the frame's
+/// rendering commands, so that it runs after them while the image is still acquired and before
+/// it is presented. The pixels can be read once the submission's timeline value is reached.
+pub(crate) struct FrameCapture {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    buffer: BufferWrapper,
+    extent: vk::Extent2D,
+    size_bytes: usize,
+    swap_red_and_blue: bool,
+    timeline_value: u64
+}
+
+impl FrameCapture {
+
+    /// Record the copy of a swapchain image in the given output layout, which it is returned to
+    /// afterwards. Fails if the images can't be copied from, or use a format other than 8-bit RGBA
+    /// or BGRA.
+    pub unsafe fn record(
+        context: &VkContext,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        output_layout: vk::ImageLayout
+    ) -> Result<Self, EngineError> {
+        let swap_red_and_blue = match format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            format => {
+                return Err(EngineError::OpFailed(
+                    format!("Capturing swapchain images of format {:?} not supported", format)));
+            }
+        };
+        let size_bytes = extent.width as usize * extent.height as usize * 4;
+        let buffer = BufferWrapper::new_readback(context, size_bytes)?;
+        let commands = match context.begin_one_time_commands(CommandQueue::Graphics) {
+            Ok(commands) => commands,
+            Err(e) => {
+                buffer.release(context);
+                return Err(e);
+            }
+        };
+        Self::record_copy(context, &commands, image, extent, output_layout, buffer.buffer());
+        if let Err(e) = context.device.end_command_buffer(commands.command_buffer) {
+            commands.discard(&context.device);
+            buffer.release(context);
+            return Err(EngineError::OpFailed(
+                format!("Error ending capture command buffer: {:?}", e)));
+        }
+        Ok(Self {
+            command_pool: commands.command_pool,
+            command_buffer: commands.command_buffer,
+            buffer,
+            extent,
+            size_bytes,
+            swap_red_and_blue,
+            timeline_value: 0
+        })
+    }
+
+    unsafe fn record_copy(
+        context: &VkContext,
+        commands: &OneTimeCommands,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        output_layout: vk::ImageLayout,
+        buffer: vk::Buffer
+    ) {
+        let device = &context.device;
+        let command_buffer = commands.command_buffer;
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        // Wait for rendering into the image, whether by render pass or by transfer
+        let to_transfer_barrier = vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(output_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(color_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer_barrier]);
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            }
+        };
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region]);
+
+        // Return the image to its output layout and make the copy visible to the host
+        let to_output_barrier = vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(output_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(color_range)
+            .build();
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_output_barrier]);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier],
+            &[]);
+    }
+
+    /// The size of the image being copied
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// The command buffer to submit after the frame's rendering commands
+    pub fn get_command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Note the graphics timeline value signalled by the submission containing the copy
+    pub fn set_timeline_value(&mut self, timeline_value: u64) {
+        self.timeline_value = timeline_value;
+    }
+
+    /// Wait for the copy to complete, then read the pixels as tightly-packed RGBA and release
+    /// everything the capture holds
+    pub unsafe fn read_pixels(self, context: &VkContext) -> Result<Vec<u8>, EngineError> {
+        let (allocator, _) = context.get_mem_allocator();
+        let pixels = context.get_graphics_timeline()
+            .wait(&context.device, self.timeline_value)
+            .and_then(|_| self.buffer.read::<u8>(allocator, 0, self.size_bytes));
+        let swap_red_and_blue = self.swap_red_and_blue;
+        self.release(context);
+        let mut pixels = pixels?;
+        if swap_red_and_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Release the buffer and command buffer, once the device has finished with them; if waiting
+    /// fails, the device is lost and they are released anyway
+    pub unsafe fn release(self, context: &VkContext) {
+        let _ = context.get_graphics_timeline().wait(&context.device, self.timeline_value);
+        self.buffer.release(context);
+        context.device.free_command_buffers(self.command_pool, &[self.command_buffer]);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::Queue;
+use error::EngineError;
+use ash::{Device, vk};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::thread::ThreadId;
+use std::time::Duration;
+
+/// CommandQueue enum
+/// The queues that one-time command buffers may be obtained for and submitted to. Compute is the
+/// async compute queue where the device has one, or the graphics queue where it doesn't; see
+/// VkContext::has_async_compute.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CommandQueue {
+    Graphics,
+    Transfer,
+    Compute
+}
+
+/// OneTimeCommands struct
+/// A primary command buffer that has been begun for one submission, ready for any commands to be
+/// recorded into it. It comes from a command pool belonging to the thread that obtained it, so
+/// it must be recorded, submitted and released on that thread.
+pub struct OneTimeCommands {
+    pub(crate) queue: CommandQueue,
+    pub(crate) command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+    pub(crate) _not_send: PhantomData<*const ()>
+}
+
+impl OneTimeCommands {
+
+    /// The queue that these commands will be submitted to
+    pub fn get_queue(&self) -> CommandQueue {
+        self.queue
+    }
+
+    /// Free the command buffer without submitting it, such as after an error while recording
+    pub unsafe fn discard(self, device: &Device) {
+        device.free_command_buffers(self.command_pool, &[self.command_buffer]);
+    }
+}
+
+/// SubmittedCommands struct
+/// One-time commands that have been submitted, with the fence signalled when they finish. The
+/// command buffer is freed on release, which waits for the fence first.
+pub struct SubmittedCommands {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    _not_send: PhantomData<*const ()>
+}
+
+impl SubmittedCommands {
+
+    pub(crate) fn new(commands: OneTimeCommands, fence: vk::Fence) -> Self {
+        Self {
+            command_pool: commands.command_pool,
+            command_buffer: commands.command_buffer,
+            fence,
+            _not_send: PhantomData
+        }
+    }
+
+    /// The fence signalled when the commands finish, for waiting on alongside other fences
+    pub fn get_fence(&self) -> vk::Fence {
+        self.fence
+    }
+
+    /// Query whether the commands have finished, without waiting
+    pub unsafe fn is_complete(&self, device: &Device) -> Result<bool, EngineError> {
+        device.get_fence_status(self.fence)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error querying one-time commands: {:?}", e)))
+    }
+
+    /// Wait up to the timeout for the commands to finish, returning whether they did
+    pub unsafe fn wait(&self, device: &Device, timeout: Duration) -> Result<bool, EngineError> {
+        let timeout_nanos = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        match device.wait_for_fences(&[self.fence], true, timeout_nanos) {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(e) => Err(EngineError::OpFailed(
+                format!("Error waiting for one-time commands: {:?}", e)))
+        }
+    }
+
+    /// Wait for the commands to finish, then free the command buffer and fence
+    pub unsafe fn release(self, device: &Device) -> Result<(), EngineError> {
+        let result = device.wait_for_fences(&[self.fence], true, u64::MAX)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error waiting for one-time commands: {:?}", e)));
+        device.destroy_fence(self.fence, None);
+        device.free_command_buffers(self.command_pool, &[self.command_buffer]);
+        result
+    }
+}
+
+/// UserCommandPools struct
+/// Command pools for one-time command buffers, one per thread and queue, so that threads can
+/// record at the same time without sharing a pool. The pools used for the engine's own frame
+/// command buffers are never handed out, since they are reset when the swapchain is recreated.
+pub(crate) struct UserCommandPools {
+    graphics_queue_family_index: u32,
+    transfer_queue_family_index: u32,
+    compute_queue_family_index: u32,
+    pools: Mutex<HashMap<(ThreadId, CommandQueue), vk::CommandPool>>
+}
+
+impl UserCommandPools {
+
+    pub(crate) fn new(
+        graphics_queue_family_index: u32,
+        transfer_queue_family_index: u32,
+        compute_queue_family_index: u32
+    ) -> Self {
+        Self {
+            graphics_queue_family_index,
+            transfer_queue_family_index,
+            compute_queue_family_index,
+            pools: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Get the calling thread's pool for the given queue, creating it if this is the thread's
+    /// first use of that queue
+    pub(crate) unsafe fn pool_for_current_thread(
+        &self,
+        device: &Device,
+        queue: CommandQueue
+    ) -> Result<vk::CommandPool, EngineError> {
+        let key = (std::thread::current().id(), queue);
+        let mut pools = self.pools.lock().unwrap();
+        if let Some(pool) = pools.get(&key) {
+            return Ok(*pool);
+        }
+        let queue_family_index = match queue {
+            CommandQueue::Graphics => self.graphics_queue_family_index,
+            CommandQueue::Transfer => self.transfer_queue_family_index,
+            CommandQueue::Compute => self.compute_queue_family_index
+        };
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+        let pool = device.create_command_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating one-time command pool: {:?}", e)))?;
+        pools.insert(key, pool);
+        Ok(pool)
+    }
+
+    /// Destroy the calling thread's pools; every command buffer from them must have been
+    /// released
+    pub(crate) unsafe fn release_current_thread(&self, device: &Device) {
+        let thread_id = std::thread::current().id();
+        self.pools.lock().unwrap().retain(|(pool_thread_id, _), pool| {
+            if *pool_thread_id != thread_id {
+                return true;
+            }
+            device.destroy_command_pool(*pool, None);
+            false
+        });
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        for (_, pool) in self.pools.lock().unwrap().drain() {
+            device.destroy_command_pool(pool, None);
+        }
+    }
+}
+
+/// SecondaryInheritance enum
+/// What a secondary command buffer continues rendering into, which must match the renderpass
+/// subpass or dynamic rendering that executes it. For dynamic rendering, the formats are those of
+/// the attachments, with undefined where there is no such attachment; see
+/// RenderpassWrapper::secondary_inheritance and DynamicRenderTarget::secondary_inheritance.
+#[derive(Clone, Debug)]
+pub enum SecondaryInheritance {
+    Renderpass {
+        renderpass: vk::RenderPass,
+        subpass: u32,
+        framebuffer: vk::Framebuffer
+    },
+    DynamicRendering {
+        color_formats: Vec<vk::Format>,
+        depth_format: vk::Format,
+        stencil_format: vk::Format,
+        samples: vk::SampleCountFlags
+    }
+}
+
+impl SecondaryInheritance {
+
+    /// Begin recording a secondary command buffer that continues this rendering. It is not begun
+    /// for one-time submission, so may be executed every frame for prerecorded commands.
+    pub unsafe fn begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer
+    ) -> Result<(), EngineError> {
+        let result = match self {
+            SecondaryInheritance::Renderpass { renderpass, subpass, framebuffer } => {
+                let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+                    .render_pass(*renderpass)
+                    .subpass(*subpass)
+                    .framebuffer(*framebuffer);
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+                    .inheritance_info(&inheritance_info);
+                device.begin_command_buffer(command_buffer, &begin_info)
+            },
+            SecondaryInheritance::DynamicRendering {
+                color_formats,
+                depth_format,
+                stencil_format,
+                samples
+            } => {
+                let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+                    .color_attachment_formats(color_formats)
+                    .depth_attachment_format(*depth_format)
+                    .stencil_attachment_format(*stencil_format)
+                    .rasterization_samples(*samples);
+                let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+                    .push_next(&mut rendering_info);
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+                    .inheritance_info(&inheritance_info);
+                device.begin_command_buffer(command_buffer, &begin_info)
+            }
+        };
+        result.map_err(|e| EngineError::OpFailed(
+            format!("Error beginning secondary command buffer: {:?}", e)))
+    }
+}
+
+/// SecondaryCommandPools struct
+/// Secondary command buffers for each swapchain image, each in a pool of its own so that all of
+/// an image's buffers can be recorded at once on different threads. They live as long as the
+/// graphics command buffers do, and are destroyed whenever those are regenerated.
+pub(crate) struct SecondaryCommandPools {
+    pools: Mutex<Vec<Vec<(vk::CommandPool, vk::CommandBuffer)>>>
+}
+
+impl SecondaryCommandPools {
+
+    pub(crate) fn new() -> Self {
+        Self {
+            pools: Mutex::new(vec![])
+        }
+    }
+
+    /// Get the given number of secondary command buffers for a swapchain image, creating any
+    /// that the image does not have yet
+    pub(crate) unsafe fn buffers_for_image(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        swapchain_image_index: usize,
+        count: usize
+    ) -> Result<Vec<vk::CommandBuffer>, EngineError> {
+        let mut pools = self.pools.lock().unwrap();
+        if pools.len() <= swapchain_image_index {
+            pools.resize_with(swapchain_image_index + 1, Vec::new);
+        }
+        let image_pools = &mut pools[swapchain_image_index];
+        while image_pools.len() < count {
+            image_pools.push(queue.create_secondary_command_buffer(device)?);
+        }
+        Ok(image_pools.iter()
+            .take(count)
+            .map(|(_, command_buffer)| *command_buffer)
+            .collect())
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        for image_pools in self.pools.lock().unwrap().drain(..) {
+            for (pool, _) in image_pools.into_iter() {
+                device.destroy_command_pool(pool, None);
+            }
+        }
+    }
+}
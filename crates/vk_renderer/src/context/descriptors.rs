@@ -0,0 +1,158 @@
+use error::EngineError;
+use ash::{Device, vk};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Number of sets that the first pool holds; each pool after it holds twice as many as the last
+const INITIAL_POOL_SET_COUNT: u32 = 32;
+
+/// Upper limit of sets in any one pool, so that growth stays bounded over a long session
+const MAX_POOL_SET_COUNT: u32 = 1024;
+
+/// Descriptors of each type that a pool holds per set
+const DESCRIPTORS_PER_SET: u32 = 2;
+
+/// Descriptor types that sets from the shared pools may contain
+const POOLED_DESCRIPTOR_TYPES: [vk::DescriptorType; 6] = [
+    vk::DescriptorType::UNIFORM_BUFFER,
+    vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_IMAGE,
+    vk::DescriptorType::INPUT_ATTACHMENT
+];
+
+/// DescriptorAllocator struct
+/// Descriptor pools shared by every resource that needs descriptor sets, so that reloading
+/// resources doesn't create and destroy a pool each. Sets are allocated from the newest pool,
+/// with a larger one added when it runs out. Recycled sets are kept per layout and handed out
+/// again for that layout before any new set is allocated.
+pub(crate) struct DescriptorAllocator {
+    state: Mutex<AllocatorState>
+}
+
+struct AllocatorState {
+    pools: Vec<vk::DescriptorPool>,
+    next_pool_set_count: u32,
+    recycled: HashMap<vk::DescriptorSetLayout, Vec<(vk::DescriptorPool, vk::DescriptorSet)>>,
+    owning_pools: HashMap<vk::DescriptorSet, vk::DescriptorPool>
+}
+
+impl DescriptorAllocator {
+
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(AllocatorState {
+                pools: vec![],
+                next_pool_set_count: INITIAL_POOL_SET_COUNT,
+                recycled: HashMap::new(),
+                owning_pools: HashMap::new()
+            })
+        }
+    }
+
+    /// Get a set of the given layout, either one recycled with that layout or a new one
+    pub(crate) unsafe fn allocate(
+        &self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout
+    ) -> Result<vk::DescriptorSet, EngineError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some((pool, set)) = state.recycled.get_mut(&layout).and_then(|sets| sets.pop()) {
+            state.owning_pools.insert(set, pool);
+            return Ok(set);
+        }
+        if let Some(&pool) = state.pools.last() {
+            match allocate_from_pool(device, pool, layout) {
+                Ok(set) => {
+                    state.owning_pools.insert(set, pool);
+                    return Ok(set);
+                },
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) |
+                Err(vk::Result::ERROR_FRAGMENTED_POOL) => {},
+                Err(e) => return Err(EngineError::OpFailed(
+                    format!("Error allocating descriptor set: {:?}", e)))
+            }
+        }
+        let pool = state.add_pool(device)?;
+        let set = allocate_from_pool(device, pool, layout)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating descriptor set from new pool: {:?}", e)))?;
+        state.owning_pools.insert(set, pool);
+        Ok(set)
+    }
+
+    /// Return a set for reuse by the next allocation with the same layout. The set must not be
+    /// in use by any pending command buffer.
+    pub(crate) fn recycle(&self, layout: vk::DescriptorSetLayout, set: vk::DescriptorSet) {
+        let mut state = self.state.lock().unwrap();
+        let Some(pool) = state.owning_pools.remove(&set) else {
+            return;
+        };
+        state.recycled.entry(layout).or_default().push((pool, set));
+    }
+
+    /// Free the recycled sets of a layout that is about to be destroyed, so that they can't be
+    /// handed out for a different layout later created with the same handle
+    pub(crate) unsafe fn forget_layout(&self, device: &Device, layout: vk::DescriptorSetLayout) {
+        let mut state = self.state.lock().unwrap();
+        for (pool, set) in state.recycled.remove(&layout).unwrap_or_default() {
+            let _ = device.free_descriptor_sets(pool, &[set]);
+        }
+    }
+
+    /// Number of pools created so far
+    pub(crate) fn pool_count(&self) -> usize {
+        self.state.lock().unwrap().pools.len()
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        let mut state = self.state.lock().unwrap();
+        for pool in state.pools.drain(..) {
+            device.destroy_descriptor_pool(pool, None);
+        }
+        state.recycled.clear();
+        state.owning_pools.clear();
+        state.next_pool_set_count = INITIAL_POOL_SET_COUNT;
+    }
+}
+
+impl AllocatorState {
+
+    unsafe fn add_pool(&mut self, device: &Device) -> Result<vk::DescriptorPool, EngineError> {
+        let set_count = self.next_pool_set_count;
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = POOLED_DESCRIPTOR_TYPES.iter()
+            .map(|descriptor_type| vk::DescriptorPoolSize {
+                ty: *descriptor_type,
+                descriptor_count: set_count * DESCRIPTORS_PER_SET
+            })
+            .collect();
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(set_count)
+            .pool_sizes(&pool_sizes);
+        let pool = device.create_descriptor_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor pool: {:?}", e)))?;
+        self.pools.push(pool);
+        self.next_pool_set_count = next_pool_set_count(set_count);
+        Ok(pool)
+    }
+}
+
+unsafe fn allocate_from_pool(
+    device: &Device,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout
+) -> Result<vk::DescriptorSet, vk::Result> {
+    let layouts = [layout];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    device.allocate_descriptor_sets(&allocate_info).map(|sets| sets[0])
+}
+
+/// Sets held by the pool created after one holding the given number
+pub(crate) fn next_pool_set_count(set_count: u32) -> u32 {
+    (set_count * 2).min(MAX_POOL_SET_COUNT)
+}
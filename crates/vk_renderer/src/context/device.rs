@@ -1,11 +1,12 @@
 
 use crate::VkCore;
 use error::EngineError;
-use ash::{vk, Device, extensions::khr::{Swapchain}};
+use ash::{vk, Device, extensions::khr::{Swapchain, PresentWait, TimelineSemaphore}};
 use std::os::raw::c_char;
 
 /// All device-related initialisation - chooses a physical device, creates the logical device, and
-/// creates a single graphics queue and single transfer queue
+/// creates a single graphics queue and single transfer queue, plus a compute queue where the core
+/// found a dedicated family for one
 pub unsafe fn make_device_resources(
     core: &VkCore
 ) -> Result<Device, EngineError> {
@@ -34,12 +35,29 @@ pub unsafe fn make_device_resources(
         )
     };
 
-    // Device extensions required
-    let device_extensions: Vec<*const c_char> = vec![ Swapchain::name().as_ptr() ];
+    // Device extensions required; headless devices have no swapchain
+    let mut device_extensions: Vec<*const c_char> = vec![];
+    if !core.is_headless() {
+        device_extensions.push(Swapchain::name().as_ptr());
+    }
+    if core.supports_present_wait() {
+        device_extensions.push(vk::KhrPresentIdFn::name().as_ptr());
+        device_extensions.push(PresentWait::name().as_ptr());
+    }
+    if core.supports_descriptor_indexing() {
+        device_extensions.push(vk::ExtDescriptorIndexingFn::name().as_ptr());
+    }
+    if core.supports_timeline_semaphore() {
+        device_extensions.push(TimelineSemaphore::name().as_ptr());
+    }
+    let portability_subset = core.get_portability_subset();
+    if portability_subset.is_some() {
+        device_extensions.push(vk::KhrPortabilitySubsetFn::name().as_ptr());
+    }
 
     // Make the logical device
     let priorities = [1.0f32];
-    let queue_infos = [
+    let mut queue_infos = vec![
         vk::DeviceQueueCreateInfo::builder()
             .queue_family_index(graphics_queue_family_index)
             .queue_priorities(&priorities)
@@ -49,10 +67,57 @@ pub unsafe fn make_device_resources(
             .queue_priorities(&priorities)
             .build()
     ];
-    let device_create_info = vk::DeviceCreateInfo::builder()
+    if let Some(compute_queue_family_index) = core.compute_queue_family_index {
+        queue_infos.push(vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(compute_queue_family_index)
+            .queue_priorities(&priorities)
+            .build());
+    }
+    let mut vulkan_1_3_features = vk::PhysicalDeviceVulkan13Features::builder()
+        .dynamic_rendering(core.supports_dynamic_rendering())
+        .synchronization2(core.supports_synchronization2())
+        .maintenance4(core.supports_maintenance4());
+    let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR::builder()
+        .present_id(true);
+    let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::builder()
+        .present_wait(true);
+    let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .runtime_descriptor_array(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_sampled_image_update_after_bind(true);
+    let mut float16_int8_features = vk::PhysicalDeviceShaderFloat16Int8Features::builder()
+        .shader_float16(true);
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+        .timeline_semaphore(true);
+    let mut portability_features = portability_subset.unwrap_or_default().to_features();
+    let mut device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_extension_names(&device_extensions)
         .enabled_features(&core.physical_device_features);
+    let uses_vulkan_1_3_features = core.supports_dynamic_rendering() ||
+        core.supports_synchronization2() ||
+        core.supports_maintenance4();
+    if uses_vulkan_1_3_features {
+        device_create_info = device_create_info.push_next(&mut vulkan_1_3_features);
+    }
+    if core.supports_present_wait() {
+        device_create_info = device_create_info
+            .push_next(&mut present_id_features)
+            .push_next(&mut present_wait_features);
+    }
+    if core.supports_descriptor_indexing() {
+        device_create_info = device_create_info.push_next(&mut descriptor_indexing_features);
+    }
+    if core.supports_shader_float16() {
+        device_create_info = device_create_info.push_next(&mut float16_int8_features);
+    }
+    if core.supports_timeline_semaphore() {
+        device_create_info = device_create_info.push_next(&mut timeline_features);
+    }
+    if portability_subset.is_some() {
+        device_create_info = device_create_info.push_next(&mut portability_features);
+    }
     let device = core.instance
         .create_device(
             core.physical_device,
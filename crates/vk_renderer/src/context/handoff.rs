@@ -0,0 +1,163 @@
+use crate::{ComputeAccess, QueueFamilyPair};
+use ash::{Device, vk};
+
+/// HandoffImage struct
+/// An image changing hands, with the transition it makes as it does; both barriers of the
+/// transfer must name the same layouts
+#[derive(Copy, Clone)]
+struct HandoffImage {
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout
+}
+
+/// ComputeHandoff struct
+/// The resources used by async compute work, passed between the graphics and compute queue
+/// families. Resources with exclusive sharing belong to one family at a time, so each needs a
+/// release barrier recorded on the queue giving it up and a matching acquire barrier recorded on
+/// the queue taking it. Images handed to compute are moved into the layout that the compute work
+/// uses, and keep it on the way back. Where both queues are of the same family, there is nothing
+/// to hand over.
+pub(crate) struct ComputeHandoff {
+    families: QueueFamilyPair,
+    buffers: Vec<vk::Buffer>,
+    images: Vec<HandoffImage>
+}
+
+impl ComputeHandoff {
+
+    pub fn new(families: QueueFamilyPair) -> Self {
+        Self { families, buffers: vec![], images: vec![] }
+    }
+
+    /// Describe handing the resources of the given accesses from graphics to compute, with
+    /// images leaving the layouts they are currently tracked in
+    pub fn for_accesses(families: QueueFamilyPair, accesses: &[ComputeAccess]) -> Self {
+        let mut handoff = Self::new(families);
+        if !families.requires_ownership_transfer() {
+            return handoff;
+        }
+        for access in accesses.iter() {
+            match access {
+                ComputeAccess::Buffer(buffer) => handoff.add_buffer(buffer.buffer()),
+                ComputeAccess::Sampled(image) => handoff.add_image(
+                    image.image,
+                    image.get_subresource_range(),
+                    image.get_layout(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ComputeAccess::Storage(image) => handoff.add_image(
+                    image.image,
+                    image.get_subresource_range(),
+                    image.get_layout(),
+                    vk::ImageLayout::GENERAL)
+            }
+        }
+        handoff
+    }
+
+    pub fn add_buffer(&mut self, buffer: vk::Buffer) {
+        self.buffers.push(buffer);
+    }
+
+    pub fn add_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout
+    ) {
+        self.images.push(HandoffImage { image, subresource_range, old_layout, new_layout });
+    }
+
+    /// Query whether there is anything to hand over
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.images.is_empty()
+    }
+
+    /// The same resources handed back the other way, with images staying in their new layouts
+    pub fn returned(&self) -> Self {
+        Self {
+            families: QueueFamilyPair::new(self.families.dst_family, self.families.src_family),
+            buffers: self.buffers.clone(),
+            images: self.images.iter()
+                .map(|image| HandoffImage { old_layout: image.new_layout, ..*image })
+                .collect()
+        }
+    }
+
+    /// Barriers to record on the queue giving the resources up, after its last use of them
+    pub fn release_barriers(&self) -> (Vec<vk::BufferMemoryBarrier>, Vec<vk::ImageMemoryBarrier>) {
+        let buffer_barriers = self.buffers.iter()
+            .map(|buffer| {
+                self.families.release_buffer_barrier(*buffer, vk::AccessFlags::MEMORY_WRITE)
+            })
+            .collect();
+        let image_barriers = self.images.iter()
+            .map(|image| self.families.release_image_barrier(
+                image.image,
+                image.subresource_range,
+                image.old_layout,
+                image.new_layout,
+                vk::AccessFlags::MEMORY_WRITE))
+            .collect();
+        (buffer_barriers, image_barriers)
+    }
+
+    /// Barriers to record on the queue taking the resources, before its first use of them
+    pub fn acquire_barriers(&self) -> (Vec<vk::BufferMemoryBarrier>, Vec<vk::ImageMemoryBarrier>) {
+        let dst_access = vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE;
+        let buffer_barriers = self.buffers.iter()
+            .map(|buffer| self.families.acquire_buffer_barrier(*buffer, dst_access))
+            .collect();
+        let image_barriers = self.images.iter()
+            .map(|image| self.families.acquire_image_barrier(
+                image.image,
+                image.subresource_range,
+                image.old_layout,
+                image.new_layout,
+                dst_access))
+            .collect();
+        (buffer_barriers, image_barriers)
+    }
+
+    /// Record the release barriers, waiting for all earlier work on the queue
+    pub unsafe fn record_release(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let (buffer_barriers, image_barriers) = self.release_barriers();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barriers,
+            &image_barriers);
+    }
+
+    /// Record the acquire barriers, ahead of the given stages. A semaphore waited on for the
+    /// release must be waited on at those same stages.
+    pub unsafe fn record_acquire(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags
+    ) {
+        let (buffer_barriers, image_barriers) = self.acquire_barriers();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            stage,
+            stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barriers,
+            &image_barriers);
+    }
+}
+
+/// PendingComputeWait struct
+/// Async compute work submitted for the frame being prepared: the stage at which the frame waits
+/// for it, and the resources that the graphics queue must take back before that stage
+pub(crate) struct PendingComputeWait {
+    pub stage: vk::PipelineStageFlags,
+    pub handoff: Option<ComputeHandoff>
+}
@@ -1,49 +1,197 @@
+pub(crate) mod barriers;
+mod capture;
+mod commands;
+pub(crate) mod descriptors;
 mod device;
+pub(crate) mod handoff;
 mod present;
 mod queues;
+mod retain;
+mod rotation;
 mod swapchain;
+mod timeline;
 
-use crate::{VkCore, ImageWrapper, mem::{MemoryAllocator, MemoryAllocatorCreateInfo}};
+use crate::{
+    VkCore, DebugLabels, ImageWrapper, ImageAccess, highest_sample_count, TexturePixelFormat,
+    TextureQuality, DepthConvention, ComputePipelineWrapper, ComputeAccess,
+    FeatureReport, PortabilitySubset, ValidationErrors, ImageState, QueueFamilyPair,
+    BufferWrapper,
+    mem::{
+        MemoryAllocator, MemoryAllocatorCreateInfo, AllocatorConfig, TransientAllocator,
+        transient::{StagedBufferUpdate, record_staged_updates}
+    }
+};
 use error::EngineError;
 use ash::{
     Device,
     extensions::khr::{
         Surface,
-        Swapchain
+        Swapchain,
+        PresentWait
     },
     vk
 };
+use capture::FrameCapture;
+use handoff::{ComputeHandoff, PendingComputeWait};
+use retain::RetainedFrame;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Duration;
 
-pub use present::PresentResult;
+pub use commands::{CommandQueue, OneTimeCommands, SubmittedCommands, SecondaryInheritance};
+pub use present::{PresentResult, AcquireResult, PresentStatus};
 pub use queues::Queue;
+pub use rotation::PreRotation;
 pub use swapchain::SwapchainWrapper;
+#[cfg(test)]
+pub(crate) use swapchain::choose_composite_alpha;
+pub use timeline::{SubmissionTimeline, SemaphoreWait};
+
+/// Default time to wait for a swapchain image before skipping the frame
+const DEFAULT_ACQUIRE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default number of consecutive acquire timeouts before reporting an error
+const DEFAULT_MAX_CONSECUTIVE_ACQUIRE_TIMEOUTS: u32 = 4;
 
-/// Wrap logical device along with Vulkan components that can exist for the life of a window
+/// Bytes of buffer updates that each frame in flight can stage through its transient buffer
+const FRAME_TRANSIENT_CAPACITY_BYTES: usize = 1_048_576;
+
+/// Largest factor that the internal render resolution may be scaled by, for supersampling
+pub const MAX_RENDER_SCALE: f32 = 4.0;
+
+/// Wrap logical device along with Vulkan components that can exist for the life of a window.
+/// Semaphores signalled on image acquisition and the graphics timeline values guarding submitted
+/// work are indexed per frame in flight, since the driver may hand out swapchain images in any
+/// order; semaphores
+/// waited on for presentation and the command buffers are indexed per swapchain image. A headless
+/// context has no surface, and its swapchain is a set of off-screen images of a fixed size that
+/// are rendered into in turn and left ready to be captured rather than presented.
 pub struct VkContext {
     pub device: Device,
     borrowed_physical_device_handle: vk::PhysicalDevice,
+    min_uniform_buffer_offset_alignment: vk::DeviceSize,
+    max_sampler_anisotropy: Option<f32>,
+    max_sampler_lod_bias: f32,
+    texture_quality: TextureQuality,
+    multi_draw_indirect_supported: bool,
+    dynamic_rendering_supported: bool,
+    synchronization2_supported: bool,
+    maintenance4_supported: bool,
+    non_solid_fill_supported: bool,
+    shader_float16_supported: bool,
+    line_width_range: [f32; 2],
+    feature_report: FeatureReport,
+    portability_subset: Option<PortabilitySubset>,
+    max_bindless_textures: u32,
+    viewport_aspect_ratio: Option<f32>,
+    render_scale: Option<f32>,
+    prefer_srgb_swapchain: bool,
+    prefer_transparent_swapchain: bool,
+    supported_compressed_formats: Vec<TexturePixelFormat>,
+    msaa_sample_counts: vk::SampleCountFlags,
+    depth_convention: DepthConvention,
+    depth_format: TexturePixelFormat,
+    supported_depth_formats: Vec<TexturePixelFormat>,
+    pipeline_creation_threads: usize,
     pub graphics_queue: Queue,
     pub transfer_queue: Queue,
+    compute_queue: Option<Queue>,
     graphics_command_buffers: Vec<vk::CommandBuffer>,
+    user_command_pools: commands::UserCommandPools,
+    secondary_command_pools: commands::SecondaryCommandPools,
+    debug_labels: DebugLabels,
+    validation_errors: ValidationErrors,
+    descriptor_allocator: descriptors::DescriptorAllocator,
+    pipeline_cache: vk::PipelineCache,
     mem_allocator: MemoryAllocator,
     sync_image_available: Vec<vk::Semaphore>,
-    sync_may_begin_rendering: Vec<vk::Fence>,
+    frame_timeline_values: Vec<u64>,
     sync_rendering_finished: Vec<vk::Semaphore>,
+    sync_compute_finished: Vec<vk::Semaphore>,
+    sync_compute_handoff: Vec<vk::Semaphore>,
+    compute_handoff: Mutex<Option<ComputeHandoff>>,
+    one_time_graphics_commands: Mutex<Vec<(vk::CommandPool, vk::CommandBuffer, u64)>>,
+    pending_compute_wait: Mutex<Option<PendingComputeWait>>,
+    frame_transients: Mutex<Option<TransientAllocator>>,
+    staged_buffer_updates: Mutex<Vec<StagedBufferUpdate>>,
+    image_frames_in_flight: Vec<Option<usize>>,
+    current_frame: usize,
     current_image_acquired: usize,
+    current_image_submitted: bool,
+    acquire_timeout: Duration,
+    max_consecutive_acquire_timeouts: u32,
+    consecutive_acquire_timeouts: u32,
+    acquired_suboptimal: bool,
     surface_fn: Surface,
     surface: vk::SurfaceKHR,
+    headless_extent: Option<vk::Extent2D>,
     swapchain_fn: Swapchain,
     swapchain: SwapchainWrapper,
+    present_wait_fn: Option<PresentWait>,
+    last_present_id: u64,
+    swapchain_first_present_id: u64,
+    capture_requested: bool,
+    pending_capture: Option<FrameCapture>,
+    retain_frames: bool,
+    retained_frame: Option<RetainedFrame>
 }
 
 impl VkContext {
 
     pub fn new<T>(core: &VkCore, window: &T) -> Result<Self, EngineError>
         where T: HasRawDisplayHandle + HasRawWindowHandle
+    {
+        Self::new_with_allocator_config(core, window, AllocatorConfig::default())
+    }
+
+    /// Create a new instance, with non-default options for the memory allocator
+    pub fn new_with_allocator_config<T>(
+        core: &VkCore,
+        window: &T,
+        allocator_config: AllocatorConfig
+    ) -> Result<Self, EngineError>
+        where T: HasRawDisplayHandle + HasRawWindowHandle
     {
         Ok(unsafe {
-            let mut context = Self::new_with_surface_without_swapchain(core, window)?;
+            let mut context = Self::new_with_surface_without_swapchain(
+                core,
+                window,
+                allocator_config)?;
+            context.create_swapchain(core)?;
+            context.regenerate_graphics_command_buffers()?;
+            context
+        })
+    }
+
+    /// Create a new headless instance, rendering into off-screen images of the given size in
+    /// place of a swapchain, such as for tests on CI machines or rendering frames to disk. The
+    /// core must have been created by VkCore::new_headless.
+    pub fn new_headless(core: &VkCore, width: u32, height: u32) -> Result<Self, EngineError> {
+        Self::new_headless_with_allocator_config(core, width, height, AllocatorConfig::default())
+    }
+
+    /// Create a new headless instance, with non-default options for the memory allocator
+    pub fn new_headless_with_allocator_config(
+        core: &VkCore,
+        width: u32,
+        height: u32,
+        allocator_config: AllocatorConfig
+    ) -> Result<Self, EngineError> {
+        if !core.is_headless() {
+            return Err(EngineError::UserError(
+                String::from("Headless contexts need a core created by VkCore::new_headless")));
+        }
+        if width == 0 || height == 0 {
+            return Err(EngineError::UserError(
+                format!("Invalid headless extent {}x{}", width, height)));
+        }
+        Ok(unsafe {
+            let mut context = Self::new_without_swapchain(
+                core,
+                vk::SurfaceKHR::null(),
+                Some(vk::Extent2D { width, height }),
+                allocator_config)?;
             context.create_swapchain(core)?;
             context.regenerate_graphics_command_buffers()?;
             context
@@ -58,9 +206,25 @@ impl VkContext {
 
     pub fn teardown(&mut self) {
         unsafe {
+            if let Some(capture) = self.pending_capture.take() {
+                capture.release(self);
+            }
+            self.retain_frames = false;
             self.destroy_swapchain_resources();
-            self.surface_fn.destroy_surface(self.surface, None);
-            self.mem_allocator.destroy(&self.transfer_queue);
+            if self.surface != vk::SurfaceKHR::null() {
+                self.surface_fn.destroy_surface(self.surface, None);
+            }
+            let leak_report = self.mem_allocator.destroy();
+            if !leak_report.is_empty() {
+                println!("Memory leak report: {}", leak_report);
+            }
+            self.user_command_pools.destroy(&self.device);
+            self.secondary_command_pools.destroy(&self.device);
+            self.descriptor_allocator.destroy(&self.device);
+            self.device.destroy_pipeline_cache(self.pipeline_cache, None);
+            if let Some(compute_queue) = &self.compute_queue {
+                compute_queue.destroy(&self.device);
+            }
             self.transfer_queue.destroy(&self.device);
             self.graphics_queue.destroy(&self.device);
             self.device.destroy_device(None);
@@ -70,13 +234,15 @@ impl VkContext {
     /// Create a new instance, but not yet creating the swapchain. For internal use.
     unsafe fn new_with_surface_without_swapchain<T>(
         core: &VkCore,
-        window: &T
+        window: &T,
+        allocator_config: AllocatorConfig
     ) -> Result<VkContext, EngineError>
         where T: HasRawDisplayHandle + HasRawWindowHandle
     {
-
-        // Create surface and surface loader
-        let surface_fn = Surface::new(&core.function_loader, &core.instance);
+        if core.is_headless() {
+            return Err(EngineError::UserError(
+                String::from("Headless cores can only create contexts through new_headless")));
+        }
         let surface = ash_window::create_surface(
             &core.function_loader,
             &core.instance,
@@ -84,6 +250,18 @@ impl VkContext {
             window.raw_window_handle(),
             None)
             .map_err(|e| EngineError::OpFailed(format!("Error creating surface: {}", e)))?;
+        Self::new_without_swapchain(core, surface, None, allocator_config)
+    }
+
+    /// Create a new instance for the given surface, or for off-screen images of the given
+    /// extent if headless, but not yet creating the swapchain. For internal use.
+    unsafe fn new_without_swapchain(
+        core: &VkCore,
+        surface: vk::SurfaceKHR,
+        headless_extent: Option<vk::Extent2D>,
+        allocator_config: AllocatorConfig
+    ) -> Result<VkContext, EngineError> {
+        let surface_fn = Surface::new(&core.function_loader, &core.instance);
 
         // Create device
         let device = device::make_device_resources(core)?;
@@ -91,44 +269,148 @@ impl VkContext {
         // Make queues
         let graphics_queue = Queue::new(&device, core.graphics_queue_family_index)?;
         let transfer_queue = Queue::new(&device, core.transfer_queue_family_index)?;
+        let compute_queue = match core.compute_queue_family_index {
+            Some(queue_family_index) => Some(Queue::new(&device, queue_family_index)?),
+            None => None
+        };
 
-        // Allocate a command buffer for the transfer queue
-        let transfer_command_buffer = transfer_queue
-            .allocate_command_buffer(&device)?;
+        // Sparse binding is done on the graphics queue, which must support it
+        let features = &core.physical_device_features;
+        let graphics_queue_flags = core.instance
+            .get_physical_device_queue_family_properties(core.physical_device)
+            [core.graphics_queue_family_index as usize]
+            .queue_flags;
+        let sparse_binding_supported = features.sparse_binding == vk::TRUE &&
+            features.sparse_residency_image2_d == vk::TRUE &&
+            graphics_queue_flags.contains(vk::QueueFlags::SPARSE_BINDING);
 
         // Create a memory allocator
         let allocator_info = MemoryAllocatorCreateInfo {
             physical_device: core.physical_device,
             device: device.clone(),
             instance: core.instance.clone(),
-            transfer_command_buffer
+            graphics_queue_family_index: core.graphics_queue_family_index,
+            transfer_queue_family_index: core.transfer_queue_family_index,
+            sparse_binding_supported,
+            timeline_semaphore_supported: core.supports_timeline_semaphore(),
+            config: allocator_config
         };
         let mem_allocator = MemoryAllocator::new(allocator_info)?;
 
+        // Pipelines are all created through one cache, so that pre-warmed state is found later
+        let pipeline_cache = device
+            .create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating pipeline cache: {:?}", e)))?;
+
         let swapchain_fn = Swapchain::new(&core.instance, &device);
+        let present_wait_fn = match core.supports_present_wait() {
+            true => Some(PresentWait::new(&core.instance, &device)),
+            false => None
+        };
+        let limits = core.instance
+            .get_physical_device_properties(core.physical_device)
+            .limits;
+        let min_uniform_buffer_offset_alignment = limits.min_uniform_buffer_offset_alignment;
+        let max_sampler_anisotropy = match features.sampler_anisotropy {
+            vk::TRUE => Some(limits.max_sampler_anisotropy),
+            _ => None
+        };
+        let portability_subset = core.get_portability_subset();
+        let max_sampler_lod_bias = match portability_subset {
+            Some(subset) if !subset.sampler_mip_lod_bias => 0.0,
+            _ => limits.max_sampler_lod_bias
+        };
+        let multi_draw_indirect_supported = features.multi_draw_indirect == vk::TRUE;
+        let line_width_range = match features.wide_lines {
+            vk::TRUE => limits.line_width_range,
+            _ => [1.0, 1.0]
+        };
 
         Ok(
             Self {
                 device,
                 borrowed_physical_device_handle: core.physical_device,
+                min_uniform_buffer_offset_alignment,
+                max_sampler_anisotropy,
+                max_sampler_lod_bias,
+                texture_quality: TextureQuality::default(),
+                multi_draw_indirect_supported,
+                dynamic_rendering_supported: core.supports_dynamic_rendering(),
+                synchronization2_supported: core.supports_synchronization2(),
+                maintenance4_supported: core.supports_maintenance4(),
+                non_solid_fill_supported: features.fill_mode_non_solid == vk::TRUE,
+                shader_float16_supported: core.supports_shader_float16(),
+                line_width_range,
+                feature_report: core.get_feature_report().clone(),
+                portability_subset,
+                max_bindless_textures: core.get_max_bindless_textures(),
+                viewport_aspect_ratio: None,
+                render_scale: None,
+                prefer_srgb_swapchain: false,
+                prefer_transparent_swapchain: false,
+                supported_compressed_formats: core.supported_compressed_formats(),
+                msaa_sample_counts: core.get_msaa_sample_counts(),
+                depth_convention: DepthConvention::default(),
+                depth_format: TexturePixelFormat::Unorm16,
+                supported_depth_formats: [
+                    TexturePixelFormat::Unorm16,
+                    TexturePixelFormat::Depth24Stencil8
+                ].into_iter().filter(|format| core.supports_depth_format(*format)).collect(),
+                pipeline_creation_threads: 1,
                 graphics_queue,
                 transfer_queue,
+                compute_queue,
                 graphics_command_buffers: vec![],
+                user_command_pools: commands::UserCommandPools::new(
+                    core.graphics_queue_family_index,
+                    core.transfer_queue_family_index,
+                    core.compute_queue_family_index.unwrap_or(core.graphics_queue_family_index)),
+                secondary_command_pools: commands::SecondaryCommandPools::new(),
+                debug_labels: core.get_debug_labels(),
+                validation_errors: core.get_validation_errors(),
+                descriptor_allocator: descriptors::DescriptorAllocator::new(),
+                pipeline_cache,
                 mem_allocator,
                 sync_image_available: vec![],
-                sync_may_begin_rendering: vec![],
+                frame_timeline_values: vec![],
                 sync_rendering_finished: vec![],
+                sync_compute_finished: vec![],
+                sync_compute_handoff: vec![],
+                compute_handoff: Mutex::new(None),
+                one_time_graphics_commands: Mutex::new(vec![]),
+                pending_compute_wait: Mutex::new(None),
+                frame_transients: Mutex::new(None),
+                staged_buffer_updates: Mutex::new(vec![]),
+                image_frames_in_flight: vec![],
+                current_frame: 0,
                 current_image_acquired: 0,
+                current_image_submitted: false,
+                acquire_timeout: DEFAULT_ACQUIRE_TIMEOUT,
+                max_consecutive_acquire_timeouts: DEFAULT_MAX_CONSECUTIVE_ACQUIRE_TIMEOUTS,
+                consecutive_acquire_timeouts: 0,
+                acquired_suboptimal: false,
                 surface_fn,
                 surface,
+                headless_extent,
                 swapchain_fn,
-                swapchain: SwapchainWrapper::default()
+                swapchain: SwapchainWrapper::default(),
+                present_wait_fn,
+                last_present_id: 0,
+                swapchain_first_present_id: 1,
+                capture_requested: false,
+                pending_capture: None,
+                retain_frames: false,
+                retained_frame: None
             }
         )
     }
 
-    /// Get the dimensions of the current surface
+    /// Get the dimensions of the current surface, or of the off-screen images if headless
     pub fn get_extent(&self) -> Result<vk::Extent2D, EngineError> {
+        if let Some(extent) = self.headless_extent {
+            return Ok(extent);
+        }
         let surface_capabilities = unsafe {
             self.surface_fn.get_physical_device_surface_capabilities(
                 self.borrowed_physical_device_handle,
@@ -141,6 +423,79 @@ impl VkContext {
         Ok(surface_capabilities.current_extent)
     }
 
+    /// Lock the area rendered into to the given width-to-height ratio, centred within the
+    /// surface, or unlock it to fill the surface. Pipelines read this when they are created, so
+    /// it should be set before the scene's dynamic resources are (re)loaded.
+    pub fn set_viewport_aspect_ratio(&mut self, aspect_ratio: Option<f32>) {
+        self.viewport_aspect_ratio = aspect_ratio;
+    }
+
+    /// Get the area of the surface that is rendered into; the whole surface unless letterboxed
+    pub fn get_render_area(&self) -> Result<vk::Rect2D, EngineError> {
+        let extent = self.get_extent()?;
+        Ok(self.render_area_within(extent))
+    }
+
+    /// Get the area of the current swapchain's images that is rendered into, which differs from
+    /// get_render_area while the surface is being resized and the swapchain not yet rebuilt
+    fn get_swapchain_render_area(&self) -> vk::Rect2D {
+        self.render_area_within(self.swapchain.get_extent())
+    }
+
+    fn render_area_within(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        match self.viewport_aspect_ratio {
+            Some(aspect_ratio) => letterbox_area(extent, aspect_ratio),
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent
+            }
+        }
+    }
+
+    /// Render the main pass at the render area's size multiplied by the given factor, into an
+    /// off-screen target that is then scaled into the swapchain image, or set None to render
+    /// straight into the swapchain image. Factors below one lighten the load on weak GPUs, while
+    /// those above supersample. Pipelines read this when they are created, so it should be set
+    /// before the scene's dynamic resources are (re)loaded.
+    pub fn set_render_scale(&mut self, scale: Option<f32>) -> Result<(), EngineError> {
+        if let Some(scale) = scale {
+            if !(scale > 0.0 && scale <= MAX_RENDER_SCALE) {
+                return Err(EngineError::UserError(format!(
+                    "Render scale {} is not above zero and up to {}",
+                    scale,
+                    MAX_RENDER_SCALE)));
+            }
+        }
+        self.render_scale = scale;
+        Ok(())
+    }
+
+    /// Get the factor that the main pass is rendered at relative to the render area, if it is
+    /// rendered at an internal resolution
+    pub fn get_render_scale(&self) -> Option<f32> {
+        self.render_scale
+    }
+
+    /// Get the area that the main pass is rendered into: the render area, unless rendering at an
+    /// internal resolution, in which case it is the whole of an off-screen target of the scaled
+    /// size. Pipelines take their viewport from this.
+    pub fn get_internal_render_area(&self) -> Result<vk::Rect2D, EngineError> {
+        let render_area = self.get_render_area()?;
+        Ok(match self.render_scale {
+            Some(scale) => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: scaled_extent(render_area.extent, scale)
+            },
+            None => render_area
+        })
+    }
+
+    /// Get how the display is rotated relative to the swapchain's images, which are presented
+    /// pre-rotated; see PreRotation. Only changes when the swapchain is recreated.
+    pub fn get_pre_rotation(&self) -> PreRotation {
+        self.swapchain.get_pre_rotation()
+    }
+
     /// Getter for swapchain image count
     pub fn get_swapchain_image_count(&self) -> usize {
         self.swapchain.get_image_count()
@@ -151,6 +506,29 @@ impl VkContext {
         self.swapchain.get_image_view(image_index)
     }
 
+    /// Getter for a swapchain image, such as for transitioning its layout when not rendering
+    /// through a renderpass
+    pub fn get_swapchain_image(&self, image_index: usize) -> Result<vk::Image, EngineError> {
+        self.swapchain.get_image(image_index)
+    }
+
+    /// Choose the format of the depth image, which takes effect when the swapchain is next
+    /// created, such as by recreate_surface. Depth24Stencil8 adds a stencil aspect, which
+    /// renderpasses then clear and pipelines may test; see StencilState.
+    pub fn set_depth_format(&mut self, format: TexturePixelFormat) -> Result<(), EngineError> {
+        if !self.supported_depth_formats.contains(&format) {
+            return Err(EngineError::Compatibility(
+                format!("Depth format {:?} is not supported by this device", format)));
+        }
+        self.depth_format = format;
+        Ok(())
+    }
+
+    /// Getter for the format that depth images are created in
+    pub fn get_depth_format(&self) -> TexturePixelFormat {
+        self.depth_format
+    }
+
     /// Getter for the depth image
     pub fn get_depth_image(&self) -> Option<&ImageWrapper> {
         self.swapchain.get_depth_image()
@@ -161,57 +539,324 @@ impl VkContext {
         self.swapchain.get_surface_format()
     }
 
+    /// Query whether this context renders into off-screen images rather than to a surface
+    pub fn is_headless(&self) -> bool {
+        self.headless_extent.is_some()
+    }
+
+    /// The layout that swapchain images are left in once rendered, for passes that write them:
+    /// ready to present, or ready to be copied from when headless
+    pub fn get_output_layout(&self) -> vk::ImageLayout {
+        match self.is_headless() {
+            true => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            false => vk::ImageLayout::PRESENT_SRC_KHR
+        }
+    }
+
     /// Create the swapchain; any previously-created swapchain should be destroyed first
     unsafe fn create_swapchain(&mut self, core: &VkCore) -> Result<(), EngineError> {
 
         let extent = self.get_extent()?;
-        self.swapchain = SwapchainWrapper::new(core, &self, &self.surface_fn, self.surface, extent)?;
+        self.swapchain = match self.is_headless() {
+            true => SwapchainWrapper::new_headless(&self, extent, self.prefer_srgb_swapchain)?,
+            false => SwapchainWrapper::new(
+                core,
+                &self,
+                &self.surface_fn,
+                self.surface,
+                extent,
+                self.prefer_srgb_swapchain,
+                self.prefer_transparent_swapchain)?
+        };
+        self.current_frame = self.swapchain.get_image_count() - 1;
         self.current_image_acquired = self.swapchain.get_image_count() - 1;
+        self.current_image_submitted = true;
+        self.consecutive_acquire_timeouts = 0;
+        self.acquired_suboptimal = false;
+        self.image_frames_in_flight = vec![None; self.swapchain.get_image_count()];
+        self.swapchain_first_present_id = self.last_present_id + 1;
 
         // Synchronisation objects
         self.sync_image_available.clear();
-        self.sync_may_begin_rendering.clear();
         self.sync_rendering_finished.clear();
+        self.sync_compute_finished.clear();
+        self.sync_compute_handoff.clear();
+        *self.compute_handoff.lock().unwrap() = None;
+        *self.pending_compute_wait.lock().unwrap() = None;
         let swapchain_size = self.swapchain.get_image_count();
         let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
-        let fence_create_info = vk::FenceCreateInfo::builder()
-            .flags(vk::FenceCreateFlags::SIGNALED);
+        self.frame_timeline_values = vec![0; swapchain_size];
         for _ in 0..swapchain_size {
             let semaphore_available = self.device
                 .create_semaphore(&semaphore_create_info, None)
                 .map_err(|e| {
                     EngineError::OpFailed(format!("{:?}", e))
                 })?;
-            let fence_begin_rendering = self.device
-                .create_fence(&fence_create_info, None)
+            let semaphore_finished = self.device
+                .create_semaphore(&semaphore_create_info, None)
                 .map_err(|e| {
                     EngineError::OpFailed(format!("{:?}", e))
                 })?;
-            let semaphore_finished = self.device
+            let semaphore_compute_finished = self.device
+                .create_semaphore(&semaphore_create_info, None)
+                .map_err(|e| {
+                    EngineError::OpFailed(format!("{:?}", e))
+                })?;
+            let semaphore_compute_handoff = self.device
                 .create_semaphore(&semaphore_create_info, None)
                 .map_err(|e| {
                     EngineError::OpFailed(format!("{:?}", e))
                 })?;
             self.sync_image_available.push(semaphore_available);
-            self.sync_may_begin_rendering.push(fence_begin_rendering);
             self.sync_rendering_finished.push(semaphore_finished);
+            self.sync_compute_finished.push(semaphore_compute_finished);
+            self.sync_compute_handoff.push(semaphore_compute_handoff);
         }
 
+        // A transient buffer for each frame in flight, to stage the buffer updates made while
+        // preparing it
+        self.staged_buffer_updates.lock().unwrap().clear();
+        let frame_transients = self.mem_allocator.create_transient_allocator(
+            &self.transfer_queue,
+            swapchain_size,
+            FRAME_TRANSIENT_CAPACITY_BYTES)?;
+        *self.frame_transients.lock().unwrap() = Some(frame_transients);
+
+        self.name_swapchain_objects();
         Ok(())
     }
 
+    /// Name the swapchain images, the depth image and the per-image synchronisation objects
+    unsafe fn name_swapchain_objects(&self) {
+        if !self.debug_labels.is_enabled() {
+            return;
+        }
+        for image_index in 0..self.swapchain.get_image_count() {
+            if let Ok(image) = self.swapchain.get_image(image_index) {
+                self.set_debug_name(image, &format!("Swapchain image {}", image_index));
+            }
+            if let Ok(image_view) = self.swapchain.get_image_view(image_index) {
+                self.set_debug_name(image_view, &format!("Swapchain image view {}", image_index));
+            }
+            self.set_debug_name(
+                self.sync_image_available[image_index],
+                &format!("Image available {}", image_index));
+            self.set_debug_name(
+                self.sync_rendering_finished[image_index],
+                &format!("Rendering finished {}", image_index));
+        }
+        if let Some(depth_image) = self.swapchain.get_depth_image() {
+            self.set_debug_name(depth_image.image, "Depth image");
+            self.set_debug_name(depth_image.image_view, "Depth image view");
+        }
+    }
+
     /// Destroy resources associated with the swapchain
     unsafe fn destroy_swapchain_resources(&mut self) {
-        for semaphore in self.sync_rendering_finished.iter() {
+        self.release_one_time_graphics(true);
+        self.staged_buffer_updates.lock().unwrap().clear();
+        if let Some(mut frame_transients) = self.frame_transients.lock().unwrap().take() {
+            let _ = frame_transients.destroy(&self.mem_allocator);
+        }
+        for semaphore in self.sync_compute_handoff.iter() {
             self.device.destroy_semaphore(*semaphore, None);
         }
-        for fence in self.sync_may_begin_rendering.iter() {
-            self.device.destroy_fence(*fence, None);
+        for semaphore in self.sync_compute_finished.iter() {
+            self.device.destroy_semaphore(*semaphore, None);
+        }
+        for semaphore in self.sync_rendering_finished.iter() {
+            self.device.destroy_semaphore(*semaphore, None);
         }
         for semaphore in self.sync_image_available.iter() {
             self.device.destroy_semaphore(*semaphore, None);
         }
         self.swapchain.destroy(&self, &self.swapchain_fn);
+
+        // The retained frame outlives the swapchain for as long as frames are being retained
+        if !self.retain_frames {
+            if let Some(retained_frame) = self.retained_frame.take() {
+                retained_frame.release(self);
+            }
+        }
+    }
+
+    /// Wait for uploads still in flight, then acquire on the graphics queue all resources
+    /// uploaded through the transfer queue since the last call. Must be done before any rendering
+    /// that uses those resources is submitted.
+    pub unsafe fn acquire_transferred_resources(&self) -> Result<usize, EngineError> {
+        self.mem_allocator.acquire_released_resources(&self.graphics_queue)
+    }
+
+    /// Alignment required of dynamic offsets into uniform buffers
+    pub fn get_min_uniform_buffer_offset_alignment(&self) -> vk::DeviceSize {
+        self.min_uniform_buffer_offset_alignment
+    }
+
+    /// Highest anisotropy that samplers may use, or None if anisotropic filtering was not
+    /// declared or is not supported
+    pub fn get_max_sampler_anisotropy(&self) -> Option<f32> {
+        self.max_sampler_anisotropy
+    }
+
+    /// Largest LOD bias, in either direction, that samplers may use; zero on portability subset
+    /// devices without sampler LOD bias
+    pub fn get_max_sampler_lod_bias(&self) -> f32 {
+        self.max_sampler_lod_bias
+    }
+
+    /// Global texture quality settings, applied to samplers as they are created
+    pub fn get_texture_quality(&self) -> TextureQuality {
+        self.texture_quality
+    }
+
+    /// Change the global texture quality settings. Samplers already created are unaffected until
+    /// rebuilt with SamplerWrapper::recreate.
+    pub fn set_texture_quality(&mut self, quality: TextureQuality) {
+        self.texture_quality = quality;
+    }
+
+    /// Query whether textures in the given format can be created on this device; see
+    /// VkCore::supports_texture_format
+    pub fn supports_texture_format(&self, format: TexturePixelFormat) -> bool {
+        !format.is_compressed() || self.supported_compressed_formats.contains(&format)
+    }
+
+    /// Sample counts that color and depth attachments may both use; see VkCore
+    pub fn get_msaa_sample_counts(&self) -> vk::SampleCountFlags {
+        self.msaa_sample_counts
+    }
+
+    /// Choose the highest sample count supported for attachments that does not exceed the one
+    /// requested, such as 4 for 4x MSAA
+    pub fn select_msaa_samples(&self, requested: u32) -> vk::SampleCountFlags {
+        highest_sample_count(self.msaa_sample_counts, requested)
+    }
+
+    /// Whether pipelines may draw triangles as lines or points rather than filling them; see
+    /// FeatureDeclaration::FillModeNonSolid
+    pub fn supports_non_solid_fill(&self) -> bool {
+        self.non_solid_fill_supported
+    }
+
+    /// Whether a single indirect draw command may issue more than one draw; see
+    /// FeatureDeclaration::MultiDrawIndirect
+    pub fn supports_multi_draw_indirect(&self) -> bool {
+        self.multi_draw_indirect_supported
+    }
+
+    /// Whether shaders may do arithmetic on 16-bit floats; see FeatureDeclaration::ShaderFloat16
+    pub fn supports_shader_float16(&self) -> bool {
+        self.shader_float16_supported
+    }
+
+    /// The smallest and largest widths that lines may be drawn with; only 1.0 unless
+    /// FeatureDeclaration::WideLines was granted
+    pub fn get_line_width_range(&self) -> [f32; 2] {
+        self.line_width_range
+    }
+
+    /// Which of the features declared when creating the core were granted, so that scenes can
+    /// fall back where one was not
+    pub fn get_feature_report(&self) -> &FeatureReport {
+        &self.feature_report
+    }
+
+    /// What the device can do if it only partly implements Vulkan, such as through MoltenVK;
+    /// None for full implementations. See VkCore::get_portability_subset.
+    pub fn get_portability_subset(&self) -> Option<PortabilitySubset> {
+        self.portability_subset
+    }
+
+    /// Whether passes may be rendered without renderpass and framebuffer objects; see
+    /// DynamicRenderTarget
+    pub fn supports_dynamic_rendering(&self) -> bool {
+        self.dynamic_rendering_supported
+    }
+
+    /// Whether barriers are recorded with vkCmdPipelineBarrier2 rather than vkCmdPipelineBarrier;
+    /// see VkCore::supports_synchronization2
+    pub fn supports_synchronization2(&self) -> bool {
+        self.synchronization2_supported
+    }
+
+    /// Whether memory requirements of images can be found without creating them; see
+    /// image_memory_requirements
+    pub fn supports_maintenance4(&self) -> bool {
+        self.maintenance4_supported
+    }
+
+    /// The memory that an image created with the given description would need, such as for
+    /// working out whether it fits in a budget before creating it. With maintenance4 this is
+    /// queried directly; otherwise a temporary image is created, queried and destroyed.
+    pub unsafe fn image_memory_requirements(
+        &self,
+        image_info: &vk::ImageCreateInfo
+    ) -> Result<vk::MemoryRequirements, EngineError> {
+        if self.maintenance4_supported {
+            let requirements_info = vk::DeviceImageMemoryRequirements::builder()
+                .create_info(image_info);
+            let mut requirements = vk::MemoryRequirements2::default();
+            self.device.get_device_image_memory_requirements(&requirements_info, &mut requirements);
+            return Ok(requirements.memory_requirements);
+        }
+        let image = self.device.create_image(image_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating image to query: {:?}", e))
+            })?;
+        let requirements = self.device.get_image_memory_requirements(image);
+        self.device.destroy_image(image, None);
+        Ok(requirements)
+    }
+
+    /// Whether bindless texture arrays are available; see BindlessTextureSet
+    pub fn supports_descriptor_indexing(&self) -> bool {
+        self.max_bindless_textures > 0
+    }
+
+    /// The most textures that a BindlessTextureSet may hold, or zero without descriptor indexing
+    pub fn get_max_bindless_textures(&self) -> u32 {
+        self.max_bindless_textures
+    }
+
+    /// The cache that pipelines are created through; see prewarm_pipelines
+    pub fn get_pipeline_cache(&self) -> vk::PipelineCache {
+        self.pipeline_cache
+    }
+
+    /// Get a descriptor set of the given layout from the shared pools, reusing one recycled with
+    /// the same layout if there is one. Return it with recycle_descriptor_set when done.
+    pub unsafe fn allocate_descriptor_set(
+        &self,
+        layout: vk::DescriptorSetLayout
+    ) -> Result<vk::DescriptorSet, EngineError> {
+        self.descriptor_allocator.allocate(&self.device, layout)
+    }
+
+    /// Return a descriptor set allocated with allocate_descriptor_set, to be handed out again for
+    /// the same layout. The set must not be in use by any pending command buffer.
+    pub fn recycle_descriptor_set(&self, layout: vk::DescriptorSetLayout, set: vk::DescriptorSet) {
+        self.descriptor_allocator.recycle(layout, set);
+    }
+
+    /// Free recycled descriptor sets of a layout that is about to be destroyed
+    pub unsafe fn forget_descriptor_set_layout(&self, layout: vk::DescriptorSetLayout) {
+        self.descriptor_allocator.forget_layout(&self.device, layout);
+    }
+
+    /// Number of descriptor pools that the shared descriptor allocator has created
+    pub fn get_descriptor_pool_count(&self) -> usize {
+        self.descriptor_allocator.pool_count()
+    }
+
+    /// The timeline tracking work submitted to the graphics queue by the engine, including each
+    /// frame, which other queues can wait on where timeline semaphores are supported
+    pub fn get_graphics_timeline(&self) -> &SubmissionTimeline {
+        self.mem_allocator.get_graphics_timeline()
+    }
+
+    /// The timeline tracking the engine's uploads and other work on the transfer queue
+    pub fn get_transfer_timeline(&self) -> &SubmissionTimeline {
+        self.mem_allocator.get_transfer_timeline()
     }
 
     /// Getter for the memory allocator
@@ -219,11 +864,379 @@ impl VkContext {
         (&self.mem_allocator, &self.transfer_queue)
     }
 
+    /// Obtain a command buffer, already begun for one submission, into which any commands may be
+    /// recorded for the given queue. It comes from a pool belonging to the calling thread, so
+    /// must be submitted from the same thread with submit_one_time_commands, or discarded.
+    pub unsafe fn begin_one_time_commands(
+        &self,
+        queue: CommandQueue
+    ) -> Result<OneTimeCommands, EngineError> {
+        let command_pool = self.user_command_pools.pool_for_current_thread(&self.device, queue)?;
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffer = self.device.allocate_command_buffers(&allocate_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating one-time command buffer: {:?}", e)))?[0];
+        let commands = OneTimeCommands {
+            queue,
+            command_pool,
+            command_buffer,
+            _not_send: PhantomData
+        };
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        if let Err(e) = self.device.begin_command_buffer(command_buffer, &begin_info) {
+            commands.discard(&self.device);
+            return Err(EngineError::OpFailed(
+                format!("Error beginning one-time command buffer: {:?}", e)));
+        }
+        Ok(commands)
+    }
+
+    /// End and submit one-time commands, returning a handle to wait on and release them.
+    /// Submissions to the transfer queue are serialised with the engine's uploads. Submissions to
+    /// the graphics queue are not serialised with the engine's frames, so must be made from the
+    /// thread that renders; made from Scene::prepare_frame_render, they execute before that
+    /// frame's rendering commands.
+    pub unsafe fn submit_one_time_commands(
+        &self,
+        commands: OneTimeCommands
+    ) -> Result<SubmittedCommands, EngineError> {
+        if let Err(e) = self.device.end_command_buffer(commands.command_buffer) {
+            commands.discard(&self.device);
+            return Err(EngineError::OpFailed(
+                format!("Error ending one-time command buffer: {:?}", e)));
+        }
+        let fence = match self.device.create_fence(&vk::FenceCreateInfo::default(), None) {
+            Ok(fence) => fence,
+            Err(e) => {
+                commands.discard(&self.device);
+                return Err(EngineError::OpFailed(format!("Error creating fence: {:?}", e)));
+            }
+        };
+        let result = match commands.queue {
+            CommandQueue::Graphics => self.graphics_queue.submit_transfer_command_buffer(
+                &self.device,
+                &commands.command_buffer,
+                &fence),
+            CommandQueue::Compute => self.get_compute_queue().submit_transfer_command_buffer(
+                &self.device,
+                &commands.command_buffer,
+                &fence),
+            CommandQueue::Transfer => self.mem_allocator.submit_transfer_with_fence(
+                &self.transfer_queue,
+                &commands.command_buffer,
+                &fence)
+        };
+        if let Err(e) = result {
+            self.device.destroy_fence(fence, None);
+            commands.discard(&self.device);
+            return Err(e);
+        }
+        self.validation_errors.check();
+        Ok(SubmittedCommands::new(commands, fence))
+    }
+
+    /// Record commands with the given function into a one-time command buffer, submit them to
+    /// the given queue and wait for them to finish
+    pub unsafe fn run_one_time_commands<F>(
+        &self,
+        queue: CommandQueue,
+        record: F
+    ) -> Result<(), EngineError>
+        where F: FnOnce(&Device, vk::CommandBuffer) -> Result<(), EngineError>
+    {
+        let commands = self.begin_one_time_commands(queue)?;
+        if let Err(e) = record(&self.device, commands.command_buffer) {
+            commands.discard(&self.device);
+            return Err(e);
+        }
+        self.submit_one_time_commands(commands)?.release(&self.device)
+    }
+
+    /// Record a dispatch of a compute pipeline into one-time commands on the graphics queue and
+    /// submit it without waiting, so that work drawn afterwards on the same queue sees its results.
+    /// The pipeline's descriptor set and the images used must not change until the returned
+    /// commands are complete.
+    pub unsafe fn dispatch_compute(
+        &self,
+        pipeline: &ComputePipelineWrapper,
+        group_counts: [u32; 3],
+        accesses: &[ComputeAccess],
+        push_constants: &[u8]
+    ) -> Result<SubmittedCommands, EngineError> {
+        let commands = self.begin_one_time_commands(CommandQueue::Graphics)?;
+        if let Err(e) = pipeline.record_dispatch(
+            &self.device,
+            commands.command_buffer,
+            group_counts,
+            accesses,
+            push_constants,
+            true
+        ) {
+            commands.discard(&self.device);
+            return Err(e);
+        }
+        self.submit_one_time_commands(commands)
+    }
+
+    /// Whether the device has a queue for compute work separate from the graphics queue, so that
+    /// CommandQueue::Compute work can run alongside rendering rather than before it
+    pub fn has_async_compute(&self) -> bool {
+        self.compute_queue.is_some()
+    }
+
+    /// The queue that CommandQueue::Compute work is submitted to
+    pub fn get_compute_queue(&self) -> &Queue {
+        self.compute_queue.as_ref().unwrap_or(&self.graphics_queue)
+    }
+
+    /// Obtain one-time commands for async compute work in the frame being prepared, which uses
+    /// the given resources. Resources with exclusive sharing belong to one queue family at a
+    /// time, so where the compute queue is of a different family from the graphics queue, the
+    /// graphics queue releases them, and these commands start by acquiring them. Sampled images
+    /// are moved into the shader read layout and storage images into the general layout as they
+    /// change hands, and must stay there until the commands are submitted. Every buffer that the
+    /// work reads or writes must be listed. Submit the commands with submit_async_compute, which
+    /// hands the resources back, before obtaining any more for the frame.
+    pub unsafe fn begin_async_compute(
+        &self,
+        accesses: &[ComputeAccess]
+    ) -> Result<OneTimeCommands, EngineError> {
+        let mut compute_handoff = self.compute_handoff.lock().unwrap();
+        if self.current_image_submitted ||
+            compute_handoff.is_some() ||
+            self.pending_compute_wait.lock().unwrap().is_some()
+        {
+            return Err(EngineError::UserError(String::from(
+                "Async compute may be submitted once per frame, before the frame is submitted")));
+        }
+        let families = QueueFamilyPair::new(
+            self.graphics_queue.queue_family_index,
+            self.get_compute_queue().queue_family_index);
+        let handoff = ComputeHandoff::for_accesses(families, accesses);
+        let commands = self.begin_one_time_commands(CommandQueue::Compute)?;
+        if handoff.is_empty() {
+            return Ok(commands);
+        }
+
+        // The graphics queue gives the resources up once its earlier work with them is done,
+        // signalling the compute submission to go ahead
+        let release = self.record_one_time_graphics(|command_buffer| {
+            handoff.record_release(&self.device, command_buffer);
+        });
+        let submitted = match release {
+            Ok(release) => self.submit_graphics_handoff(
+                release,
+                &[],
+                &[self.sync_compute_handoff[self.current_frame]]),
+            Err(e) => Err(e)
+        };
+        if let Err(e) = submitted {
+            commands.discard(&self.device);
+            return Err(e);
+        }
+        handoff.record_acquire(
+            &self.device,
+            commands.command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS);
+        for access in accesses.iter() {
+            let (image, layout) = match access {
+                ComputeAccess::Sampled(image) =>
+                    (image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ComputeAccess::Storage(image) => (image, vk::ImageLayout::GENERAL),
+                ComputeAccess::Buffer(_) => continue
+            };
+            image.assume_state(ImageState::new(
+                layout,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::empty()));
+        }
+        *compute_handoff = Some(handoff);
+        Ok(commands)
+    }
+
+    /// End and submit one-time commands obtained for CommandQueue::Compute, such that the frame
+    /// being prepared waits for them at the given stage, such as the vertex shader for particle
+    /// positions that they write. Rendering up to that stage can overlap the compute work. This
+    /// must be done from the thread that renders, after acquiring an image and before submitting
+    /// the frame, such as from Scene::prepare_frame_render, and only once per frame. Commands
+    /// obtained with begin_async_compute hand the resources they acquired back to the graphics
+    /// queue, which the frame acquires before the given stage; commands that use resources also
+    /// used by graphics must be obtained that way.
+    pub unsafe fn submit_async_compute(
+        &self,
+        commands: OneTimeCommands,
+        graphics_wait_stage: vk::PipelineStageFlags
+    ) -> Result<SubmittedCommands, EngineError> {
+        if commands.queue != CommandQueue::Compute {
+            commands.discard(&self.device);
+            return Err(EngineError::UserError(
+                String::from("Async compute needs commands obtained for the compute queue")));
+        }
+        let mut pending_compute_wait = self.pending_compute_wait.lock().unwrap();
+        if self.current_image_submitted || pending_compute_wait.is_some() {
+            commands.discard(&self.device);
+            return Err(EngineError::UserError(String::from(
+                "Async compute may be submitted once per frame, before the frame is submitted")));
+        }
+        let handoff = self.compute_handoff.lock().unwrap().take();
+        let mut waits = vec![];
+        if let Some(handoff) = &handoff {
+            handoff.returned().record_release(&self.device, commands.command_buffer);
+            waits.push(SemaphoreWait::binary(
+                self.sync_compute_handoff[self.current_frame],
+                vk::PipelineStageFlags::ALL_COMMANDS));
+        }
+        if let Err(e) = self.device.end_command_buffer(commands.command_buffer) {
+            commands.discard(&self.device);
+            return Err(EngineError::OpFailed(
+                format!("Error ending one-time command buffer: {:?}", e)));
+        }
+        let fence = match self.device.create_fence(&vk::FenceCreateInfo::default(), None) {
+            Ok(fence) => fence,
+            Err(e) => {
+                commands.discard(&self.device);
+                return Err(EngineError::OpFailed(format!("Error creating fence: {:?}", e)));
+            }
+        };
+        let result = self.get_compute_queue().submit_signalling_command_buffer(
+            &self.device,
+            commands.command_buffer,
+            &waits,
+            self.sync_compute_finished[self.current_frame],
+            fence);
+        if let Err(e) = result {
+            self.device.destroy_fence(fence, None);
+            commands.discard(&self.device);
+            return Err(e);
+        }
+        *pending_compute_wait = Some(PendingComputeWait {
+            stage: graphics_wait_stage,
+            handoff: handoff.map(|handoff| handoff.returned())
+        });
+        Ok(SubmittedCommands::new(commands, fence))
+    }
+
+    /// Record a dispatch of a compute pipeline into one-time commands and submit it as async
+    /// compute, which the frame being prepared waits for at the given stage; see
+    /// begin_async_compute and submit_async_compute. The accesses must include the buffers that
+    /// the pipeline's descriptor set binds. The pipeline's descriptor set and the resources used
+    /// must not change until the returned commands are complete.
+    pub unsafe fn dispatch_async_compute(
+        &self,
+        pipeline: &ComputePipelineWrapper,
+        group_counts: [u32; 3],
+        accesses: &[ComputeAccess],
+        push_constants: &[u8],
+        graphics_wait_stage: vk::PipelineStageFlags
+    ) -> Result<SubmittedCommands, EngineError> {
+        let commands = self.begin_async_compute(accesses)?;
+        if let Err(e) = pipeline.record_dispatch(
+            &self.device,
+            commands.command_buffer,
+            group_counts,
+            accesses,
+            push_constants,
+            !self.has_async_compute()
+        ) {
+            // Anything handed over has to be handed back, so the commands go without the dispatch
+            self.submit_async_compute(commands, graphics_wait_stage)?.release(&self.device)?;
+            return Err(e);
+        }
+        self.submit_async_compute(commands, graphics_wait_stage)
+    }
+
+    /// Record one-time commands for the graphics queue, such as those passing resources to or
+    /// from async compute, or copying in a frame's staged buffer updates
+    unsafe fn record_one_time_graphics<F>(&self, record: F) -> Result<OneTimeCommands, EngineError>
+        where F: FnOnce(vk::CommandBuffer)
+    {
+        let commands = self.begin_one_time_commands(CommandQueue::Graphics)?;
+        record(commands.command_buffer);
+        if let Err(e) = self.device.end_command_buffer(commands.command_buffer) {
+            commands.discard(&self.device);
+            return Err(EngineError::OpFailed(
+                format!("Error ending one-time command buffer: {:?}", e)));
+        }
+        Ok(commands)
+    }
+
+    /// Submit handoff commands to the graphics queue on its own, keeping them until the graphics
+    /// timeline shows them complete
+    unsafe fn submit_graphics_handoff(
+        &self,
+        commands: OneTimeCommands,
+        waits: &[SemaphoreWait],
+        signal_semaphores: &[vk::Semaphore]
+    ) -> Result<(), EngineError> {
+        let submitted = self.mem_allocator.get_graphics_timeline().submit(
+            &self.device,
+            &self.graphics_queue,
+            &[commands.command_buffer],
+            waits,
+            signal_semaphores);
+        match submitted {
+            Ok(timeline_value) => {
+                self.keep_one_time_graphics(commands, timeline_value);
+                Ok(())
+            },
+            Err(e) => {
+                commands.discard(&self.device);
+                Err(e)
+            }
+        }
+    }
+
+    /// Flush what has been staged in the current frame's transient buffer, ready for the device
+    /// to copy from
+    unsafe fn flush_frame_transients(&self) -> Result<(), EngineError> {
+        match self.frame_transients.lock().unwrap().as_ref() {
+            Some(frame_transients) => frame_transients.flush(&self.mem_allocator),
+            None => Ok(())
+        }
+    }
+
+    /// Keep submitted one-time graphics commands until the graphics timeline reaches the given
+    /// value
+    fn keep_one_time_graphics(&self, commands: OneTimeCommands, timeline_value: u64) {
+        self.one_time_graphics_commands.lock().unwrap().push(
+            (commands.command_pool, commands.command_buffer, timeline_value));
+    }
+
+    /// Free the one-time graphics commands that have completed, or all of them, waiting for them
+    /// first. This frees into the calling thread's pools, so must be done from the thread that
+    /// renders.
+    unsafe fn release_one_time_graphics(&self, wait_for_all: bool) {
+        let timeline = self.mem_allocator.get_graphics_timeline();
+        self.one_time_graphics_commands.lock().unwrap()
+            .retain(|(command_pool, command_buffer, timeline_value)| {
+                let complete = match wait_for_all {
+                    true => timeline.wait(&self.device, *timeline_value).map(|_| true),
+                    false => timeline.is_complete(&self.device, *timeline_value)
+                };
+                // If the device has been lost, nothing more will complete
+                if let Ok(false) = complete {
+                    return true;
+                }
+                self.device.free_command_buffers(*command_pool, &[*command_buffer]);
+                false
+            });
+    }
+
+    /// Destroy the calling thread's one-time command pools. Worker threads that used one-time
+    /// commands should call this, once all of theirs are released, before exiting; otherwise
+    /// the pools are kept until teardown.
+    pub unsafe fn release_thread_command_pools(&self) {
+        self.user_command_pools.release_current_thread(&self.device);
+    }
+
     pub unsafe fn wait_until_device_idle(&self) -> Result<(), EngineError> {
         self.device.device_wait_idle()
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Failed waiting for device: {:?}", e))
-            })?;
+            .map_err(|e| device_error("Failed waiting for device", e))?;
+        self.validation_errors.check();
         Ok(())
     }
 
@@ -234,10 +1247,14 @@ impl VkContext {
         &mut self
     ) -> Result<(), EngineError> {
         self.graphics_command_buffers.clear();
+        self.secondary_command_pools.destroy(&self.device);
         let graphics_command_buffers = self.graphics_queue.regenerate_command_buffers(
             &self.device,
             self.swapchain.get_image_count())?;
         self.graphics_command_buffers.extend(graphics_command_buffers);
+        for (image_index, command_buffer) in self.graphics_command_buffers.iter().enumerate() {
+            self.set_debug_name(*command_buffer, &format!("Graphics commands {}", image_index));
+        }
         Ok(())
     }
 
@@ -245,6 +1262,47 @@ impl VkContext {
         self.graphics_command_buffers[swapchain_image_index]
     }
 
+    /// Labels for naming objects and marking regions of command buffers, which can be kept for
+    /// recording commands where the context isn't available, such as by scenes
+    pub fn get_debug_labels(&self) -> DebugLabels {
+        self.debug_labels.clone()
+    }
+
+    /// Name a Vulkan object of this context's device, shown by debugging tools such as RenderDoc
+    /// and in validation messages. Does nothing without the debug utils extension, which is only
+    /// loaded in debug builds.
+    pub unsafe fn set_debug_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        self.debug_labels.set_object_name(self.device.handle(), handle, name);
+    }
+
+    /// Record the start of a labelled region of a command buffer, which debugging tools show
+    /// around the commands recorded until the matching cmd_end_label
+    pub unsafe fn cmd_begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        self.debug_labels.begin(command_buffer, name);
+    }
+
+    /// Record the end of the most recently begun labelled region of a command buffer
+    pub unsafe fn cmd_end_label(&self, command_buffer: vk::CommandBuffer) {
+        self.debug_labels.end(command_buffer);
+    }
+
+    /// Get secondary command buffers for a swapchain image, to be executed from its graphics
+    /// command buffer. Each is in a pool of its own, so all may be recorded at once on different
+    /// threads; begin them with SecondaryInheritance::begin. The same buffers are returned for an
+    /// image each time, so they may only be re-recorded once the image's previous frame has
+    /// finished, and they are freed when the graphics command buffers are regenerated.
+    pub unsafe fn get_secondary_command_buffers(
+        &self,
+        swapchain_image_index: usize,
+        count: usize
+    ) -> Result<Vec<vk::CommandBuffer>, EngineError> {
+        self.secondary_command_pools.buffers_for_image(
+            &self.device,
+            &self.graphics_queue,
+            swapchain_image_index,
+            count)
+    }
+
     pub unsafe fn recreate_surface<T>(
         &mut self,
         core: &VkCore,
@@ -252,6 +1310,10 @@ impl VkContext {
     ) -> Result<(), EngineError>
         where T: HasRawDisplayHandle + HasRawWindowHandle
     {
+        if self.is_headless() {
+            return Err(EngineError::UserError(
+                String::from("Headless contexts have no surface to recreate")));
+        }
         self.destroy_swapchain_resources();
         self.surface_fn.destroy_surface(self.surface, None);
         self.surface = ash_window::create_surface(
@@ -265,73 +1327,725 @@ impl VkContext {
         Ok(())
     }
 
-    // Increment current image number to focus on the next image in the chain, to wait for its
-    // synchronisation objects and so on.
+    /// Rebuild the swapchain for the existing surface, such as after a present reported it
+    /// suboptimal. As with recreate_surface, the device must be idle, and anything that refers to
+    /// the swapchain's images, such as framebuffers and command buffers, must be rebuilt after.
+    pub unsafe fn recreate_swapchain(&mut self, core: &VkCore) -> Result<(), EngineError> {
+        self.destroy_swapchain_resources();
+        self.create_swapchain(core)
+    }
+
+    /// Choose whether the swapchain should use an sRGB format where available, so that linear
+    /// color written by shaders is encoded to sRGB for display. This takes effect when the
+    /// swapchain is next created, such as by recreate_surface.
+    pub fn set_srgb_swapchain(&mut self, prefer_srgb: bool) {
+        self.prefer_srgb_swapchain = prefer_srgb;
+    }
+
+    /// Choose whether presented images should blend with what's behind the window, for windows
+    /// created transparent, where the surface supports it; the scene's clear color alpha then
+    /// sets how see-through the background is. This takes effect when the swapchain is next
+    /// created, such as by recreate_surface.
+    pub fn set_transparent_swapchain(&mut self, prefer_transparent: bool) {
+        self.prefer_transparent_swapchain = prefer_transparent;
+    }
+
+    /// Choose which end of the depth range is near the camera. Pipelines take their depth
+    /// comparison from this when created, so it should be set before creating any.
+    pub fn set_depth_convention(&mut self, convention: DepthConvention) {
+        self.depth_convention = convention;
+    }
+
+    /// Get the depth convention that pipelines are created with
+    pub fn get_depth_convention(&self) -> DepthConvention {
+        self.depth_convention
+    }
+
+    /// Set the most threads that create_graphics_pipelines may split a batch of pipelines
+    /// between; one, the default, creates them all on the calling thread
+    pub fn set_pipeline_creation_threads(&mut self, thread_count: usize) {
+        self.pipeline_creation_threads = thread_count.max(1);
+    }
+
+    /// Get the most threads that a batch of pipelines is created on
+    pub fn get_pipeline_creation_threads(&self) -> usize {
+        self.pipeline_creation_threads
+    }
+
+    /// Query whether the swapchain images are in an sRGB format, in which case shaders should
+    /// output linear color
+    pub fn is_swapchain_srgb(&self) -> bool {
+        matches!(
+            self.swapchain.get_surface_format().format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB)
+    }
+
+    /// Set how long to wait for a swapchain image before skipping the frame, and how many frames
+    /// in a row may be skipped that way before acquiring reports an error
+    pub fn set_acquire_timeout(&mut self, timeout: Duration, max_consecutive_timeouts: u32) {
+        self.acquire_timeout = timeout;
+        self.max_consecutive_acquire_timeouts = max_consecutive_timeouts.max(1);
+    }
+
+    // Advance to the next frame in flight, wait for the work last submitted for that frame to
+    // complete, then acquire an image while signalling the frame's semaphore. If the image is
+    // still in use by a different frame's work, that is waited on too.
     //
-    // Acquires an image while signalling a semaphore, then waits on a fence to know that the
-    // image is available to draw on.
-    pub unsafe fn acquire_next_image(&mut self) -> Result<(usize, bool), EngineError> {
-        let swapchain_size = self.get_swapchain_image_count();
-        let sync_objects_index = (self.current_image_acquired + 1) % swapchain_size;
+    // If no image becomes available within the acquire timeout, the frame should be skipped.
+    // After too many timeouts in a row, a Timeout error is returned; it can be recovered from
+    // by recreating the surface. Headless contexts simply take each frame's own image in turn.
+    pub unsafe fn acquire_next_image(&mut self) -> Result<AcquireResult, EngineError> {
+        let frame_count = self.frame_timeline_values.len();
+        let frame = (self.current_frame + 1) % frame_count;
+        self.mem_allocator.get_graphics_timeline()
+            .wait(&self.device, self.frame_timeline_values[frame])?;
+        self.release_one_time_graphics(false);
+
+        // The frame's previous work is complete, so its transient buffer can be reused; updates
+        // staged for a frame that was never submitted are dropped
+        self.staged_buffer_updates.lock().unwrap().clear();
+        if let Some(frame_transients) = self.frame_transients.lock().unwrap().as_mut() {
+            frame_transients.begin_frame(frame);
+        }
+
+        if self.is_headless() {
+            self.image_frames_in_flight[frame] = Some(frame);
+            self.current_frame = frame;
+            self.current_image_acquired = frame;
+            self.current_image_submitted = false;
+            return Ok(AcquireResult::Acquired(frame));
+        }
+
+        let timeout_nanos = u64::try_from(self.acquire_timeout.as_nanos()).unwrap_or(u64::MAX);
         let result = self.swapchain_fn.acquire_next_image(
             self.swapchain.get_swapchain(),
-            u64::MAX,
-            self.sync_image_available[sync_objects_index],
+            timeout_nanos,
+            self.sync_image_available[frame],
             vk::Fence::null());
-        let (image_index, _) = match result {
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok((0, false)),
-            Err(e) => return Err(EngineError::OpFailed(
-                format!("Image acquire failure: {:?}", e))),
-            Ok(t) => t
+        let image_index = match result {
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(AcquireResult::SwapchainOutOfDate),
+            Err(vk::Result::TIMEOUT) | Err(vk::Result::NOT_READY) => {
+                self.consecutive_acquire_timeouts += 1;
+                if self.consecutive_acquire_timeouts >= self.max_consecutive_acquire_timeouts {
+                    self.consecutive_acquire_timeouts = 0;
+                    return Err(EngineError::Timeout(format!(
+                        "No swapchain image acquired within {:?} for {} frames",
+                        self.acquire_timeout,
+                        self.max_consecutive_acquire_timeouts)));
+                }
+                return Ok(AcquireResult::TimedOut);
+            },
+            Err(e) => return Err(device_error("Image acquire failure", e)),
+            Ok((image_index, suboptimal)) => {
+                self.acquired_suboptimal = suboptimal;
+                image_index as usize
+            }
         };
-        self.current_image_acquired = image_index as usize;
-        assert_eq!(sync_objects_index, image_index as usize);
+        self.consecutive_acquire_timeouts = 0;
 
-        self.device.wait_for_fences(
-            &[self.sync_may_begin_rendering[self.current_image_acquired]],
-            true,
-            u64::MAX)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Waiting on fence error: {:?}", e))
-            })?;
-        self.device.reset_fences(&[self.sync_may_begin_rendering[self.current_image_acquired]])
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Resetting fence error: {:?}", e))
-            })?;
+        // Wait for any other frame still using this image, and drop stale references to this
+        // frame, whose timeline value will move on to work for a different image
+        if let Some(previous_frame) = self.image_frames_in_flight[image_index] {
+            if previous_frame != frame {
+                self.mem_allocator.get_graphics_timeline().wait(
+                    &self.device,
+                    self.frame_timeline_values[previous_frame])?;
+            }
+        }
+        for image_frame in self.image_frames_in_flight.iter_mut() {
+            if *image_frame == Some(frame) {
+                *image_frame = None;
+            }
+        }
+        self.image_frames_in_flight[image_index] = Some(frame);
+
+        self.current_frame = frame;
+        self.current_image_acquired = image_index;
+        self.current_image_submitted = false;
+        Ok(AcquireResult::Acquired(image_index))
+    }
 
-        Ok((self.current_image_acquired, true))
+    /// Update part of a buffer for the frame being prepared. The data is staged in the frame's
+    /// transient buffer and copied in on the graphics queue when the frame is submitted, ahead of
+    /// its commands, so the buffer needn't be host-visible. Outside of preparing a frame, such as
+    /// while loading, the buffer is updated straight away through BufferWrapper::update_range.
+    pub unsafe fn stage_buffer_update(
+        &self,
+        buffer: &BufferWrapper,
+        offset_bytes: usize,
+        data: &[u8]
+    ) -> Result<(), EngineError> {
+        if self.current_image_submitted {
+            return buffer.update_range(self, offset_bytes, data);
+        }
+        if offset_bytes + data.len() > buffer.size_bytes {
+            return Err(EngineError::EngineError(format!(
+                "Attempting to update buffer outside of range: offset {}, range {}, size {}",
+                offset_bytes,
+                data.len(),
+                buffer.size_bytes)))
+        }
+        let mut frame_transients = self.frame_transients.lock().unwrap();
+        let Some(frame_transients) = frame_transients.as_mut() else {
+            return Err(EngineError::UserError(
+                String::from("No transient buffers to stage a buffer update in")));
+        };
+        let update = frame_transients.stage_update(buffer.buffer, offset_bytes, data)?;
+        self.staged_buffer_updates.lock().unwrap().push(update);
+        Ok(())
     }
 
-    pub unsafe fn submit_and_present(&self) -> Result<PresentResult, EngineError> {
+    /// Wait until the device has finished any submitted work for the given swapchain image, so
+    /// that resources used only by that image's commands (such as its descriptor sets) can be
+    /// modified. The image currently being prepared has not been submitted, so is not waited on.
+    pub unsafe fn wait_for_swapchain_image_idle(
+        &self,
+        image_index: usize
+    ) -> Result<(), EngineError> {
+        if image_index == self.current_image_acquired && !self.current_image_submitted {
+            return Ok(());
+        }
+        let Some(Some(frame)) = self.image_frames_in_flight.get(image_index) else {
+            return Ok(());
+        };
+        self.mem_allocator.get_graphics_timeline()
+            .wait(&self.device, self.frame_timeline_values[*frame])
+    }
+
+    /// Copy one of a headless context's images back to host memory as tightly-packed RGBA
+    /// pixels, such as for tests; this is how headless frames are read back. The copy runs on the
+    /// graphics queue after the work already submitted, and is waited on. Images of a context
+    /// with a surface can't be read once presented, so fail here; capture those as part of their
+    /// frame with request_frame_capture instead.
+    pub unsafe fn capture_swapchain_image(&self, image_index: usize) -> Result<Vec<u8>, EngineError> {
+        if !self.is_headless() {
+            return Err(EngineError::UserError(String::from(
+                "Presented swapchain images can't be read back; use request_frame_capture")));
+        }
+        let mut capture = FrameCapture::record(
+            self,
+            self.swapchain.get_image(image_index)?,
+            self.swapchain.get_extent(),
+            self.swapchain.get_surface_format().format,
+            self.get_output_layout())?;
+        let submitted = self.mem_allocator.get_graphics_timeline().submit(
+            &self.device,
+            &self.graphics_queue,
+            &[capture.get_command_buffer()],
+            &[],
+            &[]);
+        match submitted {
+            Ok(timeline_value) => capture.set_timeline_value(timeline_value),
+            Err(e) => {
+                capture.release(self);
+                return Err(e);
+            }
+        }
+        capture.read_pixels(self)
+    }
+
+    /// Capture the next frame submitted through submit_and_present, copying its swapchain image
+    /// into host memory after rendering and before it is presented, such as for screenshots. The
+    /// pixels are collected with take_frame_capture. Fails if the surface does not support
+    /// copying from its images.
+    pub fn request_frame_capture(&mut self) -> Result<(), EngineError> {
+        if !self.swapchain.is_capturable() {
+            return Err(EngineError::Compatibility(
+                String::from("Surface does not support capturing swapchain images")));
+        }
+        self.capture_requested = true;
+        Ok(())
+    }
+
+    /// Collect the size and pixels of the frame last captured through request_frame_capture, as
+    /// tightly-packed RGBA, waiting for that frame's work to complete if it hasn't. Returns None
+    /// if no captured frame has been submitted since the last call. Must be called from the
+    /// thread that submits frames.
+    pub unsafe fn take_frame_capture(
+        &mut self
+    ) -> Result<Option<(vk::Extent2D, Vec<u8>)>, EngineError> {
+        match self.pending_capture.take() {
+            Some(capture) => {
+                let extent = capture.get_extent();
+                capture.read_pixels(self).map(|pixels| Some((extent, pixels)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Choose whether each frame submitted through submit_and_present has its render area copied
+    /// aside, so that the last of them can be stretched over a swapchain of another size through
+    /// present_retained_frame, such as while a live resize settles. Fails if the surface does not
+    /// support copying from and into its images. The copy is kept when the swapchain is
+    /// recreated, and is released with the swapchain's resources once retention is turned off.
+    pub fn set_frame_retention(&mut self, retain: bool) -> Result<(), EngineError> {
+        if retain && !(self.swapchain.is_capturable() && self.swapchain.is_presentable_by_copy()) {
+            return Err(EngineError::Compatibility(
+                String::from("Surface does not support copying between swapchain images")));
+        }
+        self.retain_frames = retain;
+        Ok(())
+    }
+
+    /// Whether a frame has been retained that present_retained_frame can present
+    pub fn has_retained_frame(&self) -> bool {
+        self.retained_frame.is_some()
+    }
+
+    /// Record the copy of the acquired swapchain image's render area into the retained frame,
+    /// first replacing the retained frame if the render area's size or format has changed
+    unsafe fn record_frame_retention(&mut self) -> Result<OneTimeCommands, EngineError> {
+        let render_area = self.get_swapchain_render_area();
+        let format = self.swapchain.get_surface_format().format;
+        let reusable = self.retained_frame.as_ref()
+            .map_or(false, |retained_frame| retained_frame.matches(format, render_area.extent));
+        if !reusable {
+            if let Some(previous) = self.retained_frame.take() {
+                // Earlier frames may still be copying into it or blitting from it
+                self.wait_until_device_idle()?;
+                previous.release(self);
+            }
+            self.retained_frame = Some(RetainedFrame::new(self, format, render_area.extent)?);
+            self.acquire_transferred_resources()?;
+        }
+        let swapchain_image = self.swapchain.get_image(self.current_image_acquired)?;
+        let output_layout = self.get_output_layout();
+        let retained_frame = self.retained_frame.as_ref().unwrap();
+        self.record_one_time_graphics(|command_buffer| {
+            retained_frame.record_copy(
+                &self.device,
+                command_buffer,
+                swapchain_image,
+                render_area,
+                output_layout);
+        })
+    }
+
+    /// Present the frame last retained through set_frame_retention to the swapchain image last
+    /// acquired through acquire_next_image, stretched over its render area with linear
+    /// filtering. As with present_image, this takes the place of recording commands and calling
+    /// submit_and_present. Frames presented this way are not themselves retained.
+    pub unsafe fn present_retained_frame(&mut self) -> Result<PresentResult, EngineError> {
+        let Some(retained_frame) = self.retained_frame.take() else {
+            return Err(EngineError::UserError(
+                String::from("No frame has been retained to present")));
+        };
+        let retain_frames = std::mem::replace(&mut self.retain_frames, false);
+        let presented = self.present_image(retained_frame.get_image());
+        self.retain_frames = retain_frames;
+        self.retained_frame = Some(retained_frame);
+        presented
+    }
+
+    /// Whether each present is identified, so that its arrival at the display can be queried
+    /// through present_status; see FeatureDeclaration::PresentWait
+    pub fn supports_present_wait(&self) -> bool {
+        self.present_wait_fn.is_some()
+    }
+
+    /// The identifier given to the most recent present, if presents are being identified. IDs
+    /// keep increasing across swapchain recreation.
+    pub fn get_last_present_id(&self) -> Option<u64> {
+        match self.present_wait_fn {
+            Some(_) if self.last_present_id > 0 => Some(self.last_present_id),
+            _ => None
+        }
+    }
+
+    /// Check, without blocking, whether the present with the given identifier has reached the
+    /// display. Presents queued before the swapchain was last recreated are reported as lost.
+    pub unsafe fn present_status(&self, present_id: u64) -> Result<PresentStatus, EngineError> {
+        let Some(present_wait_fn) = &self.present_wait_fn else {
+            return Err(EngineError::Compatibility(
+                String::from("Waiting for presents is not enabled on this device")));
+        };
+        if present_id < self.swapchain_first_present_id || present_id > self.last_present_id {
+            return Ok(PresentStatus::Lost);
+        }
+        match present_wait_fn.wait_for_present(self.swapchain.get_swapchain(), present_id, 0) {
+            Ok(_) => Ok(PresentStatus::Presented),
+            Err(vk::Result::TIMEOUT) => Ok(PresentStatus::Pending),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(PresentStatus::Lost),
+            Err(e) => Err(EngineError::OpFailed(format!("Error waiting for present: {:?}", e)))
+        }
+    }
+
+    /// Whether the current swapchain's images can be copied into, as present_image needs
+    pub fn supports_present_image(&self) -> bool {
+        self.swapchain.is_presentable_by_copy()
+    }
+
+    /// Present an image rendered without any scene, such as by a custom renderer, to the
+    /// swapchain image last acquired through acquire_next_image. The image is scaled to fill the
+    /// render area, with the rest of the surface cleared to black, and is left ready to be read
+    /// by transfers. The acquired image's graphics command buffer is re-recorded to do this, so
+    /// this takes the place of recording commands and calling submit_and_present. The image must
+    /// have been created with a usage that can be copied from, such as an offscreen render
+    /// target, dynamic texture or compute-written texture, and must not be modified until the
+    /// frame's work has completed.
+    pub unsafe fn present_image(
+        &mut self,
+        source: &ImageWrapper
+    ) -> Result<PresentResult, EngineError> {
+        if !self.supports_present_image() {
+            return Err(EngineError::Compatibility(
+                String::from("Surface does not support copying images into its swapchain")));
+        }
+        if self.current_image_submitted {
+            return Err(EngineError::UserError(
+                String::from("No swapchain image acquired to present into")));
+        }
+        if self.graphics_command_buffers.is_empty() {
+            self.regenerate_graphics_command_buffers()?;
+        }
 
-        // Submit graphics work
         let command_buffer = self.graphics_command_buffers[self.current_image_acquired];
-        let sync_image_available = self.sync_image_available[self.current_image_acquired];
-        let sync_may_begin_rendering = self.sync_may_begin_rendering[self.current_image_acquired];
-        let sync_rendering_finished = self.sync_rendering_finished[self.current_image_acquired];
-        self.graphics_queue.submit_graphics_command_buffer(
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &begin_info)
+            .map_err(|e| EngineError::OpFailed(format!("{:?}", e)))?;
+        self.cmd_begin_label(command_buffer, "Copy to swapchain image");
+        self.record_copy_to_swapchain_image(command_buffer, source)?;
+        self.cmd_end_label(command_buffer);
+        self.device.end_command_buffer(command_buffer)
+            .map_err(|e| EngineError::OpFailed(format!("{:?}", e)))?;
+
+        self.submit_and_present()
+    }
+
+    /// Record a blit from the source image into the render area of the acquired swapchain
+    /// image, leaving it ready to present; see record_blit_to_swapchain_image
+    unsafe fn record_copy_to_swapchain_image(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        source: &ImageWrapper
+    ) -> Result<(), EngineError> {
+        record_blit_to_swapchain_image(
             &self.device,
             command_buffer,
-            sync_image_available,
-            sync_may_begin_rendering,
-            sync_rendering_finished)?;
+            source,
+            self.swapchain.get_image(self.current_image_acquired)?,
+            self.swapchain.get_extent(),
+            self.get_output_layout(),
+            self.get_swapchain_render_area(),
+            vk::Filter::LINEAR);
+        Ok(())
+    }
+
+    pub unsafe fn submit_and_present(&mut self) -> Result<PresentResult, EngineError> {
+
+        // Submit graphics work, waiting for the image to be available and for async compute work
+        // where given, at the stage that first needs its results. Headless frames have no image
+        // to wait for and nothing to present; the frame's timeline value is all.
+        let mut command_buffers = vec![self.graphics_command_buffers[self.current_image_acquired]];
+        let mut waits = vec![];
+        let mut signal_semaphores = vec![];
+        if !self.is_headless() {
+            waits.push(SemaphoreWait::binary(
+                self.sync_image_available[self.current_frame],
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+            signal_semaphores.push(self.sync_rendering_finished[self.current_image_acquired]);
+        }
+        let pending_compute_wait = self.pending_compute_wait.lock().unwrap().take();
+        if let Some(pending) = &pending_compute_wait {
+            let compute_finished = self.sync_compute_finished[self.current_frame];
+            waits.push(SemaphoreWait::binary(compute_finished, pending.stage));
+        }
+
+        // Where a capture was requested, copy the image out in the same submission, after the
+        // frame's own commands and before the image is presented
+        let mut capture = None;
+        if self.capture_requested {
+            self.capture_requested = false;
+            let recorded = FrameCapture::record(
+                self,
+                self.swapchain.get_image(self.current_image_acquired)?,
+                self.swapchain.get_extent(),
+                self.swapchain.get_surface_format().format,
+                self.get_output_layout())?;
+            command_buffers.push(recorded.get_command_buffer());
+            capture = Some(recorded);
+        }
+
+        // Before the frame's own commands, copy in the buffer updates staged for it and, where
+        // async compute was handed resources, take them back ahead of the stage that waits for it
+        let staged_updates = std::mem::take(&mut *self.staged_buffer_updates.lock().unwrap());
+        let handoff = match pending_compute_wait {
+            Some(PendingComputeWait { stage, handoff: Some(handoff) }) => Some((stage, handoff)),
+            _ => None
+        };
+        let mut preamble_commands = None;
+        if !staged_updates.is_empty() || handoff.is_some() {
+            let recorded = self.flush_frame_transients().and_then(|_| {
+                self.record_one_time_graphics(|command_buffer| {
+                    record_staged_updates(&self.device, command_buffer, &staged_updates);
+                    if let Some((stage, handoff)) = &handoff {
+                        handoff.record_acquire(&self.device, command_buffer, *stage);
+                    }
+                })
+            });
+            match recorded {
+                Ok(preamble) => {
+                    command_buffers.insert(0, preamble.command_buffer);
+                    preamble_commands = Some(preamble);
+                },
+                Err(e) => {
+                    if let Some(capture) = capture {
+                        capture.release(self);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        // Where frames are retained, copy this one aside after its own commands, so that it can
+        // be stretched over the swapchain should it be rebuilt at another size
+        let mut retain_commands = None;
+        if self.retain_frames {
+            match self.record_frame_retention() {
+                Ok(recorded) => {
+                    command_buffers.push(recorded.command_buffer);
+                    retain_commands = Some(recorded);
+                },
+                Err(e) => {
+                    if let Some(capture) = capture {
+                        capture.release(self);
+                    }
+                    if let Some(preamble) = preamble_commands {
+                        preamble.discard(&self.device);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let submitted = self.mem_allocator.get_graphics_timeline().submit(
+            &self.device,
+            &self.graphics_queue,
+            &command_buffers,
+            &waits,
+            &signal_semaphores);
+        let timeline_value = match submitted {
+            Ok(timeline_value) => timeline_value,
+            Err(e) => {
+                if let Some(capture) = capture {
+                    capture.release(self);
+                }
+                if let Some(preamble) = preamble_commands {
+                    preamble.discard(&self.device);
+                }
+                if let Some(retain) = retain_commands {
+                    retain.discard(&self.device);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(preamble) = preamble_commands {
+            self.keep_one_time_graphics(preamble, timeline_value);
+        }
+        if let Some(retain) = retain_commands {
+            self.keep_one_time_graphics(retain, timeline_value);
+        }
+        if let Some(mut capture) = capture {
+            capture.set_timeline_value(timeline_value);
+            if let Some(previous) = self.pending_capture.replace(capture) {
+                previous.release(self);
+            }
+        }
+        self.frame_timeline_values[self.current_frame] = timeline_value;
+        self.current_image_submitted = true;
+        self.validation_errors.check();
+        if self.is_headless() {
+            return Ok(PresentResult::Ok);
+        }
 
         // Present image
         let semaphores_finished = [self.sync_rendering_finished[self.current_image_acquired]];
         let swapchains = [self.swapchain.get_swapchain()];
         let indices = [self.current_image_acquired as u32];
-        let present_info = vk::PresentInfoKHR::builder()
+        let mut present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(&semaphores_finished)
             .swapchains(&swapchains)
             .image_indices(&indices);
+        let present_ids = [self.last_present_id + 1];
+        let mut present_id_info = vk::PresentIdKHR::builder()
+            .present_ids(&present_ids);
+        if self.present_wait_fn.is_some() {
+            present_info = present_info.push_next(&mut present_id_info);
+            self.last_present_id += 1;
+        }
         let present_result = self.swapchain_fn
             .queue_present(self.graphics_queue.get_queue(), &present_info);
         return match present_result {
+            Ok(suboptimal) if suboptimal || self.acquired_suboptimal => {
+                Ok(PresentResult::Suboptimal)
+            },
             Ok(_) => Ok(PresentResult::Ok),
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 Ok(PresentResult::SwapchainOutOfDate)
             },
-            Err(e) => Err(EngineError::OpFailed(format!("Present error: {:?}", e)))
+            Err(e) => Err(device_error("Present error", e))
         };
     }
 }
+
+/// Describe a failed device operation as an error, reporting a lost device as DeviceLost rather
+/// than OpFailed, since it is only recoverable by recreating the device and everything on it
+pub(crate) fn device_error(description: &str, result: vk::Result) -> EngineError {
+    match result {
+        vk::Result::ERROR_DEVICE_LOST => {
+            EngineError::DeviceLost(format!("{}: {:?}", description, result))
+        },
+        _ => EngineError::OpFailed(format!("{}: {:?}", description, result))
+    }
+}
+
+/// The largest area of the given aspect ratio that fits in the extent, centred within it
+pub(crate) fn letterbox_area(extent: vk::Extent2D, aspect_ratio: f32) -> vk::Rect2D {
+    let extent_ratio = extent.width as f32 / extent.height as f32;
+    let area_extent = match extent_ratio > aspect_ratio {
+        true => vk::Extent2D {
+            width: ((extent.height as f32 * aspect_ratio).round() as u32).min(extent.width),
+            height: extent.height
+        },
+        false => vk::Extent2D {
+            width: extent.width,
+            height: ((extent.width as f32 / aspect_ratio).round() as u32).min(extent.height)
+        }
+    };
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: ((extent.width - area_extent.width) / 2) as i32,
+            y: ((extent.height - area_extent.height) / 2) as i32
+        },
+        extent: area_extent
+    }
+}
+
+/// An extent scaled by the given factor, rounded to whole pixels and at least one in each direction
+pub(crate) fn scaled_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * scale).round() as u32).max(1),
+        height: ((extent.height as f32 * scale).round() as u32).max(1)
+    }
+}
+
+/// Record a blit from the source image into the render area of a swapchain image, leaving it in
+/// the output layout, filtered as given where the sizes differ. The swapchain image's previous
+/// contents are discarded, and anything outside the render area is cleared to black; its
+/// transition waits on the same stage that the acquire semaphore is waited on, so happens after
+/// the image is available.
+pub(crate) unsafe fn record_blit_to_swapchain_image(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    source: &ImageWrapper,
+    swapchain_image: vk::Image,
+    swapchain_extent: vk::Extent2D,
+    output_layout: vk::ImageLayout,
+    render_area: vk::Rect2D,
+    filter: vk::Filter
+) {
+    let color_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1
+    };
+    let color_layers = vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1
+    };
+
+    source.transition(device, command_buffer, ImageAccess::TransferRead);
+    let to_transfer_barrier = vk::ImageMemoryBarrier::builder()
+        .image(swapchain_image)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(color_range)
+        .build();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[to_transfer_barrier]);
+
+    // Clear around the render area if letterboxed; the blit covers the rest
+    if render_area.extent != swapchain_extent {
+        device.cmd_clear_color_image(
+            command_buffer,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
+            &[color_range]);
+        let clear_to_blit_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[clear_to_blit_barrier],
+            &[],
+            &[]);
+    }
+
+    let source_extent = source.get_extent();
+    let blit = vk::ImageBlit {
+        src_subresource: color_layers,
+        src_offsets: [
+            vk::Offset3D { x: 0, y: 0, z: 0 },
+            vk::Offset3D {
+                x: source_extent.width as i32,
+                y: source_extent.height as i32,
+                z: 1
+            }
+        ],
+        dst_subresource: color_layers,
+        dst_offsets: [
+            vk::Offset3D { x: render_area.offset.x, y: render_area.offset.y, z: 0 },
+            vk::Offset3D {
+                x: render_area.offset.x + render_area.extent.width as i32,
+                y: render_area.offset.y + render_area.extent.height as i32,
+                z: 1
+            }
+        ]
+    };
+    device.cmd_blit_image(
+        command_buffer,
+        source.image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        swapchain_image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[blit],
+        filter);
+
+    let to_present_barrier = vk::ImageMemoryBarrier::builder()
+        .image(swapchain_image)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::empty())
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(output_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(color_range)
+        .build();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[to_present_barrier]);
+}
@@ -1,8 +1,33 @@
 
 /// PresentResult enumeration
-/// Possible outcomes of a presentation action.
+/// Possible outcomes of a presentation action. A suboptimal frame was still presented, but the
+/// swapchain no longer matches the surface exactly, so it should be rebuilt when convenient; see
+/// VkContext::recreate_swapchain.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum PresentResult {
     Ok,
-    SwapchainOutOfDate
+    Suboptimal,
+    SwapchainOutOfDate,
+    FrameSkipped
+}
+
+/// AcquireResult enumeration
+/// Possible outcomes of acquiring the next swapchain image. When no image is acquired, nothing
+/// should be submitted for the frame.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AcquireResult {
+    Acquired(usize),
+    SwapchainOutOfDate,
+    TimedOut
+}
+
+/// PresentStatus enumeration
+/// Whether an identified present has reached the display, as far as can be told without
+/// blocking. A present is lost if the swapchain it was queued to has since been replaced, or was
+/// out of date, in which case it will never be reported as presented.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PresentStatus {
+    Presented,
+    Pending,
+    Lost
 }
@@ -1,4 +1,4 @@
-
+use crate::SemaphoreWait;
 use error::EngineError;
 use ash::{
     Device,
@@ -86,6 +86,34 @@ impl Queue {
             })
     }
 
+    /// Create a secondary command buffer for this queue's family, in a pool of its own so that it
+    /// can be recorded on any thread while others are. Destroying the pool frees the buffer.
+    pub unsafe fn create_secondary_command_buffer(
+        &self,
+        device: &Device
+    ) -> Result<(vk::CommandPool, vk::CommandBuffer), EngineError> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(self.queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = device
+            .create_command_pool(&pool_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating secondary command pool: {:?}", e))
+            })?;
+        let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::SECONDARY)
+            .command_buffer_count(1);
+        match device.allocate_command_buffers(&command_buffer_alloc_info) {
+            Ok(command_buffers) => Ok((command_pool, command_buffers[0])),
+            Err(e) => {
+                device.destroy_command_pool(command_pool, None);
+                Err(EngineError::OpFailed(
+                    format!("Error allocating secondary command buffer: {:?}", e)))
+            }
+        }
+    }
+
     pub unsafe fn submit_transfer_command_buffer(
         &self,
         device: &Device,
@@ -105,35 +133,52 @@ impl Queue {
         Ok(())
     }
 
-    pub unsafe fn submit_graphics_command_buffer(
+    /// Submit a command buffer that signals a semaphore when done, as well as the fence, so that
+    /// another queue can wait for it, such as the graphics queue for async compute work. The
+    /// submission first waits on the given binary semaphores, if any.
+    pub unsafe fn submit_signalling_command_buffer(
         &self,
         device: &Device,
         command_buffer: vk::CommandBuffer,
-        sync_image_available: vk::Semaphore,
-        sync_may_begin_rendering: vk::Fence,
-        sync_rendering_finished: vk::Semaphore
+        waits: &[SemaphoreWait],
+        signal_semaphore: vk::Semaphore,
+        fence: vk::Fence
     ) -> Result<(), EngineError> {
-        let semaphores_available = [sync_image_available];
-        let waiting_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let semaphores_finished = [sync_rendering_finished];
         let command_buffers = [command_buffer];
+        let wait_semaphores: Vec<vk::Semaphore> = waits.iter()
+            .map(|wait| wait.semaphore)
+            .collect();
+        let wait_stages: Vec<vk::PipelineStageFlags> = waits.iter()
+            .map(|wait| wait.stage)
+            .collect();
+        let signal_semaphores = [signal_semaphore];
         let submit_info = [vk::SubmitInfo::builder()
-            .wait_semaphores(&semaphores_available)
-            .wait_dst_stage_mask(&waiting_stages)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
             .command_buffers(&command_buffers)
-            .signal_semaphores(&semaphores_finished)
+            .signal_semaphores(&signal_semaphores)
             .build()];
-        device.queue_submit(
-            self.queue,
-            &submit_info,
-            sync_may_begin_rendering
-        )
+        device.queue_submit(self.queue, &submit_info, fence)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Queue submit error: {:?}", e))
             })?;
         Ok(())
     }
 
+    pub unsafe fn submit_sparse_bind(
+        &self,
+        device: &Device,
+        bind_info: &vk::BindSparseInfo,
+        fence: &vk::Fence
+    ) -> Result<(), EngineError> {
+        device
+            .queue_bind_sparse(self.queue, &[*bind_info], *fence)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error submitting sparse binding: {:?}", e))
+            })?;
+        Ok(())
+    }
+
     pub unsafe fn free_command_buffer(&self, device: &Device, command_buffer: vk::CommandBuffer) {
         device.free_command_buffers(
             self.command_buffer_pool,
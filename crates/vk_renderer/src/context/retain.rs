@@ -0,0 +1,134 @@
+use crate::{VkContext, ImageWrapper, ImageAccess};
+use ecs::resource::Resource;
+use error::EngineError;
+use ash::{Device, vk};
+
+/// RetainedFrame struct
+/// A copy of the render area of the last frame presented, kept across swapchain recreation so
+/// that it can be stretched over the images of a swapchain of another size, such as while a live
+/// resize settles and nothing has yet been rebuilt to render at the new size. The copy is made
+/// in the same submission as the frame, after its rendering and before it is presented.
+pub(crate) struct RetainedFrame {
+    image: ImageWrapper
+}
+
+impl RetainedFrame {
+
+    /// Create the image frames are copied into, sized to the render area of the swapchain
+    pub unsafe fn new(
+        context: &VkContext,
+        format: vk::Format,
+        extent: vk::Extent2D
+    ) -> Result<Self, EngineError> {
+        let image = ImageWrapper::new_transfer_image(context, format, extent.width, extent.height)?;
+        image.set_debug_name(context, "Retained frame");
+        Ok(Self { image })
+    }
+
+    /// Whether frames of the given format and render area size can be copied in as they are
+    pub fn matches(&self, format: vk::Format, extent: vk::Extent2D) -> bool {
+        self.image.format == format && self.image.get_extent() == extent
+    }
+
+    /// The image holding the last frame copied in
+    pub fn get_image(&self) -> &ImageWrapper {
+        &self.image
+    }
+
+    /// Record the copy of the render area of a swapchain image in the given output layout, which
+    /// it is returned to afterwards
+    pub unsafe fn record_copy(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+        render_area: vk::Rect2D,
+        output_layout: vk::ImageLayout
+    ) {
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+        let color_layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        // Wait for rendering into the swapchain image, whether by render pass or by transfer,
+        // and for any earlier blit out of the retained image
+        let to_transfer_barrier = vk::ImageMemoryBarrier::builder()
+            .image(swapchain_image)
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(output_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(color_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer_barrier]);
+        self.image.transition(device, command_buffer, ImageAccess::TransferWrite);
+
+        let region = vk::ImageCopy {
+            src_subresource: color_layers,
+            src_offset: vk::Offset3D {
+                x: render_area.offset.x,
+                y: render_area.offset.y,
+                z: 0
+            },
+            dst_subresource: color_layers,
+            dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            extent: vk::Extent3D {
+                width: render_area.extent.width,
+                height: render_area.extent.height,
+                depth: 1
+            }
+        };
+        device.cmd_copy_image(
+            command_buffer,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.image.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region]);
+
+        // Return the swapchain image to its output layout; the retained image is moved on by
+        // whatever next uses it
+        let to_output_barrier = vk::ImageMemoryBarrier::builder()
+            .image(swapchain_image)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(output_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(color_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_output_barrier]);
+    }
+
+    /// Release the image; the device must have finished with it
+    pub fn release(self, context: &VkContext) {
+        self.image.release(context);
+    }
+}
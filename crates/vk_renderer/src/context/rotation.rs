@@ -0,0 +1,93 @@
+use ash::vk;
+
+/// PreRotation enumeration
+/// How the display is rotated relative to the surface's native orientation, such as when an
+/// Android device is turned on its side. The swapchain is created pre-transformed by this
+/// rotation, so the compositor presents its images without rotating them itself, which would
+/// otherwise cost a full-screen pass every frame. Scenes compensate by applying the clip-space
+/// matrix after their projection, and by projecting with the aspect ratio of the oriented extent,
+/// which is what the user sees, rather than that of the swapchain.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum PreRotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270
+}
+
+impl PreRotation {
+
+    /// The rotation for a surface transform, or None for transforms that also mirror the image
+    pub fn from_transform(transform: vk::SurfaceTransformFlagsKHR) -> Option<Self> {
+        match transform {
+            vk::SurfaceTransformFlagsKHR::IDENTITY => Some(PreRotation::Identity),
+            vk::SurfaceTransformFlagsKHR::ROTATE_90 => Some(PreRotation::Rotate90),
+            vk::SurfaceTransformFlagsKHR::ROTATE_180 => Some(PreRotation::Rotate180),
+            vk::SurfaceTransformFlagsKHR::ROTATE_270 => Some(PreRotation::Rotate270),
+            _ => None
+        }
+    }
+
+    /// The transform to create a swapchain with, given the surface's current transform and those
+    /// it supports: the current one if it is a plain rotation, so that the compositor needn't
+    /// rotate images itself, or otherwise a supported transform without mirroring, preferring
+    /// the identity. Only if the surface supports none of those is the current one kept.
+    pub fn choose_transform(
+        current: vk::SurfaceTransformFlagsKHR,
+        supported: vk::SurfaceTransformFlagsKHR
+    ) -> vk::SurfaceTransformFlagsKHR {
+        if Self::from_transform(current).is_some() {
+            return current;
+        }
+        [
+            vk::SurfaceTransformFlagsKHR::IDENTITY,
+            vk::SurfaceTransformFlagsKHR::ROTATE_90,
+            vk::SurfaceTransformFlagsKHR::ROTATE_180,
+            vk::SurfaceTransformFlagsKHR::ROTATE_270
+        ]
+            .into_iter()
+            .find(|transform| supported.contains(*transform))
+            .unwrap_or(current)
+    }
+
+    /// The surface transform that the swapchain is created with for this rotation
+    pub fn transform_flags(&self) -> vk::SurfaceTransformFlagsKHR {
+        match self {
+            PreRotation::Identity => vk::SurfaceTransformFlagsKHR::IDENTITY,
+            PreRotation::Rotate90 => vk::SurfaceTransformFlagsKHR::ROTATE_90,
+            PreRotation::Rotate180 => vk::SurfaceTransformFlagsKHR::ROTATE_180,
+            PreRotation::Rotate270 => vk::SurfaceTransformFlagsKHR::ROTATE_270
+        }
+    }
+
+    /// Whether the rotation is a quarter turn, so that width and height are swapped on display
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(self, PreRotation::Rotate90 | PreRotation::Rotate270)
+    }
+
+    /// The extent of the swapchain's images as the user sees them once rotated
+    pub fn oriented_extent(&self, extent: vk::Extent2D) -> vk::Extent2D {
+        match self.swaps_dimensions() {
+            true => vk::Extent2D { width: extent.height, height: extent.width },
+            false => extent
+        }
+    }
+
+    /// Column-major matrix rotating clip-space positions about the view axis by this rotation,
+    /// to be multiplied after the projection matrix, such as by cgmath's Matrix4::from
+    pub fn clip_space_matrix(&self) -> [[f32; 4]; 4] {
+        let (cos, sin) = match self {
+            PreRotation::Identity => (1.0, 0.0),
+            PreRotation::Rotate90 => (0.0, 1.0),
+            PreRotation::Rotate180 => (-1.0, 0.0),
+            PreRotation::Rotate270 => (0.0, -1.0)
+        };
+        [
+            [cos, sin, 0.0, 0.0],
+            [-sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ]
+    }
+}
@@ -1,5 +1,5 @@
 
-use crate::{VkCore, VkContext, ImageWrapper, ImageUsage, TexturePixelFormat};
+use crate::{VkCore, VkContext, ImageWrapper, ImageUsage, PreRotation, TexturePixelFormat};
 use ecs::resource::Resource;
 use error::EngineError;
 use ash::{
@@ -15,10 +15,19 @@ use std::cmp::max;
 pub const MIN_SWAPCHAIN_SIZE: u32 = 2;
 pub const MAX_SWAPCHAIN_SIZE: u32 = 3;
 
+/// Number of off-screen images standing in for the swapchain of a headless context
+pub const HEADLESS_SWAPCHAIN_SIZE: usize = 2;
+
 pub struct SwapchainWrapper {
     swapchain: vk::SwapchainKHR,
     surface_format: vk::SurfaceFormatKHR,
+    extent: vk::Extent2D,
+    capturable: bool,
+    presentable_by_copy: bool,
+    pre_rotation: PreRotation,
+    images: Vec<vk::Image>,
     image_views: Vec<vk::ImageView>,
+    headless_images: Vec<ImageWrapper>,
     depth_image: Option<ImageWrapper>
 }
 
@@ -28,7 +37,13 @@ impl Default for SwapchainWrapper {
         SwapchainWrapper {
             swapchain: vk::SwapchainKHR::null(),
             surface_format: vk::SurfaceFormatKHR::default(),
+            extent: vk::Extent2D::default(),
+            capturable: false,
+            presentable_by_copy: false,
+            pre_rotation: PreRotation::Identity,
+            images: vec![],
             image_views: vec![],
+            headless_images: vec![],
             depth_image: None
         }
     }
@@ -41,23 +56,29 @@ impl SwapchainWrapper {
         context: &VkContext,
         surface_fn: &Surface,
         surface: vk::SurfaceKHR,
-        extent: vk::Extent2D
+        extent: vk::Extent2D,
+        prefer_srgb: bool,
+        prefer_transparent: bool
     ) -> Result<SwapchainWrapper, EngineError> {
-        let (swapchain, surface_format) = Self::create_swapchain(
-            core,
-            surface_fn,
-            surface,
-            &context.swapchain_fn,
-            vk::SwapchainKHR::null())?;
-        let image_views =
+        let (swapchain, surface_format, capturable, presentable_by_copy, pre_rotation) =
+            Self::create_swapchain(
+                core,
+                surface_fn,
+                surface,
+                &context.swapchain_fn,
+                vk::SwapchainKHR::null(),
+                prefer_srgb,
+                prefer_transparent)?;
+        let (images, image_views) =
             Self::create_swapchain_image_views(
                 &context.device,
                 &context.swapchain_fn,
-                swapchain)?;
+                swapchain,
+                surface_format.format)?;
         let depth_image = ImageWrapper::new(
             context,
             ImageUsage::DepthBuffer,
-            TexturePixelFormat::Unorm16,
+            context.get_depth_format(),
             extent.width as u32,
             extent.height as u32,
             None)?;
@@ -65,7 +86,77 @@ impl SwapchainWrapper {
         Ok(SwapchainWrapper {
             swapchain,
             surface_format,
+            extent,
+            capturable,
+            presentable_by_copy,
+            pre_rotation,
+            images,
             image_views,
+            headless_images: vec![],
+            depth_image: Some(depth_image)
+        })
+    }
+
+    /// Create off-screen images in place of a swapchain, for a headless context. They are RGBA,
+    /// sRGB-encoded if preferred, and can always be copied into and captured from.
+    pub unsafe fn new_headless(
+        context: &VkContext,
+        extent: vk::Extent2D,
+        prefer_srgb: bool
+    ) -> Result<SwapchainWrapper, EngineError> {
+        let format = match prefer_srgb {
+            true => TexturePixelFormat::Srgba,
+            false => TexturePixelFormat::Rgba
+        };
+        let mut headless_images = vec![];
+        for _ in 0..HEADLESS_SWAPCHAIN_SIZE {
+            let image = ImageWrapper::new(
+                context,
+                ImageUsage::HeadlessOutput,
+                format,
+                extent.width,
+                extent.height,
+                None);
+            match image {
+                Ok(image) => headless_images.push(image),
+                Err(e) => {
+                    for image in headless_images.iter() {
+                        image.release(context);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let depth_image = ImageWrapper::new(
+            context,
+            ImageUsage::DepthBuffer,
+            context.get_depth_format(),
+            extent.width,
+            extent.height,
+            None);
+        let depth_image = match depth_image {
+            Ok(image) => image,
+            Err(e) => {
+                for image in headless_images.iter() {
+                    image.release(context);
+                }
+                return Err(e);
+            }
+        };
+
+        Ok(SwapchainWrapper {
+            swapchain: vk::SwapchainKHR::null(),
+            surface_format: vk::SurfaceFormatKHR {
+                format: format.vk_format(),
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR
+            },
+            extent,
+            capturable: true,
+            presentable_by_copy: true,
+            pre_rotation: PreRotation::Identity,
+            images: headless_images.iter().map(|image| image.image).collect(),
+            image_views: headless_images.iter().map(|image| image.image_view).collect(),
+            headless_images,
             depth_image: Some(depth_image)
         })
     }
@@ -74,6 +165,12 @@ impl SwapchainWrapper {
         if let Some(image) = &self.depth_image {
             image.release(context);
         }
+        if !self.headless_images.is_empty() {
+            for image in self.headless_images.iter() {
+                image.release(context);
+            }
+            return;
+        }
         for image_view in self.image_views.iter() {
             context.device.destroy_image_view(*image_view, None);
         }
@@ -84,6 +181,25 @@ impl SwapchainWrapper {
         self.surface_format
     }
 
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Whether the images can be copied from, for capturing what was presented
+    pub fn is_capturable(&self) -> bool {
+        self.capturable
+    }
+
+    /// Whether the images can be copied into, for presenting images rendered elsewhere
+    pub fn is_presentable_by_copy(&self) -> bool {
+        self.presentable_by_copy
+    }
+
+    /// The rotation that the images are presented with, which rendering must compensate for
+    pub fn get_pre_rotation(&self) -> PreRotation {
+        self.pre_rotation
+    }
+
     pub fn get_image_count(&self) -> usize {
         self.image_views.len()
     }
@@ -95,6 +211,12 @@ impl SwapchainWrapper {
         Ok(self.image_views[index])
     }
 
+    pub fn get_image(&self, index: usize) -> Result<vk::Image, EngineError> {
+        self.images.get(index)
+            .copied()
+            .ok_or_else(|| EngineError::EngineError(format!("Bad swapchain index: {}", index)))
+    }
+
     pub fn get_depth_image(&self) -> Option<&ImageWrapper> {
         match &self.depth_image {
             Some(image) => Some(image),
@@ -106,26 +228,53 @@ impl SwapchainWrapper {
         self.swapchain
     }
 
-    /// Create a swapchain; ensures that it is supported by the device and surface
+    /// Create a swapchain; ensures that it is supported by the device and surface. Rotations of
+    /// the display are pre-applied, while transforms that mirror are left to the compositor
+    /// where it can do them.
     unsafe fn create_swapchain(
         core: &VkCore,
         surface_fn: &Surface,
         surface: vk::SurfaceKHR,
         swapchain_fn: &Swapchain,
-        previous_swapchain: vk::SwapchainKHR
-    ) -> Result<(vk::SwapchainKHR, vk::SurfaceFormatKHR), EngineError> {
+        previous_swapchain: vk::SwapchainKHR,
+        prefer_srgb: bool,
+        prefer_transparent: bool
+    ) -> Result<
+        (vk::SwapchainKHR, vk::SurfaceFormatKHR, bool, bool, PreRotation),
+        EngineError
+    > {
 
         // Check for support and get some known-supported parameters
         let (
             min_image_count,
             current_extent,
-            current_transform
+            current_transform,
+            supported_transforms,
+            supported_usage,
+            supported_composite_alpha
         ) = Self::validate_basic_requirements(
             core,
             surface_fn,
             surface)?;
         let present_mode = Self::choose_present_mode(core.physical_device, surface_fn, surface)?;
-        let surface_format = Self::choose_surface_format(core.physical_device, surface_fn, surface)?;
+        let surface_format = Self::choose_surface_format(
+            core.physical_device,
+            surface_fn,
+            surface,
+            prefer_srgb)?;
+
+        // Allow capturing presented images where the surface supports copying from them, and
+        // presenting images rendered elsewhere where it supports copying into them
+        let capturable = supported_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC);
+        let presentable_by_copy = supported_usage.contains(vk::ImageUsageFlags::TRANSFER_DST);
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | (supported_usage &
+            (vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST));
+
+        // Pre-rotate to the display's current orientation, so the compositor needn't rotate
+        let pre_transform =
+            PreRotation::choose_transform(current_transform, supported_transforms);
+        let pre_rotation = PreRotation::from_transform(pre_transform)
+            .unwrap_or(PreRotation::Identity);
 
         // Create the swapchain
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
@@ -134,10 +283,10 @@ impl SwapchainWrapper {
             .image_color_space(surface_format.color_space)
             .image_format(surface_format.format)
             .image_extent(current_extent)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .pre_transform(pre_transform)
+            .composite_alpha(choose_composite_alpha(supported_composite_alpha, prefer_transparent))
             .present_mode(present_mode)
             .clipped(true)
             .image_array_layers(1)
@@ -147,15 +296,16 @@ impl SwapchainWrapper {
                 EngineError::OpFailed(format!("{:?}", e))
             })?;
 
-        Ok((swapchain, surface_format))
+        Ok((swapchain, surface_format, capturable, presentable_by_copy, pre_rotation))
     }
 
     /// Create the image views for the swapchain
     unsafe fn create_swapchain_image_views(
         device: &Device,
         swapchain_fn: &Swapchain,
-        swapchain: vk::SwapchainKHR
-    ) -> Result<Vec<vk::ImageView>, EngineError> {
+        swapchain: vk::SwapchainKHR,
+        format: vk::Format
+    ) -> Result<(Vec<vk::Image>, Vec<vk::ImageView>), EngineError> {
         // Make the image views over the images
         let swapchain_images = swapchain_fn.get_swapchain_images(swapchain)
             .map_err(|e| {
@@ -172,7 +322,7 @@ impl SwapchainWrapper {
                 let image_view_create_info = vk::ImageViewCreateInfo::builder()
                     .image(*image)
                     .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(vk::Format::B8G8R8A8_UNORM)
+                    .format(format)
                     .subresource_range(*subresource_range);
                 device.create_image_view(&image_view_create_info, None)
                     .map_err(|e| {
@@ -182,7 +332,7 @@ impl SwapchainWrapper {
                     .unwrap()
             })
             .collect();
-        Ok(image_views)
+        Ok((swapchain_images, image_views))
     }
 
     /// Validates that the physical device and surface supported everything needed
@@ -190,7 +340,17 @@ impl SwapchainWrapper {
         core: &VkCore,
         surface_fn: &Surface,
         surface: vk::SurfaceKHR
-    ) -> Result<(u32, vk::Extent2D, vk::SurfaceTransformFlagsKHR), EngineError> {
+    ) -> Result<
+        (
+            u32,
+            vk::Extent2D,
+            vk::SurfaceTransformFlagsKHR,
+            vk::SurfaceTransformFlagsKHR,
+            vk::ImageUsageFlags,
+            vk::CompositeAlphaFlagsKHR
+        ),
+        EngineError
+    > {
         let physical_device = core.physical_device;
         let graphics_queue_family_index = core.graphics_queue_family_index;
 
@@ -223,7 +383,10 @@ impl SwapchainWrapper {
         Ok((
             images_to_request,
             surface_capabilities.current_extent,
-            surface_capabilities.current_transform
+            surface_capabilities.current_transform,
+            surface_capabilities.supported_transforms,
+            surface_capabilities.supported_usage_flags,
+            surface_capabilities.supported_composite_alpha
         ))
     }
 
@@ -246,11 +409,14 @@ impl SwapchainWrapper {
         Ok(vk::PresentModeKHR::FIFO)
     }
 
-    /// Select a supported surface format
+    /// Select a supported surface format, in the sRGB color space. An sRGB format, which encodes
+    /// linear shader output to sRGB when written, is chosen if preferred and available; otherwise
+    /// a UNORM format, to which shader output is written unchanged.
     unsafe fn choose_surface_format(
         physical_device: vk::PhysicalDevice,
         surface_fn: &Surface,
-        surface: vk::SurfaceKHR
+        surface: vk::SurfaceKHR,
+        prefer_srgb: bool
     ) -> Result<vk::SurfaceFormatKHR, EngineError> {
         let surface_formats = surface_fn
             .get_physical_device_surface_formats(physical_device, surface)
@@ -261,13 +427,43 @@ impl SwapchainWrapper {
             return Err(EngineError::OpFailed(
                 String::from("No surface formats supported")));
         }
-        let index_of_desired = surface_formats.iter().position(|f| {
-            f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR && f.format == vk::Format::B8G8R8A8_UNORM
-        });
-        let format: vk::SurfaceFormatKHR = match index_of_desired {
-            Some(i) => surface_formats[i],
-            None => *surface_formats.first().unwrap()
+        let srgb_formats = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB];
+        let unorm_formats = [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM];
+        let desired_formats: Vec<vk::Format> = match prefer_srgb {
+            true => srgb_formats.into_iter().chain(unorm_formats).collect(),
+            false => unorm_formats.into_iter().chain(srgb_formats).collect()
         };
-        Ok(format)
+        let format = desired_formats.iter()
+            .find_map(|desired| surface_formats.iter().find(|f| {
+                f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR && f.format == *desired
+            }))
+            .unwrap_or(&surface_formats[0]);
+        Ok(*format)
     }
 }
+
+/// How the compositor should treat the alpha of presented images. A transparent window blends
+/// with whatever is behind it, using the first of the blending modes that the surface supports;
+/// otherwise, or if none is supported, images are opaque where the surface allows.
+pub(crate) fn choose_composite_alpha(
+    supported: vk::CompositeAlphaFlagsKHR,
+    prefer_transparent: bool
+) -> vk::CompositeAlphaFlagsKHR {
+    let blending = [
+        vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+    ];
+    let opaque = [
+        vk::CompositeAlphaFlagsKHR::OPAQUE,
+        vk::CompositeAlphaFlagsKHR::INHERIT
+    ];
+    let preferred: &[vk::CompositeAlphaFlagsKHR] = match prefer_transparent {
+        true => &blending,
+        false => &[]
+    };
+    preferred.iter()
+        .chain(opaque.iter())
+        .copied()
+        .find(|mode| supported.contains(*mode))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE)
+}
@@ -0,0 +1,264 @@
+use crate::{Queue, context::device_error};
+use error::EngineError;
+use ash::{Device, Instance, extensions::khr::TimelineSemaphore, vk};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// SemaphoreWait struct
+/// A semaphore that a submission waits on before the given stages may run. The value is only
+/// used for timeline semaphores, such as that of another queue's SubmissionTimeline, so that one
+/// queue can wait for another's work without the host waiting in between.
+#[derive(Copy, Clone, Debug)]
+pub struct SemaphoreWait {
+    pub semaphore: vk::Semaphore,
+    pub value: u64,
+    pub stage: vk::PipelineStageFlags
+}
+
+impl SemaphoreWait {
+
+    /// Wait on a binary semaphore, such as one signalled by acquiring a swapchain image
+    pub fn binary(semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) -> Self {
+        Self { semaphore, value: 0, stage }
+    }
+}
+
+/// TimelineState struct
+/// The last values submitted and seen to complete and, without a timeline semaphore, the fences
+/// signalled by submissions not yet seen to complete, in submission order
+struct TimelineState {
+    last_submitted: u64,
+    last_completed: u64,
+    in_flight_fences: VecDeque<(u64, vk::Fence)>,
+    spare_fences: Vec<vk::Fence>
+}
+
+/// SubmissionTimeline struct
+/// Tracks completion of the work submitted to one queue as a value that each submission advances
+/// by one, so that waiting for a value waits for that submission and all before it. Where the
+/// device supports VK_KHR_timeline_semaphore, the value is that of a single timeline semaphore
+/// signalled by every submission, so nothing is created per submission, and other queues can
+/// wait on it; see device_wait. Otherwise each submission signals a fence from a pool, which
+/// fences return to once they are seen to have signalled.
+pub struct SubmissionTimeline {
+    timeline_fn: Option<TimelineSemaphore>,
+    semaphore: vk::Semaphore,
+    state: Mutex<TimelineState>
+}
+
+impl SubmissionTimeline {
+
+    /// Create a new instance, with a timeline semaphore if use_timeline_semaphore is set, in
+    /// which case the device must have VK_KHR_timeline_semaphore and its feature enabled
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &Device,
+        use_timeline_semaphore: bool
+    ) -> Result<Self, EngineError> {
+        let (timeline_fn, semaphore) = match use_timeline_semaphore {
+            true => {
+                let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+                    .semaphore_type(vk::SemaphoreType::TIMELINE)
+                    .initial_value(0);
+                let create_info = vk::SemaphoreCreateInfo::builder()
+                    .push_next(&mut type_info);
+                let semaphore = device.create_semaphore(&create_info, None)
+                    .map_err(|e| {
+                        EngineError::OpFailed(
+                            format!("Error creating timeline semaphore: {:?}", e))
+                    })?;
+                (Some(TimelineSemaphore::new(instance, device)), semaphore)
+            },
+            false => (None, vk::Semaphore::null())
+        };
+        Ok(Self {
+            timeline_fn,
+            semaphore,
+            state: Mutex::new(TimelineState {
+                last_submitted: 0,
+                last_completed: 0,
+                in_flight_fences: VecDeque::new(),
+                spare_fences: vec![]
+            })
+        })
+    }
+
+    /// Query whether this timeline is backed by a timeline semaphore rather than fences
+    pub fn uses_timeline_semaphore(&self) -> bool {
+        self.timeline_fn.is_some()
+    }
+
+    /// The value that the most recent submission signals; zero before any submission
+    pub fn last_submitted_value(&self) -> u64 {
+        self.state.lock().unwrap().last_submitted
+    }
+
+    /// A wait for submissions to another queue to make before the given stages, so that they run
+    /// after this timeline reaches the value. None without a timeline semaphore, in which case
+    /// the host must wait for the value before submitting the dependent work.
+    pub fn device_wait(&self, value: u64, stage: vk::PipelineStageFlags) -> Option<SemaphoreWait> {
+        self.timeline_fn.as_ref()
+            .map(|_| SemaphoreWait { semaphore: self.semaphore, value, stage })
+    }
+
+    /// Submit command buffers to the queue, waiting on and signalling the given semaphores as
+    /// well as advancing the timeline, and return the value the submission signals. Submissions
+    /// from different threads are serialised, but other submissions to the queue must not be
+    /// made at the same time.
+    pub unsafe fn submit(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        command_buffers: &[vk::CommandBuffer],
+        waits: &[SemaphoreWait],
+        signal_semaphores: &[vk::Semaphore]
+    ) -> Result<u64, EngineError> {
+        let mut state = self.state.lock().unwrap();
+        let value = state.last_submitted + 1;
+        let wait_semaphores: Vec<vk::Semaphore> = waits.iter()
+            .map(|wait| wait.semaphore)
+            .collect();
+        let wait_values: Vec<u64> = waits.iter()
+            .map(|wait| wait.value)
+            .collect();
+        let wait_stages: Vec<vk::PipelineStageFlags> = waits.iter()
+            .map(|wait| wait.stage)
+            .collect();
+        let mut signals = signal_semaphores.to_vec();
+        let mut signal_values = vec![0; signal_semaphores.len()];
+
+        // Signal the timeline semaphore along with the others, or take a fence to signal
+        let fence = match self.timeline_fn {
+            Some(_) => {
+                signals.push(self.semaphore);
+                signal_values.push(value);
+                vk::Fence::null()
+            },
+            None => match state.spare_fences.pop() {
+                Some(fence) => fence,
+                None => device.create_fence(&vk::FenceCreateInfo::default(), None)
+                    .map_err(|e| {
+                        EngineError::OpFailed(format!("Error creating fence: {:?}", e))
+                    })?
+            }
+        };
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(&signals);
+        if self.timeline_fn.is_some() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+        let result = device.queue_submit(queue.get_queue(), &[submit_info.build()], fence);
+        if let Err(e) = result {
+            if fence != vk::Fence::null() {
+                state.spare_fences.push(fence);
+            }
+            return Err(device_error("Error submitting to queue", e));
+        }
+        if fence != vk::Fence::null() {
+            state.in_flight_fences.push_back((value, fence));
+        }
+        state.last_submitted = value;
+        Ok(value)
+    }
+
+    /// Query whether the submission that signals the given value has completed, without waiting
+    pub unsafe fn is_complete(&self, device: &Device, value: u64) -> Result<bool, EngineError> {
+        if let Some(timeline_fn) = &self.timeline_fn {
+            let counter = timeline_fn.get_semaphore_counter_value(self.semaphore)
+                .map_err(|e| device_error("Error querying timeline semaphore", e))?;
+            return Ok(counter >= value);
+        }
+        let mut state = self.state.lock().unwrap();
+        while value > state.last_completed {
+            let Some((_, fence)) = state.in_flight_fences.front() else {
+                break;
+            };
+            let signalled = device.get_fence_status(*fence)
+                .map_err(|e| device_error("Error querying fence", e))?;
+            if !signalled {
+                break;
+            }
+            Self::retire_oldest_fence(device, &mut state)?;
+        }
+        Ok(value <= state.last_completed)
+    }
+
+    /// Wait for the submission that signals the given value, and all before it, to complete.
+    /// Values not yet submitted are an error, since nothing would signal them.
+    pub unsafe fn wait(&self, device: &Device, value: u64) -> Result<(), EngineError> {
+        if let Some(timeline_fn) = &self.timeline_fn {
+            if value > self.last_submitted_value() {
+                return Err(EngineError::UserError(
+                    format!("Timeline value {} has not been submitted", value)));
+            }
+            let semaphores = [self.semaphore];
+            let values = [value];
+            let wait_info = vk::SemaphoreWaitInfo::builder()
+                .semaphores(&semaphores)
+                .values(&values);
+            return timeline_fn.wait_semaphores(&wait_info, u64::MAX)
+                .map_err(|e| device_error("Error waiting for timeline semaphore", e));
+        }
+        let mut state = self.state.lock().unwrap();
+        if value > state.last_submitted {
+            return Err(EngineError::UserError(
+                format!("Timeline value {} has not been submitted", value)));
+        }
+        while value > state.last_completed {
+            let Some((_, fence)) = state.in_flight_fences.front() else {
+                break;
+            };
+            device.wait_for_fences(&[*fence], true, u64::MAX)
+                .map_err(|e| device_error("Error waiting for fence", e))?;
+            Self::retire_oldest_fence(device, &mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for everything submitted so far to complete
+    pub unsafe fn wait_all(&self, device: &Device) -> Result<(), EngineError> {
+        self.wait(device, self.last_submitted_value())
+    }
+
+    /// Return the oldest in-flight fence, which has signalled, to the pool
+    unsafe fn retire_oldest_fence(
+        device: &Device,
+        state: &mut TimelineState
+    ) -> Result<(), EngineError> {
+        let Some((value, fence)) = state.in_flight_fences.pop_front() else {
+            return Ok(());
+        };
+        device.reset_fences(&[fence])
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error resetting fence: {:?}", e))
+            })?;
+        state.spare_fences.push(fence);
+        state.last_completed = value;
+        Ok(())
+    }
+
+    /// Wait for outstanding submissions, then destroy the semaphore or fences. If the device has
+    /// been lost, the wait fails, but nothing more will complete, so everything is destroyed
+    /// regardless.
+    pub unsafe fn destroy(&self, device: &Device) {
+        if let Err(e) = self.wait_all(device) {
+            println!("Destroying timeline with submissions outstanding: {:?}", e);
+        }
+        let mut state = self.state.lock().unwrap();
+        let in_flight_fences: Vec<vk::Fence> = state.in_flight_fences.drain(..)
+            .map(|(_, fence)| fence)
+            .collect();
+        for fence in state.spare_fences.drain(..).chain(in_flight_fences) {
+            device.destroy_fence(fence, None);
+        }
+        if self.semaphore != vk::Semaphore::null() {
+            device.destroy_semaphore(self.semaphore, None);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use error::EngineError;
+use ash::{Instance, vk};
+use std::ffi::CStr;
+
+/// AdapterInfo struct
+/// A physical device visible to a core's instance, as listed by VkCore::get_adapters, such as
+/// for picking a secondary adapter to create an off-screen context on; see
+/// VkCore::new_headless_on_adapter. The UUID, where the device reports one, identifies the same
+/// device across instances; otherwise devices are matched by vendor and device IDs and name.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub device_uuid: Option<[u8; vk::UUID_SIZE]>
+}
+
+impl AdapterInfo {
+
+    /// Query whether this describes the same physical device as another, which may have been
+    /// listed by a different instance
+    pub fn is_same_device(&self, other: &AdapterInfo) -> bool {
+        match (self.device_uuid, other.device_uuid) {
+            (Some(uuid), Some(other_uuid)) => uuid == other_uuid,
+            _ => self.vendor_id == other.vendor_id &&
+                self.device_id == other.device_id &&
+                self.name == other.name
+        }
+    }
+
+    /// Describe the physical device, reading its UUID where the instance and device are both at
+    /// least Vulkan 1.1
+    pub(crate) unsafe fn query(
+        instance: &Instance,
+        instance_api_version: u32,
+        physical_device: vk::PhysicalDevice
+    ) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let name = CStr::from_ptr(properties.device_name.as_ptr())
+            .to_string_lossy()
+            .into_owned();
+        let device_uuid = match instance_api_version >= vk::API_VERSION_1_1 &&
+            properties.api_version >= vk::API_VERSION_1_1
+        {
+            true => {
+                let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+                let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+                    .push_next(&mut id_properties)
+                    .build();
+                instance.get_physical_device_properties2(physical_device, &mut properties2);
+                Some(id_properties.device_uuid)
+            },
+            false => None
+        };
+        Self {
+            name,
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            device_uuid
+        }
+    }
+}
+
+/// List the instance's physical devices, in the order the instance enumerates them, along with
+/// descriptions of each
+pub unsafe fn enumerate_adapters(
+    instance: &Instance,
+    instance_api_version: u32
+) -> Result<Vec<(vk::PhysicalDevice, AdapterInfo)>, EngineError> {
+    let physical_devices = instance.enumerate_physical_devices()
+        .map_err(|e| EngineError::OpFailed(format!("Error listing physical devices: {:?}", e)))?;
+    Ok(physical_devices.into_iter()
+        .map(|physical_device| {
+            (physical_device, AdapterInfo::query(instance, instance_api_version, physical_device))
+        })
+        .collect())
+}
@@ -1,4 +1,5 @@
 
+use crate::core::VkCoreConfig;
 use error::EngineError;
 use ash::{
     vk,
@@ -6,45 +7,247 @@ use ash::{
     Instance,
     extensions::ext::DebugUtils
 };
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
 
-/// Simple debug logger; calls println to display message with type and severity
+/// ValidationSeverity enum
+/// How serious a message from the validation layers is, from least to most
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default)]
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    #[default]
+    Warning,
+    Error
+}
+
+impl ValidationSeverity {
+
+    /// The severity of a message as reported to the messenger's callback
+    pub fn from_flags(flags: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            ValidationSeverity::Error
+        } else if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            ValidationSeverity::Warning
+        } else if flags.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            ValidationSeverity::Info
+        } else {
+            ValidationSeverity::Verbose
+        }
+    }
+
+    /// The flags for this severity and every one more serious, for filtering messages
+    pub fn flags_at_least(&self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        [
+            (ValidationSeverity::Verbose, vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE),
+            (ValidationSeverity::Info, vk::DebugUtilsMessageSeverityFlagsEXT::INFO),
+            (ValidationSeverity::Warning, vk::DebugUtilsMessageSeverityFlagsEXT::WARNING),
+            (ValidationSeverity::Error, vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        ]
+            .into_iter()
+            .filter(|(severity, _)| severity >= self)
+            .fold(vk::DebugUtilsMessageSeverityFlagsEXT::empty(), |flags, (_, flag)| flags | flag)
+    }
+}
+
+/// ValidationMessage struct
+/// A message from the validation layers, as passed to a ValidationMessageHandler
+#[derive(Clone, Debug)]
+pub struct ValidationMessage {
+    pub severity: ValidationSeverity,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub message: String
+}
+
+/// Receives messages from the validation layers, such as to pass them into an app's logging. It
+/// is called on whichever thread made the Vulkan call being reported.
+pub type ValidationMessageHandler = Arc<dyn Fn(&ValidationMessage) + Send + Sync>;
+
+/// ValidationErrors struct
+/// The first validation error reported since last checked, where the core is set to panic on
+/// them. The messenger's callback only records the error, since a panic can't unwind back through
+/// the driver; the panic is raised on checking, such as by contexts after each submission. Shared
+/// by a core and the contexts created from it.
+#[derive(Clone, Default)]
+pub(crate) struct ValidationErrors {
+    first_error: Arc<Mutex<Option<String>>>
+}
+
+impl ValidationErrors {
+
+    /// Record the error, unless one is already waiting to be raised
+    pub fn record(&self, message: &str) {
+        let mut first_error = self.first_error.lock().unwrap();
+        if first_error.is_none() {
+            *first_error = Some(message.to_owned());
+        }
+    }
+
+    /// Take the error recorded since last checked, if any
+    pub fn take(&self) -> Option<String> {
+        self.first_error.lock().unwrap().take()
+    }
+
+    /// Panic with the error recorded since last checked, if any
+    pub fn check(&self) {
+        if let Some(message) = self.take() {
+            panic!("Vulkan validation error: {}", message);
+        }
+    }
+}
+
+/// Where the messenger's callback sends messages; boxed, so that the pointer given to the
+/// callback stays valid for the life of the messenger
+struct MessageRouting {
+    handler: Option<ValidationMessageHandler>,
+    panic_on_error: bool,
+    errors: ValidationErrors
+}
+
+/// DebugMessenger struct
+/// The debug utils extension, along with the messenger through which validation layers report
+pub(crate) struct DebugMessenger {
+    pub debug_utils: DebugUtils,
+    pub messenger: vk::DebugUtilsMessengerEXT,
+    routing: Box<MessageRouting>
+}
+
+impl DebugMessenger {
+
+    /// The errors recorded for raising on the Rust side, where the core panics on them
+    pub fn get_validation_errors(&self) -> ValidationErrors {
+        self.routing.errors.clone()
+    }
+}
+
+/// Pass messages to the handler, or print them with their type and severity where there is no
+/// handler. Errors to panic on are only recorded here, as nothing may unwind out of this function;
+/// see ValidationErrors.
 unsafe extern "system" fn vulkan_debug_utils_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut std::ffi::c_void
+    p_user_data: *mut std::ffi::c_void
 ) -> vk::Bool32 {
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-    let severity = format!("{:?}", message_severity);
-    let ty = format!("{:?}", message_type);
-    println!("[Debug][{}][{}] {:?}", severity, ty, message);
+    let routing = &*(p_user_data as *const MessageRouting);
+    let message = ValidationMessage {
+        severity: ValidationSeverity::from_flags(message_severity),
+        message_type,
+        message: CStr::from_ptr((*p_callback_data).p_message).to_string_lossy().into_owned()
+    };
+    match &routing.handler {
+        Some(handler) => {
+            // A handler that panics, such as to fail a test, has its panic raised on checking
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| handler(&message)));
+            if handled.is_err() {
+                routing.errors.record(&message.message);
+            }
+        },
+        None => println!(
+            "[Debug][{:?}][{:?}] {:?}",
+            message_severity,
+            message_type,
+            message.message)
+    }
+    if routing.panic_on_error && message.severity == ValidationSeverity::Error {
+        routing.errors.record(&message.message);
+    }
     vk::FALSE
 }
 
-/// Construct a debug messenger; it will be in effect immediately
+/// Construct a debug messenger if validation is enabled; it will be in effect immediately
 pub unsafe fn make_debug_utils(
     entry: &Entry,
-    instance: &Instance
-) -> Result<Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>, EngineError> {
-    if cfg!(debug_assertions) {
-        let debug_utils = DebugUtils::new(entry, instance);
-        let debug_create_info = vk::DebugUtilsMessengerCreateInfoEXT {
-            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
-                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL |
-                vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE |
-                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-            pfn_user_callback: Some(vulkan_debug_utils_callback),
-            ..Default::default()
+    instance: &Instance,
+    config: &VkCoreConfig
+) -> Result<Option<DebugMessenger>, EngineError> {
+    if !config.validation {
+        return Ok(None);
+    }
+    let debug_utils = DebugUtils::new(entry, instance);
+    let routing = Box::new(MessageRouting {
+        handler: config.message_handler.clone(),
+        panic_on_error: config.panic_on_error,
+        errors: ValidationErrors::default()
+    });
+    let debug_create_info = vk::DebugUtilsMessengerCreateInfoEXT {
+        message_severity: config.min_severity.flags_at_least(),
+        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL |
+            vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE |
+            vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+        pfn_user_callback: Some(vulkan_debug_utils_callback),
+        p_user_data: &*routing as *const MessageRouting as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    let messenger = debug_utils
+        .create_debug_utils_messenger(&debug_create_info, None)
+        .map_err(|e| {
+            EngineError::OpFailed(format!("Debug messenger creation failed: {:?}", e))
+        })?;
+    Ok(Some(DebugMessenger { debug_utils, messenger, routing }))
+}
+
+/// DebugLabels struct
+/// Names objects and marks regions of command buffers for debugging tools such as RenderDoc,
+/// which show the names in place of raw handles and group the commands recorded between a label's
+/// begin and end. Only has an effect where the debug utils extension is loaded, which is where
+/// validation is enabled, by default in debug builds; otherwise it does nothing, so callers need
+/// not check. Cheap to clone, so scenes may
+/// keep one for labelling what they record; see VkContext::get_debug_labels.
+#[derive(Clone, Default)]
+pub struct DebugLabels {
+    debug_utils: Option<DebugUtils>
+}
+
+impl DebugLabels {
+
+    pub(crate) fn new(debug_utils: Option<DebugUtils>) -> Self {
+        Self { debug_utils }
+    }
+
+    /// Labels that never have an effect
+    pub fn disabled() -> Self {
+        Self { debug_utils: None }
+    }
+
+    /// Query whether names and labels are passed on to debugging tools
+    pub fn is_enabled(&self) -> bool {
+        self.debug_utils.is_some()
+    }
+
+    /// Record the start of a labelled region, which must be ended in the same command buffer
+    pub unsafe fn begin(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        let (Some(debug_utils), Ok(name)) = (&self.debug_utils, CString::new(name)) else {
+            return;
+        };
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name);
+        debug_utils.cmd_begin_debug_utils_label(command_buffer, &label);
+    }
+
+    /// Record the end of the most recently begun labelled region
+    pub unsafe fn end(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            debug_utils.cmd_end_debug_utils_label(command_buffer);
+        }
+    }
+
+    /// Name an object of the given device. Naming is only an aid to debugging, so failures are
+    /// ignored.
+    pub unsafe fn set_object_name<H: vk::Handle>(
+        &self,
+        device: vk::Device,
+        handle: H,
+        name: &str
+    ) {
+        let (Some(debug_utils), Ok(name)) = (&self.debug_utils, CString::new(name)) else {
+            return;
         };
-        let utils_messenger = debug_utils
-            .create_debug_utils_messenger(&debug_create_info, None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Debug messenger creation failed: {:?}", e))
-            })?;
-        Ok(Some((debug_utils, utils_messenger)))
-    } else {
-        Ok(None)
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+        let _ = debug_utils.set_debug_utils_object_name(device, &name_info);
     }
 }
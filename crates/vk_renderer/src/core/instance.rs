@@ -17,10 +17,34 @@ use std::{
 
 const DEBUG_LAYER_NAME: &'static str = "VK_LAYER_KHRONOS_validation";
 
-/// Creates the instance, enabling any required extensions and layers
+/// Newest Vulkan version whose core features the engine knows how to use
+pub const NEWEST_KNOWN_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+/// Vulkan version to create the instance for; see negotiate_api_version
+pub fn instance_api_version(entry: &Entry, max_api_version: u32) -> u32 {
+    let loader_version = entry.try_enumerate_instance_version().ok().flatten();
+    negotiate_api_version(loader_version, max_api_version)
+}
+
+/// The newest version that the loader supports, no newer than the given maximum nor than the
+/// newest the engine knows how to use, so that newer core features such as dynamic rendering and
+/// synchronization2 may be used where available. Loaders that cannot report a version only
+/// support 1.0. The patch version is dropped, as it does not affect which features exist.
+pub fn negotiate_api_version(loader_version: Option<u32>, max_api_version: u32) -> u32 {
+    let version = loader_version.unwrap_or(vk::API_VERSION_1_0)
+        .min(max_api_version)
+        .min(NEWEST_KNOWN_API_VERSION)
+        .max(vk::API_VERSION_1_0);
+    vk::make_api_version(0, vk::api_version_major(version), vk::api_version_minor(version), 0)
+}
+
+/// Creates the instance, enabling any required extensions and layers; without a display handle,
+/// no surface extensions are enabled, and without validation, no debugging ones
 pub unsafe fn make_instance(
     entry: &Entry,
-    display_handle: RawDisplayHandle
+    display_handle: Option<RawDisplayHandle>,
+    api_version: u32,
+    validation: bool
 ) -> Result<Instance, EngineError> {
 
     // App info
@@ -31,15 +55,23 @@ pub unsafe fn make_instance(
         .application_version(vk::make_api_version(0, 0, 1, 0))
         .engine_name(&engine_name)
         .engine_version(vk::make_api_version(0, 0, 0, 1))
-        .api_version(vk::make_api_version(0, 1, 0, 0));
+        .api_version(api_version);
 
     // Instance extensions and validation layers
-    let mut instance_extensions = get_debug_instance_extensions(entry)?;
-    let required_platform_extensions = get_window_instance_extensions(display_handle)?;
-    instance_extensions.extend(&required_platform_extensions);
+    let mut instance_extensions = get_debug_instance_extensions(entry, validation)?;
+    if let Some(display_handle) = display_handle {
+        let required_platform_extensions = get_window_instance_extensions(display_handle)?;
+        instance_extensions.extend(&required_platform_extensions);
+    }
+    let portability_extensions = get_portability_instance_extensions(entry)?;
+    let instance_flags = match portability_extensions.is_empty() {
+        true => vk::InstanceCreateFlags::empty(),
+        false => vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    };
+    instance_extensions.extend(&portability_extensions);
 
     // Validation layers
-    let debug_layers = get_debug_instance_layers(entry)?;
+    let debug_layers = get_debug_instance_layers(entry, validation)?;
     let layer_name_pointers: Vec<_> = debug_layers
         .iter()
         .map(|name| name.as_ptr())
@@ -47,6 +79,7 @@ pub unsafe fn make_instance(
 
     // Create the instance
     let instance_create_info = vk::InstanceCreateInfo::builder()
+        .flags(instance_flags)
         .application_info(&app_info)
         .enabled_extension_names(&instance_extensions)
         .enabled_layer_names(&layer_name_pointers);
@@ -72,9 +105,32 @@ fn get_window_instance_extensions(
     Ok(extensions_as_c_str)
 }
 
+/// Gets the extension for enumerating devices that only partly implement Vulkan, such as MoltenVK,
+/// where the loader supports it; without it, the loader hides those devices
+unsafe fn get_portability_instance_extensions(
+    entry: &Entry
+) -> Result<Vec<*const c_char>, EngineError> {
+    let portability_extension = vk::KhrPortabilityEnumerationFn::name();
+    let supported_extensions = entry.enumerate_instance_extension_properties(None)
+        .map_err(|e| {
+            EngineError::OpFailed(format!("Failed to enumerate instance extensions: {:?}", e))
+        })?;
+    let is_supported = supported_extensions
+        .iter()
+        .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()).eq(portability_extension));
+    if is_supported {
+        Ok(vec![portability_extension.as_ptr()])
+    } else {
+        Ok(vec![])
+    }
+}
+
 /// Gets the extensions required for debugging
-unsafe fn get_debug_instance_extensions(entry: &Entry) -> Result<Vec<*const c_char>, EngineError> {
-    if cfg!(debug_assertions) {
+unsafe fn get_debug_instance_extensions(
+    entry: &Entry,
+    validation: bool
+) -> Result<Vec<*const c_char>, EngineError> {
+    if validation {
         let debug_extension = DebugUtils::name();
         let supported_extensions = entry.enumerate_instance_extension_properties(None)
             .map_err(|e| {
@@ -94,8 +150,11 @@ unsafe fn get_debug_instance_extensions(entry: &Entry) -> Result<Vec<*const c_ch
 }
 
 /// Gets the instance layers for debugging
-unsafe fn get_debug_instance_layers(entry: &Entry) -> Result<Vec<CString>, EngineError> {
-    if cfg!(debug_assertions) {
+unsafe fn get_debug_instance_layers(
+    entry: &Entry,
+    validation: bool
+) -> Result<Vec<CString>, EngineError> {
+    if validation {
         let validation_layer = CString::new(DEBUG_LAYER_NAME).unwrap();
         let supported_extensions = entry.enumerate_instance_layer_properties()
             .map_err(|e| {
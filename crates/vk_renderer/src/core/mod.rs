@@ -1,37 +1,152 @@
 
-mod instance;
+mod adapter;
+pub(crate) mod instance;
 mod debug;
-mod physical_device;
+pub(crate) mod physical_device;
+mod portability;
 
+use crate::TexturePixelFormat;
 use error::EngineError;
 use ash::{
     Entry,
     Instance,
-    extensions::{
-        ext::DebugUtils,
-        khr::Surface
-    },
+    extensions::khr::Surface,
     vk
 };
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 
+pub use debug::{
+    DebugLabels, ValidationSeverity, ValidationMessage, ValidationMessageHandler
+};
+pub(crate) use debug::ValidationErrors;
+pub use portability::PortabilitySubset;
+pub use adapter::AdapterInfo;
+
 /// FeatureDeclaration enum
-/// Platform feature requirements that may be declared by an application or component thereof in
-/// advance, in case it's needed during initialisation.
+/// Platform features that may be declared by an application or component thereof in advance, in
+/// case they're needed during initialisation. Each declared feature is enabled only where the
+/// selected device supports it, rather than failing device creation where it doesn't; the core's
+/// FeatureReport says which were granted, so that scenes can adapt.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum FeatureDeclaration {
-    ClipPlanes // Vulkan - see VkPhysicalDeviceFeatures.shaderClipDistance
+    ClipPlanes, // Vulkan - see VkPhysicalDeviceFeatures.shaderClipDistance
+    SparseTextures, // Vulkan - sparseBinding and sparseResidencyImage2D, both needed
+    CompressedTextures, // Vulkan - textureCompressionBC, ETC2 and ASTC_LDR; granted if any are
+    SamplerAnisotropy, // Vulkan - samplerAnisotropy
+    DynamicRendering, // Vulkan 1.3 - dynamicRendering
+    PresentWait, // VK_KHR_present_id and VK_KHR_present_wait, with their features
+    FillModeNonSolid, // Vulkan - fillModeNonSolid, for line and point polygon modes
+    DescriptorIndexing, // VK_EXT_descriptor_indexing, for bindless texture arrays
+    WideLines, // Vulkan - wideLines, for line widths other than 1.0
+    ShaderFloat16, // Vulkan 1.2 - shaderFloat16, for 16-bit float arithmetic in shaders
+    MultiDrawIndirect // Vulkan - multiDrawIndirect, for more than one draw per indirect command
+}
+
+/// VkCoreConfig struct
+/// Options for creating a core. Validation enables the validation layers, where installed, and
+/// the debug utils extension, which also names objects and labels passes for debugging tools; it
+/// defaults to on in debug builds. Messages less serious than the minimum severity are dropped,
+/// and the rest go to the message handler, or are printed without one. Panicking on validation
+/// errors suits tests; the panic is raised from the next submission or wait for the device made
+/// through a context, or from VkCore::check_validation, rather than from within the driver's
+/// callback, where it couldn't unwind. A message handler that panics is treated the same way.
+/// The instance is created
+/// for the newest Vulkan version that the loader supports, up to the maximum; lowering it, such
+/// as to vk::API_VERSION_1_0, forces the code paths used where newer core features are missing.
+#[derive(Clone)]
+pub struct VkCoreConfig {
+    pub validation: bool,
+    pub min_severity: ValidationSeverity,
+    pub message_handler: Option<ValidationMessageHandler>,
+    pub panic_on_error: bool,
+    pub max_api_version: u32
+}
+
+impl Default for VkCoreConfig {
+    fn default() -> Self {
+        Self {
+            validation: cfg!(debug_assertions),
+            min_severity: ValidationSeverity::Warning,
+            message_handler: None,
+            panic_on_error: false,
+            max_api_version: instance::NEWEST_KNOWN_API_VERSION
+        }
+    }
+}
+
+/// FeatureReport struct
+/// Which of the declared features were granted on the selected device, and which were not. Each
+/// declared feature appears once, in the order first declared.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct FeatureReport {
+    granted: Vec<FeatureDeclaration>,
+    denied: Vec<FeatureDeclaration>
+}
+
+impl FeatureReport {
+
+    /// Sort the declared features by whether they were granted
+    pub fn new<F>(declared: &[FeatureDeclaration], is_granted: F) -> Self
+        where F: Fn(FeatureDeclaration) -> bool
+    {
+        let mut report = Self::default();
+        for feature in declared.iter() {
+            if report.granted.contains(feature) || report.denied.contains(feature) {
+                continue;
+            }
+            match is_granted(*feature) {
+                true => report.granted.push(*feature),
+                false => report.denied.push(*feature)
+            }
+        }
+        report
+    }
+
+    /// Query whether the feature was declared and granted
+    pub fn is_granted(&self, feature: FeatureDeclaration) -> bool {
+        self.granted.contains(&feature)
+    }
+
+    /// The declared features that are enabled on the device
+    pub fn get_granted(&self) -> &[FeatureDeclaration] {
+        &self.granted
+    }
+
+    /// The declared features that the device does not support, which are not enabled
+    pub fn get_denied(&self) -> &[FeatureDeclaration] {
+        &self.denied
+    }
 }
 
-/// Wrap Vulkan components that can exist for the life of the app once successfully created
+/// Wrap Vulkan components that can exist for the life of the app once successfully created. A
+/// headless core has no surface extensions, so can be created without a window or display, such
+/// as on CI machines or servers; its contexts render into off-screen images instead. Each core
+/// has an instance of its own, so more than one can exist at once, such as a headless core on a
+/// secondary adapter alongside the one presenting to the window.
 pub struct VkCore {
     pub function_loader: Entry,
     pub instance: Instance,
-    debug_utils: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    debug_messenger: Option<debug::DebugMessenger>,
     pub physical_device: vk::PhysicalDevice,
+    adapters: Vec<AdapterInfo>,
+    adapter_index: usize,
     pub graphics_queue_family_index: u32,
     pub transfer_queue_family_index: u32,
-    pub physical_device_features: vk::PhysicalDeviceFeatures
+    pub compute_queue_family_index: Option<u32>,
+    pub physical_device_features: vk::PhysicalDeviceFeatures,
+    api_version: u32,
+    headless: bool,
+    dynamic_rendering_enabled: bool,
+    synchronization2_enabled: bool,
+    maintenance4_enabled: bool,
+    present_wait_enabled: bool,
+    descriptor_indexing_enabled: bool,
+    shader_float16_enabled: bool,
+    timeline_semaphore_enabled: bool,
+    portability_subset: Option<PortabilitySubset>,
+    max_bindless_textures: u32,
+    msaa_sample_counts: vk::SampleCountFlags,
+    feature_report: FeatureReport
 }
 
 impl VkCore {
@@ -40,10 +155,23 @@ impl VkCore {
         window_owner: &W,
         features: Vec<FeatureDeclaration>
     ) -> Result<Self, EngineError> where W: HasRawDisplayHandle + HasRawWindowHandle {
+        Self::new_with_config(window_owner, features, VkCoreConfig::default())
+    }
+
+    /// Create a new instance, with non-default validation options
+    pub unsafe fn new_with_config<W>(
+        window_owner: &W,
+        features: Vec<FeatureDeclaration>,
+        config: VkCoreConfig
+    ) -> Result<Self, EngineError> where W: HasRawDisplayHandle + HasRawWindowHandle {
 
         let entry = Entry::linked();
-        let instance = instance::make_instance(&entry, window_owner.raw_display_handle())?;
-        let debug_utils = debug::make_debug_utils(&entry, &instance)?;
+        let api_version = instance::instance_api_version(&entry, config.max_api_version);
+        let instance = instance::make_instance(
+            &entry,
+            Some(window_owner.raw_display_handle()),
+            api_version,
+            config.validation)?;
 
         // Create temporary surface and surface loader
         let surface_fn = Surface::new(&entry, &instance);
@@ -55,34 +183,370 @@ impl VkCore {
             None)
             .unwrap();
 
-        // Now select a physical device
+        // Select a physical device that can present to it, then destroy the temporary surface
+        let core = Self::new_for_instance(
+            entry,
+            instance,
+            api_version,
+            Some((&surface_fn, surface)),
+            None,
+            features,
+            &config);
+        surface_fn.destroy_surface(surface, None);
+        core
+    }
+
+    /// Create a new instance without any surface extensions, for contexts created with
+    /// VkContext::new_headless. Devices are selected without regard to presenting, and
+    /// PresentWait is never enabled.
+    pub unsafe fn new_headless(features: Vec<FeatureDeclaration>) -> Result<Self, EngineError> {
+        Self::new_headless_with_config(features, VkCoreConfig::default())
+    }
+
+    /// Create a new headless instance, with non-default validation options
+    pub unsafe fn new_headless_with_config(
+        features: Vec<FeatureDeclaration>,
+        config: VkCoreConfig
+    ) -> Result<Self, EngineError> {
+        let entry = Entry::linked();
+        let api_version = instance::instance_api_version(&entry, config.max_api_version);
+        let instance = instance::make_instance(&entry, None, api_version, config.validation)?;
+        Self::new_for_instance(entry, instance, api_version, None, None, features, &config)
+    }
+
+    /// Create a new headless instance on the given adapter, as listed by get_adapters of any
+    /// core, such as for baking assets in the background on a secondary GPU. Contexts created
+    /// from it with VkContext::new_headless share nothing with other cores' contexts; results
+    /// are passed between them through host memory, such as with SharedImage.
+    pub unsafe fn new_headless_on_adapter(
+        features: Vec<FeatureDeclaration>,
+        adapter: &AdapterInfo,
+        config: VkCoreConfig
+    ) -> Result<Self, EngineError> {
+        let entry = Entry::linked();
+        let api_version = instance::instance_api_version(&entry, config.max_api_version);
+        let instance = instance::make_instance(&entry, None, api_version, config.validation)?;
+        let required_device = adapter::enumerate_adapters(&instance, api_version)?
+            .into_iter()
+            .find(|(_, info)| info.is_same_device(adapter))
+            .map(|(physical_device, _)| physical_device)
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("Adapter {} not found", adapter.name)))?;
+        Self::new_for_instance(
+            entry,
+            instance,
+            api_version,
+            None,
+            Some(required_device),
+            features,
+            &config)
+    }
+
+    /// Select a physical device, able to present to the surface if there is one, or the
+    /// required one if given, and work out which of the declared features are enabled on it
+    unsafe fn new_for_instance(
+        entry: Entry,
+        instance: Instance,
+        api_version: u32,
+        surface: Option<(&Surface, vk::SurfaceKHR)>,
+        required_device: Option<vk::PhysicalDevice>,
+        features: Vec<FeatureDeclaration>,
+        config: &VkCoreConfig
+    ) -> Result<Self, EngineError> {
+        let debug_messenger = debug::make_debug_utils(&entry, &instance, config)?;
+        let headless = surface.is_none();
         let (physical_device, graphics_queue_family_index, transfer_queue_family_index, physical_device_features) =
             physical_device::select_physical_device(
                 &instance,
-                &surface_fn,
-                &surface,
-                &features)?;
+                surface,
+                &features,
+                required_device)?;
+        let adapter_devices = adapter::enumerate_adapters(&instance, api_version)?;
+        let adapter_index = adapter_devices.iter()
+            .position(|(device, _)| *device == physical_device)
+            .unwrap_or(0);
+        let adapters = adapter_devices.into_iter()
+            .map(|(_, info)| info)
+            .collect();
 
-        // Destroy the temporary surface
-        surface_fn.destroy_surface(surface, None);
+        let vulkan_1_3_support =
+            physical_device::vulkan_1_3_support(&instance, api_version, physical_device);
+        let dynamic_rendering_enabled =
+            features.contains(&FeatureDeclaration::DynamicRendering) &&
+            vulkan_1_3_support.dynamic_rendering;
+        let present_wait_enabled = !headless &&
+            features.contains(&FeatureDeclaration::PresentWait) &&
+            physical_device::supports_present_wait(&instance, api_version, physical_device);
+        let descriptor_indexing_enabled =
+            features.contains(&FeatureDeclaration::DescriptorIndexing) &&
+            physical_device::supports_descriptor_indexing(&instance, api_version, physical_device);
+        let shader_float16_enabled =
+            features.contains(&FeatureDeclaration::ShaderFloat16) &&
+            physical_device::supports_shader_float16(&instance, api_version, physical_device);
+        let timeline_semaphore_enabled =
+            physical_device::supports_timeline_semaphore(&instance, api_version, physical_device);
+        let portability_subset =
+            physical_device::portability_subset(&instance, api_version, physical_device);
+        let compute_queue_family_index = physical_device::dedicated_compute_queue_family(
+            &instance,
+            physical_device,
+            transfer_queue_family_index);
+        let max_bindless_textures = match descriptor_indexing_enabled {
+            true => physical_device::max_bindless_textures(&instance, physical_device),
+            false => 0
+        };
+
+        // Multisampled passes need the same sample count for color and depth
+        let properties = instance.get_physical_device_properties(physical_device);
+        let device_api_version = properties.api_version;
+        let limits = properties.limits;
+        let msaa_sample_counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        let enabled = &physical_device_features;
+        let feature_report = FeatureReport::new(&features, |feature| match feature {
+            FeatureDeclaration::ClipPlanes => enabled.shader_clip_distance == vk::TRUE,
+            FeatureDeclaration::SparseTextures => enabled.sparse_binding == vk::TRUE,
+            FeatureDeclaration::CompressedTextures =>
+                enabled.texture_compression_bc == vk::TRUE ||
+                    enabled.texture_compression_etc2 == vk::TRUE ||
+                    enabled.texture_compression_astc_ldr == vk::TRUE,
+            FeatureDeclaration::SamplerAnisotropy => enabled.sampler_anisotropy == vk::TRUE,
+            FeatureDeclaration::DynamicRendering => dynamic_rendering_enabled,
+            FeatureDeclaration::PresentWait => present_wait_enabled,
+            FeatureDeclaration::FillModeNonSolid => enabled.fill_mode_non_solid == vk::TRUE,
+            FeatureDeclaration::DescriptorIndexing => descriptor_indexing_enabled,
+            FeatureDeclaration::WideLines => enabled.wide_lines == vk::TRUE,
+            FeatureDeclaration::ShaderFloat16 => shader_float16_enabled,
+            FeatureDeclaration::MultiDrawIndirect => enabled.multi_draw_indirect == vk::TRUE
+        });
 
         Ok(Self {
             function_loader: entry,
             instance,
-            debug_utils,
+            debug_messenger,
             physical_device,
+            adapters,
+            adapter_index,
             graphics_queue_family_index,
             transfer_queue_family_index,
-            physical_device_features
+            compute_queue_family_index,
+            physical_device_features,
+            api_version: api_version.min(device_api_version),
+            headless,
+            dynamic_rendering_enabled,
+            synchronization2_enabled: vulkan_1_3_support.synchronization2,
+            maintenance4_enabled: vulkan_1_3_support.maintenance4,
+            present_wait_enabled,
+            descriptor_indexing_enabled,
+            shader_float16_enabled,
+            timeline_semaphore_enabled,
+            portability_subset,
+            max_bindless_textures,
+            msaa_sample_counts,
+            feature_report
         })
     }
 
+    /// Every physical device visible to this core's instance, in the order it enumerates them
+    pub fn get_adapters(&self) -> &[AdapterInfo] {
+        &self.adapters
+    }
+
+    /// The physical device that this core selected
+    pub fn get_adapter(&self) -> &AdapterInfo {
+        &self.adapters[self.adapter_index]
+    }
+
+    /// Which of the declared features were granted on the selected device
+    pub fn get_feature_report(&self) -> &FeatureReport {
+        &self.feature_report
+    }
+
+    /// Query whether ShaderFloat16 was declared and is supported, in which case it is enabled on
+    /// the device
+    pub fn supports_shader_float16(&self) -> bool {
+        self.shader_float16_enabled
+    }
+
+    /// The Vulkan version in use, being the older of the instance's and the device's; core
+    /// features of newer versions are unavailable
+    pub fn get_api_version(&self) -> u32 {
+        self.api_version
+    }
+
+    /// Query whether the device supports synchronization2 as a Vulkan 1.3 core feature, in which
+    /// case it is enabled, and image transitions are recorded with vkCmdPipelineBarrier2
+    pub fn supports_synchronization2(&self) -> bool {
+        self.synchronization2_enabled
+    }
+
+    /// Query whether the device supports maintenance4 as a Vulkan 1.3 core feature, in which case
+    /// it is enabled, and memory requirements of images can be found without creating them
+    pub fn supports_maintenance4(&self) -> bool {
+        self.maintenance4_enabled
+    }
+
+    /// Query whether the device supports timeline semaphores, in which case their extension and
+    /// feature are enabled on the device, and queue submissions are tracked with them rather
+    /// than with fences; see SubmissionTimeline
+    pub fn supports_timeline_semaphore(&self) -> bool {
+        self.timeline_semaphore_enabled
+    }
+
+    /// What the device can do if it only partly implements Vulkan, such as through MoltenVK, in
+    /// which case VK_KHR_portability_subset is enabled on it; None for full implementations
+    pub fn get_portability_subset(&self) -> Option<PortabilitySubset> {
+        self.portability_subset
+    }
+
+    /// Query whether this core was created without surface extensions, by new_headless
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    /// Query whether DynamicRendering was declared and is supported, in which case it is enabled
+    /// on the device
+    pub fn supports_dynamic_rendering(&self) -> bool {
+        self.dynamic_rendering_enabled
+    }
+
+    /// Query whether PresentWait was declared and is supported, in which case its extensions are
+    /// enabled on the device
+    pub fn supports_present_wait(&self) -> bool {
+        self.present_wait_enabled
+    }
+
+    /// Query whether DescriptorIndexing was declared and is supported, in which case its
+    /// extension and the features needed by BindlessTextureSet are enabled on the device
+    pub fn supports_descriptor_indexing(&self) -> bool {
+        self.descriptor_indexing_enabled
+    }
+
+    /// The most textures that a BindlessTextureSet may hold, or zero without descriptor indexing
+    pub fn get_max_bindless_textures(&self) -> u32 {
+        self.max_bindless_textures
+    }
+
+    /// Labels for naming objects and marking command buffers, which only have an effect where
+    /// the debug utils extension was loaded
+    pub fn get_debug_labels(&self) -> DebugLabels {
+        DebugLabels::new(self.debug_messenger.as_ref().map(|debug| debug.debug_utils.clone()))
+    }
+
+    /// Errors recorded by the debug messenger for raising on the Rust side, shared with contexts
+    pub(crate) fn get_validation_errors(&self) -> debug::ValidationErrors {
+        self.debug_messenger.as_ref()
+            .map(|debug| debug.get_validation_errors())
+            .unwrap_or_default()
+    }
+
+    /// Panic with the first validation error reported since last checked, where the core was
+    /// configured to panic on them; contexts check after each submission
+    pub fn check_validation(&self) {
+        self.get_validation_errors().check();
+    }
+
+    /// Sample counts that color and depth attachments may both use in a renderpass
+    pub fn get_msaa_sample_counts(&self) -> vk::SampleCountFlags {
+        self.msaa_sample_counts
+    }
+
+    /// Choose the highest sample count supported for attachments that does not exceed the one
+    /// requested, such as 4 for 4x MSAA; see highest_sample_count
+    pub fn select_msaa_samples(&self, requested: u32) -> vk::SampleCountFlags {
+        highest_sample_count(self.msaa_sample_counts, requested)
+    }
+
+    /// Query whether textures in the given format can be created and sampled. Compressed formats
+    /// need their family of compression enabled, by declaring CompressedTextures, as well as
+    /// support for sampling the format itself.
+    pub fn supports_texture_format(&self, format: TexturePixelFormat) -> bool {
+        let features = &self.physical_device_features;
+        let family_enabled = match format {
+            TexturePixelFormat::Bc1 | TexturePixelFormat::Bc3 | TexturePixelFormat::Bc7 =>
+                features.texture_compression_bc,
+            TexturePixelFormat::Etc2Rgb | TexturePixelFormat::Etc2Rgba =>
+                features.texture_compression_etc2,
+            TexturePixelFormat::Astc4x4 => features.texture_compression_astc_ldr,
+            TexturePixelFormat::None | TexturePixelFormat::Rgba | TexturePixelFormat::Srgba |
+                TexturePixelFormat::RgbaF16 | TexturePixelFormat::Rgb10A2 |
+                TexturePixelFormat::Unorm16 | TexturePixelFormat::Depth24Stencil8 => vk::TRUE
+        };
+        if family_enabled != vk::TRUE {
+            return false;
+        }
+        let properties = unsafe {
+            self.instance.get_physical_device_format_properties(
+                self.physical_device,
+                format.vk_format())
+        };
+        properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    }
+
+    /// Query whether depth attachments can be created in the given depth format
+    pub fn supports_depth_format(&self, format: TexturePixelFormat) -> bool {
+        if !format.is_depth() {
+            return false;
+        }
+        let properties = unsafe {
+            self.instance.get_physical_device_format_properties(
+                self.physical_device,
+                format.vk_format())
+        };
+        properties.optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    }
+
+    /// Describe the selected device, its driver and supported API version, for diagnostics
+    pub fn describe_device(&self) -> String {
+        let properties = unsafe {
+            self.instance.get_physical_device_properties(self.physical_device)
+        };
+        let device_name = unsafe {
+            std::ffi::CStr::from_ptr(properties.device_name.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        };
+        format!(
+            "{} ({:?}, vendor 0x{:04x}, device 0x{:04x}), driver version 0x{:08x}, API {}.{}.{}",
+            device_name,
+            properties.device_type,
+            properties.vendor_id,
+            properties.device_id,
+            properties.driver_version,
+            vk::api_version_major(properties.api_version),
+            vk::api_version_minor(properties.api_version),
+            vk::api_version_patch(properties.api_version))
+    }
+
+    /// The compressed texture formats that can be used on this device
+    pub fn supported_compressed_formats(&self) -> Vec<TexturePixelFormat> {
+        TexturePixelFormat::COMPRESSED_FORMATS.iter()
+            .copied()
+            .filter(|format| self.supports_texture_format(*format))
+            .collect()
+    }
+
     pub fn teardown(&mut self) {
         unsafe {
-            if let Some((debug_utils, utils_messenger)) = &self.debug_utils {
-                debug_utils.destroy_debug_utils_messenger(*utils_messenger, None);
+            if let Some(debug) = &self.debug_messenger {
+                debug.debug_utils.destroy_debug_utils_messenger(debug.messenger, None);
             }
             self.instance.destroy_instance(None);
         }
     }
 }
+
+/// The highest of the supported sample counts that does not exceed the requested count, falling
+/// back to single sampling, which every device supports
+pub fn highest_sample_count(
+    supported: vk::SampleCountFlags,
+    requested: u32
+) -> vk::SampleCountFlags {
+    [64, 32, 16, 8, 4, 2].into_iter()
+        .filter(|count| *count <= requested)
+        .map(vk::SampleCountFlags::from_raw)
+        .find(|flag| supported.contains(*flag))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
@@ -1,53 +1,60 @@
 
-use crate::core::FeatureDeclaration;
+use crate::core::{FeatureDeclaration, PortabilitySubset};
 use error::EngineError;
-use ash::{vk, extensions::khr::Surface};
+use ash::{vk, extensions::khr::{Surface, PresentWait, TimelineSemaphore}};
+use std::ffi::CStr;
 
-/// Selects the physical device to use, so long as there is one that supports everything needed
+/// Selects the physical device to use, so long as there is one that supports everything needed.
+/// Without a surface, as for headless rendering, any graphics queue family will do. Where a
+/// device is required, only that one is considered.
 pub unsafe fn select_physical_device(
     instance: &ash::Instance,
-    surface_loader: &Surface,
-    surface: &vk::SurfaceKHR,
-    features: &[FeatureDeclaration]
+    surface: Option<(&Surface, vk::SurfaceKHR)>,
+    features: &[FeatureDeclaration],
+    required_device: Option<vk::PhysicalDevice>
 ) -> Result<(vk::PhysicalDevice, u32, u32, vk::PhysicalDeviceFeatures), EngineError> {
 
-    let physical_devices = instance
+    let mut physical_devices = instance
         .enumerate_physical_devices()
         .map_err(|e| {
             EngineError::OpFailed(format!("{:?}", e))
         })?;
+    if let Some(required_device) = required_device {
+        physical_devices.retain(|physical_device| *physical_device == required_device);
+    }
     if physical_devices.is_empty() {
         return Err(EngineError::OpFailed(
             String::from("No physical devices found")));
     }
 
+    // Devices are ranked by how many of the declared features they grant, taking the first
+    // enumerated where that is tied
     let unset_value: u32 = u32::MAX;
+    let mut best_device = None;
+    let mut best_granted_count = 0;
     for physical_device in physical_devices.iter() {
         let queue_family_properties =
             instance.get_physical_device_queue_family_properties(*physical_device);
+        let supported_features = instance.get_physical_device_features(*physical_device);
+        let features_to_enable = make_feature_set_to_enable(features, &supported_features);
         let mut graphics_index: u32 = unset_value;
         let mut transfer_index: u32 = unset_value;
-        let mut features_to_enable = vk::PhysicalDeviceFeatures::default();
         for (index, properties) in queue_family_properties.iter().enumerate() {
 
             let supports_graphics =
                 properties.queue_flags.contains(vk::QueueFlags::GRAPHICS);
-            let supports_surface = surface_loader
-                .get_physical_device_surface_support(
-                    *physical_device,
-                    index as u32,
-                    *surface)
-                .unwrap();
+            let supports_surface = match surface {
+                Some((surface_loader, surface)) => surface_loader
+                    .get_physical_device_surface_support(
+                        *physical_device,
+                        index as u32,
+                        surface)
+                    .unwrap(),
+                None => true
+            };
             let supports_transfer =
                 properties.queue_flags.contains(vk::QueueFlags::TRANSFER);
 
-            let supported_features =
-                instance.get_physical_device_features(*physical_device);
-            features_to_enable = match make_feature_set_to_enable(features, &supported_features) {
-                Some(features) => features,
-                None => continue
-            };
-
             let graphics_and_surface = supports_graphics && supports_surface;
             if graphics_and_surface {
                 graphics_index = index as u32;
@@ -56,8 +63,13 @@ pub unsafe fn select_physical_device(
                 transfer_index = index as u32;
             }
         }
-        if graphics_index != unset_value && transfer_index != unset_value {
-            return Ok((
+        if graphics_index == unset_value || transfer_index == unset_value {
+            continue;
+        }
+        let granted_count = granted_feature_count(features, &features_to_enable);
+        if best_device.is_none() || granted_count > best_granted_count {
+            best_granted_count = granted_count;
+            best_device = Some((
                 *physical_device,
                 graphics_index,
                 transfer_index,
@@ -66,27 +78,297 @@ pub unsafe fn select_physical_device(
         }
     }
 
-    Err(EngineError::OpFailed(
+    best_device.ok_or_else(|| EngineError::OpFailed(
         String::from("Could not find a suitable physical device")))
 }
 
-/// Return set of features to enable during device creation, knowing that all of those features
-/// are supported by the physical device. If they are not all supported, this returns None.
-fn make_feature_set_to_enable(
+/// Count the declared features that the set to enable grants, for ranking devices. Only the
+/// Vulkan 1.0 features are counted; those of extensions and newer versions are checked once a
+/// device has been selected.
+pub(crate) fn granted_feature_count(
+    features: &[FeatureDeclaration],
+    enabled: &vk::PhysicalDeviceFeatures
+) -> usize {
+    features.iter()
+        .filter(|feature| match feature {
+            FeatureDeclaration::ClipPlanes => enabled.shader_clip_distance == vk::TRUE,
+            FeatureDeclaration::SparseTextures => enabled.sparse_binding == vk::TRUE,
+            FeatureDeclaration::CompressedTextures =>
+                enabled.texture_compression_bc == vk::TRUE ||
+                    enabled.texture_compression_etc2 == vk::TRUE ||
+                    enabled.texture_compression_astc_ldr == vk::TRUE,
+            FeatureDeclaration::SamplerAnisotropy => enabled.sampler_anisotropy == vk::TRUE,
+            FeatureDeclaration::FillModeNonSolid => enabled.fill_mode_non_solid == vk::TRUE,
+            FeatureDeclaration::WideLines => enabled.wide_lines == vk::TRUE,
+            FeatureDeclaration::MultiDrawIndirect => enabled.multi_draw_indirect == vk::TRUE,
+            FeatureDeclaration::DynamicRendering |
+            FeatureDeclaration::PresentWait |
+            FeatureDeclaration::DescriptorIndexing |
+            FeatureDeclaration::ShaderFloat16 => false
+        })
+        .count()
+}
+
+/// Return set of features to enable during device creation, being those declared that are
+/// supported by the physical device; declared features that aren't supported are left disabled
+pub(crate) fn make_feature_set_to_enable(
     features: &[FeatureDeclaration],
     supported_features: &vk::PhysicalDeviceFeatures
-) -> Option<vk::PhysicalDeviceFeatures> {
+) -> vk::PhysicalDeviceFeatures {
     let mut features_to_enable = vk::PhysicalDeviceFeatures::default();
     for feature in features.iter() {
         match feature {
             FeatureDeclaration::ClipPlanes => {
-                if supported_features.shader_clip_distance == vk::TRUE {
-                    features_to_enable.shader_clip_distance = vk::TRUE;
-                } else {
-                    return None;
+                features_to_enable.shader_clip_distance = supported_features.shader_clip_distance;
+            },
+            FeatureDeclaration::SparseTextures => {
+                if supported_features.sparse_binding == vk::TRUE &&
+                    supported_features.sparse_residency_image2_d == vk::TRUE {
+                    features_to_enable.sparse_binding = vk::TRUE;
+                    features_to_enable.sparse_residency_image2_d = vk::TRUE;
                 }
+            },
+            FeatureDeclaration::CompressedTextures => {
+                features_to_enable.texture_compression_bc =
+                    supported_features.texture_compression_bc;
+                features_to_enable.texture_compression_etc2 =
+                    supported_features.texture_compression_etc2;
+                features_to_enable.texture_compression_astc_ldr =
+                    supported_features.texture_compression_astc_ldr;
+            },
+            FeatureDeclaration::SamplerAnisotropy => {
+                features_to_enable.sampler_anisotropy = supported_features.sampler_anisotropy;
+            },
+            FeatureDeclaration::DynamicRendering => {
+                // Not one of the Vulkan 1.0 features; see supports_dynamic_rendering
+            },
+            FeatureDeclaration::PresentWait => {
+                // Not one of the Vulkan 1.0 features; see supports_present_wait
+            },
+            FeatureDeclaration::FillModeNonSolid => {
+                features_to_enable.fill_mode_non_solid = supported_features.fill_mode_non_solid;
+            },
+            FeatureDeclaration::DescriptorIndexing => {
+                // Not one of the Vulkan 1.0 features; see supports_descriptor_indexing
+            },
+            FeatureDeclaration::WideLines => {
+                features_to_enable.wide_lines = supported_features.wide_lines;
+            },
+            FeatureDeclaration::ShaderFloat16 => {
+                // Not one of the Vulkan 1.0 features; see supports_shader_float16
+            },
+            FeatureDeclaration::MultiDrawIndirect => {
+                features_to_enable.multi_draw_indirect = supported_features.multi_draw_indirect;
             }
         }
     }
-    Some(features_to_enable)
+    features_to_enable
+}
+
+/// Find a queue family for async compute: one that supports compute but not graphics, so that its
+/// work can overlap rendering, and that isn't the family chosen for transfers
+pub unsafe fn dedicated_compute_queue_family(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    transfer_queue_family_index: u32
+) -> Option<u32> {
+    instance.get_physical_device_queue_family_properties(physical_device)
+        .iter()
+        .enumerate()
+        .find(|(index, properties)| {
+            properties.queue_count > 0 &&
+                properties.queue_flags.contains(vk::QueueFlags::COMPUTE) &&
+                !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS) &&
+                *index as u32 != transfer_queue_family_index
+        })
+        .map(|(index, _)| index as u32)
+}
+
+/// Query whether the device supports 16-bit float arithmetic in shaders, as a Vulkan 1.2 core
+/// feature, which also needs the instance to have been created for Vulkan 1.2
+pub unsafe fn supports_shader_float16(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> bool {
+    if instance_api_version < vk::API_VERSION_1_2 {
+        return false;
+    }
+    let properties = instance.get_physical_device_properties(physical_device);
+    if properties.api_version < vk::API_VERSION_1_2 {
+        return false;
+    }
+    let mut float16_int8_features = vk::PhysicalDeviceShaderFloat16Int8Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut float16_int8_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    float16_int8_features.shader_float16 == vk::TRUE
+}
+
+/// Vulkan13Support struct
+/// Which of the Vulkan 1.3 core features that the engine can use are supported by a device
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Vulkan13Support {
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub maintenance4: bool
+}
+
+/// Query which Vulkan 1.3 core features the device supports; none are where the device, or the
+/// instance it was created for, is older than 1.3
+pub unsafe fn vulkan_1_3_support(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> Vulkan13Support {
+    if instance_api_version < vk::API_VERSION_1_3 {
+        return Vulkan13Support::default();
+    }
+    let properties = instance.get_physical_device_properties(physical_device);
+    if properties.api_version < vk::API_VERSION_1_3 {
+        return Vulkan13Support::default();
+    }
+    let mut vulkan_1_3_features = vk::PhysicalDeviceVulkan13Features::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut vulkan_1_3_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    Vulkan13Support {
+        dynamic_rendering: vulkan_1_3_features.dynamic_rendering == vk::TRUE,
+        synchronization2: vulkan_1_3_features.synchronization2 == vk::TRUE,
+        maintenance4: vulkan_1_3_features.maintenance4 == vk::TRUE
+    }
+}
+
+/// Query whether the device supports waiting for presentation of identified frames, which needs
+/// both the present ID and present wait extensions and their features. Features are queried
+/// through vkGetPhysicalDeviceFeatures2, so this also needs the instance to be Vulkan 1.1 or later.
+pub unsafe fn supports_present_wait(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> bool {
+    if instance_api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+    let Ok(extensions) = instance.enumerate_device_extension_properties(physical_device) else {
+        return false;
+    };
+    let has_extension = |name: &CStr| extensions.iter()
+        .any(|properties| CStr::from_ptr(properties.extension_name.as_ptr()) == name);
+    if !has_extension(vk::KhrPresentIdFn::name()) || !has_extension(PresentWait::name()) {
+        return false;
+    }
+    let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+    let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut present_id_features)
+        .push_next(&mut present_wait_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    present_id_features.present_id == vk::TRUE && present_wait_features.present_wait == vk::TRUE
+}
+
+/// Query whether the device supports descriptor indexing with the features that bindless texture
+/// arrays rely on: non-uniform indexing of sampled image arrays, runtime-sized arrays, and
+/// partially-bound bindings that may be updated after being bound. As for present wait, the
+/// features are queried through vkGetPhysicalDeviceFeatures2, needing a Vulkan 1.1 instance.
+pub unsafe fn supports_descriptor_indexing(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> bool {
+    if instance_api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+    let Ok(extensions) = instance.enumerate_device_extension_properties(physical_device) else {
+        return false;
+    };
+    let extension_name = vk::ExtDescriptorIndexingFn::name();
+    let has_extension = extensions.iter()
+        .any(|properties| CStr::from_ptr(properties.extension_name.as_ptr()) == extension_name);
+    if !has_extension {
+        return false;
+    }
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut indexing_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE &&
+        indexing_features.runtime_descriptor_array == vk::TRUE &&
+        indexing_features.descriptor_binding_partially_bound == vk::TRUE &&
+        indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+}
+
+/// Query whether the device supports timeline semaphores through VK_KHR_timeline_semaphore. The
+/// extension is promoted to Vulkan 1.2 but still advertised by drivers implementing it, and is
+/// enabled as an extension either way so that one set of functions is loaded. As for present
+/// wait, the feature is queried through vkGetPhysicalDeviceFeatures2, needing a Vulkan 1.1
+/// instance.
+pub unsafe fn supports_timeline_semaphore(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> bool {
+    if instance_api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+    let Ok(extensions) = instance.enumerate_device_extension_properties(physical_device) else {
+        return false;
+    };
+    let extension_name = TimelineSemaphore::name();
+    let has_extension = extensions.iter()
+        .any(|properties| CStr::from_ptr(properties.extension_name.as_ptr()) == extension_name);
+    if !has_extension {
+        return false;
+    }
+    let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut timeline_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    timeline_features.timeline_semaphore == vk::TRUE
+}
+
+/// Query what the device can do if it only partly implements Vulkan, being where it exposes
+/// VK_KHR_portability_subset. As for present wait, the features are queried through
+/// vkGetPhysicalDeviceFeatures2; with an older instance, none are assumed available.
+pub unsafe fn portability_subset(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    physical_device: vk::PhysicalDevice
+) -> Option<PortabilitySubset> {
+    let extensions = instance.enumerate_device_extension_properties(physical_device).ok()?;
+    let extension_name = vk::KhrPortabilitySubsetFn::name();
+    let has_extension = extensions.iter()
+        .any(|properties| CStr::from_ptr(properties.extension_name.as_ptr()) == extension_name);
+    if !has_extension {
+        return None;
+    }
+    if instance_api_version < vk::API_VERSION_1_1 {
+        return Some(PortabilitySubset::default());
+    }
+    let mut portability_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut portability_features)
+        .build();
+    instance.get_physical_device_features2(physical_device, &mut features);
+    Some(PortabilitySubset::from_features(&portability_features))
+}
+
+/// The largest number of sampled images that a bindless texture array may hold on the device,
+/// being the lower of its limits for update-after-bind sets and for a single shader stage
+pub unsafe fn max_bindless_textures(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice
+) -> u32 {
+    let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut indexing_properties)
+        .build();
+    instance.get_physical_device_properties2(physical_device, &mut properties);
+    indexing_properties.max_descriptor_set_update_after_bind_sampled_images
+        .min(indexing_properties.max_per_stage_descriptor_update_after_bind_samplers)
 }
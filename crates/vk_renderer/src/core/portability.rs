@@ -0,0 +1,69 @@
+use crate::StencilState;
+use ash::vk;
+
+/// PortabilitySubset struct
+/// What a device that only partly implements Vulkan can do, where it is layered over another API,
+/// such as by MoltenVK on macOS and iOS. Such devices are only enumerated by instances that opt in
+/// to portability enumeration, and expose VK_KHR_portability_subset, which lists features they
+/// lack that full implementations always have. Each field here is true where that feature is
+/// available; they are all false where they couldn't be queried, which needs Vulkan 1.1.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct PortabilitySubset {
+    pub constant_alpha_color_blend_factors: bool,
+    pub image_view_format_swizzle: bool,
+    pub point_polygons: bool,
+    pub sampler_mip_lod_bias: bool,
+    pub separate_stencil_mask_ref: bool,
+    pub triangle_fans: bool
+}
+
+impl PortabilitySubset {
+
+    /// Read the subset from the features reported by the device
+    pub fn from_features(features: &vk::PhysicalDevicePortabilitySubsetFeaturesKHR) -> Self {
+        Self {
+            constant_alpha_color_blend_factors:
+                features.constant_alpha_color_blend_factors == vk::TRUE,
+            image_view_format_swizzle: features.image_view_format_swizzle == vk::TRUE,
+            point_polygons: features.point_polygons == vk::TRUE,
+            sampler_mip_lod_bias: features.sampler_mip_lod_bias == vk::TRUE,
+            separate_stencil_mask_ref: features.separate_stencil_mask_ref == vk::TRUE,
+            triangle_fans: features.triangle_fans == vk::TRUE
+        }
+    }
+
+    /// The features to enable on the device, being all of those available
+    pub fn to_features(&self) -> vk::PhysicalDevicePortabilitySubsetFeaturesKHR {
+        vk::PhysicalDevicePortabilitySubsetFeaturesKHR::builder()
+            .constant_alpha_color_blend_factors(self.constant_alpha_color_blend_factors)
+            .image_view_format_swizzle(self.image_view_format_swizzle)
+            .point_polygons(self.point_polygons)
+            .sampler_mip_lod_bias(self.sampler_mip_lod_bias)
+            .separate_stencil_mask_ref(self.separate_stencil_mask_ref)
+            .triangle_fans(self.triangle_fans)
+            .build()
+    }
+
+    /// Whether triangles may be rasterized in the given polygon mode; drawing them as points
+    /// needs point polygons
+    pub fn supports_polygon_mode(&self, polygon_mode: vk::PolygonMode) -> bool {
+        polygon_mode != vk::PolygonMode::POINT || self.point_polygons
+    }
+
+    /// Whether the stencil state may be used with the given cull mode. Without separate stencil
+    /// masks and references, both faces must share them wherever both faces are drawn.
+    pub fn supports_stencil_state(
+        &self,
+        stencil_state: &StencilState,
+        cull_mode: vk::CullModeFlags
+    ) -> bool {
+        if self.separate_stencil_mask_ref || !stencil_state.test_enable ||
+            cull_mode != vk::CullModeFlags::NONE {
+            return true;
+        }
+        let (front, back) = (&stencil_state.front, &stencil_state.back);
+        front.reference == back.reference &&
+            front.compare_mask == back.compare_mask &&
+            front.write_mask == back.write_mask
+    }
+}
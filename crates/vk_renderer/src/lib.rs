@@ -4,20 +4,80 @@ mod mem;
 mod resource;
 mod pipeline;
 
+#[cfg(test)]
+mod tests;
+
 pub use crate::core::VkCore;
-pub use crate::core::FeatureDeclaration;
-pub use context::VkContext;
-pub use context::PresentResult;
-pub use context::Queue;
+pub use crate::core::{FeatureDeclaration, FeatureReport};
+pub use crate::core::highest_sample_count;
+pub use crate::core::{
+    DebugLabels, VkCoreConfig, ValidationSeverity, ValidationMessage, ValidationMessageHandler
+};
+pub use crate::core::{PortabilitySubset, AdapterInfo};
+pub(crate) use crate::core::ValidationErrors;
+pub use context::{VkContext, MAX_RENDER_SCALE};
+pub use context::{PresentResult, AcquireResult, PresentStatus, PreRotation};
+pub use context::{Queue, SubmissionTimeline, SemaphoreWait};
+pub use context::{CommandQueue, OneTimeCommands, SubmittedCommands, SecondaryInheritance};
+pub use color::Color;
+pub use crate::mem::{
+    AllocatorConfig, UploadPolicy, AllocationRecord, LeakReport, MemoryStatistics,
+    TransientAllocator, TransientAllocation, SparseImageBacking, QueueFamilyPair, MemoryLocation,
+    DefragmentReport
+};
 pub use crate::resource::{
-    ShaderStage, ShaderCreationData, UboUsage, DescriptorSetLayoutCreationData,
+    ShaderStage, ShaderSource, ShaderCreationData, UboUsage, DescriptorSetLayoutCreationData,
     PipelineLayoutCreationData
 };
+pub use crate::resource::sampler::{SamplerWrapper, SamplerCreationData, TextureQuality};
+pub use crate::resource::shared::SharedImage;
+pub use crate::resource::streaming::{
+    StreamedTexture, StreamedTextureData, StreamingRequest, TextureStreamer,
+    TextureStreamingConfig, StreamingPlan
+};
 pub use crate::resource::util::{TextureCodec, ResourceUtilities};
 pub use crate::resource::buffer::{BufferWrapper, BufferUsage, VboCreationData};
-pub use crate::resource::image::{ImageWrapper, ImageUsage, TexturePixelFormat, TextureCreationData};
+pub use crate::resource::image::{
+    ImageWrapper, ImageUsage, ImageAccess, ImageState, TexturePixelFormat, TextureCreationData,
+    mip_level_extent, full_mip_chain_length
+};
 pub use pipeline::{
     wrapper::{PipelineWrapper, PipelineCreationData},
-    renderpass::{RenderpassWrapper, RenderpassTarget, RenderpassCreationData},
-    offscreen_framebuffer::{OffscreenFramebufferWrapper, OffscreenFramebufferData}
+    bindless::{
+        BindlessTextureSet, BindlessTextureSetCreationData, BindlessMaterial,
+        BINDLESS_TEXTURE_SET, MATERIAL_INDEX_PUSH_CONSTANT_SIZE,
+        MATERIAL_INDEX_PUSH_CONSTANT_STAGES, record_material_index
+    },
+    dynamic_rendering::{DynamicRenderTarget, DynamicRenderTargetData},
+    metadata::{
+        DrawMetadata, DrawMetadataBuffer, DrawMetadataCreationData, DrawMetadataBinding,
+        DRAW_METADATA_BINDING
+    },
+    blend::{BlendMode, BlendFactors},
+    clustering::{
+        ClusteredLights, ClusteredLightsCreationData, ClusterGrid, PointLight,
+        LIGHT_CLUSTER_SHADER, LIGHT_LIST_BINDING, LIGHT_CLUSTER_BINDING, MAX_CLUSTERED_LIGHTS,
+        MAX_LIGHTS_PER_CLUSTER
+    },
+    compute::{
+        ComputePipelineWrapper, ComputePipelineCreationData, ComputeAccess, work_group_count
+    },
+    depth::{DepthConvention, DepthState},
+    descriptor::{DescriptorBinding, DynamicUniformLayout, write_descriptor_bindings},
+    queue::{RenderQueue, QueuedStep, back_to_front_order, record_render_queues},
+    rasterizer::RasterizerState,
+    specialization::SpecializationConstant,
+    stencil::{StencilState, StencilFaceState},
+    upscale::{UpscaleTarget, UpscaleTargetData},
+    renderpass::{
+        RenderpassWrapper, RenderpassTarget, RenderpassCreationData, CustomRenderpassDescription,
+        CustomSubpass, CustomSubpassDependency, CustomAttachment, INPUT_ATTACHMENT_FIRST_BINDING
+    },
+    offscreen_framebuffer::{OffscreenFramebufferWrapper, OffscreenFramebufferData},
+    permutation::{PipelinePermutation, create_graphics_pipelines, prewarm_pipelines},
+    post_process::{
+        PostProcessChain, PostProcessChainCreationData, PostProcessPass,
+        MAX_POST_PROCESS_PUSH_CONSTANT_SIZE
+    },
+    vertex::{VertexLayout, VertexAttribute, VertexAttributeFormat}
 };
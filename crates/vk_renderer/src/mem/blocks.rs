@@ -0,0 +1,356 @@
+use crate::mem::{MemoryAllocator, MemoryAllocation};
+use error::EngineError;
+use ash::vk;
+
+/// Size of each block of device memory that smaller allocations are sub-allocated from
+pub const MEMORY_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Largest allocation that is sub-allocated from a block; anything larger gets its own memory
+const MAX_BLOCK_ALLOCATION_SIZE: vk::DeviceSize = MEMORY_BLOCK_SIZE / 4;
+
+/// BlockKind enum
+/// What a block's allocations back. Buffers and optimally-tiled images are kept in separate
+/// blocks, so that neighbouring allocations never need spacing for bufferImageGranularity.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BlockKind {
+    Buffer,
+    Image
+}
+
+/// BlockRange struct
+/// One allocation within a block, with the alignment it was placed with
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlockRange {
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub alignment: vk::DeviceSize
+}
+
+/// BlockSpace struct
+/// The ranges in use within one block of memory, in order of offset. This is only bookkeeping,
+/// so that placement and compaction can be worked out without a device.
+#[derive(Clone, Debug)]
+pub struct BlockSpace {
+    size: vk::DeviceSize,
+    ranges: Vec<BlockRange>
+}
+
+impl BlockSpace {
+
+    pub fn new(size: vk::DeviceSize) -> Self {
+        Self { size, ranges: vec![] }
+    }
+
+    /// Place an allocation in the first gap that fits it, returning its offset
+    pub fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize
+    ) -> Option<vk::DeviceSize> {
+        let alignment = alignment.max(1);
+        let mut gap_start = 0;
+        for index in 0..=self.ranges.len() {
+            let gap_end = match self.ranges.get(index) {
+                Some(range) => range.offset,
+                None => self.size
+            };
+            let offset = align_up(gap_start, alignment);
+            if offset + size <= gap_end {
+                self.ranges.insert(index, BlockRange { offset, size, alignment });
+                return Some(offset);
+            }
+            if let Some(range) = self.ranges.get(index) {
+                gap_start = range.offset + range.size;
+            }
+        }
+        None
+    }
+
+    /// Place an allocation at the given offset, returning false if it overlaps another or runs
+    /// past the end of the block
+    pub fn reserve(
+        &mut self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize
+    ) -> bool {
+        if offset % alignment.max(1) != 0 || offset + size > self.size {
+            return false;
+        }
+        let index = self.ranges.partition_point(|range| range.offset < offset);
+        let after_previous = match index {
+            0 => true,
+            _ => {
+                let previous = &self.ranges[index - 1];
+                previous.offset + previous.size <= offset
+            }
+        };
+        let before_next = match self.ranges.get(index) {
+            Some(next) => offset + size <= next.offset,
+            None => true
+        };
+        if !after_previous || !before_next {
+            return false;
+        }
+        self.ranges.insert(index, BlockRange { offset, size, alignment });
+        true
+    }
+
+    /// Free the allocation at the given offset, returning false if there was none
+    pub fn free(&mut self, offset: vk::DeviceSize) -> bool {
+        match self.ranges.binary_search_by_key(&offset, |range| range.offset) {
+            Ok(index) => {
+                self.ranges.remove(index);
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn used_bytes(&self) -> vk::DeviceSize {
+        self.ranges.iter().map(|range| range.size).sum()
+    }
+
+    pub fn ranges(&self) -> &[BlockRange] {
+        &self.ranges
+    }
+}
+
+/// MemoryLocation struct
+/// A place in device memory: the memory and an offset within it
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct MemoryLocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize
+}
+
+/// BlockMove struct
+/// A move of one allocation into another block, as planned by plan_compaction. Blocks are
+/// identified by their index in the list that was planned over.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlockMove {
+    pub from_block: usize,
+    pub from_offset: vk::DeviceSize,
+    pub to_block: usize,
+    pub to_offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub alignment: vk::DeviceSize
+}
+
+/// Plan moves that empty as many of the given blocks as possible into the free space of the
+/// others, so that the emptied blocks can be freed. Blocks are emptied least-used first, into the
+/// first of the fullest blocks that have room, and only when everything in them can be moved, so
+/// each block the plan touches as a source ends up empty. Allocations that is_pinned reports
+/// hold their block in place. Moves must be carried out in order, since an allocation moved into
+/// a block may be moved again when that block is emptied in turn.
+pub fn plan_compaction<F>(blocks: &[BlockSpace], is_pinned: F) -> Vec<BlockMove>
+    where F: Fn(usize, &BlockRange) -> bool
+{
+    let mut spaces = blocks.to_vec();
+    let pinned: Vec<bool> = blocks.iter().enumerate()
+        .map(|(index, space)| space.ranges().iter().any(|range| is_pinned(index, range)))
+        .collect();
+    let mut by_usage: Vec<usize> = (0..blocks.len())
+        .filter(|index| !blocks[*index].is_empty())
+        .collect();
+    by_usage.sort_by(|a, b| blocks[*b].used_bytes().cmp(&blocks[*a].used_bytes()));
+
+    let mut emptied = vec![false; blocks.len()];
+    let mut moves = vec![];
+    for source in by_usage.iter().rev().copied() {
+        if pinned[source] {
+            continue;
+        }
+
+        // Try moving everything out, largest first, without touching the real layout until it
+        // is known that all of it fits
+        let mut ranges = spaces[source].ranges().to_vec();
+        ranges.sort_by(|a, b| b.size.cmp(&a.size));
+        let mut trial = spaces.clone();
+        let mut trial_moves = vec![];
+        for range in ranges.iter() {
+            let destination = by_usage.iter()
+                .copied()
+                .filter(|index| *index != source && !emptied[*index])
+                .find_map(|index| {
+                    trial[index].allocate(range.size, range.alignment)
+                        .map(|offset| (index, offset))
+                });
+            let Some((to_block, to_offset)) = destination else {
+                break;
+            };
+            trial_moves.push(BlockMove {
+                from_block: source,
+                from_offset: range.offset,
+                to_block,
+                to_offset,
+                size: range.size,
+                alignment: range.alignment
+            });
+        }
+        if trial_moves.len() < ranges.len() {
+            continue;
+        }
+        for planned in trial_moves.iter() {
+            trial[source].free(planned.from_offset);
+        }
+        spaces = trial;
+        emptied[source] = true;
+        moves.extend(trial_moves);
+    }
+    moves
+}
+
+fn align_up(offset: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+/// MemoryBlock struct
+/// A block of device memory that allocations of one kind are sub-allocated from
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    kind: BlockKind,
+    space: BlockSpace
+}
+
+/// MemoryBlocks struct
+/// All blocks that allocations are currently sub-allocated from. A block is freed as soon as its
+/// last allocation is.
+#[derive(Default)]
+pub(crate) struct MemoryBlocks {
+    blocks: Vec<MemoryBlock>
+}
+
+impl MemoryAllocator {
+
+    /// Allocate memory for a resource with the given requirements, sub-allocating it from a block
+    /// where it is small enough and the memory is never mapped, since a block's memory can only
+    /// be mapped once at a time. Anything else gets memory of its own. The allocation is not yet
+    /// tracked or bound.
+    pub(crate) unsafe fn allocate_resource_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        kind: BlockKind
+    ) -> Result<MemoryAllocation, EngineError> {
+        let host_visible = self.memory_type_flags
+            .get(memory_type as usize)
+            .map_or(false, |flags| flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE));
+        if host_visible || requirements.size > MAX_BLOCK_ALLOCATION_SIZE {
+            let memory = self.allocate_device_memory(requirements.size, memory_type)?;
+            return Ok(MemoryAllocation {
+                memory,
+                offset: 0,
+                size: requirements.size,
+                memory_type
+            });
+        }
+
+        let mut memory_blocks = self.memory_blocks.lock().unwrap();
+        for block in memory_blocks.blocks.iter_mut() {
+            if block.memory_type != memory_type || block.kind != kind {
+                continue;
+            }
+            if let Some(offset) = block.space.allocate(requirements.size, requirements.alignment) {
+                return Ok(MemoryAllocation {
+                    memory: block.memory,
+                    offset,
+                    size: requirements.size,
+                    memory_type
+                });
+            }
+        }
+        let memory = self.allocate_device_memory(MEMORY_BLOCK_SIZE, memory_type)?;
+        let mut space = BlockSpace::new(MEMORY_BLOCK_SIZE);
+        let offset = space.allocate(requirements.size, requirements.alignment).unwrap();
+        memory_blocks.blocks.push(MemoryBlock { memory, memory_type, kind, space });
+        Ok(MemoryAllocation {
+            memory,
+            offset,
+            size: requirements.size,
+            memory_type
+        })
+    }
+
+    /// Allocate memory for a resource at a planned place in an existing block, such as when
+    /// defragmentation moves it there. The allocation is not yet tracked or bound.
+    pub(crate) fn reserve_block_memory(
+        &self,
+        location: MemoryLocation,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize
+    ) -> Result<MemoryAllocation, EngineError> {
+        let mut memory_blocks = self.memory_blocks.lock().unwrap();
+        let block = memory_blocks.blocks.iter_mut()
+            .find(|block| block.memory == location.memory);
+        let Some(block) = block else {
+            return Err(EngineError::OpFailed(String::from("No such memory block to allocate in")));
+        };
+        if !block.space.reserve(location.offset, size, alignment) {
+            return Err(EngineError::OpFailed(format!(
+                "Memory block has no room for {} bytes at offset {}",
+                size,
+                location.offset)));
+        }
+        Ok(MemoryAllocation {
+            memory: location.memory,
+            offset: location.offset,
+            size,
+            memory_type: block.memory_type
+        })
+    }
+
+    /// Free memory allocated by allocate_resource_memory, freeing its block too if nothing else
+    /// is left in it. The resource using it must already have been destroyed.
+    pub(crate) unsafe fn free_resource_memory(&self, allocation: &MemoryAllocation) {
+        let mut memory_blocks = self.memory_blocks.lock().unwrap();
+        let block_index = memory_blocks.blocks.iter()
+            .position(|block| block.memory == allocation.memory);
+        let Some(block_index) = block_index else {
+            self.device.free_memory(allocation.memory, None);
+            return;
+        };
+        let block = &mut memory_blocks.blocks[block_index];
+        block.space.free(allocation.offset);
+        if block.space.is_empty() {
+            let block = memory_blocks.blocks.remove(block_index);
+            self.device.free_memory(block.memory, None);
+        }
+    }
+
+    /// Free every block, whatever is left in them; for teardown only
+    pub(crate) unsafe fn free_memory_blocks(&self) {
+        for block in self.memory_blocks.lock().unwrap().blocks.drain(..) {
+            self.device.free_memory(block.memory, None);
+        }
+    }
+
+    /// Number of blocks that allocations are currently sub-allocated from
+    pub fn get_block_count(&self) -> usize {
+        self.memory_blocks.lock().unwrap().blocks.len()
+    }
+
+    /// The memory, contents and kind of every block, for planning compaction over
+    pub(crate) fn snapshot_blocks(&self) -> Vec<(vk::DeviceMemory, u32, BlockKind, BlockSpace)> {
+        self.memory_blocks.lock().unwrap().blocks.iter()
+            .map(|block| (block.memory, block.memory_type, block.kind, block.space.clone()))
+            .collect()
+    }
+
+    unsafe fn allocate_device_memory(
+        &self,
+        size: vk::DeviceSize,
+        memory_type: u32
+    ) -> Result<vk::DeviceMemory, EngineError> {
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+        self.device.allocate_memory(&allocate_info, None)
+            .map_err(|e| EngineError::OpFailed(format!("Error allocating memory: {:?}", e)))
+    }
+}
@@ -1,5 +1,6 @@
 
 use crate::mem::{MemoryAllocator, ManagesBufferMemory, MemoryAllocation, ManagesMemoryTransfers};
+use crate::mem::blocks::{BlockKind, MemoryLocation};
 use crate::Queue;
 
 use error::EngineError;
@@ -21,27 +22,24 @@ impl ManagesBufferMemory for MemoryAllocator {
 
         // Allocate the final memory to be used for backing the buffer
         let requirements = self.device.get_buffer_memory_requirements(*buffer);
-        let memory_type = match host_accessible {
-            true => self.allocation_parameters.memory_type_host_visible,
-            false => self.allocation_parameters.memory_type_bulk_performance
-        };
-        let allocate_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(requirements.size)
-            .memory_type_index(memory_type);
-        let memory = self.device.allocate_memory(&allocate_info, None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error allocating buffer memory: {:?}", e))
-            })?;
-        let allocation = MemoryAllocation {
-            memory,
-            size: requirements.size
+        let direct_upload = init_data.is_some() && self.buffer_uploads_bypass_staging();
+        let memory_type = match (host_accessible, direct_upload) {
+            (true, _) => self.allocation_parameters.memory_type_host_visible,
+            (false, true) => self.direct_upload_memory_type(),
+            (false, false) => self.allocation_parameters.memory_type_bulk_performance
         };
+        let allocation = self.allocate_resource_memory(
+            requirements,
+            memory_type,
+            BlockKind::Buffer)?;
 
         // Bind the buffer's memory
-        self.device.bind_buffer_memory(*buffer, memory, 0)
-            .map_err(|e| {
-                EngineError::OpFailed(format! ("Error binding memory to image: {:?}", e))
-            })?;
+        let bound = self.device.bind_buffer_memory(*buffer, allocation.memory, allocation.offset);
+        if let Err(e) = bound {
+            self.free_resource_memory(&allocation);
+            return Err(EngineError::OpFailed(format!("Error binding memory to buffer: {:?}", e)));
+        }
+        self.track_allocation(&allocation, "Unnamed buffer".to_owned());
 
         // If memory needs to be initialised with data, do it via a separate function that handles
         // the staging buffer (or doesn't use it if it's not applicable on this device).
@@ -62,8 +60,261 @@ impl ManagesBufferMemory for MemoryAllocator {
         buffer: vk::Buffer,
         allocation: &MemoryAllocation
     ) -> Result<(), EngineError> {
+        self.wait_for_uploads()?;
+        self.forget_pending_buffer_acquire(buffer);
         self.device.destroy_buffer(buffer, None);
-        self.device.free_memory(allocation.memory, None);
+        self.free_resource_memory(allocation);
+        self.untrack_allocation(allocation);
+        Ok(())
+    }
+
+    /// Moves a buffer's contents into a newly-created buffer bound at the given place in a
+    /// memory block, such as one planned by defragmentation, then destroys the old buffer and
+    /// frees its memory. The old buffer must have been created with TRANSFER_SRC usage, and must
+    /// not be in use by the device. If anything fails, the old buffer is left as it was and
+    /// everything made for the new one is released.
+    unsafe fn relocate_buffer(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        buffer: vk::Buffer,
+        allocation: &MemoryAllocation,
+        usage_flags: vk::BufferUsageFlags,
+        size_bytes: usize,
+        destination: MemoryLocation
+    ) -> Result<(vk::Buffer, MemoryAllocation), EngineError> {
+
+        // Create the new buffer and bind it at the destination
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(size_bytes as u64)
+            .usage(usage_flags | vk::BufferUsageFlags::TRANSFER_DST)
+            .build();
+        let new_buffer = self.device.create_buffer(&buffer_create_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating buffer: {:?}", e))
+            })?;
+        let requirements = self.device.get_buffer_memory_requirements(new_buffer);
+        let reserved = self.reserve_block_memory(
+            destination,
+            requirements.size,
+            requirements.alignment);
+        let new_allocation = match reserved {
+            Ok(new_allocation) => new_allocation,
+            Err(e) => {
+                self.device.destroy_buffer(new_buffer, None);
+                return Err(e);
+            }
+        };
+        let bound = self.device.bind_buffer_memory(
+            new_buffer,
+            new_allocation.memory,
+            new_allocation.offset);
+        if let Err(e) = bound {
+            self.device.destroy_buffer(new_buffer, None);
+            self.free_resource_memory(&new_allocation);
+            return Err(EngineError::OpFailed(format!("Error binding buffer memory: {:?}", e)));
+        }
+
+        // Copy the whole buffer, which also releases the new one to the graphics queue
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: size_bytes as vk::DeviceSize
+        };
+        let copied =
+            self.copy_buffer(graphics_queue, transfer_queue, buffer, new_buffer, &[region]);
+        if let Err(e) = copied {
+            self.forget_pending_buffer_acquire(new_buffer);
+            self.device.destroy_buffer(new_buffer, None);
+            self.free_resource_memory(&new_allocation);
+            return Err(e);
+        }
+
+        // Release the old buffer, carrying its debug name over to the new allocation
+        self.track_allocation(&new_allocation, "Unnamed buffer".to_owned());
+        self.copy_allocation_name(allocation, &new_allocation);
+        self.destroy_buffer(buffer, allocation)?;
+
+        Ok((new_buffer, new_allocation))
+    }
+}
+
+impl MemoryAllocator {
+
+    /// Copy regions of one buffer into another on the transfer queue, waiting for the copy to
+    /// complete. The source must have TRANSFER_SRC usage and the destination TRANSFER_DST usage,
+    /// and neither may be in use by the device. Both are first released from the graphics queue
+    /// if its family differs, and both are released back to it afterward.
+    pub unsafe fn copy_buffer(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        regions: &[vk::BufferCopy]
+    ) -> Result<(), EngineError> {
+
+        // Earlier uploads into either buffer must land first, and graphics must give them up
+        self.wait_for_uploads()?;
+        let acquire_barriers = self.release_buffers_to_transfer(
+            graphics_queue,
+            transfer_queue,
+            &[src_buffer, dst_buffer],
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE)?;
+
+        // Use the transfer context belonging to this thread
+        let transfer_context = self.transfer_context()?;
+
+        // Begin recording
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(transfer_context.command_buffer, &command_begin_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
+            })?;
+
+        // Acquire both buffers if needed, copy, then release the destination to the graphics queue
+        if !acquire_barriers.is_empty() {
+            self.device.cmd_pipeline_barrier(
+                transfer_context.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquire_barriers,
+                &[]
+            );
+        }
+        self.device.cmd_copy_buffer(
+            transfer_context.command_buffer,
+            src_buffer,
+            dst_buffer,
+            regions
+        );
+        self.release_buffer_to_graphics(
+            transfer_context.command_buffer,
+            transfer_queue,
+            dst_buffer);
+        if !acquire_barriers.is_empty() {
+            self.release_buffer_to_graphics(
+                transfer_context.command_buffer,
+                transfer_queue,
+                src_buffer);
+        }
+
+        // Finish recording commands, run them and wait for the transfer timeline to reach them
+        self.device.end_command_buffer(transfer_context.command_buffer)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+            })?;
+        let timeline_value = self.submit_transfer(transfer_queue, transfer_context.command_buffer)?;
+        self.transfer_timeline.wait(&self.device, timeline_value)?;
+
+        Ok(())
+    }
+
+    /// Write data into part of a buffer, leaving the rest of its contents in place. Host-visible
+    /// memory is written directly, so the caller must ensure that no submitted work is using
+    /// that range. Otherwise the data is copied through a temporary host buffer by the graphics
+    /// queue, ordered after all work already submitted to that queue, and this waits for the copy
+    /// to complete. Resources released by the transfer queue are acquired first.
+    pub unsafe fn update_buffer_range(
+        &self,
+        graphics_queue: &Queue,
+        buffer: vk::Buffer,
+        allocation: &MemoryAllocation,
+        offset_bytes: vk::DeviceSize,
+        data_ptr: *const u8,
+        size_bytes: usize
+    ) -> Result<(), EngineError> {
+        if self.is_host_visible(allocation) {
+            return self.write_memory_range(allocation, offset_bytes, data_ptr, size_bytes);
+        }
+        self.acquire_released_resources(graphics_queue)?;
+
+        // Copy data into a temporary buffer
+        let size = size_bytes as vk::DeviceSize;
+        let temporary_buffer = Self::create_host_buffer(
+            &self.device,
+            self.allocation_parameters.memory_type_host_visible,
+            size)?;
+        self.write_memory_range(&temporary_buffer.allocation, 0, data_ptr, size_bytes)?;
+
+        // Allocate a single-use command buffer and begin recording
+        let command_buffer = graphics_queue.allocate_command_buffer(&self.device)?;
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &command_begin_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
+            })?;
+
+        // Wait for earlier work reading the range, copy, then make the new data visible to
+        // later work
+        let barrier_before = vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(vk::AccessFlags::MEMORY_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .offset(offset_bytes)
+            .size(size)
+            .build();
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier_before],
+            &[]
+        );
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: offset_bytes,
+            size
+        };
+        self.device.cmd_copy_buffer(
+            command_buffer,
+            temporary_buffer.buffer,
+            buffer,
+            &[region]
+        );
+        let barrier_after = vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .offset(offset_bytes)
+            .size(size)
+            .build();
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier_after],
+            &[]
+        );
+
+        // Finish recording commands, run them and wait for the graphics timeline to reach them
+        self.device.end_command_buffer(command_buffer)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+            })?;
+        let timeline_value = self.graphics_timeline.submit(
+            &self.device,
+            graphics_queue,
+            &[command_buffer],
+            &[],
+            &[])?;
+        self.graphics_timeline.wait(&self.device, timeline_value)?;
+        graphics_queue.free_command_buffer(&self.device, command_buffer);
+        self.device.destroy_buffer(temporary_buffer.buffer, None);
+        self.device.free_memory(temporary_buffer.allocation.memory, None);
+
         Ok(())
     }
 }
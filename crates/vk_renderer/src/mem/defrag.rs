@@ -0,0 +1,123 @@
+use crate::mem::blocks::{BlockSpace, MemoryLocation, plan_compaction};
+use crate::{VkContext, BufferWrapper, ImageWrapper};
+use ecs::EcsManager;
+use error::EngineError;
+use std::collections::HashSet;
+
+/// DefragmentReport struct
+/// What a pass of defragmentation did: how many buffers and images were moved, and how many
+/// blocks of device memory were freed by moving them
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct DefragmentReport {
+    pub relocated_count: usize,
+    pub blocks_freed: usize
+}
+
+impl VkContext {
+
+    /// Compact the memory blocks that resources are sub-allocated from, so that memory left
+    /// fragmented by repeated resource reloads can be returned to the driver. Blocks of the same
+    /// memory type are emptied, least-used first, into the free space of the others by moving
+    /// the buffers and images in them, and each emptied block is freed.
+    ///
+    /// Only buffers and images registered in the ECS are moved, and only device-local buffers
+    /// and non-sparse images created with TRANSFER_SRC usage; a block holding anything else
+    /// stays where it is. The device must be idle. Handles are rebound within their wrappers,
+    /// but the old handles are destroyed, so everything that copied one is left referring to
+    /// freed memory and must be recreated before it is used again: descriptor sets and the
+    /// pipelines holding them (such as by reloading dynamic resources), and every recorded
+    /// command buffer. Nothing needs recreating if the report says nothing was relocated.
+    pub unsafe fn defragment_memory(
+        &self,
+        ecs: &mut EcsManager<VkContext>
+    ) -> Result<DefragmentReport, EngineError> {
+        let (allocator, _) = self.get_mem_allocator();
+        allocator.wait_for_uploads()?;
+        allocator.acquire_released_resources(&self.graphics_queue)?;
+
+        let mut movable = HashSet::new();
+        ecs.for_each_item::<BufferWrapper, _>(|_, buffer| {
+            movable.extend(buffer.movable_locations());
+        });
+        ecs.for_each_item::<ImageWrapper, _>(|_, image| {
+            movable.extend(image.movable_location());
+        });
+
+        // Only blocks of the same memory type and kind can take each other's allocations
+        let blocks = allocator.snapshot_blocks();
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for (index, (_, memory_type, kind, _)) in blocks.iter().enumerate() {
+            let group = groups.iter_mut().find(|group| {
+                let (_, group_type, group_kind, _) = &blocks[group[0]];
+                group_type == memory_type && group_kind == kind
+            });
+            match group {
+                Some(group) => group.push(index),
+                None => groups.push(vec![index])
+            }
+        }
+
+        let mut relocated_count = 0;
+        for group in groups.iter() {
+            let spaces: Vec<BlockSpace> = group.iter()
+                .map(|index| blocks[*index].3.clone())
+                .collect();
+            let moves = plan_compaction(&spaces, |block, range| {
+                let memory = blocks[group[block]].0;
+                !movable.contains(&MemoryLocation { memory, offset: range.offset })
+            });
+            for planned in moves.iter() {
+                let from = MemoryLocation {
+                    memory: blocks[group[planned.from_block]].0,
+                    offset: planned.from_offset
+                };
+                let to = MemoryLocation {
+                    memory: blocks[group[planned.to_block]].0,
+                    offset: planned.to_offset
+                };
+                self.relocate_allocation(ecs, from, to)?;
+                relocated_count += 1;
+            }
+        }
+
+        Ok(DefragmentReport {
+            relocated_count,
+            blocks_freed: blocks.len().saturating_sub(allocator.get_block_count())
+        })
+    }
+
+    /// Move whichever buffer or image in the ECS has its memory at one location to another
+    unsafe fn relocate_allocation(
+        &self,
+        ecs: &mut EcsManager<VkContext>,
+        from: MemoryLocation,
+        to: MemoryLocation
+    ) -> Result<(), EngineError> {
+        let (allocator, transfer_queue) = self.get_mem_allocator();
+        let mut relocated = false;
+        ecs.try_for_each_item_mut::<BufferWrapper, _>(|_, buffer| {
+            if !relocated {
+                relocated = buffer.relocate_from(
+                    allocator,
+                    &self.graphics_queue,
+                    transfer_queue,
+                    from,
+                    to)?;
+            }
+            Ok(())
+        })?;
+        ecs.try_for_each_item_mut::<ImageWrapper, _>(|_, image| {
+            if !relocated && image.movable_location() == Some(from) {
+                image.relocate_to(self, to)?;
+                relocated = true;
+            }
+            Ok(())
+        })?;
+        match relocated {
+            true => Ok(()),
+            false => Err(EngineError::OpFailed(format!(
+                "No movable resource found at offset {} of a memory block",
+                from.offset)))
+        }
+    }
+}
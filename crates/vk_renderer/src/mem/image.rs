@@ -1,6 +1,7 @@
 
 use crate::mem::{MemoryAllocator, ManagesImageMemory, MemoryAllocation, ManagesMemoryTransfers};
-use crate::Queue;
+use crate::mem::blocks::{BlockKind, MemoryLocation};
+use crate::{Queue, TexturePixelFormat};
 use error::EngineError;
 use ash::vk;
 
@@ -22,8 +23,11 @@ impl ManagesImageMemory for MemoryAllocator {
         transfer_queue: &Queue,
         image: &vk::Image,
         aspect: vk::ImageAspectFlags,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         init_layer_data: Option<&[Vec<u8>]>,
         initialising_layout: vk::ImageLayout,
         expected_layout: vk::ImageLayout
@@ -31,23 +35,18 @@ impl ManagesImageMemory for MemoryAllocator {
 
         // Allocate the final memory to be used for backing the image
         let requirements = self.device.get_image_memory_requirements(*image);
-        let allocate_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(requirements.size)
-            .memory_type_index(self.allocation_parameters.memory_type_bulk_performance);
-        let memory = self.device.allocate_memory(&allocate_info, None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error allocating image memory: {:?}", e))
-            })?;
-        let allocation = MemoryAllocation {
-            memory,
-            size: requirements.size
-        };
+        let allocation = self.allocate_resource_memory(
+            requirements,
+            self.allocation_parameters.memory_type_bulk_performance,
+            BlockKind::Image)?;
 
         // Bind the image's memory
-        self.device.bind_image_memory(*image, memory, 0)
-            .map_err(|e| {
-                EngineError::OpFailed(format! ("Error binding memory to image: {:?}", e))
-            })?;
+        let bound = self.device.bind_image_memory(*image, allocation.memory, allocation.offset);
+        if let Err(e) = bound {
+            self.free_resource_memory(&allocation);
+            return Err(EngineError::OpFailed(format!("Error binding memory to image: {:?}", e)));
+        }
+        self.track_allocation(&allocation, "Unnamed image".to_owned());
 
         // If memory needs to be initialised with data, do it via a separate function that handles
         // the staging buffer (or doesn't use it if it's not applicable on this device). If no
@@ -55,8 +54,11 @@ impl ManagesImageMemory for MemoryAllocator {
         if let Some(layer_data) = init_layer_data {
             self.transfer_data_to_new_texture(
                 transfer_queue,
+                format,
                 width,
                 height,
+                depth,
+                mip_levels,
                 image,
                 aspect,
                 expected_layout,
@@ -79,8 +81,183 @@ impl ManagesImageMemory for MemoryAllocator {
         image: vk::Image,
         allocation: &MemoryAllocation
     ) -> Result<(), EngineError> {
+        self.wait_for_uploads()?;
+        self.forget_pending_image_acquire(image);
         self.device.destroy_image(image, None);
-        self.device.free_memory(allocation.memory, None);
+        self.free_resource_memory(allocation);
+        self.untrack_allocation(allocation);
+        Ok(())
+    }
+}
+
+impl MemoryAllocator {
+
+    /// Bind a new image to memory at a planned place in an existing block, such as where
+    /// defragmentation moves an image. The image is left unbound if this fails.
+    pub(crate) unsafe fn bind_relocated_image(
+        &self,
+        image: vk::Image,
+        location: MemoryLocation
+    ) -> Result<MemoryAllocation, EngineError> {
+        let requirements = self.device.get_image_memory_requirements(image);
+        let allocation =
+            self.reserve_block_memory(location, requirements.size, requirements.alignment)?;
+        let bound = self.device.bind_image_memory(image, allocation.memory, allocation.offset);
+        if let Err(e) = bound {
+            self.free_resource_memory(&allocation);
+            return Err(EngineError::OpFailed(format!("Error binding memory to image: {:?}", e)));
+        }
+        self.track_allocation(&allocation, "Unnamed image".to_owned());
+        Ok(allocation)
+    }
+
+    /// Destroy an image that has been relocated and free its memory, carrying its debug name
+    /// over to the allocation it was moved to
+    pub(crate) unsafe fn finish_image_relocation(
+        &self,
+        old_image: vk::Image,
+        old_allocation: &MemoryAllocation,
+        new_allocation: &MemoryAllocation
+    ) -> Result<(), EngineError> {
+        self.copy_allocation_name(old_allocation, new_allocation);
+        self.destroy_image(old_image, old_allocation)
+    }
+
+    /// Copy texel data into a region of the first mip level and layer of an image, which is
+    /// left in the given layout as it was found and released to the graphics queue. The data
+    /// must exactly cover the region. Goes through this thread's staging ring without waiting
+    /// where there is one. If the queue families differ, graphics first releases the image so
+    /// that the contents outside the region are kept, which is waited on.
+    pub(crate) unsafe fn upload_image_region(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        image: &vk::Image,
+        aspect: vk::ImageAspectFlags,
+        layout: vk::ImageLayout,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+        data: &[u8]
+    ) -> Result<(), EngineError> {
+        // Barrier moving to transfer layout, keeping the contents outside the region. This is
+        // obtained first, as it may need to wait for uploads from this thread's staging ring.
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: aspect,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+        let barrier = self.release_image_to_transfer(
+            graphics_queue,
+            transfer_queue,
+            *image,
+            subresource_range,
+            layout,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::MEMORY_READ,
+            vk::AccessFlags::TRANSFER_WRITE)?;
+
+        // Use the staging ring belonging to this thread. Without one, make a temporary
+        // host-visible buffer since optimally-tiled memory cannot be written directly, and wait for
+        // the transfer using the context's own command buffer.
+        let mut transfer_context = self.transfer_context()?;
+        let waited_command_buffer = transfer_context.command_buffer;
+        let size_bytes = data.len() as vk::DeviceSize;
+        let (source_buffer, source_offset, command_buffer, temporary_buffer) =
+            match &mut transfer_context.staging_ring {
+                Some(staging_ring) => {
+                    let staging_offset = staging_ring.reserve(
+                        &self.device,
+                        &self.transfer_timeline,
+                        size_bytes)?;
+                    let command_buffer = staging_ring.command_buffer(&self.device)?;
+                    let allocation = &staging_ring.staging_buffer.allocation;
+                    let dst_ptr = self.map_memory::<u8>(allocation)?;
+                    dst_ptr.offset(staging_offset as isize)
+                        .copy_from_nonoverlapping(data.as_ptr(), data.len());
+                    self.unmap_memory(allocation)?;
+                    (staging_ring.staging_buffer.buffer, staging_offset, command_buffer, None)
+                },
+                None => {
+                    let temporary_buffer = Self::create_host_buffer(
+                        &self.device,
+                        self.allocation_parameters.memory_type_host_visible,
+                        size_bytes)?;
+                    let dst_ptr = self.map_memory::<u8>(&temporary_buffer.allocation)?;
+                    dst_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+                    self.unmap_memory(&temporary_buffer.allocation)?;
+                    (temporary_buffer.buffer, 0, waited_command_buffer, Some(temporary_buffer))
+                }
+            };
+
+        // Begin recording
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &command_begin_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
+            })?;
+
+        // Memory dependency - move to transfer layout
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier]
+        );
+
+        // Copy command
+        let region = vk::BufferImageCopy {
+            buffer_offset: source_offset,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_offset: offset,
+            image_extent: extent,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: aspect,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            }
+        };
+        self.device.cmd_copy_buffer_to_image(
+            command_buffer,
+            source_buffer,
+            *image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region]
+        );
+
+        // Memory dependency - return to the layout used for sampling, releasing to graphics
+        self.release_image_to_graphics(
+            command_buffer,
+            transfer_queue,
+            *image,
+            subresource_range,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            layout,
+            vk::AccessFlags::TRANSFER_WRITE);
+
+        // Finish recording commands and run them. The staging ring keeps the timeline value,
+        // otherwise wait for the transfer timeline to reach it and clean up.
+        self.device.end_command_buffer(command_buffer)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+            })?;
+        let timeline_value = self.submit_transfer(transfer_queue, command_buffer)?;
+        if let Some(buffer) = temporary_buffer {
+            self.transfer_timeline.wait(&self.device, timeline_value)?;
+            self.device.destroy_buffer(buffer.buffer, None);
+            self.device.free_memory(buffer.allocation.memory, None);
+        } else if let Some(staging_ring) = &mut transfer_context.staging_ring {
+            staging_ring.commit(source_offset, size_bytes, command_buffer, timeline_value);
+        }
+
         Ok(())
     }
+
 }
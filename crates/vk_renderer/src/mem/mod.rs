@@ -1,10 +1,30 @@
 mod image;
+pub(crate) mod blocks;
 mod buffer;
+mod defrag;
+mod sparse;
+pub(crate) mod staging_ring;
+mod tracking;
 mod transfer;
-
-use crate::Queue;
+mod transfer_pool;
+pub(crate) mod transient;
+
+pub use tracking::{AllocationRecord, LeakReport, MemoryStatistics};
+pub use transient::{TransientAllocator, TransientAllocation};
+pub use sparse::SparseImageBacking;
+pub use transfer::QueueFamilyPair;
+pub use blocks::MemoryLocation;
+pub use defrag::DefragmentReport;
+
+use crate::{Queue, TexturePixelFormat, SubmissionTimeline};
+use blocks::MemoryBlocks;
+use tracking::AllocationTracker;
+use transfer::PendingAcquires;
+use transfer_pool::TransferContextPool;
 use error::EngineError;
 use ash::{Device, Instance, vk};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 
 const BULK_MEMORY_USABLE_MINIMUM: vk::DeviceSize = 536_870_912;
 const INITIAL_STAGING_BUFFER_SIZE: vk::DeviceSize = 134_217_728;
@@ -26,6 +46,17 @@ pub trait ManagesBufferMemory {
         buffer: vk::Buffer,
         allocation: &MemoryAllocation
     ) -> Result<(), EngineError>;
+
+    unsafe fn relocate_buffer(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        buffer: vk::Buffer,
+        allocation: &MemoryAllocation,
+        usage_flags: vk::BufferUsageFlags,
+        size_bytes: usize,
+        destination: MemoryLocation
+    ) -> Result<(vk::Buffer, MemoryAllocation), EngineError>;
 }
 
 /// Trait indicating that this type can create images and back them with memory
@@ -36,8 +67,11 @@ pub trait ManagesImageMemory {
         transfer_queue: &Queue,
         image: &vk::Image,
         aspect: vk::ImageAspectFlags,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         init_layer_data: Option<&[Vec<u8>]>,
         initialising_layout: vk::ImageLayout,
         expected_layout: vk::ImageLayout
@@ -90,8 +124,11 @@ pub trait ManagesMemoryTransfers {
     unsafe fn transfer_data_to_new_texture(
         &self,
         transfer_queue: &Queue,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
@@ -102,6 +139,8 @@ pub trait ManagesMemoryTransfers {
     unsafe fn transfer_data_to_new_texture_without_staging_buffer(
         &self,
         transfer_queue: &Queue,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
@@ -112,18 +151,39 @@ pub trait ManagesMemoryTransfers {
     unsafe fn transfer_data_to_new_texture_with_staging_buffer(
         &self,
         transfer_queue: &Queue,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
         layer_data: &[Vec<u8>]
     ) -> Result<(), EngineError>;
+
+    unsafe fn transfer_image_to_host(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        image: &vk::Image,
+        aspect: vk::ImageAspectFlags,
+        current_layout: vk::ImageLayout,
+        width: u32,
+        height: u32,
+        bytes_per_pixel: usize
+    ) -> Result<Vec<u8>, EngineError>;
 }
 
+/// MemoryAllocation struct
+/// Memory backing a buffer or image. Small device-only allocations share a block of memory with
+/// others, at their own offset within it; anything the host may map has its memory to itself,
+/// at offset zero.
 pub struct MemoryAllocation {
     memory: vk::DeviceMemory,
-    size: vk::DeviceSize
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    memory_type: u32
 }
 
 impl MemoryAllocation {
@@ -131,7 +191,9 @@ impl MemoryAllocation {
     pub fn null() -> Self {
         Self {
             memory: vk::DeviceMemory::null(),
-            size: 0
+            offset: 0,
+            size: 0,
+            memory_type: 0
         }
     }
 
@@ -139,16 +201,49 @@ impl MemoryAllocation {
     pub fn get_memory(&self) -> vk::DeviceMemory {
         self.memory
     }
+
+    #[inline]
+    pub fn get_offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    /// Where the allocation lives, which identifies it among those sharing a block
+    pub(crate) fn location(&self) -> MemoryLocation {
+        MemoryLocation { memory: self.memory, offset: self.offset }
+    }
+}
+
+/// UploadPolicy enum
+/// Decides whether buffer uploads go through the staging buffer or are written directly into
+/// host-visible memory. Forcing either behaviour is mostly useful for testing both paths on a
+/// single device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum UploadPolicy {
+    #[default]
+    Automatic,
+    ForceStaging,
+    ForceDirect
+}
+
+/// AllocatorConfig struct
+/// Options affecting how the memory allocator behaves, supplied when the context is created.
+/// Enabling allocation tracking records a name and size for every live allocation, so that any
+/// not freed by teardown can be reported.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AllocatorConfig {
+    pub upload_policy: UploadPolicy,
+    pub track_allocations: bool
 }
 
 struct MemoryAllocationParameters {
     memory_type_bulk_performance: u32,
     memory_type_host_visible: u32,
     memory_type_staging_buffer: Option<u32>,
+    memory_type_resizable_bar: Option<u32>,
     _prefer_image_tiling: bool
 }
 
-struct StagingBuffer {
+pub(crate) struct StagingBuffer {
     buffer: vk::Buffer,
     allocation: MemoryAllocation
 }
@@ -157,14 +252,31 @@ pub struct MemoryAllocatorCreateInfo {
     pub physical_device: vk::PhysicalDevice,
     pub device: Device,
     pub instance: Instance,
-    pub transfer_command_buffer: vk::CommandBuffer
+    pub graphics_queue_family_index: u32,
+    pub transfer_queue_family_index: u32,
+    pub sparse_binding_supported: bool,
+    pub timeline_semaphore_supported: bool,
+    pub config: AllocatorConfig
 }
 
 pub struct MemoryAllocator {
     device: Device,
     allocation_parameters: MemoryAllocationParameters,
-    transfer_command_buffer: vk::CommandBuffer,
-    staging_buffer: Option<StagingBuffer>
+    upload_policy: UploadPolicy,
+    staging_memory_type: Option<u32>,
+    transfer_contexts: TransferContextPool,
+    transfer_timeline: SubmissionTimeline,
+    graphics_timeline: SubmissionTimeline,
+    allocation_tracker: Option<Mutex<AllocationTracker>>,
+    allocation_count: AtomicUsize,
+    allocated_bytes: AtomicU64,
+    bulk_heap_bytes: vk::DeviceSize,
+    sparse_binding_supported: bool,
+    graphics_queue_family_index: u32,
+    pending_acquires: Mutex<PendingAcquires>,
+    memory_blocks: Mutex<MemoryBlocks>,
+    memory_type_flags: Vec<vk::MemoryPropertyFlags>,
+    non_coherent_atom_size: vk::DeviceSize
 }
 
 /// Memory allocator for buffers and images.
@@ -180,11 +292,24 @@ pub struct MemoryAllocator {
 /// AMD-like devices (like NVIDIA, plus an extra heap that is both host-visible and device-local
 /// but very small) can put things like uniform buffers in that extra heap (ideally check budget).
 ///
-/// SAM (or ReBAR) devices will not be specifically accounted for here, but should work fine with
-/// the other logic.
+/// SAM (or ReBAR) devices expose a large heap that is both host-visible and device-local. These
+/// are detected, and buffer uploads will then bypass the staging buffer by writing directly into
+/// that heap. The UploadPolicy in the AllocatorConfig can force either behaviour.
 ///
 /// APU-like devices (two heaps similar to NVIDIA but the device-local one is quite small) should
 /// limit their use of device-local memory, hoping that other memory is at least nearly as fast.
+///
+/// Transfers may be made from any thread. Each thread gets its own command pool, command buffer
+/// and staging ring the first time it makes a transfer, and submissions are serialised. Uploads
+/// through the staging ring don't wait for completion; see wait_for_uploads. Completion of work
+/// submitted to the transfer queue, and to the graphics queue by the allocator and for frames, is
+/// tracked by a SubmissionTimeline for each queue.
+///
+/// Where the transfer and graphics queues are from different families, uploaded resources are
+/// released by the transfer queue, and must be acquired by the graphics queue (see
+/// acquire_released_resources) before they are used for rendering. Resources that graphics
+/// already owns, such as images being partly updated or read back, are released by the graphics
+/// queue before the transfer queue touches them.
 impl MemoryAllocator {
 
     pub unsafe fn new(allocator_info: MemoryAllocatorCreateInfo) -> Result<Self, EngineError> {
@@ -193,26 +318,85 @@ impl MemoryAllocator {
         let memory_properties = allocator_info.instance
             .get_physical_device_memory_properties(allocator_info.physical_device);
         let allocation_parameters = Self::select_memory_types(memory_properties)?;
-        let staging_buffer_parameters = match allocation_parameters.memory_type_staging_buffer {
-            Some(memory_type) => Some(
-                Self::create_staging_buffer_parameters(&allocator_info.device, memory_type)?),
-            None => None
+        let bulk_heap_index = memory_properties
+            .memory_types[allocation_parameters.memory_type_bulk_performance as usize]
+            .heap_index;
+        let bulk_heap_bytes = memory_properties.memory_heaps[bulk_heap_index as usize].size;
+        let memory_type_flags = memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .map(|memory_type| memory_type.property_flags)
+            .collect();
+        let non_coherent_atom_size = allocator_info.instance
+            .get_physical_device_properties(allocator_info.physical_device)
+            .limits
+            .non_coherent_atom_size;
+        let upload_policy = allocator_info.config.upload_policy;
+        let staging_memory_type = match (
+            allocation_parameters.memory_type_staging_buffer,
+            upload_policy
+        ) {
+            (None, UploadPolicy::ForceStaging) => Some(allocation_parameters.memory_type_host_visible),
+            (memory_type, _) => memory_type
+        };
+        let transfer_contexts = TransferContextPool::new(
+            &allocator_info.device,
+            allocator_info.transfer_queue_family_index,
+            staging_memory_type)?;
+        let transfer_timeline = SubmissionTimeline::new(
+            &allocator_info.instance,
+            &allocator_info.device,
+            allocator_info.timeline_semaphore_supported)?;
+        let graphics_timeline = SubmissionTimeline::new(
+            &allocator_info.instance,
+            &allocator_info.device,
+            allocator_info.timeline_semaphore_supported)?;
+        let allocation_tracker = match allocator_info.config.track_allocations {
+            true => Some(Mutex::new(AllocationTracker::default())),
+            false => None
         };
 
         Ok(Self {
             device: allocator_info.device,
             allocation_parameters,
-            transfer_command_buffer: allocator_info.transfer_command_buffer,
-            staging_buffer: staging_buffer_parameters
+            upload_policy,
+            staging_memory_type,
+            transfer_contexts,
+            transfer_timeline,
+            graphics_timeline,
+            allocation_tracker,
+            allocation_count: AtomicUsize::new(0),
+            allocated_bytes: AtomicU64::new(0),
+            bulk_heap_bytes,
+            sparse_binding_supported: allocator_info.sparse_binding_supported,
+            graphics_queue_family_index: allocator_info.graphics_queue_family_index,
+            pending_acquires: Mutex::new(PendingAcquires::default()),
+            memory_blocks: Mutex::new(MemoryBlocks::default()),
+            memory_type_flags,
+            non_coherent_atom_size
         })
     }
 
-    pub unsafe fn destroy(&mut self, transfer_queue: &Queue) {
-        if let Some(staging_buffer_parameters) = &self.staging_buffer {
-            self.device.destroy_buffer(staging_buffer_parameters.buffer, None);
-            self.device.free_memory(staging_buffer_parameters.allocation.memory, None);
-        }
-        transfer_queue.free_command_buffer(&self.device, self.transfer_command_buffer);
+    /// Release the allocator's own resources, returning a report of any allocations that were
+    /// never freed (only populated if allocation tracking is enabled)
+    pub unsafe fn destroy(&mut self) -> LeakReport {
+        self.transfer_contexts.destroy(&self.device, &self.transfer_timeline);
+        self.transfer_timeline.destroy(&self.device);
+        self.graphics_timeline.destroy(&self.device);
+        let leak_report = self.leak_report();
+        self.free_memory_blocks();
+        leak_report
+    }
+
+    /// The timeline tracking work submitted to the transfer queue
+    pub fn get_transfer_timeline(&self) -> &SubmissionTimeline {
+        &self.transfer_timeline
+    }
+
+    /// The timeline tracking work submitted to the graphics queue, both by the allocator and for
+    /// each frame
+    pub fn get_graphics_timeline(&self) -> &SubmissionTimeline {
+        &self.graphics_timeline
     }
 
     /// Return appropriate memory types for various purposes, or an error
@@ -337,10 +521,20 @@ impl MemoryAllocator {
         let Some(uniform_type) = chosen_type_uniform_buffer else {
             return Err(EngineError::Compatibility("Logic error selecting memory".to_owned()));
         };
+
+        // Discrete devices with a large heap that is both device-local and host-accessible have
+        // resizable BAR (or SAM) enabled; small heaps of this kind are the usual 256MB window
+        let resizable_bar_type = match has_device_local_only && has_flexible_memory &&
+            flexible_memory_size >= BULK_MEMORY_USABLE_MINIMUM {
+            true => Some(flexible_memory_index),
+            false => None
+        };
+
         Ok(MemoryAllocationParameters {
             memory_type_bulk_performance: performance_type,
             memory_type_host_visible: uniform_type,
             memory_type_staging_buffer: chosen_type_staging_buffer,
+            memory_type_resizable_bar: resizable_bar_type,
             _prefer_image_tiling: prefer_image_tiling
         })
     }
@@ -349,9 +543,18 @@ impl MemoryAllocator {
         device: &Device,
         memory_type: u32
     ) -> Result<StagingBuffer, EngineError> {
+        Self::create_host_buffer(device, memory_type, INITIAL_STAGING_BUFFER_SIZE)
+    }
+
+    /// Create a buffer in host-accessible memory to be used as the source of transfers
+    unsafe fn create_host_buffer(
+        device: &Device,
+        memory_type: u32,
+        size_bytes: vk::DeviceSize
+    ) -> Result<StagingBuffer, EngineError> {
 
         let buffer_create_info = vk::BufferCreateInfo::builder()
-            .size(INITIAL_STAGING_BUFFER_SIZE)
+            .size(size_bytes)
             .usage(vk::BufferUsageFlags::TRANSFER_SRC)
             .build();
         let buffer = device.create_buffer(&buffer_create_info, None)
@@ -378,11 +581,40 @@ impl MemoryAllocator {
             buffer,
             allocation: MemoryAllocation {
                 size: requirements.size,
-                memory
+                memory,
+                offset: 0,
+                memory_type
             }
         })
     }
 
+    /// Query whether a resizable BAR (or SAM) heap was detected on this device
+    pub fn has_resizable_bar(&self) -> bool {
+        self.allocation_parameters.memory_type_resizable_bar.is_some()
+    }
+
+    /// Decide whether initialised buffers should be written directly into host-visible memory
+    /// rather than copied through the staging buffer
+    pub(crate) fn buffer_uploads_bypass_staging(&self) -> bool {
+        if self.staging_memory_type.is_none() {
+            return true;
+        }
+        match self.upload_policy {
+            UploadPolicy::Automatic => self.has_resizable_bar(),
+            UploadPolicy::ForceStaging => false,
+            UploadPolicy::ForceDirect => true
+        }
+    }
+
+    /// Memory type to use for device-only buffers that are initialised by writing directly into
+    /// their memory
+    pub(crate) fn direct_upload_memory_type(&self) -> u32 {
+        match self.allocation_parameters.memory_type_resizable_bar {
+            Some(memory_type) => memory_type,
+            None => self.allocation_parameters.memory_type_host_visible
+        }
+    }
+
     pub unsafe fn map_memory<T>(&self, allocation: &MemoryAllocation) -> Result<*mut T, EngineError> {
         let data_ptr = self.device
             .map_memory(allocation.memory, 0, allocation.size, vk::MemoryMapFlags::empty())
@@ -396,4 +628,142 @@ impl MemoryAllocator {
         self.device.unmap_memory(allocation.memory);
         Ok(())
     }
+
+    /// Whether an allocation's memory can be mapped by the host
+    pub fn is_host_visible(&self, allocation: &MemoryAllocation) -> bool {
+        self.memory_type_property_flags(allocation)
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+    }
+
+    fn memory_type_property_flags(&self, allocation: &MemoryAllocation) -> vk::MemoryPropertyFlags {
+        self.memory_type_flags
+            .get(allocation.memory_type as usize)
+            .copied()
+            .unwrap_or(vk::MemoryPropertyFlags::empty())
+    }
+
+    /// Map only the given range of a host-visible allocation, copy data into it, and flush it if
+    /// the memory is not host-coherent. The mapped range is widened to the device's non-coherent
+    /// atom size as required for flushing.
+    pub unsafe fn write_memory_range(
+        &self,
+        allocation: &MemoryAllocation,
+        offset_bytes: vk::DeviceSize,
+        src_ptr: *const u8,
+        size_bytes: usize
+    ) -> Result<(), EngineError> {
+        let (map_offset, map_size) = atom_aligned_range(
+            offset_bytes,
+            size_bytes as vk::DeviceSize,
+            self.non_coherent_atom_size,
+            allocation.size);
+        let data_ptr = self.device
+            .map_memory(allocation.memory, map_offset, map_size, vk::MemoryMapFlags::empty())
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error mapping memory: {:?}", e))
+            })? as *mut u8;
+        data_ptr.offset((offset_bytes - map_offset) as isize)
+            .copy_from_nonoverlapping(src_ptr, size_bytes);
+        let coherent = self.memory_type_property_flags(allocation)
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        let flush_result = match coherent {
+            true => Ok(()),
+            false => self.device.flush_mapped_memory_ranges(&[vk::MappedMemoryRange::builder()
+                .memory(allocation.memory)
+                .offset(map_offset)
+                .size(map_size)
+                .build()])
+        };
+        self.device.unmap_memory(allocation.memory);
+        flush_result.map_err(|e| {
+            EngineError::OpFailed(format!("Error flushing mapped memory: {:?}", e))
+        })
+    }
+
+    /// Flush a range of an allocation that is kept mapped, where its memory is not host-coherent,
+    /// so that the device sees what the host wrote into it
+    pub unsafe fn flush_mapped_range(
+        &self,
+        allocation: &MemoryAllocation,
+        offset_bytes: vk::DeviceSize,
+        size_bytes: vk::DeviceSize
+    ) -> Result<(), EngineError> {
+        let coherent = self.memory_type_property_flags(allocation)
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        if coherent {
+            return Ok(());
+        }
+        let (map_offset, map_size) = atom_aligned_range(
+            offset_bytes,
+            size_bytes,
+            self.non_coherent_atom_size,
+            allocation.size);
+        let range = vk::MappedMemoryRange::builder()
+            .memory(allocation.memory)
+            .offset(map_offset)
+            .size(map_size)
+            .build();
+        self.device.flush_mapped_memory_ranges(&[range])
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error flushing mapped memory: {:?}", e))
+            })
+    }
+
+    /// Map only the given range of a host-visible allocation, invalidating it first if the memory
+    /// is not host-coherent so that device writes are visible, and copy data out of it
+    pub unsafe fn read_memory_range(
+        &self,
+        allocation: &MemoryAllocation,
+        offset_bytes: vk::DeviceSize,
+        dst_ptr: *mut u8,
+        size_bytes: usize
+    ) -> Result<(), EngineError> {
+        let (map_offset, map_size) = atom_aligned_range(
+            offset_bytes,
+            size_bytes as vk::DeviceSize,
+            self.non_coherent_atom_size,
+            allocation.size);
+        let data_ptr = self.device
+            .map_memory(allocation.memory, map_offset, map_size, vk::MemoryMapFlags::empty())
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error mapping memory: {:?}", e))
+            })? as *mut u8;
+        let coherent = self.memory_type_property_flags(allocation)
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        if !coherent {
+            let invalidate_result = self.device.invalidate_mapped_memory_ranges(&[
+                vk::MappedMemoryRange::builder()
+                    .memory(allocation.memory)
+                    .offset(map_offset)
+                    .size(map_size)
+                    .build()
+            ]);
+            if let Err(e) = invalidate_result {
+                self.device.unmap_memory(allocation.memory);
+                return Err(EngineError::OpFailed(
+                    format!("Error invalidating mapped memory: {:?}", e)));
+            }
+        }
+        data_ptr.offset((offset_bytes - map_offset) as isize)
+            .copy_to_nonoverlapping(dst_ptr, size_bytes);
+        self.device.unmap_memory(allocation.memory);
+        Ok(())
+    }
+}
+
+/// Widen a range within an allocation to start and end on multiples of the non-coherent atom
+/// size, as needed to flush or invalidate it; the end is clamped to the allocation size, which
+/// is always valid
+pub(crate) fn atom_aligned_range(
+    offset_bytes: vk::DeviceSize,
+    size_bytes: vk::DeviceSize,
+    atom_size: vk::DeviceSize,
+    allocation_size: vk::DeviceSize
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let atom_size = atom_size.max(1);
+    let start = offset_bytes - offset_bytes % atom_size;
+    let end = (offset_bytes + size_bytes)
+        .next_multiple_of(atom_size)
+        .min(allocation_size);
+    (start, end - start)
 }
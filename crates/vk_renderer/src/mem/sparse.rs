@@ -0,0 +1,342 @@
+
+use crate::mem::{MemoryAllocator, MemoryAllocation, ManagesMemoryTransfers};
+use crate::Queue;
+use error::EngineError;
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// SparseImageBacking struct
+/// Memory bookkeeping for an image created with sparse residency. The image is divided into tiles
+/// of the size reported by the device, and each tile is backed by its own allocation only while it
+/// is resident. A mip tail, if the device needs one, stays bound for the life of the image.
+pub struct SparseImageBacking {
+    image_extent: vk::Extent3D,
+    tile_extent: vk::Extent3D,
+    page_size: vk::DeviceSize,
+    memory_type: u32,
+    aspect: vk::ImageAspectFlags,
+    layout: vk::ImageLayout,
+    bytes_per_texel: usize,
+    mip_tail: Option<MemoryAllocation>,
+    resident_tiles: Mutex<HashMap<(u32, u32), MemoryAllocation>>
+}
+
+impl SparseImageBacking {
+
+    /// Size in texels of a single tile
+    pub fn get_tile_extent(&self) -> vk::Extent3D {
+        self.tile_extent
+    }
+
+    /// Number of tiles across and down the image
+    pub fn get_tile_count(&self) -> (u32, u32) {
+        (
+            self.image_extent.width.div_ceil(self.tile_extent.width),
+            self.image_extent.height.div_ceil(self.tile_extent.height)
+        )
+    }
+
+    /// Query whether a tile currently has memory bound to it
+    pub fn is_tile_resident(&self, tile_x: u32, tile_y: u32) -> bool {
+        self.resident_tiles.lock().unwrap().contains_key(&(tile_x, tile_y))
+    }
+
+    /// Number of tiles currently resident
+    pub fn resident_tile_count(&self) -> usize {
+        self.resident_tiles.lock().unwrap().len()
+    }
+
+    /// Texel offset and extent of a tile; tiles along the right and bottom edges are clipped to
+    /// the image
+    fn tile_region(
+        &self,
+        tile_x: u32,
+        tile_y: u32
+    ) -> Result<(vk::Offset3D, vk::Extent3D), EngineError> {
+        let (tiles_across, tiles_down) = self.get_tile_count();
+        if tile_x >= tiles_across || tile_y >= tiles_down {
+            return Err(EngineError::OpFailed(
+                format!("Tile ({}, {}) is outside of the sparse image", tile_x, tile_y)));
+        }
+        let x = tile_x * self.tile_extent.width;
+        let y = tile_y * self.tile_extent.height;
+        let offset = vk::Offset3D { x: x as i32, y: y as i32, z: 0 };
+        let extent = vk::Extent3D {
+            width: self.tile_extent.width.min(self.image_extent.width - x),
+            height: self.tile_extent.height.min(self.image_extent.height - y),
+            depth: 1
+        };
+        Ok((offset, extent))
+    }
+}
+
+impl MemoryAllocator {
+
+    /// Query whether sparse textures can be created on this device. This requires the
+    /// SparseTextures feature to have been declared, and the device to support it.
+    pub fn supports_sparse_textures(&self) -> bool {
+        self.sparse_binding_supported
+    }
+
+    /// Prepare an image that was created with sparse residency flags. No tiles are resident
+    /// afterwards, but the image is transitioned to the layout it will be sampled in.
+    pub(crate) unsafe fn back_sparse_image(
+        &self,
+        sparse_queue: &Queue,
+        transfer_queue: &Queue,
+        image: &vk::Image,
+        aspect: vk::ImageAspectFlags,
+        width: u32,
+        height: u32,
+        bytes_per_texel: usize,
+        expected_layout: vk::ImageLayout
+    ) -> Result<SparseImageBacking, EngineError> {
+        if !self.sparse_binding_supported {
+            return Err(EngineError::Compatibility(
+                "Sparse textures are not enabled on this device".to_owned()));
+        }
+
+        // Find the tile size and memory requirements
+        let requirements = self.device.get_image_memory_requirements(*image);
+        let memory_type = self.allocation_parameters.memory_type_bulk_performance;
+        if requirements.memory_type_bits & (1 << memory_type) == 0 {
+            return Err(EngineError::Compatibility(
+                "Sparse image cannot use the device-local memory type".to_owned()));
+        }
+        let sparse_requirements = self.device.get_image_sparse_memory_requirements(*image);
+        let Some(sparse_requirements) = sparse_requirements.iter()
+            .find(|r| r.format_properties.aspect_mask.contains(aspect)) else {
+            return Err(EngineError::Compatibility(
+                "No sparse memory requirements found for image aspect".to_owned()));
+        };
+
+        // Bind the mip tail, if the image has one; it is never paged out
+        let mip_tail = match sparse_requirements.image_mip_tail_first_lod == 0 {
+            true => {
+                let allocate_info = vk::MemoryAllocateInfo::builder()
+                    .allocation_size(sparse_requirements.image_mip_tail_size)
+                    .memory_type_index(memory_type);
+                let memory = self.device.allocate_memory(&allocate_info, None)
+                    .map_err(|e| {
+                        EngineError::OpFailed(format!("Error allocating mip tail memory: {:?}", e))
+                    })?;
+                let allocation = MemoryAllocation {
+                    memory,
+                    offset: 0,
+                    size: sparse_requirements.image_mip_tail_size,
+                    memory_type
+                };
+                self.track_allocation(&allocation, "Sparse image mip tail".to_owned());
+                let binds = [vk::SparseMemoryBind {
+                    resource_offset: sparse_requirements.image_mip_tail_offset,
+                    size: sparse_requirements.image_mip_tail_size,
+                    memory,
+                    memory_offset: 0,
+                    flags: vk::SparseMemoryBindFlags::empty()
+                }];
+                let opaque_binds = [vk::SparseImageOpaqueMemoryBindInfo::builder()
+                    .image(*image)
+                    .binds(&binds)
+                    .build()];
+                let bind_info = vk::BindSparseInfo::builder()
+                    .image_opaque_binds(&opaque_binds)
+                    .build();
+                self.submit_sparse_bind(sparse_queue, &bind_info)?;
+                Some(allocation)
+            },
+            false => None
+        };
+
+        self.transition_image_layout(
+            transfer_queue,
+            image,
+            aspect,
+            vk::ImageLayout::UNDEFINED,
+            expected_layout)?;
+
+        Ok(SparseImageBacking {
+            image_extent: vk::Extent3D { width, height, depth: 1 },
+            tile_extent: sparse_requirements.format_properties.image_granularity,
+            page_size: requirements.alignment,
+            memory_type,
+            aspect,
+            layout: expected_layout,
+            bytes_per_texel,
+            mip_tail,
+            resident_tiles: Mutex::new(HashMap::new())
+        })
+    }
+
+    /// Bind memory to a tile of a sparse image, returning false if it was already resident. The
+    /// tile's contents are undefined until written with upload_sparse_tile.
+    pub(crate) unsafe fn make_sparse_tile_resident(
+        &self,
+        sparse_queue: &Queue,
+        image: &vk::Image,
+        backing: &SparseImageBacking,
+        tile_x: u32,
+        tile_y: u32
+    ) -> Result<bool, EngineError> {
+        if backing.is_tile_resident(tile_x, tile_y) {
+            return Ok(false);
+        }
+        let (offset, extent) = backing.tile_region(tile_x, tile_y)?;
+
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(backing.page_size)
+            .memory_type_index(backing.memory_type);
+        let memory = self.device.allocate_memory(&allocate_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error allocating sparse tile memory: {:?}", e))
+            })?;
+        let allocation = MemoryAllocation {
+            memory,
+            offset: 0,
+            size: backing.page_size,
+            memory_type: backing.memory_type
+        };
+        self.track_allocation(&allocation, format!("Sparse image tile ({}, {})", tile_x, tile_y));
+
+        self.bind_sparse_tile(sparse_queue, image, backing, offset, extent, memory)?;
+        backing.resident_tiles.lock().unwrap().insert((tile_x, tile_y), allocation);
+        Ok(true)
+    }
+
+    /// Unbind and free the memory of a tile of a sparse image, returning false if it was not
+    /// resident. The caller must make sure no pending rendering still samples the tile.
+    pub(crate) unsafe fn evict_sparse_tile(
+        &self,
+        sparse_queue: &Queue,
+        image: &vk::Image,
+        backing: &SparseImageBacking,
+        tile_x: u32,
+        tile_y: u32
+    ) -> Result<bool, EngineError> {
+        let Some(allocation) = backing.resident_tiles.lock().unwrap()
+            .remove(&(tile_x, tile_y)) else {
+            return Ok(false);
+        };
+        let (offset, extent) = backing.tile_region(tile_x, tile_y)?;
+        self.bind_sparse_tile(
+            sparse_queue,
+            image,
+            backing,
+            offset,
+            extent,
+            vk::DeviceMemory::null())?;
+        self.device.free_memory(allocation.memory, None);
+        self.untrack_allocation(&allocation);
+        Ok(true)
+    }
+
+    /// Copy texel data into a resident tile of a sparse image. The data must exactly cover the
+    /// tile, which is smaller than the usual tile extent along the right and bottom edges.
+    pub(crate) unsafe fn upload_sparse_tile(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        image: &vk::Image,
+        backing: &SparseImageBacking,
+        tile_x: u32,
+        tile_y: u32,
+        data: &[u8]
+    ) -> Result<(), EngineError> {
+        if !backing.is_tile_resident(tile_x, tile_y) {
+            return Err(EngineError::OpFailed(
+                format!("Tile ({}, {}) is not resident", tile_x, tile_y)));
+        }
+        let (offset, extent) = backing.tile_region(tile_x, tile_y)?;
+        let expected_size =
+            extent.width as usize * extent.height as usize * backing.bytes_per_texel;
+        if data.len() != expected_size {
+            return Err(EngineError::OpFailed(
+                format!("Tile data is {} bytes, expected {}", data.len(), expected_size)));
+        }
+
+        self.upload_image_region(
+            graphics_queue,
+            transfer_queue,
+            image,
+            backing.aspect,
+            backing.layout,
+            offset,
+            extent,
+            data)
+    }
+
+    /// Destroy a sparse image, freeing its mip tail and all resident tiles
+    pub(crate) unsafe fn destroy_sparse_image(
+        &self,
+        image: vk::Image,
+        backing: &SparseImageBacking
+    ) -> Result<(), EngineError> {
+        self.wait_for_uploads()?;
+        self.forget_pending_image_acquire(image);
+        self.device.destroy_image(image, None);
+        let mut resident_tiles = backing.resident_tiles.lock().unwrap();
+        for (_, allocation) in resident_tiles.drain() {
+            self.device.free_memory(allocation.memory, None);
+            self.untrack_allocation(&allocation);
+        }
+        if let Some(allocation) = &backing.mip_tail {
+            self.device.free_memory(allocation.memory, None);
+            self.untrack_allocation(allocation);
+        }
+        Ok(())
+    }
+
+    /// Bind memory (or null, to unbind) to one tile of a sparse image
+    unsafe fn bind_sparse_tile(
+        &self,
+        sparse_queue: &Queue,
+        image: &vk::Image,
+        backing: &SparseImageBacking,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+        memory: vk::DeviceMemory
+    ) -> Result<(), EngineError> {
+        let binds = [vk::SparseImageMemoryBind {
+            subresource: vk::ImageSubresource {
+                aspect_mask: backing.aspect,
+                mip_level: 0,
+                array_layer: 0
+            },
+            offset,
+            extent,
+            memory,
+            memory_offset: 0,
+            flags: vk::SparseMemoryBindFlags::empty()
+        }];
+        let image_binds = [vk::SparseImageMemoryBindInfo::builder()
+            .image(*image)
+            .binds(&binds)
+            .build()];
+        let bind_info = vk::BindSparseInfo::builder()
+            .image_binds(&image_binds)
+            .build();
+        self.submit_sparse_bind(sparse_queue, &bind_info)
+    }
+
+    /// Submit a sparse binding operation and wait for it to complete
+    unsafe fn submit_sparse_bind(
+        &self,
+        sparse_queue: &Queue,
+        bind_info: &vk::BindSparseInfo
+    ) -> Result<(), EngineError> {
+        let fence = self.device
+            .create_fence(&vk::FenceCreateInfo::default(), None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating fence: {:?}", e))
+            })?;
+        sparse_queue.submit_sparse_bind(&self.device, bind_info, &fence)?;
+        self.device
+            .wait_for_fences(&[fence], true, u64::MAX)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error waiting for fence: {:?}", e))
+            })?;
+        self.device
+            .destroy_fence(fence, None);
+        Ok(())
+    }
+}
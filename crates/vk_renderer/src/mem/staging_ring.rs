@@ -0,0 +1,218 @@
+
+use crate::mem::{MemoryAllocator, StagingBuffer};
+use crate::SubmissionTimeline;
+use error::EngineError;
+use ash::{Device, vk};
+use std::collections::VecDeque;
+
+/// Alignment of each region handed out by the staging ring; satisfies the buffer offset
+/// requirements of buffer-to-image copies for every format the engine uses
+const STAGING_RING_ALIGNMENT: vk::DeviceSize = 256;
+
+/// RingRegions struct
+/// Book-keeping for a ring of bytes handed out in order and released in the same order. Regions
+/// are never split across the end of the ring; if one doesn't fit before the end, it starts
+/// again from the beginning.
+pub(crate) struct RingRegions {
+    capacity: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    regions: VecDeque<(vk::DeviceSize, vk::DeviceSize)>
+}
+
+impl RingRegions {
+
+    pub(crate) fn new(capacity: vk::DeviceSize, alignment: vk::DeviceSize) -> Self {
+        Self {
+            capacity,
+            alignment,
+            regions: VecDeque::new()
+        }
+    }
+
+    /// Find the offset at which a region of the given size could be placed, or None if there is
+    /// not currently enough free space. Does not claim the region; see push.
+    pub(crate) fn find_space(&self, size_bytes: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let size_bytes = size_bytes.max(1);
+        if size_bytes > self.capacity {
+            return None;
+        }
+        let (Some(oldest), Some(newest)) = (self.regions.front(), self.regions.back()) else {
+            return Some(0);
+        };
+        let next_start = newest.1.next_multiple_of(self.alignment);
+        let wrapped = newest.1 <= oldest.0;
+        if wrapped {
+            match next_start + size_bytes <= oldest.0 {
+                true => Some(next_start),
+                false => None
+            }
+        } else if next_start + size_bytes <= self.capacity {
+            Some(next_start)
+        } else if size_bytes <= oldest.0 {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Claim a region previously found with find_space
+    pub(crate) fn push(&mut self, offset: vk::DeviceSize, size_bytes: vk::DeviceSize) {
+        self.regions.push_back((offset, offset + size_bytes.max(1)));
+    }
+
+    /// Release the oldest claimed region
+    pub(crate) fn pop_oldest(&mut self) {
+        self.regions.pop_front();
+    }
+
+    /// Number of regions currently claimed
+    pub(crate) fn len(&self) -> usize {
+        self.regions.len()
+    }
+}
+
+/// StagingSubmission struct
+/// A transfer submitted from a region of the staging ring, which must complete before the region
+/// can be reused, identified by the transfer timeline value that it signals
+struct StagingSubmission {
+    command_buffer: vk::CommandBuffer,
+    timeline_value: u64
+}
+
+/// StagingRing struct
+/// A staging buffer shared by consecutive uploads. Each upload copies its data into the next free
+/// region, then submits its transfer without waiting for it. Regions are reclaimed once the
+/// transfer timeline reaches their transfers' values, and an upload only waits if the ring is
+/// full.
+pub(crate) struct StagingRing {
+    pub(crate) staging_buffer: StagingBuffer,
+    command_pool: vk::CommandPool,
+    regions: RingRegions,
+    submissions: VecDeque<StagingSubmission>,
+    spare_command_buffers: Vec<vk::CommandBuffer>
+}
+
+impl StagingRing {
+
+    pub(crate) unsafe fn new(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        memory_type: u32
+    ) -> Result<Self, EngineError> {
+        let staging_buffer = MemoryAllocator::create_staging_buffer_parameters(
+            device,
+            memory_type)?;
+        let regions = RingRegions::new(staging_buffer.allocation.size, STAGING_RING_ALIGNMENT);
+        Ok(Self {
+            staging_buffer,
+            command_pool,
+            regions,
+            submissions: VecDeque::new(),
+            spare_command_buffers: vec![]
+        })
+    }
+
+    /// Find space for an upload of the given size, reclaiming regions of completed transfers and
+    /// waiting for more to complete if needed. The returned offset must be committed, with the
+    /// transfer that reads from it, before reserving again.
+    pub(crate) unsafe fn reserve(
+        &mut self,
+        device: &Device,
+        timeline: &SubmissionTimeline,
+        size_bytes: vk::DeviceSize
+    ) -> Result<vk::DeviceSize, EngineError> {
+        if size_bytes > self.staging_buffer.allocation.size {
+            return Err(EngineError::OpFailed(format!(
+                "Upload of {} bytes exceeds staging buffer size of {} bytes",
+                size_bytes,
+                self.staging_buffer.allocation.size)));
+        }
+        self.retire_completed(device, timeline, false)?;
+        loop {
+            if let Some(offset) = self.regions.find_space(size_bytes) {
+                return Ok(offset);
+            }
+            self.retire_oldest(device, timeline)?;
+        }
+    }
+
+    /// Get a command buffer to record a transfer into
+    pub(crate) unsafe fn command_buffer(
+        &mut self,
+        device: &Device
+    ) -> Result<vk::CommandBuffer, EngineError> {
+        if let Some(command_buffer) = self.spare_command_buffers.pop() {
+            return Ok(command_buffer);
+        }
+        let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .command_buffer_count(1);
+        let command_buffer = device
+            .allocate_command_buffers(&command_buffer_alloc_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error allocating command buffer: {:?}", e))
+            })?[0];
+        Ok(command_buffer)
+    }
+
+    /// Claim a reserved region, along with the submitted transfer that reads from it
+    pub(crate) fn commit(
+        &mut self,
+        offset: vk::DeviceSize,
+        size_bytes: vk::DeviceSize,
+        command_buffer: vk::CommandBuffer,
+        timeline_value: u64
+    ) {
+        self.regions.push(offset, size_bytes);
+        self.submissions.push_back(StagingSubmission { command_buffer, timeline_value });
+    }
+
+    /// Reclaim regions whose transfers have completed; if wait_all is set, wait for every
+    /// submitted transfer to complete
+    pub(crate) unsafe fn retire_completed(
+        &mut self,
+        device: &Device,
+        timeline: &SubmissionTimeline,
+        wait_all: bool
+    ) -> Result<(), EngineError> {
+        while let Some(submission) = self.submissions.front() {
+            let complete = timeline.is_complete(device, submission.timeline_value)?;
+            if !complete && !wait_all {
+                break;
+            }
+            self.retire_oldest(device, timeline)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the oldest submitted transfer, then reclaim its region
+    unsafe fn retire_oldest(
+        &mut self,
+        device: &Device,
+        timeline: &SubmissionTimeline
+    ) -> Result<(), EngineError> {
+        let Some(submission) = self.submissions.pop_front() else {
+            return Err(EngineError::OpFailed(
+                "Internal error: staging ring full with nothing in flight".to_owned()));
+        };
+        timeline.wait(device, submission.timeline_value)?;
+        self.spare_command_buffers.push(submission.command_buffer);
+        self.regions.pop_oldest();
+        Ok(())
+    }
+
+    /// Number of submitted transfers not yet known to have completed
+    pub(crate) fn in_flight_count(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Wait for outstanding transfers and free the ring's resources. Command buffers are freed
+    /// along with the pool they came from.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device, timeline: &SubmissionTimeline) {
+        if let Err(e) = self.retire_completed(device, timeline, true) {
+            println!("Destroying staging ring with copies outstanding: {:?}", e);
+        }
+        device.destroy_buffer(self.staging_buffer.buffer, None);
+        device.free_memory(self.staging_buffer.allocation.memory, None);
+    }
+}
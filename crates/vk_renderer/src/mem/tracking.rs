@@ -0,0 +1,139 @@
+use crate::mem::{MemoryAllocator, MemoryAllocation};
+use ash::vk;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::Ordering;
+
+/// AllocationRecord struct
+/// Debug information kept about a live allocation when allocation tracking is enabled
+#[derive(Clone, Debug)]
+pub struct AllocationRecord {
+    pub name: String,
+    pub size_bytes: vk::DeviceSize
+}
+
+/// LeakReport struct
+/// List of allocations that were still live when the report was made
+#[derive(Clone, Debug, Default)]
+pub struct LeakReport {
+    pub allocations: Vec<AllocationRecord>
+}
+
+impl LeakReport {
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> vk::DeviceSize {
+        self.allocations.iter().map(|record| record.size_bytes).sum()
+    }
+}
+
+impl Display for LeakReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} allocation(s) not freed, totalling {} bytes:",
+            self.allocations.len(),
+            self.total_bytes())?;
+        for record in self.allocations.iter() {
+            writeln!(f, "  {}: {} bytes", record.name, record.size_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// MemoryStatistics struct
+/// Totals for the memory allocated for buffers and images, which are kept whether or not
+/// allocation tracking is enabled, along with the size of the heap that bulk resources such as
+/// textures are allocated from; useful for setting budgets
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryStatistics {
+    pub allocation_count: usize,
+    pub allocated_bytes: vk::DeviceSize,
+    pub bulk_heap_bytes: vk::DeviceSize
+}
+
+/// AllocationTracker struct
+/// Records of all live allocations, keyed by their device memory handle and their offset within
+/// it, since allocations sub-allocated from a block share its memory
+#[derive(Default)]
+pub(crate) struct AllocationTracker {
+    records: HashMap<(vk::DeviceMemory, vk::DeviceSize), AllocationRecord>
+}
+
+impl MemoryAllocator {
+
+    /// Record a new allocation in the statistics, and in more detail if tracking is enabled
+    pub(crate) fn track_allocation(&self, allocation: &MemoryAllocation, name: String) {
+        self.allocation_count.fetch_add(1, Ordering::Relaxed);
+        self.allocated_bytes.fetch_add(allocation.size, Ordering::Relaxed);
+        if let Some(tracker) = &self.allocation_tracker {
+            tracker.lock().unwrap().records.insert(
+                (allocation.memory, allocation.offset),
+                AllocationRecord { name, size_bytes: allocation.size });
+        }
+    }
+
+    /// Remove the record of an allocation that is being freed, returning it if there was one
+    pub(crate) fn untrack_allocation(
+        &self,
+        allocation: &MemoryAllocation
+    ) -> Option<AllocationRecord> {
+        self.allocation_count.fetch_sub(1, Ordering::Relaxed);
+        self.allocated_bytes.fetch_sub(allocation.size, Ordering::Relaxed);
+        match &self.allocation_tracker {
+            Some(tracker) => {
+                let key = (allocation.memory, allocation.offset);
+                tracker.lock().unwrap().records.remove(&key)
+            },
+            None => None
+        }
+    }
+
+    /// Give an allocation a name to identify it in leak reports; does nothing unless tracking is
+    /// enabled
+    pub fn set_allocation_name(&self, allocation: &MemoryAllocation, name: &str) {
+        if let Some(tracker) = &self.allocation_tracker {
+            let key = (allocation.memory, allocation.offset);
+            if let Some(record) = tracker.lock().unwrap().records.get_mut(&key) {
+                record.name = name.to_owned();
+            }
+        }
+    }
+
+    /// Give an allocation the name of another, such as one whose resource was moved into it
+    pub(crate) fn copy_allocation_name(&self, from: &MemoryAllocation, to: &MemoryAllocation) {
+        if let Some(tracker) = &self.allocation_tracker {
+            let mut tracker = tracker.lock().unwrap();
+            let Some(record) = tracker.records.get(&(from.memory, from.offset)) else {
+                return;
+            };
+            let name = record.name.clone();
+            if let Some(record) = tracker.records.get_mut(&(to.memory, to.offset)) {
+                record.name = name;
+            }
+        }
+    }
+
+    /// Report all allocations that are currently live. Always empty unless tracking is enabled.
+    pub fn leak_report(&self) -> LeakReport {
+        let Some(tracker) = &self.allocation_tracker else {
+            return LeakReport::default();
+        };
+        let mut allocations: Vec<AllocationRecord> = tracker.lock().unwrap().records.values()
+            .cloned()
+            .collect();
+        allocations.sort_by(|a, b| a.name.cmp(&b.name));
+        LeakReport { allocations }
+    }
+    /// Get totals for the memory currently allocated
+    pub fn statistics(&self) -> MemoryStatistics {
+        MemoryStatistics {
+            allocation_count: self.allocation_count.load(Ordering::Relaxed),
+            allocated_bytes: self.allocated_bytes.load(Ordering::Relaxed),
+            bulk_heap_bytes: self.bulk_heap_bytes
+        }
+    }
+}
@@ -1,10 +1,12 @@
 
 use crate::mem::{
-    MemoryAllocator, ManagesMemoryTransfers, MemoryAllocation
+    MemoryAllocator, ManagesMemoryTransfers, ManagesBufferMemory, MemoryAllocation
 };
-use crate::Queue;
+use crate::{Queue, TexturePixelFormat};
+use crate::resource::image::mip_level_extent;
 use error::EngineError;
 use ash::vk;
+use std::collections::{HashMap, HashSet};
 
 impl ManagesMemoryTransfers for MemoryAllocator {
 
@@ -17,7 +19,7 @@ impl ManagesMemoryTransfers for MemoryAllocator {
         data_size_bytes: usize
     ) -> Result<(), EngineError> {
 
-        if self.staging_buffer.is_some() {
+        if !self.buffer_uploads_bypass_staging() {
             self.transfer_data_to_new_buffer_with_staging_buffer(
                 transfer_queue, buffer, init_data, data_size_bytes)
         } else {
@@ -49,21 +51,29 @@ impl ManagesMemoryTransfers for MemoryAllocator {
         data_size_bytes: usize
     ) -> Result<(), EngineError> {
 
-        let Some(staging_parameters) = &self.staging_buffer else {
+        // Use the staging ring belonging to this thread
+        let mut transfer_context = self.transfer_context()?;
+        let Some(staging_ring) = &mut transfer_context.staging_ring else {
             return Err(EngineError::OpFailed(
                 "Internal error: transferring from staging without a buffer".to_owned()
             ));
         };
-
-        // Copy data into staging buffer
-        let dst_ptr = self.map_memory::<u8>(&staging_parameters.allocation)?;
-        dst_ptr.copy_from_nonoverlapping(init_data, data_size_bytes);
-        self.unmap_memory(&staging_parameters.allocation).unwrap();
-
-        // Allocate a single-use command buffer and begin recording
+        let size_bytes = data_size_bytes as vk::DeviceSize;
+        let staging_offset =
+            staging_ring.reserve(&self.device, &self.transfer_timeline, size_bytes)?;
+        let staging_buffer = staging_ring.staging_buffer.buffer;
+        let command_buffer = staging_ring.command_buffer(&self.device)?;
+
+        // Copy data into the reserved region of the staging buffer
+        let dst_ptr = self.map_memory::<u8>(&staging_ring.staging_buffer.allocation)?;
+        dst_ptr.offset(staging_offset as isize)
+            .copy_from_nonoverlapping(init_data, data_size_bytes);
+        self.unmap_memory(&staging_ring.staging_buffer.allocation).unwrap();
+
+        // Begin recording
         let command_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        self.device.begin_command_buffer(self.transfer_command_buffer, &command_begin_info)
+        self.device.begin_command_buffer(command_buffer, &command_begin_info)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
             })?;
@@ -79,7 +89,7 @@ impl ManagesMemoryTransfers for MemoryAllocator {
             .size(vk::WHOLE_SIZE)
             .build();
         self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
+            command_buffer,
             vk::PipelineStageFlags::TOP_OF_PIPE,
             vk::PipelineStageFlags::TRANSFER,
             vk::DependencyFlags::empty(),
@@ -90,58 +100,28 @@ impl ManagesMemoryTransfers for MemoryAllocator {
 
         // Copy command
         let region = vk::BufferCopy {
-            src_offset: 0,
+            src_offset: staging_offset,
             dst_offset: 0,
             size: data_size_bytes as vk::DeviceSize
         };
         self.device.cmd_copy_buffer(
-            self.transfer_command_buffer,
-            staging_parameters.buffer,
+            command_buffer,
+            staging_buffer,
             *buffer,
             &[region]
         );
 
-        // Final memory dependency
-        let barrier = vk::BufferMemoryBarrier::builder()
-            .buffer(*buffer)
-            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .offset(0)
-            .size(vk::WHOLE_SIZE)
-            .build();
-        self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[barrier],
-            &[]
-        );
+        // Final memory dependency, releasing the buffer to the graphics queue
+        self.release_buffer_to_graphics(command_buffer, transfer_queue, *buffer);
 
-        // Finish recording commands, create a fence, run the command, wait for fence, clean up
-        self.device.end_command_buffer(self.transfer_command_buffer)
+        // Finish recording commands and run them; the staging ring keeps the timeline value and
+        // reclaims the region once the transfer timeline reaches it
+        self.device.end_command_buffer(command_buffer)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
             })?;
-        let fence = self.device
-            .create_fence(&vk::FenceCreateInfo::default(), None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error creating fence: {:?}", e))
-            })?;
-        transfer_queue.submit_transfer_command_buffer(
-            &self.device,
-            &self.transfer_command_buffer,
-            &fence)?;
-        self.device
-            .wait_for_fences(&[fence], true, u64::MAX)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error waiting for fence: {:?}", e))
-            })?;
-        self.device
-            .destroy_fence(fence, None);
+        let timeline_value = self.submit_transfer(transfer_queue, command_buffer)?;
+        staging_ring.commit(staging_offset, size_bytes, command_buffer, timeline_value);
 
         Ok(())
     }
@@ -155,62 +135,40 @@ impl ManagesMemoryTransfers for MemoryAllocator {
         new_layout: vk::ImageLayout
     ) -> Result<(), EngineError> {
 
+        // Use the transfer context belonging to this thread
+        let transfer_context = self.transfer_context()?;
+
         // Allocate a single-use command buffer and begin recording
         let command_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        self.device.begin_command_buffer(self.transfer_command_buffer, &command_begin_info)
+        self.device.begin_command_buffer(transfer_context.command_buffer, &command_begin_info)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
             })?;
 
-        // Memory dependency - move to final image layout
-        let barrier = vk::ImageMemoryBarrier::builder()
-            .image(*image)
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-            .old_layout(old_layout)
-            .new_layout(new_layout)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .subresource_range(vk::ImageSubresourceRange {
+        // Memory dependency - move to final image layout, releasing to the graphics queue
+        self.release_image_to_graphics(
+            transfer_context.command_buffer,
+            transfer_queue,
+            *image,
+            vk::ImageSubresourceRange {
                 aspect_mask: aspect,
                 base_mip_level: 0,
                 level_count: 1,
                 base_array_layer: 0,
                 layer_count: vk::REMAINING_ARRAY_LAYERS
-            })
-            .build();
-        self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier]
-        );
+            },
+            old_layout,
+            new_layout,
+            vk::AccessFlags::empty());
 
-        // Finish recording commands, create a fence, run the command, wait for fence, clean up
-        self.device.end_command_buffer(self.transfer_command_buffer)
+        // Finish recording commands, run them and wait for the transfer timeline to reach them
+        self.device.end_command_buffer(transfer_context.command_buffer)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
             })?;
-        let fence = self.device
-            .create_fence(&vk::FenceCreateInfo::default(), None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error creating fence: {:?}", e))
-            })?;
-        transfer_queue.submit_transfer_command_buffer(
-            &self.device,
-            &self.transfer_command_buffer,
-            &fence)?;
-        self.device
-            .wait_for_fences(&[fence], true, u64::MAX)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error waiting for fence: {:?}", e))
-            })?;
-        self.device
-            .destroy_fence(fence, None);
+        let timeline_value = self.submit_transfer(transfer_queue, transfer_context.command_buffer)?;
+        self.transfer_timeline.wait(&self.device, timeline_value)?;
 
         Ok(())
     }
@@ -218,8 +176,11 @@ impl ManagesMemoryTransfers for MemoryAllocator {
     unsafe fn transfer_data_to_new_texture(
         &self,
         transfer_queue: &Queue,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
@@ -227,27 +188,53 @@ impl ManagesMemoryTransfers for MemoryAllocator {
         layer_data: &[Vec<u8>]
     ) -> Result<(), EngineError> {
 
-        let layer_count = layer_data.len();
-        let layer_size_bytes = layer_data[0].len();
+        // Volume images are given one item of data per depth slice rather than per layer
+        if depth > 1 && (layer_data.len() != depth as usize || mip_levels != 1) {
+            return Err(EngineError::UserError(format!(
+                "Volume image data must be {} slices with no mip levels",
+                depth)));
+        }
 
-        // Staging buffer
-        let expected_data_size: usize = layer_count * 4 * width as usize * height as usize;
-        if expected_data_size != layer_count * layer_size_bytes {
-            panic!("Image data does not match expected size");
+        // Every layer must be exactly the size of the image and its mip levels in this format,
+        // which for compressed formats means whole blocks covering every texel
+        let expected_layer_size_bytes = format.mip_chain_size_bytes(width, height, mip_levels);
+        if layer_data.iter().any(|layer| layer.len() != expected_layer_size_bytes) {
+            return Err(EngineError::UserError(format!(
+                "Image data does not match expected size of {} bytes per layer for {:?}",
+                expected_layer_size_bytes,
+                format)));
         }
 
-        if self.staging_buffer.is_some() {
+        if self.staging_memory_type.is_some() {
             self.transfer_data_to_new_texture_with_staging_buffer(
-                transfer_queue, width, height, image_dst, aspect, expected_layout, layer_data)
+                transfer_queue,
+                format,
+                width,
+                height,
+                depth,
+                mip_levels,
+                image_dst,
+                aspect,
+                expected_layout,
+                layer_data)
         } else {
             self.transfer_data_to_new_texture_without_staging_buffer(
-                transfer_queue, image_dst, aspect, expected_layout, allocation, layer_data)
+                transfer_queue,
+                depth,
+                mip_levels,
+                image_dst,
+                aspect,
+                expected_layout,
+                allocation,
+                layer_data)
         }
     }
 
     unsafe fn transfer_data_to_new_texture_without_staging_buffer(
         &self,
         transfer_queue: &Queue,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
@@ -255,8 +242,8 @@ impl ManagesMemoryTransfers for MemoryAllocator {
         layer_data: &[Vec<u8>]
     ) -> Result<(), EngineError> {
 
-        // Copy data into image memory
-        let layer_count = layer_data.len();
+        // Copy data into image memory; slices of a volume image are all in its one layer
+        let layer_count = if depth > 1 { 1 } else { layer_data.len() };
         let layer_size_bytes = layer_data[0].len();
         for (layer_no, data) in layer_data.iter().enumerate() {
             let src_ptr = data.as_ptr() as *const u8;
@@ -267,62 +254,40 @@ impl ManagesMemoryTransfers for MemoryAllocator {
             self.unmap_memory(&allocation).unwrap();
         }
 
+        // Use the transfer context belonging to this thread
+        let transfer_context = self.transfer_context()?;
+
         // Allocate a single-use command buffer and begin recording
         let command_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        self.device.begin_command_buffer(self.transfer_command_buffer, &command_begin_info)
+        self.device.begin_command_buffer(transfer_context.command_buffer, &command_begin_info)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
             })?;
 
-        // Memory dependency - move to final image layout
-        let barrier = vk::ImageMemoryBarrier::builder()
-            .image(*image_dst)
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-            .old_layout(vk::ImageLayout::PREINITIALIZED)
-            .new_layout(expected_layout)
-            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .subresource_range(vk::ImageSubresourceRange {
+        // Memory dependency - move to final image layout, releasing to the graphics queue
+        self.release_image_to_graphics(
+            transfer_context.command_buffer,
+            transfer_queue,
+            *image_dst,
+            vk::ImageSubresourceRange {
                 aspect_mask: aspect,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
                 layer_count: layer_count as u32
-            })
-            .build();
-        self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[barrier]
-        );
+            },
+            vk::ImageLayout::PREINITIALIZED,
+            expected_layout,
+            vk::AccessFlags::empty());
 
-        // Finish recording commands, create a fence, run the command, wait for fence, clean up
-        self.device.end_command_buffer(self.transfer_command_buffer)
+        // Finish recording commands, run them and wait for the transfer timeline to reach them
+        self.device.end_command_buffer(transfer_context.command_buffer)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
             })?;
-        let fence = self.device
-            .create_fence(&vk::FenceCreateInfo::default(), None)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error creating fence: {:?}", e))
-            })?;
-        transfer_queue.submit_transfer_command_buffer(
-            &self.device,
-            &self.transfer_command_buffer,
-            &fence)?;
-        self.device
-            .wait_for_fences(&[fence], true, u64::MAX)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error waiting for fence: {:?}", e))
-            })?;
-        self.device
-            .destroy_fence(fence, None);
+        let timeline_value = self.submit_transfer(transfer_queue, transfer_context.command_buffer)?;
+        self.transfer_timeline.wait(&self.device, timeline_value)?;
 
         Ok(())
     }
@@ -330,36 +295,48 @@ impl ManagesMemoryTransfers for MemoryAllocator {
     unsafe fn transfer_data_to_new_texture_with_staging_buffer(
         &self,
         transfer_queue: &Queue,
+        format: TexturePixelFormat,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
         image_dst: &vk::Image,
         aspect: vk::ImageAspectFlags,
         expected_layout: vk::ImageLayout,
         layer_data: &[Vec<u8>]
     ) -> Result<(), EngineError> {
 
-        let Some(staging_parameters) = &self.staging_buffer else {
+        // Use the staging ring belonging to this thread
+        let mut transfer_context = self.transfer_context()?;
+        let Some(staging_ring) = &mut transfer_context.staging_ring else {
             return Err(EngineError::OpFailed(
                 "Internal error: transferring from staging without a buffer".to_owned()
             ));
         };
-
-        // Copy data into staging buffer
         let layer_size_bytes = layer_data[0].len();
         let layer_count = layer_data.len();
+        let size_bytes = (layer_size_bytes * layer_count) as vk::DeviceSize;
+        let array_layer_count = if depth > 1 { 1 } else { layer_count as u32 };
+        let staging_offset =
+            staging_ring.reserve(&self.device, &self.transfer_timeline, size_bytes)?;
+        let staging_buffer = staging_ring.staging_buffer.buffer;
+        let command_buffer = staging_ring.command_buffer(&self.device)?;
+
+        // Copy data into the reserved region of the staging buffer
         for (layer_no, data) in layer_data.iter().enumerate() {
             let src_ptr = data.as_ptr() as *const u8;
-            let mut dst_ptr = self.map_memory::<u8>(&staging_parameters.allocation)?;
-            let dst_offset_elements = (layer_no * layer_size_bytes) as isize;
+            let mut dst_ptr = self.map_memory::<u8>(&staging_ring.staging_buffer.allocation)?;
+            let dst_offset_elements =
+                staging_offset as isize + (layer_no * layer_size_bytes) as isize;
             dst_ptr = dst_ptr.offset(dst_offset_elements);
             dst_ptr.copy_from_nonoverlapping(src_ptr, layer_size_bytes);
-            self.unmap_memory(&staging_parameters.allocation).unwrap();
+            self.unmap_memory(&staging_ring.staging_buffer.allocation).unwrap();
         }
 
-        // Allocate a single-use command buffer and begin recording
+        // Begin recording
         let command_begin_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        self.device.begin_command_buffer(self.transfer_command_buffer, &command_begin_info)
+        self.device.begin_command_buffer(command_buffer, &command_begin_info)
             .map_err(|e| {
                 EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
             })?;
@@ -376,13 +353,13 @@ impl ManagesMemoryTransfers for MemoryAllocator {
             .subresource_range(vk::ImageSubresourceRange {
                 aspect_mask: aspect,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
-                layer_count: layer_count as u32
+                layer_count: array_layer_count
             })
             .build();
         self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
+            command_buffer,
             vk::PipelineStageFlags::TOP_OF_PIPE,
             vk::PipelineStageFlags::TRANSFER,
             vk::DependencyFlags::empty(),
@@ -391,12 +368,152 @@ impl ManagesMemoryTransfers for MemoryAllocator {
             &[barrier]
         );
 
+        // Copy commands, one per mip level of each layer, or one per slice of a volume image;
+        // row pitch and layer height are in texels, and compressed data is made of whole blocks,
+        // so partial blocks at the edges still take up a full block of texels
+        let (block_width, block_height) = format.block_extent();
+        let mut regions = Vec::with_capacity(layer_count * mip_levels as usize);
+        for layer_no in 0..layer_count {
+            let mut buffer_offset = staging_offset + (layer_no * layer_size_bytes) as u64;
+            let (base_array_layer, slice) = match depth {
+                1 => (layer_no as u32, 0),
+                _ => (0, layer_no as i32)
+            };
+            for mip_level in 0..mip_levels {
+                let (level_width, level_height) = mip_level_extent(width, height, mip_level);
+                regions.push(vk::BufferImageCopy {
+                    buffer_offset,
+                    buffer_row_length: level_width.div_ceil(block_width) * block_width,
+                    buffer_image_height: level_height.div_ceil(block_height) * block_height,
+                    image_offset: vk::Offset3D { x: 0, y: 0, z: slice },
+                    image_extent: vk::Extent3D {
+                        width: level_width,
+                        height: level_height,
+                        depth: 1
+                    },
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: aspect,
+                        mip_level,
+                        base_array_layer,
+                        layer_count: 1
+                    }
+                });
+                buffer_offset += format.layer_size_bytes(level_width, level_height) as u64;
+            }
+        }
+        self.device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging_buffer,
+            *image_dst,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions
+        );
+
+        // Final memory dependency, releasing the image to the graphics queue
+        self.release_image_to_graphics(
+            command_buffer,
+            transfer_queue,
+            *image_dst,
+            vk::ImageSubresourceRange {
+                aspect_mask: aspect,
+                base_mip_level: 0,
+                level_count: mip_levels,
+                base_array_layer: 0,
+                layer_count: array_layer_count
+            },
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            expected_layout,
+            vk::AccessFlags::TRANSFER_WRITE);
+
+        // Finish recording commands and run them; the staging ring keeps the timeline value and
+        // reclaims the region once the transfer timeline reaches it
+        self.device.end_command_buffer(command_buffer)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+            })?;
+        let timeline_value = self.submit_transfer(transfer_queue, command_buffer)?;
+        staging_ring.commit(staging_offset, size_bytes, command_buffer, timeline_value);
+
+        Ok(())
+    }
+
+    /// Copy the first layer of an image back into host memory, returning the tightly-packed pixel
+    /// data. The image must have been created with TRANSFER_SRC usage, and is returned to its
+    /// current layout afterwards. If the queue families differ, ownership is passed to the
+    /// transfer queue for the copy and has been given back to graphics by the time this returns.
+    unsafe fn transfer_image_to_host(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        image: &vk::Image,
+        aspect: vk::ImageAspectFlags,
+        current_layout: vk::ImageLayout,
+        width: u32,
+        height: u32,
+        bytes_per_pixel: usize
+    ) -> Result<Vec<u8>, EngineError> {
+
+        // Create a host-visible buffer to receive the image data
+        let data_size_bytes = width as usize * height as usize * bytes_per_pixel;
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(data_size_bytes as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .build();
+        let readback_buffer = self.device.create_buffer(&buffer_create_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating buffer: {:?}", e))
+            })?;
+        let readback_allocation = self.back_buffer_memory(
+            transfer_queue,
+            &readback_buffer,
+            true,
+            None,
+            data_size_bytes)?;
+
+        // Initial memory dependency, which may need graphics to release the image first
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: aspect,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+        let barrier = self.release_image_to_transfer(
+            graphics_queue,
+            transfer_queue,
+            *image,
+            subresource_range,
+            current_layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::AccessFlags::TRANSFER_READ)?;
+
+        // Use the transfer context belonging to this thread
+        let transfer_context = self.transfer_context()?;
+
+        // Begin recording
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(transfer_context.command_buffer, &command_begin_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error starting copy command buffer: {:?}", e))
+            })?;
+        self.device.cmd_pipeline_barrier(
+            transfer_context.command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier]
+        );
+
         // Copy command
         let image_subresource = vk::ImageSubresourceLayers {
             aspect_mask: aspect,
             mip_level: 0,
             base_array_layer: 0,
-            layer_count: layer_count as u32
+            layer_count: 1
         };
         let region = vk::BufferImageCopy {
             buffer_offset: 0,
@@ -406,62 +523,451 @@ impl ManagesMemoryTransfers for MemoryAllocator {
             image_extent: vk::Extent3D { width, height, depth: 1 },
             image_subresource
         };
-        self.device.cmd_copy_buffer_to_image(
-            self.transfer_command_buffer,
-            staging_parameters.buffer,
-            *image_dst,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        self.device.cmd_copy_image_to_buffer(
+            transfer_context.command_buffer,
+            *image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            readback_buffer,
             &[region]
         );
 
-        // Final memory dependency
-        let barrier = vk::ImageMemoryBarrier::builder()
-            .image(*image_dst)
+        // Final memory dependencies - restore the image layout, releasing it to graphics, and
+        // make the buffer visible to host
+        self.release_image_to_graphics(
+            transfer_context.command_buffer,
+            transfer_queue,
+            *image,
+            subresource_range,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            current_layout,
+            vk::AccessFlags::TRANSFER_READ);
+        let buffer_barrier = vk::BufferMemoryBarrier::builder()
+            .buffer(readback_buffer)
             .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .new_layout(expected_layout)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
             .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
             .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: aspect,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: layer_count as u32
-            })
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
             .build();
         self.device.cmd_pipeline_barrier(
-            self.transfer_command_buffer,
+            transfer_context.command_buffer,
             vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[buffer_barrier],
+            &[]
+        );
+
+        // Finish recording commands, run them and wait for the transfer timeline to reach them
+        self.device.end_command_buffer(transfer_context.command_buffer)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+            })?;
+        let timeline_value = self.submit_transfer(transfer_queue, transfer_context.command_buffer)?;
+        self.transfer_timeline.wait(&self.device, timeline_value)?;
+        drop(transfer_context);
+        self.acquire_released_resources(graphics_queue)?;
+
+        // Copy data out of the readback buffer, then release it
+        let mut pixel_data = vec![0u8; data_size_bytes];
+        let src_ptr = self.map_memory::<u8>(&readback_allocation)?;
+        src_ptr.copy_to_nonoverlapping(pixel_data.as_mut_ptr(), data_size_bytes);
+        self.unmap_memory(&readback_allocation).unwrap();
+        self.destroy_buffer(readback_buffer, &readback_allocation)?;
+
+        Ok(pixel_data)
+    }
+}
+
+/// QueueFamilyPair struct
+/// The queue families either side of a queue ownership transfer. A resource with exclusive
+/// sharing that is written using one queue family and then used by another needs a release
+/// barrier recorded on the first and a matching acquire barrier recorded on the second. When both
+/// families are the same, these helpers produce ordinary barriers instead.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct QueueFamilyPair {
+    pub src_family: u32,
+    pub dst_family: u32
+}
+
+impl QueueFamilyPair {
+
+    pub fn new(src_family: u32, dst_family: u32) -> Self {
+        Self { src_family, dst_family }
+    }
+
+    /// Query whether ownership must actually be transferred between these families
+    pub fn requires_ownership_transfer(&self) -> bool {
+        self.src_family != self.dst_family
+    }
+
+    /// Queue family indices to put in barriers; ignored if no transfer is needed
+    fn barrier_families(&self) -> (u32, u32) {
+        match self.requires_ownership_transfer() {
+            true => (self.src_family, self.dst_family),
+            false => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+
+    /// Barrier to record on the source queue after it has finished writing a buffer
+    pub fn release_buffer_barrier(
+        &self,
+        buffer: vk::Buffer,
+        src_access: vk::AccessFlags
+    ) -> vk::BufferMemoryBarrier {
+        let (src_family, dst_family) = self.barrier_families();
+        let dst_access = match self.requires_ownership_transfer() {
+            true => vk::AccessFlags::empty(),
+            false => vk::AccessFlags::MEMORY_READ
+        };
+        vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build()
+    }
+
+    /// Barrier to record on the destination queue before it first uses a released buffer
+    pub fn acquire_buffer_barrier(
+        &self,
+        buffer: vk::Buffer,
+        dst_access: vk::AccessFlags
+    ) -> vk::BufferMemoryBarrier {
+        let (src_family, dst_family) = self.barrier_families();
+        vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build()
+    }
+
+    /// Barrier to record on the source queue after it has finished writing an image. Any layout
+    /// transition given here must be repeated exactly in the acquire barrier.
+    pub fn release_image_barrier(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access: vk::AccessFlags
+    ) -> vk::ImageMemoryBarrier {
+        let (src_family, dst_family) = self.barrier_families();
+        let dst_access = match self.requires_ownership_transfer() {
+            true => vk::AccessFlags::empty(),
+            false => vk::AccessFlags::MEMORY_READ
+        };
+        vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .subresource_range(subresource_range)
+            .build()
+    }
+
+    /// Barrier to record on the destination queue before it first uses a released image
+    pub fn acquire_image_barrier(
+        &self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        dst_access: vk::AccessFlags
+    ) -> vk::ImageMemoryBarrier {
+        let (src_family, dst_family) = self.barrier_families();
+        vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .subresource_range(subresource_range)
+            .build()
+    }
+}
+
+/// PendingImageAcquire struct
+/// What is needed to rebuild the acquire barrier matching an image's release barrier
+#[derive(Copy, Clone)]
+struct PendingImageAcquire {
+    subresource_range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout
+}
+
+/// PendingAcquires struct
+/// Resources released by one queue family whose acquire barriers have not yet been recorded on
+/// the other, keyed by resource and queue family pair
+#[derive(Default)]
+pub(crate) struct PendingAcquires {
+    buffers: HashSet<(vk::Buffer, QueueFamilyPair)>,
+    images: HashMap<(vk::Image, QueueFamilyPair), PendingImageAcquire>
+}
+
+impl MemoryAllocator {
+
+    /// Queue families for handing a resource written on the given transfer queue to graphics
+    pub fn transfer_to_graphics_families(&self, transfer_queue: &Queue) -> QueueFamilyPair {
+        QueueFamilyPair::new(
+            transfer_queue.queue_family_index,
+            self.graphics_queue_family_index)
+    }
+
+    /// Record the release of a buffer written on the transfer queue, and remember to acquire it
+    /// on the graphics queue if the families differ
+    pub(crate) unsafe fn release_buffer_to_graphics(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        transfer_queue: &Queue,
+        buffer: vk::Buffer
+    ) {
+        let families = self.transfer_to_graphics_families(transfer_queue);
+        let barrier = families.release_buffer_barrier(buffer, vk::AccessFlags::TRANSFER_WRITE);
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier],
+            &[]
+        );
+        if families.requires_ownership_transfer() {
+            self.pending_acquires.lock().unwrap().buffers.insert((buffer, families));
+        }
+    }
+
+    /// Record the release of an image written or transitioned on the transfer queue, and
+    /// remember to acquire it on the graphics queue if the families differ
+    pub(crate) unsafe fn release_image_to_graphics(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        transfer_queue: &Queue,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access: vk::AccessFlags
+    ) {
+        let families = self.transfer_to_graphics_families(transfer_queue);
+        let barrier = families.release_image_barrier(
+            image,
+            subresource_range,
+            old_layout,
+            new_layout,
+            src_access);
+        let src_stage = match src_access.is_empty() {
+            true => vk::PipelineStageFlags::TOP_OF_PIPE,
+            false => vk::PipelineStageFlags::TRANSFER
+        };
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
             vk::PipelineStageFlags::BOTTOM_OF_PIPE,
             vk::DependencyFlags::empty(),
             &[],
             &[],
             &[barrier]
         );
+        if families.requires_ownership_transfer() {
+            self.pending_acquires.lock().unwrap().images.insert(
+                (image, families),
+                PendingImageAcquire { subresource_range, old_layout, new_layout });
+        }
+    }
+
+    /// Queue families for handing a resource used by graphics to the given transfer queue
+    pub fn graphics_to_transfer_families(&self, transfer_queue: &Queue) -> QueueFamilyPair {
+        QueueFamilyPair::new(
+            self.graphics_queue_family_index,
+            transfer_queue.queue_family_index)
+    }
+
+    /// Release buffers that graphics may own so that the transfer queue can use them, waiting
+    /// for the release to complete. Returns the acquire barriers to record on the transfer queue
+    /// before the buffers are used there, which is none if the families are the same.
+    pub(crate) unsafe fn release_buffers_to_transfer(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        buffers: &[vk::Buffer],
+        dst_access: vk::AccessFlags
+    ) -> Result<Vec<vk::BufferMemoryBarrier>, EngineError> {
+        let families = self.graphics_to_transfer_families(transfer_queue);
+        if !families.requires_ownership_transfer() {
+            return Ok(vec![]);
+        }
+        self.acquire_released_resources(graphics_queue)?;
+        let release_barriers: Vec<vk::BufferMemoryBarrier> = buffers.iter()
+            .map(|buffer| families.release_buffer_barrier(*buffer, vk::AccessFlags::MEMORY_WRITE))
+            .collect();
+        self.submit_graphics_barriers(
+            graphics_queue,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &release_barriers,
+            &[])?;
+        Ok(buffers.iter()
+            .map(|buffer| families.acquire_buffer_barrier(*buffer, dst_access))
+            .collect())
+    }
+
+    /// Release an image that graphics owns so that the transfer queue can use it, waiting for
+    /// the release to complete. Returns the barrier to record on the transfer queue before the
+    /// image is used there; this is the matching acquire if the families differ, otherwise an
+    /// ordinary barrier making the given transition.
+    pub(crate) unsafe fn release_image_to_transfer(
+        &self,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access: vk::AccessFlags,
+        dst_access: vk::AccessFlags
+    ) -> Result<vk::ImageMemoryBarrier, EngineError> {
+        let families = self.graphics_to_transfer_families(transfer_queue);
+        if !families.requires_ownership_transfer() {
+            return Ok(vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build());
+        }
+        self.acquire_released_resources(graphics_queue)?;
+        let release_barrier = families.release_image_barrier(
+            image,
+            subresource_range,
+            old_layout,
+            new_layout,
+            src_access);
+        self.submit_graphics_barriers(
+            graphics_queue,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[],
+            &[release_barrier])?;
+        Ok(families.acquire_image_barrier(
+            image,
+            subresource_range,
+            old_layout,
+            new_layout,
+            dst_access))
+    }
+
+    /// Drop any pending acquire for a resource that is being destroyed
+    pub(crate) fn forget_pending_buffer_acquire(&self, buffer: vk::Buffer) {
+        self.pending_acquires.lock().unwrap().buffers.retain(|(b, _)| *b != buffer);
+    }
+
+    /// Drop any pending acquire for a resource that is being destroyed
+    pub(crate) fn forget_pending_image_acquire(&self, image: vk::Image) {
+        self.pending_acquires.lock().unwrap().images.retain(|(i, _), _| *i != image);
+    }
+
+    /// Wait for in-flight uploads, then record and submit acquire barriers on the graphics queue
+    /// for every resource released by the transfer queue since the last call, waiting for
+    /// completion. Returns the number of resources acquired. Only the wait is needed when both
+    /// queues belong to the same family.
+    pub unsafe fn acquire_released_resources(
+        &self,
+        graphics_queue: &Queue
+    ) -> Result<usize, EngineError> {
+        self.wait_for_uploads()?;
+        let pending = std::mem::take(&mut *self.pending_acquires.lock().unwrap());
+        let buffer_barriers: Vec<vk::BufferMemoryBarrier> = pending.buffers.iter()
+            .map(|(buffer, families)| {
+                families.acquire_buffer_barrier(*buffer, vk::AccessFlags::MEMORY_READ)
+            })
+            .collect();
+        let image_barriers: Vec<vk::ImageMemoryBarrier> = pending.images.iter()
+            .map(|((image, families), acquire)| {
+                families.acquire_image_barrier(
+                    *image,
+                    acquire.subresource_range,
+                    acquire.old_layout,
+                    acquire.new_layout,
+                    vk::AccessFlags::MEMORY_READ)
+            })
+            .collect();
+        let resource_count = buffer_barriers.len() + image_barriers.len();
+        if resource_count == 0 {
+            return Ok(0);
+        }
+
+        self.submit_graphics_barriers(
+            graphics_queue,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            &buffer_barriers,
+            &image_barriers)?;
+
+        Ok(resource_count)
+    }
 
-        // Finish recording commands, create a fence, run the command, wait for fence, clean up
-        self.device.end_command_buffer(self.transfer_command_buffer)
+    /// Record barriers into a single-use command buffer on the graphics queue, submit it, and
+    /// wait for it to complete
+    unsafe fn submit_graphics_barriers(
+        &self,
+        graphics_queue: &Queue,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        buffer_barriers: &[vk::BufferMemoryBarrier],
+        image_barriers: &[vk::ImageMemoryBarrier]
+    ) -> Result<(), EngineError> {
+
+        // Allocate a single-use command buffer and begin recording
+        let command_buffer = graphics_queue.allocate_command_buffer(&self.device)?;
+        let command_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &command_begin_info)
             .map_err(|e| {
-                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
+                EngineError::OpFailed(format!("Error starting barrier command buffer: {:?}", e))
             })?;
-        let fence = self.device
-            .create_fence(&vk::FenceCreateInfo::default(), None)
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            buffer_barriers,
+            image_barriers
+        );
+
+        // Finish recording commands, run them and wait for the graphics timeline to reach them
+        self.device.end_command_buffer(command_buffer)
             .map_err(|e| {
-                EngineError::OpFailed(format!("Error creating fence: {:?}", e))
+                EngineError::OpFailed(format!("Error ending command buffer: {:?}", e))
             })?;
-        transfer_queue.submit_transfer_command_buffer(
+        let timeline_value = self.graphics_timeline.submit(
             &self.device,
-            &self.transfer_command_buffer,
-            &fence)?;
-        self.device
-            .wait_for_fences(&[fence], true, u64::MAX)
-            .map_err(|e| {
-                EngineError::OpFailed(format!("Error waiting for fence: {:?}", e))
-            })?;
-        self.device
-            .destroy_fence(fence, None);
+            graphics_queue,
+            &[command_buffer],
+            &[],
+            &[])?;
+        self.graphics_timeline.wait(&self.device, timeline_value)?;
+        graphics_queue.free_command_buffer(&self.device, command_buffer);
 
         Ok(())
     }
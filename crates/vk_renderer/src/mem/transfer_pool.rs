@@ -0,0 +1,210 @@
+use crate::mem::staging_ring::StagingRing;
+use crate::mem::MemoryAllocator;
+use crate::{Queue, SubmissionTimeline};
+use error::EngineError;
+use ash::{Device, vk};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::thread::ThreadId;
+
+/// TransferContext struct
+/// Resources needed to record and submit transfers from one thread: a command pool (which must
+/// not be used from more than one thread at a time), a command buffer from it for transfers that
+/// are waited on, and a staging ring if the device needs one
+pub(crate) struct TransferContext {
+    command_pool: vk::CommandPool,
+    pub(crate) command_buffer: vk::CommandBuffer,
+    pub(crate) staging_ring: Option<StagingRing>
+}
+
+impl TransferContext {
+
+    unsafe fn new(
+        device: &Device,
+        queue_family_index: u32,
+        staging_memory_type: Option<u32>
+    ) -> Result<Self, EngineError> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = device
+            .create_command_pool(&pool_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating transfer command pool: {:?}", e))
+            })?;
+        let command_buffer_alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .command_buffer_count(1);
+        let command_buffer = device
+            .allocate_command_buffers(&command_buffer_alloc_info)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error allocating command buffer: {:?}", e))
+            })?[0];
+        let staging_ring = match staging_memory_type {
+            Some(memory_type) => Some(StagingRing::new(device, command_pool, memory_type)?),
+            None => None
+        };
+        Ok(Self {
+            command_pool,
+            command_buffer,
+            staging_ring
+        })
+    }
+
+    unsafe fn destroy(&mut self, device: &Device, timeline: &SubmissionTimeline) {
+        if let Some(staging_ring) = &mut self.staging_ring {
+            staging_ring.destroy(device, timeline);
+        }
+        device.destroy_command_pool(self.command_pool, None);
+    }
+}
+
+/// TransferContextPool struct
+/// Transfer contexts keyed by the thread that uses them, so that worker threads can upload
+/// resources in parallel. Submissions to the shared transfer queue are serialised.
+pub(crate) struct TransferContextPool {
+    queue_family_index: u32,
+    staging_memory_type: Option<u32>,
+    contexts: Mutex<HashMap<ThreadId, TransferContext>>,
+    submit_lock: Mutex<()>
+}
+
+impl TransferContextPool {
+
+    /// Create a new instance, with a context ready for the creating thread
+    pub(crate) unsafe fn new(
+        device: &Device,
+        queue_family_index: u32,
+        staging_memory_type: Option<u32>
+    ) -> Result<Self, EngineError> {
+        let mut contexts = HashMap::new();
+        contexts.insert(
+            std::thread::current().id(),
+            TransferContext::new(device, queue_family_index, staging_memory_type)?);
+        Ok(Self {
+            queue_family_index,
+            staging_memory_type,
+            contexts: Mutex::new(contexts),
+            submit_lock: Mutex::new(())
+        })
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device, timeline: &SubmissionTimeline) {
+        let mut contexts = self.contexts.lock().unwrap();
+        for (_, mut context) in contexts.drain() {
+            context.destroy(device, timeline);
+        }
+    }
+}
+
+/// TransferContextGuard struct
+/// Exclusive use of the current thread's transfer context, returned to the pool when dropped
+pub(crate) struct TransferContextGuard<'a> {
+    pool: &'a TransferContextPool,
+    context: Option<TransferContext>
+}
+
+impl<'a> Deref for TransferContextGuard<'a> {
+    type Target = TransferContext;
+
+    fn deref(&self) -> &TransferContext {
+        self.context.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for TransferContextGuard<'a> {
+    fn deref_mut(&mut self) -> &mut TransferContext {
+        self.context.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for TransferContextGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(context) = self.context.take() {
+            self.pool.contexts.lock().unwrap()
+                .insert(std::thread::current().id(), context);
+        }
+    }
+}
+
+impl MemoryAllocator {
+
+    /// Take the current thread's transfer context, creating one if this thread has not made a
+    /// transfer before
+    pub(crate) unsafe fn transfer_context(&self) -> Result<TransferContextGuard<'_>, EngineError> {
+        let pool = &self.transfer_contexts;
+        let existing = pool.contexts.lock().unwrap()
+            .remove(&std::thread::current().id());
+        let context = match existing {
+            Some(context) => context,
+            None => TransferContext::new(
+                &self.device,
+                pool.queue_family_index,
+                pool.staging_memory_type)?
+        };
+        Ok(TransferContextGuard {
+            pool,
+            context: Some(context)
+        })
+    }
+
+    /// Submit a transfer command buffer, holding the queue lock for the duration of the submit,
+    /// and return the transfer timeline value that it signals
+    pub(crate) unsafe fn submit_transfer(
+        &self,
+        transfer_queue: &Queue,
+        command_buffer: vk::CommandBuffer
+    ) -> Result<u64, EngineError> {
+        let _lock = self.transfer_contexts.submit_lock.lock().unwrap();
+        self.transfer_timeline.submit(&self.device, transfer_queue, &[command_buffer], &[], &[])
+    }
+
+    /// Submit a transfer command buffer that signals a fence instead of advancing the transfer
+    /// timeline, holding the queue lock for the duration of the submit
+    pub(crate) unsafe fn submit_transfer_with_fence(
+        &self,
+        transfer_queue: &Queue,
+        command_buffer: &vk::CommandBuffer,
+        fence: &vk::Fence
+    ) -> Result<(), EngineError> {
+        let _lock = self.transfer_contexts.submit_lock.lock().unwrap();
+        transfer_queue.submit_transfer_command_buffer(&self.device, command_buffer, fence)
+    }
+
+    /// Free the transfer context belonging to the calling thread, if it has one. Worker threads
+    /// should call this before exiting; otherwise the context is kept until the allocator is
+    /// destroyed.
+    pub unsafe fn release_thread_transfer_context(&self) {
+        let existing = self.transfer_contexts.contexts.lock().unwrap()
+            .remove(&std::thread::current().id());
+        if let Some(mut context) = existing {
+            context.destroy(&self.device, &self.transfer_timeline);
+        }
+    }
+
+    /// Wait for uploads still in flight from every thread's staging ring. Uploads made through a
+    /// staging ring are submitted without waiting, so this must be done before the uploaded
+    /// resources are used on another queue or destroyed. Transfer contexts in use by other
+    /// threads at the time of the call are skipped.
+    pub unsafe fn wait_for_uploads(&self) -> Result<(), EngineError> {
+        let mut contexts = self.transfer_contexts.contexts.lock().unwrap();
+        for context in contexts.values_mut() {
+            if let Some(staging_ring) = &mut context.staging_ring {
+                staging_ring.retire_completed(&self.device, &self.transfer_timeline, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of uploads from the calling thread that have been submitted but not yet seen to
+    /// complete
+    pub fn uploads_in_flight(&self) -> usize {
+        let contexts = self.transfer_contexts.contexts.lock().unwrap();
+        match contexts.get(&std::thread::current().id()) {
+            Some(TransferContext { staging_ring: Some(staging_ring), .. }) =>
+                staging_ring.in_flight_count(),
+            _ => 0
+        }
+    }
+}
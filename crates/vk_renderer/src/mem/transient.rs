@@ -0,0 +1,270 @@
+use crate::mem::{MemoryAllocator, MemoryAllocation, ManagesBufferMemory};
+use crate::Queue;
+use error::EngineError;
+use ash::{Device, vk};
+
+/// Alignment of every transient allocation. This is the largest value that the Vulkan spec
+/// permits for minUniformBufferOffsetAlignment, so allocations are always usable as dynamic
+/// uniform buffer offsets.
+const TRANSIENT_ALLOCATION_ALIGNMENT: usize = 256;
+
+/// TransientFrame struct
+/// One persistently-mapped buffer, used for the transient data of a single frame
+struct TransientFrame {
+    buffer: vk::Buffer,
+    allocation: MemoryAllocation,
+    mapped_ptr: *mut u8,
+    offset: usize
+}
+
+/// TransientAllocation struct
+/// A region of a transient buffer, valid until the same frame slot is begun again
+#[derive(Copy, Clone, Debug)]
+pub struct TransientAllocation {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize
+}
+
+/// StagedBufferUpdate struct
+/// Data written into a transient buffer while preparing a frame, to be copied into part of
+/// another buffer ahead of the frame's own commands
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct StagedBufferUpdate {
+    pub source: TransientAllocation,
+    pub buffer: vk::Buffer,
+    pub offset_bytes: vk::DeviceSize
+}
+
+/// TransientAllocator struct
+/// Linear allocator for per-frame data such as dynamic uniform data and debug vertices. Each
+/// frame slot owns a host-visible buffer that is bumped through during the frame and reset when
+/// the slot is next begun, so nothing is allocated or freed while rendering. VkContext keeps one
+/// with a slot per frame in flight, staging the uniform updates made while preparing a frame.
+pub struct TransientAllocator {
+    frames: Vec<TransientFrame>,
+    capacity_bytes: usize,
+    current_frame: usize
+}
+
+impl MemoryAllocator {
+
+    /// Create a transient allocator with one buffer of the given capacity per frame slot. The
+    /// frame count should match the number of frames that may be in flight at once, and must be
+    /// at least one.
+    pub unsafe fn create_transient_allocator(
+        &self,
+        transfer_queue: &Queue,
+        frame_count: usize,
+        capacity_bytes: usize
+    ) -> Result<TransientAllocator, EngineError> {
+        if frame_count == 0 {
+            return Err(EngineError::UserError(
+                String::from("Transient allocator needs at least one frame slot")));
+        }
+        let mut transient_allocator = TransientAllocator {
+            frames: Vec::with_capacity(frame_count),
+            capacity_bytes,
+            current_frame: 0
+        };
+        for _ in 0..frame_count {
+            match self.create_transient_frame(transfer_queue, capacity_bytes) {
+                Ok(frame) => transient_allocator.frames.push(frame),
+                Err(e) => {
+                    let _ = transient_allocator.destroy(self);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(transient_allocator)
+    }
+
+    unsafe fn create_transient_frame(
+        &self,
+        transfer_queue: &Queue,
+        capacity_bytes: usize
+    ) -> Result<TransientFrame, EngineError> {
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(capacity_bytes as u64)
+            .usage(
+                vk::BufferUsageFlags::UNIFORM_BUFFER |
+                vk::BufferUsageFlags::VERTEX_BUFFER |
+                vk::BufferUsageFlags::INDEX_BUFFER |
+                vk::BufferUsageFlags::TRANSFER_SRC)
+            .build();
+        let buffer = self.device.create_buffer(&buffer_create_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating buffer: {:?}", e))
+            })?;
+        let allocation = match self.back_buffer_memory(
+            transfer_queue,
+            &buffer,
+            true,
+            None,
+            capacity_bytes
+        ) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                self.device.destroy_buffer(buffer, None);
+                return Err(e);
+            }
+        };
+        self.set_allocation_name(&allocation, "Transient buffer");
+        let mapped_ptr = match self.map_memory::<u8>(&allocation) {
+            Ok(mapped_ptr) => mapped_ptr,
+            Err(e) => {
+                let _ = self.destroy_buffer(buffer, &allocation);
+                return Err(e);
+            }
+        };
+        Ok(TransientFrame {
+            buffer,
+            allocation,
+            mapped_ptr,
+            offset: 0
+        })
+    }
+}
+
+/// Find where an allocation of the given size starts in a frame's buffer, after the bytes used
+/// so far and aligned for use as a dynamic uniform buffer offset, or None if it doesn't fit
+pub(crate) fn transient_offset(
+    used_bytes: usize,
+    size_bytes: usize,
+    capacity_bytes: usize
+) -> Option<usize> {
+    let offset = used_bytes.checked_next_multiple_of(TRANSIENT_ALLOCATION_ALIGNMENT)?;
+    match offset.checked_add(size_bytes)? <= capacity_bytes {
+        true => Some(offset),
+        false => None
+    }
+}
+
+impl TransientAllocator {
+
+    /// Switch to the given frame slot, wrapping around the number of slots, and discard
+    /// everything previously allocated from it. The device must have finished with the slot's
+    /// previous frame. Does nothing once destroyed.
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        if self.frames.is_empty() {
+            return;
+        }
+        self.current_frame = frame_index % self.frames.len();
+        self.frames[self.current_frame].offset = 0;
+    }
+
+    /// Reserve a region of the current frame's buffer, returning it along with a pointer for
+    /// writing into it
+    pub unsafe fn allocate(
+        &mut self,
+        size_bytes: usize
+    ) -> Result<(TransientAllocation, *mut u8), EngineError> {
+        let Some(frame) = self.frames.get_mut(self.current_frame) else {
+            return Err(EngineError::UserError(
+                String::from("Transient allocator used after being destroyed")));
+        };
+        let Some(offset) = transient_offset(frame.offset, size_bytes, self.capacity_bytes) else {
+            return Err(EngineError::OpFailed(format!(
+                "Transient allocator out of space: requested {}, used {}, capacity {}",
+                size_bytes,
+                frame.offset,
+                self.capacity_bytes)));
+        };
+        frame.offset = offset + size_bytes;
+        let allocation = TransientAllocation {
+            buffer: frame.buffer,
+            offset: offset as vk::DeviceSize,
+            size: size_bytes as vk::DeviceSize
+        };
+        Ok((allocation, frame.mapped_ptr.add(offset)))
+    }
+
+    /// Copy a slice of data into a new region of the current frame's buffer
+    pub unsafe fn push<T: Copy>(&mut self, data: &[T]) -> Result<TransientAllocation, EngineError> {
+        let size_bytes = std::mem::size_of_val(data);
+        let (allocation, dst_ptr) = self.allocate(size_bytes)?;
+        dst_ptr.copy_from_nonoverlapping(data.as_ptr() as *const u8, size_bytes);
+        Ok(allocation)
+    }
+
+    /// Stage an update to part of a buffer, copying the data into the current frame's buffer;
+    /// record_staged_updates then copies it into place
+    pub(crate) unsafe fn stage_update(
+        &mut self,
+        buffer: vk::Buffer,
+        offset_bytes: usize,
+        data: &[u8]
+    ) -> Result<StagedBufferUpdate, EngineError> {
+        let source = self.push(data)?;
+        Ok(StagedBufferUpdate {
+            source,
+            buffer,
+            offset_bytes: offset_bytes as vk::DeviceSize
+        })
+    }
+
+    /// Flush what the host has written into the current frame's buffer, where its memory is not
+    /// host-coherent, so that the device reads it
+    pub unsafe fn flush(&self, allocator: &MemoryAllocator) -> Result<(), EngineError> {
+        match self.frames.get(self.current_frame) {
+            Some(frame) if frame.offset > 0 => allocator.flush_mapped_range(
+                &frame.allocation,
+                0,
+                frame.offset as vk::DeviceSize),
+            _ => Ok(())
+        }
+    }
+
+    /// Number of bytes used so far in the current frame, including alignment padding
+    pub fn used_bytes(&self) -> usize {
+        self.frames.get(self.current_frame).map_or(0, |frame| frame.offset)
+    }
+
+    /// Release all buffers and their memory
+    pub unsafe fn destroy(&mut self, allocator: &MemoryAllocator) -> Result<(), EngineError> {
+        for frame in self.frames.drain(..) {
+            allocator.unmap_memory(&frame.allocation)?;
+            allocator.destroy_buffer(frame.buffer, &frame.allocation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Record copying staged updates into their buffers, followed by a barrier that makes them
+/// visible to shaders reading those buffers as uniforms
+pub(crate) unsafe fn record_staged_updates(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    updates: &[StagedBufferUpdate]
+) {
+    if updates.is_empty() {
+        return;
+    }
+    for update in updates.iter() {
+        let region = vk::BufferCopy {
+            src_offset: update.source.offset,
+            dst_offset: update.offset_bytes,
+            size: update.source.size
+        };
+        device.cmd_copy_buffer(command_buffer, update.source.buffer, update.buffer, &[region]);
+    }
+    let barriers: Vec<vk::BufferMemoryBarrier> = updates.iter()
+        .map(|update| vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::UNIFORM_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(update.buffer)
+            .offset(update.offset_bytes)
+            .size(update.source.size)
+            .build())
+        .collect();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &barriers,
+        &[]);
+}
@@ -0,0 +1,259 @@
+use crate::{VkContext, ImageWrapper, SamplerWrapper, StreamedTexture};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use ash::{Device, vk};
+
+/// Index of the set that a bindless texture array is bound as, after a pipeline's own set
+pub const BINDLESS_TEXTURE_SET: u32 = 1;
+
+/// Size of the push constant that selects a material's texture, at offset zero
+pub const MATERIAL_INDEX_PUSH_CONSTANT_SIZE: u32 = std::mem::size_of::<u32>() as u32;
+
+/// Shader stages that may read the material index push constant
+pub const MATERIAL_INDEX_PUSH_CONSTANT_STAGES: vk::ShaderStageFlags =
+    vk::ShaderStageFlags::from_raw(
+        vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw());
+
+/// BindlessTextureSetCreationData struct
+/// Information needed to create a bindless texture array. The textures, looked up by resource
+/// index, fill the array in the order given, so that each one's position is the material index
+/// that shaders use to sample it; the rest of the array, up to its capacity, is left unbound for
+/// textures written later. Every texture is sampled through the referenced sampler.
+pub struct BindlessTextureSetCreationData {
+    pub capacity: u32,
+    pub sampler_index: u32,
+    pub texture_indices: Vec<u32>
+}
+
+/// BindlessMaterial struct
+/// Selects the texture that a pipeline samples from a bindless texture array, looked up by
+/// resource index. The pipeline's layout must have been created with the same array.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BindlessMaterial {
+    pub texture_set_index: u32,
+    pub material_index: u32
+}
+
+/// BindlessTextureSet struct
+/// A descriptor set holding one array of combined image samplers, for scenes with many materials.
+/// Pipeline layouts created with it bind it as BINDLESS_TEXTURE_SET and take a material index as
+/// a push constant, which shaders use to index the array, as in:
+///     layout(set = 1, binding = 0) uniform sampler2D textures[];
+///     layout(push_constant) uniform Material { uint index; } material;
+///     texture(textures[nonuniformEXT(material.index)], uv)
+/// Needs the DescriptorIndexing feature. Entries may be rewritten while the set is bound, so long
+/// as no pending command buffer reads them.
+pub struct BindlessTextureSet {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    capacity: u32
+}
+
+impl Resource<VkContext> for BindlessTextureSet {
+    type CreationData = BindlessTextureSetCreationData;
+
+    fn create(
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &BindlessTextureSetCreationData
+    ) -> Result<Self, EngineError> {
+        if !loader.supports_descriptor_indexing() {
+            return Err(EngineError::Compatibility(
+                "Bindless textures need the DescriptorIndexing feature".to_owned()));
+        }
+        if data.capacity == 0 || data.capacity > loader.get_max_bindless_textures() {
+            return Err(EngineError::UserError(format!(
+                "Bindless texture capacity {} not within 1..={}",
+                data.capacity,
+                loader.get_max_bindless_textures())));
+        }
+        if data.texture_indices.len() > data.capacity as usize {
+            return Err(EngineError::UserError(format!(
+                "{} textures given for a bindless texture array of capacity {}",
+                data.texture_indices.len(),
+                data.capacity)));
+        }
+        let sampler = ecs
+            .get_item::<SamplerWrapper>(Handle::for_resource(data.sampler_index))
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No sampler at index {}", data.sampler_index)))?
+            .get_sampler();
+        let image_views = data.texture_indices.iter()
+            .map(|texture_index| texture_image_view(ecs, *texture_index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut texture_set = BindlessTextureSet {
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            sampler,
+            capacity: data.capacity
+        };
+        unsafe {
+            if let Err(e) = texture_set.create_objects(loader) {
+                texture_set.release(loader);
+                return Err(e);
+            }
+            for (array_index, image_view) in image_views.iter().enumerate() {
+                texture_set.write_texture(loader, array_index as u32, *image_view)?;
+            }
+        }
+        Ok(texture_set)
+    }
+
+    fn release(&self, loader: &VkContext) {
+        unsafe {
+            let device = &loader.device;
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+impl BindlessTextureSet {
+
+    unsafe fn create_objects(&mut self, context: &VkContext) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        let layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(self.capacity)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let binding_flags = [
+            vk::DescriptorBindingFlags::PARTIALLY_BOUND |
+                vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+        ];
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags);
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&layout_bindings)
+            .push_next(&mut binding_flags_info);
+        self.descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating bindless descriptor set layout: {:?}", e)))?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: self.capacity
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating bindless descriptor pool: {:?}", e)))?;
+
+        let set_layouts = [self.descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        self.descriptor_set = device.allocate_descriptor_sets(&allocate_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating bindless descriptor set: {:?}", e)))?[0];
+        Ok(())
+    }
+
+    /// Getter for the layout, for creating pipeline layouts that use this array
+    pub fn get_descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    /// Getter for the descriptor set
+    pub fn get_descriptor_set(&self) -> vk::DescriptorSet {
+        self.descriptor_set
+    }
+
+    /// Number of textures that the array can hold
+    pub fn get_capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Write a texture into the array at the given material index, replacing any already there
+    pub unsafe fn write_texture(
+        &self,
+        context: &VkContext,
+        material_index: u32,
+        image_view: vk::ImageView
+    ) -> Result<(), EngineError> {
+        check_material_index(material_index, self.capacity)?;
+        let image_infos = [vk::DescriptorImageInfo {
+            image_view,
+            sampler: self.sampler,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(material_index)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        context.device.update_descriptor_sets(&[write], &[]);
+        Ok(())
+    }
+
+    /// Record binding the array as BINDLESS_TEXTURE_SET, for a pipeline layout created with it
+    pub unsafe fn record_bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout
+    ) {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            BINDLESS_TEXTURE_SET,
+            &[self.descriptor_set],
+            &[]);
+    }
+}
+
+/// Record selecting the material whose texture following draws sample, for a pipeline layout
+/// created with a bindless texture array
+pub unsafe fn record_material_index(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    material_index: u32
+) {
+    device.cmd_push_constants(
+        command_buffer,
+        pipeline_layout,
+        MATERIAL_INDEX_PUSH_CONSTANT_STAGES,
+        0,
+        &material_index.to_ne_bytes());
+}
+
+/// Check that a material index selects an entry of a bindless texture array of the given capacity
+pub(crate) fn check_material_index(material_index: u32, capacity: u32) -> Result<(), EngineError> {
+    match material_index < capacity {
+        true => Ok(()),
+        false => Err(EngineError::UserError(format!(
+            "Material index {} out of range for bindless texture array of capacity {}",
+            material_index,
+            capacity)))
+    }
+}
+
+fn texture_image_view(
+    ecs: &EcsManager<VkContext>,
+    texture_index: u32
+) -> Result<vk::ImageView, EngineError> {
+    let texture_handle = Handle::for_resource(texture_index);
+    match ecs.get_item::<ImageWrapper>(texture_handle) {
+        Some(image) => Ok(image.image_view),
+        None => Ok(ecs
+            .get_item::<StreamedTexture>(texture_handle)
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No texture at index {}", texture_index)))?
+            .get_image()
+            .image_view)
+    }
+}
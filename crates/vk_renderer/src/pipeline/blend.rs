@@ -0,0 +1,93 @@
+use crate::RenderQueue;
+use ash::vk;
+
+/// BlendFactors struct
+/// The factors and operations combining a fragment's color and alpha with what is already in the
+/// framebuffer, for blending that none of the stock blend modes describe
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlendFactors {
+    pub src_color: vk::BlendFactor,
+    pub dst_color: vk::BlendFactor,
+    pub color_op: vk::BlendOp,
+    pub src_alpha: vk::BlendFactor,
+    pub dst_alpha: vk::BlendFactor,
+    pub alpha_op: vk::BlendOp
+}
+
+/// BlendMode enum
+/// How a pipeline's output combines with the color already drawn. Opaque output replaces it.
+/// Alpha blending mixes in straight (non-premultiplied) color by its alpha, while premultiplied
+/// alpha expects color already multiplied by alpha, which filters correctly at texture edges.
+/// Additive output brightens what is behind it by the color weighted by its alpha, suiting
+/// particles such as fire and sparks, which can then be drawn in any order.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BlendMode {
+    Opaque,
+    AlphaBlend,
+    Additive,
+    PremultipliedAlpha,
+    Custom(BlendFactors)
+}
+
+impl BlendMode {
+
+    /// The blend mode that suits steps in a render queue when nothing else is needed
+    pub fn for_render_queue(render_queue: RenderQueue) -> Self {
+        match render_queue.blends() {
+            true => BlendMode::AlphaBlend,
+            false => BlendMode::Opaque
+        }
+    }
+
+    /// The factors and operations for this mode, or None if it does not blend
+    pub fn factors(&self) -> Option<BlendFactors> {
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => return None,
+            BlendMode::Custom(factors) => return Some(*factors),
+            BlendMode::AlphaBlend => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+            ),
+            BlendMode::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ZERO,
+                vk::BlendFactor::ONE
+            ),
+            BlendMode::PremultipliedAlpha => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+            )
+        };
+        Some(BlendFactors {
+            src_color,
+            dst_color,
+            color_op: vk::BlendOp::ADD,
+            src_alpha,
+            dst_alpha,
+            alpha_op: vk::BlendOp::ADD
+        })
+    }
+
+    /// The color blend state for an attachment written with this mode
+    pub fn attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        let builder = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        match self.factors() {
+            None => builder.blend_enable(false).build(),
+            Some(factors) => builder
+                .blend_enable(true)
+                .src_color_blend_factor(factors.src_color)
+                .dst_color_blend_factor(factors.dst_color)
+                .color_blend_op(factors.color_op)
+                .src_alpha_blend_factor(factors.src_alpha)
+                .dst_alpha_blend_factor(factors.dst_alpha)
+                .alpha_blend_op(factors.alpha_op)
+                .build()
+        }
+    }
+}
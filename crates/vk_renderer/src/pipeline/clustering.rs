@@ -0,0 +1,403 @@
+use crate::{
+    VkContext, BufferWrapper, DescriptorBinding, ComputePipelineWrapper,
+    ComputePipelineCreationData, ComputeAccess, SubmittedCommands, work_group_count
+};
+use ecs::{EcsManager, resource::Resource};
+use error::EngineError;
+use ash::{Device, vk};
+use vk_shader_macros::include_glsl;
+
+/// Compute shader that bins lights into clusters, for creating the shader module that
+/// ClusteredLights is created with
+pub const LIGHT_CLUSTER_SHADER: &[u32] =
+    include_glsl!("../../resources/shaders/light_cluster.comp");
+
+/// Binding of the light list within descriptor sets whose layout includes light clusters. These
+/// come after any input attachments, so at most five of those can be used alongside them.
+pub const LIGHT_LIST_BINDING: u32 = 8;
+
+/// Binding of the per-cluster light index lists within descriptor sets whose layout includes
+/// light clusters
+pub const LIGHT_CLUSTER_BINDING: u32 = 9;
+
+/// Most lights that can be binned in one frame
+pub const MAX_CLUSTERED_LIGHTS: usize = 512;
+
+/// Most lights that any one cluster lists; further lights touching it are left out. Each cluster
+/// is laid out as a count followed by this many indices, which shaders must agree with.
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 31;
+
+/// Work group size of the binning shader, with one invocation per cluster
+const LIGHT_CLUSTER_WORK_GROUP_SIZE: u32 = 64;
+
+/// Words of the cluster buffer taken by each cluster
+const CLUSTER_STRIDE: usize = MAX_LIGHTS_PER_CLUSTER + 1;
+
+/// PointLight struct
+/// A light shining in all directions from a point, fading out to nothing at its radius. The
+/// position is in view space, as seen by the camera the light list is written for. Laid out as
+/// two vec4s in a storage buffer.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32
+}
+
+/// LightListHeader struct
+/// What precedes the lights in the light list buffer: the projection scale in x and y with the
+/// near and far planes, then the grid dimensions with the number of lights
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LightListHeader {
+    projection: [f32; 4],
+    grid: [u32; 4]
+}
+
+/// ClusterGrid struct
+/// Division of the view frustum into clusters, or froxels: evenly across the screen in x and y,
+/// and exponentially in depth between the near and far planes, so that clusters further away
+/// are no thinner relative to their size than near ones. View space is taken to look down +Z,
+/// with the projection scaling x and y by its first two diagonal entries before dividing by
+/// depth, as with PlayerCamera. Clusters are indexed x first, then y, then depth.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ClusterGrid {
+    pub dimensions: [u32; 3],
+    pub near: f32,
+    pub far: f32,
+    pub projection_scale: [f32; 2]
+}
+
+impl ClusterGrid {
+
+    /// Grid for a perspective projection matrix, given in column-major order, and the near and
+    /// far planes it was made with
+    pub fn for_projection(
+        dimensions: [u32; 3],
+        near: f32,
+        far: f32,
+        projection: [[f32; 4]; 4]
+    ) -> Self {
+        Self {
+            dimensions,
+            near,
+            far,
+            projection_scale: [projection[0][0], projection[1][1]]
+        }
+    }
+
+    pub fn cluster_count(&self) -> usize {
+        self.dimensions.iter().map(|d| *d as usize).product()
+    }
+
+    /// Index of a cluster in the cluster buffer, from its position in the grid
+    pub fn cluster_index(&self, cluster: [u32; 3]) -> usize {
+        let [x_count, y_count, _] = self.dimensions;
+        cluster[0] as usize +
+            cluster[1] as usize * x_count as usize +
+            cluster[2] as usize * x_count as usize * y_count as usize
+    }
+
+    /// Depth slice containing a view-space depth, if between the near and far planes
+    pub fn depth_slice(&self, depth: f32) -> Option<u32> {
+        if depth < self.near || depth >= self.far {
+            return None;
+        }
+        let slice_count = self.dimensions[2];
+        let slice = (depth / self.near).ln() / (self.far / self.near).ln() * slice_count as f32;
+        Some((slice as u32).min(slice_count - 1))
+    }
+
+    /// Nearest and furthest view-space depths of a depth slice
+    pub fn slice_depth_range(&self, slice: u32) -> (f32, f32) {
+        let slice_count = self.dimensions[2] as f32;
+        let ratio = self.far / self.near;
+        (
+            self.near * ratio.powf(slice as f32 / slice_count),
+            self.near * ratio.powf((slice + 1) as f32 / slice_count)
+        )
+    }
+
+    /// Cluster containing a view-space position, if it is within the frustum
+    pub fn cluster_at(&self, position: [f32; 3]) -> Option<[u32; 3]> {
+        let slice = self.depth_slice(position[2])?;
+        let mut cluster = [0, 0, slice];
+        for axis in 0..2 {
+            let ndc = self.projection_scale[axis] * position[axis] / position[2];
+            if !(-1.0..=1.0).contains(&ndc) {
+                return None;
+            }
+            let tile_count = self.dimensions[axis];
+            let tile = ((ndc * 0.5 + 0.5) * tile_count as f32) as u32;
+            cluster[axis] = tile.min(tile_count - 1);
+        }
+        Some(cluster)
+    }
+
+    /// View-space bounding box of a cluster, as its minimum and maximum corners, taken from the
+    /// corners of its tile at either end of its depth slice
+    pub fn cluster_bounds(&self, cluster: [u32; 3]) -> ([f32; 3], [f32; 3]) {
+        let (depth_min, depth_max) = self.slice_depth_range(cluster[2]);
+        let mut bounds_min = [0.0, 0.0, depth_min];
+        let mut bounds_max = [0.0, 0.0, depth_max];
+        for axis in 0..2 {
+            let tile_count = self.dimensions[axis] as f32;
+            let ndc_min = cluster[axis] as f32 / tile_count * 2.0 - 1.0;
+            let ndc_max = (cluster[axis] + 1) as f32 / tile_count * 2.0 - 1.0;
+            let scale = self.projection_scale[axis];
+            let corners = [
+                ndc_min * depth_min / scale,
+                ndc_min * depth_max / scale,
+                ndc_max * depth_min / scale,
+                ndc_max * depth_max / scale
+            ];
+            bounds_min[axis] = corners.iter().copied().fold(f32::INFINITY, f32::min);
+            bounds_max[axis] = corners.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        }
+        (bounds_min, bounds_max)
+    }
+
+    /// Whether a light's sphere touches a cluster's bounding box
+    pub fn light_touches_cluster(&self, light: &PointLight, cluster: [u32; 3]) -> bool {
+        let (bounds_min, bounds_max) = self.cluster_bounds(cluster);
+        let distance_squared: f32 = (0..3)
+            .map(|axis| {
+                let closest = light.position[axis].clamp(bounds_min[axis], bounds_max[axis]);
+                let offset = light.position[axis] - closest;
+                offset * offset
+            })
+            .sum();
+        distance_squared <= light.radius * light.radius
+    }
+
+    /// Bin lights into every cluster as the binning shader does, giving each cluster's light
+    /// indices in order, up to MAX_LIGHTS_PER_CLUSTER
+    pub fn bin_lights(&self, lights: &[PointLight]) -> Vec<Vec<u32>> {
+        let mut clusters = vec![vec![]; self.cluster_count()];
+        for z in 0..self.dimensions[2] {
+            for y in 0..self.dimensions[1] {
+                for x in 0..self.dimensions[0] {
+                    let cluster = [x, y, z];
+                    let indices = &mut clusters[self.cluster_index(cluster)];
+                    for (index, light) in lights.iter().enumerate() {
+                        if indices.len() == MAX_LIGHTS_PER_CLUSTER {
+                            break;
+                        }
+                        if self.light_touches_cluster(light, cluster) {
+                            indices.push(index as u32);
+                        }
+                    }
+                }
+            }
+        }
+        clusters
+    }
+}
+
+/// ClusteredLightsCreationData struct
+/// Information needed to create the buffers and binning pipeline for lights binned into a grid
+/// of the given dimensions. The shader module is looked up by index, and should have been
+/// created from LIGHT_CLUSTER_SHADER with the compute shader stage.
+pub struct ClusteredLightsCreationData {
+    pub shader_index: u32,
+    pub dimensions: [u32; 3]
+}
+
+/// ClusteredLights struct
+/// Lights binned into a cluster grid by a compute pass, for shading to loop over only the lights
+/// near each fragment. The light list is written from the host while preparing a frame, and
+/// the binning dispatch then fills the list of each cluster's lights. Both are storage buffers,
+/// bound to graphics pipelines at LIGHT_LIST_BINDING and LIGHT_CLUSTER_BINDING; being written
+/// every frame, there should be one of these per swapchain image.
+pub struct ClusteredLights {
+    dimensions: [u32; 3],
+    light_buffer: BufferWrapper,
+    cluster_buffer: BufferWrapper,
+    pipeline: ComputePipelineWrapper
+}
+
+impl Resource<VkContext> for ClusteredLights {
+    type CreationData = ClusteredLightsCreationData;
+
+    fn create(
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &ClusteredLightsCreationData
+    ) -> Result<Self, EngineError> {
+        if data.dimensions.contains(&0) {
+            return Err(EngineError::UserError(
+                "Light cluster grid requested with no clusters".to_owned()));
+        }
+        let cluster_count: usize = data.dimensions.iter().map(|d| *d as usize).product();
+        let header_words = std::mem::size_of::<LightListHeader>() / std::mem::size_of::<f32>();
+        let light_words = std::mem::size_of::<PointLight>() / std::mem::size_of::<f32>();
+        let light_data = vec![0u32; header_words + light_words * MAX_CLUSTERED_LIGHTS];
+        let cluster_data = vec![0u32; cluster_count * CLUSTER_STRIDE];
+        let light_buffer = unsafe { BufferWrapper::new_storage(loader, &light_data)? };
+        let cluster_buffer = match unsafe { BufferWrapper::new_storage(loader, &cluster_data) } {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                light_buffer.release(loader);
+                return Err(e);
+            }
+        };
+
+        let creation_data = ComputePipelineCreationData {
+            shader_index: data.shader_index,
+            descriptor_types: vec![
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER
+            ],
+            push_constant_size_bytes: 0,
+            specialization_constants: vec![]
+        };
+        let pipeline = match ComputePipelineWrapper::create(loader, ecs, &creation_data) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                cluster_buffer.release(loader);
+                light_buffer.release(loader);
+                return Err(e);
+            }
+        };
+        let lights = ClusteredLights {
+            dimensions: data.dimensions,
+            light_buffer,
+            cluster_buffer,
+            pipeline
+        };
+        unsafe {
+            lights.pipeline.write_bindings(loader, &[
+                (0, lights.light_list_binding()),
+                (1, lights.cluster_binding())
+            ]);
+        }
+        Ok(lights)
+    }
+
+    fn release(&self, loader: &VkContext) {
+        self.pipeline.release(loader);
+        self.cluster_buffer.release(loader);
+        self.light_buffer.release(loader);
+    }
+}
+
+impl ClusteredLights {
+
+    pub fn get_dimensions(&self) -> [u32; 3] {
+        self.dimensions
+    }
+
+    /// Replace the lights to bin, given in view space for the grid's projection. The buffer may
+    /// be in use by submitted frames, so this should be done while preparing a frame, as with
+    /// uniform buffers.
+    pub unsafe fn write_lights(
+        &self,
+        context: &VkContext,
+        grid: &ClusterGrid,
+        lights: &[PointLight]
+    ) -> Result<(), EngineError> {
+        if grid.dimensions != self.dimensions {
+            return Err(EngineError::UserError(format!(
+                "Light cluster grid of {:?} written to clusters of {:?}",
+                grid.dimensions,
+                self.dimensions)));
+        }
+        if lights.len() > MAX_CLUSTERED_LIGHTS {
+            return Err(EngineError::UserError(format!(
+                "{} lights given for binning, at most {} supported",
+                lights.len(),
+                MAX_CLUSTERED_LIGHTS)));
+        }
+        let header = LightListHeader {
+            projection: [
+                grid.projection_scale[0],
+                grid.projection_scale[1],
+                grid.near,
+                grid.far
+            ],
+            grid: [
+                grid.dimensions[0],
+                grid.dimensions[1],
+                grid.dimensions[2],
+                lights.len() as u32
+            ]
+        };
+        let (allocator, _) = context.get_mem_allocator();
+        let header_size_bytes = std::mem::size_of::<LightListHeader>();
+        self.light_buffer.update(
+            allocator,
+            0,
+            &header as *const LightListHeader as *const u8,
+            header_size_bytes)?;
+        self.light_buffer.update(
+            allocator,
+            header_size_bytes as isize,
+            lights.as_ptr() as *const u8,
+            std::mem::size_of_val(lights))
+    }
+
+    /// Record the binning dispatch into commands for the graphics queue, ahead of the rendering
+    /// that reads the clusters, which it is made visible to
+    pub unsafe fn record_binning(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer
+    ) -> Result<(), EngineError> {
+        self.pipeline.record_dispatch(
+            device,
+            command_buffer,
+            self.group_counts(),
+            &[],
+            &[],
+            true)
+    }
+
+    /// Submit the binning dispatch as async compute, which the frame being prepared waits for
+    /// before shading fragments; see VkContext::dispatch_async_compute
+    pub unsafe fn dispatch_async(
+        &self,
+        context: &VkContext
+    ) -> Result<SubmittedCommands, EngineError> {
+        context.dispatch_async_compute(
+            &self.pipeline,
+            self.group_counts(),
+            &[
+                ComputeAccess::Buffer(&self.light_buffer),
+                ComputeAccess::Buffer(&self.cluster_buffer)
+            ],
+            &[],
+            vk::PipelineStageFlags::FRAGMENT_SHADER)
+    }
+
+    /// Descriptor bindings for both buffers at LIGHT_LIST_BINDING and LIGHT_CLUSTER_BINDING,
+    /// for graphics pipelines whose layout includes light clusters
+    pub fn descriptor_bindings(&self) -> [(u32, DescriptorBinding); 2] {
+        [
+            (LIGHT_LIST_BINDING, self.light_list_binding()),
+            (LIGHT_CLUSTER_BINDING, self.cluster_binding())
+        ]
+    }
+
+    fn group_counts(&self) -> [u32; 3] {
+        let cluster_count = self.dimensions.iter().product();
+        [work_group_count(cluster_count, LIGHT_CLUSTER_WORK_GROUP_SIZE), 1, 1]
+    }
+
+    fn light_list_binding(&self) -> DescriptorBinding {
+        DescriptorBinding::StorageBuffer {
+            buffer: self.light_buffer.buffer(),
+            offset: 0,
+            range: vk::WHOLE_SIZE
+        }
+    }
+
+    fn cluster_binding(&self) -> DescriptorBinding {
+        DescriptorBinding::StorageBuffer {
+            buffer: self.cluster_buffer.buffer(),
+            offset: 0,
+            range: vk::WHOLE_SIZE
+        }
+    }
+}
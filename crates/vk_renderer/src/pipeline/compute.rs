@@ -0,0 +1,272 @@
+use crate::{
+    VkContext, ImageWrapper, BufferWrapper, DescriptorBinding, SpecializationConstant, write_descriptor_bindings,
+    pipeline::specialization::SpecializationData
+};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use ash::{Device, vk};
+use std::ffi::CString;
+
+/// ComputePipelineCreationData struct
+/// Information needed to prepare a compute pipeline ahead of time. The shader module is looked
+/// up by index, and should have been created with the compute shader stage. Each descriptor type
+/// gets one binding, numbered in the order given, visible to the compute stage. Push constants
+/// of the given size are available to the shader starting at offset zero. Specialization
+/// constants may set values such as the work group size.
+pub struct ComputePipelineCreationData {
+    pub shader_index: u32,
+    pub descriptor_types: Vec<vk::DescriptorType>,
+    pub push_constant_size_bytes: u32,
+    pub specialization_constants: Vec<SpecializationConstant>
+}
+
+/// ComputeAccess enum
+/// A resource used by a dispatch. Images say whether the shader reads them by sampling or writes
+/// them as storage images, so that they can be moved into the right layout beforehand. Buffers
+/// bound to the pipeline need listing only for async compute, whose resources change queue family
+/// ownership; see VkContext::begin_async_compute.
+#[derive(Copy, Clone)]
+pub enum ComputeAccess<'a> {
+    Sampled(&'a ImageWrapper),
+    Storage(&'a ImageWrapper),
+    Buffer(&'a BufferWrapper)
+}
+
+/// ComputePipelineWrapper struct
+/// A compute pipeline with its own layout and a single descriptor set, whose bindings are
+/// written with write_bindings before dispatching
+pub struct ComputePipelineWrapper {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    push_constant_size_bytes: u32,
+    pipeline: vk::Pipeline
+}
+
+impl Resource<VkContext> for ComputePipelineWrapper {
+    type CreationData = ComputePipelineCreationData;
+
+    fn create(
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &ComputePipelineCreationData
+    ) -> Result<Self, EngineError> {
+        let shader_module = ecs
+            .get_item::<vk::ShaderModule>(Handle::for_resource(data.shader_index))
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No shader module at index {}", data.shader_index)))?;
+        let mut wrapper = ComputePipelineWrapper {
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            push_constant_size_bytes: data.push_constant_size_bytes,
+            pipeline: vk::Pipeline::null()
+        };
+        unsafe {
+            if let Err(e) = wrapper.create_objects(loader, *shader_module, data) {
+                wrapper.release(loader);
+                return Err(e);
+            }
+        }
+        Ok(wrapper)
+    }
+
+    fn release(&self, loader: &VkContext) {
+        unsafe {
+            let device = &loader.device;
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+impl ComputePipelineWrapper {
+
+    unsafe fn create_objects(
+        &mut self,
+        context: &VkContext,
+        shader_module: vk::ShaderModule,
+        data: &ComputePipelineCreationData
+    ) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = data.descriptor_types.iter()
+            .enumerate()
+            .map(|(index, descriptor_type)| vk::DescriptorSetLayoutBinding::builder()
+                .binding(index as u32)
+                .descriptor_type(*descriptor_type)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build())
+            .collect();
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&layout_bindings);
+        self.descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor set layout: {:?}", e)))?;
+
+        let set_layouts = [self.descriptor_set_layout];
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: data.push_constant_size_bytes
+        }];
+        let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts);
+        if data.push_constant_size_bytes > 0 {
+            pipeline_layout_info = pipeline_layout_info.push_constant_ranges(&push_constant_ranges);
+        }
+        self.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating pipeline layout: {:?}", e)))?;
+
+        if !layout_bindings.is_empty() {
+            let pool_sizes: Vec<vk::DescriptorPoolSize> = data.descriptor_types.iter()
+                .map(|descriptor_type| vk::DescriptorPoolSize {
+                    ty: *descriptor_type,
+                    descriptor_count: 1
+                })
+                .collect();
+            let pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+            self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)
+                .map_err(|e| EngineError::OpFailed(
+                    format!("Error creating descriptor pool: {:?}", e)))?;
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&set_layouts);
+            self.descriptor_set = device.allocate_descriptor_sets(&allocate_info)
+                .map_err(|e| EngineError::OpFailed(
+                    format!("Error allocating descriptor set: {:?}", e)))?[0];
+        }
+
+        let main_function_name = CString::new("main").unwrap();
+        let specialization_data = SpecializationData::new(&data.specialization_constants);
+        let specialization_info = specialization_data.info();
+        let mut stage_info = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(&main_function_name);
+        if !specialization_data.is_empty() {
+            stage_info = stage_info.specialization_info(&specialization_info);
+        }
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage_info.build())
+            .layout(self.pipeline_layout)
+            .build();
+        self.pipeline = device
+            .create_compute_pipelines(context.get_pipeline_cache(), &[pipeline_info], None)
+            .map_err(|(_, e)| EngineError::OpFailed(
+                format!("Error creating compute pipeline: {:?}", e)))?[0];
+        Ok(())
+    }
+
+    /// Rewrite bindings of the pipeline's descriptor set. The set must not be in use by any
+    /// pending dispatch.
+    pub unsafe fn write_bindings(
+        &self,
+        context: &VkContext,
+        bindings: &[(u32, DescriptorBinding)]
+    ) {
+        write_descriptor_bindings(context, self.descriptor_set, bindings);
+    }
+
+    /// Record a dispatch of the given number of work groups in each dimension. Images used by the
+    /// shader are first moved into the layout for how they are used; those written are left
+    /// tracked as written by the compute stage, so transitioning them for their next use waits for
+    /// the dispatch. Writes to buffers are made visible to any later shader, vertex input or
+    /// indirect draw read, so that buffers filled by compute, such as particles, may be drawn
+    /// straight afterwards. Queues without graphics support, such as a dedicated compute queue,
+    /// have no such stages, so there only later compute work is made to wait; graphics work must
+    /// then wait on a semaphore, as it does for async compute.
+    pub unsafe fn record_dispatch(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        group_counts: [u32; 3],
+        accesses: &[ComputeAccess],
+        push_constants: &[u8],
+        queue_supports_graphics: bool
+    ) -> Result<(), EngineError> {
+        if push_constants.len() as u32 != self.push_constant_size_bytes {
+            return Err(EngineError::UserError(format!(
+                "Compute pipeline takes {} bytes of push constants, given {}",
+                self.push_constant_size_bytes,
+                push_constants.len())));
+        }
+        for access in accesses.iter() {
+            match access {
+                ComputeAccess::Sampled(image) => image.transition_to(
+                    device,
+                    command_buffer,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ),
+                ComputeAccess::Storage(image) => image.transition_to(
+                    device,
+                    command_buffer,
+                    vk::ImageLayout::GENERAL,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+                ComputeAccess::Buffer(_) => false
+            };
+        }
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        if self.descriptor_set != vk::DescriptorSet::null() {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[]);
+        }
+        if !push_constants.is_empty() {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constants);
+        }
+        device.cmd_dispatch(command_buffer, group_counts[0], group_counts[1], group_counts[2]);
+
+        let buffer_write_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::SHADER_READ |
+                    vk::AccessFlags::VERTEX_ATTRIBUTE_READ |
+                    vk::AccessFlags::INDEX_READ |
+                    vk::AccessFlags::INDIRECT_COMMAND_READ)
+            .build();
+        let dst_stages = match queue_supports_graphics {
+            true => vk::PipelineStageFlags::DRAW_INDIRECT |
+                vk::PipelineStageFlags::VERTEX_INPUT |
+                vk::PipelineStageFlags::VERTEX_SHADER |
+                vk::PipelineStageFlags::FRAGMENT_SHADER |
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            false => vk::PipelineStageFlags::COMPUTE_SHADER
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[buffer_write_barrier],
+            &[],
+            &[]);
+        Ok(())
+    }
+}
+
+/// The number of work groups needed to cover the given number of items, for work groups of the
+/// given size in that dimension
+pub fn work_group_count(item_count: u32, work_group_size: u32) -> u32 {
+    item_count.div_ceil(work_group_size.max(1))
+}
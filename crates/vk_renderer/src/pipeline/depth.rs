@@ -0,0 +1,81 @@
+use crate::RenderQueue;
+use ash::vk;
+
+/// DepthConvention enum
+/// Which end of the depth range is near the camera. Standard depth puts the near plane at 0.0
+/// and the far plane at 1.0. Reversed-Z swaps them, which pairs the precision of floating point
+/// depth, greatest near zero, with the precision lost to the perspective divide far from the
+/// camera, so that distant surfaces no longer fight over the same depth values. The projection
+/// matrix must agree with the convention; see PlayerCamera::set_reversed_z.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum DepthConvention {
+    #[default]
+    Standard,
+    ReversedZ
+}
+
+impl DepthConvention {
+
+    /// The value depth attachments are cleared to, which is the far end of the range
+    pub fn clear_depth(&self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::ReversedZ => 0.0
+        }
+    }
+
+    /// The comparison that passes fragments at least as near as what is already drawn
+    pub fn compare_op(&self) -> vk::CompareOp {
+        match self {
+            DepthConvention::Standard => vk::CompareOp::LESS_OR_EQUAL,
+            DepthConvention::ReversedZ => vk::CompareOp::GREATER_OR_EQUAL
+        }
+    }
+}
+
+/// DepthState struct
+/// Whether a pipeline tests fragments against the depth buffer, whether it writes the depth of
+/// those that pass, and how they are compared. Without a compare op, the one matching the
+/// context's depth convention is used, which keeps pipelines correct under reversed-Z. A sky box
+/// is typically drawn testing without writing; UI layers drawn over everything need neither.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DepthState {
+    pub test_enable: bool,
+    pub write_enable: bool,
+    pub compare_op: Option<vk::CompareOp>
+}
+
+impl DepthState {
+
+    /// Test depth, and write it if the render queue's steps write depth
+    pub fn for_render_queue(render_queue: RenderQueue) -> Self {
+        Self {
+            test_enable: true,
+            write_enable: render_queue.writes_depth(),
+            compare_op: None
+        }
+    }
+
+    /// Test depth without writing it
+    pub fn test_only() -> Self {
+        Self {
+            test_enable: true,
+            write_enable: false,
+            compare_op: None
+        }
+    }
+
+    /// Neither test nor write depth, so everything drawn appears over what is already drawn
+    pub fn disabled() -> Self {
+        Self {
+            test_enable: false,
+            write_enable: false,
+            compare_op: None
+        }
+    }
+
+    /// The compare op to use, falling back to the one for the given depth convention
+    pub fn compare_op_for(&self, convention: DepthConvention) -> vk::CompareOp {
+        self.compare_op.unwrap_or_else(|| convention.compare_op())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::VkContext;
+use ash::vk;
+
+/// DescriptorBinding enum
+/// New contents for a single binding within an existing descriptor set
+#[derive(Copy, Clone, Debug)]
+pub enum DescriptorBinding {
+    UniformBuffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize
+    },
+    DynamicUniformBuffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize
+    },
+    CombinedImageSampler {
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout
+    },
+    StorageImage {
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout
+    },
+    InputAttachment {
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout
+    },
+    StorageBuffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize
+    }
+}
+
+/// DynamicUniformLayout struct
+/// Arrangement of per-object slices within a single uniform buffer bound as a dynamic uniform
+/// buffer descriptor. Each slice starts at a multiple of the device's minimum uniform buffer
+/// offset alignment, so its offset can be passed when binding the descriptor set.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DynamicUniformLayout {
+    pub object_size_bytes: usize,
+    pub stride_bytes: usize,
+    pub object_count: usize
+}
+
+impl DynamicUniformLayout {
+
+    /// Lay out slices of the given size for a number of objects, with the given alignment (which
+    /// Vulkan guarantees to be a power of two)
+    pub fn new(object_size_bytes: usize, object_count: usize, min_alignment: usize) -> Self {
+        let stride_bytes = object_size_bytes.max(1).next_multiple_of(min_alignment.max(1));
+        Self {
+            object_size_bytes,
+            stride_bytes,
+            object_count
+        }
+    }
+
+    /// Size of the uniform buffer needed to hold every object's slice
+    pub fn total_size_bytes(&self) -> usize {
+        self.stride_bytes * self.object_count
+    }
+
+    /// Offset of an object's slice, as passed as the dynamic offset when binding
+    pub fn offset(&self, object_index: usize) -> u32 {
+        (object_index * self.stride_bytes) as u32
+    }
+}
+
+/// Rewrite individual bindings of a descriptor set, leaving the others untouched. The set must
+/// not be in use by any pending command buffer.
+pub unsafe fn write_descriptor_bindings(
+    context: &VkContext,
+    descriptor_set: vk::DescriptorSet,
+    bindings: &[(u32, DescriptorBinding)]
+) {
+    let buffer_infos: Vec<[vk::DescriptorBufferInfo; 1]> = bindings.iter()
+        .map(|(_, binding)| match binding {
+            DescriptorBinding::UniformBuffer { buffer, offset, range } |
+            DescriptorBinding::DynamicUniformBuffer { buffer, offset, range } |
+            DescriptorBinding::StorageBuffer { buffer, offset, range } => [
+                vk::DescriptorBufferInfo { buffer: *buffer, offset: *offset, range: *range }
+            ],
+            _ => [vk::DescriptorBufferInfo::default()]
+        })
+        .collect();
+    let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = bindings.iter()
+        .map(|(_, binding)| match binding {
+            DescriptorBinding::CombinedImageSampler { image_view, sampler, image_layout } => [
+                vk::DescriptorImageInfo {
+                    image_view: *image_view,
+                    sampler: *sampler,
+                    image_layout: *image_layout
+                }
+            ],
+            DescriptorBinding::StorageImage { image_view, image_layout } |
+            DescriptorBinding::InputAttachment { image_view, image_layout } => [
+                vk::DescriptorImageInfo {
+                    image_view: *image_view,
+                    sampler: vk::Sampler::null(),
+                    image_layout: *image_layout
+                }
+            ],
+            _ => [vk::DescriptorImageInfo::default()]
+        })
+        .collect();
+    let descriptor_set_writes: Vec<vk::WriteDescriptorSet> = bindings.iter()
+        .enumerate()
+        .map(|(index, (binding_index, binding))| {
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(*binding_index);
+            match binding {
+                DescriptorBinding::UniformBuffer { .. } => write
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&buffer_infos[index])
+                    .build(),
+                DescriptorBinding::DynamicUniformBuffer { .. } => write
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                    .buffer_info(&buffer_infos[index])
+                    .build(),
+                DescriptorBinding::CombinedImageSampler { .. } => write
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_infos[index])
+                    .build(),
+                DescriptorBinding::StorageImage { .. } => write
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&image_infos[index])
+                    .build(),
+                DescriptorBinding::InputAttachment { .. } => write
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .image_info(&image_infos[index])
+                    .build(),
+                DescriptorBinding::StorageBuffer { .. } => write
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&buffer_infos[index])
+                    .build()
+            }
+        })
+        .collect();
+    context.device.update_descriptor_sets(&descriptor_set_writes, &[]);
+}
@@ -0,0 +1,324 @@
+use crate::{
+    VkContext, SecondaryInheritance, ImageWrapper, context::barriers::record_image_barriers
+};
+use ecs::{EcsManager, resource::Resource};
+use error::EngineError;
+use ash::{vk, Device};
+
+/// DynamicRenderTargetData struct
+/// Information needed to render into a swapchain image without a renderpass. With more than one
+/// sample, rendering goes into multisampled color and depth attachments owned by the target, and
+/// the color is resolved into the swapchain image as rendering ends.
+pub struct DynamicRenderTargetData {
+    pub swapchain_image_index: usize,
+    pub samples: vk::SampleCountFlags
+}
+
+/// DynamicRenderTarget struct
+/// The attachments for rendering a simple pass into a swapchain image, along with its depth
+/// image, using dynamic rendering rather than renderpass and framebuffer objects. Unless
+/// multisampled, nothing is created on the device, so rebuilding these after the swapchain is
+/// recreated is cheap. Only
+/// usable where the context supports dynamic rendering; pipelines for such passes are created
+/// with use_dynamic_rendering set. A depth image with a stencil aspect is also used as the stencil
+/// attachment, cleared along with depth. Pipelines rendering into it use its sample count.
+pub struct DynamicRenderTarget {
+    color_image: vk::Image,
+    color_image_view: vk::ImageView,
+    color_format: vk::Format,
+    depth_image: vk::Image,
+    depth_image_view: vk::ImageView,
+    depth_format: vk::Format,
+    extent: vk::Extent2D,
+    output_layout: vk::ImageLayout,
+    synchronization2: bool,
+    samples: vk::SampleCountFlags,
+    msaa_attachments: Option<(ImageWrapper, ImageWrapper)>
+}
+
+impl Resource<VkContext> for DynamicRenderTarget {
+    type CreationData = DynamicRenderTargetData;
+
+    fn create(
+        loader: &VkContext,
+        _ecs: &EcsManager<VkContext>,
+        data: &DynamicRenderTargetData
+    ) -> Result<Self, EngineError> {
+        if !loader.supports_dynamic_rendering() {
+            return Err(EngineError::Compatibility(
+                "Dynamic rendering is not enabled on this device".to_owned()));
+        }
+        let depth_image = loader.get_depth_image()
+            .ok_or_else(|| EngineError::OpFailed(
+                "Creating dynamic render target with no depth image available".to_owned()))?;
+        let color_format = unsafe { loader.get_surface_format().format };
+        let extent = loader.get_extent()?;
+        let msaa_attachments = match data.samples == vk::SampleCountFlags::TYPE_1 {
+            true => None,
+            false => unsafe {
+                let color = ImageWrapper::new_attachment(
+                    loader,
+                    color_format,
+                    extent.width,
+                    extent.height,
+                    data.samples)?;
+                match ImageWrapper::new_attachment(
+                    loader,
+                    depth_image.format,
+                    extent.width,
+                    extent.height,
+                    data.samples
+                ) {
+                    Ok(depth) => Some((color, depth)),
+                    Err(e) => {
+                        color.release(loader);
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        Ok(Self {
+            color_image: loader.get_swapchain_image(data.swapchain_image_index)?,
+            color_image_view: loader.get_swapchain_image_view(data.swapchain_image_index)?,
+            color_format,
+            depth_image: depth_image.image,
+            depth_image_view: depth_image.image_view,
+            depth_format: depth_image.format,
+            extent,
+            output_layout: loader.get_output_layout(),
+            synchronization2: loader.supports_synchronization2(),
+            samples: data.samples,
+            msaa_attachments
+        })
+    }
+
+    fn release(&self, loader: &VkContext) {
+        if let Some((color, depth)) = &self.msaa_attachments {
+            color.release(loader);
+            depth.release(loader);
+        }
+    }
+}
+
+impl DynamicRenderTarget {
+
+    pub fn get_color_format(&self) -> vk::Format {
+        self.color_format
+    }
+
+    pub fn get_depth_format(&self) -> vk::Format {
+        self.depth_format
+    }
+
+    /// The number of samples per pixel rendered into this target
+    pub fn get_samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    /// The format of the stencil attachment, which is the depth image where it has a stencil
+    /// aspect, or undefined where there is none
+    pub fn get_stencil_format(&self) -> vk::Format {
+        stencil_format_of(self.depth_format)
+    }
+
+    fn depth_aspect(&self) -> vk::ImageAspectFlags {
+        match self.get_stencil_format() {
+            vk::Format::UNDEFINED => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+    }
+
+    /// What secondary command buffers executed while rendering into this target continue
+    /// rendering into; see begin_for_secondary_buffers
+    pub fn secondary_inheritance(&self) -> SecondaryInheritance {
+        SecondaryInheritance::DynamicRendering {
+            color_formats: vec![self.color_format],
+            depth_format: self.depth_format,
+            stencil_format: self.get_stencil_format(),
+            samples: self.samples
+        }
+    }
+
+    /// Begin rendering, clearing both attachments. Replaces beginning a renderpass, including the
+    /// layout transitions it would have done, and the resolve it would have done if multisampled.
+    pub unsafe fn begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        color_clear_value: vk::ClearValue,
+        depth_clear_value: vk::ClearValue
+    ) {
+        self.begin_with_flags(
+            device,
+            command_buffer,
+            color_clear_value,
+            depth_clear_value,
+            vk::RenderingFlags::empty());
+    }
+
+    /// Begin rendering as with begin, but such that the rendering commands come from secondary
+    /// command buffers executed in it rather than being recorded inline
+    pub unsafe fn begin_for_secondary_buffers(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        color_clear_value: vk::ClearValue,
+        depth_clear_value: vk::ClearValue
+    ) {
+        self.begin_with_flags(
+            device,
+            command_buffer,
+            color_clear_value,
+            depth_clear_value,
+            vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS);
+    }
+
+    unsafe fn begin_with_flags(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        color_clear_value: vk::ClearValue,
+        depth_clear_value: vk::ClearValue,
+        flags: vk::RenderingFlags
+    ) {
+        // Previous contents are discarded, so transition from an undefined layout; the depth
+        // image is shared between swapchain images, so wait for earlier depth writes. When
+        // multisampled, the swapchain image is only written by the resolve.
+        let (render_image, render_image_view, depth_image, depth_image_view) =
+            match &self.msaa_attachments {
+                Some((color, depth)) =>
+                    (color.image, color.image_view, depth.image, depth.image_view),
+                None => (
+                    self.color_image,
+                    self.color_image_view,
+                    self.depth_image,
+                    self.depth_image_view
+                )
+            };
+        let mut barriers = vec![
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.color_image)
+                .subresource_range(Self::subresource_range(vk::ImageAspectFlags::COLOR))
+                .build(),
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ |
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(depth_image)
+                .subresource_range(Self::subresource_range(self.depth_aspect()))
+                .build()
+        ];
+        if render_image != self.color_image {
+            barriers.push(vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(render_image)
+                .subresource_range(Self::subresource_range(vk::ImageAspectFlags::COLOR))
+                .build());
+        }
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT |
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS |
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        record_image_barriers(
+            device,
+            command_buffer,
+            self.synchronization2,
+            attachment_stages,
+            attachment_stages,
+            &barriers);
+
+        let color_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(render_image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .clear_value(color_clear_value);
+        let color_attachment = match render_image != self.color_image {
+            true => color_attachment
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(self.color_image_view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            false => color_attachment.store_op(vk::AttachmentStoreOp::STORE)
+        };
+        let color_attachments = [color_attachment.build()];
+        let depth_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(depth_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(depth_clear_value);
+        let rendering_info = vk::RenderingInfo::builder()
+            .flags(flags)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment);
+        let rendering_info = match self.get_stencil_format() {
+            vk::Format::UNDEFINED => rendering_info,
+            _ => rendering_info.stencil_attachment(&depth_attachment)
+        };
+        device.cmd_begin_rendering(command_buffer, &rendering_info);
+    }
+
+    /// End rendering, and transition the swapchain image so that it can be presented, or copied
+    /// from if the context is headless
+    pub unsafe fn end(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        device.cmd_end_rendering(command_buffer);
+        let barriers = [
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::empty())
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(self.output_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.color_image)
+                .subresource_range(Self::subresource_range(vk::ImageAspectFlags::COLOR))
+                .build()
+        ];
+        record_image_barriers(
+            device,
+            command_buffer,
+            self.synchronization2,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &barriers);
+    }
+
+    fn subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        }
+    }
+}
+
+/// The stencil attachment format that goes with a depth attachment format: the same format if it
+/// has a stencil aspect, otherwise undefined
+pub(crate) fn stencil_format_of(depth_format: vk::Format) -> vk::Format {
+    match depth_format {
+        vk::Format::D24_UNORM_S8_UINT => depth_format,
+        _ => vk::Format::UNDEFINED
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{VkContext, BufferWrapper, DescriptorBinding};
+use ecs::{EcsManager, resource::Resource};
+use error::EngineError;
+use ash::vk;
+
+/// Binding of the draw metadata buffer within descriptor sets whose layout includes it
+pub const DRAW_METADATA_BINDING: u32 = 2;
+
+/// DrawMetadata struct
+/// Identifies what a single draw is, so that debug views (such as ID or overdraw visualisations)
+/// and picking all agree on it. Laid out as a uvec4 per draw in a storage buffer, which shaders
+/// index with gl_InstanceIndex; see DrawMetadataBinding. Zero IDs mean "none".
+#[repr(C)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct DrawMetadata {
+    pub material_id: u32,
+    pub object_id: u32,
+    pub flags: u32,
+    reserved: u32
+}
+
+impl DrawMetadata {
+
+    /// The draw can be selected by the picking pass
+    pub const FLAG_PICKABLE: u32 = 0x1;
+
+    /// The draw is highlighted, such as when selected in a debug view
+    pub const FLAG_HIGHLIGHTED: u32 = 0x2;
+
+    pub fn new(material_id: u32, object_id: u32, flags: u32) -> Self {
+        Self {
+            material_id,
+            object_id,
+            flags,
+            reserved: 0
+        }
+    }
+
+    pub fn has_flags(&self, flags: u32) -> bool {
+        self.flags & flags == flags
+    }
+}
+
+/// DrawMetadataCreationData struct
+/// Information needed to create a draw metadata buffer, initially filled with default entries
+pub struct DrawMetadataCreationData {
+    pub capacity: usize
+}
+
+/// DrawMetadataBinding struct
+/// How a pipeline uses a draw metadata buffer: the buffer's resource index, and the entry for
+/// the pipeline's first object. Each object is drawn with its entry's index as the first
+/// instance, so gl_InstanceIndex selects the entry in shaders.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DrawMetadataBinding {
+    pub buffer_index: u32,
+    pub first_entry: u32
+}
+
+/// DrawMetadataBuffer struct
+/// Host-visible storage buffer of DrawMetadata entries, shared by any pipelines that bind it
+pub struct DrawMetadataBuffer {
+    buffer: BufferWrapper
+}
+
+impl Resource<VkContext> for DrawMetadataBuffer {
+    type CreationData = DrawMetadataCreationData;
+
+    fn create(
+        loader: &VkContext,
+        _ecs: &EcsManager<VkContext>,
+        data: &DrawMetadataCreationData
+    ) -> Result<Self, EngineError> {
+        if data.capacity == 0 {
+            return Err(EngineError::UserError(
+                "Draw metadata buffer requested without any entries".to_owned()));
+        }
+        let entries = vec![DrawMetadata::default(); data.capacity];
+        let buffer = unsafe { BufferWrapper::new_storage(loader, &entries)? };
+        Ok(Self { buffer })
+    }
+
+    fn release(&self, loader: &VkContext) {
+        self.buffer.release(loader);
+    }
+}
+
+impl DrawMetadataBuffer {
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.element_count
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.buffer()
+    }
+
+    /// Overwrite entries starting from the given index. Entries may be in use by submitted
+    /// frames, so this should be done while preparing a frame, as with uniform buffers.
+    pub unsafe fn write(
+        &self,
+        context: &VkContext,
+        first_entry: usize,
+        entries: &[DrawMetadata]
+    ) -> Result<(), EngineError> {
+        let (allocator, _) = context.get_mem_allocator();
+        self.buffer.update(allocator, first_entry as isize, entries.as_ptr(), entries.len())
+    }
+
+    /// Descriptor binding for the whole buffer, for binding DRAW_METADATA_BINDING
+    pub fn descriptor_binding(&self) -> DescriptorBinding {
+        DescriptorBinding::StorageBuffer {
+            buffer: self.buffer.buffer(),
+            offset: 0,
+            range: vk::WHOLE_SIZE
+        }
+    }
+}
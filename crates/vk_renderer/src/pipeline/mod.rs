@@ -1,3 +1,19 @@
+pub mod bindless;
+pub mod blend;
+pub mod clustering;
+pub mod compute;
+pub mod depth;
+pub mod descriptor;
+pub mod dynamic_rendering;
+pub mod metadata;
+pub mod queue;
+pub mod rasterizer;
 pub mod renderpass;
 pub mod offscreen_framebuffer;
+pub mod permutation;
+pub mod post_process;
+pub mod specialization;
+pub mod stencil;
+pub mod upscale;
+pub mod vertex;
 pub mod wrapper;
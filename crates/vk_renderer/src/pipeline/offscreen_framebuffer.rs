@@ -1,25 +1,47 @@
 
-use crate::{VkContext, ImageWrapper, TexturePixelFormat, ImageUsage};
+use crate::{VkContext, ImageWrapper, TexturePixelFormat, ImageUsage, ImageAccess};
 use ecs::{EcsManager, resource::Resource};
 use error::EngineError;
+use ash::{Device, vk};
 
 /// OffscreenFramebufferData struct
-/// Information needed to prepare a non-swapchain framebuffer
+/// Information needed to prepare a non-swapchain framebuffer. Each color format adds a color
+/// texture, so that a pass such as filling a G-buffer can write several at once, with fragment
+/// shader output locations following the order given here; there must be at least one.
 pub struct OffscreenFramebufferData {
     pub width: u32,
     pub height: u32,
-    pub color_format: TexturePixelFormat,
+    pub color_formats: Vec<TexturePixelFormat>,
     pub depth_format: TexturePixelFormat
 }
 
+impl OffscreenFramebufferData {
+
+    /// Data for a framebuffer that the main pass renders into at the context's internal render
+    /// resolution, with a depth texture in the context's depth format; see
+    /// VkContext::set_render_scale. It must be recreated along with the swapchain.
+    pub fn at_internal_resolution(
+        context: &VkContext,
+        color_formats: Vec<TexturePixelFormat>
+    ) -> Result<Self, EngineError> {
+        let extent = context.get_internal_render_area()?.extent;
+        Ok(Self {
+            width: extent.width,
+            height: extent.height,
+            color_formats,
+            depth_format: context.get_depth_format()
+        })
+    }
+}
+
 /// FramebufferCreationData struct
 /// Specification for how a framebuffer (render target) resource is to be created
 pub struct OffscreenFramebufferWrapper {
-    pub color_texture: ImageWrapper,
+    pub color_textures: Vec<ImageWrapper>,
     pub depth_texture: Option<ImageWrapper>,
     pub width: u32,
     pub height: u32,
-    pub color_format: TexturePixelFormat,
+    pub color_formats: Vec<TexturePixelFormat>,
     pub depth_format: TexturePixelFormat
 }
 
@@ -36,14 +58,16 @@ impl Resource<VkContext> for OffscreenFramebufferWrapper {
                 loader,
                 data.width,
                 data.height,
-                data.color_format,
+                &data.color_formats,
                 data.depth_format)?
         };
         Ok(framebuffer)
     }
 
     fn release(&self, loader: &VkContext) {
-        self.color_texture.release(loader);
+        for color_texture in self.color_textures.iter() {
+            color_texture.release(loader);
+        }
         if let Some(depth_image) = &self.depth_texture {
             depth_image.release(loader);
         }
@@ -56,37 +80,82 @@ impl OffscreenFramebufferWrapper {
         context: &VkContext,
         width: u32,
         height: u32,
-        color_format: TexturePixelFormat,
+        color_formats: &[TexturePixelFormat],
         depth_format: TexturePixelFormat
     ) -> Result<OffscreenFramebufferWrapper, EngineError> {
-        let color_texture = ImageWrapper::new(
-            context,
-            ImageUsage::OffscreenRenderSampleColorWriteDepth,
-            color_format,
+        if color_formats.is_empty() {
+            return Err(EngineError::UserError(
+                String::from("Off-screen framebuffer needs at least one color format")));
+        }
+        let mut framebuffer = Self {
+            color_textures: Vec::with_capacity(color_formats.len()),
+            depth_texture: None,
             width,
             height,
-            None
-        )?;
-        let depth_texture = match depth_format {
+            color_formats: color_formats.to_vec(),
+            depth_format
+        };
+        if let Err(e) = framebuffer.create_textures(context) {
+            framebuffer.release(context);
+            return Err(e);
+        }
+        Ok(framebuffer)
+    }
+
+    unsafe fn create_textures(&mut self, context: &VkContext) -> Result<(), EngineError> {
+        for color_format in self.color_formats.iter() {
+            self.color_textures.push(ImageWrapper::new(
+                context,
+                ImageUsage::OffscreenRenderSampleColorWriteDepth,
+                *color_format,
+                self.width,
+                self.height,
+                None
+            )?);
+        }
+        self.depth_texture = match self.depth_format {
             TexturePixelFormat::None => None,
             format => Some(
                 ImageWrapper::new(
                     context,
                     ImageUsage::DepthBuffer,
                     format,
-                    width,
-                    height,
+                    self.width,
+                    self.height,
                     None
                     )?
             )
         };
-        Ok(Self {
-            color_texture,
-            depth_texture,
-            width,
-            height,
-            color_format,
-            depth_format
-        })
+        Ok(())
+    }
+
+    /// The first color texture, which is the only one unless several color formats were given
+    pub fn color_texture(&self) -> &ImageWrapper {
+        &self.color_textures[0]
+    }
+
+    /// Record that a renderpass into this framebuffer has just ended, which leaves the color
+    /// textures as color attachments, without recording any barrier; the next transition of
+    /// each texture then waits for the renderpass's writes
+    pub fn end_rendering(&self) {
+        for color_texture in self.color_textures.iter() {
+            color_texture.assume_access(ImageAccess::ColorAttachment);
+        }
+        if let Some(depth_texture) = &self.depth_texture {
+            depth_texture.assume_access(ImageAccess::DepthAttachment);
+        }
+    }
+
+    /// Record that a renderpass into this framebuffer has just ended, which leaves the color
+    /// textures as color attachments, then record the barriers that make them ready to sample
+    pub unsafe fn end_rendering_for_sampling(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer
+    ) {
+        self.end_rendering();
+        for color_texture in self.color_textures.iter() {
+            color_texture.transition(device, command_buffer, ImageAccess::ShaderRead);
+        }
     }
 }
@@ -0,0 +1,477 @@
+use crate::{
+    VkContext, RenderpassWrapper, BlendMode, DepthState, RasterizerState, VertexLayout,
+    DynamicRenderTarget, SpecializationConstant, StencilState,
+    pipeline::{dynamic_rendering::stencil_format_of, specialization::SpecializationData}
+};
+use ecs::{EcsManager, Handle};
+use error::EngineError;
+use ash::{Device, vk};
+use std::ffi::{CString, c_void};
+
+/// Number of pipelines created in each call while pre-warming, between progress reports
+const PREWARM_BATCH_SIZE: usize = 16;
+
+/// PipelinePermutation struct
+/// The parts of a pipeline's description that decide what the driver compiles: the shaders, the
+/// vertex layout and topology, the target and subpass it renders in, the fixed-function state its
+/// blend mode, depth state, stencil state and rasterizer state imply, along with the values given
+/// to the shaders' specialization constants. Pipelines created from the same permutation differ
+/// only in the buffers and textures they bind, so creating one warms the pipeline cache for every
+/// other.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PipelinePermutation {
+    pub pipeline_layout_index: u32,
+    pub renderpass_index: u32,
+    pub subpass: u32,
+    pub use_dynamic_rendering: bool,
+    pub vertex_shader_index: u32,
+    pub fragment_shader_index: u32,
+    pub vertex_layout: VertexLayout,
+    pub topology: vk::PrimitiveTopology,
+    pub blend_mode: BlendMode,
+    pub depth_state: DepthState,
+    pub stencil_state: StencilState,
+    pub rasterizer_state: RasterizerState,
+    pub specialization_constants: Vec<SpecializationConstant>,
+    pub swapchain_image_index: usize
+}
+
+/// ResolvedPermutation struct
+/// A permutation along with the objects that it refers to, looked up from the ECS beforehand so
+/// that its pipeline can be created away from the ECS, such as on another thread
+struct ResolvedPermutation {
+    permutation: PipelinePermutation,
+    renderpass: vk::RenderPass,
+    samples: vk::SampleCountFlags,
+    color_attachment_count: u32,
+    dynamic_rendering_formats: Option<(vk::Format, vk::Format)>,
+    pipeline_layout: vk::PipelineLayout,
+    vertex_shader_module: vk::ShaderModule,
+    fragment_shader_module: vk::ShaderModule,
+    depth_compare_op: vk::CompareOp
+}
+
+/// Check that a permutation can be created on this device, and look up its shaders, layout and
+/// renderpass or dynamic render target in the ECS
+unsafe fn resolve_permutation(
+    context: &VkContext,
+    ecs: &EcsManager<VkContext>,
+    permutation: &PipelinePermutation
+) -> Result<ResolvedPermutation, EngineError> {
+
+    match permutation.topology {
+        vk::PrimitiveTopology::POINT_LIST | vk::PrimitiveTopology::LINE_LIST |
+            vk::PrimitiveTopology::LINE_STRIP | vk::PrimitiveTopology::TRIANGLE_LIST |
+            vk::PrimitiveTopology::TRIANGLE_STRIP => {},
+        topology => return Err(EngineError::UserError(
+            format!("Primitive topology {:?} not supported", topology)))
+    };
+    let rasterizer_state = permutation.rasterizer_state;
+    if rasterizer_state.needs_non_solid_fill() && !context.supports_non_solid_fill() {
+        return Err(EngineError::Compatibility(format!(
+            "Polygon mode {:?} needs the non-solid fill feature",
+            rasterizer_state.polygon_mode)));
+    }
+    if let Some(subset) = context.get_portability_subset() {
+        if !subset.supports_polygon_mode(rasterizer_state.polygon_mode) {
+            return Err(EngineError::Compatibility(
+                String::from("Drawing triangles as points is not supported on this device")));
+        }
+        let cull_mode = rasterizer_state.cull_mode;
+        if !subset.supports_stencil_state(&permutation.stencil_state, cull_mode) {
+            return Err(EngineError::Compatibility(String::from(
+                "Front and back stencil masks and references must match on this device")));
+        }
+    }
+
+    // Query renderpass, or attachment formats for dynamic rendering, and pipeline layout
+    let renderpass_index = permutation.renderpass_index;
+    let renderpass_handle = Handle::for_resource_variation(
+        renderpass_index,
+        permutation.swapchain_image_index as u32).unwrap();
+    let subpass = permutation.subpass;
+    let (renderpass, samples, color_attachment_count, dynamic_rendering_formats) =
+        match permutation.use_dynamic_rendering {
+            true => {
+                let target = ecs
+                    .get_item::<DynamicRenderTarget>(renderpass_handle)
+                    .ok_or_else(|| EngineError::MissingResource(
+                        format!("No dynamic render target at index {}", renderpass_index)))?;
+                if subpass != 0 {
+                    return Err(EngineError::UserError(
+                        format!("Dynamic rendering has no subpass {}", subpass)));
+                }
+                (
+                    vk::RenderPass::null(),
+                    target.get_samples(),
+                    1,
+                    Some((target.get_color_format(), target.get_depth_format()))
+                )
+            },
+            false => {
+                let renderpass_wrapper = ecs
+                    .get_item::<RenderpassWrapper>(renderpass_handle)
+                    .ok_or_else(|| EngineError::MissingResource(
+                        format!("No renderpass at index {}", renderpass_index)))?;
+                let color_attachment_count = renderpass_wrapper
+                    .get_color_attachment_count(subpass)
+                    .ok_or_else(|| EngineError::UserError(format!(
+                        "Renderpass at index {} has no subpass {}",
+                        renderpass_index,
+                        subpass)))?;
+                (
+                    renderpass_wrapper.renderpass,
+                    renderpass_wrapper.get_samples(),
+                    color_attachment_count,
+                    None
+                )
+            }
+        };
+    let pipeline_layout = ecs
+        .get_item::<vk::PipelineLayout>(
+            Handle::for_resource(permutation.pipeline_layout_index))
+        .ok_or_else(|| EngineError::MissingResource(
+            format!("No pipeline layout at index {}", permutation.pipeline_layout_index)))?;
+
+    // Query shader modules
+    let vertex_shader_module = ecs
+        .get_item::<vk::ShaderModule>(
+            Handle::for_resource(permutation.vertex_shader_index))
+        .ok_or_else(|| EngineError::MissingResource(
+            format!("No shader module at index {}", permutation.vertex_shader_index)))?;
+    let fragment_shader_module = ecs
+        .get_item::<vk::ShaderModule>(
+            Handle::for_resource(permutation.fragment_shader_index))
+        .ok_or_else(|| EngineError::MissingResource(
+            format!("No shader module at index {}", permutation.fragment_shader_index)))?;
+
+    Ok(ResolvedPermutation {
+        permutation: permutation.clone(),
+        renderpass,
+        samples,
+        color_attachment_count,
+        dynamic_rendering_formats,
+        pipeline_layout: *pipeline_layout,
+        vertex_shader_module: *vertex_shader_module,
+        fragment_shader_module: *fragment_shader_module,
+        depth_compare_op: permutation.depth_state.compare_op_for(context.get_depth_convention())
+    })
+}
+
+/// PipelineDescription struct
+/// The state structures describing one pipeline, along with the arrays they point into. Each is
+/// kept on the heap, so the pointers between them stay valid when the description is moved.
+struct PipelineDescription {
+    _main_function_name: CString,
+    _specialization_data: SpecializationData,
+    _specialization_info: Box<vk::SpecializationInfo>,
+    shader_stages: Vec<vk::PipelineShaderStageCreateInfo>,
+    _vertex_attrib_descriptions: Vec<vk::VertexInputAttributeDescription>,
+    _vertex_binding_descriptions: Vec<vk::VertexInputBindingDescription>,
+    vertex_input_info: Box<vk::PipelineVertexInputStateCreateInfo>,
+    input_assembly_info: Box<vk::PipelineInputAssemblyStateCreateInfo>,
+    _viewports: Vec<vk::Viewport>,
+    _scissors: Vec<vk::Rect2D>,
+    viewport_info: Box<vk::PipelineViewportStateCreateInfo>,
+    rasterizer_info: Box<vk::PipelineRasterizationStateCreateInfo>,
+    multisampler_info: Box<vk::PipelineMultisampleStateCreateInfo>,
+    depth_stencil_info: Box<vk::PipelineDepthStencilStateCreateInfo>,
+    _colour_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    colour_blend_info: Box<vk::PipelineColorBlendStateCreateInfo>,
+    _color_formats: Vec<vk::Format>,
+    rendering_info: Option<Box<vk::PipelineRenderingCreateInfo>>,
+    pipeline_layout: vk::PipelineLayout,
+    renderpass: vk::RenderPass,
+    subpass: u32
+}
+
+impl PipelineDescription {
+
+    fn new(resolved: &ResolvedPermutation, render_area: vk::Rect2D) -> Self {
+        let permutation = &resolved.permutation;
+
+        // Make shader modules, with the same specialization constants given to both stages
+        let main_function_name = CString::new("main").unwrap();
+        let specialization_data = SpecializationData::new(&permutation.specialization_constants);
+        let specialization_info = Box::new(specialization_data.info());
+        let mut vertex_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(resolved.vertex_shader_module)
+            .name(&main_function_name);
+        let mut fragment_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(resolved.fragment_shader_module)
+            .name(&main_function_name);
+        if !specialization_data.is_empty() {
+            vertex_shader_stage = vertex_shader_stage.specialization_info(&specialization_info);
+            fragment_shader_stage =
+                fragment_shader_stage.specialization_info(&specialization_info);
+        }
+        let shader_stages = vec![vertex_shader_stage.build(), fragment_shader_stage.build()];
+
+        // Vertex input configuration
+        let vertex_layout = &permutation.vertex_layout;
+        let vertex_attrib_descriptions = vertex_layout.attribute_descriptions();
+        let vertex_binding_descriptions = vec![vertex_layout.binding_description()];
+        let vertex_input_info = Box::new(vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_attribute_descriptions(&vertex_attrib_descriptions)
+            .vertex_binding_descriptions(&vertex_binding_descriptions)
+            .build());
+        let input_assembly_info = Box::new(vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(permutation.topology)
+            .build());
+
+        // Viewport, which may be letterboxed within the surface
+        let viewports = vec![vk::Viewport {
+            x: render_area.offset.x as f32,
+            y: render_area.offset.y as f32,
+            width: render_area.extent.width as f32,
+            height: render_area.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0
+        }];
+        let scissors = vec![render_area];
+        let viewport_info = Box::new(vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&viewports)
+            .scissors(&scissors)
+            .build());
+
+        // Random pipeline configurations
+        let rasterizer_state = permutation.rasterizer_state;
+        let depth_state = permutation.depth_state;
+        let stencil_state = permutation.stencil_state;
+        let rasterizer_info = Box::new(vk::PipelineRasterizationStateCreateInfo::builder()
+            .line_width(1.0)
+            .front_face(rasterizer_state.front_face)
+            .cull_mode(rasterizer_state.cull_mode)
+            .polygon_mode(rasterizer_state.polygon_mode)
+            .build());
+        let multisampler_info = Box::new(vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(resolved.samples)
+            .build());
+        let depth_stencil_info = Box::new(vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(depth_state.test_enable)
+            .depth_write_enable(depth_state.write_enable)
+            .depth_compare_op(resolved.depth_compare_op)
+            .stencil_test_enable(stencil_state.test_enable)
+            .front(stencil_state.front.op_state())
+            .back(stencil_state.back.op_state())
+            .build());
+        let colour_blend_attachments = vec![
+            permutation.blend_mode.attachment_state();
+            resolved.color_attachment_count as usize
+        ];
+        let colour_blend_info = Box::new(vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&colour_blend_attachments)
+            .build());
+
+        // Describe the attachments directly when there is no renderpass
+        let (color_format, depth_format) = resolved.dynamic_rendering_formats
+            .unwrap_or((vk::Format::UNDEFINED, vk::Format::UNDEFINED));
+        let color_formats = vec![color_format];
+        let rendering_info = resolved.dynamic_rendering_formats.map(|_| {
+            Box::new(vk::PipelineRenderingCreateInfo::builder()
+                .color_attachment_formats(&color_formats)
+                .depth_attachment_format(depth_format)
+                .stencil_attachment_format(stencil_format_of(depth_format))
+                .build())
+        });
+
+        Self {
+            _main_function_name: main_function_name,
+            _specialization_data: specialization_data,
+            _specialization_info: specialization_info,
+            shader_stages,
+            _vertex_attrib_descriptions: vertex_attrib_descriptions,
+            _vertex_binding_descriptions: vertex_binding_descriptions,
+            vertex_input_info,
+            input_assembly_info,
+            _viewports: viewports,
+            _scissors: scissors,
+            viewport_info,
+            rasterizer_info,
+            multisampler_info,
+            depth_stencil_info,
+            _colour_blend_attachments: colour_blend_attachments,
+            colour_blend_info,
+            _color_formats: color_formats,
+            rendering_info,
+            pipeline_layout: resolved.pipeline_layout,
+            renderpass: resolved.renderpass,
+            subpass: permutation.subpass
+        }
+    }
+
+    /// Info to create the pipeline from this description, valid while the description lives.
+    /// A derivative refers to its base by its position in the same create call.
+    fn create_info(
+        &self,
+        flags: vk::PipelineCreateFlags,
+        base_pipeline_index: i32
+    ) -> vk::GraphicsPipelineCreateInfo {
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .flags(flags)
+            .stages(&self.shader_stages)
+            .vertex_input_state(&self.vertex_input_info)
+            .input_assembly_state(&self.input_assembly_info)
+            .viewport_state(&self.viewport_info)
+            .rasterization_state(&self.rasterizer_info)
+            .multisample_state(&self.multisampler_info)
+            .depth_stencil_state(&self.depth_stencil_info)
+            .color_blend_state(&self.colour_blend_info)
+            .layout(self.pipeline_layout)
+            .render_pass(self.renderpass)
+            .subpass(self.subpass)
+            .base_pipeline_index(base_pipeline_index)
+            .build();
+        if let Some(rendering_info) = &self.rendering_info {
+            create_info.p_next =
+                rendering_info.as_ref() as *const vk::PipelineRenderingCreateInfo as *const c_void;
+        }
+        create_info
+    }
+}
+
+/// Create the pipelines for resolved permutations in a single call. The first pipeline using
+/// each pair of shaders allows derivatives, and later pipelines using the same pair derive from
+/// it, so the driver may share work between variants such as those for each swapchain image.
+unsafe fn create_resolved_pipelines(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    resolved: &[ResolvedPermutation],
+    render_area: vk::Rect2D
+) -> Result<Vec<vk::Pipeline>, EngineError> {
+    let descriptions: Vec<PipelineDescription> = resolved.iter()
+        .map(|resolved| PipelineDescription::new(resolved, render_area))
+        .collect();
+    let mut bases: Vec<((vk::ShaderModule, vk::ShaderModule), i32)> = vec![];
+    let create_infos: Vec<vk::GraphicsPipelineCreateInfo> = resolved.iter()
+        .zip(descriptions.iter())
+        .enumerate()
+        .map(|(index, (resolved, description))| {
+            let shaders = (resolved.vertex_shader_module, resolved.fragment_shader_module);
+            match bases.iter().find(|(base_shaders, _)| *base_shaders == shaders) {
+                Some((_, base_index)) =>
+                    description.create_info(vk::PipelineCreateFlags::DERIVATIVE, *base_index),
+                None => {
+                    bases.push((shaders, index as i32));
+                    description.create_info(vk::PipelineCreateFlags::ALLOW_DERIVATIVES, -1)
+                }
+            }
+        })
+        .collect();
+    device.create_graphics_pipelines(pipeline_cache, &create_infos, None)
+        .map_err(|(pipelines, e)| {
+            destroy_pipelines(device, &pipelines);
+            EngineError::OpFailed(format!("{:?}", e))
+        })
+}
+
+unsafe fn destroy_pipelines(device: &Device, pipelines: &[vk::Pipeline]) {
+    for pipeline in pipelines.iter().filter(|pipeline| **pipeline != vk::Pipeline::null()) {
+        device.destroy_pipeline(*pipeline, None);
+    }
+}
+
+/// Create the graphics pipeline for a permutation through the context's pipeline cache, looking
+/// up its shaders, layout and renderpass or dynamic render target in the ECS
+pub(crate) unsafe fn create_graphics_pipeline(
+    context: &VkContext,
+    ecs: &EcsManager<VkContext>,
+    permutation: &PipelinePermutation,
+    render_area: vk::Rect2D
+) -> Result<vk::Pipeline, EngineError> {
+    let resolved = [resolve_permutation(context, ecs, permutation)?];
+    let pipelines = create_resolved_pipelines(
+        &context.device,
+        context.get_pipeline_cache(),
+        &resolved,
+        render_area)?;
+    Ok(pipelines[0])
+}
+
+/// Create the graphics pipelines for several permutations through the context's pipeline cache,
+/// in as few calls as possible, returning them in the same order. They are split between as many
+/// threads as the context's pipeline creation thread count allows; see
+/// VkContext::set_pipeline_creation_threads. If any fails, none are returned.
+pub unsafe fn create_graphics_pipelines(
+    context: &VkContext,
+    ecs: &EcsManager<VkContext>,
+    permutations: &[&PipelinePermutation],
+    render_area: vk::Rect2D
+) -> Result<Vec<vk::Pipeline>, EngineError> {
+    let resolved = permutations.iter()
+        .map(|permutation| resolve_permutation(context, ecs, permutation))
+        .collect::<Result<Vec<ResolvedPermutation>, EngineError>>()?;
+    if resolved.is_empty() {
+        return Ok(vec![]);
+    }
+    let device = &context.device;
+    let pipeline_cache = context.get_pipeline_cache();
+    let thread_count = context.get_pipeline_creation_threads().clamp(1, resolved.len());
+    if thread_count == 1 {
+        return create_resolved_pipelines(device, pipeline_cache, &resolved, render_area);
+    }
+
+    // The pipeline cache is internally synchronised, so threads may share it
+    let chunk_size = resolved.len().div_ceil(thread_count);
+    let results: Vec<Result<Vec<vk::Pipeline>, EngineError>> = std::thread::scope(|scope| {
+        let threads: Vec<_> = resolved.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                create_resolved_pipelines(device, pipeline_cache, chunk, render_area)
+            }))
+            .collect();
+        threads.into_iter()
+            .map(|thread| thread.join().unwrap_or_else(|_| Err(EngineError::OpFailed(
+                String::from("Pipeline creation thread panicked")))))
+            .collect()
+    });
+    let mut pipelines = Vec::with_capacity(resolved.len());
+    let mut first_error = None;
+    for result in results.into_iter() {
+        match result {
+            Ok(created) => pipelines.extend(created),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) => {
+            destroy_pipelines(device, &pipelines);
+            Err(e)
+        },
+        None => Ok(pipelines)
+    }
+}
+
+/// Create then destroy the pipeline for each permutation, so that pipelines created from them
+/// later find their compiled state in the context's pipeline cache rather than compiling on
+/// first use. Permutations repeated in the list are only created once, and the rest are created
+/// in batches. The callback is given the number of permutations done so far and the total after
+/// each batch.
+pub unsafe fn prewarm_pipelines<F>(
+    context: &VkContext,
+    ecs: &EcsManager<VkContext>,
+    permutations: &[PipelinePermutation],
+    mut on_progress: F
+) -> Result<(), EngineError>
+    where F: FnMut(usize, usize)
+{
+    let mut unique_permutations: Vec<&PipelinePermutation> = vec![];
+    for permutation in permutations.iter() {
+        if !unique_permutations.contains(&permutation) {
+            unique_permutations.push(permutation);
+        }
+    }
+    let render_area = context.get_internal_render_area()?;
+    let total = unique_permutations.len();
+    let mut completed = 0;
+    for batch in unique_permutations.chunks(PREWARM_BATCH_SIZE) {
+        let pipelines = create_graphics_pipelines(context, ecs, batch, render_area)?;
+        destroy_pipelines(&context.device, &pipelines);
+        completed += batch.len();
+        on_progress(completed, total);
+    }
+    Ok(())
+}
@@ -0,0 +1,499 @@
+use crate::{
+    VkContext, ImageWrapper, ImageUsage, ImageAccess, TexturePixelFormat, SamplerWrapper,
+    OffscreenFramebufferWrapper, context::record_blit_to_swapchain_image
+};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use ash::{Device, vk};
+use std::ffi::CString;
+
+/// Most bytes of push constants that a post-processing pass may take; every device supports at
+/// least this many
+pub const MAX_POST_PROCESS_PUSH_CONSTANT_SIZE: usize = 128;
+
+/// PostProcessPass struct
+/// One pass of a post-processing chain, drawn with the referenced fragment shader over the whole
+/// target. The shader samples the previous pass's output, or the chain's source for the first
+/// pass, as:
+///     layout(set = 0, binding = 0) uniform sampler2D previous;
+/// The push constants, if any, are available to the fragment shader at offset zero, such as for
+/// a blur direction; their size must be a multiple of four.
+pub struct PostProcessPass {
+    pub fragment_shader_index: u32,
+    pub push_constants: Vec<u8>
+}
+
+/// PostProcessChainCreationData struct
+/// Information needed to create a post-processing chain. The source, looked up by resource index,
+/// is either an image or an off-screen framebuffer, whose first color texture is then used. The
+/// vertex shader is shared by every pass, and must cover the target without vertex input, such
+/// as a full-screen triangle generated from gl_VertexIndex. Every pass samples its input through
+/// the referenced sampler.
+pub struct PostProcessChainCreationData {
+    pub width: u32,
+    pub height: u32,
+    pub format: TexturePixelFormat,
+    pub source_index: u32,
+    pub sampler_index: u32,
+    pub vertex_shader_index: u32,
+    pub passes: Vec<PostProcessPass>
+}
+
+/// PostProcessChain struct
+/// A sequence of full-screen passes, such as blurs and tone mapping, that ping-pong between two
+/// off-screen targets, each pass reading what the one before it wrote. The last output can be
+/// blitted into a swapchain image, so as a resource depending on the swapchain, it should be
+/// reloaded along with the bearer's other dynamic resources. The source must be ready to sample
+/// when the chain is recorded, such as after
+/// OffscreenFramebufferWrapper::end_rendering_for_sampling.
+pub struct PostProcessChain {
+    targets: Vec<ImageWrapper>,
+    renderpass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    source_descriptor_set: vk::DescriptorSet,
+    target_descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    pipelines: Vec<vk::Pipeline>,
+    push_constants: Vec<Vec<u8>>,
+    extent: vk::Extent2D,
+    swapchain_images: Vec<vk::Image>,
+    swapchain_extent: vk::Extent2D,
+    output_layout: vk::ImageLayout,
+    render_area: vk::Rect2D,
+    presentable_by_copy: bool
+}
+
+impl Resource<VkContext> for PostProcessChain {
+    type CreationData = PostProcessChainCreationData;
+
+    fn create(
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &PostProcessChainCreationData
+    ) -> Result<Self, EngineError> {
+        validate_passes(&data.passes)?;
+        if !data.format.is_color_attachment_format() {
+            return Err(EngineError::UserError(format!(
+                "Post-processing targets can't be rendered in format {:?}",
+                data.format)));
+        }
+        let source_image_view =
+            source_image_view(ecs, Handle::for_resource(data.source_index))?;
+        let sampler = ecs
+            .get_item::<SamplerWrapper>(Handle::for_resource(data.sampler_index))
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No sampler at index {}", data.sampler_index)))?
+            .get_sampler();
+        let vertex_shader = *ecs
+            .get_item::<vk::ShaderModule>(Handle::for_resource(data.vertex_shader_index))
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No shader module at index {}", data.vertex_shader_index)))?;
+        let fragment_shaders = data.passes.iter()
+            .map(|pass| ecs
+                .get_item::<vk::ShaderModule>(Handle::for_resource(pass.fragment_shader_index))
+                .copied()
+                .ok_or_else(|| EngineError::MissingResource(
+                    format!("No shader module at index {}", pass.fragment_shader_index))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let swapchain_images = (0..loader.get_swapchain_image_count())
+            .map(|image_index| loader.get_swapchain_image(image_index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut chain = PostProcessChain {
+            targets: Vec::with_capacity(2),
+            renderpass: vk::RenderPass::null(),
+            framebuffers: Vec::with_capacity(2),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            source_descriptor_set: vk::DescriptorSet::null(),
+            target_descriptor_sets: Vec::with_capacity(2),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipelines: Vec::with_capacity(data.passes.len()),
+            push_constants: data.passes.iter().map(|pass| pass.push_constants.clone()).collect(),
+            extent: vk::Extent2D { width: data.width, height: data.height },
+            swapchain_images,
+            swapchain_extent: loader.get_extent()?,
+            output_layout: loader.get_output_layout(),
+            render_area: loader.get_render_area()?,
+            presentable_by_copy: loader.supports_present_image()
+        };
+        unsafe {
+            let result = chain.create_objects(loader, data.format)
+                .and_then(|_| {
+                    chain.write_descriptor_sets(loader, source_image_view, sampler);
+                    chain.create_pipelines(loader, vertex_shader, &fragment_shaders)
+                });
+            if let Err(e) = result {
+                chain.release(loader);
+                return Err(e);
+            }
+        }
+        Ok(chain)
+    }
+
+    fn release(&self, loader: &VkContext) {
+        unsafe {
+            let device = &loader.device;
+            for pipeline in self.pipelines.iter() {
+                device.destroy_pipeline(*pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            for framebuffer in self.framebuffers.iter() {
+                device.destroy_framebuffer(*framebuffer, None);
+            }
+            device.destroy_render_pass(self.renderpass, None);
+        }
+        for target in self.targets.iter() {
+            target.release(loader);
+        }
+    }
+}
+
+impl PostProcessChain {
+
+    unsafe fn create_objects(
+        &mut self,
+        context: &VkContext,
+        format: TexturePixelFormat
+    ) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        // The targets are moved into the attachment layout by a tracked barrier before each
+        // pass, which also waits for the previous pass to finish sampling them
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(format.vk_format())
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build()];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        }];
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build()];
+        let renderpass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+        self.renderpass = device.create_render_pass(&renderpass_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating post-processing renderpass: {:?}", e)))?;
+
+        for _ in 0..2 {
+            let target = ImageWrapper::new(
+                context,
+                ImageUsage::OffscreenRenderSampleColorWriteDepth,
+                format,
+                self.extent.width,
+                self.extent.height,
+                None)?;
+            let attachment_views = [target.image_view];
+            self.targets.push(target);
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.renderpass)
+                .attachments(&attachment_views)
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(1);
+            self.framebuffers.push(device.create_framebuffer(&framebuffer_info, None)
+                .map_err(|e| EngineError::OpFailed(
+                    format!("Error creating post-processing framebuffer: {:?}", e)))?);
+        }
+
+        let layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&layout_bindings);
+        self.descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor set layout: {:?}", e)))?;
+
+        // One set samples the source, and one each of the targets
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 3
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(3)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor pool: {:?}", e)))?;
+        let set_layouts = [self.descriptor_set_layout; 3];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_sets = device.allocate_descriptor_sets(&allocate_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating descriptor sets: {:?}", e)))?;
+        self.source_descriptor_set = descriptor_sets[0];
+        self.target_descriptor_sets = descriptor_sets[1..].to_vec();
+
+        let push_constant_size_bytes = self.push_constants.iter()
+            .map(|push_constants| push_constants.len())
+            .max()
+            .unwrap_or(0) as u32;
+        let set_layouts = [self.descriptor_set_layout];
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: push_constant_size_bytes
+        }];
+        let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts);
+        if push_constant_size_bytes > 0 {
+            pipeline_layout_info = pipeline_layout_info.push_constant_ranges(&push_constant_ranges);
+        }
+        self.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating pipeline layout: {:?}", e)))?;
+        Ok(())
+    }
+
+    unsafe fn write_descriptor_sets(
+        &self,
+        context: &VkContext,
+        source_image_view: vk::ImageView,
+        sampler: vk::Sampler
+    ) {
+        let image_views = std::iter::once(source_image_view)
+            .chain(self.targets.iter().map(|target| target.image_view));
+        let descriptor_sets = std::iter::once(self.source_descriptor_set)
+            .chain(self.target_descriptor_sets.iter().copied());
+        let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = image_views
+            .map(|image_view| [vk::DescriptorImageInfo {
+                image_view,
+                sampler,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }])
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = descriptor_sets
+            .zip(image_infos.iter())
+            .map(|(descriptor_set, image_info)| vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_info)
+                .build())
+            .collect();
+        context.device.update_descriptor_sets(&writes, &[]);
+    }
+
+    unsafe fn create_pipelines(
+        &mut self,
+        context: &VkContext,
+        vertex_shader: vk::ShaderModule,
+        fragment_shaders: &[vk::ShaderModule]
+    ) -> Result<(), EngineError> {
+        let main_function_name = CString::new("main").unwrap();
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent
+        }];
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&viewports)
+            .scissors(&scissors);
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&blend_attachments);
+
+        let stages: Vec<[vk::PipelineShaderStageCreateInfo; 2]> = fragment_shaders.iter()
+            .map(|fragment_shader| [
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .module(vertex_shader)
+                    .name(&main_function_name)
+                    .build(),
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .module(*fragment_shader)
+                    .name(&main_function_name)
+                    .build()
+            ])
+            .collect();
+        let pipeline_infos: Vec<vk::GraphicsPipelineCreateInfo> = stages.iter()
+            .map(|pass_stages| vk::GraphicsPipelineCreateInfo::builder()
+                .stages(pass_stages)
+                .vertex_input_state(&vertex_input_info)
+                .input_assembly_state(&input_assembly_info)
+                .viewport_state(&viewport_info)
+                .rasterization_state(&rasterization_info)
+                .multisample_state(&multisample_info)
+                .color_blend_state(&blend_info)
+                .layout(self.pipeline_layout)
+                .render_pass(self.renderpass)
+                .subpass(0)
+                .build())
+            .collect();
+        self.pipelines = context.device
+            .create_graphics_pipelines(context.get_pipeline_cache(), &pipeline_infos, None)
+            .map_err(|(pipelines, e)| {
+                for pipeline in pipelines.iter() {
+                    if *pipeline != vk::Pipeline::null() {
+                        context.device.destroy_pipeline(*pipeline, None);
+                    }
+                }
+                EngineError::OpFailed(format!("Error creating post-processing pipelines: {:?}", e))
+            })?;
+        Ok(())
+    }
+
+    /// Number of passes in the chain
+    pub fn get_pass_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    /// The target that the last pass writes, which is left ready to sample once the chain has
+    /// been recorded, such as for presenting with VkContext::present_image
+    pub fn get_output(&self) -> &ImageWrapper {
+        &self.targets[(self.pipelines.len() - 1) % 2]
+    }
+
+    /// Record every pass in turn, each reading the output of the one before. Every target is
+    /// left ready to sample.
+    pub unsafe fn record(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        for (pass_index, pipeline) in self.pipelines.iter().enumerate() {
+            let target = &self.targets[pass_index % 2];
+            let input_descriptor_set = match pass_index {
+                0 => self.source_descriptor_set,
+                _ => self.target_descriptor_sets[(pass_index - 1) % 2]
+            };
+            target.transition(device, command_buffer, ImageAccess::ColorAttachment);
+
+            let renderpass_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.renderpass)
+                .framebuffer(self.framebuffers[pass_index % 2])
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.extent
+                });
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &renderpass_begin_info,
+                vk::SubpassContents::INLINE);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[input_descriptor_set],
+                &[]);
+            let push_constants = &self.push_constants[pass_index];
+            if !push_constants.is_empty() {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    push_constants);
+            }
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+
+            target.assume_access(ImageAccess::ColorAttachment);
+            target.transition(device, command_buffer, ImageAccess::ShaderRead);
+        }
+    }
+
+    /// Record blitting the chain's output into the render area of a swapchain image, leaving
+    /// it ready to present; record the chain first. This takes the place of a renderpass into
+    /// the swapchain image, so needs a surface whose swapchain images can be copied into.
+    pub unsafe fn record_blit_to_swapchain(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image_index: usize
+    ) -> Result<(), EngineError> {
+        if !self.presentable_by_copy {
+            return Err(EngineError::Compatibility(
+                String::from("Surface does not support copying images into its swapchain")));
+        }
+        let swapchain_image = *self.swapchain_images.get(swapchain_image_index)
+            .ok_or_else(|| EngineError::UserError(format!(
+                "Swapchain image index {} out of range",
+                swapchain_image_index)))?;
+        record_blit_to_swapchain_image(
+            device,
+            command_buffer,
+            self.get_output(),
+            swapchain_image,
+            self.swapchain_extent,
+            self.output_layout,
+            self.render_area,
+            vk::Filter::LINEAR);
+        Ok(())
+    }
+}
+
+/// Check that a chain has passes, and that their push constants fit
+pub(crate) fn validate_passes(passes: &[PostProcessPass]) -> Result<(), EngineError> {
+    if passes.is_empty() {
+        return Err(EngineError::UserError(
+            String::from("Post-processing chain needs at least one pass")));
+    }
+    for (pass_index, pass) in passes.iter().enumerate() {
+        let size = pass.push_constants.len();
+        if size % 4 != 0 || size > MAX_POST_PROCESS_PUSH_CONSTANT_SIZE {
+            return Err(EngineError::UserError(format!(
+                "Post-processing pass {} has {} bytes of push constants; must be a multiple of 4 \
+                up to {}",
+                pass_index,
+                size,
+                MAX_POST_PROCESS_PUSH_CONSTANT_SIZE)));
+        }
+    }
+    Ok(())
+}
+
+/// The view of an image, or of an off-screen framebuffer's first color texture, to sample from
+pub(crate) fn source_image_view(
+    ecs: &EcsManager<VkContext>,
+    source_handle: Handle
+) -> Result<vk::ImageView, EngineError> {
+    match ecs.get_item::<ImageWrapper>(source_handle) {
+        Some(image) => Ok(image.image_view),
+        None => Ok(ecs
+            .get_item::<OffscreenFramebufferWrapper>(source_handle)
+            .ok_or_else(|| EngineError::MissingResource(format!(
+                "No image or off-screen framebuffer at index {}",
+                source_handle.table_index())))?
+            .color_texture()
+            .image_view)
+    }
+}
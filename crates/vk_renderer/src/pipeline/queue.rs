@@ -0,0 +1,92 @@
+
+use crate::{VkContext, PipelineWrapper};
+use ash::vk;
+
+/// RenderQueue enum
+/// Classification of pipeline steps by how their output combines with what is already drawn.
+/// Opaque steps write depth and don't blend; transparent steps blend over what is behind them
+/// and test depth without writing it, so they must be drawn afterward, back to front.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RenderQueue {
+    Opaque,
+    Transparent
+}
+
+impl RenderQueue {
+
+    /// Whether steps in this queue blend with the colour already in the framebuffer
+    pub fn blends(&self) -> bool {
+        *self == RenderQueue::Transparent
+    }
+
+    /// Whether steps in this queue write to the depth buffer
+    pub fn writes_depth(&self) -> bool {
+        *self == RenderQueue::Opaque
+    }
+}
+
+/// QueuedStep struct
+/// A pipeline step to be recorded by record_render_queues, along with the world-space position
+/// of each of its objects; positions are only used to sort transparent steps
+pub struct QueuedStep<'a> {
+    pub pipeline: &'a PipelineWrapper,
+    pub object_positions: &'a [[f32; 3]]
+}
+
+/// Order a set of positions from furthest to nearest the camera
+pub fn back_to_front_order(positions: &[[f32; 3]], camera_position: [f32; 3]) -> Vec<usize> {
+    let distances: Vec<f32> = positions.iter()
+        .map(|position| {
+            let dx = position[0] - camera_position[0];
+            let dy = position[1] - camera_position[1];
+            let dz = position[2] - camera_position[2];
+            dx * dx + dy * dy + dz * dz
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|a, b| distances[*b].total_cmp(&distances[*a]));
+    order
+}
+
+/// Record a frame's pipeline steps, assuming beginning/ending the renderpass is done separately.
+/// Opaque steps are drawn first, in the order given. Then every object of every transparent step
+/// is drawn back to front from the camera position, interleaving steps where needed; objects
+/// without a position are treated as being at the camera. Since the order depends on the
+/// camera, scenes with transparent steps should record their commands every frame.
+pub unsafe fn record_render_queues(
+    command_buffer: vk::CommandBuffer,
+    context: &VkContext,
+    pipeline_layout: vk::PipelineLayout,
+    steps: &[QueuedStep],
+    camera_position: [f32; 3]
+) {
+    for step in steps.iter() {
+        if step.pipeline.get_render_queue() == RenderQueue::Opaque {
+            step.pipeline.record_commands(command_buffer, context, pipeline_layout);
+        }
+    }
+
+    let mut transparent_objects: Vec<(usize, usize)> = vec![];
+    let mut transparent_positions: Vec<[f32; 3]> = vec![];
+    for (step_index, step) in steps.iter().enumerate() {
+        if step.pipeline.get_render_queue() != RenderQueue::Transparent {
+            continue;
+        }
+        for object_index in 0..step.pipeline.get_object_count() {
+            transparent_objects.push((step_index, object_index));
+            transparent_positions.push(
+                step.object_positions.get(object_index).copied().unwrap_or(camera_position));
+        }
+    }
+
+    let mut bound_step = None;
+    for index in back_to_front_order(&transparent_positions, camera_position) {
+        let (step_index, object_index) = transparent_objects[index];
+        let pipeline = steps[step_index].pipeline;
+        if bound_step != Some(step_index) {
+            pipeline.record_bind(command_buffer, context, pipeline_layout);
+            bound_step = Some(step_index);
+        }
+        pipeline.record_object_draw(command_buffer, context, pipeline_layout, object_index);
+    }
+}
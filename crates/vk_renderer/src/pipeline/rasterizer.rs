@@ -0,0 +1,48 @@
+use ash::vk;
+
+/// RasterizerState struct
+/// Which faces a pipeline culls, which winding order counts as front-facing, and whether
+/// triangles are filled or drawn as lines or points. Drawing other than filled needs the
+/// non-solid fill feature; see FeatureDeclaration::FillModeNonSolid. The default culls back faces
+/// of counter-clockwise triangles and fills them.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RasterizerState {
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub polygon_mode: vk::PolygonMode
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL
+        }
+    }
+}
+
+impl RasterizerState {
+
+    /// Draw both sides of every triangle, such as for foliage or other thin surfaces
+    pub fn double_sided() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::NONE,
+            ..Self::default()
+        }
+    }
+
+    /// Draw the edges of every triangle, front or back, such as for wireframe debug views
+    pub fn wireframe() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::LINE,
+            ..Self::default()
+        }
+    }
+
+    /// Whether this needs the non-solid fill feature
+    pub fn needs_non_solid_fill(&self) -> bool {
+        self.polygon_mode != vk::PolygonMode::FILL
+    }
+}
@@ -1,35 +1,288 @@
 
-use crate::{VkContext, OffscreenFramebufferWrapper, TexturePixelFormat};
+use crate::{
+    VkContext, OffscreenFramebufferWrapper, TexturePixelFormat, ImageWrapper,
+    SecondaryInheritance, DRAW_METADATA_BINDING, pipeline::dynamic_rendering::stencil_format_of
+};
+use color::Color;
 use ecs::{EcsManager, Handle, resource::Resource};
 use error::EngineError;
-use ash::vk;
+use ash::{Device, vk};
+
+/// Binding at which a descriptor set layout created with input attachments holds the first of
+/// them; each further one takes the next binding
+pub const INPUT_ATTACHMENT_FIRST_BINDING: u32 = DRAW_METADATA_BINDING + 1;
 
 /// RenderpassTarget enum
 /// Used to signal what arrangement of attachments and subpasses will be used in a renderpass
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub enum RenderpassTarget {
 
     // Will require one renderpass per swapchain image
     SwapchainImageWithDepth,
 
     // Contains the index of the offscreen framebuffer, then the width, then the height
-    OffscreenImageWithDepth(u32, u32, u32)
+    OffscreenImageWithDepth(u32, u32, u32),
+
+    // Renders into a swapchain image through the described subpasses; will require one
+    // renderpass per swapchain image
+    Custom(CustomRenderpassDescription)
+}
+
+/// CustomAttachment enum
+/// An attachment referred to by the subpasses of a custom renderpass. The output is the swapchain
+/// image, stored for presenting, and the depth attachment is the context's depth image.
+/// Intermediate attachments, indexing the description's intermediate formats, are created by the
+/// renderpass at the swapchain's size and discarded at its end, so that tiled GPUs may keep them
+/// in tile memory rather than writing them out.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CustomAttachment {
+    Output,
+    Depth,
+    Intermediate(usize)
+}
+
+/// CustomSubpass struct
+/// The attachments that one subpass of a custom renderpass writes and reads. Input attachments
+/// must be intermediate ones written by an earlier subpass; the fragment shader reads them at its
+/// own pixel through a descriptor set layout created with input attachments, in the order given
+/// here from INPUT_ATTACHMENT_FIRST_BINDING.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct CustomSubpass {
+    pub color_attachments: Vec<CustomAttachment>,
+    pub input_attachments: Vec<CustomAttachment>,
+    pub depth_attachment: Option<CustomAttachment>
+}
+
+/// CustomSubpassDependency struct
+/// Makes a subpass wait, pixel by pixel, for an earlier one's attachment writes before reading
+/// them; needed wherever one subpass reads another's output as an input attachment
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CustomSubpassDependency {
+    pub src_subpass: u32,
+    pub dst_subpass: u32
+}
+
+/// CustomRenderpassDescription struct
+/// Subpasses that run in order within one renderpass, with the formats of the intermediate
+/// attachments passed between them. For deferred shading, a first subpass might write albedo and
+/// normals into intermediate attachments along with depth, and a second read them as input
+/// attachments to light each pixel into the output, with a dependency from the first to the
+/// second. Pipelines choose which subpass they draw in with the subpass index in their creation
+/// data.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CustomRenderpassDescription {
+    pub intermediate_formats: Vec<TexturePixelFormat>,
+    pub subpasses: Vec<CustomSubpass>,
+    pub dependencies: Vec<CustomSubpassDependency>
+}
+
+impl CustomRenderpassDescription {
+
+    /// Check that every attachment referred to exists and suits how it is used, and that each
+    /// dependency runs forwards between existing subpasses
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if self.subpasses.is_empty() {
+            return Err(EngineError::UserError("Custom renderpass has no subpasses".to_owned()));
+        }
+        for format in self.intermediate_formats.iter() {
+            match format {
+                format if format.is_color_attachment_format() || format.is_depth() => {},
+                _ => return Err(EngineError::UserError(
+                    format!("Cannot use {:?} for an intermediate attachment", format)))
+            }
+        }
+        for (subpass_index, subpass) in self.subpasses.iter().enumerate() {
+            let holds_depth = |attachment: CustomAttachment| self.holds_depth(attachment)
+                .ok_or_else(|| EngineError::UserError(format!(
+                    "Subpass {} refers to missing attachment {:?}",
+                    subpass_index,
+                    attachment)));
+            for attachment in subpass.color_attachments.iter() {
+                if holds_depth(*attachment)? {
+                    return Err(EngineError::UserError(format!(
+                        "Subpass {} uses depth attachment {:?} as a color attachment",
+                        subpass_index,
+                        attachment)));
+                }
+            }
+            if let Some(attachment) = subpass.depth_attachment {
+                if !holds_depth(attachment)? {
+                    return Err(EngineError::UserError(format!(
+                        "Subpass {} uses color attachment {:?} as its depth attachment",
+                        subpass_index,
+                        attachment)));
+                }
+            }
+            for attachment in subpass.input_attachments.iter() {
+                holds_depth(*attachment)?;
+                if !matches!(attachment, CustomAttachment::Intermediate(_)) {
+                    return Err(EngineError::UserError(format!(
+                        "Subpass {} reads {:?}, but only intermediate attachments can be inputs",
+                        subpass_index,
+                        attachment)));
+                }
+                if subpass.color_attachments.contains(attachment) ||
+                    subpass.depth_attachment == Some(*attachment)
+                {
+                    return Err(EngineError::UserError(format!(
+                        "Subpass {} both writes and reads {:?}",
+                        subpass_index,
+                        attachment)));
+                }
+            }
+        }
+        let subpass_count = self.subpasses.len() as u32;
+        for dependency in self.dependencies.iter() {
+            if dependency.src_subpass >= dependency.dst_subpass ||
+                dependency.dst_subpass >= subpass_count
+            {
+                return Err(EngineError::UserError(format!(
+                    "Dependency from subpass {} to {} is not forwards between existing subpasses",
+                    dependency.src_subpass,
+                    dependency.dst_subpass)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Clear values for every attachment of a renderpass created from this description, in the
+    /// order that beginning the renderpass takes them
+    pub fn clear_values(&self, color: Color, depth: f32) -> Vec<vk::ClearValue> {
+        let mut clear_values = vec![
+            RenderpassWrapper::color_clear_value(color),
+            RenderpassWrapper::depth_clear_value(depth)
+        ];
+        clear_values.extend(self.intermediate_formats.iter().map(|format| match format.is_depth() {
+            true => RenderpassWrapper::depth_clear_value(depth),
+            false => RenderpassWrapper::color_clear_value(color)
+        }));
+        clear_values
+    }
+
+    /// Whether an attachment holds depth, or None if there is no such attachment
+    fn holds_depth(&self, attachment: CustomAttachment) -> Option<bool> {
+        match attachment {
+            CustomAttachment::Output => Some(false),
+            CustomAttachment::Depth => Some(true),
+            CustomAttachment::Intermediate(index) => self.intermediate_formats
+                .get(index)
+                .map(|format| format.is_depth())
+        }
+    }
+
+    /// Position of an attachment within the renderpass: the output, then the depth image, then
+    /// the intermediate attachments in order
+    fn attachment_index(attachment: CustomAttachment) -> u32 {
+        match attachment {
+            CustomAttachment::Output => 0,
+            CustomAttachment::Depth => 1,
+            CustomAttachment::Intermediate(index) => 2 + index as u32
+        }
+    }
+
+    /// Reference to an attachment as a subpass writes it, or reads it as an input attachment
+    fn attachment_reference(
+        &self,
+        attachment: CustomAttachment,
+        as_input: bool
+    ) -> vk::AttachmentReference {
+        let holds_depth = self.holds_depth(attachment).unwrap_or(false);
+        vk::AttachmentReference {
+            attachment: Self::attachment_index(attachment),
+            layout: match (holds_depth, as_input) {
+                (false, false) => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                (false, true) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                (true, false) => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                (true, true) => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            }
+        }
+    }
 }
 
 /// RenderpassCreationData struct
-/// Information needed to prepare a (potentially reusable) renderpass ahead of time
+/// Information needed to prepare a (potentially reusable) renderpass ahead of time. With more
+/// than one sample, rendering goes into multisampled color and depth attachments owned by the
+/// renderpass, and the color is resolved into the target at the end; see
+/// VkContext::select_msaa_samples.
 pub struct RenderpassCreationData {
     pub target: RenderpassTarget,
-    pub swapchain_image_index: usize
+    pub swapchain_image_index: usize,
+    pub samples: vk::SampleCountFlags
 }
 
 /// RenderpassWrapper struct
 /// Wraps resources related to renderpasses, including framebuffers. Resources need to be recreated
-/// if the swapchain is recreated.
+/// if the swapchain is recreated. Pipelines created for the renderpass use its sample count, and
+/// blend into as many color attachments as their subpass writes.
 pub struct RenderpassWrapper {
     pub renderpass: vk::RenderPass,
     pub swapchain_framebuffer: vk::Framebuffer,
-    pub custom_framebuffer: Option<vk::Framebuffer>
+    pub custom_framebuffer: Option<vk::Framebuffer>,
+    samples: vk::SampleCountFlags,
+    msaa_attachments: Option<MsaaAttachments>,
+    intermediate_attachments: Vec<ImageWrapper>,
+    subpass_color_attachment_counts: Vec<u32>,
+    attachment_holds_depth: Vec<bool>
+}
+
+/// Multisampled images rendered into before resolving, one for each color attachment; there is
+/// no depth image if the target has no depth
+struct MsaaAttachments {
+    colors: Vec<ImageWrapper>,
+    depth: Option<ImageWrapper>
+}
+
+impl MsaaAttachments {
+
+    unsafe fn new(
+        context: &VkContext,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags
+    ) -> Result<Self, EngineError> {
+        let mut attachments = Self { colors: vec![], depth: None };
+        for color_format in color_formats.iter() {
+            match ImageWrapper::new_attachment(
+                context,
+                *color_format,
+                extent.width,
+                extent.height,
+                samples
+            ) {
+                Ok(color) => attachments.colors.push(color),
+                Err(e) => {
+                    attachments.release(context);
+                    return Err(e);
+                }
+            }
+        }
+        if let Some(format) = depth_format {
+            match ImageWrapper::new_attachment(
+                context,
+                format,
+                extent.width,
+                extent.height,
+                samples
+            ) {
+                Ok(depth) => attachments.depth = Some(depth),
+                Err(e) => {
+                    attachments.release(context);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(attachments)
+    }
+
+    fn release(&self, context: &VkContext) {
+        for color in self.colors.iter() {
+            color.release(context);
+        }
+        if let Some(depth) = &self.depth {
+            depth.release(context);
+        }
+    }
 }
 
 impl Resource<VkContext> for RenderpassWrapper {
@@ -40,21 +293,31 @@ impl Resource<VkContext> for RenderpassWrapper {
         ecs: &EcsManager<VkContext>,
         data: &RenderpassCreationData
     ) -> Result<Self, EngineError> {
-        match data.target {
+        match &data.target {
             RenderpassTarget::SwapchainImageWithDepth => {
                 let renderpass = RenderpassWrapper::new_with_swapchain_target(
                     loader,
-                    data.swapchain_image_index)?;
+                    data.swapchain_image_index,
+                    data.samples)?;
                 Ok(renderpass)
             },
             RenderpassTarget::OffscreenImageWithDepth(framebuffer_index, _, _) => {
                 let framebuffer  = ecs
                     .get_item::<OffscreenFramebufferWrapper>(
-                        Handle::for_resource(framebuffer_index))
+                        Handle::for_resource(*framebuffer_index))
                     .unwrap();
                 let renderpass = RenderpassWrapper::new_with_offscreen_target(
                     loader,
-                    &framebuffer)?;
+                    &framebuffer,
+                    data.samples)?;
+                Ok(renderpass)
+            },
+            RenderpassTarget::Custom(description) => {
+                let renderpass = RenderpassWrapper::new_with_custom_target(
+                    loader,
+                    description,
+                    data.swapchain_image_index,
+                    data.samples)?;
                 Ok(renderpass)
             }
         }
@@ -68,25 +331,53 @@ impl Resource<VkContext> for RenderpassWrapper {
             }
             loader.device.destroy_render_pass(self.renderpass, None);
         }
+        if let Some(attachments) = &self.msaa_attachments {
+            attachments.release(loader);
+        }
+        for attachment in self.intermediate_attachments.iter() {
+            attachment.release(loader);
+        }
     }
 }
 
 impl RenderpassWrapper {
 
+    /// Clear value for a color attachment, taking the color's linear components
+    pub fn color_clear_value(color: Color) -> vk::ClearValue {
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: color.to_array()
+            }
+        }
+    }
+
+    /// Clear value for a depth attachment
+    pub fn depth_clear_value(depth: f32) -> vk::ClearValue {
+        Self::depth_stencil_clear_value(depth, 0)
+    }
+
+    /// Clear value for a depth attachment with a stencil aspect, such as Depth24Stencil8
+    pub fn depth_stencil_clear_value(depth: f32, stencil: u32) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth,
+                stencil
+            }
+        }
+    }
+
     /// Create a new instance for rendering to a swapchain image, with all resources initialised
     pub fn new_with_swapchain_target(
         context: &VkContext,
-        image_index: usize
+        image_index: usize,
+        samples: vk::SampleCountFlags
     ) -> Result<RenderpassWrapper, EngineError> {
-        let mut wrapper = RenderpassWrapper {
-            renderpass: vk::RenderPass::null(),
-            swapchain_framebuffer: vk::Framebuffer::null(),
-            custom_framebuffer: None
-        };
+        let mut wrapper = RenderpassWrapper::new_empty(samples);
         unsafe {
-            wrapper.create_swapchain_renderpass_resources(
-                context,
-                image_index)?;
+            if let Err(e) = wrapper.create_swapchain_renderpass_resources(context, image_index) {
+                wrapper.release(context);
+                return Err(e);
+            }
         }
         Ok(wrapper)
     }
@@ -94,23 +385,134 @@ impl RenderpassWrapper {
     /// Create a new instance, with all resources initialised
     pub fn new_with_offscreen_target(
         context: &VkContext,
-        target: &OffscreenFramebufferWrapper
+        target: &OffscreenFramebufferWrapper,
+        samples: vk::SampleCountFlags
     ) -> Result<RenderpassWrapper, EngineError> {
-        let mut wrapper = RenderpassWrapper {
-            renderpass: vk::RenderPass::null(),
-            swapchain_framebuffer: vk::Framebuffer::null(),
-            custom_framebuffer: None
-        };
+        let mut wrapper = RenderpassWrapper::new_empty(samples);
         unsafe {
-            wrapper.create_offscreen_renderpass_resources(
-                context,
-                target,
-                true)?;
+            if let Err(e) = wrapper.create_offscreen_renderpass_resources(context, target, true) {
+                wrapper.release(context);
+                return Err(e);
+            }
         }
         Ok(wrapper)
     }
 
-    /// Create all resources for rendering into a swapchain image
+    /// Create a new instance for rendering to a swapchain image through the subpasses of a
+    /// custom description, with all resources initialised. Custom renderpasses are not
+    /// multisampled.
+    pub fn new_with_custom_target(
+        context: &VkContext,
+        description: &CustomRenderpassDescription,
+        image_index: usize,
+        samples: vk::SampleCountFlags
+    ) -> Result<RenderpassWrapper, EngineError> {
+        description.validate()?;
+        if samples != vk::SampleCountFlags::TYPE_1 {
+            return Err(EngineError::UserError(
+                format!("Custom renderpasses cannot be multisampled, got {:?}", samples)));
+        }
+        let mut wrapper = RenderpassWrapper::new_empty(samples);
+        unsafe {
+            if let Err(e) =
+                wrapper.create_custom_renderpass_resources(context, image_index, description)
+            {
+                wrapper.release(context);
+                return Err(e);
+            }
+        }
+        Ok(wrapper)
+    }
+
+    fn new_empty(samples: vk::SampleCountFlags) -> RenderpassWrapper {
+        RenderpassWrapper {
+            renderpass: vk::RenderPass::null(),
+            swapchain_framebuffer: vk::Framebuffer::null(),
+            custom_framebuffer: None,
+            samples,
+            msaa_attachments: None,
+            intermediate_attachments: vec![],
+            subpass_color_attachment_counts: vec![1],
+            attachment_holds_depth: vec![]
+        }
+    }
+
+    /// The number of samples per pixel rendered by this renderpass
+    pub fn get_samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    /// The number of subpasses in this renderpass
+    pub fn get_subpass_count(&self) -> u32 {
+        self.subpass_color_attachment_counts.len() as u32
+    }
+
+    /// The number of color attachments that a subpass writes, or None if there is no such subpass
+    pub fn get_color_attachment_count(&self, subpass: u32) -> Option<u32> {
+        self.subpass_color_attachment_counts.get(subpass as usize).copied()
+    }
+
+    /// Clear values for every attachment of this renderpass, in the order that beginning the
+    /// renderpass takes them, clearing color attachments to the given color and depth attachments
+    /// to the given depth
+    pub fn clear_values(&self, color: Color, depth: f32) -> Vec<vk::ClearValue> {
+        Self::clear_values_of_kinds(&self.attachment_holds_depth, color, depth)
+    }
+
+    /// Clear values for attachments of the given kinds, one each, in order; see attachment_kinds
+    pub(crate) fn clear_values_of_kinds(
+        holds_depth: &[bool],
+        color: Color,
+        depth: f32
+    ) -> Vec<vk::ClearValue> {
+        holds_depth.iter()
+            .map(|holds_depth| match holds_depth {
+                true => Self::depth_clear_value(depth),
+                false => Self::color_clear_value(color)
+            })
+            .collect()
+    }
+
+    /// Whether each of the described attachments holds depth
+    pub(crate) fn attachment_kinds(attachments: &[vk::AttachmentDescription]) -> Vec<bool> {
+        attachments.iter()
+            .map(|attachment|
+                attachment.final_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .collect()
+    }
+
+    /// Note which of the attachments described for the renderpass hold depth, for clear values
+    fn record_attachment_kinds(&mut self, attachments: &[vk::AttachmentDescription]) {
+        self.attachment_holds_depth = Self::attachment_kinds(attachments);
+    }
+
+    /// Image view of one of a custom renderpass's intermediate attachments, for writing into the
+    /// descriptor sets of pipelines that read it as an input attachment
+    pub fn get_intermediate_image_view(&self, index: usize) -> Option<vk::ImageView> {
+        self.intermediate_attachments.get(index).map(|image| image.image_view)
+    }
+
+    /// Record moving on to the next subpass, whose commands are recorded inline
+    pub unsafe fn record_next_subpass(device: &Device, command_buffer: vk::CommandBuffer) {
+        device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+    }
+
+    /// What secondary command buffers executed in the given subpass continue rendering into. The
+    /// subpass must be begun with SECONDARY_COMMAND_BUFFERS contents to execute them.
+    pub fn secondary_inheritance(&self, subpass: u32) -> SecondaryInheritance {
+        SecondaryInheritance::Renderpass {
+            renderpass: self.renderpass,
+            subpass,
+            framebuffer: self.custom_framebuffer.unwrap_or(self.swapchain_framebuffer)
+        }
+    }
+
+    fn is_multisampled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Create all resources for rendering into a swapchain image. With multisampling, the
+    /// swapchain image becomes the resolve attachment.
     unsafe fn create_swapchain_renderpass_resources(
         &mut self,
         context: &VkContext,
@@ -124,31 +526,22 @@ impl RenderpassWrapper {
             ))
         };
 
-        // Define subpass with single colour attachment
+        // Define subpass with single colour attachment, rendered into directly or resolved into
         let surface_format = context.get_surface_format().format;
-        let attachments = [
-            vk::AttachmentDescription::builder()
-                .format(surface_format)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE)
-                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .build(),
-            vk::AttachmentDescription::builder()
-                .format(depth_image.format)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .build()
-        ];
+        let multisampled = self.is_multisampled();
+        let attachments = Self::swapchain_attachments(
+            surface_format,
+            depth_image.format,
+            context.get_output_layout(),
+            self.samples);
+        self.record_attachment_kinds(&attachments);
         let color_attachment_refs = [
+            vk::AttachmentReference {
+                attachment: if multisampled { 2 } else { 0 },
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            }
+        ];
+        let resolve_attachment_refs = [
             vk::AttachmentReference {
                 attachment: 0,
                 layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
@@ -158,13 +551,14 @@ impl RenderpassWrapper {
             attachment: 1,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
         };
-        let subpasses = [
-            vk::SubpassDescription::builder()
-                .color_attachments(&color_attachment_refs)
-                .depth_stencil_attachment(&depth_attachment_ref)
-                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                .build()
-        ];
+        let mut subpass_description = vk::SubpassDescription::builder()
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if multisampled {
+            subpass_description = subpass_description.resolve_attachments(&resolve_attachment_refs);
+        }
+        let subpasses = [subpass_description.build()];
         let subpass_dependencies = [
             vk::SubpassDependency::builder()
                 .src_subpass(vk::SUBPASS_EXTERNAL)
@@ -187,6 +581,17 @@ impl RenderpassWrapper {
             .map_err(|e| {
                 EngineError::OpFailed(format!("{:?}", e))
             })?;
+        self.renderpass = renderpass;
+
+        // With multisampling, the depth buffer is the renderpass's own rather than the context's
+        if multisampled {
+            self.msaa_attachments = Some(MsaaAttachments::new(
+                context,
+                &[surface_format],
+                Some(depth_image.format),
+                context.get_extent()?,
+                self.samples)?);
+        }
 
         // Create framebuffers for the swapchain image views for use in this renderpass
         let framebuffer = self.create_swapchain_framebuffer(
@@ -194,14 +599,15 @@ impl RenderpassWrapper {
             image_index,
             renderpass)?;
 
-        self.renderpass = renderpass;
         self.swapchain_framebuffer = framebuffer;
         self.custom_framebuffer = None;
 
         Ok(())
     }
 
-    /// Create all resources for rendering into an offscreen framebuffer
+    /// Create all resources for rendering into an offscreen framebuffer, writing each of its
+    /// color textures from the one subpass. With multisampling, the framebuffer's color textures
+    /// become the resolve attachments, and its depth texture is not used.
     unsafe fn create_offscreen_renderpass_resources(
         &mut self,
         context: &VkContext,
@@ -217,65 +623,58 @@ impl RenderpassWrapper {
             );
         }
 
-        // Get the texture to use for color attachment
-        let color_format = match target.color_format {
-            TexturePixelFormat::Rgba => vk::Format::R8G8B8A8_UNORM,
-            _ => return Err(EngineError::OpFailed(
-                format!("Cannot set color attachment to {:?}", target.color_format)))
-        };
+        // Get the formats to use for color attachments
+        let color_formats = target.color_formats.iter()
+            .map(|format| match format {
+                format if format.is_color_attachment_format() => Ok(format.vk_format()),
+                _ => Err(EngineError::OpFailed(
+                    format!("Cannot set color attachment to {:?}", format)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let color_count = color_formats.len() as u32;
 
-        // Define subpass with single colour attachment and optionally depth attachment
-        let initial_layout = vk::ImageLayout::UNDEFINED;
-        let mut attachments = vec![vk::AttachmentDescription::builder()
-            .format(color_format)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(initial_layout)
-            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .build()];
-        let depth_texture_image_view = match &target.depth_texture {
-            Some(depth_texture) => {
+        // Define subpass with the colour attachments and optionally depth attachment
+        let multisampled = self.is_multisampled();
+        let depth_format = match &target.depth_texture {
+            Some(_) => {
                 // Get the texture to use for depth attachment
-                match target.depth_format {
-                    TexturePixelFormat::Unorm16 => {
-                        attachments.push(vk::AttachmentDescription::builder()
-                            .format(vk::Format::D16_UNORM)
-                            .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                            .initial_layout(initial_layout)
-                            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                            .samples(vk::SampleCountFlags::TYPE_1)
-                            .build());
-                    },
-                    _ => return Err(EngineError::OpFailed(
-                        format!("Cannot set depth attachment tp {:?}", target.depth_format))
-                    )
-                };
-                Some(depth_texture.image_view)
+                if !target.depth_format.is_depth() {
+                    return Err(EngineError::OpFailed(
+                        format!("Cannot set depth attachment tp {:?}", target.depth_format)));
+                }
+                Some(target.depth_format.vk_format())
             },
             _ => None
         };
+        let attachments = Self::offscreen_attachments(&color_formats, depth_format, self.samples);
+        let first_msaa_color_index = color_count + depth_format.map_or(0, |_| 1);
+        self.record_attachment_kinds(&attachments);
 
-        let color_attachment_refs = [
-            vk::AttachmentReference {
-                attachment: 0,
+        let color_attachment_refs: Vec<vk::AttachmentReference> = (0..color_count)
+            .map(|index| vk::AttachmentReference {
+                attachment: if multisampled { first_msaa_color_index + index } else { index },
                 layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-            }
-        ];
+            })
+            .collect();
+        let resolve_attachment_refs: Vec<vk::AttachmentReference> = (0..color_count)
+            .map(|index| vk::AttachmentReference {
+                attachment: index,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            })
+            .collect();
 
         let depth_attachment_ref = vk::AttachmentReference {
-            attachment: 1,
+            attachment: color_count,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
         };
         let subpasses = {
-            let subpass_description = vk::SubpassDescription::builder()
+            let mut subpass_description = vk::SubpassDescription::builder()
                 .color_attachments(&color_attachment_refs)
                 .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+            if multisampled {
+                subpass_description =
+                    subpass_description.resolve_attachments(&resolve_attachment_refs);
+            }
             if target.depth_texture.is_some() {
                 [subpass_description.depth_stencil_attachment(&depth_attachment_ref).build()]
             } else {
@@ -312,20 +711,287 @@ impl RenderpassWrapper {
             .map_err(|e| {
                 EngineError::OpFailed(format!("{:?}", e))
             })?;
-
-        // Create framebuffers for swapchain image views, or new framebuffers from scratch, for use in this renderpass
         self.renderpass = renderpass;
+        self.subpass_color_attachment_counts = vec![color_count];
+
+        // Attachments in the framebuffer follow the order of their descriptions
+        let mut attachment_image_views: Vec<vk::ImageView> = target.color_textures.iter()
+            .map(|texture| texture.image_view)
+            .collect();
+        if multisampled {
+            let msaa_attachments = MsaaAttachments::new(
+                context,
+                &color_formats,
+                depth_format,
+                vk::Extent2D { width: target.width, height: target.height },
+                self.samples)?;
+            if let Some(depth) = &msaa_attachments.depth {
+                attachment_image_views.push(depth.image_view);
+            }
+            attachment_image_views.extend(
+                msaa_attachments.colors.iter().map(|color| color.image_view));
+            self.msaa_attachments = Some(msaa_attachments);
+        } else if let Some(depth_texture) = &target.depth_texture {
+            attachment_image_views.push(depth_texture.image_view);
+        }
+
+        // Create new framebuffers from scratch for use in this renderpass
         self.swapchain_framebuffer = vk::Framebuffer::null();
         self.custom_framebuffer = Some(Self::create_offscreen_framebuffer(
             context,
             renderpass,
             target,
-            target.color_texture.image_view,
-            depth_texture_image_view)?);
+            &attachment_image_views)?);
 
         Ok(())
     }
 
+    /// Create all resources for rendering into a swapchain image through the subpasses of a
+    /// custom description
+    unsafe fn create_custom_renderpass_resources(
+        &mut self,
+        context: &VkContext,
+        image_index: usize,
+        description: &CustomRenderpassDescription
+    ) -> Result<(), EngineError> {
+
+        let depth_image = match context.get_depth_image() {
+            Some(image) => image,
+            _ => return Err(EngineError::OpFailed(
+                String::from("Creating new renderpass wrapper with no depth image available")
+            ))
+        };
+
+        // The output and depth image come first, as with a single subpass, then the
+        // intermediate attachments, which don't outlive the renderpass
+        let surface_format = context.get_surface_format().format;
+        let single_sample = vk::SampleCountFlags::TYPE_1;
+        let mut attachments = vec![
+            vk::AttachmentDescription::builder()
+                .format(surface_format)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(context.get_output_layout())
+                .samples(single_sample)
+                .build(),
+            Self::depth_attachment(depth_image.format, single_sample)
+        ];
+        attachments.extend(description.intermediate_formats.iter().map(|format| {
+            match format.is_depth() {
+                true => Self::depth_attachment(format.vk_format(), single_sample),
+                false => Self::transient_color_attachment(format.vk_format(), single_sample)
+            }
+        }));
+        self.record_attachment_kinds(&attachments);
+
+        // Attachment references for each subpass, which must live until the renderpass is made
+        let color_attachment_refs: Vec<Vec<vk::AttachmentReference>> = description.subpasses
+            .iter()
+            .map(|subpass| subpass.color_attachments.iter()
+                .map(|attachment| description.attachment_reference(*attachment, false))
+                .collect())
+            .collect();
+        let input_attachment_refs: Vec<Vec<vk::AttachmentReference>> = description.subpasses
+            .iter()
+            .map(|subpass| subpass.input_attachments.iter()
+                .map(|attachment| description.attachment_reference(*attachment, true))
+                .collect())
+            .collect();
+        let depth_attachment_refs: Vec<Option<vk::AttachmentReference>> = description.subpasses
+            .iter()
+            .map(|subpass| subpass.depth_attachment
+                .map(|attachment| description.attachment_reference(attachment, false)))
+            .collect();
+        let subpasses: Vec<vk::SubpassDescription> = (0..description.subpasses.len())
+            .map(|index| {
+                let mut subpass_description = vk::SubpassDescription::builder()
+                    .color_attachments(&color_attachment_refs[index])
+                    .input_attachments(&input_attachment_refs[index])
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+                if let Some(depth_attachment_ref) = &depth_attachment_refs[index] {
+                    subpass_description =
+                        subpass_description.depth_stencil_attachment(depth_attachment_ref);
+                }
+                subpass_description.build()
+            })
+            .collect();
+
+        // Wait for the output to be free before the first subpass, and for each subpass's writes
+        // before a later one reads them
+        let attachment_output_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT |
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS |
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let attachment_write_access = vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        let mut subpass_dependencies = vec![
+            vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(attachment_output_stages)
+                .dst_subpass(0)
+                .dst_stage_mask(attachment_output_stages)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | attachment_write_access)
+                .build()
+        ];
+        subpass_dependencies.extend(description.dependencies.iter().map(|dependency| {
+            vk::SubpassDependency::builder()
+                .src_subpass(dependency.src_subpass)
+                .src_stage_mask(attachment_output_stages)
+                .src_access_mask(attachment_write_access)
+                .dst_subpass(dependency.dst_subpass)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | attachment_output_stages)
+                .dst_access_mask(
+                    vk::AccessFlags::INPUT_ATTACHMENT_READ |
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ |
+                        attachment_write_access
+                )
+                .dependency_flags(vk::DependencyFlags::BY_REGION)
+                .build()
+        }));
+
+        // Create the renderpass with all of its subpasses
+        let renderpass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&subpass_dependencies);
+        let renderpass = context.device
+            .create_render_pass(&renderpass_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("{:?}", e))
+            })?;
+        self.renderpass = renderpass;
+        self.subpass_color_attachment_counts = description.subpasses.iter()
+            .map(|subpass| subpass.color_attachments.len() as u32)
+            .collect();
+
+        // Create the intermediate attachments at the size of the swapchain
+        let extent = context.get_extent()?;
+        for format in description.intermediate_formats.iter() {
+            self.intermediate_attachments.push(ImageWrapper::new_input_attachment(
+                context,
+                format.vk_format(),
+                extent.width,
+                extent.height)?);
+        }
+
+        // Create a framebuffer for the swapchain image view and every other attachment
+        self.swapchain_framebuffer = self.create_swapchain_framebuffer(
+            context,
+            image_index,
+            renderpass)?;
+        self.custom_framebuffer = None;
+
+        Ok(())
+    }
+
+    /// Description of a depth attachment, cleared when the renderpass begins along with its
+    /// stencil aspect if the format has one, and not kept afterwards
+    /// Attachments of a renderpass rendering into a swapchain image: the swapchain image, the
+    /// depth image and, with multisampling, the color image resolved into the swapchain image
+    pub(crate) fn swapchain_attachments(
+        surface_format: vk::Format,
+        depth_format: vk::Format,
+        output_layout: vk::ImageLayout,
+        samples: vk::SampleCountFlags
+    ) -> Vec<vk::AttachmentDescription> {
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+        let mut attachments = vec![
+            vk::AttachmentDescription::builder()
+                .format(surface_format)
+                .load_op(match multisampled {
+                    true => vk::AttachmentLoadOp::DONT_CARE,
+                    false => vk::AttachmentLoadOp::CLEAR
+                })
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(output_layout)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .build(),
+            Self::depth_attachment(depth_format, samples)
+        ];
+        if multisampled {
+            attachments.push(Self::transient_color_attachment(surface_format, samples));
+        }
+        attachments
+    }
+
+    /// Attachments of a renderpass rendering into an offscreen framebuffer: its color textures,
+    /// its depth texture if it has one and, with multisampling, a color image resolved into each
+    /// of its color textures
+    pub(crate) fn offscreen_attachments(
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+        samples: vk::SampleCountFlags
+    ) -> Vec<vk::AttachmentDescription> {
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+        let mut attachments: Vec<vk::AttachmentDescription> = color_formats.iter()
+            .map(|color_format| vk::AttachmentDescription::builder()
+                .format(*color_format)
+                .load_op(match multisampled {
+                    true => vk::AttachmentLoadOp::DONT_CARE,
+                    false => vk::AttachmentLoadOp::CLEAR
+                })
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .build())
+            .collect();
+        if let Some(depth_format) = depth_format {
+            attachments.push(Self::depth_attachment(depth_format, samples));
+        }
+        if multisampled {
+            for color_format in color_formats.iter() {
+                attachments.push(Self::transient_color_attachment(*color_format, samples));
+            }
+        }
+        attachments
+    }
+
+    fn depth_attachment(
+        format: vk::Format,
+        samples: vk::SampleCountFlags
+    ) -> vk::AttachmentDescription {
+        let stencil_load_op = match stencil_format_of(format) {
+            vk::Format::UNDEFINED => vk::AttachmentLoadOp::DONT_CARE,
+            _ => vk::AttachmentLoadOp::CLEAR
+        };
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(stencil_load_op)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .samples(samples)
+            .build()
+    }
+
+    /// Description of a color attachment that is cleared and later discarded rather than stored,
+    /// such as a multisampled one that is resolved
+    fn transient_color_attachment(
+        format: vk::Format,
+        samples: vk::SampleCountFlags
+    ) -> vk::AttachmentDescription {
+        vk::AttachmentDescription::builder()
+            .format(format)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .samples(samples)
+            .build()
+    }
+
     /// Create a framebuffer for rendering into a swapchain image
     unsafe fn create_swapchain_framebuffer(
         &self,
@@ -335,11 +1001,13 @@ impl RenderpassWrapper {
     ) -> Result<vk::Framebuffer, EngineError> {
         let extent = context.get_extent()?;
         let image_view = context.get_swapchain_image_view(image_index)?;
-        let depth_image = context.get_depth_image().unwrap();
-        let attachments_array = [
-            image_view,
-            depth_image.image_view
-        ];
+        let mut attachments_array = match &self.msaa_attachments {
+            Some(MsaaAttachments { colors, depth: Some(depth) }) =>
+                vec![image_view, depth.image_view, colors[0].image_view],
+            _ => vec![image_view, context.get_depth_image().unwrap().image_view]
+        };
+        attachments_array.extend(
+            self.intermediate_attachments.iter().map(|image| image.image_view));
         let framebuffer_info = vk::FramebufferCreateInfo::builder()
             .render_pass(renderpass)
             .attachments(&attachments_array)
@@ -359,21 +1027,15 @@ impl RenderpassWrapper {
         context: &VkContext,
         renderpass: vk::RenderPass,
         target: &OffscreenFramebufferWrapper,
-        color_image: vk::ImageView,
-        depth_image: Option<vk::ImageView>
+        attachment_image_views: &[vk::ImageView]
     ) -> Result<vk::Framebuffer, EngineError> {
 
         let width = target.width as u32;
         let height = target.height as u32;
 
-        let mut attachment_image_view = vec![color_image];
-        if let Some(image_view) = depth_image.as_ref() {
-            attachment_image_view.push(*image_view);
-        }
-
         let framebuffer_info = vk::FramebufferCreateInfo::builder()
             .render_pass(renderpass)
-            .attachments(attachment_image_view.as_slice())
+            .attachments(attachment_image_views)
             .width(width)
             .height(height)
             .layers(1);
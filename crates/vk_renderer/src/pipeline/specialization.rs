@@ -0,0 +1,74 @@
+use ash::vk;
+
+/// SpecializationConstant struct
+/// A value given to a shader's specialization constant of the given ID (its constant_id layout
+/// qualifier) when a pipeline is created, so that one shader module can be compiled into variants
+/// such as with lighting on or off. Every value is 32 bits; use the constructor matching the
+/// constant's type in the shader.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SpecializationConstant {
+    pub id: u32,
+    pub value: u32
+}
+
+impl SpecializationConstant {
+
+    /// A value for a bool constant
+    pub fn from_bool(id: u32, value: bool) -> Self {
+        Self { id, value: if value { vk::TRUE } else { vk::FALSE } }
+    }
+
+    /// A value for an int constant
+    pub fn from_int(id: u32, value: i32) -> Self {
+        Self { id, value: value as u32 }
+    }
+
+    /// A value for a uint constant
+    pub fn from_uint(id: u32, value: u32) -> Self {
+        Self { id, value }
+    }
+
+    /// A value for a float constant
+    pub fn from_float(id: u32, value: f32) -> Self {
+        Self { id, value: value.to_bits() }
+    }
+}
+
+/// SpecializationData struct
+/// Map entries and packed values for a list of specialization constants, kept together so that
+/// the vk::SpecializationInfo built from them stays valid while a pipeline is created
+pub(crate) struct SpecializationData {
+    pub(crate) map_entries: Vec<vk::SpecializationMapEntry>,
+    pub(crate) data: Vec<u8>
+}
+
+impl SpecializationData {
+
+    pub(crate) fn new(constants: &[SpecializationConstant]) -> Self {
+        let value_size = std::mem::size_of::<u32>();
+        let map_entries = constants.iter()
+            .enumerate()
+            .map(|(index, constant)| vk::SpecializationMapEntry {
+                constant_id: constant.id,
+                offset: (index * value_size) as u32,
+                size: value_size
+            })
+            .collect();
+        let data = constants.iter()
+            .flat_map(|constant| constant.value.to_ne_bytes())
+            .collect();
+        Self { map_entries, data }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.map_entries.is_empty()
+    }
+
+    /// Info referring to this data, for any shader stage of a pipeline
+    pub(crate) fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.map_entries)
+            .data(&self.data)
+            .build()
+    }
+}
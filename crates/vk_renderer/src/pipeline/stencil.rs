@@ -0,0 +1,119 @@
+use ash::vk;
+
+/// StencilFaceState struct
+/// How fragments of one facing are tested against the stencil buffer and what is written back.
+/// The test compares the reference value with the stored value, both masked by the compare mask,
+/// and the ops say what to store on failing the stencil test, passing it, or passing it but
+/// failing the depth test; only bits in the write mask are changed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct StencilFaceState {
+    pub fail_op: vk::StencilOp,
+    pub pass_op: vk::StencilOp,
+    pub depth_fail_op: vk::StencilOp,
+    pub compare_op: vk::CompareOp,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32
+}
+
+impl StencilFaceState {
+
+    /// Pass every fragment, storing the reference value where it is drawn
+    pub fn write_reference(reference: u32) -> Self {
+        Self {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::REPLACE,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: u32::MAX,
+            write_mask: u32::MAX,
+            reference
+        }
+    }
+
+    /// Pass only fragments whose stored value compares to the reference as given, leaving the
+    /// stencil buffer unchanged
+    pub fn test_reference(compare_op: vk::CompareOp, reference: u32) -> Self {
+        Self {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op,
+            compare_mask: u32::MAX,
+            write_mask: 0,
+            reference
+        }
+    }
+
+    /// The Vulkan op state for this face
+    pub fn op_state(&self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: self.fail_op,
+            pass_op: self.pass_op,
+            depth_fail_op: self.depth_fail_op,
+            compare_op: self.compare_op,
+            compare_mask: self.compare_mask,
+            write_mask: self.write_mask,
+            reference: self.reference
+        }
+    }
+}
+
+/// StencilState struct
+/// Whether a pipeline tests fragments against the stencil buffer, and how for front and back
+/// faces. Only has an effect where the depth image has a stencil aspect, such as with the
+/// Depth24Stencil8 depth format; see VkContext::set_depth_format. An outline, for example, draws
+/// the object writing a reference value, then draws it enlarged testing for not-equal, so that
+/// only the rim outside the object is drawn. Mirrors and portals likewise mark their surface and
+/// then draw what is seen through it testing for equal.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct StencilState {
+    pub test_enable: bool,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl StencilState {
+
+    /// Neither test nor write the stencil buffer
+    pub fn disabled() -> Self {
+        Self {
+            test_enable: false,
+            front: StencilFaceState::test_reference(vk::CompareOp::ALWAYS, 0),
+            back: StencilFaceState::test_reference(vk::CompareOp::ALWAYS, 0)
+        }
+    }
+
+    /// Store the reference value wherever either face is drawn
+    pub fn write_reference(reference: u32) -> Self {
+        Self {
+            test_enable: true,
+            front: StencilFaceState::write_reference(reference),
+            back: StencilFaceState::write_reference(reference)
+        }
+    }
+
+    /// Draw either face only where the stored value equals the reference
+    pub fn test_equal(reference: u32) -> Self {
+        Self {
+            test_enable: true,
+            front: StencilFaceState::test_reference(vk::CompareOp::EQUAL, reference),
+            back: StencilFaceState::test_reference(vk::CompareOp::EQUAL, reference)
+        }
+    }
+
+    /// Draw either face only where the stored value differs from the reference
+    pub fn test_not_equal(reference: u32) -> Self {
+        Self {
+            test_enable: true,
+            front: StencilFaceState::test_reference(vk::CompareOp::NOT_EQUAL, reference),
+            back: StencilFaceState::test_reference(vk::CompareOp::NOT_EQUAL, reference)
+        }
+    }
+}
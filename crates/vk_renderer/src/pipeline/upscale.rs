@@ -0,0 +1,414 @@
+use crate::{
+    VkContext, ImageWrapper, ImageAccess, context::record_blit_to_swapchain_image,
+    pipeline::post_process::source_image_view
+};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use ash::{Device, vk};
+use vk_shader_macros::include_glsl;
+use std::ffi::CStr;
+
+const UPSCALE_VERTEX_SHADER: &[u32] = include_glsl!("../../resources/shaders/upscale.vert");
+const UPSCALE_FRAGMENT_SHADER: &[u32] = include_glsl!("../../resources/shaders/upscale.frag");
+
+/// UpscaleTargetData struct
+/// Information needed to scale images into a swapchain image. The filter is either linear, which
+/// suits most scenes, or nearest, which keeps the hard edges of pixel art. The source, an image
+/// or an off-screen framebuffer whose first color texture is used, is only looked up where the
+/// surface's images can't be copied into, to sample from when scaling by shader.
+pub struct UpscaleTargetData {
+    pub swapchain_image_index: usize,
+    pub source: Handle,
+    pub filter: vk::Filter
+}
+
+/// UpscaleTarget struct
+/// A swapchain image that a pass rendered at the internal resolution is scaled into, filling the
+/// render area; see VkContext::set_render_scale. Where the surface's images can be copied into,
+/// the image is blitted and nothing is created on the device. Otherwise it is drawn with a
+/// full-screen triangle sampling the source, through a renderpass, pipeline and descriptor set
+/// of its own. Either way, as it depends on the swapchain, it should be rebuilt along with the
+/// bearer's other dynamic resources. For upsampling by a shader of the app's own instead, such
+/// as with sharpening, the internal image can be the source of a PostProcessChain of the render
+/// area's size, whose output is then blitted without scaling.
+pub struct UpscaleTarget {
+    swapchain_image: vk::Image,
+    swapchain_extent: vk::Extent2D,
+    output_layout: vk::ImageLayout,
+    render_area: vk::Rect2D,
+    filter: vk::Filter,
+    shader_upscale: Option<ShaderUpscale>
+}
+
+/// Objects for drawing the source into the swapchain image where it can't be blitted into
+struct ShaderUpscale {
+    renderpass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline
+}
+
+impl Resource<VkContext> for UpscaleTarget {
+    type CreationData = UpscaleTargetData;
+
+    fn create(
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &UpscaleTargetData
+    ) -> Result<Self, EngineError> {
+        if data.filter != vk::Filter::LINEAR && data.filter != vk::Filter::NEAREST {
+            return Err(EngineError::UserError(
+                format!("Upscaling needs a linear or nearest filter, got {:?}", data.filter)));
+        }
+        let mut target = Self {
+            swapchain_image: loader.get_swapchain_image(data.swapchain_image_index)?,
+            swapchain_extent: loader.get_extent()?,
+            output_layout: loader.get_output_layout(),
+            render_area: loader.get_render_area()?,
+            filter: data.filter,
+            shader_upscale: None
+        };
+        if !loader.supports_present_image() {
+            let source_image_view = source_image_view(ecs, data.source)?;
+            let mut shader_upscale = ShaderUpscale::new_empty();
+            unsafe {
+                let result = shader_upscale.create_objects(
+                    loader,
+                    data.swapchain_image_index,
+                    target.swapchain_extent,
+                    target.render_area,
+                    data.filter,
+                    source_image_view);
+                if let Err(e) = result {
+                    shader_upscale.release(loader);
+                    return Err(e);
+                }
+            }
+            target.shader_upscale = Some(shader_upscale);
+        }
+        Ok(target)
+    }
+
+    fn release(&self, loader: &VkContext) {
+        if let Some(shader_upscale) = &self.shader_upscale {
+            unsafe {
+                shader_upscale.release(loader);
+            }
+        }
+    }
+}
+
+impl UpscaleTarget {
+
+    /// The area of the swapchain image that is scaled into
+    pub fn get_render_area(&self) -> vk::Rect2D {
+        self.render_area
+    }
+
+    /// Whether the source is drawn into the swapchain image by shader, since the surface's images
+    /// can't be copied into
+    pub fn is_shader_upscale(&self) -> bool {
+        self.shader_upscale.is_some()
+    }
+
+    /// Record scaling the source, such as the color texture of an off-screen framebuffer that
+    /// the main pass has just been rendered into, into the swapchain image, leaving the image
+    /// ready to present. This takes the place of a renderpass into the swapchain image. The
+    /// source's tracked state must be up to date, such as through
+    /// OffscreenFramebufferWrapper::end_rendering, and when scaling by shader it must be the
+    /// source that this was created with.
+    pub unsafe fn record_upscale(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        source: &ImageWrapper
+    ) {
+        match &self.shader_upscale {
+            Some(shader_upscale) => {
+                source.transition(device, command_buffer, ImageAccess::ShaderRead);
+                shader_upscale.record(device, command_buffer, self.swapchain_extent);
+            },
+            None => record_blit_to_swapchain_image(
+                device,
+                command_buffer,
+                source,
+                self.swapchain_image,
+                self.swapchain_extent,
+                self.output_layout,
+                self.render_area,
+                self.filter)
+        }
+    }
+}
+
+impl ShaderUpscale {
+
+    fn new_empty() -> Self {
+        Self {
+            renderpass: vk::RenderPass::null(),
+            framebuffer: vk::Framebuffer::null(),
+            sampler: vk::Sampler::null(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null()
+        }
+    }
+
+    unsafe fn create_objects(
+        &mut self,
+        context: &VkContext,
+        swapchain_image_index: usize,
+        swapchain_extent: vk::Extent2D,
+        render_area: vk::Rect2D,
+        filter: vk::Filter,
+        source_image_view: vk::ImageView
+    ) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        // The swapchain image is cleared, so that letterboxing around the render area is black
+        let attachments = [vk::AttachmentDescription::builder()
+            .format(context.get_surface_format().format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(context.get_output_layout())
+            .build()];
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        }];
+        let subpasses = [vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .build()];
+        let subpass_dependencies = [vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_subpass(0)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .build()];
+        let renderpass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&subpass_dependencies);
+        self.renderpass = device.create_render_pass(&renderpass_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating upscaling renderpass: {:?}", e)))?;
+
+        let attachment_views = [context.get_swapchain_image_view(swapchain_image_index)?];
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.renderpass)
+            .attachments(&attachment_views)
+            .width(swapchain_extent.width)
+            .height(swapchain_extent.height)
+            .layers(1);
+        self.framebuffer = device.create_framebuffer(&framebuffer_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating upscaling framebuffer: {:?}", e)))?;
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .min_filter(filter)
+            .mag_filter(filter)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        self.sampler = device.create_sampler(&sampler_info, None)
+            .map_err(|e| EngineError::OpFailed(format!("Error creating sampler: {:?}", e)))?;
+
+        let layout_bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&layout_bindings);
+        self.descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor set layout: {:?}", e)))?;
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor pool: {:?}", e)))?;
+        let set_layouts = [self.descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        self.descriptor_set = device.allocate_descriptor_sets(&allocate_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating descriptor set: {:?}", e)))?[0];
+        let image_info = [vk::DescriptorImageInfo {
+            image_view: source_image_view,
+            sampler: self.sampler,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        }];
+        let writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build()];
+        device.update_descriptor_sets(&writes, &[]);
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts);
+        self.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating pipeline layout: {:?}", e)))?;
+
+        self.pipeline = self.create_pipeline(context, render_area)?;
+        Ok(())
+    }
+
+    /// Create the pipeline drawing into the render area, with shader modules that are only kept
+    /// while it is created
+    unsafe fn create_pipeline(
+        &self,
+        context: &VkContext,
+        render_area: vk::Rect2D
+    ) -> Result<vk::Pipeline, EngineError> {
+        let device = &context.device;
+        let vertex_shader_info = vk::ShaderModuleCreateInfo::builder()
+            .code(UPSCALE_VERTEX_SHADER);
+        let vertex_shader = device.create_shader_module(&vertex_shader_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating shader module: {:?}", e)))?;
+        let fragment_shader_info = vk::ShaderModuleCreateInfo::builder()
+            .code(UPSCALE_FRAGMENT_SHADER);
+        let fragment_shader = match device.create_shader_module(&fragment_shader_info, None) {
+            Ok(module) => module,
+            Err(e) => {
+                device.destroy_shader_module(vertex_shader, None);
+                return Err(EngineError::OpFailed(
+                    format!("Error creating shader module: {:?}", e)));
+            }
+        };
+
+        let entry_point = CStr::from_bytes_with_nul_unchecked(b"main\0");
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_shader)
+                .name(entry_point)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_shader)
+                .name(entry_point)
+                .build()
+        ];
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewports = [vk::Viewport {
+            x: render_area.offset.x as f32,
+            y: render_area.offset.y as f32,
+            width: render_area.extent.width as f32,
+            height: render_area.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0
+        }];
+        let scissors = [render_area];
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&viewports)
+            .scissors(&scissors);
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .line_width(1.0);
+        let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&blend_attachments);
+        let pipeline_infos = [vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_info)
+            .color_blend_state(&blend_info)
+            .layout(self.pipeline_layout)
+            .render_pass(self.renderpass)
+            .subpass(0)
+            .build()];
+        let result = device.create_graphics_pipelines(
+            context.get_pipeline_cache(),
+            &pipeline_infos,
+            None);
+        device.destroy_shader_module(vertex_shader, None);
+        device.destroy_shader_module(fragment_shader, None);
+        result
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, e)| EngineError::OpFailed(
+                format!("Error creating upscaling pipeline: {:?}", e)))
+    }
+
+    /// Record drawing the source over the render area, leaving the swapchain image ready to
+    /// present; the source must already be ready to sample
+    unsafe fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_extent: vk::Extent2D
+    ) {
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] }
+        }];
+        let renderpass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.renderpass)
+            .framebuffer(self.framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: swapchain_extent
+            })
+            .clear_values(&clear_values);
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &renderpass_begin_info,
+            vk::SubpassContents::INLINE);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[]);
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_render_pass(command_buffer);
+    }
+
+    unsafe fn release(&self, context: &VkContext) {
+        let device = &context.device;
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_render_pass(self.renderpass, None);
+    }
+}
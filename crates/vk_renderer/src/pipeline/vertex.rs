@@ -0,0 +1,110 @@
+
+use ash::vk;
+use model::StaticVertex;
+
+/// VertexAttributeFormat enum
+/// Data types that a single vertex attribute may have
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum VertexAttributeFormat {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    UnormByte4,
+    UintByte4
+}
+
+impl VertexAttributeFormat {
+
+    /// Format of this attribute as read by the vertex input stage
+    pub fn format(&self) -> vk::Format {
+        match self {
+            VertexAttributeFormat::Float => vk::Format::R32_SFLOAT,
+            VertexAttributeFormat::Vec2 => vk::Format::R32G32_SFLOAT,
+            VertexAttributeFormat::Vec3 => vk::Format::R32G32B32_SFLOAT,
+            VertexAttributeFormat::Vec4 => vk::Format::R32G32B32A32_SFLOAT,
+            VertexAttributeFormat::UnormByte4 => vk::Format::R8G8B8A8_UNORM,
+            VertexAttributeFormat::UintByte4 => vk::Format::R8G8B8A8_UINT
+        }
+    }
+
+    /// Size of this attribute within a vertex
+    pub fn size_bytes(&self) -> u32 {
+        match self {
+            VertexAttributeFormat::Float => 4,
+            VertexAttributeFormat::Vec2 => 8,
+            VertexAttributeFormat::Vec3 => 12,
+            VertexAttributeFormat::Vec4 => 16,
+            VertexAttributeFormat::UnormByte4 => 4,
+            VertexAttributeFormat::UintByte4 => 4
+        }
+    }
+}
+
+/// VertexAttribute struct
+/// A single attribute within a vertex, read by the vertex shader input at the given location
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: VertexAttributeFormat,
+    pub offset_bytes: u32
+}
+
+/// VertexLayout struct
+/// Description of the vertices in a vertex buffer, for building a pipeline's vertex input state
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct VertexLayout {
+    pub stride_bytes: u32,
+    pub attributes: Vec<VertexAttribute>
+}
+
+impl VertexLayout {
+
+    /// Create a layout with tightly-packed attributes, bound to consecutive locations from 0
+    pub fn packed(formats: &[VertexAttributeFormat]) -> Self {
+        let mut attributes = Vec::with_capacity(formats.len());
+        let mut offset_bytes = 0;
+        for (location, format) in formats.iter().enumerate() {
+            attributes.push(VertexAttribute {
+                location: location as u32,
+                format: *format,
+                offset_bytes
+            });
+            offset_bytes += format.size_bytes();
+        }
+        Self {
+            stride_bytes: offset_bytes,
+            attributes
+        }
+    }
+
+    /// Layout of the model crate's StaticVertex: position, normal and texture coordinate
+    pub fn static_vertex() -> Self {
+        let layout = Self::packed(&[
+            VertexAttributeFormat::Vec3,
+            VertexAttributeFormat::Vec3,
+            VertexAttributeFormat::Vec2
+        ]);
+        debug_assert_eq!(layout.stride_bytes as usize, std::mem::size_of::<StaticVertex>());
+        layout
+    }
+
+    pub(crate) fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes.iter()
+            .map(|attribute| vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: attribute.location,
+                offset: attribute.offset_bytes,
+                format: attribute.format.format()
+            })
+            .collect()
+    }
+
+    pub(crate) fn binding_description(&self) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: self.stride_bytes,
+            input_rate: vk::VertexInputRate::VERTEX
+        }
+    }
+}
@@ -1,39 +1,134 @@
 
 use crate::{
-    VkContext, BufferWrapper, RenderpassWrapper, ImageWrapper, BufferUsage,
-    VboCreationData
+    VkContext, BufferWrapper, ImageWrapper, BufferUsage, RenderQueue, BlendMode, DepthState,
+    StencilState, RasterizerState, SpecializationConstant, VertexLayout, VboCreationData,
+    DescriptorBinding, DynamicUniformLayout, SamplerWrapper, StreamedTexture, DrawMetadataBinding,
+    DrawMetadataBuffer, DRAW_METADATA_BINDING, PipelinePermutation, BindlessTextureSet,
+    BindlessMaterial, BINDLESS_TEXTURE_SET, write_descriptor_bindings, create_graphics_pipelines,
+    record_material_index,
+    pipeline::{permutation::create_graphics_pipeline, bindless::check_material_index}
 };
 use ecs::{EcsManager, Handle, resource::Resource};
 use error::EngineError;
 use ash::vk;
-use std::ffi::CString;
 
 /// PipelineCreationData struct
-/// Information needed to prepare a (potentially reusable) pipeline ahead of time
+/// Information needed to prepare a (potentially reusable) pipeline ahead of time. Setting a UBO
+/// object count makes the UBO hold an aligned slice of the given size for each of that many
+/// objects, to be drawn one after another; the descriptor set layout must then use a dynamic UBO.
+/// The render queue decides when the step is drawn, while the blend mode and depth state decide
+/// how its output combines with what is already drawn, the stencil state whether it tests or marks
+/// the stencil buffer, and the rasterizer state which faces are drawn and how; for the usual
+/// settings for the queue, see BlendMode::for_render_queue and DepthState::for_render_queue. The
+/// texture is sampled through the referenced sampler, which may be shared between pipelines.
+/// With dynamic rendering, the renderpass index refers to a DynamicRenderTarget instead of a
+/// RenderpassWrapper, and the subpass must be zero; otherwise it picks which of the renderpass's
+/// subpasses the pipeline draws in. Draw metadata needs a descriptor set layout created with
+/// draw_metadata set. The topology may be a point, line or triangle list, or a line or triangle
+/// strip; drawing points needs the vertex shader to write gl_PointSize. The specialization
+/// constants are given to both shaders, each ignoring IDs it doesn't declare. Without a vertex
+/// layout, the one stored with the vertex buffer is used; one given here must match it, if the
+/// buffer has one. With a bindless material, the pipeline layout must have been created with the
+/// same bindless texture array; see PipelineWrapper::set_bindless_material.
 pub struct PipelineCreationData {
     pub pipeline_layout_index: u32,
     pub renderpass_index: u32,
+    pub subpass: u32,
+    pub use_dynamic_rendering: bool,
     pub descriptor_set_layout_id: u32,
     pub vertex_shader_index: u32,
     pub fragment_shader_index: u32,
     pub vbo_index: u32,
     pub texture_index: u32,
-    pub vbo_stride_bytes: u32,
+    pub sampler_index: u32,
+    pub bindless_material: Option<BindlessMaterial>,
+    pub draw_metadata: Option<DrawMetadataBinding>,
+    pub vertex_layout: Option<VertexLayout>,
+    pub topology: vk::PrimitiveTopology,
     pub ubo_size_bytes: usize,
+    pub ubo_object_count: Option<usize>,
+    pub render_queue: RenderQueue,
+    pub blend_mode: BlendMode,
+    pub depth_state: DepthState,
+    pub stencil_state: StencilState,
+    pub rasterizer_state: RasterizerState,
+    pub specialization_constants: Vec<SpecializationConstant>,
     pub swapchain_image_index: usize
 }
 
+impl PipelineCreationData {
+
+    /// The permutation that pipelines created from this data compile, for declaring ahead of
+    /// time so that it can be pre-warmed. Without a vertex layout here, the vertex buffer must
+    /// already be loaded, to take the layout from.
+    pub fn permutation(
+        &self,
+        ecs: &EcsManager<VkContext>
+    ) -> Result<PipelinePermutation, EngineError> {
+        let vertex_layout = match &self.vertex_layout {
+            Some(vertex_layout) => vertex_layout.clone(),
+            None => {
+                let vbo_wrapper = ecs
+                    .get_item::<BufferWrapper>(Handle::for_resource(self.vbo_index))
+                    .ok_or_else(|| EngineError::MissingResource(
+                        format!("No vertex buffer at index {}", self.vbo_index)))?;
+                resolve_vertex_layout(self.vbo_index, vbo_wrapper, None)?
+            }
+        };
+        Ok(PipelinePermutation {
+            pipeline_layout_index: self.pipeline_layout_index,
+            renderpass_index: self.renderpass_index,
+            subpass: self.subpass,
+            use_dynamic_rendering: self.use_dynamic_rendering,
+            vertex_shader_index: self.vertex_shader_index,
+            fragment_shader_index: self.fragment_shader_index,
+            vertex_layout,
+            topology: self.topology,
+            blend_mode: self.blend_mode,
+            depth_state: self.depth_state,
+            stencil_state: self.stencil_state,
+            rasterizer_state: self.rasterizer_state,
+            specialization_constants: self.specialization_constants.clone(),
+            swapchain_image_index: self.swapchain_image_index
+        })
+    }
+}
+
+/// The vertex layout that a pipeline drawing the given vertex buffer reads with: the one
+/// requested, which must match any stored with the buffer, or otherwise the buffer's own
+fn resolve_vertex_layout(
+    vbo_index: u32,
+    vbo_wrapper: &BufferWrapper,
+    requested: Option<&VertexLayout>
+) -> Result<VertexLayout, EngineError> {
+    match (requested, vbo_wrapper.get_vertex_layout()) {
+        (Some(requested), Some(stored)) if requested != stored => Err(EngineError::UserError(format!(
+            "Vertex layout doesn't match the one stored with vertex buffer {}",
+            vbo_index))),
+        (Some(requested), _) => Ok(requested.clone()),
+        (None, Some(stored)) => Ok(stored.clone()),
+        (None, None) => Err(EngineError::UserError(format!(
+            "No vertex layout given, and none stored with vertex buffer {}",
+            vbo_index)))
+    }
+}
+
 /// PipelineWrapper struct
 /// Resources for a Vulkan pipeline to render a single step within a renderpass within the full
 /// rendering description for a particular scene.
 pub struct PipelineWrapper {
     vertex_buffer: vk::Buffer,
     vertex_count: usize,
+    index_buffer: Option<(vk::Buffer, usize)>,
     uniform_buffer: BufferWrapper,
+    dynamic_uniform_layout: Option<DynamicUniformLayout>,
+    first_draw_metadata_entry: u32,
     texture_image_view: vk::ImageView, // TODO - Vec
-    sampler: vk::Sampler, // TODO - Vec
-    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
     descriptor_set: vk::DescriptorSet,
+    bindless_material: Option<(vk::DescriptorSet, u32)>,
+    swapchain_image_index: usize,
+    render_queue: RenderQueue,
     pipeline: vk::Pipeline
 }
 
@@ -46,26 +141,11 @@ impl Resource<VkContext> for PipelineWrapper {
         data: &PipelineCreationData
     ) -> Result<Self, EngineError> {
 
-        let render_extent = loader.get_extent()?;
+        let render_area = loader.get_internal_render_area()?;
         let mut pipeline = PipelineWrapper::new();
         unsafe {
-            pipeline.create_resources(
-                loader,
-                ecs,
-                data.swapchain_image_index,
-                data.renderpass_index,
-                data.descriptor_set_layout_id,
-                data.pipeline_layout_index,
-                data.vbo_index,
-                data.fragment_shader_index,
-                data.vbo_index,
-                data.vbo_stride_bytes,
-                data.ubo_size_bytes,
-                false,
-                data.texture_index,
-                false,
-                render_extent
-            )?;
+            let permutation = pipeline.prepare_from_data(loader, ecs, data)?;
+            pipeline.pipeline = create_graphics_pipeline(loader, ecs, &permutation, render_area)?;
         }
         Ok(pipeline)
     }
@@ -74,8 +154,9 @@ impl Resource<VkContext> for PipelineWrapper {
         unsafe {
             loader.device.destroy_pipeline(self.pipeline, None);
             self.uniform_buffer.release(loader);
-            loader.device.destroy_descriptor_pool(self.descriptor_pool, None);
-            loader.device.destroy_sampler(self.sampler, None);
+            if self.descriptor_set != vk::DescriptorSet::null() {
+                loader.recycle_descriptor_set(self.descriptor_set_layout, self.descriptor_set);
+            }
         }
     }
 }
@@ -87,11 +168,16 @@ impl PipelineWrapper {
         PipelineWrapper {
             vertex_buffer: vk::Buffer::null(),
             vertex_count: 0,
+            index_buffer: None,
             uniform_buffer: BufferWrapper::empty(),
+            dynamic_uniform_layout: None,
+            first_draw_metadata_entry: 0,
             texture_image_view: vk::ImageView::null(),
-            sampler: vk::Sampler::null(),
-            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
             descriptor_set: vk::DescriptorSet::null(),
+            bindless_material: None,
+            swapchain_image_index: 0,
+            render_queue: RenderQueue::Opaque,
             pipeline: vk::Pipeline::null()
         }
     }
@@ -104,6 +190,82 @@ impl PipelineWrapper {
         self.descriptor_set
     }
 
+    pub fn get_render_queue(&self) -> RenderQueue {
+        self.render_queue
+    }
+
+    /// Getter for the bindless texture array's descriptor set and the material index that this
+    /// step draws with, if it uses one
+    pub fn get_bindless_material(&self) -> Option<(vk::DescriptorSet, u32)> {
+        self.bindless_material
+    }
+
+    /// Draw this step with a texture from a bindless texture array: whenever the step is bound,
+    /// the array is bound as BINDLESS_TEXTURE_SET and the material index pushed as a constant.
+    /// The pipeline layout drawn with must have been created with the same array.
+    pub fn set_bindless_material(
+        &mut self,
+        ecs: &EcsManager<VkContext>,
+        material: BindlessMaterial
+    ) -> Result<(), EngineError> {
+        let texture_set = ecs
+            .get_item::<BindlessTextureSet>(Handle::for_resource(material.texture_set_index))
+            .ok_or_else(|| EngineError::MissingResource(format!(
+                "No bindless texture array at index {}",
+                material.texture_set_index)))?;
+        check_material_index(material.material_index, texture_set.get_capacity())?;
+        self.bindless_material =
+            Some((texture_set.get_descriptor_set(), material.material_index));
+        Ok(())
+    }
+
+    /// Number of objects drawn by this step; more than one only with a dynamic UBO
+    pub fn get_object_count(&self) -> usize {
+        self.dynamic_uniform_layout
+            .map(|layout| layout.object_count)
+            .unwrap_or(1)
+    }
+
+    /// Create pipelines for several steps, creating every pipeline in a single batch; see
+    /// create_graphics_pipelines. Where many pipelines are recreated together, such as one for
+    /// each swapchain image after resizing, this is much quicker than creating each in turn. If
+    /// any step fails, the resources of every other are released.
+    pub unsafe fn create_batch(
+        context: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &[PipelineCreationData]
+    ) -> Result<Vec<PipelineWrapper>, EngineError> {
+        let render_area = context.get_internal_render_area()?;
+        let mut wrappers = Vec::with_capacity(data.len());
+        let mut permutations = Vec::with_capacity(data.len());
+        for item in data.iter() {
+            let mut wrapper = PipelineWrapper::new();
+            match wrapper.prepare_from_data(context, ecs, item) {
+                Ok(permutation) => {
+                    wrappers.push(wrapper);
+                    permutations.push(permutation);
+                },
+                Err(e) => {
+                    wrappers.iter().for_each(|wrapper| wrapper.release(context));
+                    return Err(e);
+                }
+            }
+        }
+        let permutation_refs: Vec<&PipelinePermutation> = permutations.iter().collect();
+        match create_graphics_pipelines(context, ecs, &permutation_refs, render_area) {
+            Ok(pipelines) => {
+                for (wrapper, pipeline) in wrappers.iter_mut().zip(pipelines.into_iter()) {
+                    wrapper.pipeline = pipeline;
+                }
+                Ok(wrappers)
+            },
+            Err(e) => {
+                wrappers.iter().for_each(|wrapper| wrapper.release(context));
+                Err(e)
+            }
+        }
+    }
+
     /// Create resources needed to render a single step within a pass
     pub unsafe fn create_resources(
         &mut self,
@@ -111,56 +273,132 @@ impl PipelineWrapper {
         ecs: &EcsManager<VkContext>,
         swapchain_image_index: usize,
         renderpass_id: u32,
+        subpass: u32,
+        use_dynamic_rendering: bool,
         descriptor_set_layout_id: u32,
         pipeline_layout_index: u32,
         vertex_shader_index: u32,
         fragment_shader_index: u32,
         vbo_index: u32,
-        vbo_stride_bytes: u32,
+        vertex_layout: Option<&VertexLayout>,
+        topology: vk::PrimitiveTopology,
         ubo_size_bytes: usize,
+        ubo_object_count: Option<usize>,
         draw_indexed: bool,
         texture_index: u32,
-        depth_test: bool,
-        render_extent: vk::Extent2D
+        sampler_index: u32,
+        draw_metadata: Option<DrawMetadataBinding>,
+        render_queue: RenderQueue,
+        blend_mode: BlendMode,
+        depth_state: DepthState,
+        stencil_state: StencilState,
+        rasterizer_state: RasterizerState,
+        specialization_constants: &[SpecializationConstant],
+        render_area: vk::Rect2D
     ) -> Result<(), EngineError> {
+        let permutation = self.prepare_resources(
+            context,
+            ecs,
+            swapchain_image_index,
+            renderpass_id,
+            subpass,
+            use_dynamic_rendering,
+            descriptor_set_layout_id,
+            pipeline_layout_index,
+            vertex_shader_index,
+            fragment_shader_index,
+            vbo_index,
+            vertex_layout,
+            topology,
+            ubo_size_bytes,
+            ubo_object_count,
+            draw_indexed,
+            texture_index,
+            sampler_index,
+            draw_metadata,
+            render_queue,
+            blend_mode,
+            depth_state,
+            stencil_state,
+            rasterizer_state,
+            specialization_constants)?;
+        self.pipeline = create_graphics_pipeline(context, ecs, &permutation, render_area)?;
+        Ok(())
+    }
+
+    /// Create the resources for a step described by creation data, other than the pipeline
+    unsafe fn prepare_from_data(
+        &mut self,
+        loader: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        data: &PipelineCreationData
+    ) -> Result<PipelinePermutation, EngineError> {
+        if let Some(material) = data.bindless_material {
+            self.set_bindless_material(ecs, material)?;
+        }
+        self.prepare_resources(
+            loader,
+            ecs,
+            data.swapchain_image_index,
+            data.renderpass_index,
+            data.subpass,
+            data.use_dynamic_rendering,
+            data.descriptor_set_layout_id,
+            data.pipeline_layout_index,
+            data.vertex_shader_index,
+            data.fragment_shader_index,
+            data.vbo_index,
+            data.vertex_layout.as_ref(),
+            data.topology,
+            data.ubo_size_bytes,
+            data.ubo_object_count,
+            false,
+            data.texture_index,
+            data.sampler_index,
+            data.draw_metadata,
+            data.render_queue,
+            data.blend_mode,
+            data.depth_state,
+            data.stencil_state,
+            data.rasterizer_state,
+            &data.specialization_constants)
+    }
+
+    /// Create the resources needed to render a step other than the pipeline itself, returning
+    /// the permutation to create the pipeline from
+    unsafe fn prepare_resources(
+        &mut self,
+        context: &VkContext,
+        ecs: &EcsManager<VkContext>,
+        swapchain_image_index: usize,
+        renderpass_id: u32,
+        subpass: u32,
+        use_dynamic_rendering: bool,
+        descriptor_set_layout_id: u32,
+        pipeline_layout_index: u32,
+        vertex_shader_index: u32,
+        fragment_shader_index: u32,
+        vbo_index: u32,
+        vertex_layout: Option<&VertexLayout>,
+        topology: vk::PrimitiveTopology,
+        ubo_size_bytes: usize,
+        ubo_object_count: Option<usize>,
+        draw_indexed: bool,
+        texture_index: u32,
+        sampler_index: u32,
+        draw_metadata: Option<DrawMetadataBinding>,
+        render_queue: RenderQueue,
+        blend_mode: BlendMode,
+        depth_state: DepthState,
+        stencil_state: StencilState,
+        rasterizer_state: RasterizerState,
+        specialization_constants: &[SpecializationConstant]
+    ) -> Result<PipelinePermutation, EngineError> {
 
-        // Query renderpass and pipeline layout
-        let renderpass_wrapper  = ecs
-            .get_item::<RenderpassWrapper>(
-                Handle::for_resource_variation(renderpass_id, swapchain_image_index as u32)
-                    .unwrap())
-            .unwrap();
         let descriptor_set_layout  = ecs
             .get_item::<vk::DescriptorSetLayout>(
                 Handle::for_resource(descriptor_set_layout_id))
             .unwrap();
-        let pipeline_layout  = ecs
-            .get_item::<vk::PipelineLayout>(
-                Handle::for_resource(pipeline_layout_index))
-            .unwrap();
-
-        // Query shader modules
-        let vertex_shader_module  = ecs
-            .get_item::<vk::ShaderModule>(
-                Handle::for_resource(vertex_shader_index as u32))
-            .unwrap();
-        let fragment_shader_module  = ecs
-            .get_item::<vk::ShaderModule>(
-                Handle::for_resource(fragment_shader_index as u32))
-            .unwrap();
-
-        // Make shader modules
-        let main_function_name = CString::new("main").unwrap();
-        let vertex_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .module(*vertex_shader_module)
-            .name(&main_function_name);
-        let fragment_shader_stage = vk::PipelineShaderStageCreateInfo::builder()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .module(*fragment_shader_module)
-            .name(&main_function_name);
-        let shader_stages =
-            vec![vertex_shader_stage.build(), fragment_shader_stage.build()];
 
         // Vertex buffer
         let vbo_wrapper  = ecs
@@ -168,48 +406,45 @@ impl PipelineWrapper {
                 Handle::for_resource(vbo_index as u32))
             .unwrap();
         let vbo_handle = vbo_wrapper.buffer;
+        let index_buffer = vbo_wrapper.index_buffer();
+        if draw_indexed && index_buffer.is_none() {
+            return Err(EngineError::UserError(format!(
+                "Indexed drawing requested but vertex buffer {} has no index buffer",
+                vbo_index)));
+        }
 
         // Vertex input configuration
-        let vertex_attrib_descriptions = [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                offset: 0,
-                format: vk::Format::R32G32B32_SFLOAT
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                offset: 12,
-                format: vk::Format::R32G32B32_SFLOAT
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                offset: 24,
-                format: vk::Format::R32G32_SFLOAT
-            }
-        ];
-        let vertex_binding_descriptions = [
-            vk::VertexInputBindingDescription {
-                binding: 0,
-                stride: vbo_stride_bytes,
-                input_rate: vk::VertexInputRate::VERTEX
-            }
-        ];
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_attribute_descriptions(&vertex_attrib_descriptions)
-            .vertex_binding_descriptions(&vertex_binding_descriptions);
-        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-
-        // Create uniform buffer
+        let vertex_layout = resolve_vertex_layout(vbo_index, vbo_wrapper, vertex_layout)?;
+        if vbo_wrapper.element_count * vertex_layout.stride_bytes as usize !=
+            vbo_wrapper.size_bytes
+        {
+            return Err(EngineError::UserError(format!(
+                "Vertex stride {} doesn't match buffer {} of {} bytes for {} vertices",
+                vertex_layout.stride_bytes,
+                vbo_index,
+                vbo_wrapper.size_bytes,
+                vbo_wrapper.element_count)));
+        }
+
+        // Create uniform buffer, with a slice for each object if using a dynamic UBO
+        let dynamic_uniform_layout = ubo_object_count.map(|object_count| {
+            DynamicUniformLayout::new(
+                ubo_size_bytes,
+                object_count.max(1),
+                context.get_min_uniform_buffer_offset_alignment() as usize)
+        });
+        let (ubo_descriptor_type, uniform_buffer_size_bytes) = match &dynamic_uniform_layout {
+            Some(layout) =>
+                (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, layout.total_size_bytes()),
+            None => (vk::DescriptorType::UNIFORM_BUFFER, ubo_size_bytes)
+        };
         let uniform_buffer = {
-            let uniform_buffer_data: Vec<u8> = vec![0; ubo_size_bytes];
+            let uniform_buffer_data: Vec<u8> = vec![0; uniform_buffer_size_bytes];
             let creation_data = VboCreationData {
                 vertex_data: Some(uniform_buffer_data.as_ptr()),
                 vertex_size_bytes: std::mem::size_of::<u8>(),
-                vertex_count: ubo_size_bytes,
+                vertex_count: uniform_buffer_size_bytes,
+                vertex_layout: None,
                 draw_indexed: false,
                 index_data: None,
                 usage: BufferUsage::UniformBuffer
@@ -223,50 +458,38 @@ impl PipelineWrapper {
 
         // Texture image
         //TODO - Vec from texture_indices.iter().map(|index| ...).collect()
-        let texture_image_view  = ecs
-            .get_item::<ImageWrapper>(
-                Handle::for_resource(texture_index as u32))
-            .unwrap()
-            .image_view;
-
-        // Samplers
-        let sampler_info = vk::SamplerCreateInfo::builder()
-            .min_filter(vk::Filter::LINEAR)
-            .mag_filter(vk::Filter::LINEAR);
-        let sampler: vk::Sampler = //TODO - Vec from texture_image_views.iter().map(|_| ...).collect()
-            context.device
-                .create_sampler(&sampler_info, None)
-                .map_err(|e| EngineError::OpFailed(format!("Error creating sampler: {:?}", e)))?;
-
-        // All the stuff around descriptors
-        let pool_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: 1
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: 1 //TODO - texture_image_views.len() as u32
-            }
-        ];
-        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(1)
-            .pool_sizes(&pool_sizes);
-        let descriptor_pool = context.device
-            .create_descriptor_pool(&descriptor_pool_info, None)
-            .map_err(|e|
-                EngineError::OpFailed(format!("Error creating descriptor pool: {:?}", e))
-            )?;
-        let descriptor_layouts = vec![*descriptor_set_layout];
-        let descriptor_set_alloc_info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&descriptor_layouts);
-        let descriptor_set = context.device
-            .allocate_descriptor_sets(&descriptor_set_alloc_info)
-            .map_err(|e|
-                EngineError::OpFailed(format!("Failed allocating descriptor sets: {:?}", e))
-            )?
-            [0];
+        let texture_handle = Handle::for_resource(texture_index);
+        let texture_image_view = match ecs.get_item::<ImageWrapper>(texture_handle) {
+            Some(image) => image.image_view,
+            None => ecs
+                .get_item::<StreamedTexture>(texture_handle)
+                .ok_or_else(|| EngineError::MissingResource(
+                    format!("No texture at index {}", texture_index)))?
+                .get_image()
+                .image_view
+        };
+
+        // Sampler
+        //TODO - Vec from sampler_indices.iter().map(|index| ...).collect()
+        let sampler = ecs
+            .get_item::<SamplerWrapper>(
+                Handle::for_resource(sampler_index))
+            .ok_or_else(|| EngineError::MissingResource(
+                format!("No sampler at index {}", sampler_index)))?
+            .get_sampler();
+
+        // Draw metadata buffer
+        let draw_metadata_buffer = match draw_metadata {
+            Some(binding) => Some(ecs
+                .get_item::<DrawMetadataBuffer>(
+                    Handle::for_resource(binding.buffer_index))
+                .ok_or_else(|| EngineError::MissingResource(
+                    format!("No draw metadata buffer at index {}", binding.buffer_index)))?),
+            None => None
+        };
+
+        // Descriptor set, from the context's shared pools
+        let descriptor_set = context.allocate_descriptor_set(*descriptor_set_layout)?;
 
         // Descriptor bindings
         let buffer_infos = [vk::DescriptorBufferInfo {
@@ -284,7 +507,7 @@ impl PipelineWrapper {
             let mut writes = vec![vk::WriteDescriptorSet::builder()
                 .dst_set(descriptor_set)
                 .dst_binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(ubo_descriptor_type)
                 .buffer_info(&buffer_infos)
                 .build()];
             // TODO - foreach index in texture_image_views, push with binding 1 + index
@@ -299,92 +522,69 @@ impl PipelineWrapper {
         context.device.update_descriptor_sets(
             &descriptor_set_writes.as_slice(),
             &[]);
+        if let Some(buffer) = draw_metadata_buffer {
+            write_descriptor_bindings(
+                context,
+                descriptor_set,
+                &[(DRAW_METADATA_BINDING, buffer.descriptor_binding())]);
+        }
 
-        // Viewport
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: render_extent.width as f32,
-            height: render_extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0
-        }];
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: render_extent
-        }];
-        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(&viewports)
-            .scissors(&scissors);
-
-        // Random pipeline configurations
-        let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
-            .line_width(1.0)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .polygon_mode(vk::PolygonMode::FILL);
-        let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL);
-        let colour_blend_attachments = [
-            vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(true)
-                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .alpha_blend_op(vk::BlendOp::ADD)
-                .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .build()
-        ];
-        let colour_blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
-            .attachments(&colour_blend_attachments);
-
-        // Make pipeline
-        let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly_info)
-            .viewport_state(&viewport_info)
-            .rasterization_state(&rasterizer_info)
-            .multisample_state(&multisampler_info)
-            .depth_stencil_state(&depth_stencil_info)
-            .color_blend_state(&colour_blend_info)
-            .layout(*pipeline_layout)
-            .render_pass(renderpass_wrapper.renderpass)
-            .subpass(0);
-        let graphics_pipeline = context.device
-            .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[pipeline_create_info.build()],
-                None)
-            .map_err(|e|
-                EngineError::OpFailed(format!("{:?}", e))
-            )?;
+        // Describe the pipeline itself, to be created through the cache
+        let permutation = PipelinePermutation {
+            pipeline_layout_index,
+            renderpass_index: renderpass_id,
+            subpass,
+            use_dynamic_rendering,
+            vertex_shader_index,
+            fragment_shader_index,
+            vertex_layout,
+            topology,
+            blend_mode,
+            depth_state,
+            stencil_state,
+            rasterizer_state,
+            specialization_constants: specialization_constants.to_vec(),
+            swapchain_image_index
+        };
 
         self.vertex_buffer = vbo_handle;
         self.vertex_count = vbo_wrapper.element_count;
+        self.index_buffer = index_buffer;
         self.uniform_buffer = uniform_buffer;
+        self.dynamic_uniform_layout = dynamic_uniform_layout;
+        self.first_draw_metadata_entry = draw_metadata
+            .map(|binding| binding.first_entry)
+            .unwrap_or(0);
         self.texture_image_view = texture_image_view; // TODO - Vec
-        self.sampler = sampler; // TODO - Vec
-        self.descriptor_pool = descriptor_pool;
+        self.descriptor_set_layout = *descriptor_set_layout;
         self.descriptor_set = descriptor_set;
-        self.pipeline = graphics_pipeline[0];
+        self.swapchain_image_index = swapchain_image_index;
+        self.render_queue = render_queue;
 
-        Ok(())
+        Ok(permutation)
     }
 
     /// Record the commands to render this step; assume that beginning/ending the renderpass is
-    /// done separately
+    /// done separately. With a dynamic UBO, each object is drawn in turn using its own slice.
     pub unsafe fn record_commands(
         &self,
         command_buffer: vk::CommandBuffer,
         context: &VkContext,
         pipeline_layout: vk::PipelineLayout
+    ) {
+        self.record_bind(command_buffer, context, pipeline_layout);
+        for object_index in 0..self.get_object_count() {
+            self.record_object_draw(command_buffer, context, pipeline_layout, object_index);
+        }
+    }
+
+    /// Record binding this step's pipeline, vertex buffer and index buffer, and any bindless
+    /// texture array along with its material index, ready for drawing individual objects
+    pub unsafe fn record_bind(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        context: &VkContext,
+        pipeline_layout: vk::PipelineLayout
     ) {
         context.device.cmd_bind_pipeline(
             command_buffer,
@@ -395,33 +595,200 @@ impl PipelineWrapper {
             0,
             &[self.vertex_buffer],
             &[0]);
+        if let Some((index_buffer, _)) = self.index_buffer {
+            context.device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer,
+                0,
+                vk::IndexType::UINT16);
+        }
+        if let Some((texture_set, material_index)) = self.bindless_material {
+            context.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                BINDLESS_TEXTURE_SET,
+                &[texture_set],
+                &[]);
+            record_material_index(&context.device, command_buffer, pipeline_layout, material_index);
+        }
+    }
+
+    /// Record drawing one of this step's objects, using its slice of the UBO if dynamic; this
+    /// step must have been bound with record_bind
+    pub unsafe fn record_object_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        context: &VkContext,
+        pipeline_layout: vk::PipelineLayout,
+        object_index: usize
+    ) {
+        let dynamic_offsets = match &self.dynamic_uniform_layout {
+            Some(layout) => vec![layout.offset(object_index)],
+            None => vec![]
+        };
         context.device.cmd_bind_descriptor_sets(
             command_buffer,
             vk::PipelineBindPoint::GRAPHICS,
             pipeline_layout,
             0,
             &[self.descriptor_set],
-            &[]);
-        context.device.cmd_draw(
+            &dynamic_offsets);
+        self.record_draw(
             command_buffer,
-            self.vertex_count as u32,
-            1,
+            context,
+            self.first_draw_metadata_entry + object_index as u32);
+    }
+
+    /// Record drawing this step using commands read from an indirect draw buffer, so that the
+    /// draw list can change without re-recording; the buffer must have been created with
+    /// BufferWrapper::new_indexed_indirect_draws if this step has an index buffer, or with
+    /// BufferWrapper::new_indirect_draws otherwise. Draws with the first object's slice of the
+    /// UBO if dynamic. Where multi-draw indirect is unsupported, one command is issued per draw.
+    pub unsafe fn record_indirect_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        context: &VkContext,
+        pipeline_layout: vk::PipelineLayout,
+        indirect_buffer: &BufferWrapper,
+        first_draw: usize,
+        draw_count: usize
+    ) -> Result<(), EngineError> {
+        if first_draw + draw_count > indirect_buffer.element_count {
+            return Err(EngineError::UserError(format!(
+                "Indirect draws {}..{} out of range for buffer of {} commands",
+                first_draw,
+                first_draw + draw_count,
+                indirect_buffer.element_count)));
+        }
+        let stride_bytes = match self.index_buffer {
+            Some(_) => std::mem::size_of::<vk::DrawIndexedIndirectCommand>(),
+            None => std::mem::size_of::<vk::DrawIndirectCommand>()
+        };
+        if indirect_buffer.size_bytes != indirect_buffer.element_count * stride_bytes {
+            return Err(EngineError::UserError(
+                "Indirect draw buffer does not match whether this step is indexed".to_owned()));
+        }
+
+        self.record_bind(command_buffer, context, pipeline_layout);
+        let dynamic_offsets = match &self.dynamic_uniform_layout {
+            Some(layout) => vec![layout.offset(0)],
+            None => vec![]
+        };
+        context.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
             0,
-            0);
+            &[self.descriptor_set],
+            &dynamic_offsets);
+
+        let (calls, draws_per_call) = match context.supports_multi_draw_indirect() {
+            true => (1, draw_count),
+            false => (draw_count, 1)
+        };
+        for call in 0..calls {
+            let offset_bytes = ((first_draw + call) * stride_bytes) as vk::DeviceSize;
+            match self.index_buffer {
+                Some(_) => context.device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    indirect_buffer.buffer,
+                    offset_bytes,
+                    draws_per_call as u32,
+                    stride_bytes as u32),
+                None => context.device.cmd_draw_indirect(
+                    command_buffer,
+                    indirect_buffer.buffer,
+                    offset_bytes,
+                    draws_per_call as u32,
+                    stride_bytes as u32)
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a draw of this step's vertices, indexed if there is an index buffer bound
+    unsafe fn record_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        context: &VkContext,
+        first_instance: u32
+    ) {
+        match self.index_buffer {
+            Some((_, index_count)) => context.device.cmd_draw_indexed(
+                command_buffer,
+                index_count as u32,
+                1,
+                0,
+                0,
+                first_instance),
+            None => context.device.cmd_draw(
+                command_buffer,
+                self.vertex_count as u32,
+                1,
+                0,
+                first_instance)
+        }
+    }
+
+    /// Rewrite some bindings of this step's descriptor set, such as to point the uniform buffer
+    /// binding at a buffer that replaced the original. First waits for any submitted frame that
+    /// uses this step's swapchain image to finish, so this is safe to call at any time, but is
+    /// cheapest when preparing that image's frame.
+    pub unsafe fn rewrite_descriptor_bindings(
+        &self,
+        context: &VkContext,
+        bindings: &[(u32, DescriptorBinding)]
+    ) -> Result<(), EngineError> {
+        context.wait_for_swapchain_image_idle(self.swapchain_image_index)?;
+        write_descriptor_bindings(context, self.descriptor_set, bindings);
+        Ok(())
+    }
+
+    /// Getter for the arrangement of per-object slices in the UBO, if using a dynamic UBO
+    pub fn get_dynamic_uniform_layout(&self) -> Option<DynamicUniformLayout> {
+        self.dynamic_uniform_layout
+    }
+
+    /// Update one object's slice of a dynamic UBO from the supplied pointer and data size, staged
+    /// as for update_uniform_buffer
+    pub unsafe fn update_object_uniform_buffer(
+        &self,
+        context: &VkContext,
+        object_index: usize,
+        data_ptr: *const u8,
+        size_bytes: usize
+    ) -> Result<(), EngineError> {
+        let Some(layout) = &self.dynamic_uniform_layout else {
+            return Err(EngineError::UserError(
+                "Updating per-object uniforms of a pipeline without a dynamic UBO".to_owned()));
+        };
+        if object_index >= layout.object_count || size_bytes > layout.object_size_bytes {
+            return Err(EngineError::UserError(format!(
+                "Uniform update out of range: object {} of {}, {} bytes of {}",
+                object_index,
+                layout.object_count,
+                size_bytes,
+                layout.object_size_bytes)));
+        }
+        context.stage_buffer_update(
+            &self.uniform_buffer,
+            layout.offset(object_index) as usize,
+            std::slice::from_raw_parts(data_ptr, size_bytes))
     }
 
-    /// Update the uniform buffer for this step from the supplied pointer and data size
+    /// Update the uniform buffer for this step from the supplied pointer and data size. While
+    /// preparing a frame, the data is staged through the frame's transient buffer; see
+    /// VkContext::stage_buffer_update.
     pub unsafe fn update_uniform_buffer(
         &self,
         context: &VkContext,
         data_ptr: *const u8,
         size_bytes: usize
     ) -> Result<(), EngineError> {
-        let (allocator, _) = context.get_mem_allocator();
-        self.uniform_buffer.update::<u8>(
-            allocator,
+        context.stage_buffer_update(
+            &self.uniform_buffer,
             0,
-            data_ptr,
-            size_bytes)
+            std::slice::from_raw_parts(data_ptr, size_bytes))
     }
 }
@@ -1,6 +1,6 @@
 
-use crate::VkContext;
-use crate::mem::{MemoryAllocator, MemoryAllocation, ManagesBufferMemory};
+use crate::{VkContext, Queue, VertexLayout};
+use crate::mem::{MemoryAllocator, MemoryAllocation, ManagesBufferMemory, MemoryLocation};
 use ecs::{EcsManager, resource::Resource};
 use error::EngineError;
 use ash::vk;
@@ -10,7 +10,11 @@ use ash::vk;
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum BufferUsage {
     InitialiseOnceVertexBuffer,
-    UniformBuffer
+    InitialiseOnceIndexBuffer,
+    UniformBuffer,
+    IndirectDrawBuffer,
+    StorageBuffer,
+    Readback
 }
 
 /// BufferCreationParams struct
@@ -21,20 +25,28 @@ struct BufferCreationParams {
 }
 
 /// BufferWrapper struct
-/// Wraps up a Vulkan Buffer and its memory allocation that backs it
+/// Wraps up a Vulkan Buffer and its memory allocation that backs it, along with an index buffer
+/// for vertex buffers that are drawn indexed
 pub struct BufferWrapper {
     pub buffer: vk::Buffer,
     pub size_bytes: usize,
     pub element_count: usize,
-    allocation: MemoryAllocation
+    usage_flags: vk::BufferUsageFlags,
+    host_accessible: bool,
+    allocation: MemoryAllocation,
+    index_buffer: Option<Box<BufferWrapper>>,
+    vertex_layout: Option<VertexLayout>
 }
 
 /// VboCreationData struct
-/// Specification for how a vertex buffer is to be created
+/// Specification for how a vertex buffer is to be created. The vertex layout describes what each
+/// vertex holds, and is kept with the buffer so that pipelines drawing it can take their vertex
+/// input from it; its stride must match the vertex size. Buffers not read as vertices have none.
 pub struct VboCreationData {
     pub vertex_data: Option<*const u8>,
     pub vertex_size_bytes: usize,
     pub vertex_count: usize,
+    pub vertex_layout: Option<VertexLayout>,
     pub draw_indexed: bool,
     pub index_data: Option<Vec<u16>>,
     pub usage: BufferUsage
@@ -48,7 +60,29 @@ impl Resource<VkContext> for BufferWrapper {
         _ecs: &EcsManager<VkContext>,
         data: &VboCreationData
     ) -> Result<Self, EngineError> {
-        let buffer = unsafe {
+        let index_data = match (data.draw_indexed, &data.index_data) {
+            (false, _) => None,
+            (true, Some(index_data)) if !index_data.is_empty() => Some(index_data),
+            (true, _) => return Err(EngineError::UserError(
+                "Indexed drawing requested without any index data".to_owned()))
+        };
+        if let Some(vertex_layout) = &data.vertex_layout {
+            if vertex_layout.stride_bytes as usize != data.vertex_size_bytes {
+                return Err(EngineError::UserError(format!(
+                    "Vertex layout stride {} doesn't match vertex size {}",
+                    vertex_layout.stride_bytes,
+                    data.vertex_size_bytes)));
+            }
+        }
+        if let Some(&max_index) = index_data.and_then(|indices| indices.iter().max()) {
+            if max_index as usize >= data.vertex_count {
+                return Err(EngineError::UserError(format!(
+                    "Index {} out of range for {} vertices",
+                    max_index,
+                    data.vertex_count)));
+            }
+        }
+        let mut buffer = unsafe {
             BufferWrapper::new(
                 loader,
                 data.usage,
@@ -56,10 +90,31 @@ impl Resource<VkContext> for BufferWrapper {
                 data.vertex_count,
                 data.vertex_data)?
         };
+        buffer.vertex_layout = data.vertex_layout.clone();
+        if let Some(index_data) = index_data {
+            let index_buffer = unsafe {
+                BufferWrapper::new(
+                    loader,
+                    BufferUsage::InitialiseOnceIndexBuffer,
+                    std::mem::size_of_val(index_data.as_slice()),
+                    index_data.len(),
+                    Some(index_data.as_ptr() as *const u8))
+            };
+            match index_buffer {
+                Ok(index_buffer) => buffer.index_buffer = Some(Box::new(index_buffer)),
+                Err(e) => {
+                    buffer.release(loader);
+                    return Err(e);
+                }
+            }
+        }
         Ok(buffer)
     }
 
     fn release(&self, loader: &VkContext) {
+        if let Some(index_buffer) = &self.index_buffer {
+            index_buffer.release(loader);
+        }
         let (allocator, _) = loader.get_mem_allocator();
         unsafe {
             allocator.destroy_buffer(self.buffer, &self.allocation)
@@ -89,11 +144,38 @@ impl BufferWrapper {
 
         let creation_params = match buffer_usage {
             BufferUsage::InitialiseOnceVertexBuffer => BufferCreationParams {
-                usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER | transfer_usage,
+                usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER |
+                    vk::BufferUsageFlags::TRANSFER_SRC |
+                    transfer_usage,
+                host_accessible: false
+            },
+            BufferUsage::InitialiseOnceIndexBuffer => BufferCreationParams {
+                usage_flags: vk::BufferUsageFlags::INDEX_BUFFER |
+                    vk::BufferUsageFlags::TRANSFER_SRC |
+                    transfer_usage,
                 host_accessible: false
             },
             BufferUsage::UniformBuffer => BufferCreationParams {
-                usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER | transfer_usage,
+                usage_flags: vk::BufferUsageFlags::UNIFORM_BUFFER |
+                    vk::BufferUsageFlags::TRANSFER_SRC |
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                host_accessible: false
+            },
+            BufferUsage::IndirectDrawBuffer => BufferCreationParams {
+                usage_flags: vk::BufferUsageFlags::INDIRECT_BUFFER |
+                    vk::BufferUsageFlags::STORAGE_BUFFER |
+                    vk::BufferUsageFlags::TRANSFER_SRC |
+                    transfer_usage,
+                host_accessible: true
+            },
+            BufferUsage::StorageBuffer => BufferCreationParams {
+                usage_flags: vk::BufferUsageFlags::STORAGE_BUFFER |
+                    vk::BufferUsageFlags::TRANSFER_SRC |
+                    transfer_usage,
+                host_accessible: true
+            },
+            BufferUsage::Readback => BufferCreationParams {
+                usage_flags: vk::BufferUsageFlags::TRANSFER_DST,
                 host_accessible: true
             }
         };
@@ -119,7 +201,11 @@ impl BufferWrapper {
             buffer,
             size_bytes,
             element_count,
-            allocation
+            usage_flags: creation_params.usage_flags,
+            host_accessible: creation_params.host_accessible,
+            allocation,
+            index_buffer: None,
+            vertex_layout: None
         })
     }
 
@@ -129,8 +215,72 @@ impl BufferWrapper {
             buffer: vk::Buffer::null(),
             size_bytes: 0,
             element_count: 0,
-            allocation: MemoryAllocation::null()
+            usage_flags: vk::BufferUsageFlags::empty(),
+            host_accessible: false,
+            allocation: MemoryAllocation::null(),
+            index_buffer: None,
+            vertex_layout: None
+        }
+    }
+
+    /// Create a buffer of draw commands for PipelineWrapper::record_indirect_draw, for steps
+    /// without an index buffer. The buffer is host-visible so that the draw list can be rewritten
+    /// each frame with update, and may also be written by shaders as a storage buffer.
+    pub unsafe fn new_indirect_draws(
+        context: &VkContext,
+        commands: &[vk::DrawIndirectCommand]
+    ) -> Result<BufferWrapper, EngineError> {
+        if commands.is_empty() {
+            return Err(EngineError::UserError(
+                "Indirect draw buffer requested without any commands".to_owned()));
+        }
+        BufferWrapper::new(
+            context,
+            BufferUsage::IndirectDrawBuffer,
+            std::mem::size_of_val(commands),
+            commands.len(),
+            Some(commands.as_ptr() as *const u8))
+    }
+
+    /// Create a buffer of indexed draw commands for PipelineWrapper::record_indirect_draw, for
+    /// steps with an index buffer; see new_indirect_draws
+    pub unsafe fn new_indexed_indirect_draws(
+        context: &VkContext,
+        commands: &[vk::DrawIndexedIndirectCommand]
+    ) -> Result<BufferWrapper, EngineError> {
+        if commands.is_empty() {
+            return Err(EngineError::UserError(
+                "Indirect draw buffer requested without any commands".to_owned()));
         }
+        BufferWrapper::new(
+            context,
+            BufferUsage::IndirectDrawBuffer,
+            std::mem::size_of_val(commands),
+            commands.len(),
+            Some(commands.as_ptr() as *const u8))
+    }
+
+    /// Create a host-visible storage buffer holding the given elements, to be read by shaders and
+    /// rewritten with update as needed
+    pub unsafe fn new_storage<T: Sized + Copy>(
+        context: &VkContext,
+        elements: &[T]
+    ) -> Result<BufferWrapper, EngineError> {
+        BufferWrapper::new(
+            context,
+            BufferUsage::StorageBuffer,
+            std::mem::size_of_val(elements),
+            elements.len(),
+            Some(elements.as_ptr() as *const u8))
+    }
+
+    /// Create a host-visible buffer for the device to copy data into, to be read with read once
+    /// the copy has completed
+    pub unsafe fn new_readback(
+        context: &VkContext,
+        size_bytes: usize
+    ) -> Result<BufferWrapper, EngineError> {
+        BufferWrapper::new(context, BufferUsage::Readback, size_bytes, size_bytes, None)
     }
 
     /// Map the backed memory, then update it from a host-owned pointer
@@ -150,15 +300,210 @@ impl BufferWrapper {
                 update_range_bytes,
                 self.size_bytes)))
         }
-        let mut dst_ptr = allocator.map_memory::<T>(&self.allocation)?;
-        dst_ptr = dst_ptr.offset(dst_offset_elements);
-        dst_ptr.copy_from_nonoverlapping(src_ptr, element_count);
-        allocator.unmap_memory(&self.allocation).unwrap();
-        Ok(())
+        allocator.write_memory_range(
+            &self.allocation,
+            offset_bytes as vk::DeviceSize,
+            src_ptr as *const u8,
+            update_range_bytes)
+    }
+
+    /// Replace part of the buffer's contents, given a byte offset into it. Unlike update, this
+    /// also works for buffers in device-only memory, such as vertex buffers, which are patched
+    /// through a copy on the graphics queue; see MemoryAllocator::update_buffer_range.
+    pub unsafe fn update_range(
+        &self,
+        context: &VkContext,
+        offset_bytes: usize,
+        data: &[u8]
+    ) -> Result<(), EngineError> {
+        if offset_bytes + data.len() > self.size_bytes {
+            return Err(EngineError::EngineError(format!(
+                "Attempting to update buffer outside of range: offset {}, range {}, size {}",
+                offset_bytes,
+                data.len(),
+                self.size_bytes)))
+        }
+        let (allocator, _) = context.get_mem_allocator();
+        if !allocator.is_host_visible(&self.allocation) &&
+            !self.usage_flags.contains(vk::BufferUsageFlags::TRANSFER_DST)
+        {
+            return Err(EngineError::EngineError(
+                "Attempting to update a device-only buffer that was not initialised".to_owned()))
+        }
+        allocator.update_buffer_range(
+            &context.graphics_queue,
+            self.buffer,
+            &self.allocation,
+            offset_bytes as vk::DeviceSize,
+            data.as_ptr(),
+            data.len())
+    }
+
+    /// Map the backed memory, then copy its contents into a new host-owned vector; intended for
+    /// buffers written by the device, such as those with Readback usage
+    pub unsafe fn read<T: Sized + Copy>(
+        &self,
+        allocator: &MemoryAllocator,
+        src_offset_elements: isize,
+        element_count: usize
+    ) -> Result<Vec<T>, EngineError> {
+        let offset_bytes = src_offset_elements as usize * std::mem::size_of::<T>();
+        let read_range_bytes = element_count * std::mem::size_of::<T>();
+        if offset_bytes + read_range_bytes > self.size_bytes {
+            return Err(EngineError::EngineError(format!(
+                "Attempting to read buffer outside of range: offset {}, range {}, size {}",
+                offset_bytes,
+                read_range_bytes,
+                self.size_bytes)))
+        }
+        let mut data: Vec<T> = Vec::with_capacity(element_count);
+        allocator.read_memory_range(
+            &self.allocation,
+            offset_bytes as vk::DeviceSize,
+            data.as_mut_ptr() as *mut u8,
+            read_range_bytes)?;
+        data.set_len(element_count);
+        Ok(data)
+    }
+
+    /// Create a copy of this buffer in device-only memory, for data that is written by the host
+    /// once and then only read by the device. This buffer is left as it was, and may be released
+    /// once the copy is no longer needed; only buffers created with data can be copied.
+    pub unsafe fn clone_to_gpu_only(
+        &self,
+        context: &VkContext
+    ) -> Result<BufferWrapper, EngineError> {
+        if !self.usage_flags.contains(vk::BufferUsageFlags::TRANSFER_SRC) {
+            return Err(EngineError::EngineError(
+                "Attempting to copy a buffer that cannot be a transfer source".to_owned()))
+        }
+        let usage_flags = self.usage_flags |
+            vk::BufferUsageFlags::TRANSFER_SRC |
+            vk::BufferUsageFlags::TRANSFER_DST;
+        let buffer_create_info = vk::BufferCreateInfo::builder()
+            .size(self.size_bytes as u64)
+            .usage(usage_flags)
+            .build();
+        let buffer = context.device.create_buffer(&buffer_create_info, None)
+            .map_err(|e| {
+                EngineError::OpFailed(format!("Error creating buffer: {:?}", e))
+            })?;
+
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        let allocation = allocator.back_buffer_memory(
+            transfer_queue,
+            &buffer,
+            false,
+            None,
+            self.size_bytes)?;
+        let mut gpu_buffer = BufferWrapper {
+            buffer,
+            size_bytes: self.size_bytes,
+            element_count: self.element_count,
+            usage_flags,
+            host_accessible: false,
+            allocation,
+            index_buffer: None,
+            vertex_layout: self.vertex_layout.clone()
+        };
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: self.size_bytes as vk::DeviceSize
+        };
+        let copied = allocator
+            .copy_buffer(&context.graphics_queue, transfer_queue, self.buffer, buffer, &[region])
+            .and_then(|_| match &self.index_buffer {
+                Some(index_buffer) => {
+                    let gpu_index_buffer = index_buffer.clone_to_gpu_only(context)?;
+                    gpu_buffer.index_buffer = Some(Box::new(gpu_index_buffer));
+                    Ok(())
+                },
+                None => Ok(())
+            });
+        if let Err(e) = copied {
+            gpu_buffer.release(context);
+            return Err(e);
+        }
+        Ok(gpu_buffer)
+    }
+
+    /// Whether this buffer can be moved to a new allocation by defragmentation
+    pub fn is_movable(&self) -> bool {
+        self.buffer != vk::Buffer::null() &&
+            !self.host_accessible &&
+            self.usage_flags.contains(vk::BufferUsageFlags::TRANSFER_SRC)
+    }
+
+    /// Where the memory of this buffer and of its index buffer lives, for whichever of them
+    /// defragmentation can move
+    pub(crate) fn movable_locations(&self) -> Vec<MemoryLocation> {
+        let mut locations = vec![];
+        if self.is_movable() {
+            locations.push(self.allocation.location());
+        }
+        if let Some(index_buffer) = &self.index_buffer {
+            locations.extend(index_buffer.movable_locations());
+        }
+        locations
+    }
+
+    /// Move whichever of this buffer or its index buffer is bound at one location into a new
+    /// buffer bound at another, returning whether either was. Any copies of the old buffer handle
+    /// held elsewhere are invalidated.
+    pub(crate) unsafe fn relocate_from(
+        &mut self,
+        allocator: &MemoryAllocator,
+        graphics_queue: &Queue,
+        transfer_queue: &Queue,
+        from: MemoryLocation,
+        to: MemoryLocation
+    ) -> Result<bool, EngineError> {
+        if let Some(index_buffer) = &mut self.index_buffer {
+            if index_buffer.relocate_from(allocator, graphics_queue, transfer_queue, from, to)? {
+                return Ok(true);
+            }
+        }
+        if !self.is_movable() || self.allocation.location() != from {
+            return Ok(false);
+        }
+        let (buffer, allocation) = allocator.relocate_buffer(
+            graphics_queue,
+            transfer_queue,
+            self.buffer,
+            &self.allocation,
+            self.usage_flags,
+            self.size_bytes,
+            to)?;
+        self.buffer = buffer;
+        self.allocation = allocation;
+        Ok(true)
+    }
+
+    /// Name the memory backing this buffer, for identifying it in allocation leak reports
+    pub fn set_debug_name(&self, context: &VkContext, name: &str) {
+        let (allocator, _) = context.get_mem_allocator();
+        allocator.set_allocation_name(&self.allocation, name);
+        if let Some(index_buffer) = &self.index_buffer {
+            index_buffer.set_debug_name(context, &format!("{} (indices)", name));
+        }
     }
 
     /// Getter for the buffer within
     pub fn buffer(&self) -> vk::Buffer {
         self.buffer
     }
+
+    /// Getter for the index buffer and the number of 16-bit indices within it, if this buffer
+    /// was created for indexed drawing
+    pub fn index_buffer(&self) -> Option<(vk::Buffer, usize)> {
+        self.index_buffer
+            .as_ref()
+            .map(|index_buffer| (index_buffer.buffer, index_buffer.element_count))
+    }
+
+    /// Getter for the layout of the vertices within, if this buffer was created as vertices
+    pub fn get_vertex_layout(&self) -> Option<&VertexLayout> {
+        self.vertex_layout.as_ref()
+    }
 }
@@ -0,0 +1,213 @@
+use crate::TexturePixelFormat;
+use error::EngineError;
+
+/// Identifier at the start of every KTX2 file
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, b'\r', b'\n', 0x1a, b'\n'
+];
+
+/// Size of the KTX2 header and index, after which the level index begins
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+/// Size of the DDS magic number and header, and of the optional DX10 header extension
+const DDS_HEADER_END: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+
+/// DDS header flags, pixel format flags and capabilities used here
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xfc00;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// ContainerTexture struct
+/// Texture data read from a container file such as KTX2 or DDS. Each layer (one per cube face,
+/// for cube maps) holds every mip level, largest first, as TextureCreationData expects.
+pub struct ContainerTexture {
+    pub format: TexturePixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub layer_data: Vec<Vec<u8>>
+}
+
+/// Parse a KTX2 file. Array textures, 3D textures and supercompressed data are not supported.
+pub fn parse_ktx2(bytes: &[u8]) -> Result<ContainerTexture, EngineError> {
+    if bytes.len() < KTX2_LEVEL_INDEX_OFFSET || bytes[0..12] != KTX2_IDENTIFIER {
+        return Err(EngineError::OpFailed("Not a KTX2 file".to_owned()));
+    }
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let array_layers = read_u32(bytes, 32)?;
+    let face_count = read_u32(bytes, 36)?;
+    let mip_levels = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+    if depth > 1 || array_layers > 1 {
+        return Err(EngineError::Compatibility(
+            "KTX2 3D and array textures are not supported".to_owned()));
+    }
+    if supercompression != 0 {
+        return Err(EngineError::Compatibility(format!(
+            "KTX2 supercompression scheme {} is not supported",
+            supercompression)));
+    }
+    if face_count != 1 && face_count != 6 {
+        return Err(EngineError::OpFailed(format!("Invalid KTX2 face count {}", face_count)));
+    }
+    let format = format_for_vk_format(vk_format)
+        .ok_or_else(|| EngineError::Compatibility(
+            format!("KTX2 Vulkan format {} is not supported", vk_format)))?;
+    validate_dimensions(width, height, mip_levels)?;
+
+    // Levels are each stored as all faces one after the other, so gather each face's part of
+    // every level into that face's layer
+    let mut layer_data = vec![Vec::new(); face_count as usize];
+    for level in 0..mip_levels {
+        let index_offset = KTX2_LEVEL_INDEX_OFFSET + level as usize * 24;
+        let level_offset = read_u64(bytes, index_offset)? as usize;
+        let level_length = read_u64(bytes, index_offset + 8)? as usize;
+        let (level_width, level_height) = crate::mip_level_extent(width, height, level);
+        let face_size_bytes = format.layer_size_bytes(level_width, level_height);
+        if level_length != face_size_bytes * face_count as usize {
+            return Err(EngineError::OpFailed(format!(
+                "KTX2 level {} is {} bytes, expected {}",
+                level,
+                level_length,
+                face_size_bytes * face_count as usize)));
+        }
+        let level_data = read_slice(bytes, level_offset, level_length)?;
+        for (layer, face_data) in layer_data.iter_mut().zip(level_data.chunks(face_size_bytes)) {
+            layer.extend_from_slice(face_data);
+        }
+    }
+
+    Ok(ContainerTexture { format, width, height, mip_levels, layer_data })
+}
+
+/// Parse a DDS file, either with a legacy header or the DX10 header extension. Only cube maps
+/// with all six faces are supported, and array textures are not.
+pub fn parse_dds(bytes: &[u8]) -> Result<ContainerTexture, EngineError> {
+    if bytes.len() < DDS_HEADER_END || &bytes[0..4] != b"DDS " || read_u32(bytes, 4)? != 124 {
+        return Err(EngineError::OpFailed("Not a DDS file".to_owned()));
+    }
+    let flags = read_u32(bytes, 8)?;
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let mip_levels = match flags & DDSD_MIPMAPCOUNT {
+        0 => 1,
+        _ => read_u32(bytes, 28)?.max(1)
+    };
+    let pixel_format_flags = read_u32(bytes, 80)?;
+    let four_cc = read_slice(bytes, 84, 4)?;
+    let caps2 = read_u32(bytes, 112)?;
+
+    let (format, is_cube, data_offset) = if pixel_format_flags & DDPF_FOURCC != 0 {
+        match four_cc {
+            b"DXT1" => (TexturePixelFormat::Bc1, false, DDS_HEADER_END),
+            b"DXT5" => (TexturePixelFormat::Bc3, false, DDS_HEADER_END),
+            b"DX10" => {
+                let dxgi_format = read_u32(bytes, DDS_HEADER_END)?;
+                let misc_flags = read_u32(bytes, DDS_HEADER_END + 8)?;
+                let array_size = read_u32(bytes, DDS_HEADER_END + 12)?;
+                if array_size > 1 {
+                    return Err(EngineError::Compatibility(
+                        "DDS array textures are not supported".to_owned()));
+                }
+                let format = match dxgi_format {
+                    10 => TexturePixelFormat::RgbaF16,
+                    24 => TexturePixelFormat::Rgb10A2,
+                    28 => TexturePixelFormat::Rgba,
+                    29 => TexturePixelFormat::Srgba,
+                    71 => TexturePixelFormat::Bc1,
+                    77 => TexturePixelFormat::Bc3,
+                    98 => TexturePixelFormat::Bc7,
+                    _ => return Err(EngineError::Compatibility(
+                        format!("DDS DXGI format {} is not supported", dxgi_format)))
+                };
+                let is_cube = misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+                (format, is_cube, DDS_HEADER_END + DDS_DX10_HEADER_SIZE)
+            },
+            _ => return Err(EngineError::Compatibility(
+                format!("DDS format {:?} is not supported", String::from_utf8_lossy(four_cc))))
+        }
+    } else if pixel_format_flags & DDPF_RGB != 0 && is_rgba8_layout(bytes)? {
+        (TexturePixelFormat::Rgba, false, DDS_HEADER_END)
+    } else {
+        return Err(EngineError::Compatibility("DDS pixel format is not supported".to_owned()));
+    };
+
+    // Legacy headers flag cube maps in the capabilities, which may name only some faces
+    let legacy_cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+    if legacy_cube && caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES {
+        return Err(EngineError::Compatibility(
+            "DDS cube maps must have all six faces".to_owned()));
+    }
+    let is_cube = is_cube || legacy_cube;
+    validate_dimensions(width, height, mip_levels)?;
+
+    // Faces are stored one after the other, each with all of its mip levels
+    let face_count = if is_cube { 6 } else { 1 };
+    let layer_size_bytes = format.mip_chain_size_bytes(width, height, mip_levels);
+    let layer_data = (0..face_count)
+        .map(|face| read_slice(bytes, data_offset + face * layer_size_bytes, layer_size_bytes)
+            .map(|data| data.to_vec()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ContainerTexture { format, width, height, mip_levels, layer_data })
+}
+
+/// Find the sampleable format using the given Vulkan format, if it is one the engine knows
+fn format_for_vk_format(vk_format: u32) -> Option<TexturePixelFormat> {
+    [
+        TexturePixelFormat::Rgba,
+        TexturePixelFormat::Srgba,
+        TexturePixelFormat::RgbaF16,
+        TexturePixelFormat::Rgb10A2
+    ].into_iter()
+        .chain(TexturePixelFormat::COMPRESSED_FORMATS)
+        .find(|format| format.vk_format().as_raw() as u32 == vk_format)
+}
+
+/// Check that a legacy DDS uncompressed pixel format is 32-bit RGBA in byte order
+fn is_rgba8_layout(bytes: &[u8]) -> Result<bool, EngineError> {
+    Ok(read_u32(bytes, 88)? == 32 &&
+        read_u32(bytes, 92)? == 0x000000ff &&
+        read_u32(bytes, 96)? == 0x0000ff00 &&
+        read_u32(bytes, 100)? == 0x00ff0000 &&
+        read_u32(bytes, 104)? == 0xff000000)
+}
+
+fn validate_dimensions(width: u32, height: u32, mip_levels: u32) -> Result<(), EngineError> {
+    if width == 0 || height == 0 {
+        return Err(EngineError::OpFailed(format!("Invalid texture size {}x{}", width, height)));
+    }
+    if mip_levels > crate::full_mip_chain_length(width, height) {
+        return Err(EngineError::OpFailed(format!(
+            "Too many mip levels ({}) for {}x{} texture",
+            mip_levels,
+            width,
+            height)));
+    }
+    Ok(())
+}
+
+fn read_slice(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], EngineError> {
+    offset.checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| EngineError::OpFailed("Texture file is truncated".to_owned()))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, EngineError> {
+    let slice = read_slice(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, EngineError> {
+    let slice = read_slice(bytes, offset, 8)?;
+    let mut word = [0u8; 8];
+    word.copy_from_slice(slice);
+    Ok(u64::from_le_bytes(word))
+}
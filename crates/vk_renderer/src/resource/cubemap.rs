@@ -0,0 +1,254 @@
+use crate::{
+    VkContext, ImageWrapper, ImageUsage, ImageAccess, TexturePixelFormat, TextureCreationData,
+    DescriptorBinding, write_descriptor_bindings
+};
+use ecs::resource::Resource;
+use error::EngineError;
+use ash::vk;
+use vk_shader_macros::include_glsl;
+use std::ffi::CStr;
+
+const EQUIRECT_TO_CUBE_SHADER: &[u32] =
+    include_glsl!("../../resources/shaders/equirect_to_cube.comp");
+
+/// Size of the compute shader's work groups in each dimension of a face
+const WORK_GROUP_SIZE: u32 = 8;
+
+/// Fill a new compute-writable sky box by sampling an equirectangular image with a compute
+/// shader, then leave it ready for sampling. The sky box starts in the layout for storage
+/// writes, so needs no barrier before the dispatch. Everything but the sky box is released
+/// afterwards.
+pub unsafe fn equirectangular_to_cubemap(
+    context: &VkContext,
+    equirectangular: &TextureCreationData,
+    face_size: u32
+) -> Result<ImageWrapper, EngineError> {
+    let Some(layer_data) = equirectangular.layer_data.as_ref() else {
+        return Err(EngineError::UserError(
+            String::from("Equirectangular image must be initialised with data")));
+    };
+    if face_size == 0 {
+        return Err(EngineError::UserError(String::from("Cube map faces must not be empty")));
+    }
+    let source = ImageWrapper::new_with_mip_levels(
+        context,
+        ImageUsage::TextureSampleOnly,
+        equirectangular.format,
+        equirectangular.width,
+        equirectangular.height,
+        equirectangular.mip_levels,
+        Some(layer_data.as_slice()))?;
+    let cubemap = match ImageWrapper::new(
+        context,
+        ImageUsage::Skybox,
+        TexturePixelFormat::Rgba,
+        face_size,
+        face_size,
+        None
+    ) {
+        Ok(cubemap) => cubemap,
+        Err(e) => {
+            source.release(context);
+            return Err(e);
+        }
+    };
+
+    let result = ConversionPass::new(context)
+        .and_then(|pass| {
+            let result = pass.run(context, &source, &cubemap, face_size);
+            pass.destroy(context);
+            result
+        });
+    source.release(context);
+    match result {
+        Ok(()) => Ok(cubemap),
+        Err(e) => {
+            cubemap.release(context);
+            Err(e)
+        }
+    }
+}
+
+/// Vulkan objects used only for the duration of a conversion
+struct ConversionPass {
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    shader_module: vk::ShaderModule,
+    pipeline: vk::Pipeline
+}
+
+impl ConversionPass {
+
+    /// Create every object, destroying any already created if one fails
+    unsafe fn new(context: &VkContext) -> Result<Self, EngineError> {
+        let mut pass = Self {
+            sampler: vk::Sampler::null(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            shader_module: vk::ShaderModule::null(),
+            pipeline: vk::Pipeline::null()
+        };
+        if let Err(e) = pass.create_objects(context) {
+            pass.destroy(context);
+            return Err(e);
+        }
+        Ok(pass)
+    }
+
+    unsafe fn create_objects(&mut self, context: &VkContext) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        // Wrap around horizontally, since longitude is continuous, but not vertically
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .min_filter(vk::Filter::LINEAR)
+            .mag_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        self.sampler = device.create_sampler(&sampler_info, None)
+            .map_err(|e| EngineError::OpFailed(format!("Error creating sampler: {:?}", e)))?;
+
+        let layout_bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        ];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&layout_bindings);
+        self.descriptor_set_layout = device.create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor set layout: {:?}", e)))?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1
+            }
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = device.create_descriptor_pool(&pool_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating descriptor pool: {:?}", e)))?;
+
+        let set_layouts = [self.descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        self.descriptor_set = device.allocate_descriptor_sets(&allocate_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error allocating descriptor set: {:?}", e)))?[0];
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts);
+        self.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating pipeline layout: {:?}", e)))?;
+
+        let shader_info = vk::ShaderModuleCreateInfo::builder()
+            .code(EQUIRECT_TO_CUBE_SHADER);
+        self.shader_module = device.create_shader_module(&shader_info, None)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error creating shader module: {:?}", e)))?;
+
+        let entry_point = CStr::from_bytes_with_nul_unchecked(b"main\0");
+        let stage_info = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(self.shader_module)
+            .name(entry_point)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage_info)
+            .layout(self.pipeline_layout)
+            .build();
+        self.pipeline = device
+            .create_compute_pipelines(context.get_pipeline_cache(), &[pipeline_info], None)
+            .map_err(|(_, e)| EngineError::OpFailed(
+                format!("Error creating compute pipeline: {:?}", e)))?[0];
+        Ok(())
+    }
+
+    /// Record and submit the conversion, waiting for it to finish
+    unsafe fn run(
+        &self,
+        context: &VkContext,
+        source: &ImageWrapper,
+        cubemap: &ImageWrapper,
+        face_size: u32
+    ) -> Result<(), EngineError> {
+        let device = &context.device;
+
+        // Both images were prepared through the transfer queue
+        context.acquire_transferred_resources()?;
+        write_descriptor_bindings(context, self.descriptor_set, &[
+            (0, DescriptorBinding::CombinedImageSampler {
+                image_view: source.image_view,
+                sampler: self.sampler,
+                image_layout: source.get_layout()
+            }),
+            (1, DescriptorBinding::StorageImage {
+                image_view: cubemap.image_view,
+                image_layout: ImageAccess::StorageWrite.layout()
+            })
+        ]);
+
+        let command_buffer = context.graphics_queue.allocate_command_buffer(device)?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &begin_info)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error beginning command buffer: {:?}", e)))?;
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[]);
+        let group_count = (face_size + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE;
+        device.cmd_dispatch(command_buffer, group_count, group_count, 6);
+        cubemap.transition(device, command_buffer, ImageAccess::ShaderRead);
+        device.end_command_buffer(command_buffer)
+            .map_err(|e| EngineError::OpFailed(
+                format!("Error ending command buffer: {:?}", e)))?;
+
+        let graphics_timeline = context.get_graphics_timeline();
+        let result = graphics_timeline
+            .submit(device, &context.graphics_queue, &[command_buffer], &[], &[])
+            .and_then(|timeline_value| graphics_timeline.wait(device, timeline_value));
+        context.graphics_queue.free_command_buffer(device, command_buffer);
+        result
+    }
+
+    unsafe fn destroy(&self, context: &VkContext) {
+        let device = &context.device;
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_shader_module(self.shader_module, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        device.destroy_sampler(self.sampler, None);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::ShaderStage;
+use error::EngineError;
+
+/// Compile GLSL source for the given stage into SPIR-V targeting Vulkan 1.0, as include_glsl
+/// does at build time
+#[cfg(feature = "runtime-shaders")]
+pub(crate) fn compile_glsl(source: &str, stage: ShaderStage) -> Result<Vec<u32>, EngineError> {
+    let compiler = shaderc::Compiler::new()
+        .ok_or_else(|| EngineError::OpFailed(String::from("Error creating shader compiler")))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| EngineError::OpFailed(String::from("Error creating compile options")))?;
+    options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_0 as u32);
+    let kind = match stage {
+        ShaderStage::Vertex => shaderc::ShaderKind::Vertex,
+        ShaderStage::Fragment => shaderc::ShaderKind::Fragment,
+        ShaderStage::Compute => shaderc::ShaderKind::Compute
+    };
+    let artifact = compiler
+        .compile_into_spirv(source, kind, "runtime.glsl", "main", Some(&options))
+        .map_err(|e| EngineError::UserError(format!("Error compiling shader: {}", e)))?;
+    Ok(artifact.as_binary().to_vec())
+}
+
+/// Without the runtime-shaders feature there is no compiler, so GLSL source is not supported
+#[cfg(not(feature = "runtime-shaders"))]
+pub(crate) fn compile_glsl(_source: &str, _stage: ShaderStage) -> Result<Vec<u32>, EngineError> {
+    Err(EngineError::Compatibility(String::from(
+        "Compiling GLSL at runtime needs the runtime-shaders feature")))
+}
@@ -1,56 +1,375 @@
 
-use crate::{context::VkContext, mem::{MemoryAllocation, ManagesImageMemory}};
+use crate::{
+    context::{VkContext, CommandQueue, barriers::record_image_barriers},
+    mem::{
+        MemoryAllocation, MemoryLocation, ManagesImageMemory, ManagesMemoryTransfers,
+        SparseImageBacking
+    }
+};
 use ecs::{EcsManager, resource::Resource};
 use error::EngineError;
-use ash::vk;
+use ash::{Device, vk};
+use std::sync::Mutex;
 
 /// TexturePixelFormat enum
-/// Abstraction of the set of pixel formats known by the engine
+/// Abstraction of the set of pixel formats known by the engine. Srgba holds the same data as
+/// Rgba, but is decoded from sRGB to linear when sampled and encoded back when rendered to, which
+/// suits color textures authored in sRGB. RgbaF16 holds half-float components and Rgb10A2 10-bit
+/// unorm color with 2-bit alpha, so that lighting can be accumulated in HDR, or with more
+/// precision, before tone mapping; both are supported on every device. The block-compressed
+/// formats are all RGB(A) with 8-bit unorm precision, and may only be used for sampled textures
+/// on devices that support them; see VkContext::supports_texture_format. Unorm16 and
+/// Depth24Stencil8 are depth formats, the latter with a stencil aspect, for effects such as
+/// outlines and portals.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum TexturePixelFormat {
     None,
     Rgba,
-    Unorm16
+    Srgba,
+    RgbaF16,
+    Rgb10A2,
+    Unorm16,
+    Depth24Stencil8,
+    Bc1,
+    Bc3,
+    Bc7,
+    Etc2Rgb,
+    Etc2Rgba,
+    Astc4x4
+}
+
+impl TexturePixelFormat {
+
+    /// All of the block-compressed formats
+    pub const COMPRESSED_FORMATS: [TexturePixelFormat; 6] = [
+        TexturePixelFormat::Bc1,
+        TexturePixelFormat::Bc3,
+        TexturePixelFormat::Bc7,
+        TexturePixelFormat::Etc2Rgb,
+        TexturePixelFormat::Etc2Rgba,
+        TexturePixelFormat::Astc4x4
+    ];
+
+    /// Query whether data in this format is stored in compressed blocks of texels
+    pub fn is_compressed(&self) -> bool {
+        self.block_extent() != (1, 1)
+    }
+
+    /// Query whether this is an uncompressed color format, which can be rendered into as a color
+    /// attachment as well as sampled
+    pub fn is_color_attachment_format(&self) -> bool {
+        matches!(
+            self,
+            TexturePixelFormat::Rgba | TexturePixelFormat::Srgba | TexturePixelFormat::RgbaF16 |
+                TexturePixelFormat::Rgb10A2)
+    }
+
+    /// Query whether this is a format for depth attachments
+    pub fn is_depth(&self) -> bool {
+        matches!(self, TexturePixelFormat::Unorm16 | TexturePixelFormat::Depth24Stencil8)
+    }
+
+    /// Query whether this is a depth format that also holds stencil values
+    pub fn has_stencil(&self) -> bool {
+        *self == TexturePixelFormat::Depth24Stencil8
+    }
+
+    /// The aspects of images in this format: depth, with stencil where the format holds it, or
+    /// color
+    pub fn aspect_flags(&self) -> vk::ImageAspectFlags {
+        match self {
+            TexturePixelFormat::Unorm16 => vk::ImageAspectFlags::DEPTH,
+            TexturePixelFormat::Depth24Stencil8 =>
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+            _ => vk::ImageAspectFlags::COLOR
+        }
+    }
+
+    /// The Vulkan format of images holding data in this format; Unorm16 and Depth24Stencil8 are
+    /// only used for depth
+    pub fn vk_format(&self) -> vk::Format {
+        match self {
+            TexturePixelFormat::None => vk::Format::UNDEFINED,
+            TexturePixelFormat::Rgba => vk::Format::R8G8B8A8_UNORM,
+            TexturePixelFormat::Srgba => vk::Format::R8G8B8A8_SRGB,
+            TexturePixelFormat::RgbaF16 => vk::Format::R16G16B16A16_SFLOAT,
+            TexturePixelFormat::Rgb10A2 => vk::Format::A2B10G10R10_UNORM_PACK32,
+            TexturePixelFormat::Unorm16 => vk::Format::D16_UNORM,
+            TexturePixelFormat::Depth24Stencil8 => vk::Format::D24_UNORM_S8_UINT,
+            TexturePixelFormat::Bc1 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            TexturePixelFormat::Bc3 => vk::Format::BC3_UNORM_BLOCK,
+            TexturePixelFormat::Bc7 => vk::Format::BC7_UNORM_BLOCK,
+            TexturePixelFormat::Etc2Rgb => vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+            TexturePixelFormat::Etc2Rgba => vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+            TexturePixelFormat::Astc4x4 => vk::Format::ASTC_4X4_UNORM_BLOCK
+        }
+    }
+
+    /// Width and height in texels of each block of data; 1x1 for uncompressed formats
+    pub fn block_extent(&self) -> (u32, u32) {
+        match self {
+            TexturePixelFormat::None | TexturePixelFormat::Unorm16 |
+                TexturePixelFormat::Depth24Stencil8 => (1, 1),
+            format if format.is_color_attachment_format() => (1, 1),
+            _ => (4, 4)
+        }
+    }
+
+    /// Size of each block of data, which for uncompressed formats is a single texel
+    pub fn block_size_bytes(&self) -> usize {
+        match self {
+            TexturePixelFormat::None => 0,
+            TexturePixelFormat::Unorm16 => 2,
+            TexturePixelFormat::Rgba | TexturePixelFormat::Srgba | TexturePixelFormat::Rgb10A2 |
+                TexturePixelFormat::Depth24Stencil8 => 4,
+            TexturePixelFormat::RgbaF16 | TexturePixelFormat::Bc1 |
+                TexturePixelFormat::Etc2Rgb => 8,
+            TexturePixelFormat::Bc3 | TexturePixelFormat::Bc7 | TexturePixelFormat::Etc2Rgba |
+                TexturePixelFormat::Astc4x4 => 16
+        }
+    }
+
+    /// Size of one layer of an image in this format; partial blocks at the right and bottom
+    /// edges take up a whole block
+    pub fn layer_size_bytes(&self, width: u32, height: u32) -> usize {
+        let (block_width, block_height) = self.block_extent();
+        let blocks_wide = width.div_ceil(block_width) as usize;
+        let blocks_high = height.div_ceil(block_height) as usize;
+        blocks_wide * blocks_high * self.block_size_bytes()
+    }
+
+    /// Size of one layer of an image including its mip levels, which are stored one after the
+    /// other from largest to smallest, each level halving the dimensions down to a minimum of 1
+    pub fn mip_chain_size_bytes(&self, width: u32, height: u32, mip_levels: u32) -> usize {
+        (0..mip_levels)
+            .map(|level| {
+                let (level_width, level_height) = mip_level_extent(width, height, level);
+                self.layer_size_bytes(level_width, level_height)
+            })
+            .sum()
+    }
+}
+
+/// Dimensions of the given mip level of an image
+pub fn mip_level_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// Number of mip levels in a full chain down to 1x1 for an image of the given dimensions
+pub fn full_mip_chain_length(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
 }
 
 /// ImageUsage enum
-/// An enumeration of what purpose image resources can be used for
+/// An enumeration of what purpose image resources can be used for. A texture array has one layer
+/// per item of initialisation data, all the same size, and is sampled with a layer index, such as
+/// for terrain splatting or sprite sheets bound with a single descriptor. A volume texture is a
+/// 3D image, such as for volumetric fog or noise lookup tables, initialised a depth slice at a
+/// time. A sky box created without data, which must be RGBA, is left writable by compute shaders.
+/// A dynamic texture is sampled like any other, but its contents may be rewritten from the host
+/// at any time with update_region, such as for video frames, atlases or font caches. An MSAA
+/// color target holds multiple samples per pixel, to be resolved into a single-sampled image at
+/// the end of a renderpass, and must be created with new_multisampled. A headless output stands
+/// in for a swapchain image when rendering without a surface, so can be rendered, blitted into
+/// and read back.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ImageUsage {
     TextureSampleOnly,
     DepthBuffer,
     OffscreenRenderSampleColorWriteDepth,
-    Skybox
+    Skybox,
+    SparseTexture,
+    ComputeWriteSampled,
+    TextureArray,
+    VolumeTexture,
+    DynamicTexture,
+    MsaaColorTarget,
+    HeadlessOutput
+}
+
+/// ImageAccess enum
+/// The ways in which an image may be used, each implying a layout and the pipeline stages and
+/// accesses involved. Transitions between them are computed from these rather than written out
+/// by hand.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ImageAccess {
+    Undefined,
+    TransferRead,
+    TransferWrite,
+    ShaderRead,
+    StorageWrite,
+    ColorAttachment,
+    DepthAttachment,
+    Present
+}
+
+impl ImageAccess {
+
+    /// The layout an image must be in for this kind of access
+    pub fn layout(&self) -> vk::ImageLayout {
+        match self {
+            ImageAccess::Undefined => vk::ImageLayout::UNDEFINED,
+            ImageAccess::TransferRead => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageAccess::TransferWrite => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageAccess::ShaderRead => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageAccess::StorageWrite => vk::ImageLayout::GENERAL,
+            ImageAccess::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageAccess::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageAccess::Present => vk::ImageLayout::PRESENT_SRC_KHR
+        }
+    }
+
+    /// The pipeline stages in which this kind of access happens
+    pub fn stage_mask(&self) -> vk::PipelineStageFlags {
+        match self {
+            ImageAccess::Undefined => vk::PipelineStageFlags::TOP_OF_PIPE,
+            ImageAccess::TransferRead | ImageAccess::TransferWrite =>
+                vk::PipelineStageFlags::TRANSFER,
+            ImageAccess::ShaderRead => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ImageAccess::StorageWrite => vk::PipelineStageFlags::COMPUTE_SHADER,
+            ImageAccess::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ImageAccess::DepthAttachment =>
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS |
+                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            ImageAccess::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        }
+    }
+
+    /// The memory accesses made by this kind of access
+    pub fn access_mask(&self) -> vk::AccessFlags {
+        match self {
+            ImageAccess::Undefined | ImageAccess::Present => vk::AccessFlags::empty(),
+            ImageAccess::TransferRead => vk::AccessFlags::TRANSFER_READ,
+            ImageAccess::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+            ImageAccess::ShaderRead => vk::AccessFlags::SHADER_READ,
+            ImageAccess::StorageWrite =>
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ImageAccess::ColorAttachment =>
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ImageAccess::DepthAttachment =>
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ |
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        }
+    }
+
+    /// Query whether this kind of access never writes to the image
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, ImageAccess::TransferRead | ImageAccess::ShaderRead | ImageAccess::Present)
+    }
+
+    /// Query whether moving from this access to the next needs a barrier; it does unless the
+    /// layout is unchanged and neither access writes
+    pub fn needs_barrier_to(&self, next: ImageAccess) -> bool {
+        *self != next || !self.is_read_only()
+    }
+
+    /// The layout, stages and accesses that this kind of access implies
+    pub fn state(&self) -> ImageState {
+        ImageState {
+            layout: self.layout(),
+            stage_mask: self.stage_mask(),
+            access_mask: self.access_mask()
+        }
+    }
+}
+
+/// ImageState struct
+/// The layout an image is in, with the pipeline stages and memory accesses that last used it.
+/// This covers accesses that ImageAccess does not, such as sampling from a vertex shader.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stage_mask: vk::PipelineStageFlags,
+    pub access_mask: vk::AccessFlags
+}
+
+impl ImageState {
+
+    pub fn new(
+        layout: vk::ImageLayout,
+        stage_mask: vk::PipelineStageFlags,
+        access_mask: vk::AccessFlags
+    ) -> Self {
+        Self { layout, stage_mask, access_mask }
+    }
+
+    /// Query whether any of the accesses write to the image
+    pub fn writes(&self) -> bool {
+        let write_mask = vk::AccessFlags::SHADER_WRITE |
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE |
+            vk::AccessFlags::TRANSFER_WRITE |
+            vk::AccessFlags::HOST_WRITE |
+            vk::AccessFlags::MEMORY_WRITE;
+        self.access_mask.intersects(write_mask)
+    }
+
+    /// Query whether moving from this state to the next needs a barrier; it does unless the
+    /// layout is unchanged and neither state writes
+    pub fn needs_barrier_to(&self, next: &ImageState) -> bool {
+        self.layout != next.layout || self.writes() || next.writes()
+    }
+
+    /// The state after also making the next accesses with no barrier between. Later barriers
+    /// must then wait for the stages of both.
+    pub fn merged_with(&self, next: &ImageState) -> ImageState {
+        ImageState {
+            layout: self.layout,
+            stage_mask: self.stage_mask | next.stage_mask,
+            access_mask: self.access_mask | next.access_mask
+        }
+    }
 }
 
 /// TextureCreationData struct
-/// Specification for how a texture resource is to be created
+/// Specification for how a texture resource is to be created. Where there is more than one mip
+/// level, each layer's data holds every level, largest first. Depth is 1 except for volume
+/// textures, for which the data holds one item per depth slice instead of per layer.
 pub struct TextureCreationData {
     pub layer_data: Option<Vec<Vec<u8>>>,
     pub width: u32,
     pub height: u32,
+    pub depth: u32,
+    pub mip_levels: u32,
     pub format: TexturePixelFormat,
     pub usage: ImageUsage
 }
 
 /// ImageCreationParams struct
-/// Description for creating an image; should cover all use cases needed by the engine
+/// Description for creating an image; should cover all use cases needed by the engine. Images
+/// that are host-updatable may have their contents rewritten from the host after creation,
+/// through a staging buffer on the transfer queue; their memory is device-local like any other.
 struct ImageCreationParams {
     format: vk::Format,
     usage: vk::ImageUsageFlags,
     aspect: vk::ImageAspectFlags,
     view_type: vk::ImageViewType,
     initialising_layout: vk::ImageLayout,
-    expected_layout: vk::ImageLayout,
+    expected_access: ImageAccess,
     layer_count: u32,
-    host_visible: bool
+    host_updatable: bool,
+    sparse: bool
 }
 
 /// ImageWrapper struct
 /// Wraps a Vulkan image, image view, the format used by the image, and the memory allocation
-/// backing the image
+/// backing the image. Sparse images instead have their memory backed a tile at a time. The way
+/// the image was last accessed is tracked, so that transitions can compute their own layouts
+/// and barriers.
 pub struct ImageWrapper {
     allocation: MemoryAllocation,
+    sparse_backing: Option<SparseImageBacking>,
+    aspect: vk::ImageAspectFlags,
+    usage: vk::ImageUsageFlags,
+    view_type: vk::ImageViewType,
+    extent: vk::Extent2D,
+    depth: u32,
+    layer_count: u32,
+    mip_levels: u32,
+    samples: vk::SampleCountFlags,
+    host_updatable: bool,
+    synchronization2: bool,
+    current_state: Mutex<ImageState>,
     pub image: vk::Image,
     pub image_view: vk::ImageView,
     pub format: vk::Format
@@ -66,20 +385,24 @@ impl Resource<VkContext> for ImageWrapper {
     ) -> Result<Self, EngineError> {
         let texture = unsafe {
             match data.layer_data.as_ref() {
-                Some(init_data) => ImageWrapper::new(
+                Some(init_data) => ImageWrapper::new_with_extent(
                     loader,
                     data.usage,
                     data.format,
                     data.width,
                     data.height,
+                    data.depth,
+                    data.mip_levels,
                     Some(init_data.as_slice()))?,
                 // TODO - One per swapchain image?
-                None => ImageWrapper::new(
+                None => ImageWrapper::new_with_extent(
                     loader,
                     data.usage,
                     data.format,
                     data.width,
                     data.height,
+                    data.depth,
+                    data.mip_levels,
                     None
                 )?
             }
@@ -91,7 +414,10 @@ impl Resource<VkContext> for ImageWrapper {
         let (allocator, _) = loader.get_mem_allocator();
         unsafe {
             loader.device.destroy_image_view(self.image_view, None);
-            allocator.destroy_image(self.image, &self.allocation).unwrap();
+            match &self.sparse_backing {
+                Some(backing) => allocator.destroy_sparse_image(self.image, backing).unwrap(),
+                None => allocator.destroy_image(self.image, &self.allocation).unwrap()
+            }
         }
     }
 }
@@ -102,6 +428,18 @@ impl ImageWrapper {
     pub fn empty() -> ImageWrapper {
         ImageWrapper {
             allocation: MemoryAllocation::null(),
+            sparse_backing: None,
+            aspect: vk::ImageAspectFlags::empty(),
+            usage: vk::ImageUsageFlags::empty(),
+            view_type: vk::ImageViewType::TYPE_2D,
+            extent: vk::Extent2D::default(),
+            depth: 0,
+            layer_count: 0,
+            mip_levels: 0,
+            samples: vk::SampleCountFlags::TYPE_1,
+            host_updatable: false,
+            synchronization2: false,
+            current_state: Mutex::new(ImageAccess::Undefined.state()),
             image: vk::Image::null(),
             image_view: vk::ImageView::null(),
             format: vk::Format::UNDEFINED
@@ -117,98 +455,368 @@ impl ImageWrapper {
         height: u32,
         init_layer_data: Option<&[Vec<u8>]>
     ) -> Result<ImageWrapper, EngineError> {
+        Self::new_with_mip_levels(context, usage, format, width, height, 1, init_layer_data)
+    }
+
+    /// Create a new instance with the given number of mip levels, fully initialised. Only
+    /// sample-only textures, sky boxes and texture arrays may have more than one level, and the
+    /// data for every level must be supplied.
+    pub unsafe fn new_with_mip_levels(
+        context: &VkContext,
+        usage: ImageUsage,
+        format: TexturePixelFormat,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        init_layer_data: Option<&[Vec<u8>]>
+    ) -> Result<ImageWrapper, EngineError> {
+        Self::new_with_extent(
+            context,
+            usage,
+            format,
+            width,
+            height,
+            1,
+            mip_levels,
+            init_layer_data)
+    }
+
+    /// Create a new instance with the given depth and number of mip levels, fully initialised.
+    /// Only volume textures may have a depth greater than 1; their data is one item per slice.
+    pub unsafe fn new_with_extent(
+        context: &VkContext,
+        usage: ImageUsage,
+        format: TexturePixelFormat,
+        width: u32,
+        height: u32,
+        depth: u32,
+        mip_levels: u32,
+        init_layer_data: Option<&[Vec<u8>]>
+    ) -> Result<ImageWrapper, EngineError> {
+
+        if depth == 0 || (depth > 1 && usage != ImageUsage::VolumeTexture) {
+            return Err(EngineError::UserError(
+                format!("Invalid depth {} for image with usage {:?}", depth, usage)));
+        }
+        if mip_levels == 0 || mip_levels > full_mip_chain_length(width, height) {
+            return Err(EngineError::UserError(
+                format!("Invalid mip level count {} for {}x{} image", mip_levels, width, height)));
+        }
+        let mips_allowed = matches!(
+            usage,
+            ImageUsage::TextureSampleOnly | ImageUsage::Skybox | ImageUsage::TextureArray);
+        if mip_levels > 1 && !mips_allowed {
+            return Err(EngineError::UserError(
+                format!("Images with usage {:?} may not have mip levels", usage)));
+        }
+
+        if format.is_compressed() && !context.supports_texture_format(format) {
+            return Err(EngineError::Compatibility(
+                format!("Texture format {:?} is not supported by this device", format)));
+        }
 
         let creation_params = match (usage, format) {
             // Typical depth buffer
-            (ImageUsage::DepthBuffer, TexturePixelFormat::Unorm16) => {
+            (
+                ImageUsage::DepthBuffer,
+                TexturePixelFormat::Unorm16 | TexturePixelFormat::Depth24Stencil8
+            ) => {
                 if init_layer_data.is_some() {
                     return Err(EngineError::OpFailed(
                         String::from("Initialising depth buffer not allowed")));
                 }
                 ImageCreationParams {
-                    format: vk::Format::D16_UNORM,
+                    format: format.vk_format(),
                     usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-                    aspect: vk::ImageAspectFlags::DEPTH,
+                    aspect: format.aspect_flags(),
                     view_type: vk::ImageViewType::TYPE_2D,
                     initialising_layout: vk::ImageLayout::UNDEFINED,
-                    expected_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    expected_access: ImageAccess::DepthAttachment,
                     layer_count: 1,
-                    host_visible: false
+                    host_updatable: false,
+                    sparse: false
                 }
             },
 
             // Typical off-screen-rendered color attachment
-            (ImageUsage::OffscreenRenderSampleColorWriteDepth, TexturePixelFormat::Rgba) => {
+            (ImageUsage::OffscreenRenderSampleColorWriteDepth, format)
+                if format.is_color_attachment_format() => {
                 if init_layer_data.is_some() {
                     return Err(EngineError::OpFailed(
                         String::from("Initialising off-screen render image not allowed")));
                 }
                 ImageCreationParams {
-                    format: vk::Format::R8G8B8A8_UNORM,
-                    usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::SAMPLED |
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT |
+                        vk::ImageUsageFlags::TRANSFER_SRC,
                     aspect: vk::ImageAspectFlags::COLOR,
                     view_type: vk::ImageViewType::TYPE_2D,
                     initialising_layout: vk::ImageLayout::UNDEFINED,
-                    expected_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    expected_access: ImageAccess::ColorAttachment,
                     layer_count: 1,
-                    host_visible: false
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Image standing in for a swapchain image in a headless context
+            (ImageUsage::HeadlessOutput, TexturePixelFormat::Rgba | TexturePixelFormat::Srgba) => {
+                if init_layer_data.is_some() {
+                    return Err(EngineError::OpFailed(
+                        String::from("Initialising headless output image not allowed")));
+                }
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::COLOR_ATTACHMENT |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::TRANSFER_DST,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    initialising_layout: vk::ImageLayout::UNDEFINED,
+                    expected_access: ImageAccess::ColorAttachment,
+                    layer_count: 1,
+                    host_updatable: false,
+                    sparse: false
                 }
             },
 
             // Typical off-screen-rendered depth attachment
-            (ImageUsage::OffscreenRenderSampleColorWriteDepth, TexturePixelFormat::Unorm16) => {
+            (
+                ImageUsage::OffscreenRenderSampleColorWriteDepth,
+                TexturePixelFormat::Unorm16 | TexturePixelFormat::Depth24Stencil8
+            ) => {
                 if init_layer_data.is_some() {
                     return Err(EngineError::OpFailed(
                         String::from("Initialising off-screen render image not allowed")));
                 }
                 ImageCreationParams {
-                    format: vk::Format::D16_UNORM,
+                    format: format.vk_format(),
                     usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-                    aspect: vk::ImageAspectFlags::DEPTH,
+                    aspect: format.aspect_flags(),
                     view_type: vk::ImageViewType::TYPE_2D,
                     initialising_layout: vk::ImageLayout::UNDEFINED,
-                    expected_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    expected_access: ImageAccess::DepthAttachment,
                     layer_count: 1,
-                    host_visible: false
+                    host_updatable: false,
+                    sparse: false
                 }
             },
 
             // Typical initialised texture
-            (ImageUsage::TextureSampleOnly, TexturePixelFormat::Rgba) => {
+            (ImageUsage::TextureSampleOnly, format) if format.is_color_attachment_format() => {
                 if init_layer_data.is_none() {
                     return Err(EngineError::OpFailed(
                         String::from("Not initialising sample-only texture not allowed")));
                 }
                 ImageCreationParams {
-                    format: vk::Format::R8G8B8A8_UNORM,
-                    usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    initialising_layout: vk::ImageLayout::PREINITIALIZED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count: 1,
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Texture uploaded already compressed
+            (ImageUsage::TextureSampleOnly, format) if format.is_compressed() => {
+                if init_layer_data.is_none() {
+                    return Err(EngineError::OpFailed(
+                        String::from("Not initialising sample-only texture not allowed")));
+                }
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
                     aspect: vk::ImageAspectFlags::COLOR,
                     view_type: vk::ImageViewType::TYPE_2D,
                     initialising_layout: vk::ImageLayout::PREINITIALIZED,
-                    expected_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    expected_access: ImageAccess::ShaderRead,
                     layer_count: 1,
-                    host_visible: false
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Sky box written by compute shaders, such as when converted from another layout
+            (ImageUsage::Skybox, TexturePixelFormat::Rgba) if init_layer_data.is_none() => {
+                ImageCreationParams {
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    usage: vk::ImageUsageFlags::STORAGE |
+                        vk::ImageUsageFlags::SAMPLED |
+                        vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::CUBE,
+                    initialising_layout: vk::ImageLayout::UNDEFINED,
+                    expected_access: ImageAccess::StorageWrite,
+                    layer_count: 6,
+                    host_updatable: false,
+                    sparse: false
                 }
             },
 
             // Typical sky box (cube map)
-            (ImageUsage::Skybox, TexturePixelFormat::Rgba) => {
+            (ImageUsage::Skybox, TexturePixelFormat::Rgba | TexturePixelFormat::Srgba) => {
                 if init_layer_data.is_none() {
                     return Err(EngineError::OpFailed(
                         String::from("Not initialising cube map texture not allowed")));
                 }
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::CUBE,
+                    initialising_layout: vk::ImageLayout::PREINITIALIZED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count: 6,
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Large texture with tiles made resident on demand
+            (ImageUsage::SparseTexture, TexturePixelFormat::Rgba) => {
+                if init_layer_data.is_some() {
+                    return Err(EngineError::OpFailed(
+                        String::from("Initialising sparse texture not allowed; upload tiles")));
+                }
                 ImageCreationParams {
                     format: vk::Format::R8G8B8A8_UNORM,
                     usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
                     aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    initialising_layout: vk::ImageLayout::UNDEFINED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count: 1,
+                    host_updatable: false,
+                    sparse: true
+                }
+            },
+
+            // Texture written by compute shaders as a storage image, then sampled
+            (ImageUsage::ComputeWriteSampled, TexturePixelFormat::Rgba) => {
+                let initialising_layout = match init_layer_data {
+                    Some(_) => vk::ImageLayout::PREINITIALIZED,
+                    None => vk::ImageLayout::UNDEFINED
+                };
+                ImageCreationParams {
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    usage: vk::ImageUsageFlags::STORAGE |
+                        vk::ImageUsageFlags::SAMPLED |
+                        vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    initialising_layout,
+                    expected_access: ImageAccess::StorageWrite,
+                    layer_count: 1,
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Sky box uploaded already compressed
+            (ImageUsage::Skybox, format) if format.is_compressed() => {
+                if init_layer_data.is_none() {
+                    return Err(EngineError::OpFailed(
+                        String::from("Not initialising cube map texture not allowed")));
+                }
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
                     view_type: vk::ImageViewType::CUBE,
                     initialising_layout: vk::ImageLayout::PREINITIALIZED,
-                    expected_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    expected_access: ImageAccess::ShaderRead,
                     layer_count: 6,
-                    host_visible: false
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Array of same-sized textures, possibly compressed, with one layer per data item
+            (ImageUsage::TextureArray, format)
+                if format.is_compressed() ||
+                    matches!(format, TexturePixelFormat::Rgba | TexturePixelFormat::Srgba) => {
+                let layer_count = match init_layer_data {
+                    Some(layer_data) if !layer_data.is_empty() => layer_data.len() as u32,
+                    _ => {
+                        return Err(EngineError::OpFailed(
+                            String::from("Texture array must be initialised with its layers")));
+                    }
+                };
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D_ARRAY,
+                    initialising_layout: vk::ImageLayout::PREINITIALIZED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count,
+                    host_updatable: false,
+                    sparse: false
                 }
             },
 
+            // Volume texture, uploaded a slice at a time
+            (ImageUsage::VolumeTexture, TexturePixelFormat::Rgba | TexturePixelFormat::Srgba) => {
+                if init_layer_data.is_none() {
+                    return Err(EngineError::OpFailed(
+                        String::from("Not initialising volume texture not allowed")));
+                }
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_3D,
+                    initialising_layout: vk::ImageLayout::PREINITIALIZED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count: 1,
+                    host_updatable: false,
+                    sparse: false
+                }
+            },
+
+            // Texture rewritten from the host after creation; any initial data is uploaded the
+            // same way as later updates, once the image is ready for sampling
+            (ImageUsage::DynamicTexture, TexturePixelFormat::Rgba | TexturePixelFormat::Srgba) => {
+                ImageCreationParams {
+                    format: format.vk_format(),
+                    usage: vk::ImageUsageFlags::TRANSFER_DST |
+                        vk::ImageUsageFlags::TRANSFER_SRC |
+                        vk::ImageUsageFlags::SAMPLED,
+                    aspect: vk::ImageAspectFlags::COLOR,
+                    view_type: vk::ImageViewType::TYPE_2D,
+                    initialising_layout: vk::ImageLayout::UNDEFINED,
+                    expected_access: ImageAccess::ShaderRead,
+                    layer_count: 1,
+                    host_updatable: true,
+                    sparse: false
+                }
+            },
+
+            // Multisampled images have their own constructor
+            (ImageUsage::MsaaColorTarget, _) => {
+                return Err(EngineError::UserError(
+                    String::from("MSAA color targets must be created with new_multisampled")));
+            },
+
             // Unhandled cases
             _ => {
                 return Err(EngineError::OpFailed(
@@ -216,56 +824,783 @@ impl ImageWrapper {
             }
         };
 
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        if creation_params.sparse && !allocator.supports_sparse_textures() {
+            return Err(EngineError::Compatibility(
+                String::from("Sparse textures are not supported or were not declared")));
+        }
+
         let image = Self::make_image(
             context,
             width,
             height,
+            depth,
+            mip_levels,
+            vk::SampleCountFlags::TYPE_1,
             &creation_params)?;
 
-        let (allocator, transfer_queue) = context.get_mem_allocator();
+        if creation_params.sparse {
+            let sparse_backing = allocator.back_sparse_image(
+                &context.graphics_queue,
+                transfer_queue,
+                &image,
+                creation_params.aspect,
+                width,
+                height,
+                4,
+                creation_params.expected_access.layout())?;
+            let image_view = Self::make_image_view(
+                context,
+                image,
+                mip_levels,
+                &creation_params)?;
+            return Ok(ImageWrapper {
+                allocation: MemoryAllocation::null(),
+                sparse_backing: Some(sparse_backing),
+                aspect: creation_params.aspect,
+                usage: creation_params.usage,
+                view_type: creation_params.view_type,
+                extent: vk::Extent2D { width, height },
+                depth,
+                layer_count: creation_params.layer_count,
+                mip_levels,
+                samples: vk::SampleCountFlags::TYPE_1,
+                host_updatable: creation_params.host_updatable,
+                synchronization2: context.supports_synchronization2(),
+                current_state: Mutex::new(creation_params.expected_access.state()),
+                image,
+                image_view,
+                format: creation_params.format
+            });
+        }
+
+        let upload_after_creation = creation_params.host_updatable && init_layer_data.is_some();
         let allocation = allocator.back_image_memory(
             transfer_queue,
             &image,
             creation_params.aspect,
+            format,
             width,
             height,
-            init_layer_data,
+            depth,
+            mip_levels,
+            init_layer_data.filter(|_| !upload_after_creation),
             creation_params.initialising_layout,
-            creation_params.expected_layout)?;
+            creation_params.expected_access.layout())?;
 
         let image_view = Self::make_image_view(
             context,
             image,
+            mip_levels,
             &creation_params)?;
 
+        let image_wrapper = ImageWrapper {
+            allocation,
+            sparse_backing: None,
+            aspect: creation_params.aspect,
+            usage: creation_params.usage,
+            view_type: creation_params.view_type,
+            extent: vk::Extent2D { width, height },
+            depth,
+            layer_count: creation_params.layer_count,
+            mip_levels,
+            samples: vk::SampleCountFlags::TYPE_1,
+            host_updatable: creation_params.host_updatable,
+            synchronization2: context.supports_synchronization2(),
+            current_state: Mutex::new(creation_params.expected_access.state()),
+            image,
+            image_view,
+            format: creation_params.format
+        };
+        if let Some(layer_data) = init_layer_data.filter(|_| upload_after_creation) {
+            let uploaded = match layer_data {
+                [texels] => image_wrapper.update_region(context, 0, 0, width, height, texels),
+                _ => Err(EngineError::UserError(
+                    String::from("Host-updatable image must be initialised with one layer")))
+            };
+            if let Err(e) = uploaded {
+                image_wrapper.release(context);
+                return Err(e);
+            }
+        }
+        Ok(image_wrapper)
+    }
+
+    /// Create a new multisampled image for rendering into, with a sample count that the device
+    /// supports for framebuffers; see VkContext::get_msaa_sample_counts. Only MSAA color targets,
+    /// which must be RGBA, and depth buffers may be multisampled. Their contents are only meant
+    /// to live for the duration of a renderpass.
+    pub unsafe fn new_multisampled(
+        context: &VkContext,
+        usage: ImageUsage,
+        format: TexturePixelFormat,
+        width: u32,
+        height: u32,
+        samples: vk::SampleCountFlags
+    ) -> Result<ImageWrapper, EngineError> {
+        let vk_format = match (usage, format) {
+            (ImageUsage::MsaaColorTarget, format) if format.is_color_attachment_format() =>
+                format.vk_format(),
+            (
+                ImageUsage::DepthBuffer,
+                TexturePixelFormat::Unorm16 | TexturePixelFormat::Depth24Stencil8
+            ) => format.vk_format(),
+            _ => return Err(EngineError::UserError(
+                format!("Cannot create a multisampled {:?} image as {:?}", format, usage)))
+        };
+        Self::new_attachment(context, vk_format, width, height, samples)
+    }
+
+    /// Create a multisampled color or depth attachment in the given format, which may be one that
+    /// TexturePixelFormat does not cover, such as the swapchain's
+    pub(crate) unsafe fn new_attachment(
+        context: &VkContext,
+        vk_format: vk::Format,
+        width: u32,
+        height: u32,
+        samples: vk::SampleCountFlags
+    ) -> Result<ImageWrapper, EngineError> {
+        Self::new_transient_attachment(
+            context,
+            vk_format,
+            width,
+            height,
+            samples,
+            vk::ImageUsageFlags::empty())
+    }
+
+    /// Create a single-sampled color or depth attachment that later subpasses of the same
+    /// renderpass may read as an input attachment
+    pub(crate) unsafe fn new_input_attachment(
+        context: &VkContext,
+        vk_format: vk::Format,
+        width: u32,
+        height: u32
+    ) -> Result<ImageWrapper, EngineError> {
+        Self::new_transient_attachment(
+            context,
+            vk_format,
+            width,
+            height,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::INPUT_ATTACHMENT)
+    }
+
+    /// Create an attachment whose contents live only within a renderpass
+    unsafe fn new_transient_attachment(
+        context: &VkContext,
+        vk_format: vk::Format,
+        width: u32,
+        height: u32,
+        samples: vk::SampleCountFlags,
+        extra_usage: vk::ImageUsageFlags
+    ) -> Result<ImageWrapper, EngineError> {
+        let single_count = samples.as_raw().count_ones() == 1;
+        if !single_count || !context.get_msaa_sample_counts().contains(samples) {
+            return Err(EngineError::Compatibility(
+                format!("Sample count {:?} is not supported for attachments", samples)));
+        }
+        let (usage, aspect, expected_access) = match vk_format {
+            vk::Format::D16_UNORM => (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH,
+                ImageAccess::DepthAttachment
+            ),
+            vk::Format::D24_UNORM_S8_UINT => (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+                ImageAccess::DepthAttachment
+            ),
+            _ => (
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                vk::ImageAspectFlags::COLOR,
+                ImageAccess::ColorAttachment
+            )
+        };
+        let creation_params = ImageCreationParams {
+            format: vk_format,
+            usage: usage | extra_usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            aspect,
+            view_type: vk::ImageViewType::TYPE_2D,
+            initialising_layout: vk::ImageLayout::UNDEFINED,
+            expected_access,
+            layer_count: 1,
+            host_updatable: false,
+            sparse: false
+        };
+
+        let image = Self::make_image(context, width, height, 1, 1, samples, &creation_params)?;
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        let allocation = allocator.back_image_memory(
+            transfer_queue,
+            &image,
+            creation_params.aspect,
+            TexturePixelFormat::None,
+            width,
+            height,
+            1,
+            1,
+            None,
+            creation_params.initialising_layout,
+            creation_params.expected_access.layout())?;
+        let image_view = Self::make_image_view(context, image, 1, &creation_params)?;
+
         Ok(ImageWrapper {
             allocation,
+            sparse_backing: None,
+            aspect: creation_params.aspect,
+            usage: creation_params.usage,
+            view_type: creation_params.view_type,
+            extent: vk::Extent2D { width, height },
+            depth: 1,
+            layer_count: 1,
+            mip_levels: 1,
+            samples,
+            host_updatable: false,
+            synchronization2: context.supports_synchronization2(),
+            current_state: Mutex::new(creation_params.expected_access.state()),
             image,
             image_view,
             format: creation_params.format
         })
     }
 
+    /// Create a color image that is only ever copied into and blitted from, in the given format,
+    /// which may be one that TexturePixelFormat does not cover, such as the swapchain's. It is
+    /// left ready to be read by transfers.
+    pub(crate) unsafe fn new_transfer_image(
+        context: &VkContext,
+        vk_format: vk::Format,
+        width: u32,
+        height: u32
+    ) -> Result<ImageWrapper, EngineError> {
+        let creation_params = ImageCreationParams {
+            format: vk_format,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+            aspect: vk::ImageAspectFlags::COLOR,
+            view_type: vk::ImageViewType::TYPE_2D,
+            initialising_layout: vk::ImageLayout::UNDEFINED,
+            expected_access: ImageAccess::TransferRead,
+            layer_count: 1,
+            host_updatable: false,
+            sparse: false
+        };
+
+        let image = Self::make_image(
+            context,
+            width,
+            height,
+            1,
+            1,
+            vk::SampleCountFlags::TYPE_1,
+            &creation_params)?;
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        let backed = allocator.back_image_memory(
+            transfer_queue,
+            &image,
+            creation_params.aspect,
+            TexturePixelFormat::None,
+            width,
+            height,
+            1,
+            1,
+            None,
+            creation_params.initialising_layout,
+            creation_params.expected_access.layout());
+        let allocation = match backed {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                context.device.destroy_image(image, None);
+                return Err(e);
+            }
+        };
+        let image_view = match Self::make_image_view(context, image, 1, &creation_params) {
+            Ok(image_view) => image_view,
+            Err(e) => {
+                let _ = allocator.destroy_image(image, &allocation);
+                return Err(e);
+            }
+        };
+
+        Ok(ImageWrapper {
+            allocation,
+            sparse_backing: None,
+            aspect: creation_params.aspect,
+            usage: creation_params.usage,
+            view_type: creation_params.view_type,
+            extent: vk::Extent2D { width, height },
+            depth: 1,
+            layer_count: 1,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            host_updatable: false,
+            synchronization2: context.supports_synchronization2(),
+            current_state: Mutex::new(creation_params.expected_access.state()),
+            image,
+            image_view,
+            format: creation_params.format
+        })
+    }
+
+    /// Whether this image can be moved to a new allocation by defragmentation; sparse images,
+    /// and images that cannot be copied from, stay where they are
+    pub fn is_movable(&self) -> bool {
+        self.image != vk::Image::null() &&
+            self.sparse_backing.is_none() &&
+            self.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    /// Where the memory of this image lives, if defragmentation can move it
+    pub(crate) fn movable_location(&self) -> Option<MemoryLocation> {
+        match self.is_movable() {
+            true => Some(self.allocation.location()),
+            false => None
+        }
+    }
+
+    /// Move this image into a new image bound at the given place in a memory block, copying
+    /// every mip level and layer across on the graphics queue and leaving it in the same layout,
+    /// then destroy the old image and its view. The device must not be using the image. Any
+    /// copies of the old image or view handles held elsewhere, such as in descriptor sets, are
+    /// invalidated. If anything fails, the old image is left as it was.
+    pub(crate) unsafe fn relocate_to(
+        &mut self,
+        context: &VkContext,
+        to: MemoryLocation
+    ) -> Result<(), EngineError> {
+        if !self.is_movable() {
+            return Err(EngineError::OpFailed(String::from("Image cannot be relocated")));
+        }
+        let creation_params = ImageCreationParams {
+            format: self.format,
+            usage: self.usage,
+            aspect: self.aspect,
+            view_type: self.view_type,
+            initialising_layout: vk::ImageLayout::UNDEFINED,
+            expected_access: ImageAccess::Undefined,
+            layer_count: self.layer_count,
+            host_updatable: self.host_updatable,
+            sparse: false
+        };
+        let image = Self::make_image(
+            context,
+            self.extent.width,
+            self.extent.height,
+            self.depth,
+            self.mip_levels,
+            self.samples,
+            &creation_params)?;
+        let (allocator, _) = context.get_mem_allocator();
+        let allocation = match allocator.bind_relocated_image(image, to) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                context.device.destroy_image(image, None);
+                return Err(e);
+            }
+        };
+
+        // Contents of an image never yet used are undefined, so there is nothing to copy
+        let state = self.get_state();
+        let copied = match state.layout {
+            vk::ImageLayout::UNDEFINED => Ok(()),
+            _ => context.run_one_time_commands(
+                CommandQueue::Graphics,
+                |device, command_buffer| {
+                    self.record_relocation_copy(device, command_buffer, image, &state);
+                    Ok(())
+                })
+        };
+        let image_view = copied.and_then(|_| {
+            Self::make_image_view(context, image, self.mip_levels, &creation_params)
+        });
+        let image_view = match image_view {
+            Ok(image_view) => image_view,
+            Err(e) => {
+                let _ = allocator.destroy_image(image, &allocation);
+                return Err(e);
+            }
+        };
+
+        // Swap the new image in, and make the next use wait on the copy into it
+        context.device.destroy_image_view(self.image_view, None);
+        let old_image = std::mem::replace(&mut self.image, image);
+        let old_allocation = std::mem::replace(&mut self.allocation, allocation);
+        self.image_view = image_view;
+        if state.layout != vk::ImageLayout::UNDEFINED {
+            self.assume_state(ImageState::new(
+                state.layout,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE));
+        }
+        allocator.finish_image_relocation(old_image, &old_allocation, &self.allocation)
+    }
+
+    /// Record the copy of every mip level and layer of this image, in the given state, into a
+    /// new unused image of the same description, leaving the new image in the same layout
+    unsafe fn record_relocation_copy(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        new_image: vk::Image,
+        state: &ImageState
+    ) {
+        let subresource_range = self.get_subresource_range();
+        let to_transfer_barriers = [
+            vk::ImageMemoryBarrier::builder()
+                .image(self.image)
+                .src_access_mask(state.access_mask)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(state.layout)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build(),
+            vk::ImageMemoryBarrier::builder()
+                .image(new_image)
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build()
+        ];
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            state.stage_mask | vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer_barriers);
+
+        let regions: Vec<vk::ImageCopy> = (0..self.mip_levels)
+            .map(|level| {
+                let (width, height) =
+                    mip_level_extent(self.extent.width, self.extent.height, level);
+                let layers = vk::ImageSubresourceLayers {
+                    aspect_mask: self.aspect,
+                    mip_level: level,
+                    base_array_layer: 0,
+                    layer_count: self.layer_count
+                };
+                vk::ImageCopy {
+                    src_subresource: layers,
+                    src_offset: vk::Offset3D::default(),
+                    dst_subresource: layers,
+                    dst_offset: vk::Offset3D::default(),
+                    extent: vk::Extent3D { width, height, depth: (self.depth >> level).max(1) }
+                }
+            })
+            .collect();
+        device.cmd_copy_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions);
+
+        let to_layout_barrier = vk::ImageMemoryBarrier::builder()
+            .image(new_image)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(state.layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(subresource_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_layout_barrier]);
+    }
+
+    /// Name the memory backing this image, for identifying it in allocation leak reports
+    pub fn set_debug_name(&self, context: &VkContext, name: &str) {
+        let (allocator, _) = context.get_mem_allocator();
+        allocator.set_allocation_name(&self.allocation, name);
+    }
+
+    /// Get the width and height of the image's top mip level
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    /// Get the layout, stages and accesses that last used this image, as far as recorded
+    /// transitions are concerned
+    pub fn get_state(&self) -> ImageState {
+        *self.current_state.lock().unwrap()
+    }
+
+    /// Get the layout this image is in, as far as recorded transitions are concerned
+    pub fn get_layout(&self) -> vk::ImageLayout {
+        self.get_state().layout
+    }
+
+    /// Record that the image is now in the layout for the given access without recording a
+    /// barrier, such as after a renderpass whose final layout moved it there
+    pub fn assume_access(&self, access: ImageAccess) {
+        *self.current_state.lock().unwrap() = access.state();
+    }
+
+    /// Record that the image is now in the given state without recording a barrier, such as
+    /// after a queue family ownership transfer that moved it there
+    pub(crate) fn assume_state(&self, state: ImageState) {
+        *self.current_state.lock().unwrap() = state;
+    }
+
+    /// The whole of the image: every mip level and layer of its aspect
+    pub(crate) fn get_subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect,
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.layer_count
+        }
+    }
+
+    /// Record a barrier into the command buffer moving this image from the way it was last
+    /// accessed to the way it is about to be, with layouts and masks worked out from both.
+    /// Returns false if no barrier was needed. The tracked state is updated as recording goes,
+    /// so command buffers must be submitted in the order that they were recorded. The barrier is
+    /// recorded with synchronization2 where the image's context supports it.
+    pub unsafe fn transition(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        next_access: ImageAccess
+    ) -> bool {
+        self.transition_to(
+            device,
+            command_buffer,
+            next_access.layout(),
+            next_access.stage_mask(),
+            next_access.access_mask())
+    }
+
+    /// Record a barrier into the command buffer moving this image into the given layout, ready
+    /// for the given stages to make the given accesses. Use this for accesses that ImageAccess
+    /// does not describe; otherwise it behaves the same as transition.
+    pub unsafe fn transition_to(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        stage_mask: vk::PipelineStageFlags,
+        access_mask: vk::AccessFlags
+    ) -> bool {
+        let next_state = ImageState::new(layout, stage_mask, access_mask);
+        let mut current_state = self.current_state.lock().unwrap();
+        if !current_state.needs_barrier_to(&next_state) {
+            *current_state = current_state.merged_with(&next_state);
+            return false;
+        }
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .image(self.image)
+            .src_access_mask(current_state.access_mask)
+            .dst_access_mask(next_state.access_mask)
+            .old_layout(current_state.layout)
+            .new_layout(next_state.layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(self.get_subresource_range())
+            .build();
+        record_image_barriers(
+            device,
+            command_buffer,
+            self.synchronization2,
+            current_state.stage_mask,
+            next_state.stage_mask,
+            &[barrier]);
+        *current_state = next_state;
+        true
+    }
+
+    /// Get the sparse memory bookkeeping for this image, if it was created as a sparse texture;
+    /// this reports the tile size and which tiles are resident
+    pub fn get_sparse_backing(&self) -> Option<&SparseImageBacking> {
+        self.sparse_backing.as_ref()
+    }
+
+    /// Page in one tile of a sparse texture, optionally filling it with texel data. Returns false
+    /// if the tile was already resident, in which case any data given is still uploaded. Must be
+    /// called from the thread that submits rendering, since binding uses the graphics queue.
+    pub unsafe fn make_tile_resident(
+        &self,
+        context: &VkContext,
+        tile_x: u32,
+        tile_y: u32,
+        data: Option<&[u8]>
+    ) -> Result<bool, EngineError> {
+        let Some(backing) = &self.sparse_backing else {
+            return Err(EngineError::OpFailed(String::from("Image is not a sparse texture")));
+        };
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        let newly_resident = allocator.make_sparse_tile_resident(
+            &context.graphics_queue,
+            &self.image,
+            backing,
+            tile_x,
+            tile_y)?;
+        if let Some(data) = data {
+            allocator.upload_sparse_tile(
+                &context.graphics_queue,
+                transfer_queue,
+                &self.image,
+                backing,
+                tile_x,
+                tile_y,
+                data)?;
+        }
+        Ok(newly_resident)
+    }
+
+    /// Page out one tile of a sparse texture, freeing its memory. Returns false if the tile was
+    /// not resident. No submitted rendering may still be sampling the tile.
+    pub unsafe fn evict_tile(
+        &self,
+        context: &VkContext,
+        tile_x: u32,
+        tile_y: u32
+    ) -> Result<bool, EngineError> {
+        let Some(backing) = &self.sparse_backing else {
+            return Err(EngineError::OpFailed(String::from("Image is not a sparse texture")));
+        };
+        let (allocator, _) = context.get_mem_allocator();
+        allocator.evict_sparse_tile(
+            &context.graphics_queue,
+            &self.image,
+            backing,
+            tile_x,
+            tile_y)
+    }
+
+    /// Rewrite a rectangle of a host-updatable image, such as a dynamic texture, with
+    /// tightly-packed texel data covering exactly that rectangle. The data is copied through a
+    /// staging buffer on the transfer queue, moving the image to a transfer layout and back, and
+    /// the graphics queue has re-acquired the image by the time this returns. No submitted
+    /// rendering may still be sampling the image. To update every frame, keep one texture per
+    /// swapchain image and update the one for the image being prepared.
+    pub unsafe fn update_region(
+        &self,
+        context: &VkContext,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8]
+    ) -> Result<(), EngineError> {
+        if !self.host_updatable {
+            return Err(EngineError::UserError(
+                String::from("Only host-updatable images can be updated after creation")));
+        }
+        let fits = x.checked_add(width).is_some_and(|right| right <= self.extent.width) &&
+            y.checked_add(height).is_some_and(|bottom| bottom <= self.extent.height);
+        if width == 0 || height == 0 || !fits {
+            return Err(EngineError::UserError(format!(
+                "Region {}x{} at ({}, {}) does not fit in {}x{} image",
+                width,
+                height,
+                x,
+                y,
+                self.extent.width,
+                self.extent.height)));
+        }
+        let bytes_per_texel = match self.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => 4,
+            _ => {
+                return Err(EngineError::OpFailed(
+                    format!("Updating images of format {:?} not supported", self.format)));
+            }
+        };
+        let expected_size = width as usize * height as usize * bytes_per_texel;
+        if data.len() != expected_size {
+            return Err(EngineError::UserError(
+                format!("Region data is {} bytes, expected {}", data.len(), expected_size)));
+        }
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        allocator.upload_image_region(
+            &context.graphics_queue,
+            transfer_queue,
+            &self.image,
+            self.aspect,
+            self.get_layout(),
+            vk::Offset3D { x: x as i32, y: y as i32, z: 0 },
+            vk::Extent3D { width, height, depth: 1 },
+            data)?;
+        allocator.acquire_released_resources(&context.graphics_queue)?;
+        Ok(())
+    }
+
+    /// Copy the image's contents back to host memory, such as for screenshots or picking. Only
+    /// images with off-screen render usage support this. The image is expected to be in its
+    /// tracked layout, and is returned to it afterwards.
+    pub unsafe fn read_to_host(
+        &self,
+        context: &VkContext,
+        width: u32,
+        height: u32
+    ) -> Result<Vec<u8>, EngineError> {
+        let (aspect, bytes_per_pixel) = match self.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB |
+                vk::Format::A2B10G10R10_UNORM_PACK32 => (vk::ImageAspectFlags::COLOR, 4),
+            vk::Format::R16G16B16A16_SFLOAT => (vk::ImageAspectFlags::COLOR, 8),
+            _ => {
+                return Err(EngineError::OpFailed(
+                    format!("Reading back images of format {:?} not supported", self.format)));
+            }
+        };
+        let (allocator, transfer_queue) = context.get_mem_allocator();
+        allocator.transfer_image_to_host(
+            &context.graphics_queue,
+            transfer_queue,
+            &self.image,
+            aspect,
+            self.get_layout(),
+            width,
+            height,
+            bytes_per_pixel)
+    }
+
     /// Create the image
     unsafe fn make_image(
         context: &VkContext,
         width: u32,
         height: u32,
+        depth: u32,
+        mip_levels: u32,
+        samples: vk::SampleCountFlags,
         creation_params: &ImageCreationParams
     ) -> Result<vk::Image, EngineError> {
-        let extent3d = vk::Extent3D { width, height, depth: 1 };
-        let flags = match creation_params.view_type {
+        let extent3d = vk::Extent3D { width, height, depth };
+        let image_type = match creation_params.view_type {
+            vk::ImageViewType::TYPE_3D => vk::ImageType::TYPE_3D,
+            _ => vk::ImageType::TYPE_2D
+        };
+        let mut flags = match creation_params.view_type {
             vk::ImageViewType::CUBE => vk::ImageCreateFlags::CUBE_COMPATIBLE,
             _ => vk::ImageCreateFlags::empty()
         };
+        if creation_params.sparse {
+            flags |= vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY;
+        }
         let image_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
+            .image_type(image_type)
             .flags(flags)
             .format(creation_params.format)
             .extent(extent3d)
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .array_layers(creation_params.layer_count)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(creation_params.usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
@@ -283,12 +1618,13 @@ impl ImageWrapper {
     unsafe fn make_image_view(
         context: &VkContext,
         image: vk::Image,
+        mip_levels: u32,
         creation_params: &ImageCreationParams
     ) -> Result<vk::ImageView, EngineError> {
         let subresource_range = vk::ImageSubresourceRange::builder()
             .aspect_mask(creation_params.aspect)
             .base_mip_level(0)
-            .level_count(1)
+            .level_count(mip_levels)
             .base_array_layer(0)
             .layer_count(creation_params.layer_count);
         let image_view_create_info = vk::ImageViewCreateInfo::builder()
@@ -1,8 +1,18 @@
 pub mod buffer;
+pub mod container;
+pub mod cubemap;
+pub(crate) mod glsl;
 pub mod image;
+pub mod sampler;
+pub mod shared;
+pub mod streaming;
 pub mod util;
 
-use crate::VkContext;
+use crate::{
+    VkContext, BindlessTextureSet, DRAW_METADATA_BINDING, MATERIAL_INDEX_PUSH_CONSTANT_SIZE,
+    MATERIAL_INDEX_PUSH_CONSTANT_STAGES, INPUT_ATTACHMENT_FIRST_BINDING, LIGHT_LIST_BINDING,
+    LIGHT_CLUSTER_BINDING
+};
 use ecs::{EcsManager, Handle, resource::Resource};
 use error::EngineError;
 use ash::vk;
@@ -12,13 +22,24 @@ use ash::vk;
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum ShaderStage {
     Vertex,
-    Fragment
+    Fragment,
+    Compute
+}
+
+/// ShaderSource enum
+/// The code for a shader: either SPIR-V, usually compiled at build time with include_glsl, or
+/// GLSL source supplied at runtime, such as by tools or mods. GLSL is compiled when the shader
+/// module is created, which needs the runtime-shaders feature.
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+    SpirV(&'static [u32]),
+    Glsl(String)
 }
 
 /// ShaderCreationData struct
 /// Information needed to prepare a reusable shader ahead of time
 pub struct ShaderCreationData {
-    pub data: &'static [u32],
+    pub source: ShaderSource,
     pub stage: ShaderStage
 }
 
@@ -31,15 +52,26 @@ pub enum UboUsage {
 }
 
 /// DescriptorSetLayoutCreationData struct
-/// Information needed to describe a descriptor set layout
+/// Information needed to describe a descriptor set layout. A dynamic UBO is bound with a
+/// per-object offset, for pipelines created with a UBO object count. Draw metadata adds a storage
+/// buffer at DRAW_METADATA_BINDING, for pipelines created with a draw metadata binding. Input
+/// attachments, read by the fragment shader in a subpass of a custom renderpass, are added from
+/// INPUT_ATTACHMENT_FIRST_BINDING. Light clusters add the storage buffers of ClusteredLights,
+/// read by the fragment shader, at LIGHT_LIST_BINDING and LIGHT_CLUSTER_BINDING.
 pub struct DescriptorSetLayoutCreationData {
-    pub ubo_usage: UboUsage
+    pub ubo_usage: UboUsage,
+    pub dynamic_ubo: bool,
+    pub draw_metadata: bool,
+    pub input_attachment_count: u32,
+    pub light_clusters: bool
 }
 
 /// PipelineLayoutCreationData struct
-/// Information needed to describe a pipeline layout
+/// Information needed to describe a pipeline layout. With a bindless texture array, looked up by
+/// index, the array is added as BINDLESS_TEXTURE_SET along with the material index push constant.
 pub struct PipelineLayoutCreationData {
-    pub descriptor_set_layout_index: u32
+    pub descriptor_set_layout_index: u32,
+    pub bindless_texture_set_index: Option<u32>
 }
 
 impl Resource<VkContext, > for vk::ShaderModule {
@@ -50,9 +82,17 @@ impl Resource<VkContext, > for vk::ShaderModule {
         _ecs: &EcsManager<VkContext>,
         data: &ShaderCreationData
     ) -> Result<Self, EngineError> {
+        let compiled_code;
+        let code = match &data.source {
+            ShaderSource::SpirV(code) => *code,
+            ShaderSource::Glsl(source) => {
+                compiled_code = glsl::compile_glsl(source, data.stage)?;
+                compiled_code.as_slice()
+            }
+        };
         unsafe {
             let shader_create_info = vk::ShaderModuleCreateInfo::builder()
-                .code(data.data);
+                .code(code);
             loader.device
                 .create_shader_module(&shader_create_info, None)
                 .map_err(|e| EngineError::OpFailed(format!("{:?}", e)))
@@ -74,16 +114,27 @@ impl Resource<VkContext> for vk::DescriptorSetLayout {
         _ecs: &EcsManager<VkContext>,
         data: &DescriptorSetLayoutCreationData
     ) -> Result<Self, EngineError> {
+        if data.light_clusters &&
+            INPUT_ATTACHMENT_FIRST_BINDING + data.input_attachment_count > LIGHT_LIST_BINDING
+        {
+            return Err(EngineError::UserError(format!(
+                "{} input attachments overlap the light cluster bindings",
+                data.input_attachment_count)));
+        }
         let ubo_stage_flags = match data.ubo_usage {
             UboUsage::VertexShaderRead =>
                 vk::ShaderStageFlags::VERTEX,
             UboUsage::VertexAndFragmentShaderRead =>
                 vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
         };
+        let ubo_descriptor_type = match data.dynamic_ubo {
+            true => vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            false => vk::DescriptorType::UNIFORM_BUFFER
+        };
         let descriptor_set_layout_binding_infos: Vec<vk::DescriptorSetLayoutBinding> = {
             let mut bindings = vec![vk::DescriptorSetLayoutBinding::builder()
                 .binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(ubo_descriptor_type)
                 .descriptor_count(1)
                 .stage_flags(ubo_stage_flags)
                 .build()];
@@ -94,6 +145,32 @@ impl Resource<VkContext> for vk::DescriptorSetLayout {
                 .descriptor_count(1)
                 .stage_flags(vk::ShaderStageFlags::FRAGMENT)
                 .build());
+            if data.draw_metadata {
+                bindings.push(vk::DescriptorSetLayoutBinding::builder()
+                    .binding(DRAW_METADATA_BINDING)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                    .build());
+            }
+            for index in 0..data.input_attachment_count {
+                bindings.push(vk::DescriptorSetLayoutBinding::builder()
+                    .binding(INPUT_ATTACHMENT_FIRST_BINDING + index)
+                    .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build());
+            }
+            if data.light_clusters {
+                for binding in [LIGHT_LIST_BINDING, LIGHT_CLUSTER_BINDING] {
+                    bindings.push(vk::DescriptorSetLayoutBinding::builder()
+                        .binding(binding)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                        .build());
+                }
+            }
             bindings
         };
         let descriptor_set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
@@ -110,6 +187,7 @@ impl Resource<VkContext> for vk::DescriptorSetLayout {
 
     fn release(&self, loader: &VkContext) {
         unsafe {
+            loader.forget_descriptor_set_layout(*self);
             loader.device.destroy_descriptor_set_layout(*self, None);
         }
     }
@@ -127,9 +205,24 @@ impl Resource<VkContext> for vk::PipelineLayout {
             .get_item::<vk::DescriptorSetLayout>(
                 Handle::for_resource(data.descriptor_set_layout_index))
             .unwrap();
-        let pipeline_descriptor_layouts = [*descriptor_set_layout];
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        let mut pipeline_descriptor_layouts = vec![*descriptor_set_layout];
+        if let Some(bindless_index) = data.bindless_texture_set_index {
+            let texture_set = ecs
+                .get_item::<BindlessTextureSet>(Handle::for_resource(bindless_index))
+                .ok_or_else(|| EngineError::MissingResource(
+                    format!("No bindless texture array at index {}", bindless_index)))?;
+            pipeline_descriptor_layouts.push(texture_set.get_descriptor_set_layout());
+        }
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: MATERIAL_INDEX_PUSH_CONSTANT_STAGES,
+            offset: 0,
+            size: MATERIAL_INDEX_PUSH_CONSTANT_SIZE
+        }];
+        let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
             .set_layouts(&pipeline_descriptor_layouts);
+        if data.bindless_texture_set_index.is_some() {
+            pipeline_layout_info = pipeline_layout_info.push_constant_ranges(&push_constant_ranges);
+        }
         let pipeline_layout = unsafe {
             loader.device
                 .create_pipeline_layout(&pipeline_layout_info, None)
@@ -0,0 +1,180 @@
+use crate::{VkContext, mip_level_extent};
+use ecs::{EcsManager, resource::Resource};
+use error::EngineError;
+use ash::vk;
+
+/// TextureQuality struct
+/// Global settings applied to every sampler when it is created, so that a texture quality setting
+/// can reduce detail without reloading textures or editing materials. The LOD bias is added to
+/// each sampler's own bias, and the resolution limit stops sampling from any mip levels larger
+/// than it, for samplers that know the size of the textures they sample.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct TextureQuality {
+    pub lod_bias: f32,
+    pub max_resolution: Option<u32>
+}
+
+/// SamplerCreationData struct
+/// Information needed to create a sampler, shared by any pipelines that reference it. Anisotropy
+/// is clamped to what the device allows, and ignored unless SamplerAnisotropy was declared and
+/// is supported. A compare op makes this a comparison sampler, such as for shadow maps. The
+/// texture extent is only needed for TextureQuality's resolution limit to apply.
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerCreationData {
+    pub min_filter: vk::Filter,
+    pub mag_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub max_anisotropy: Option<f32>,
+    pub compare_op: Option<vk::CompareOp>,
+    pub lod_bias: f32,
+    pub texture_extent: Option<(u32, u32)>
+}
+
+impl Default for SamplerCreationData {
+
+    /// Linear filtering between texels and mip levels, with repeating texture coordinates
+    fn default() -> Self {
+        Self {
+            min_filter: vk::Filter::LINEAR,
+            mag_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            compare_op: None,
+            lod_bias: 0.0,
+            texture_extent: None
+        }
+    }
+}
+
+impl SamplerCreationData {
+
+    /// Set the same addressing mode in all directions
+    pub fn with_address_mode(self, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            ..self
+        }
+    }
+
+    /// Request anisotropic filtering, up to the given number of samples, which keeps textures
+    /// viewed at oblique angles (such as terrain) sharp without shimmering
+    pub fn with_anisotropy(self, max_anisotropy: f32) -> Self {
+        Self {
+            max_anisotropy: Some(max_anisotropy),
+            ..self
+        }
+    }
+
+    /// The anisotropy to actually use, given the device's limit, if any
+    pub fn effective_anisotropy(&self, device_max_anisotropy: Option<f32>) -> Option<f32> {
+        match (self.max_anisotropy, device_max_anisotropy) {
+            (Some(requested), Some(device_max)) if requested > 1.0 =>
+                Some(requested.min(device_max)),
+            _ => None
+        }
+    }
+
+    /// The LOD bias to actually use, combining this sampler's bias with the global one and
+    /// clamping to the device's limit
+    pub fn effective_lod_bias(&self, quality: &TextureQuality, device_max_lod_bias: f32) -> f32 {
+        (self.lod_bias + quality.lod_bias).clamp(-device_max_lod_bias, device_max_lod_bias)
+    }
+
+    /// The lowest LOD that may be sampled, which skips the mip levels larger than the global
+    /// resolution limit
+    pub fn min_lod(&self, quality: &TextureQuality) -> f32 {
+        let limit_and_extent = (quality.max_resolution, self.texture_extent);
+        let (max_resolution, (width, height)) = match limit_and_extent {
+            (Some(max_resolution), Some(extent)) => (max_resolution, extent),
+            _ => return 0.0
+        };
+        let mut level = 0;
+        loop {
+            let (level_width, level_height) = mip_level_extent(width, height, level);
+            if level_width.max(level_height) <= max_resolution.max(1) {
+                return level as f32;
+            }
+            level += 1;
+        }
+    }
+}
+
+/// SamplerWrapper struct
+/// A sampler kept along with the data it was created from, so that it can be rebuilt when the
+/// global texture quality changes
+pub struct SamplerWrapper {
+    sampler: vk::Sampler,
+    creation_data: SamplerCreationData
+}
+
+impl SamplerWrapper {
+
+    pub fn get_sampler(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    /// Replace the sampler with one using the loader's current texture quality. Nothing may be
+    /// using the old sampler, and any descriptor sets referring to it must be written again.
+    pub fn recreate(&mut self, loader: &VkContext) -> Result<(), EngineError> {
+        let sampler = Self::create_sampler(loader, &self.creation_data)?;
+        self.release(loader);
+        self.sampler = sampler;
+        Ok(())
+    }
+
+    fn create_sampler(
+        loader: &VkContext,
+        data: &SamplerCreationData
+    ) -> Result<vk::Sampler, EngineError> {
+        let quality = loader.get_texture_quality();
+        let anisotropy = data.effective_anisotropy(loader.get_max_sampler_anisotropy());
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .min_filter(data.min_filter)
+            .mag_filter(data.mag_filter)
+            .mipmap_mode(data.mipmap_mode)
+            .address_mode_u(data.address_mode_u)
+            .address_mode_v(data.address_mode_v)
+            .address_mode_w(data.address_mode_w)
+            .mip_lod_bias(data.effective_lod_bias(&quality, loader.get_max_sampler_lod_bias()))
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .compare_enable(data.compare_op.is_some())
+            .compare_op(data.compare_op.unwrap_or(vk::CompareOp::NEVER))
+            .min_lod(data.min_lod(&quality))
+            .max_lod(vk::LOD_CLAMP_NONE);
+        unsafe {
+            loader.device
+                .create_sampler(&sampler_info, None)
+                .map_err(|e| EngineError::OpFailed(format!("Error creating sampler: {:?}", e)))
+        }
+    }
+}
+
+impl Resource<VkContext> for SamplerWrapper {
+    type CreationData = SamplerCreationData;
+
+    fn create(
+        loader: &VkContext,
+        _ecs: &EcsManager<VkContext>,
+        data: &SamplerCreationData
+    ) -> Result<Self, EngineError> {
+        Ok(Self {
+            sampler: Self::create_sampler(loader, data)?,
+            creation_data: *data
+        })
+    }
+
+    fn release(&self, loader: &VkContext) {
+        unsafe {
+            loader.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
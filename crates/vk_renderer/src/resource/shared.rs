@@ -0,0 +1,78 @@
+use crate::{VkContext, ImageWrapper, ImageUsage, TexturePixelFormat};
+use error::EngineError;
+use ash::vk;
+
+/// SharedImage struct
+/// An image's texels copied to host memory, for passing results between contexts that share no
+/// device, such as from a context on a secondary adapter baking assets in the background to the
+/// one rendering to the window; see VkCore::new_headless_on_adapter. Read it from an image on
+/// one context, then create an image from it on another or write it into an existing one.
+#[derive(Clone, Debug)]
+pub struct SharedImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: TexturePixelFormat,
+    pub texels: Vec<u8>
+}
+
+impl SharedImage {
+
+    /// Copy an image's contents back from its context; see ImageWrapper::read_to_host for which
+    /// images support this. Rendering into the image must have been waited on.
+    pub unsafe fn read_from(
+        context: &VkContext,
+        image: &ImageWrapper
+    ) -> Result<Self, EngineError> {
+        let format = Self::format_of(image.format)?;
+        let extent = image.get_extent();
+        let texels = image.read_to_host(context, extent.width, extent.height)?;
+        Ok(Self { width: extent.width, height: extent.height, format, texels })
+    }
+
+    /// Create a new image on the given context holding these texels, such as a sample-only
+    /// texture of a baked result
+    pub unsafe fn upload_to(
+        &self,
+        context: &VkContext,
+        usage: ImageUsage
+    ) -> Result<ImageWrapper, EngineError> {
+        ImageWrapper::new(
+            context,
+            usage,
+            self.format,
+            self.width,
+            self.height,
+            Some(std::slice::from_ref(&self.texels)))
+    }
+
+    /// Overwrite an existing image of the same size with these texels, which needs a
+    /// host-visible image; see ImageWrapper::update_region
+    pub unsafe fn write_into(
+        &self,
+        context: &VkContext,
+        image: &ImageWrapper
+    ) -> Result<(), EngineError> {
+        let extent = image.get_extent();
+        if extent.width != self.width || extent.height != self.height {
+            return Err(EngineError::UserError(format!(
+                "Shared image is {}x{}, but the image written into is {}x{}",
+                self.width,
+                self.height,
+                extent.width,
+                extent.height)));
+        }
+        image.update_region(context, 0, 0, self.width, self.height, &self.texels)
+    }
+
+    /// The texel format of images in the given Vulkan format that can be read back
+    fn format_of(format: vk::Format) -> Result<TexturePixelFormat, EngineError> {
+        match format {
+            vk::Format::R8G8B8A8_UNORM => Ok(TexturePixelFormat::Rgba),
+            vk::Format::R8G8B8A8_SRGB => Ok(TexturePixelFormat::Srgba),
+            vk::Format::R16G16B16A16_SFLOAT => Ok(TexturePixelFormat::RgbaF16),
+            vk::Format::A2B10G10R10_UNORM_PACK32 => Ok(TexturePixelFormat::Rgb10A2),
+            _ => Err(EngineError::OpFailed(
+                format!("Sharing images of format {:?} not supported", format)))
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{VkContext, ImageWrapper, TextureCreationData, MemoryStatistics, mip_level_extent};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use std::sync::{Arc, Mutex};
+
+/// StreamedTextureData struct
+/// Information needed to create a streamed texture. The full texture, with every mip level, is
+/// kept in host memory so that levels can be made resident and evicted again as needed. The
+/// given number of the smallest levels always stay resident.
+pub struct StreamedTextureData {
+    pub source: Arc<TextureCreationData>,
+    pub min_resident_levels: u32
+}
+
+/// StreamingRequest struct
+/// The detail a streamed texture would like to have, as the most detailed mip level wanted, and
+/// how important it is compared to other streamed textures
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StreamingRequest {
+    pub first_level: u32,
+    pub priority: f32
+}
+
+/// StagedLevels struct
+/// An image holding a new range of a streamed texture's mip levels, whose upload through the
+/// transfer queue completes once the transfer timeline reaches the given value
+struct StagedLevels {
+    image: ImageWrapper,
+    first_level: u32,
+    upload_value: u64
+}
+
+/// StreamedTexture struct
+/// A texture of which only a range of mip levels, down to the smallest, is resident at any time.
+/// Scenes request the detail they want while preparing frames, such as from how large the
+/// texture appears on screen, and a TextureStreamer decides what is actually resident. Pipelines
+/// can sample these in place of ImageWrappers.
+pub struct StreamedTexture {
+    source: Arc<TextureCreationData>,
+    image: ImageWrapper,
+    first_resident_level: u32,
+    coarsest_first_level: u32,
+    staged: Option<StagedLevels>,
+    request: Mutex<StreamingRequest>
+}
+
+impl Resource<VkContext> for StreamedTexture {
+    type CreationData = StreamedTextureData;
+
+    fn create(
+        loader: &VkContext,
+        _ecs: &EcsManager<VkContext>,
+        data: &StreamedTextureData
+    ) -> Result<Self, EngineError> {
+        if data.source.layer_data.is_none() {
+            return Err(EngineError::UserError(
+                "Streamed texture created without any texture data".to_owned()));
+        }
+        let coarsest_first_level = data.source.mip_levels
+            - data.min_resident_levels.clamp(1, data.source.mip_levels);
+        let image = unsafe { Self::make_image(loader, &data.source, coarsest_first_level)? };
+        Ok(Self {
+            source: data.source.clone(),
+            image,
+            first_resident_level: coarsest_first_level,
+            coarsest_first_level,
+            staged: None,
+            request: Mutex::new(StreamingRequest {
+                first_level: coarsest_first_level,
+                priority: 0.0
+            })
+        })
+    }
+
+    fn release(&self, loader: &VkContext) {
+        self.image.release(loader);
+        if let Some(staged) = &self.staged {
+            staged.image.release(loader);
+        }
+    }
+}
+
+impl StreamedTexture {
+
+    /// Get the image holding the resident mip levels; this is replaced whenever they change
+    pub fn get_image(&self) -> &ImageWrapper {
+        &self.image
+    }
+
+    /// Get the most detailed mip level that is resident
+    pub fn get_first_resident_level(&self) -> u32 {
+        self.first_resident_level
+    }
+
+    /// Ask for the mip levels from the given one down to be resident; the streamer will load
+    /// them if the budget allows, favouring textures with higher priority
+    pub fn request_detail(&self, first_level: u32, priority: f32) {
+        *self.request.lock().unwrap() = StreamingRequest {
+            first_level: first_level.min(self.coarsest_first_level),
+            priority
+        };
+    }
+
+    pub fn get_request(&self) -> StreamingRequest {
+        *self.request.lock().unwrap()
+    }
+
+    /// Size of each mip level, over all layers, as resident in device memory
+    fn level_sizes_bytes(&self) -> Vec<usize> {
+        let layer_count = self.source.layer_data.as_ref().map_or(0, |layers| layers.len());
+        (0..self.source.mip_levels)
+            .map(|level| {
+                let (width, height) =
+                    mip_level_extent(self.source.width, self.source.height, level);
+                self.source.format.layer_size_bytes(width, height) * layer_count
+            })
+            .collect()
+    }
+
+    /// The most detailed mip level that will be resident once any staged upload is swapped in
+    fn planned_first_level(&self) -> u32 {
+        self.staged.as_ref().map_or(self.first_resident_level, |staged| staged.first_level)
+    }
+
+    /// Start uploading an image holding the mip levels from the given one down, to be swapped in
+    /// by swap_in_staged once the upload completes. The current image stays in use until then.
+    unsafe fn stage_first_resident_level(
+        &mut self,
+        context: &VkContext,
+        first_level: u32
+    ) -> Result<(), EngineError> {
+        if self.staged.is_some() {
+            return Err(EngineError::UserError(
+                "Streamed texture already has an upload staged".to_owned()));
+        }
+        let image = Self::make_image(context, &self.source, first_level)?;
+        self.staged = Some(StagedLevels {
+            image,
+            first_level,
+            upload_value: context.get_transfer_timeline().last_submitted_value()
+        });
+        Ok(())
+    }
+
+    /// Query, without waiting, whether a staged upload has completed and can be swapped in
+    unsafe fn is_staged_upload_complete(&self, context: &VkContext) -> Result<bool, EngineError> {
+        match &self.staged {
+            Some(staged) => context.get_transfer_timeline()
+                .is_complete(&context.device, staged.upload_value),
+            None => Ok(false)
+        }
+    }
+
+    /// Replace the image with the staged one, whose upload must have completed. Nothing may be
+    /// using the old image, and any descriptor sets referring to it must be written again.
+    unsafe fn swap_in_staged(&mut self, context: &VkContext) {
+        if let Some(staged) = self.staged.take() {
+            self.image.release(context);
+            self.image = staged.image;
+            self.first_resident_level = staged.first_level;
+        }
+    }
+
+    /// Create an image holding the mip levels of a texture from the given one down, uploading
+    /// them through the staging ring without waiting for the copy to complete
+    unsafe fn make_image(
+        context: &VkContext,
+        source: &TextureCreationData,
+        first_level: u32
+    ) -> Result<ImageWrapper, EngineError> {
+        let skipped_bytes: usize = (0..first_level)
+            .map(|level| {
+                let (width, height) = mip_level_extent(source.width, source.height, level);
+                source.format.layer_size_bytes(width, height)
+            })
+            .sum();
+        let layer_data: Vec<Vec<u8>> = source.layer_data.as_ref()
+            .map_or(&[][..], |layers| layers.as_slice())
+            .iter()
+            .map(|layer| layer[skipped_bytes.min(layer.len())..].to_vec())
+            .collect();
+        let (width, height) = mip_level_extent(source.width, source.height, first_level);
+        ImageWrapper::new_with_extent(
+            context,
+            source.usage,
+            source.format,
+            width,
+            height,
+            source.depth,
+            source.mip_levels - first_level,
+            Some(&layer_data))
+    }
+}
+
+/// StreamingCandidate struct
+/// A streamed texture as seen by the residency planner
+#[derive(Clone, Debug)]
+pub(crate) struct StreamingCandidate {
+    pub request: StreamingRequest,
+    pub coarsest_first_level: u32,
+    pub level_sizes_bytes: Vec<usize>
+}
+
+impl StreamingCandidate {
+
+    pub fn resident_bytes(&self, first_level: u32) -> usize {
+        self.level_sizes_bytes[first_level as usize..].iter().sum()
+    }
+}
+
+/// Decide the most detailed resident level for each candidate within a budget. Every texture
+/// keeps its smallest levels, then textures are visited in order of priority, each getting as
+/// much of the detail it asked for as still fits.
+pub(crate) fn plan_residency(candidates: &[StreamingCandidate], budget_bytes: usize) -> Vec<u32> {
+    let mut first_levels: Vec<u32> = candidates.iter()
+        .map(|candidate| candidate.coarsest_first_level)
+        .collect();
+    let minimum_bytes: usize = candidates.iter()
+        .map(|candidate| candidate.resident_bytes(candidate.coarsest_first_level))
+        .sum();
+    let mut remaining_bytes = budget_bytes.saturating_sub(minimum_bytes);
+
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|a, b| candidates[*b].request.priority
+        .total_cmp(&candidates[*a].request.priority));
+    for index in order {
+        let candidate = &candidates[index];
+        let base_bytes = candidate.resident_bytes(candidate.coarsest_first_level);
+        for level in candidate.request.first_level..candidate.coarsest_first_level {
+            let extra_bytes = candidate.resident_bytes(level) - base_bytes;
+            if extra_bytes <= remaining_bytes {
+                remaining_bytes -= extra_bytes;
+                first_levels[index] = level;
+                break;
+            }
+        }
+    }
+    first_levels
+}
+
+/// TextureStreamingConfig struct
+/// How much memory streamed textures may use, as a fraction of the heap that textures are
+/// allocated from, after everything else allocated has been accounted for; and how many textures
+/// may gain levels per frame, which spreads the cost of uploads over several frames
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TextureStreamingConfig {
+    pub budget_fraction: f32,
+    pub max_loads_per_frame: usize
+}
+
+impl Default for TextureStreamingConfig {
+    fn default() -> Self {
+        Self {
+            budget_fraction: 0.5,
+            max_loads_per_frame: 2
+        }
+    }
+}
+
+/// StreamingPlan struct
+/// Changes to resident mip levels decided for one frame. Evictions are staged before loads,
+/// though memory is only freed as each new image is swapped in.
+#[derive(Clone, Default)]
+pub struct StreamingPlan {
+    evictions: Vec<(Handle, u32)>,
+    loads: Vec<(Handle, u32)>
+}
+
+impl StreamingPlan {
+
+    pub fn is_empty(&self) -> bool {
+        self.evictions.is_empty() && self.loads.is_empty()
+    }
+
+    pub fn eviction_count(&self) -> usize {
+        self.evictions.len()
+    }
+
+    pub fn load_count(&self) -> usize {
+        self.loads.len()
+    }
+}
+
+/// TextureStreamer struct
+/// Decides each frame which mip levels of the StreamedTextures in the ECS should be resident,
+/// within a budget worked out from the allocator's statistics. Carrying out a plan is split in
+/// two: new images are staged, uploading on the transfer queue while frames keep using the old
+/// ones, and each is swapped in once the transfer timeline shows that its upload has completed.
+/// A texture is not planned again while it has an upload staged.
+pub struct TextureStreamer {
+    config: TextureStreamingConfig
+}
+
+impl TextureStreamer {
+
+    pub fn new(config: TextureStreamingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Bytes available for streamed textures, given the allocator's statistics and the bytes
+    /// that streamed textures currently have resident
+    pub fn budget_bytes(&self, statistics: &MemoryStatistics, streamed_bytes: usize) -> usize {
+        let heap_budget = (statistics.bulk_heap_bytes as f64 * self.config.budget_fraction as f64)
+            as usize;
+        let other_bytes = (statistics.allocated_bytes as usize).saturating_sub(streamed_bytes);
+        heap_budget.saturating_sub(other_bytes)
+    }
+
+    /// Decide what to load and evict this frame, from the detail requested by each texture
+    pub fn plan(&self, context: &VkContext, ecs: &EcsManager<VkContext>) -> StreamingPlan {
+        let mut handles = vec![];
+        let mut candidates = vec![];
+        let mut current_levels = vec![];
+        let mut staged = vec![];
+        ecs.for_each_item::<StreamedTexture, _>(|handle, texture| {
+            handles.push(handle);
+            current_levels.push(texture.planned_first_level());
+            staged.push(texture.staged.is_some());
+            candidates.push(StreamingCandidate {
+                request: texture.get_request(),
+                coarsest_first_level: texture.coarsest_first_level,
+                level_sizes_bytes: texture.level_sizes_bytes()
+            });
+        });
+        let streamed_bytes = candidates.iter().zip(current_levels.iter())
+            .map(|(candidate, level)| candidate.resident_bytes(*level))
+            .sum();
+        let (allocator, _) = context.get_mem_allocator();
+        let budget_bytes = self.budget_bytes(&allocator.statistics(), streamed_bytes);
+        let first_levels = plan_residency(&candidates, budget_bytes);
+
+        // Loads are taken in order of priority, up to the per-frame limit
+        let mut plan = StreamingPlan::default();
+        let mut loads = vec![];
+        for (index, first_level) in first_levels.into_iter().enumerate() {
+            if staged[index] {
+                // Changed again once the upload already staged has been swapped in
+                continue;
+            }
+            if first_level > current_levels[index] {
+                plan.evictions.push((handles[index], first_level));
+            } else if first_level < current_levels[index] {
+                loads.push((candidates[index].request.priority, handles[index], first_level));
+            }
+        }
+        loads.sort_by(|a, b| b.0.total_cmp(&a.0));
+        plan.loads = loads.into_iter()
+            .take(self.config.max_loads_per_frame)
+            .map(|(_, handle, first_level)| (handle, first_level))
+            .collect();
+        plan
+    }
+
+    /// Start carrying out a plan by uploading a new image for each texture that changes, through
+    /// the transfer queue and without waiting. The images in use are untouched until
+    /// swap_in_completed, so this may be done while submitted frames are still using them.
+    pub unsafe fn stage(
+        &self,
+        plan: &StreamingPlan,
+        context: &VkContext,
+        ecs: &mut EcsManager<VkContext>
+    ) -> Result<(), EngineError> {
+        for (handle, first_level) in plan.evictions.iter().chain(plan.loads.iter()) {
+            if let Some(texture) = ecs.get_item_mut::<StreamedTexture>(*handle) {
+                texture.stage_first_resident_level(context, *first_level)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Query, without waiting, whether any staged upload has completed
+    pub unsafe fn has_completed_uploads(
+        &self,
+        context: &VkContext,
+        ecs: &EcsManager<VkContext>
+    ) -> Result<bool, EngineError> {
+        let mut completed = false;
+        let mut result = Ok(());
+        ecs.for_each_item::<StreamedTexture, _>(|_, texture| {
+            if completed || result.is_err() {
+                return;
+            }
+            match texture.is_staged_upload_complete(context) {
+                Ok(complete) => completed = complete,
+                Err(e) => result = Err(e)
+            }
+        });
+        result.map(|_| completed)
+    }
+
+    /// Swap in each staged image whose upload has completed, releasing the image it replaces,
+    /// and return the number swapped. Submitted frames must no longer be using the replaced
+    /// images, and descriptor sets referring to streamed textures must be written again
+    /// afterwards, such as by reloading dynamic resources.
+    pub unsafe fn swap_in_completed(
+        &self,
+        context: &VkContext,
+        ecs: &mut EcsManager<VkContext>
+    ) -> Result<usize, EngineError> {
+        let mut swapped = 0;
+        ecs.try_for_each_item_mut::<StreamedTexture, _>(|_, texture| {
+            if texture.is_staged_upload_complete(context)? {
+                texture.swap_in_staged(context);
+                swapped += 1;
+            }
+            Ok(())
+        })?;
+        Ok(swapped)
+    }
+}
@@ -1,26 +1,42 @@
 
-use crate::{ImageUsage, TexturePixelFormat, TextureCreationData};
+use crate::{VkContext, ImageWrapper, ImageUsage, TexturePixelFormat, TextureCreationData};
+use crate::resource::container::{ContainerTexture, parse_ktx2, parse_dds};
+use crate::resource::cubemap::equirectangular_to_cubemap;
 use error::EngineError;
-use model::{Model, StaticVertex, StoresAsFile};
+use model::{Model, StoresAsFile};
 use std::io::Cursor;
 use image::{
     DynamicImage,
+    ImageFormat,
     codecs::jpeg::JpegDecoder,
-    codecs::png::PngDecoder
+    codecs::png::PngDecoder,
+    codecs::tga::TgaDecoder
 };
 
+/// TextureCodec enum
+/// File formats that textures can be decoded from. PNG and TGA keep their alpha channel. HDR
+/// (Radiance) images, such as equirectangular sky panoramas, are clamped to 8 bits per channel.
+/// KTX2 and DDS are containers holding GPU-ready data, possibly compressed, with any mip levels
+/// and cube map faces they were built with.
 #[derive(Copy, Clone)]
 pub enum TextureCodec {
     Jpeg,
-    Png
+    Png,
+    Tga,
+    Hdr,
+    Ktx2,
+    Dds
 }
 
 pub struct ResourceUtilities;
 
 impl ResourceUtilities {
-    /// Decode a model file generated by the model crate's utility functions.
-    pub unsafe fn decode_model(model_file_bytes: &[u8]) -> (Vec<StaticVertex>, usize) {
-        let model: Model<StaticVertex> = unsafe {
+    /// Decode a model file generated by the model crate's utility functions, for any vertex type
+    /// that the model crate can store
+    pub unsafe fn decode_model<V>(model_file_bytes: &[u8]) -> (Vec<V>, usize)
+        where Model<V>: StoresAsFile<V>
+    {
+        let model: Model<V> = unsafe {
             Model::new_from_bytes(model_file_bytes).unwrap()
         };
         let vertex_count: usize = model.vertices.len();
@@ -49,14 +65,180 @@ impl ResourceUtilities {
                     .map_err(|e| EngineError::OpFailed(format!("Failed decoding image: {:?}", e)))?;
                 let image_data_rgba = image_pixel_data.to_rgba8();
                 (image_data_rgba.to_vec(), image_data_rgba.width(), image_data_rgba.height())
-            }
+            },
+            TextureCodec::Tga => {
+                let src_cursor = Cursor::new(image_file_bytes.to_vec());
+                let decoder = TgaDecoder::new(src_cursor)
+                    .map_err(|e| EngineError::OpFailed(format!("Failed decoding image: {:?}", e)))?;
+                let image_pixel_data = DynamicImage::from_decoder(decoder)
+                    .map_err(|e| EngineError::OpFailed(format!("Failed decoding image: {:?}", e)))?;
+                let image_data_rgba = image_pixel_data.to_rgba8();
+                (image_data_rgba.to_vec(), image_data_rgba.width(), image_data_rgba.height())
+            },
+            TextureCodec::Hdr => {
+                let image_pixel_data = image::load_from_memory_with_format(
+                    image_file_bytes,
+                    ImageFormat::Hdr)
+                    .map_err(|e| EngineError::OpFailed(format!("Failed decoding image: {:?}", e)))?;
+                let image_data_rgba = image_pixel_data.to_rgba8();
+                (image_data_rgba.to_vec(), image_data_rgba.width(), image_data_rgba.height())
+            },
+            TextureCodec::Ktx2 =>
+                return Self::container_texture(parse_ktx2(image_file_bytes)?, usage),
+            TextureCodec::Dds =>
+                return Self::container_texture(parse_dds(image_file_bytes)?, usage)
         };
         Ok(TextureCreationData {
             layer_data: Some(vec![data]),
             width,
             height,
+            depth: 1,
+            mip_levels: 1,
             format: TexturePixelFormat::Rgba,
             usage
         })
     }
+
+    /// Combine separately-decoded textures into the layers of one texture array, such as the
+    /// frames of a sprite sheet or the materials for terrain splatting. Every texture must have
+    /// the same dimensions, format and mip levels, and a single layer.
+    pub fn texture_array(
+        textures: Vec<TextureCreationData>
+    ) -> Result<TextureCreationData, EngineError> {
+        let Some(first) = textures.first() else {
+            return Err(EngineError::UserError(
+                "Texture array needs at least one texture".to_owned()));
+        };
+        let (width, height, mip_levels, format) =
+            (first.width, first.height, first.mip_levels, first.format);
+        let mut layer_data = Vec::with_capacity(textures.len());
+        for (index, texture) in textures.into_iter().enumerate() {
+            let description = (texture.width, texture.height, texture.mip_levels, texture.format);
+            if description != (width, height, mip_levels, format) {
+                return Err(EngineError::UserError(format!(
+                    "Texture {} of array differs in size, format or mip levels from the first",
+                    index)));
+            }
+            match texture.layer_data {
+                Some(mut layers) if layers.len() == 1 => layer_data.push(layers.remove(0)),
+                _ => {
+                    return Err(EngineError::UserError(
+                        format!("Texture {} of array must have exactly one layer", index)));
+                }
+            }
+        }
+        Ok(TextureCreationData {
+            layer_data: Some(layer_data),
+            width,
+            height,
+            depth: 1,
+            mip_levels,
+            format,
+            usage: ImageUsage::TextureArray
+        })
+    }
+
+    /// Split tightly-packed texels of a 3D image, such as a generated noise or lookup table,
+    /// into the depth slices that a volume texture is created from
+    pub fn volume_texture(
+        texels: Vec<u8>,
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: TexturePixelFormat
+    ) -> Result<TextureCreationData, EngineError> {
+        let slice_size_bytes = format.layer_size_bytes(width, height);
+        let size_matches = depth > 0 && texels.len() == slice_size_bytes * depth as usize;
+        if format.is_compressed() || !size_matches {
+            return Err(EngineError::UserError(format!(
+                "Volume texture data must be {} uncompressed slices of {}x{} {:?}",
+                depth,
+                width,
+                height,
+                format)));
+        }
+        let slices = texels.chunks(slice_size_bytes).map(|slice| slice.to_vec()).collect();
+        Ok(TextureCreationData {
+            layer_data: Some(slices),
+            width,
+            height,
+            depth,
+            mip_levels: 1,
+            format,
+            usage: ImageUsage::VolumeTexture
+        })
+    }
+
+    /// Convert a single equirectangular image, such as a decoded panorama, into a sky box with
+    /// faces of the given size, on the GPU. The result is an RGBA cube map ready for sampling,
+    /// which may be added to the ECS like any other image.
+    pub unsafe fn equirectangular_to_skybox(
+        context: &VkContext,
+        equirectangular: &TextureCreationData,
+        face_size: u32
+    ) -> Result<ImageWrapper, EngineError> {
+        equirectangular_to_cubemap(context, equirectangular, face_size)
+    }
+
+    /// Check that a texture read from a container suits the usage, which decides whether it
+    /// must be a cube map or may be an array
+    fn container_texture(
+        container: ContainerTexture,
+        usage: ImageUsage
+    ) -> Result<TextureCreationData, EngineError> {
+        let expected_layers = match usage {
+            ImageUsage::Skybox => 6,
+            ImageUsage::TextureArray => container.layer_data.len().max(1),
+            _ => 1
+        };
+        if container.layer_data.len() != expected_layers {
+            return Err(EngineError::UserError(format!(
+                "Texture has {} faces but {:?} usage needs {}",
+                container.layer_data.len(),
+                usage,
+                expected_layers)));
+        }
+        Ok(TextureCreationData {
+            layer_data: Some(container.layer_data),
+            width: container.width,
+            height: container.height,
+            depth: 1,
+            mip_levels: container.mip_levels,
+            format: container.format,
+            usage
+        })
+    }
+
+    /// Wrap texture data that is already block-compressed, such as the payload of a KTX or DDS
+    /// file, checking that every layer is the right size for the format and dimensions
+    pub fn compressed_texture(
+        layer_data: Vec<Vec<u8>>,
+        width: u32,
+        height: u32,
+        format: TexturePixelFormat,
+        usage: ImageUsage
+    ) -> Result<TextureCreationData, EngineError> {
+        if !format.is_compressed() {
+            return Err(EngineError::UserError(format!("{:?} is not a compressed format", format)));
+        }
+        let expected_size_bytes = format.layer_size_bytes(width, height);
+        let sizes_match = layer_data.iter().all(|layer| layer.len() == expected_size_bytes);
+        if layer_data.is_empty() || !sizes_match {
+            return Err(EngineError::UserError(format!(
+                "Compressed texture layers must each be {} bytes for {}x{} {:?}",
+                expected_size_bytes,
+                width,
+                height,
+                format)));
+        }
+        Ok(TextureCreationData {
+            layer_data: Some(layer_data),
+            width,
+            height,
+            depth: 1,
+            mip_levels: 1,
+            format,
+            usage
+        })
+    }
 }
@@ -0,0 +1,1089 @@
+use crate::mem::staging_ring::RingRegions;
+use crate::mem::atom_aligned_range;
+use crate::mem::transient::transient_offset;
+use crate::mem::blocks::{BlockSpace, BlockMove, plan_compaction};
+use crate::context::{letterbox_area, scaled_extent, device_error, choose_composite_alpha};
+use crate::context::descriptors::next_pool_set_count;
+use crate::context::handoff::ComputeHandoff;
+use crate::context::barriers::{stage_flags2, access_flags2};
+use crate::core::instance::negotiate_api_version;
+use crate::core::physical_device::{make_feature_set_to_enable, granted_feature_count};
+use crate::resource::container::{parse_ktx2, parse_dds};
+use crate::resource::streaming::{StreamingCandidate, plan_residency};
+use crate::pipeline::specialization::SpecializationData;
+use crate::pipeline::post_process::validate_passes;
+use crate::pipeline::bindless::check_material_index;
+use crate::resource::glsl::compile_glsl;
+use crate::{
+    ImageAccess, ImageState, ImageUsage, TexturePixelFormat, TextureCodec, ResourceUtilities,
+    DynamicUniformLayout, RenderQueue, VertexLayout, VertexAttributeFormat,
+    SamplerCreationData, TextureQuality, DrawMetadata, TextureCreationData, StreamingRequest,
+    TextureStreamer, TextureStreamingConfig, MemoryStatistics, back_to_front_order,
+    full_mip_chain_length, highest_sample_count, DepthConvention,
+    DepthState, BlendMode, RasterizerState, StencilState, SpecializationConstant, ShaderStage,
+    work_group_count, CustomRenderpassDescription, CustomSubpass, CustomSubpassDependency,
+    CustomAttachment, DebugLabels, PostProcessPass, MAX_POST_PROCESS_PUSH_CONSTANT_SIZE,
+    PreRotation, FeatureDeclaration, FeatureReport, PortabilitySubset, StencilFaceState,
+    ValidationSeverity, ValidationErrors, QueueFamilyPair, AdapterInfo, RenderpassWrapper,
+    BufferWrapper, ImageWrapper, ComputeAccess, ClusterGrid, PointLight, MAX_LIGHTS_PER_CLUSTER
+};
+use error::EngineError;
+use ash::vk;
+
+#[test]
+fn staging_ring_fills_in_order() {
+    let mut ring = RingRegions::new(1024, 256);
+    let first = ring.find_space(100).unwrap();
+    ring.push(first, 100);
+    let second = ring.find_space(100).unwrap();
+    ring.push(second, 100);
+    assert_eq!(first, 0);
+    assert_eq!(second, 256);
+    assert_eq!(ring.len(), 2);
+}
+
+#[test]
+fn staging_ring_is_full_until_oldest_released() {
+    let mut ring = RingRegions::new(1024, 256);
+    for _ in 0..4 {
+        let offset = ring.find_space(256).unwrap();
+        ring.push(offset, 256);
+    }
+    assert_eq!(ring.find_space(1), None);
+    ring.pop_oldest();
+    assert_eq!(ring.find_space(256), Some(0));
+    assert_eq!(ring.find_space(257), None);
+}
+
+#[test]
+fn staging_ring_wraps_without_splitting_regions() {
+    let mut ring = RingRegions::new(1024, 256);
+    ring.push(0, 512);
+    ring.push(512, 300);
+    ring.pop_oldest();
+
+    // Doesn't fit between the newest region and the end, so starts again from the beginning
+    let wrapped = ring.find_space(400).unwrap();
+    assert_eq!(wrapped, 0);
+    ring.push(wrapped, 400);
+
+    // Now the only space left is before the oldest region
+    assert_eq!(ring.find_space(100), None);
+    ring.pop_oldest();
+    assert_eq!(ring.find_space(100), Some(512));
+}
+
+#[test]
+fn staging_ring_rejects_oversized_regions() {
+    let ring = RingRegions::new(1024, 256);
+    assert_eq!(ring.find_space(1025), None);
+    assert_eq!(ring.find_space(1024), Some(0));
+}
+
+#[test]
+fn image_access_implies_layout() {
+    assert_eq!(ImageAccess::ShaderRead.layout(), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    assert_eq!(
+        ImageAccess::DepthAttachment.layout(),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    assert_eq!(ImageAccess::TransferWrite.stage_mask(), vk::PipelineStageFlags::TRANSFER);
+    assert_eq!(ImageAccess::StorageWrite.layout(), vk::ImageLayout::GENERAL);
+}
+
+#[test]
+fn repeated_reads_need_no_barrier() {
+    assert!(!ImageAccess::ShaderRead.needs_barrier_to(ImageAccess::ShaderRead));
+    assert!(ImageAccess::ColorAttachment.needs_barrier_to(ImageAccess::ColorAttachment));
+    assert!(ImageAccess::ColorAttachment.needs_barrier_to(ImageAccess::ShaderRead));
+    assert!(ImageAccess::ShaderRead.needs_barrier_to(ImageAccess::TransferRead));
+    assert!(ImageAccess::StorageWrite.needs_barrier_to(ImageAccess::StorageWrite));
+}
+
+#[test]
+fn reads_from_other_stages_merge_into_the_tracked_state() {
+    let fragment_read = ImageAccess::ShaderRead.state();
+    let vertex_read = ImageState::new(
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::PipelineStageFlags::VERTEX_SHADER,
+        vk::AccessFlags::SHADER_READ);
+    assert!(!fragment_read.needs_barrier_to(&vertex_read));
+    let merged = fragment_read.merged_with(&vertex_read);
+    assert_eq!(
+        merged.stage_mask,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::VERTEX_SHADER);
+    assert!(merged.needs_barrier_to(&ImageAccess::ColorAttachment.state()));
+    assert!(!merged.writes());
+    assert!(ImageAccess::StorageWrite.state().writes());
+}
+
+#[test]
+fn dynamic_uniform_slices_are_aligned() {
+    let layout = DynamicUniformLayout::new(72, 3, 64);
+    assert_eq!(layout.stride_bytes, 128);
+    assert_eq!(layout.total_size_bytes(), 384);
+    assert_eq!(layout.offset(0), 0);
+    assert_eq!(layout.offset(2), 256);
+    assert_eq!(DynamicUniformLayout::new(64, 1, 64).stride_bytes, 64);
+}
+
+#[test]
+fn transparent_objects_sort_back_to_front() {
+    let positions = [[0.0, 0.0, 2.0], [0.0, 0.0, -5.0], [3.0, 0.0, 0.0]];
+    assert_eq!(back_to_front_order(&positions, [0.0, 0.0, 0.0]), vec![1, 2, 0]);
+    assert_eq!(back_to_front_order(&positions, [0.0, 0.0, -5.0]), vec![0, 2, 1]);
+}
+
+#[test]
+fn only_opaque_queue_writes_depth() {
+    assert!(RenderQueue::Opaque.writes_depth() && !RenderQueue::Opaque.blends());
+    assert!(!RenderQueue::Transparent.writes_depth() && RenderQueue::Transparent.blends());
+}
+
+#[test]
+fn flushed_ranges_cover_whole_atoms() {
+    assert_eq!(atom_aligned_range(100, 50, 64, 1024), (64, 128));
+    assert_eq!(atom_aligned_range(128, 64, 64, 1024), (128, 64));
+    assert_eq!(atom_aligned_range(1000, 20, 64, 1020), (960, 60));
+}
+
+#[test]
+fn packed_vertex_layout_offsets_follow_attribute_sizes() {
+    let layout = VertexLayout::packed(&[
+        VertexAttributeFormat::Vec2,
+        VertexAttributeFormat::UnormByte4,
+        VertexAttributeFormat::Vec3
+    ]);
+    assert_eq!(layout.stride_bytes, 24);
+    let offsets: Vec<u32> = layout.attributes.iter().map(|a| a.offset_bytes).collect();
+    assert_eq!(offsets, vec![0, 8, 12]);
+    assert_eq!(layout.attributes[2].location, 2);
+    assert_eq!(VertexLayout::static_vertex().stride_bytes, 32);
+}
+
+#[test]
+fn letterbox_centres_the_largest_area_of_the_ratio() {
+    let wide = vk::Extent2D { width: 1000, height: 400 };
+    let area = letterbox_area(wide, 2.0);
+    assert_eq!((area.offset.x, area.offset.y), (100, 0));
+    assert_eq!((area.extent.width, area.extent.height), (800, 400));
+
+    let tall = vk::Extent2D { width: 800, height: 800 };
+    let area = letterbox_area(tall, 2.0);
+    assert_eq!((area.offset.x, area.offset.y), (0, 200));
+    assert_eq!((area.extent.width, area.extent.height), (800, 400));
+}
+
+#[test]
+fn internal_resolution_scales_to_whole_pixels() {
+    let extent = vk::Extent2D { width: 1921, height: 1081 };
+    let half = scaled_extent(extent, 0.5);
+    assert_eq!((half.width, half.height), (961, 541));
+    let double = scaled_extent(extent, 2.0);
+    assert_eq!((double.width, double.height), (3842, 2162));
+    let tiny = scaled_extent(vk::Extent2D { width: 3, height: 1 }, 0.1);
+    assert_eq!((tiny.width, tiny.height), (1, 1));
+}
+
+#[test]
+fn only_a_lost_device_is_reported_as_device_lost() {
+    let lost = device_error("Present error", vk::Result::ERROR_DEVICE_LOST);
+    assert!(matches!(lost, EngineError::DeviceLost(_)));
+    let other = device_error("Present error", vk::Result::ERROR_OUT_OF_HOST_MEMORY);
+    assert!(matches!(other, EngineError::OpFailed(_)));
+}
+
+#[test]
+fn pre_rotation_turns_clip_space_and_swaps_quarter_turn_extents() {
+    let extent = vk::Extent2D { width: 1080, height: 2400 };
+    let rotation = PreRotation::from_transform(vk::SurfaceTransformFlagsKHR::ROTATE_90).unwrap();
+    assert_eq!(rotation, PreRotation::Rotate90);
+    assert_eq!(rotation.transform_flags(), vk::SurfaceTransformFlagsKHR::ROTATE_90);
+    let oriented = rotation.oriented_extent(extent);
+    assert_eq!((oriented.width, oriented.height), (2400, 1080));
+    let oriented = PreRotation::Rotate180.oriented_extent(extent);
+    assert_eq!((oriented.width, oriented.height), (1080, 2400));
+    assert!(PreRotation::from_transform(vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR).is_none());
+
+    // Mirrored transforms fall back to a supported one that only rotates
+    let mirrored = vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR;
+    let rotate_90 = vk::SurfaceTransformFlagsKHR::ROTATE_90;
+    let identity = vk::SurfaceTransformFlagsKHR::IDENTITY;
+    assert_eq!(PreRotation::choose_transform(rotate_90, rotate_90), rotate_90);
+    assert_eq!(PreRotation::choose_transform(mirrored, mirrored | identity), identity);
+    assert_eq!(PreRotation::choose_transform(mirrored, mirrored | rotate_90), rotate_90);
+    assert_eq!(PreRotation::choose_transform(mirrored, mirrored), mirrored);
+
+    // Rotating clip-space x onto y, and back round after a full turn
+    let matrix = PreRotation::Rotate90.clip_space_matrix();
+    assert_eq!((matrix[0][0], matrix[0][1]), (0.0, 1.0));
+    assert_eq!(PreRotation::Identity.clip_space_matrix()[1], [0.0, 1.0, 0.0, 0.0]);
+    let matrix = PreRotation::Rotate270.clip_space_matrix();
+    assert_eq!((matrix[0][0], matrix[0][1]), (0.0, -1.0));
+}
+
+#[test]
+fn compressed_layers_are_whole_blocks() {
+    assert_eq!(TexturePixelFormat::Rgba.layer_size_bytes(5, 3), 60);
+    assert_eq!(TexturePixelFormat::Bc1.layer_size_bytes(8, 8), 32);
+    assert_eq!(TexturePixelFormat::Bc7.layer_size_bytes(5, 3), 32);
+    assert!(TexturePixelFormat::Astc4x4.is_compressed());
+    assert!(!TexturePixelFormat::Rgba.is_compressed());
+}
+
+#[test]
+fn mip_chains_halve_down_to_one_texel() {
+    assert_eq!(full_mip_chain_length(8, 4), 4);
+    assert_eq!(full_mip_chain_length(1, 1), 1);
+    assert_eq!(TexturePixelFormat::Rgba.mip_chain_size_bytes(4, 2, 3), 32 + 8 + 4);
+    assert_eq!(TexturePixelFormat::Bc1.mip_chain_size_bytes(8, 8, 4), 32 + 8 + 8 + 8);
+}
+
+#[test]
+fn ktx2_cube_map_faces_gather_their_mip_levels() {
+    let mut file = vec![0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, b'\r', b'\n', 0x1a, b'\n'];
+    for value in [37u32, 1, 2, 2, 0, 0, 6, 2, 0] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    file.resize(80, 0);
+
+    // Level 0 is 6 faces of 2x2 texels, level 1 is 6 faces of 1 texel; each face is filled with
+    // its index so that the gathered layers can be told apart
+    let level_offsets = [128u64, 224];
+    for (offset, length) in level_offsets.iter().zip([96u64, 24]) {
+        for value in [*offset, length, length] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    file.resize(128, 0);
+    for face_size in [16, 4] {
+        for face in 0..6u8 {
+            file.extend(std::iter::repeat(face).take(face_size));
+        }
+    }
+
+    let texture = parse_ktx2(&file).unwrap();
+    assert_eq!(texture.format, TexturePixelFormat::Rgba);
+    assert_eq!((texture.width, texture.height, texture.mip_levels), (2, 2, 2));
+    assert_eq!(texture.layer_data.len(), 6);
+    assert_eq!(texture.layer_data[3], vec![3; 20]);
+    assert!(parse_ktx2(&file[..200]).is_err());
+}
+
+#[test]
+fn dds_block_compressed_mip_levels_are_read() {
+    let mut file = b"DDS ".to_vec();
+    for value in [124u32, 0x20000, 8, 8, 0, 0, 4] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    file.resize(80, 0);
+    file.extend_from_slice(&4u32.to_le_bytes());
+    file.extend_from_slice(b"DXT1");
+    file.resize(128, 0);
+    file.extend(std::iter::repeat(7).take(56));
+
+    let texture = parse_dds(&file).unwrap();
+    assert_eq!(texture.format, TexturePixelFormat::Bc1);
+    assert_eq!((texture.width, texture.height, texture.mip_levels), (8, 8, 4));
+    assert_eq!(texture.layer_data, vec![vec![7; 56]]);
+
+    // Partial cube maps are refused
+    file[112..116].copy_from_slice(&0x0600u32.to_le_bytes());
+    assert!(parse_dds(&file).is_err());
+}
+
+#[test]
+fn tga_decodes_with_alpha() {
+    // Uncompressed 32-bit true-colour, 2x1, top-left origin; pixels are stored as BGRA
+    let mut file = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 32, 0x28];
+    file.extend_from_slice(&[0x30, 0x20, 0x10, 0x80, 0x00, 0x00, 0xff, 0x00]);
+    let texture = ResourceUtilities::decode_texture(
+        &file,
+        TextureCodec::Tga,
+        ImageUsage::TextureSampleOnly
+    ).unwrap();
+    assert_eq!((texture.width, texture.height), (2, 1));
+    assert_eq!(
+        texture.layer_data.unwrap()[0],
+        vec![0x10, 0x20, 0x30, 0x80, 0xff, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn hdr_decodes_clamped_to_eight_bits() {
+    // Radiance header for a 2x1 image, then flat RGBE pixels: 1.0 and 0.0
+    let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+    file.extend_from_slice(&[128, 128, 128, 129, 0, 0, 0, 0]);
+    let texture = ResourceUtilities::decode_texture(
+        &file,
+        TextureCodec::Hdr,
+        ImageUsage::TextureSampleOnly
+    ).unwrap();
+    assert_eq!((texture.width, texture.height), (2, 1));
+    assert_eq!(
+        texture.layer_data.unwrap()[0],
+        vec![0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff]);
+}
+
+#[test]
+fn srgb_textures_share_the_rgba_layout() {
+    assert_eq!(TexturePixelFormat::Srgba.vk_format(), vk::Format::R8G8B8A8_SRGB);
+    assert_eq!(
+        TexturePixelFormat::Srgba.layer_size_bytes(5, 3),
+        TexturePixelFormat::Rgba.layer_size_bytes(5, 3));
+    assert!(!TexturePixelFormat::Srgba.is_compressed());
+}
+
+#[test]
+fn sampler_anisotropy_is_clamped_to_the_device() {
+    let sampler = SamplerCreationData {
+        max_anisotropy: Some(16.0),
+        ..SamplerCreationData::default()
+    }.with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+    assert_eq!(sampler.address_mode_w, vk::SamplerAddressMode::CLAMP_TO_EDGE);
+    assert_eq!(sampler.effective_anisotropy(Some(8.0)), Some(8.0));
+    assert_eq!(sampler.effective_anisotropy(None), None);
+    assert_eq!(SamplerCreationData::default().effective_anisotropy(Some(8.0)), None);
+    let sampler = SamplerCreationData::default().with_anisotropy(4.0);
+    assert_eq!(sampler.effective_anisotropy(Some(16.0)), Some(4.0));
+}
+
+#[test]
+fn texture_quality_biases_and_limits_sampled_mips() {
+    let quality = TextureQuality {
+        lod_bias: 1.5,
+        max_resolution: Some(256)
+    };
+    let sampler = SamplerCreationData {
+        lod_bias: 0.5,
+        texture_extent: Some((1024, 512)),
+        ..SamplerCreationData::default()
+    };
+    assert_eq!(sampler.effective_lod_bias(&quality, 15.0), 2.0);
+    assert_eq!(sampler.effective_lod_bias(&quality, 1.0), 1.0);
+    assert_eq!(sampler.min_lod(&quality), 2.0);
+    assert_eq!(sampler.min_lod(&TextureQuality::default()), 0.0);
+    assert_eq!(SamplerCreationData::default().min_lod(&quality), 0.0);
+}
+
+#[test]
+fn draw_metadata_is_laid_out_as_a_uvec4() {
+    assert_eq!(std::mem::size_of::<DrawMetadata>(), 16);
+    let metadata = DrawMetadata::new(3, 7, DrawMetadata::FLAG_PICKABLE);
+    assert!(metadata.has_flags(DrawMetadata::FLAG_PICKABLE));
+    assert!(!metadata.has_flags(DrawMetadata::FLAG_PICKABLE | DrawMetadata::FLAG_HIGHLIGHTED));
+    assert_eq!(DrawMetadata::default(), DrawMetadata::new(0, 0, 0));
+}
+
+#[test]
+fn texture_arrays_gather_one_layer_per_texture() {
+    let texture = |fill: u8, width: u32| TextureCreationData {
+        layer_data: Some(vec![vec![fill; (width * 2 * 4) as usize]]),
+        width,
+        height: 2,
+        depth: 1,
+        mip_levels: 1,
+        format: TexturePixelFormat::Rgba,
+        usage: ImageUsage::TextureSampleOnly
+    };
+    let array = ResourceUtilities::texture_array(vec![texture(1, 2), texture(2, 2)]).unwrap();
+    assert_eq!(array.usage, ImageUsage::TextureArray);
+    assert_eq!(array.layer_data.unwrap(), vec![vec![1; 16], vec![2; 16]]);
+    assert!(ResourceUtilities::texture_array(vec![texture(1, 2), texture(2, 4)]).is_err());
+    assert!(ResourceUtilities::texture_array(vec![]).is_err());
+}
+
+#[test]
+fn volume_textures_are_split_into_slices() {
+    let texels = (0..3u8).flat_map(|slice| vec![slice; 2 * 2 * 4]).collect();
+    let texture = ResourceUtilities::volume_texture(texels, 2, 2, 3, TexturePixelFormat::Rgba)
+        .unwrap();
+    assert_eq!((texture.depth, texture.usage), (3, ImageUsage::VolumeTexture));
+    assert_eq!(texture.layer_data.unwrap(), vec![vec![0; 16], vec![1; 16], vec![2; 16]]);
+    assert!(ResourceUtilities::volume_texture(vec![0; 40], 2, 2, 3, TexturePixelFormat::Rgba)
+        .is_err());
+}
+
+#[test]
+fn streaming_gives_detail_to_higher_priorities_within_budget() {
+    // Levels of 64, 16 and 4 bytes, with the smallest always resident
+    let candidate = |first_level: u32, priority: f32| StreamingCandidate {
+        request: StreamingRequest { first_level, priority },
+        coarsest_first_level: 2,
+        level_sizes_bytes: vec![64, 16, 4]
+    };
+    let candidates = [candidate(0, 1.0), candidate(0, 2.0), candidate(1, 0.5)];
+    assert_eq!(plan_residency(&candidates, 1000), vec![0, 0, 1]);
+
+    // The most important texture gets its full detail first; the rest make do with what fits
+    assert_eq!(plan_residency(&candidates, 12 + 80 + 16), vec![1, 0, 2]);
+
+    // Smallest levels stay resident even over budget
+    assert_eq!(plan_residency(&candidates, 0), vec![2, 2, 2]);
+
+    // The budget is what remains of the heap fraction once everything else is accounted for
+    let streamer = TextureStreamer::new(TextureStreamingConfig::default());
+    let statistics = MemoryStatistics {
+        allocation_count: 3,
+        allocated_bytes: 600,
+        bulk_heap_bytes: 1000
+    };
+    assert_eq!(streamer.budget_bytes(&statistics, 200), 100);
+    assert_eq!(streamer.budget_bytes(&statistics, 0), 0);
+}
+
+#[test]
+fn msaa_falls_back_to_the_highest_supported_count_not_above_the_request() {
+    let supported = vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_2 |
+        vk::SampleCountFlags::TYPE_8;
+    assert_eq!(highest_sample_count(supported, 8), vk::SampleCountFlags::TYPE_8);
+    assert_eq!(highest_sample_count(supported, 4), vk::SampleCountFlags::TYPE_2);
+    assert_eq!(highest_sample_count(supported, 1), vk::SampleCountFlags::TYPE_1);
+    assert_eq!(
+        highest_sample_count(vk::SampleCountFlags::TYPE_1, 4),
+        vk::SampleCountFlags::TYPE_1);
+}
+
+#[test]
+fn reversed_z_clears_to_the_far_end_and_keeps_greater_depths() {
+    let standard = DepthConvention::Standard;
+    let reversed = DepthConvention::ReversedZ;
+    assert_eq!(standard.clear_depth(), 1.0);
+    assert_eq!(standard.compare_op(), vk::CompareOp::LESS_OR_EQUAL);
+    assert_eq!(reversed.clear_depth(), 0.0);
+    assert_eq!(reversed.compare_op(), vk::CompareOp::GREATER_OR_EQUAL);
+}
+
+#[test]
+fn blend_modes_map_to_attachment_blend_state() {
+    let opaque = BlendMode::Opaque.attachment_state();
+    assert_eq!(opaque.blend_enable, vk::FALSE);
+    assert_eq!(opaque.color_write_mask, vk::ColorComponentFlags::RGBA);
+
+    let additive = BlendMode::Additive.attachment_state();
+    assert_eq!(additive.blend_enable, vk::TRUE);
+    assert_eq!(additive.src_color_blend_factor, vk::BlendFactor::SRC_ALPHA);
+    assert_eq!(additive.dst_color_blend_factor, vk::BlendFactor::ONE);
+
+    let premultiplied = BlendMode::PremultipliedAlpha.attachment_state();
+    assert_eq!(premultiplied.src_color_blend_factor, vk::BlendFactor::ONE);
+    assert_eq!(premultiplied.dst_color_blend_factor, vk::BlendFactor::ONE_MINUS_SRC_ALPHA);
+
+    let mut factors = BlendMode::AlphaBlend.factors().unwrap();
+    factors.color_op = vk::BlendOp::MAX;
+    let custom = BlendMode::Custom(factors).attachment_state();
+    assert_eq!(custom.color_blend_op, vk::BlendOp::MAX);
+    assert_eq!(custom.src_alpha_blend_factor, vk::BlendFactor::SRC_ALPHA);
+
+    assert_eq!(BlendMode::for_render_queue(RenderQueue::Transparent), BlendMode::AlphaBlend);
+    assert_eq!(BlendMode::for_render_queue(RenderQueue::Opaque), BlendMode::Opaque);
+}
+
+#[test]
+fn depth_state_compare_op_follows_the_convention_unless_overridden() {
+    let transparent = DepthState::for_render_queue(RenderQueue::Transparent);
+    assert!(transparent.test_enable && !transparent.write_enable);
+    assert_eq!(
+        transparent.compare_op_for(DepthConvention::ReversedZ),
+        vk::CompareOp::GREATER_OR_EQUAL);
+    let overridden = DepthState {
+        compare_op: Some(vk::CompareOp::EQUAL),
+        ..DepthState::test_only()
+    };
+    assert_eq!(overridden.compare_op_for(DepthConvention::Standard), vk::CompareOp::EQUAL);
+    assert!(!DepthState::disabled().test_enable);
+}
+
+#[test]
+fn only_the_depth_stencil_format_has_a_stencil_aspect() {
+    let format = TexturePixelFormat::Depth24Stencil8;
+    assert!(format.is_depth() && format.has_stencil());
+    assert_eq!(format.vk_format(), vk::Format::D24_UNORM_S8_UINT);
+    assert_eq!(
+        format.aspect_flags(),
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL);
+    assert!(TexturePixelFormat::Unorm16.is_depth() && !TexturePixelFormat::Unorm16.has_stencil());
+    assert!(!TexturePixelFormat::Rgba.is_depth());
+    assert_eq!(TexturePixelFormat::Rgba.aspect_flags(), vk::ImageAspectFlags::COLOR);
+}
+
+#[test]
+fn stencil_presets_mark_then_test_the_reference_value() {
+    assert!(!StencilState::default().test_enable);
+    let mark = StencilState::write_reference(1);
+    assert!(mark.test_enable);
+    assert_eq!(mark.front.pass_op, vk::StencilOp::REPLACE);
+    assert_eq!(mark.front.compare_op, vk::CompareOp::ALWAYS);
+    let outline = StencilState::test_not_equal(1);
+    assert_eq!(outline.back.compare_op, vk::CompareOp::NOT_EQUAL);
+    assert_eq!(outline.back.write_mask, 0);
+    assert_eq!(outline.front.op_state().reference, 1);
+}
+
+#[test]
+fn only_line_and_point_polygon_modes_need_non_solid_fill() {
+    assert!(!RasterizerState::default().needs_non_solid_fill());
+    assert!(!RasterizerState::double_sided().needs_non_solid_fill());
+    assert_eq!(RasterizerState::double_sided().cull_mode, vk::CullModeFlags::NONE);
+    assert!(RasterizerState::wireframe().needs_non_solid_fill());
+}
+
+#[test]
+fn work_group_counts_cover_every_item() {
+    assert_eq!(work_group_count(0, 64), 0);
+    assert_eq!(work_group_count(64, 64), 1);
+    assert_eq!(work_group_count(65, 64), 2);
+    assert_eq!(work_group_count(1000, 16), 63);
+}
+
+#[test]
+fn specialization_constants_are_packed_as_consecutive_words() {
+    let data = SpecializationData::new(&[
+        SpecializationConstant::from_bool(3, true),
+        SpecializationConstant::from_float(7, 0.5)
+    ]);
+    assert_eq!(data.map_entries.len(), 2);
+    assert_eq!(data.map_entries[1].constant_id, 7);
+    assert_eq!(data.map_entries[1].offset, 4);
+    assert_eq!(data.map_entries[1].size, 4);
+    assert_eq!(&data.data[0..4], &vk::TRUE.to_ne_bytes());
+    assert_eq!(&data.data[4..8], &0.5f32.to_ne_bytes());
+    assert!(SpecializationData::new(&[]).is_empty());
+}
+
+#[test]
+fn glsl_compiles_at_runtime_only_with_the_runtime_shaders_feature() {
+    let result = compile_glsl("#version 450\nvoid main() {}\n", ShaderStage::Compute);
+    if cfg!(feature = "runtime-shaders") {
+        assert_eq!(result.unwrap()[0], 0x07230203);
+    } else {
+        assert!(matches!(result, Err(EngineError::Compatibility(_))));
+    }
+}
+
+#[test]
+fn descriptor_pools_grow_geometrically_up_to_a_limit() {
+    let mut set_count = 32;
+    let mut counts = vec![];
+    for _ in 0..7 {
+        set_count = next_pool_set_count(set_count);
+        counts.push(set_count);
+    }
+    assert_eq!(counts, vec![64, 128, 256, 512, 1024, 1024, 1024]);
+}
+
+#[test]
+fn custom_renderpasses_check_how_subpasses_use_attachments() {
+    let deferred = CustomRenderpassDescription {
+        intermediate_formats: vec![TexturePixelFormat::Rgba, TexturePixelFormat::Rgba],
+        subpasses: vec![
+            CustomSubpass {
+                color_attachments: vec![
+                    CustomAttachment::Intermediate(0),
+                    CustomAttachment::Intermediate(1)
+                ],
+                input_attachments: vec![],
+                depth_attachment: Some(CustomAttachment::Depth)
+            },
+            CustomSubpass {
+                color_attachments: vec![CustomAttachment::Output],
+                input_attachments: vec![
+                    CustomAttachment::Intermediate(0),
+                    CustomAttachment::Intermediate(1)
+                ],
+                depth_attachment: None
+            }
+        ],
+        dependencies: vec![CustomSubpassDependency { src_subpass: 0, dst_subpass: 1 }]
+    };
+    assert!(deferred.validate().is_ok());
+    assert_eq!(deferred.clear_values(color::Color::BLACK, 1.0).len(), 4);
+
+    let mut missing_attachment = deferred.clone();
+    missing_attachment.subpasses[1].input_attachments.push(CustomAttachment::Intermediate(2));
+    assert!(matches!(missing_attachment.validate(), Err(EngineError::UserError(_))));
+
+    let mut output_as_input = deferred.clone();
+    output_as_input.subpasses[1].input_attachments = vec![CustomAttachment::Output];
+    assert!(matches!(output_as_input.validate(), Err(EngineError::UserError(_))));
+
+    let mut depth_as_color = deferred.clone();
+    depth_as_color.subpasses[0].color_attachments.push(CustomAttachment::Depth);
+    assert!(matches!(depth_as_color.validate(), Err(EngineError::UserError(_))));
+
+    let mut backwards = deferred.clone();
+    backwards.dependencies = vec![CustomSubpassDependency { src_subpass: 1, dst_subpass: 0 }];
+    assert!(matches!(backwards.validate(), Err(EngineError::UserError(_))));
+}
+
+#[test]
+fn renderpasses_take_one_clear_value_per_attachment() {
+    let color = color::Color::BLACK;
+
+    // Multisampled swapchain target: the resolved image, depth, then the multisampled color
+    let msaa = RenderpassWrapper::attachment_kinds(&RenderpassWrapper::swapchain_attachments(
+        vk::Format::B8G8R8A8_SRGB,
+        vk::Format::D16_UNORM,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::SampleCountFlags::TYPE_4));
+    assert_eq!(msaa, vec![false, true, false]);
+    assert_eq!(RenderpassWrapper::clear_values_of_kinds(&msaa, color, 1.0).len(), 3);
+    let single = RenderpassWrapper::attachment_kinds(&RenderpassWrapper::swapchain_attachments(
+        vk::Format::B8G8R8A8_SRGB,
+        vk::Format::D16_UNORM,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::SampleCountFlags::TYPE_1));
+    assert_eq!(RenderpassWrapper::clear_values_of_kinds(&single, color, 1.0).len(), 2);
+
+    // Depth-only offscreen target, such as for a shadow map
+    let depth_only = RenderpassWrapper::attachment_kinds(&RenderpassWrapper::offscreen_attachments(
+        &[],
+        Some(vk::Format::D16_UNORM),
+        vk::SampleCountFlags::TYPE_1));
+    assert_eq!(depth_only, vec![true]);
+    let clear_values = RenderpassWrapper::clear_values_of_kinds(&depth_only, color, 0.0);
+    assert_eq!(clear_values.len(), 1);
+    assert_eq!(unsafe { clear_values[0].depth_stencil.depth }, 0.0);
+
+    // Multisampled offscreen target with two color textures and depth
+    let formats = [vk::Format::R8G8B8A8_UNORM, vk::Format::R16G16B16A16_SFLOAT];
+    let msaa_offscreen = RenderpassWrapper::attachment_kinds(
+        &RenderpassWrapper::offscreen_attachments(
+            &formats,
+            Some(vk::Format::D16_UNORM),
+            vk::SampleCountFlags::TYPE_4));
+    assert_eq!(msaa_offscreen, vec![false, false, true, false, false]);
+    assert_eq!(RenderpassWrapper::clear_values_of_kinds(&msaa_offscreen, color, 1.0).len(), 5);
+}
+
+#[test]
+fn hdr_formats_are_uncompressed_color_attachment_formats() {
+    let half_float = TexturePixelFormat::RgbaF16;
+    assert_eq!(half_float.vk_format(), vk::Format::R16G16B16A16_SFLOAT);
+    assert_eq!(half_float.layer_size_bytes(5, 3), 120);
+    assert!(half_float.is_color_attachment_format() && !half_float.is_compressed());
+
+    let packed = TexturePixelFormat::Rgb10A2;
+    assert_eq!(packed.vk_format(), vk::Format::A2B10G10R10_UNORM_PACK32);
+    assert_eq!(packed.layer_size_bytes(5, 3), 60);
+    assert!(packed.is_color_attachment_format() && !packed.is_compressed());
+
+    assert!(!TexturePixelFormat::Bc7.is_color_attachment_format());
+    assert!(!TexturePixelFormat::Unorm16.is_color_attachment_format());
+}
+
+#[test]
+fn disabled_debug_labels_record_nothing() {
+    let labels = DebugLabels::disabled();
+    assert!(!labels.is_enabled());
+    assert!(!DebugLabels::default().is_enabled());
+    unsafe {
+        labels.begin(vk::CommandBuffer::null(), "Main");
+        labels.end(vk::CommandBuffer::null());
+        labels.set_object_name(vk::Device::null(), vk::Image::null(), "Depth image");
+    }
+}
+
+#[test]
+fn post_processing_passes_need_word_sized_push_constants() {
+    let pass = |size: usize| PostProcessPass {
+        fragment_shader_index: 0,
+        push_constants: vec![0; size]
+    };
+    assert!(matches!(validate_passes(&[]), Err(EngineError::UserError(_))));
+    assert!(validate_passes(&[pass(0), pass(8)]).is_ok());
+    assert!(validate_passes(&[pass(MAX_POST_PROCESS_PUSH_CONSTANT_SIZE)]).is_ok());
+    assert!(validate_passes(&[pass(8), pass(6)]).is_err());
+    assert!(validate_passes(&[pass(MAX_POST_PROCESS_PUSH_CONSTANT_SIZE + 4)]).is_err());
+}
+
+#[test]
+fn feature_report_sorts_declared_features_once_each() {
+    let declared = [
+        FeatureDeclaration::WideLines,
+        FeatureDeclaration::ShaderFloat16,
+        FeatureDeclaration::WideLines,
+        FeatureDeclaration::MultiDrawIndirect
+    ];
+    let report = FeatureReport::new(
+        &declared,
+        |feature| feature != FeatureDeclaration::ShaderFloat16);
+    assert_eq!(
+        report.get_granted(),
+        &[FeatureDeclaration::WideLines, FeatureDeclaration::MultiDrawIndirect]);
+    assert_eq!(report.get_denied(), &[FeatureDeclaration::ShaderFloat16]);
+    assert!(report.is_granted(FeatureDeclaration::WideLines));
+    assert!(!report.is_granted(FeatureDeclaration::ShaderFloat16));
+    assert!(!report.is_granted(FeatureDeclaration::ClipPlanes));
+}
+
+#[test]
+fn portability_subset_without_separate_stencil_needs_matching_faces() {
+    let subset = PortabilitySubset::default();
+    let matching = StencilState::write_reference(1);
+    let mismatched = StencilState {
+        back: StencilFaceState::write_reference(2),
+        ..matching
+    };
+    assert!(subset.supports_stencil_state(&matching, vk::CullModeFlags::NONE));
+    assert!(!subset.supports_stencil_state(&mismatched, vk::CullModeFlags::NONE));
+    assert!(subset.supports_stencil_state(&mismatched, vk::CullModeFlags::BACK));
+    assert!(subset.supports_stencil_state(&StencilState::disabled(), vk::CullModeFlags::NONE));
+    assert!(!subset.supports_polygon_mode(vk::PolygonMode::POINT));
+    assert!(subset.supports_polygon_mode(vk::PolygonMode::LINE));
+    let full = PortabilitySubset { separate_stencil_mask_ref: true, ..subset };
+    assert!(full.supports_stencil_state(&mismatched, vk::CullModeFlags::NONE));
+}
+
+#[test]
+fn validation_severity_filter_includes_more_serious_messages() {
+    let warnings = ValidationSeverity::Warning.flags_at_least();
+    assert_eq!(
+        warnings,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+    assert_eq!(
+        ValidationSeverity::Error.flags_at_least(),
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+    assert!(ValidationSeverity::Verbose.flags_at_least()
+        .contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO));
+    assert_eq!(
+        ValidationSeverity::from_flags(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING),
+        ValidationSeverity::Warning);
+}
+
+#[test]
+fn validation_errors_keep_the_first_until_taken() {
+    let errors = ValidationErrors::default();
+    let shared = errors.clone();
+    assert_eq!(errors.take(), None);
+    shared.record("first");
+    shared.record("second");
+    assert_eq!(errors.take(), Some(String::from("first")));
+    assert_eq!(errors.take(), None);
+    errors.check();
+}
+
+#[test]
+#[should_panic(expected = "Vulkan validation error: bad usage")]
+fn validation_errors_panic_when_checked() {
+    let errors = ValidationErrors::default();
+    errors.record("bad usage");
+    errors.check();
+}
+
+#[test]
+fn release_and_acquire_barriers_name_the_same_families_in_both_directions() {
+    let to_graphics = QueueFamilyPair::new(1, 0);
+    let to_transfer = QueueFamilyPair::new(0, 1);
+    let release = to_transfer.release_buffer_barrier(
+        vk::Buffer::null(),
+        vk::AccessFlags::MEMORY_WRITE);
+    let acquire = to_transfer.acquire_buffer_barrier(
+        vk::Buffer::null(),
+        vk::AccessFlags::TRANSFER_READ);
+    assert_eq!(release.src_queue_family_index, 0);
+    assert_eq!(release.dst_queue_family_index, 1);
+    assert_eq!(release.dst_access_mask, vk::AccessFlags::empty());
+    assert_eq!(acquire.src_queue_family_index, release.src_queue_family_index);
+    assert_eq!(acquire.dst_queue_family_index, release.dst_queue_family_index);
+    assert_eq!(acquire.src_access_mask, vk::AccessFlags::empty());
+    assert_ne!(to_graphics, to_transfer);
+
+    let same_family = QueueFamilyPair::new(0, 0);
+    let barrier = same_family.release_buffer_barrier(
+        vk::Buffer::null(),
+        vk::AccessFlags::TRANSFER_WRITE);
+    assert!(!same_family.requires_ownership_transfer());
+    assert_eq!(barrier.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    assert_eq!(barrier.dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+}
+
+#[test]
+fn adapters_match_by_uuid_where_both_report_one() {
+    let adapter = AdapterInfo {
+        name: String::from("GPU"),
+        device_type: vk::PhysicalDeviceType::DISCRETE_GPU,
+        vendor_id: 0x10de,
+        device_id: 0x2204,
+        device_uuid: Some([1; vk::UUID_SIZE])
+    };
+    let twin = AdapterInfo { device_uuid: Some([2; vk::UUID_SIZE]), ..adapter.clone() };
+    assert!(!adapter.is_same_device(&twin));
+    let without_uuid = AdapterInfo { device_uuid: None, ..adapter.clone() };
+    assert!(adapter.is_same_device(&without_uuid));
+    let other_device = AdapterInfo { device_id: 0x2206, ..without_uuid.clone() };
+    assert!(!adapter.is_same_device(&other_device));
+}
+
+#[test]
+fn api_version_is_the_newest_allowed_without_patch() {
+    let loader_1_3 = vk::make_api_version(0, 1, 3, 250);
+    assert_eq!(negotiate_api_version(Some(loader_1_3), vk::API_VERSION_1_3), vk::API_VERSION_1_3);
+    assert_eq!(negotiate_api_version(Some(loader_1_3), vk::API_VERSION_1_0), vk::API_VERSION_1_0);
+    let loader_1_4 = vk::make_api_version(0, 1, 4, 0);
+    assert_eq!(negotiate_api_version(Some(loader_1_4), u32::MAX), vk::API_VERSION_1_3);
+    assert_eq!(negotiate_api_version(None, vk::API_VERSION_1_3), vk::API_VERSION_1_0);
+}
+
+#[test]
+fn synchronization2_flags_keep_bits_but_drop_execution_only_stages() {
+    let src = stage_flags2(
+        vk::PipelineStageFlags::TOP_OF_PIPE | vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::TOP_OF_PIPE);
+    assert_eq!(src, vk::PipelineStageFlags2::TRANSFER);
+    let dst = stage_flags2(
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+    assert_eq!(dst, vk::PipelineStageFlags2::NONE);
+    assert_eq!(
+        access_flags2(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+        vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+}
+
+#[test]
+fn transparent_swapchains_blend_only_where_supported() {
+    let opaque_only = vk::CompositeAlphaFlagsKHR::OPAQUE;
+    let all = vk::CompositeAlphaFlagsKHR::OPAQUE |
+        vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED |
+        vk::CompositeAlphaFlagsKHR::INHERIT;
+    assert_eq!(choose_composite_alpha(all, true), vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED);
+    assert_eq!(choose_composite_alpha(all, false), vk::CompositeAlphaFlagsKHR::OPAQUE);
+    assert_eq!(choose_composite_alpha(opaque_only, true), vk::CompositeAlphaFlagsKHR::OPAQUE);
+    assert_eq!(
+        choose_composite_alpha(vk::CompositeAlphaFlagsKHR::INHERIT, false),
+        vk::CompositeAlphaFlagsKHR::INHERIT);
+}
+
+#[test]
+fn material_indices_must_select_an_entry_of_the_bindless_array() {
+    assert!(check_material_index(0, 1).is_ok());
+    assert!(check_material_index(15, 16).is_ok());
+    assert!(matches!(check_material_index(16, 16), Err(EngineError::UserError(_))));
+}
+
+#[test]
+fn devices_rank_by_declared_features_granted() {
+    let declared = [
+        FeatureDeclaration::ClipPlanes,
+        FeatureDeclaration::SamplerAnisotropy,
+        FeatureDeclaration::DynamicRendering
+    ];
+    let full_support = vk::PhysicalDeviceFeatures {
+        shader_clip_distance: vk::TRUE,
+        sampler_anisotropy: vk::TRUE,
+        wide_lines: vk::TRUE,
+        ..Default::default()
+    };
+    let partial_support = vk::PhysicalDeviceFeatures {
+        sampler_anisotropy: vk::TRUE,
+        ..Default::default()
+    };
+    let full_enabled = make_feature_set_to_enable(&declared, &full_support);
+    let partial_enabled = make_feature_set_to_enable(&declared, &partial_support);
+    assert_eq!(full_enabled.wide_lines, vk::FALSE);
+    assert_eq!(granted_feature_count(&declared, &full_enabled), 2);
+    assert_eq!(granted_feature_count(&declared, &partial_enabled), 1);
+    let no_support = make_feature_set_to_enable(&declared, &vk::PhysicalDeviceFeatures::default());
+    assert_eq!(granted_feature_count(&declared, &no_support), 0);
+}
+
+#[test]
+fn transient_allocations_align_and_respect_capacity() {
+    assert_eq!(transient_offset(0, 100, 1024), Some(0));
+    assert_eq!(transient_offset(100, 100, 1024), Some(256));
+    assert_eq!(transient_offset(256, 768, 1024), Some(256));
+    assert_eq!(transient_offset(257, 100, 1024), Some(512));
+    assert_eq!(transient_offset(257, 600, 1024), None);
+    assert_eq!(transient_offset(0, 1025, 1024), None);
+    assert_eq!(transient_offset(usize::MAX - 10, 1, usize::MAX), None);
+}
+
+#[test]
+fn compute_handoff_passes_resources_between_distinct_families_and_back() {
+    let buffer = BufferWrapper::empty();
+    let image = ImageWrapper::empty();
+    let accesses = [ComputeAccess::Buffer(&buffer), ComputeAccess::Storage(&image)];
+    let same_family = ComputeHandoff::for_accesses(QueueFamilyPair::new(0, 0), &accesses);
+    assert!(same_family.is_empty());
+
+    let to_compute = ComputeHandoff::for_accesses(QueueFamilyPair::new(0, 2), &accesses);
+    let (release_buffers, release_images) = to_compute.release_barriers();
+    let (acquire_buffers, acquire_images) = to_compute.acquire_barriers();
+    assert_eq!(release_buffers.len(), 1);
+    assert_eq!(release_images.len(), 1);
+    assert_eq!(release_buffers[0].src_queue_family_index, 0);
+    assert_eq!(release_buffers[0].dst_queue_family_index, 2);
+    assert_eq!(acquire_buffers[0].src_queue_family_index, 0);
+    assert_eq!(acquire_buffers[0].dst_queue_family_index, 2);
+    assert_eq!(release_images[0].old_layout, image.get_layout());
+    assert_eq!(release_images[0].new_layout, vk::ImageLayout::GENERAL);
+    assert_eq!(acquire_images[0].old_layout, release_images[0].old_layout);
+    assert_eq!(acquire_images[0].new_layout, release_images[0].new_layout);
+
+    let to_graphics = to_compute.returned();
+    let (release_buffers, release_images) = to_graphics.release_barriers();
+    let (_, acquire_images) = to_graphics.acquire_barriers();
+    assert_eq!(release_buffers[0].src_queue_family_index, 2);
+    assert_eq!(release_buffers[0].dst_queue_family_index, 0);
+    assert_eq!(release_images[0].old_layout, vk::ImageLayout::GENERAL);
+    assert_eq!(release_images[0].new_layout, vk::ImageLayout::GENERAL);
+    assert_eq!(acquire_images[0].old_layout, vk::ImageLayout::GENERAL);
+}
+
+#[test]
+fn block_space_fills_gaps_first_and_reserves_only_free_ranges() {
+    let mut space = BlockSpace::new(1024);
+    assert_eq!(space.allocate(100, 64), Some(0));
+    assert_eq!(space.allocate(100, 64), Some(128));
+    assert_eq!(space.allocate(100, 256), Some(256));
+    assert!(space.free(128));
+    assert!(!space.free(128));
+    assert_eq!(space.allocate(50, 16), Some(112));
+    assert!(!space.reserve(200, 100, 8));
+    assert!(!space.reserve(1000, 100, 8));
+    assert!(!space.reserve(404, 100, 8));
+    assert!(space.reserve(400, 100, 8));
+    assert_eq!(space.used_bytes(), 350);
+    assert_eq!(space.allocate(1024, 1), None);
+}
+
+#[test]
+fn compaction_empties_sparse_blocks_into_the_others() {
+    // Four blocks a quarter full, in allocations scattered through them
+    let mut blocks: Vec<BlockSpace> = (0..4).map(|_| BlockSpace::new(1024)).collect();
+    for block in blocks.iter_mut() {
+        for offset in [0, 384, 768] {
+            assert!(block.reserve(offset, 64, 64));
+        }
+        assert!(block.reserve(512, 64, 64));
+    }
+    let occupied = |blocks: &[BlockSpace]| blocks.iter().filter(|b| !b.is_empty()).count();
+    assert_eq!(occupied(&blocks), 4);
+
+    // Carrying out the moves in order leaves only one block holding anything
+    let apply = |blocks: &mut [BlockSpace], moves: &[BlockMove]| {
+        for planned in moves.iter() {
+            assert!(blocks[planned.from_block].free(planned.from_offset));
+            assert!(blocks[planned.to_block].reserve(
+                planned.to_offset,
+                planned.size,
+                planned.alignment));
+        }
+    };
+    let moves = plan_compaction(&blocks, |_, _| false);
+    apply(&mut blocks, &moves);
+    assert_eq!(moves.len(), 12);
+    assert_eq!(occupied(&blocks), 1);
+    assert_eq!(blocks.iter().map(|b| b.used_bytes()).sum::<u64>(), 16 * 64);
+
+    // A block holding anything pinned is never a source, but can still take allocations
+    let mut blocks: Vec<BlockSpace> = (0..3).map(|_| BlockSpace::new(1024)).collect();
+    assert!(blocks[0].reserve(0, 128, 64));
+    assert!(blocks[1].reserve(256, 64, 64));
+    assert!(blocks[2].reserve(512, 64, 64));
+    let moves = plan_compaction(&blocks, |block, range| block == 1 && range.offset == 256);
+    assert!(moves.iter().all(|planned| planned.from_block != 1));
+    apply(&mut blocks, &moves);
+    assert_eq!(occupied(&blocks), 1);
+    assert_eq!(blocks[1].used_bytes(), 256);
+
+    // Nothing moves when no block can be emptied completely
+    let mut blocks: Vec<BlockSpace> = (0..2).map(|_| BlockSpace::new(1024)).collect();
+    assert!(blocks[0].reserve(0, 768, 64));
+    assert!(blocks[1].reserve(0, 512, 64));
+    assert!(plan_compaction(&blocks, |_, _| false).is_empty());
+}
+
+/// Point within a cluster of the grid, at the centre of its tile and the middle of its slice
+fn point_in_cluster(grid: &ClusterGrid, cluster: [u32; 3]) -> [f32; 3] {
+    let (depth_min, depth_max) = grid.slice_depth_range(cluster[2]);
+    let depth = (depth_min * depth_max).sqrt();
+    let mut position = [0.0, 0.0, depth];
+    for axis in 0..2 {
+        let ndc = (cluster[axis] as f32 + 0.5) / grid.dimensions[axis] as f32 * 2.0 - 1.0;
+        position[axis] = ndc * depth / grid.projection_scale[axis];
+    }
+    position
+}
+
+#[test]
+fn cluster_grid_slices_depth_exponentially_and_locates_positions() {
+    let projection = [
+        [0.5, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 1.0],
+        [0.0, 0.0, -1.0, 0.0]
+    ];
+    let grid = ClusterGrid::for_projection([4, 2, 8], 1.0, 100.0, projection);
+    assert_eq!(grid.projection_scale, [0.5, 1.0]);
+    assert_eq!(grid.cluster_count(), 64);
+    assert_eq!(grid.cluster_index([3, 1, 7]), 63);
+    assert_eq!(grid.cluster_index([1, 1, 2]), 21);
+
+    // Each slice spans the same ratio of depths, a quarter of a power of ten here
+    assert_eq!(grid.depth_slice(0.5), None);
+    assert_eq!(grid.depth_slice(1.0), Some(0));
+    assert_eq!(grid.depth_slice(10.5), Some(4));
+    assert_eq!(grid.depth_slice(99.0), Some(7));
+    assert_eq!(grid.depth_slice(100.0), None);
+    let (near, far) = grid.slice_depth_range(4);
+    assert!((near - 10.0).abs() < 1e-3);
+    assert!((far - 10.0f32.powf(1.25)).abs() < 1e-3);
+    for slice in 0..8 {
+        let (near, far) = grid.slice_depth_range(slice);
+        assert_eq!(grid.depth_slice((near * far).sqrt()), Some(slice));
+    }
+
+    // Tiles divide the screen evenly, through the projection scale
+    assert_eq!(grid.cluster_at([0.9 * 5.0 / 0.5, -0.9 * 5.0, 5.0]), Some([3, 0, 2]));
+    assert_eq!(grid.cluster_at(point_in_cluster(&grid, [1, 1, 6])), Some([1, 1, 6]));
+    assert_eq!(grid.cluster_at([1.5 * 5.0 / 0.5, 0.0, 5.0]), None);
+    assert_eq!(grid.cluster_at([0.0, 0.0, -5.0]), None);
+}
+
+#[test]
+fn lights_are_binned_into_the_clusters_they_touch() {
+    let grid = ClusterGrid {
+        dimensions: [4, 4, 8],
+        near: 1.0,
+        far: 100.0,
+        projection_scale: [1.0, 1.0]
+    };
+    let light_at = |position: [f32; 3], radius: f32| PointLight {
+        position,
+        radius,
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0
+    };
+    let lit_clusters = |bins: &[Vec<u32>]| bins.iter().filter(|b| !b.is_empty()).count();
+
+    // A small light lands only in the cluster holding it, a large one in its neighbours too
+    let cluster = [2, 1, 3];
+    let position = point_in_cluster(&grid, cluster);
+    let bins = grid.bin_lights(&[light_at(position, 0.01)]);
+    assert_eq!(bins.len(), grid.cluster_count());
+    assert_eq!(bins[grid.cluster_index(cluster)], vec![0]);
+    assert_eq!(lit_clusters(&bins), 1);
+    let bins = grid.bin_lights(&[light_at(position, 3.0)]);
+    assert!(lit_clusters(&bins) > 1);
+    for (index, bin) in bins.iter().enumerate() {
+        let cluster = [index as u32 % 4, (index as u32 / 4) % 4, index as u32 / 16];
+        let touches = grid.light_touches_cluster(&light_at(position, 3.0), cluster);
+        assert_eq!(bin.is_empty(), !touches);
+    }
+
+    // Lights beyond the far plane, behind the camera or off to the side touch nothing
+    let outside = [
+        light_at([0.0, 0.0, 150.0], 1.0),
+        light_at([0.0, 0.0, -5.0], 1.0),
+        light_at([50.0, 0.0, 5.0], 1.0)
+    ];
+    assert_eq!(lit_clusters(&grid.bin_lights(&outside)), 0);
+
+    // Clusters list lights in order, up to their capacity
+    let crowded: Vec<PointLight> = (0..MAX_LIGHTS_PER_CLUSTER + 9)
+        .map(|_| light_at(position, 0.01))
+        .collect();
+    let bins = grid.bin_lights(&crowded);
+    let expected: Vec<u32> = (0..MAX_LIGHTS_PER_CLUSTER as u32).collect();
+    assert_eq!(bins[grid.cluster_index(cluster)], expected);
+}
@@ -0,0 +1,66 @@
+/// Test rendering contexts on more than one adapter.
+/// Each VkCore has its own instance, so a headless core can be created on any adapter listed by
+/// another, and images passed between their contexts through host memory with SharedImage.
+///
+/// The test creates a headless VkCore and VkContext, then a second pair on the last adapter that
+/// the first core lists, which is the same adapter on machines with only one. An image written
+/// on the second context is shared to the first, presented there and read back.
+
+use vk_renderer::{
+    VkCore, VkCoreConfig, VkContext, ImageWrapper, ImageUsage, TexturePixelFormat, AcquireResult,
+    PresentResult, SharedImage
+};
+use ecs::resource::Resource;
+
+/// Test: share an image written on a secondary context to the primary one and present it.
+/// Expected: the selected adapter is among those listed, the secondary core selects the adapter
+/// asked for, and every captured pixel matches the shared image, with no validation errors.
+fn main() {
+    unsafe {
+        let config = VkCoreConfig {
+            panic_on_error: true,
+            ..VkCoreConfig::default()
+        };
+        let mut core = VkCore::new_headless_with_config(vec![], config.clone()).unwrap();
+        assert!(core.get_adapters().contains(core.get_adapter()));
+        let adapter = core.get_adapters().last().unwrap().clone();
+        let mut secondary_core = VkCore::new_headless_on_adapter(vec![], &adapter, config)
+            .unwrap();
+        assert!(secondary_core.get_adapter().is_same_device(&adapter));
+
+        // Write an image on the secondary context and copy it back to the host
+        let mut secondary_context = VkContext::new_headless(&secondary_core, 16, 16).unwrap();
+        let baked_image = ImageWrapper::new(
+            &secondary_context,
+            ImageUsage::DynamicTexture,
+            TexturePixelFormat::Rgba,
+            16,
+            16,
+            None).unwrap();
+        baked_image.update_region(&secondary_context, 0, 0, 16, 16, &[0x40; 16 * 16 * 4])
+            .unwrap();
+        let shared = SharedImage::read_from(&secondary_context, &baked_image).unwrap();
+        assert_eq!((shared.width, shared.height), (16, 16));
+        assert_eq!(shared.format, TexturePixelFormat::Rgba);
+
+        // Create it on the primary context and present it there
+        let mut context = VkContext::new_headless(&core, 16, 16).unwrap();
+        let image = shared.upload_to(&context, ImageUsage::DynamicTexture).unwrap();
+        let image_index = match context.acquire_next_image().unwrap() {
+            AcquireResult::Acquired(image_index) => image_index,
+            _ => panic!("Headless context failed to acquire an image")
+        };
+        assert_eq!(context.present_image(&image).unwrap(), PresentResult::Ok);
+        let pixels = context.capture_swapchain_image(image_index).unwrap();
+        assert_eq!(pixels, shared.texels);
+
+        context.wait_until_device_idle().unwrap();
+        image.release(&context);
+        context.teardown();
+        secondary_context.wait_until_device_idle().unwrap();
+        baked_image.release(&secondary_context);
+        secondary_context.teardown();
+        secondary_core.teardown();
+        core.teardown();
+    }
+}
@@ -0,0 +1,102 @@
+/// Test compute dispatches on the graphics queue and as async compute.
+/// A headless VkCore and VkContext are used, so this runs where there is no display. Where the
+/// device has a compute queue of its own, async compute hands the buffer it writes between queue
+/// families, which validation checks.
+///
+/// The test fills a storage buffer with a compute shader, first on the graphics queue and then as
+/// async compute within a frame, reads it back after each, and destroys everything.
+
+use vk_renderer::{
+    VkCore, VkCoreConfig, VkContext, AcquireResult, PresentResult, BufferWrapper,
+    ShaderCreationData, ShaderSource, ShaderStage, ComputePipelineCreationData,
+    ComputePipelineWrapper, ComputeAccess, DescriptorBinding, work_group_count
+};
+use ecs::{EcsManager, Handle, resource::Resource};
+use error::EngineError;
+use ash::vk;
+use vk_shader_macros::include_glsl;
+
+const VALUE_COUNT: usize = 256;
+const WORK_GROUP_SIZE: u32 = 64;
+
+const SHADER_INDEX_FILL: u32 = 0;
+const FILL_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/fill.comp");
+
+/// Read back every value in the buffer
+unsafe fn read_values(context: &VkContext, buffer: &BufferWrapper) -> Vec<u32> {
+    let (allocator, _) = context.get_mem_allocator();
+    buffer.read::<u32>(allocator, 0, VALUE_COUNT).unwrap()
+}
+
+/// Test: fill a buffer on the graphics queue, then again as async compute waited on by a frame.
+/// Expected: the buffer holds the value written by each dispatch in turn, a second async compute
+/// submission in the frame is refused, and there are no validation errors.
+fn main() {
+    unsafe {
+        let config = VkCoreConfig {
+            panic_on_error: true,
+            ..VkCoreConfig::default()
+        };
+        let mut core = VkCore::new_headless_with_config(vec![], config).unwrap();
+        let mut context = VkContext::new_headless(&core, 16, 16).unwrap();
+        let mut ecs = EcsManager::new();
+
+        let creation_data = ShaderCreationData {
+            source: ShaderSource::SpirV(FILL_SHADER),
+            stage: ShaderStage::Compute
+        };
+        let shader = vk::ShaderModule::create(&context, &ecs, &creation_data).unwrap();
+        ecs.push_new_with_handle(Handle::for_resource(SHADER_INDEX_FILL), shader);
+        let creation_data = ComputePipelineCreationData {
+            shader_index: SHADER_INDEX_FILL,
+            descriptor_types: vec![vk::DescriptorType::STORAGE_BUFFER],
+            push_constant_size_bytes: std::mem::size_of::<u32>() as u32,
+            specialization_constants: vec![]
+        };
+        let pipeline = ComputePipelineWrapper::create(&context, &ecs, &creation_data).unwrap();
+        let buffer = BufferWrapper::new_storage(&context, &[0u32; VALUE_COUNT]).unwrap();
+        pipeline.write_bindings(&context, &[(0, DescriptorBinding::StorageBuffer {
+            buffer: buffer.buffer(),
+            offset: 0,
+            range: vk::WHOLE_SIZE
+        })]);
+        let group_counts = [work_group_count(VALUE_COUNT as u32, WORK_GROUP_SIZE), 1, 1];
+        let accesses = [ComputeAccess::Buffer(&buffer)];
+
+        // Dispatch on the graphics queue
+        context.dispatch_compute(&pipeline, group_counts, &accesses, &7u32.to_ne_bytes())
+            .unwrap()
+            .release(&context.device)
+            .unwrap();
+        assert!(read_values(&context, &buffer).iter().all(|value| *value == 7));
+
+        // Dispatch as async compute, which the frame waits for
+        let image_index = match context.acquire_next_image().unwrap() {
+            AcquireResult::Acquired(image_index) => image_index,
+            _ => panic!("Headless context failed to acquire an image")
+        };
+        let command_buffer = context.get_graphics_command_buffer(image_index);
+        let begin_info = vk::CommandBufferBeginInfo::builder();
+        context.device.begin_command_buffer(command_buffer, &begin_info).unwrap();
+        context.device.end_command_buffer(command_buffer).unwrap();
+        let submitted = context.dispatch_async_compute(
+            &pipeline,
+            group_counts,
+            &accesses,
+            &9u32.to_ne_bytes(),
+            vk::PipelineStageFlags::VERTEX_SHADER).unwrap();
+        assert!(matches!(
+            context.begin_async_compute(&accesses),
+            Err(EngineError::UserError(_))));
+        assert_eq!(context.submit_and_present().unwrap(), PresentResult::Ok);
+        context.wait_until_device_idle().unwrap();
+        submitted.release(&context.device).unwrap();
+        assert!(read_values(&context, &buffer).iter().all(|value| *value == 9));
+
+        pipeline.release(&context);
+        buffer.release(&context);
+        ecs.free_all_resources(&context).unwrap();
+        context.teardown();
+        core.teardown();
+    }
+}
@@ -3,9 +3,14 @@
 /// The context relies on the VkCore, and the core relies on having an actual window to use for the
 /// instance and the surface.
 ///
-/// The test creates a window, then creates and destroys a VkCore and VkContext.
+/// The test creates a window, then creates a VkCore and VkContext, presents a host-written image
+/// directly to the swapchain, and destroys them. Where the surface allows, the presented frame is
+/// retained and stretched over a rebuilt swapchain, as happens while a resize settles.
 
-use vk_renderer::{VkCore, VkContext};
+use vk_renderer::{
+    VkCore, VkContext, ImageWrapper, ImageUsage, TexturePixelFormat, AcquireResult
+};
+use ecs::resource::Resource;
 use window::{
     WindowEventLooper, RenderCycleEvent, RenderEventHandler, ControlFlow, Event, WindowEvent,
     WindowEventHandler, WindowStateEvent, Window, MessageProxy, WindowCommand
@@ -25,6 +30,31 @@ impl VulkanTestApp {
         unsafe {
             let mut core = VkCore::new(window, vec![]).unwrap();
             let mut context = VkContext::new(&core, window).unwrap();
+            let image = ImageWrapper::new(
+                &context,
+                ImageUsage::DynamicTexture,
+                TexturePixelFormat::Rgba,
+                16,
+                16,
+                None).unwrap();
+            image.update_region(&context, 0, 0, 16, 16, &[0x80; 16 * 16 * 4]).unwrap();
+            if context.supports_present_image() {
+                let retaining = context.set_frame_retention(true).is_ok();
+                if let AcquireResult::Acquired(_) = context.acquire_next_image().unwrap() {
+                    context.present_image(&image).unwrap();
+                }
+                if retaining && context.has_retained_frame() {
+                    context.wait_until_device_idle().unwrap();
+                    context.recreate_swapchain(&core).unwrap();
+                    context.regenerate_graphics_command_buffers().unwrap();
+                    if let AcquireResult::Acquired(_) = context.acquire_next_image().unwrap() {
+                        context.present_retained_frame().unwrap();
+                    }
+                    context.set_frame_retention(false).unwrap();
+                }
+            }
+            context.wait_until_device_idle().unwrap();
+            image.release(&context);
             context.teardown();
             core.teardown();
         }
@@ -70,7 +100,8 @@ fn main() {
                     WindowCommand::Custom(e) => {
                         app.on_window_custom_event(e);
                         ()
-                    }
+                    },
+                    _ => {}
                 }
             },
             Event::WindowEvent { event, window_id }
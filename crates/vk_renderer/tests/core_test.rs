@@ -67,7 +67,8 @@ fn main() {
                     WindowCommand::Custom(e) => {
                         app.on_window_custom_event(e);
                         ()
-                    }
+                    },
+                    _ => {}
                 }
             },
             Event::WindowEvent { event, window_id }
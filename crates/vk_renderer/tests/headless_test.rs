@@ -0,0 +1,48 @@
+/// Test rendering without a window.
+/// A headless VkCore has no surface extensions, and a headless VkContext renders into off-screen
+/// images in place of a swapchain, so this runs where there is no display, such as on CI machines.
+///
+/// The test creates a headless VkCore and VkContext, presents a host-written image to one of the
+/// off-screen images, reads it back, and destroys them.
+
+use vk_renderer::{
+    VkCore, VkCoreConfig, VkContext, ImageWrapper, ImageUsage, TexturePixelFormat, AcquireResult,
+    PresentResult
+};
+use ecs::resource::Resource;
+
+/// Test: present a uniformly grey image to a headless context and capture the result.
+/// Expected: every captured pixel matches the image that was presented, with no validation errors.
+fn main() {
+    unsafe {
+        let config = VkCoreConfig {
+            panic_on_error: true,
+            ..VkCoreConfig::default()
+        };
+        let mut core = VkCore::new_headless_with_config(vec![], config).unwrap();
+        let mut context = VkContext::new_headless(&core, 16, 16).unwrap();
+        assert!(context.is_headless());
+        let image = ImageWrapper::new(
+            &context,
+            ImageUsage::DynamicTexture,
+            TexturePixelFormat::Rgba,
+            16,
+            16,
+            None).unwrap();
+        image.update_region(&context, 0, 0, 16, 16, &[0x80; 16 * 16 * 4]).unwrap();
+        let image_index = match context.acquire_next_image().unwrap() {
+            AcquireResult::Acquired(image_index) => image_index,
+            _ => panic!("Headless context failed to acquire an image")
+        };
+        let submitted_before = context.get_graphics_timeline().last_submitted_value();
+        assert_eq!(context.present_image(&image).unwrap(), PresentResult::Ok);
+        assert!(context.get_graphics_timeline().last_submitted_value() > submitted_before);
+        let pixels = context.capture_swapchain_image(image_index).unwrap();
+        assert_eq!(pixels.len(), 16 * 16 * 4);
+        assert!(pixels.iter().all(|value| *value == 0x80));
+        context.wait_until_device_idle().unwrap();
+        image.release(&context);
+        context.teardown();
+        core.teardown();
+    }
+}
@@ -8,7 +8,7 @@
 
 use vk_renderer::{
     VkCore, VkContext, TextureCodec, ResourceUtilities, BufferUsage, ImageUsage, VboCreationData, BufferWrapper,
-    ImageWrapper
+    ImageWrapper, TexturePixelFormat, VertexLayout
 };
 use window::{
     WindowEventLooper, RenderCycleEvent, RenderEventHandler, ControlFlow, Event, WindowEvent,
@@ -30,6 +30,10 @@ const TEXTURE_INDEX_TERRAIN: u32 = 0;
 const TERRAIN_TEXTURE_BYTES: &[u8] =
     include_bytes!("../../../resources/test/textures/simple_outdoor_texture.jpg");
 
+const TEXTURE_INDEX_DYNAMIC: u32 = 1;
+
+const UBO_INDEX_DEFRAG_BASE: u32 = 100;
+
 struct ResourceSource {}
 
 impl RawResourceBearer<VkContext> for ResourceSource {
@@ -50,6 +54,7 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             vertex_data: Some(scene_model.vertices.as_ptr() as *const u8),
             vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
             vertex_count: scene_vertex_count,
+            vertex_layout: Some(VertexLayout::static_vertex()),
             draw_indexed: false,
             index_data: None,
             usage: BufferUsage::InitialiseOnceVertexBuffer
@@ -69,6 +74,17 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             Handle::for_resource(TEXTURE_INDEX_TERRAIN),
             texture);
 
+        let dynamic_texture = unsafe {
+            ImageWrapper::new(loader, ImageUsage::DynamicTexture, TexturePixelFormat::Rgba, 16, 16, None)?
+        };
+        unsafe {
+            dynamic_texture.update_region(loader, 4, 4, 8, 2, &[0xff; 8 * 2 * 4])?;
+            assert!(dynamic_texture.update_region(loader, 12, 0, 8, 1, &[0xff; 8 * 4]).is_err());
+        }
+        ecs.push_new_with_handle(
+            Handle::for_resource(TEXTURE_INDEX_DYNAMIC),
+            dynamic_texture);
+
         Ok(())
     }
 
@@ -103,6 +119,36 @@ impl VulkanTestApp {
                 .initialise_static_resources(&mut ecs, &context)
                 .unwrap();
 
+            // Defragmentation: fill at least two memory blocks with uniform buffers, release most
+            // of them, and check that what is left is compacted into fewer blocks
+            let blocks_before_fill = context.get_mem_allocator().0.get_block_count();
+            let creation_data = VboCreationData {
+                vertex_data: None,
+                vertex_size_bytes: 1024 * 1024,
+                vertex_count: 12,
+                vertex_layout: None,
+                draw_indexed: false,
+                index_data: None,
+                usage: BufferUsage::UniformBuffer
+            };
+            for index in 0..10 {
+                let buffer = BufferWrapper::create(&context, &ecs, &creation_data).unwrap();
+                match index % 3 {
+                    0 => ecs.push_new_with_handle(
+                        Handle::for_resource(UBO_INDEX_DEFRAG_BASE + index),
+                        buffer),
+                    _ => buffer.release(&context)
+                }
+            }
+            let blocks_before = context.get_mem_allocator().0.get_block_count();
+            let report = context.defragment_memory(&mut ecs).unwrap();
+            let blocks_after = context.get_mem_allocator().0.get_block_count();
+            assert_eq!(blocks_after, blocks_before - report.blocks_freed);
+            if blocks_before > blocks_before_fill + 1 {
+                assert!(report.relocated_count > 0);
+                assert!(blocks_after < blocks_before);
+            }
+
             // Release
             ecs.free_all_resources(&context).unwrap();
             context.teardown();
@@ -150,7 +196,8 @@ fn main() {
                     WindowCommand::Custom(e) => {
                         app.on_window_custom_event(e);
                         ()
-                    }
+                    },
+                    _ => {}
                 }
             },
             Event::WindowEvent { event, window_id }
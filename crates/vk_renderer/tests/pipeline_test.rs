@@ -8,10 +8,14 @@
 
 use vk_renderer::{
     VkCore, VkContext, TextureCodec, ResourceUtilities, BufferUsage, ImageUsage,
-    VboCreationData, ShaderCreationData, ShaderStage, RenderpassCreationData,
+    VboCreationData, ShaderCreationData, ShaderSource, ShaderStage, RenderpassCreationData,
     DescriptorSetLayoutCreationData, PipelineLayoutCreationData, PipelineCreationData,
     RenderpassTarget, UboUsage, BufferWrapper, ImageWrapper, RenderpassWrapper,
-    PipelineWrapper
+    PipelineWrapper, RenderQueue, BlendMode, DepthState, StencilState, RasterizerState,
+    VertexLayout,
+    SamplerCreationData, SamplerWrapper, PipelinePermutation, FeatureDeclaration,
+    BindlessTextureSet, BindlessTextureSetCreationData, BindlessMaterial, create_graphics_pipelines,
+    prewarm_pipelines
 };
 use window::{
     WindowEventLooper, RenderCycleEvent, RenderEventHandler, ControlFlow, Event, WindowEvent,
@@ -25,7 +29,9 @@ use ecs::{EcsManager, Handle, resource::{RawResourceBearer, Resource}};
 use error::EngineError;
 use model::{COLLADA, Config, StaticVertex};
 
-const VBO_INDEX_SCENE: u32 = 0;
+// Deliberately not an index that any shader module is loaded at, so that a pipeline looking up its
+// vertex shader by the vertex buffer's index fails to be created
+const VBO_INDEX_SCENE: u32 = 2;
 const SCENE_MODEL_BYTES: &[u8] =
     include_bytes!("../../../resources/test/models/Cubes.dae");
 
@@ -33,6 +39,8 @@ const TEXTURE_INDEX_TERRAIN: u32 = 0;
 const TERRAIN_TEXTURE_BYTES: &[u8] =
     include_bytes!("../../../resources/test/textures/simple_outdoor_texture.jpg");
 
+const SAMPLER_INDEX_MAIN: u32 = 0;
+
 const SHADER_INDEX_VERTEX: u32 = 0;
 const VERTEX_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock.vert");
 
@@ -47,12 +55,45 @@ const PIPELINE_LAYOUT_INDEX_MAIN: u32 = 0;
 
 const PIPELINE_INDEX_MAIN: u32 = 0;
 
+const BINDLESS_TEXTURE_SET_INDEX: u32 = 0;
+
+const PIPELINE_LAYOUT_INDEX_BINDLESS: u32 = 1;
+
 #[repr(C)]
 struct SomeUniformBuffer {
     pub x: f32,
     pub y: f32
 }
 
+/// Creation data for the main pipeline, for the given swapchain image
+fn main_pipeline_creation_data(swapchain_image_index: usize) -> PipelineCreationData {
+    PipelineCreationData {
+        pipeline_layout_index: PIPELINE_LAYOUT_INDEX_MAIN,
+        renderpass_index: RENDERPASS_INDEX_MAIN,
+        subpass: 0,
+        use_dynamic_rendering: false,
+        descriptor_set_layout_id: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
+        vertex_shader_index: SHADER_INDEX_VERTEX,
+        fragment_shader_index: SHADER_INDEX_FRAGMENT,
+        vbo_index: VBO_INDEX_SCENE,
+        texture_index: TEXTURE_INDEX_TERRAIN,
+        sampler_index: SAMPLER_INDEX_MAIN,
+        bindless_material: None,
+        draw_metadata: None,
+        vertex_layout: None,
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        ubo_size_bytes: std::mem::size_of::<SomeUniformBuffer>(),
+        ubo_object_count: None,
+        render_queue: RenderQueue::Opaque,
+        blend_mode: BlendMode::Opaque,
+        depth_state: DepthState::for_render_queue(RenderQueue::Opaque),
+        stencil_state: StencilState::disabled(),
+        rasterizer_state: RasterizerState::default(),
+        specialization_constants: vec![],
+        swapchain_image_index
+    }
+}
+
 struct ResourceSource {}
 
 impl RawResourceBearer<VkContext> for ResourceSource {
@@ -73,6 +114,7 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             vertex_data: Some(scene_model.vertices.as_ptr() as *const u8),
             vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
             vertex_count: scene_vertex_count,
+            vertex_layout: Some(VertexLayout::static_vertex()),
             draw_indexed: false,
             index_data: None,
             usage: BufferUsage::InitialiseOnceVertexBuffer
@@ -92,8 +134,14 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             Handle::for_resource(TEXTURE_INDEX_TERRAIN),
             texture);
 
+        let creation_data = SamplerCreationData::default();
+        let sampler = SamplerWrapper::create(loader, &ecs, &creation_data)?;
+        ecs.push_new_with_handle(
+            Handle::for_resource(SAMPLER_INDEX_MAIN),
+            sampler);
+
         let creation_data = ShaderCreationData {
-            data: VERTEX_SHADER,
+            source: ShaderSource::SpirV(VERTEX_SHADER),
             stage: ShaderStage::Vertex
         };
         let vertex_shader = vk::ShaderModule::create(loader, &ecs, &creation_data)?;
@@ -102,7 +150,7 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             vertex_shader);
 
         let creation_data = ShaderCreationData {
-            data: FRAGMENT_SHADER,
+            source: ShaderSource::SpirV(FRAGMENT_SHADER),
             stage: ShaderStage::Fragment
         };
         let fragment_shader = vk::ShaderModule::create(loader, &ecs, &creation_data)?;
@@ -123,7 +171,8 @@ impl RawResourceBearer<VkContext> for ResourceSource {
         for i in 0..swapchain_image_count {
             let creation_data = RenderpassCreationData {
                 target: RenderpassTarget::SwapchainImageWithDepth,
-                swapchain_image_index: i
+                swapchain_image_index: i,
+                samples: loader.select_msaa_samples(4)
             };
             let renderpass = RenderpassWrapper::create(loader, &ecs, &creation_data)?;
             ecs.push_new_with_handle(
@@ -133,7 +182,11 @@ impl RawResourceBearer<VkContext> for ResourceSource {
         }
 
         let creation_data = DescriptorSetLayoutCreationData {
-            ubo_usage: UboUsage::VertexShaderRead
+            ubo_usage: UboUsage::VertexShaderRead,
+            dynamic_ubo: false,
+            draw_metadata: false,
+            input_attachment_count: 0,
+            light_clusters: false
         };
         let descriptor_set_layout = vk::DescriptorSetLayout::create(loader, &ecs, &creation_data)?;
         ecs.push_new_with_handle(
@@ -141,7 +194,8 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             descriptor_set_layout);
 
         let creation_data = PipelineLayoutCreationData {
-            descriptor_set_layout_index: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN
+            descriptor_set_layout_index: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
+            bindless_texture_set_index: None
         };
         let pipeline_layout = vk::PipelineLayout::create(loader, &ecs, &creation_data)?;
         ecs.push_new_with_handle(
@@ -149,18 +203,7 @@ impl RawResourceBearer<VkContext> for ResourceSource {
             pipeline_layout);
 
         for i in 0..swapchain_image_count {
-            let creation_data = PipelineCreationData {
-                pipeline_layout_index: PIPELINE_LAYOUT_INDEX_MAIN,
-                renderpass_index: RENDERPASS_INDEX_MAIN,
-                descriptor_set_layout_id: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
-                vertex_shader_index: SHADER_INDEX_VERTEX,
-                fragment_shader_index: SHADER_INDEX_FRAGMENT,
-                vbo_index: VBO_INDEX_SCENE,
-                texture_index: TEXTURE_INDEX_TERRAIN,
-                vbo_stride_bytes: std::mem::size_of::<StaticVertex>() as u32,
-                ubo_size_bytes: std::mem::size_of::<SomeUniformBuffer>(),
-                swapchain_image_index: i
-            };
+            let creation_data = main_pipeline_creation_data(i);
             let pipeline = PipelineWrapper::create(loader, &ecs, &creation_data)?;
             ecs.push_new_with_handle(
                 Handle::for_resource_variation(PIPELINE_INDEX_MAIN, i as u32)
@@ -185,7 +228,8 @@ impl VulkanTestApp {
         unsafe {
 
             // Creation of required components
-            let mut core = VkCore::new(window, vec![]).unwrap();
+            let mut core = VkCore::new(window, vec![FeatureDeclaration::DescriptorIndexing])
+                .unwrap();
             let mut context = VkContext::new(&core, window).unwrap();
             let resource_source: Box<dyn RawResourceBearer<VkContext>> = Box::new(ResourceSource {});
             let mut ecs = EcsManager::new();
@@ -200,6 +244,80 @@ impl VulkanTestApp {
                     swapchain_image_count)
                 .unwrap();
 
+            // Pre-warm the main pipeline's permutation, declared once per swapchain image
+            let permutations: Vec<_> = (0..swapchain_image_count)
+                .map(|_| main_pipeline_creation_data(0).permutation(&ecs).unwrap())
+                .collect();
+            let mut progress = vec![];
+            prewarm_pipelines(&context, &ecs, &permutations, |completed, total| {
+                progress.push((completed, total));
+            }).unwrap();
+            assert_eq!(progress, vec![(1, 1)]);
+
+            // Create the main pipeline for every swapchain image in one batch, across threads
+            context.set_pipeline_creation_threads(2);
+            let image_permutations: Vec<PipelinePermutation> = (0..swapchain_image_count)
+                .map(|i| PipelinePermutation {
+                    swapchain_image_index: i,
+                    ..permutations[0].clone()
+                })
+                .collect();
+            let permutation_refs: Vec<&PipelinePermutation> = image_permutations.iter().collect();
+            let render_area = context.get_render_area().unwrap();
+            let pipelines =
+                create_graphics_pipelines(&context, &ecs, &permutation_refs, render_area).unwrap();
+            assert_eq!(pipelines.len(), swapchain_image_count);
+            for pipeline in pipelines.into_iter() {
+                context.device.destroy_pipeline(pipeline, None);
+            }
+
+            // Where descriptor indexing is supported, create a pipeline that samples from a
+            // bindless texture array, which is bound along with its material index
+            if context.supports_descriptor_indexing() {
+                let creation_data = BindlessTextureSetCreationData {
+                    capacity: 4,
+                    sampler_index: SAMPLER_INDEX_MAIN,
+                    texture_indices: vec![TEXTURE_INDEX_TERRAIN]
+                };
+                let texture_set = BindlessTextureSet::create(&context, &ecs, &creation_data)
+                    .unwrap();
+                let texture_set_descriptor_set = texture_set.get_descriptor_set();
+                ecs.push_new_with_handle(
+                    Handle::for_resource(BINDLESS_TEXTURE_SET_INDEX),
+                    texture_set);
+                let creation_data = PipelineLayoutCreationData {
+                    descriptor_set_layout_index: DESCRIPTOR_SET_LAYOUT_INDEX_MAIN,
+                    bindless_texture_set_index: Some(BINDLESS_TEXTURE_SET_INDEX)
+                };
+                let pipeline_layout = vk::PipelineLayout::create(&context, &ecs, &creation_data)
+                    .unwrap();
+                ecs.push_new_with_handle(
+                    Handle::for_resource(PIPELINE_LAYOUT_INDEX_BINDLESS),
+                    pipeline_layout);
+                let creation_data = PipelineCreationData {
+                    pipeline_layout_index: PIPELINE_LAYOUT_INDEX_BINDLESS,
+                    bindless_material: Some(BindlessMaterial {
+                        texture_set_index: BINDLESS_TEXTURE_SET_INDEX,
+                        material_index: 0
+                    }),
+                    ..main_pipeline_creation_data(0)
+                };
+                let pipeline = PipelineWrapper::create(&context, &ecs, &creation_data).unwrap();
+                assert_eq!(
+                    pipeline.get_bindless_material(),
+                    Some((texture_set_descriptor_set, 0)));
+                pipeline.release(&context);
+
+                let creation_data = PipelineCreationData {
+                    bindless_material: Some(BindlessMaterial {
+                        texture_set_index: BINDLESS_TEXTURE_SET_INDEX,
+                        material_index: 4
+                    }),
+                    ..creation_data
+                };
+                assert!(PipelineWrapper::create(&context, &ecs, &creation_data).is_err());
+            }
+
             // Release
             ecs.free_all_resources(&context).unwrap();
             context.teardown();
@@ -247,7 +365,8 @@ fn main() {
                     WindowCommand::Custom(e) => {
                         app.on_window_custom_event(e);
                         ()
-                    }
+                    },
+                    _ => {}
                 }
             },
             Event::WindowEvent { event, window_id }
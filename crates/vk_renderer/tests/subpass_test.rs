@@ -0,0 +1,284 @@
+/// Test drawing in a later subpass of a custom renderpass.
+/// A headless VkCore and VkContext are used, so this runs where there is no display.
+///
+/// The test builds a renderpass of two subpasses, where the first draws a texture into an
+/// intermediate attachment and the second copies that attachment into the output through an input
+/// attachment. It draws a pipeline in each, moving between them with
+/// RenderpassWrapper::record_next_subpass, reads back the output, and destroys everything.
+
+use vk_renderer::{
+    VkCore, VkCoreConfig, VkContext, ImageWrapper, ImageUsage, TexturePixelFormat, AcquireResult,
+    PresentResult, BufferUsage, BufferWrapper, VboCreationData, VertexLayout, ShaderCreationData,
+    ShaderSource, ShaderStage, SamplerCreationData, SamplerWrapper, DescriptorSetLayoutCreationData,
+    PipelineLayoutCreationData, PipelineCreationData, PipelineWrapper, RenderpassWrapper,
+    RenderQueue, BlendMode, DepthState, StencilState, RasterizerState, UboUsage,
+    CustomRenderpassDescription, CustomSubpass, CustomSubpassDependency, CustomAttachment,
+    DescriptorBinding, write_descriptor_bindings, INPUT_ATTACHMENT_FIRST_BINDING
+};
+use ecs::{EcsManager, Handle, resource::Resource};
+use model::StaticVertex;
+use color::Color;
+use ash::vk;
+use vk_shader_macros::include_glsl;
+
+const EXTENT: u32 = 16;
+
+const VBO_INDEX_TRIANGLE: u32 = 0;
+const TEXTURE_INDEX_RED: u32 = 0;
+const SAMPLER_INDEX_MAIN: u32 = 0;
+
+const SHADER_INDEX_VERTEX: u32 = 0;
+const VERTEX_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock.vert");
+
+const SHADER_INDEX_FRAGMENT: u32 = 1;
+const FRAGMENT_SHADER: &[u32] = include_glsl!("../../resources/test/shaders/stock.frag");
+
+const SHADER_INDEX_INPUT_FRAGMENT: u32 = 2;
+const INPUT_FRAGMENT_SHADER: &[u32] =
+    include_glsl!("../../resources/test/shaders/subpass_input.frag");
+
+const RENDERPASS_INDEX_MAIN: u32 = 0;
+
+const DESCRIPTOR_SET_LAYOUT_INDEX_MAIN: u32 = 0;
+const DESCRIPTOR_SET_LAYOUT_INDEX_INPUT: u32 = 1;
+
+const PIPELINE_LAYOUT_INDEX_MAIN: u32 = 0;
+const PIPELINE_LAYOUT_INDEX_INPUT: u32 = 1;
+
+const RED_PIXEL: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+
+/// A texture drawn into the intermediate attachment, then copied into the output
+fn description() -> CustomRenderpassDescription {
+    CustomRenderpassDescription {
+        intermediate_formats: vec![TexturePixelFormat::Rgba],
+        subpasses: vec![
+            CustomSubpass {
+                color_attachments: vec![CustomAttachment::Intermediate(0)],
+                input_attachments: vec![],
+                depth_attachment: None
+            },
+            CustomSubpass {
+                color_attachments: vec![CustomAttachment::Output],
+                input_attachments: vec![CustomAttachment::Intermediate(0)],
+                depth_attachment: None
+            }
+        ],
+        dependencies: vec![CustomSubpassDependency { src_subpass: 0, dst_subpass: 1 }]
+    }
+}
+
+/// Creation data for a pipeline drawing the triangle in the given subpass
+fn pipeline_creation_data(
+    subpass: u32,
+    swapchain_image_index: usize
+) -> PipelineCreationData {
+    let (descriptor_set_layout_id, pipeline_layout_index, fragment_shader_index) = match subpass {
+        0 => (DESCRIPTOR_SET_LAYOUT_INDEX_MAIN, PIPELINE_LAYOUT_INDEX_MAIN, SHADER_INDEX_FRAGMENT),
+        _ => (
+            DESCRIPTOR_SET_LAYOUT_INDEX_INPUT,
+            PIPELINE_LAYOUT_INDEX_INPUT,
+            SHADER_INDEX_INPUT_FRAGMENT
+        )
+    };
+    PipelineCreationData {
+        pipeline_layout_index,
+        renderpass_index: RENDERPASS_INDEX_MAIN,
+        subpass,
+        use_dynamic_rendering: false,
+        descriptor_set_layout_id,
+        vertex_shader_index: SHADER_INDEX_VERTEX,
+        fragment_shader_index,
+        vbo_index: VBO_INDEX_TRIANGLE,
+        texture_index: TEXTURE_INDEX_RED,
+        sampler_index: SAMPLER_INDEX_MAIN,
+        bindless_material: None,
+        draw_metadata: None,
+        vertex_layout: None,
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        ubo_size_bytes: std::mem::size_of::<[f32; 16]>(),
+        ubo_object_count: None,
+        render_queue: RenderQueue::Opaque,
+        blend_mode: BlendMode::Opaque,
+        depth_state: DepthState::disabled(),
+        stencil_state: StencilState::disabled(),
+        rasterizer_state: RasterizerState::double_sided(),
+        specialization_constants: vec![],
+        swapchain_image_index
+    }
+}
+
+/// A triangle covering the whole of clip space, drawn with an identity matrix
+fn create_static_resources(ecs: &mut EcsManager<VkContext>, context: &VkContext) {
+    let vertex = |px: f32, py: f32| StaticVertex {
+        px, py, pz: 0.5, nx: 0.0, ny: 0.0, nz: 1.0, tu: 0.0, tv: 0.0
+    };
+    let vertices = [vertex(-1.0, -1.0), vertex(3.0, -1.0), vertex(-1.0, 3.0)];
+    let creation_data = VboCreationData {
+        vertex_data: Some(vertices.as_ptr() as *const u8),
+        vertex_size_bytes: std::mem::size_of::<StaticVertex>(),
+        vertex_count: vertices.len(),
+        vertex_layout: Some(VertexLayout::static_vertex()),
+        draw_indexed: false,
+        index_data: None,
+        usage: BufferUsage::InitialiseOnceVertexBuffer
+    };
+    let vertex_buffer = BufferWrapper::create(context, ecs, &creation_data).unwrap();
+    ecs.push_new_with_handle(Handle::for_resource(VBO_INDEX_TRIANGLE), vertex_buffer);
+
+    let texture = unsafe {
+        ImageWrapper::new(
+            context,
+            ImageUsage::DynamicTexture,
+            TexturePixelFormat::Rgba,
+            EXTENT,
+            EXTENT,
+            None).unwrap()
+    };
+    let texels: Vec<u8> = RED_PIXEL.repeat((EXTENT * EXTENT) as usize);
+    unsafe {
+        texture.update_region(context, 0, 0, EXTENT, EXTENT, &texels).unwrap();
+    }
+    ecs.push_new_with_handle(Handle::for_resource(TEXTURE_INDEX_RED), texture);
+
+    let sampler = SamplerWrapper::create(context, ecs, &SamplerCreationData::default()).unwrap();
+    ecs.push_new_with_handle(Handle::for_resource(SAMPLER_INDEX_MAIN), sampler);
+
+    let shaders = [
+        (SHADER_INDEX_VERTEX, VERTEX_SHADER, ShaderStage::Vertex),
+        (SHADER_INDEX_FRAGMENT, FRAGMENT_SHADER, ShaderStage::Fragment),
+        (SHADER_INDEX_INPUT_FRAGMENT, INPUT_FRAGMENT_SHADER, ShaderStage::Fragment)
+    ];
+    for (index, code, stage) in shaders.into_iter() {
+        let creation_data = ShaderCreationData { source: ShaderSource::SpirV(code), stage };
+        let shader = vk::ShaderModule::create(context, ecs, &creation_data).unwrap();
+        ecs.push_new_with_handle(Handle::for_resource(index), shader);
+    }
+
+    let layouts = [
+        (DESCRIPTOR_SET_LAYOUT_INDEX_MAIN, PIPELINE_LAYOUT_INDEX_MAIN, 0),
+        (DESCRIPTOR_SET_LAYOUT_INDEX_INPUT, PIPELINE_LAYOUT_INDEX_INPUT, 1)
+    ];
+    for (set_layout_index, pipeline_layout_index, input_attachment_count) in layouts.into_iter() {
+        let creation_data = DescriptorSetLayoutCreationData {
+            ubo_usage: UboUsage::VertexShaderRead,
+            dynamic_ubo: false,
+            draw_metadata: false,
+            input_attachment_count,
+            light_clusters: false
+        };
+        let set_layout = vk::DescriptorSetLayout::create(context, ecs, &creation_data).unwrap();
+        ecs.push_new_with_handle(Handle::for_resource(set_layout_index), set_layout);
+        let creation_data = PipelineLayoutCreationData {
+            descriptor_set_layout_index: set_layout_index,
+            bindless_texture_set_index: None
+        };
+        let pipeline_layout = vk::PipelineLayout::create(context, ecs, &creation_data).unwrap();
+        ecs.push_new_with_handle(Handle::for_resource(pipeline_layout_index), pipeline_layout);
+    }
+}
+
+/// Test: draw a red texture in the first subpass and copy it to the output in the second.
+/// Expected: the renderpass has two subpasses, a pipeline can be created for the second, and every
+/// captured pixel is red, which it would not be had the second subpass not drawn, with no
+/// validation errors.
+fn main() {
+    unsafe {
+        let config = VkCoreConfig {
+            panic_on_error: true,
+            ..VkCoreConfig::default()
+        };
+        let mut core = VkCore::new_headless_with_config(vec![], config).unwrap();
+        let mut context = VkContext::new_headless(&core, EXTENT, EXTENT).unwrap();
+        let mut ecs = EcsManager::new();
+        create_static_resources(&mut ecs, &context);
+
+        let image_index = match context.acquire_next_image().unwrap() {
+            AcquireResult::Acquired(image_index) => image_index,
+            _ => panic!("Headless context failed to acquire an image")
+        };
+        let renderpass = RenderpassWrapper::new_with_custom_target(
+            &context,
+            &description(),
+            image_index,
+            vk::SampleCountFlags::TYPE_1).unwrap();
+        assert_eq!(renderpass.get_subpass_count(), 2);
+        let input_image_view = renderpass.get_intermediate_image_view(0).unwrap();
+        let renderpass_handle =
+            Handle::for_resource_variation(RENDERPASS_INDEX_MAIN, image_index as u32).unwrap();
+        ecs.push_new_with_handle(renderpass_handle, renderpass);
+
+        let identity: [f32; 16] = [
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0
+        ];
+        let pipelines: Vec<PipelineWrapper> = (0..2)
+            .map(|subpass| {
+                let creation_data = pipeline_creation_data(subpass, image_index);
+                let pipeline = PipelineWrapper::create(&context, &ecs, &creation_data).unwrap();
+                pipeline.update_uniform_buffer(
+                    &context,
+                    identity.as_ptr() as *const u8,
+                    std::mem::size_of_val(&identity)).unwrap();
+                pipeline
+            })
+            .collect();
+        write_descriptor_bindings(
+            &context,
+            pipelines[1].get_descriptor_set(),
+            &[(
+                INPUT_ATTACHMENT_FIRST_BINDING,
+                DescriptorBinding::InputAttachment {
+                    image_view: input_image_view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                }
+            )]);
+
+        // Record both subpasses into the acquired image's command buffer
+        let device = &context.device;
+        let command_buffer = context.get_graphics_command_buffer(image_index);
+        let renderpass = ecs.get_item::<RenderpassWrapper>(renderpass_handle).unwrap();
+        let clear_values = renderpass.clear_values(Color::BLACK, 1.0);
+        let begin_info = vk::CommandBufferBeginInfo::builder();
+        device.begin_command_buffer(command_buffer, &begin_info).unwrap();
+        let renderpass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(renderpass.renderpass)
+            .framebuffer(renderpass.custom_framebuffer.unwrap())
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: context.get_extent().unwrap()
+            })
+            .clear_values(&clear_values);
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &renderpass_begin_info,
+            vk::SubpassContents::INLINE);
+        let pipeline_layouts = [PIPELINE_LAYOUT_INDEX_MAIN, PIPELINE_LAYOUT_INDEX_INPUT];
+        for (subpass, pipeline) in pipelines.iter().enumerate() {
+            if subpass > 0 {
+                RenderpassWrapper::record_next_subpass(device, command_buffer);
+            }
+            let pipeline_layout = ecs
+                .get_item::<vk::PipelineLayout>(
+                    Handle::for_resource(pipeline_layouts[subpass]))
+                .unwrap();
+            pipeline.record_commands(command_buffer, &context, *pipeline_layout);
+        }
+        device.cmd_end_render_pass(command_buffer);
+        device.end_command_buffer(command_buffer).unwrap();
+
+        assert_eq!(context.submit_and_present().unwrap(), PresentResult::Ok);
+        let pixels = context.capture_swapchain_image(image_index).unwrap();
+        assert_eq!(pixels.len(), (EXTENT * EXTENT * 4) as usize);
+        assert!(pixels.chunks(4).all(|pixel| pixel == RED_PIXEL));
+
+        context.wait_until_device_idle().unwrap();
+        for pipeline in pipelines.iter() {
+            pipeline.release(&context);
+        }
+        ecs.free_all_resources(&context).unwrap();
+        context.teardown();
+        core.teardown();
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{WindowOptions, AspectRatioLock};
+use winit::dpi::PhysicalSize;
+
+/// WindowIcon struct
+/// An icon for the window's title bar and task bar entry, as RGBA texels with 8 bits each
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowIcon {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32
+}
+
+/// WindowConfig struct
+/// Attributes that a window is created with, built up by chaining calls. Limits on its size and
+/// shape are kept as WindowOptions, since they also apply as it is resized; the rest only apply
+/// on creation. A transparent window only shows through where the scene clears or draws with
+/// alpha below 1, and only on platforms whose surfaces can blend.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowConfig {
+    pub options: WindowOptions,
+    pub client_size: Option<PhysicalSize<u32>>,
+    pub resizable: bool,
+    pub decorations: bool,
+    pub transparent: bool,
+    pub icon: Option<WindowIcon>
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            options: WindowOptions::default(),
+            client_size: None,
+            resizable: true,
+            decorations: true,
+            transparent: false,
+            icon: None
+        }
+    }
+}
+
+impl From<WindowOptions> for WindowConfig {
+    fn from(options: WindowOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+}
+
+impl WindowConfig {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the initial size of the client area; otherwise the platform chooses
+    pub fn with_client_size(mut self, width: u32, height: u32) -> Self {
+        self.client_size = Some(PhysicalSize::new(width, height));
+        self
+    }
+
+    /// Set the smallest size the client area may be resized to
+    pub fn with_min_client_size(mut self, width: u32, height: u32) -> Self {
+        self.options.min_client_size = Some(PhysicalSize::new(width, height));
+        self
+    }
+
+    /// Set the largest size the client area may be resized to
+    pub fn with_max_client_size(mut self, width: u32, height: u32) -> Self {
+        self.options.max_client_size = Some(PhysicalSize::new(width, height));
+        self
+    }
+
+    /// Keep the rendered image at a fixed aspect ratio
+    pub fn with_aspect_ratio(mut self, aspect_ratio: AspectRatioLock) -> Self {
+        self.options.aspect_ratio = Some(aspect_ratio);
+        self
+    }
+
+    /// Choose whether the window is never shown, such as for rendering off-screen in tests
+    pub fn with_hidden(mut self, hidden: bool) -> Self {
+        self.options.hidden = hidden;
+        self
+    }
+
+    /// Choose whether the user may resize the window
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Choose whether the window has a title bar and border
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    /// Choose whether what's behind the window shows through where it renders with alpha
+    pub fn with_transparency(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// Set the icon from RGBA texels, which must number width times height
+    pub fn with_icon(mut self, rgba: Vec<u8>, width: u32, height: u32) -> Self {
+        self.icon = Some(WindowIcon { rgba, width, height });
+        self
+    }
+}
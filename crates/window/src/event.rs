@@ -1,11 +1,17 @@
 
-use crate::{WindowCommand, KeyCode, KeyState};
+use crate::{WindowCommand, KeyCode, KeyState, FullscreenMode, MonitorInfo};
+use crate::monitor::describe_monitors;
 use winit::event::Event;
 use winit::event_loop::{
     ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget
 };
 use winit::platform::run_return::EventLoopExtRunReturn;
 use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Refresh interval assumed when the monitor's refresh rate is unknown
+const FALLBACK_REFRESH_INTERVAL: Duration = Duration::from_nanos(16_666_667);
 
 #[derive(PartialEq)]
 pub enum WindowStateEvent {
@@ -13,14 +19,114 @@ pub enum WindowStateEvent {
     FocusGained,
     FocusLost,
     Closing,
-    KeyEvent(KeyCode, KeyState)
+    KeyEvent(KeyCode, KeyState),
+    DisplayModeChanged(FullscreenMode),
+    /// Relative mouse movement, reported only while the cursor is locked
+    MouseMotion { dx: f64, dy: f64 },
+    /// A character of text typed, after keyboard layout and dead keys are applied; control
+    /// characters such as backspace are only reported as key events
+    CharacterReceived(char),
+    /// Text being composed by an input method, not yet part of the text being edited; the cursor
+    /// is a byte range within it, and empty text means composition has been cancelled
+    TextComposition { text: String, cursor: Option<(usize, usize)> },
+    /// Text finished by an input method, to be inserted where the text is being edited
+    TextCommitted(String),
+    /// The number of physical pixels per logical pixel changed, such as on moving the window to
+    /// a monitor of different DPI
+    ScaleFactorChanged(f64),
+    /// A window added by Engine::add_window was closed, carrying the number that returned
+    ViewportClosed(usize),
+    /// A window added by Engine::add_window failed to update or render, and is closed; a
+    /// ViewportClosed event follows
+    ViewportFailed { number: usize, error: String },
+    /// The render context of a window added by Engine::add_window was lost, and has been
+    /// recreated along with its scene's resources
+    ViewportDeviceRestored(usize),
+    /// The monitors connected to the system, in reply to WindowCommand::ListMonitors
+    MonitorsListed(Vec<MonitorInfo>),
+    /// A window command couldn't be carried out, such as moving to a monitor that isn't
+    /// connected or locking the cursor where the platform can't, with the reason
+    CommandFailed(String),
+    /// The frame captured in reply to WindowCommand::RequestScreenshot, as tightly-packed RGBA
+    /// pixels covering the whole window
+    ScreenshotCaptured { width: u32, height: u32, pixels: Vec<u8> },
+    /// A requested screenshot could not be taken, such as when the surface does not allow its
+    /// images to be copied
+    ScreenshotFailed(String),
+    /// Device memory was compacted in reply to WindowCommand::DefragmentMemory, moving the given
+    /// number of buffers and images and freeing the given number of memory blocks
+    MemoryDefragmented { relocated: usize, blocks_freed: usize },
+    /// A shader file watched through Engine::set_shader_reloader changed, and the new module is
+    /// now in use
+    ShaderReloaded(PathBuf),
+    /// A watched shader file changed but couldn't be read or compiled, so the previous module is
+    /// kept until it is fixed
+    ShaderReloadFailed { path: PathBuf, error: String }
+}
+
+/// FrameTiming struct
+/// Timing of a frame in the render cycle. Times are measured from the start of the engine's
+/// timer. The predicted present time is an estimate, assuming the frame is presented at the next
+/// refresh of the monitor the window is on, and is intended for applications that interpolate or
+/// extrapolate animation to when the frame will actually be seen.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FrameTiming {
+    pub elapsed_millis: u64,
+    pub frame_index: u64,
+    pub frame_start_time: Duration,
+    pub refresh_interval: Duration,
+    pub predicted_present_time: Duration
+}
+
+impl FrameTiming {
+
+    /// Create timing for a frame beginning at the given time, on a monitor with the given refresh
+    /// interval if known
+    pub fn new(
+        elapsed_millis: u64,
+        frame_index: u64,
+        frame_start_time: Duration,
+        refresh_interval: Option<Duration>
+    ) -> Self {
+        let refresh_interval = refresh_interval
+            .filter(|interval| !interval.is_zero())
+            .unwrap_or(FALLBACK_REFRESH_INTERVAL);
+        Self {
+            elapsed_millis,
+            frame_index,
+            frame_start_time,
+            refresh_interval,
+            predicted_present_time: frame_start_time + refresh_interval
+        }
+    }
+
+    /// Convert a refresh rate in millihertz, as reported for a monitor, to a refresh interval
+    pub fn refresh_interval_from_millihertz(refresh_rate_millihertz: u32) -> Option<Duration> {
+        match refresh_rate_millihertz {
+            0 => None,
+            rate => Some(Duration::from_secs_f64(1000.0 / rate as f64))
+        }
+    }
 }
 
 #[derive(PartialEq)]
 pub enum RenderCycleEvent {
-    PrepareUpdate(u64),
-    RenderingFrame,
-    RecreatingSurface(f32) // Aspect ratio passed
+    PrepareUpdate(FrameTiming),
+    RenderingFrame(FrameTiming),
+    /// The surface is about to be rebuilt for a new size, with the aspect ratio rendered at and
+    /// the number of physical pixels per logical pixel, for sizing UI text and the like
+    RecreatingSurface { aspect_ratio: f32, scale_factor: f64 },
+    Loading(LoadingEvent)
+}
+
+/// LoadingEvent enum
+/// Progress through the loading phase before a scene's first frame, such as for drawing a
+/// progress bar. Pre-warming reports the number of pipeline permutations done so far and the
+/// total, after each one.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoadingEvent {
+    PrewarmingPipelines { completed: usize, total: usize },
+    Finished(Duration)
 }
 
 pub trait WindowEventHandler<T: 'static> {
@@ -48,6 +154,11 @@ impl<M: 'static + Send + Debug> WindowEventLooper<M> {
         self.event_loop.create_proxy()
     }
 
+    /// List the monitors connected to the system, such as to choose one before any window opens
+    pub fn get_monitors(&self) -> Vec<MonitorInfo> {
+        describe_monitors(self.event_loop.available_monitors(), self.event_loop.primary_monitor())
+    }
+
     pub fn run_loop<F>(mut self, event_handler: F) -> i32
         where F: FnMut(Event<'_, WindowCommand<M>>, &EventLoopWindowTarget<WindowCommand<M>>, &mut ControlFlow)
     {
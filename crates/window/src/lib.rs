@@ -1,17 +1,28 @@
 mod window;
 mod event;
+mod proxy;
+mod options;
+mod config;
+mod monitor;
 
-pub use crate::window::Window;
+pub use crate::window::{Window, FullscreenMode, CursorMode};
 pub use crate::event::{
-    WindowEventLooper, RenderCycleEvent, WindowStateEvent, RenderEventHandler, WindowEventHandler
+    WindowEventLooper, RenderCycleEvent, LoadingEvent, FrameTiming, WindowStateEvent,
+    RenderEventHandler, WindowEventHandler
 };
+pub use crate::proxy::{CommandSender, CommandBatch};
+pub use crate::options::{WindowOptions, AspectRatioLock};
+pub use crate::config::{WindowConfig, WindowIcon};
+pub use crate::monitor::{MonitorInfo, DisplayMode};
 
-pub use winit::dpi::PhysicalSize;
+pub use winit::dpi::{PhysicalPosition, PhysicalSize, LogicalSize};
 pub use winit::event::VirtualKeyCode as KeyCode;
 pub use winit::event::ElementState as KeyState;
+pub use winit::event::MouseButton;
 pub use winit::event_loop::EventLoopProxy as MessageProxy;
-pub use winit::event::{Event, WindowEvent, KeyboardInput};
+pub use winit::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, Ime};
 pub use winit::event_loop::ControlFlow;
+pub use winit::window::WindowId;
 
 use std::fmt::Debug;
 
@@ -19,5 +30,99 @@ use std::fmt::Debug;
 pub enum WindowCommand<T> {
     Custom(T),
     RequestRedraw,
-    RequestClose
+    RequestClose,
+    ToggleFullscreen,
+    SetFullscreen(FullscreenMode),
+    SetFullscreenOnMonitor {
+        mode: FullscreenMode,
+        monitor_index: usize,
+        display_mode: Option<DisplayMode>
+    },
+    MoveToMonitor(usize),
+    ListMonitors,
+    SetCursorMode(CursorMode),
+    SetTextInput { enabled: bool, position: PhysicalPosition<i32> },
+    RequestScreenshot,
+    DefragmentMemory,
+    Batch(Vec<WindowCommand<T>>)
 }
+
+impl<T> WindowCommand<T> {
+
+    /// Command to close the window and exit the event loop
+    pub fn quit() -> Self {
+        WindowCommand::RequestClose
+    }
+
+    /// Command to switch between windowed and borderless fullscreen
+    pub fn toggle_fullscreen() -> Self {
+        WindowCommand::ToggleFullscreen
+    }
+
+    /// Command to switch to the given fullscreen or windowed mode
+    pub fn set_fullscreen(mode: FullscreenMode) -> Self {
+        WindowCommand::SetFullscreen(mode)
+    }
+
+    /// Command to switch to the given mode on a chosen monitor, by its index in the monitor
+    /// list, using the given display mode for exclusive fullscreen where the monitor has it
+    pub fn set_fullscreen_on_monitor(
+        mode: FullscreenMode,
+        monitor_index: usize,
+        display_mode: Option<DisplayMode>
+    ) -> Self {
+        WindowCommand::SetFullscreenOnMonitor { mode, monitor_index, display_mode }
+    }
+
+    /// Command to move the window to a chosen monitor, by its index in the monitor list
+    pub fn move_to_monitor(monitor_index: usize) -> Self {
+        WindowCommand::MoveToMonitor(monitor_index)
+    }
+
+    /// Command to list the monitors, which are sent back in a MonitorsListed event
+    pub fn list_monitors() -> Self {
+        WindowCommand::ListMonitors
+    }
+
+    /// Command to show, hide or lock the mouse cursor
+    pub fn set_cursor_mode(mode: CursorMode) -> Self {
+        WindowCommand::SetCursorMode(mode)
+    }
+
+    /// Command to allow input methods while a text field at the given position has focus, or to
+    /// disallow them once it loses focus
+    pub fn set_text_input(enabled: bool, position: PhysicalPosition<i32>) -> Self {
+        WindowCommand::SetTextInput { enabled, position }
+    }
+
+    /// Command to capture the next presented frame, which is sent back in a ScreenshotCaptured
+    /// event
+    pub fn screenshot() -> Self {
+        WindowCommand::RequestScreenshot
+    }
+
+    /// Command to compact the device memory that resources are sub-allocated from, reported back
+    /// in a MemoryDefragmented event
+    pub fn defragment_memory() -> Self {
+        WindowCommand::DefragmentMemory
+    }
+
+    /// Command to send a custom message to the application
+    pub fn custom(message: T) -> Self {
+        WindowCommand::Custom(message)
+    }
+
+    /// Expand this command into the sequence of individual commands it represents, flattening
+    /// any nested batches
+    pub fn into_commands(self) -> Vec<WindowCommand<T>> {
+        match self {
+            WindowCommand::Batch(commands) => commands.into_iter()
+                .flat_map(|command| command.into_commands())
+                .collect(),
+            command => vec![command]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
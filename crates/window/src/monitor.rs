@@ -0,0 +1,88 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::monitor::{MonitorHandle, VideoMode};
+
+/// DisplayMode struct
+/// A resolution, refresh rate and color depth that a monitor can be switched to for exclusive
+/// fullscreen
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DisplayMode {
+    pub size: PhysicalSize<u32>,
+    pub refresh_rate_millihertz: u32,
+    pub bit_depth: u16
+}
+
+impl DisplayMode {
+
+    fn of(video_mode: &VideoMode) -> Self {
+        Self {
+            size: video_mode.size(),
+            refresh_rate_millihertz: video_mode.refresh_rate_millihertz(),
+            bit_depth: video_mode.bit_depth()
+        }
+    }
+
+    /// Choose the requested mode if it is among those given, or otherwise the one with the most
+    /// pixels, then the highest refresh rate and color depth
+    pub fn choose(modes: &[DisplayMode], requested: Option<DisplayMode>) -> Option<DisplayMode> {
+        match requested {
+            Some(requested) if modes.contains(&requested) => Some(requested),
+            _ => modes.iter()
+                .copied()
+                .max_by_key(|mode| (
+                    mode.size.width as u64 * mode.size.height as u64,
+                    mode.refresh_rate_millihertz,
+                    mode.bit_depth
+                ))
+        }
+    }
+}
+
+/// MonitorInfo struct
+/// A monitor connected to the system, such as for listing in a display settings menu. Monitors
+/// are identified by their index in the list, which holds until monitors are connected or
+/// disconnected. The position is that of its top-left corner on the desktop.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub position: PhysicalPosition<i32>,
+    pub size: PhysicalSize<u32>,
+    pub refresh_rate_millihertz: Option<u32>,
+    pub scale_factor: f64,
+    pub is_primary: bool,
+    pub display_modes: Vec<DisplayMode>
+}
+
+/// Describe each of the given monitors, in order
+pub(crate) fn describe_monitors(
+    monitors: impl Iterator<Item = MonitorHandle>,
+    primary: Option<MonitorHandle>
+) -> Vec<MonitorInfo> {
+    monitors
+        .enumerate()
+        .map(|(index, monitor)| MonitorInfo {
+            index,
+            name: monitor.name(),
+            position: monitor.position(),
+            size: monitor.size(),
+            refresh_rate_millihertz: monitor.refresh_rate_millihertz(),
+            scale_factor: monitor.scale_factor(),
+            is_primary: primary.as_ref() == Some(&monitor),
+            display_modes: monitor.video_modes()
+                .map(|video_mode| DisplayMode::of(&video_mode))
+                .collect()
+        })
+        .collect()
+}
+
+/// Find the monitor's video mode to use for exclusive fullscreen, which is the requested display
+/// mode if the monitor has it or otherwise its best mode; see DisplayMode::choose
+pub(crate) fn choose_video_mode(
+    monitor: &MonitorHandle,
+    requested: Option<DisplayMode>
+) -> Option<VideoMode> {
+    let video_modes: Vec<VideoMode> = monitor.video_modes().collect();
+    let display_modes: Vec<DisplayMode> = video_modes.iter().map(DisplayMode::of).collect();
+    let chosen = DisplayMode::choose(&display_modes, requested)?;
+    video_modes.into_iter().find(|video_mode| DisplayMode::of(video_mode) == chosen)
+}
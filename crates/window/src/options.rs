@@ -0,0 +1,99 @@
+
+use winit::dpi::PhysicalSize;
+
+/// AspectRatioLock enum
+/// Ways of keeping the rendered image at a fixed width-to-height ratio. Letterbox lets the window
+/// take any shape and renders into the largest centred area of the ratio, leaving bars either
+/// side; ConstrainedResize snaps the window itself back to the ratio after each resize, falling
+/// back to letterboxing while fullscreen.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AspectRatioLock {
+    Letterbox(f32),
+    ConstrainedResize(f32)
+}
+
+/// WindowOptions struct
+/// Limits on the size and shape of the window's client area; the default has none. A hidden
+/// window is never shown, which suits rendering off-screen, such as in tests.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct WindowOptions {
+    pub min_client_size: Option<PhysicalSize<u32>>,
+    pub max_client_size: Option<PhysicalSize<u32>>,
+    pub aspect_ratio: Option<AspectRatioLock>,
+    pub hidden: bool
+}
+
+impl WindowOptions {
+
+    /// The ratio that rendering should be letterboxed to, if any
+    pub fn letterbox_aspect_ratio(&self, fullscreen: bool) -> Option<f32> {
+        match self.aspect_ratio {
+            Some(AspectRatioLock::Letterbox(ratio)) => Some(ratio),
+            Some(AspectRatioLock::ConstrainedResize(ratio)) if fullscreen => Some(ratio),
+            _ => None
+        }
+    }
+
+    /// Aspect ratio of the area rendered into, for a client area of the given size
+    pub fn render_aspect_ratio(&self, client_size: PhysicalSize<u32>) -> f32 {
+        match self.aspect_ratio {
+            Some(AspectRatioLock::Letterbox(ratio)) |
+            Some(AspectRatioLock::ConstrainedResize(ratio)) => ratio,
+            None => client_size.width as f32 / client_size.height as f32
+        }
+    }
+
+    /// Size the client area should be snapped to after being resized from the previous size, if
+    /// resizing is constrained to an aspect ratio. Whichever dimension changed by the greater
+    /// proportion is kept and the other follows it, then both are held within the size limits.
+    /// Returns the size unchanged if resizing is not constrained.
+    pub fn constrain_client_size(
+        &self,
+        size: PhysicalSize<u32>,
+        previous_size: PhysicalSize<u32>
+    ) -> PhysicalSize<u32> {
+        let Some(AspectRatioLock::ConstrainedResize(ratio)) = self.aspect_ratio else {
+            return size;
+        };
+        if size.width == 0 || size.height == 0 {
+            return size;
+        }
+        let relative_change = |new: u32, old: u32| match old {
+            0 => f32::INFINITY,
+            _ => (new as f32 / old as f32 - 1.0).abs()
+        };
+        let width_leads = relative_change(size.width, previous_size.width) >=
+            relative_change(size.height, previous_size.height);
+        let mut constrained = match width_leads {
+            true => PhysicalSize::new(size.width, height_for_width(size.width, ratio)),
+            false => PhysicalSize::new(width_for_height(size.height, ratio), size.height)
+        };
+
+        // Pull back inside the limits, keeping the ratio by deriving the other dimension again
+        if let Some(max) = self.max_client_size {
+            if constrained.width > max.width {
+                constrained = PhysicalSize::new(max.width, height_for_width(max.width, ratio));
+            }
+            if constrained.height > max.height {
+                constrained = PhysicalSize::new(width_for_height(max.height, ratio), max.height);
+            }
+        }
+        if let Some(min) = self.min_client_size {
+            if constrained.width < min.width {
+                constrained = PhysicalSize::new(min.width, height_for_width(min.width, ratio));
+            }
+            if constrained.height < min.height {
+                constrained = PhysicalSize::new(width_for_height(min.height, ratio), min.height);
+            }
+        }
+        constrained
+    }
+}
+
+fn height_for_width(width: u32, ratio: f32) -> u32 {
+    ((width as f32 / ratio).round() as u32).max(1)
+}
+
+fn width_for_height(height: u32, ratio: f32) -> u32 {
+    ((height as f32 * ratio).round() as u32).max(1)
+}
@@ -0,0 +1,73 @@
+
+use crate::{WindowCommand, MessageProxy};
+
+/// CommandSender struct
+/// Wraps a message proxy for sending commands to the event loop, typically from background
+/// threads. Sending never panics; if the loop has already exited, the unsent commands are given
+/// back to the caller.
+pub struct CommandSender<T: 'static> {
+    proxy: MessageProxy<WindowCommand<T>>
+}
+
+impl<T: 'static> Clone for CommandSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            proxy: self.proxy.clone()
+        }
+    }
+}
+
+impl<T: 'static> CommandSender<T> {
+
+    pub fn new(proxy: MessageProxy<WindowCommand<T>>) -> Self {
+        Self { proxy }
+    }
+
+    /// Send a single command, returning it if the event loop is no longer running
+    pub fn try_send(&self, command: WindowCommand<T>) -> Result<(), WindowCommand<T>> {
+        self.proxy.send_event(command)
+            .map_err(|e| e.0)
+    }
+
+    /// Start a batch of commands, which will be delivered to the event loop with a single wakeup
+    pub fn batch(&self) -> CommandBatch<'_, T> {
+        CommandBatch {
+            sender: self,
+            commands: vec![]
+        }
+    }
+}
+
+/// CommandBatch struct
+/// Collects commands to be sent together; they are processed in the order they were queued
+pub struct CommandBatch<'a, T: 'static> {
+    sender: &'a CommandSender<T>,
+    commands: Vec<WindowCommand<T>>
+}
+
+impl<'a, T: 'static> CommandBatch<'a, T> {
+
+    /// Queue a command to be sent with this batch
+    pub fn push(&mut self, command: WindowCommand<T>) -> &mut Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Send all queued commands, returning them if the event loop is no longer running. Sending
+    /// an empty batch does nothing.
+    pub fn send(self) -> Result<(), Vec<WindowCommand<T>>> {
+        if self.commands.is_empty() {
+            return Ok(());
+        }
+        self.sender.try_send(WindowCommand::Batch(self.commands))
+            .map_err(|command| command.into_commands())
+    }
+}
@@ -0,0 +1,135 @@
+
+use crate::{
+    WindowCommand, FrameTiming, WindowOptions, AspectRatioLock, PhysicalSize, FullscreenMode,
+    WindowConfig, DisplayMode
+};
+use std::time::Duration;
+
+#[test]
+fn nested_batches_flatten_in_order() {
+    let command = WindowCommand::Batch(vec![
+        WindowCommand::custom(1),
+        WindowCommand::Batch(vec![
+            WindowCommand::custom(2),
+            WindowCommand::toggle_fullscreen()
+        ]),
+        WindowCommand::quit()
+    ]);
+    let commands = command.into_commands();
+    assert_eq!(commands.len(), 4);
+    assert!(matches!(commands[0], WindowCommand::Custom(1)));
+    assert!(matches!(commands[1], WindowCommand::Custom(2)));
+    assert!(matches!(commands[2], WindowCommand::ToggleFullscreen));
+    assert!(matches!(commands[3], WindowCommand::RequestClose));
+}
+
+#[test]
+fn single_command_is_not_expanded() {
+    let commands = WindowCommand::<()>::screenshot().into_commands();
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(commands[0], WindowCommand::RequestScreenshot));
+}
+
+#[test]
+fn frame_presents_one_refresh_after_starting() {
+    let refresh_interval = FrameTiming::refresh_interval_from_millihertz(120_000);
+    let timing = FrameTiming::new(8, 3, Duration::from_millis(100), refresh_interval);
+    assert_eq!(timing.refresh_interval.as_micros(), 8333);
+    assert_eq!(timing.predicted_present_time.as_micros(), 108333);
+}
+
+#[test]
+fn unknown_refresh_rate_assumes_sixty_hertz() {
+    assert!(FrameTiming::refresh_interval_from_millihertz(0).is_none());
+    let timing = FrameTiming::new(16, 0, Duration::ZERO, None);
+    assert_eq!(timing.refresh_interval.as_micros(), 16666);
+}
+
+#[test]
+fn constrained_resize_follows_the_dimension_dragged() {
+    let options = WindowOptions {
+        aspect_ratio: Some(AspectRatioLock::ConstrainedResize(2.0)),
+        ..WindowOptions::default()
+    };
+    let previous = PhysicalSize::new(800, 400);
+    assert_eq!(
+        options.constrain_client_size(PhysicalSize::new(1000, 400), previous),
+        PhysicalSize::new(1000, 500));
+    assert_eq!(
+        options.constrain_client_size(PhysicalSize::new(800, 300), previous),
+        PhysicalSize::new(600, 300));
+    assert_eq!(
+        options.constrain_client_size(PhysicalSize::new(600, 300), previous),
+        PhysicalSize::new(600, 300));
+}
+
+#[test]
+fn constrained_resize_stays_within_limits() {
+    let options = WindowOptions {
+        min_client_size: Some(PhysicalSize::new(200, 100)),
+        max_client_size: Some(PhysicalSize::new(1000, 1000)),
+        aspect_ratio: Some(AspectRatioLock::ConstrainedResize(2.0)),
+        hidden: false
+    };
+    let previous = PhysicalSize::new(800, 400);
+    assert_eq!(
+        options.constrain_client_size(PhysicalSize::new(1600, 400), previous),
+        PhysicalSize::new(1000, 500));
+    assert_eq!(
+        options.constrain_client_size(PhysicalSize::new(800, 50), previous),
+        PhysicalSize::new(200, 100));
+}
+
+#[test]
+fn letterbox_leaves_window_shape_alone() {
+    let options = WindowOptions {
+        aspect_ratio: Some(AspectRatioLock::Letterbox(1.5)),
+        ..WindowOptions::default()
+    };
+    let size = PhysicalSize::new(1000, 400);
+    assert_eq!(options.constrain_client_size(size, PhysicalSize::new(900, 600)), size);
+    assert_eq!(options.letterbox_aspect_ratio(false), Some(1.5));
+    assert_eq!(options.render_aspect_ratio(size), 1.5);
+}
+
+#[test]
+fn toggling_fullscreen_leaves_any_fullscreen_mode() {
+    assert_eq!(FullscreenMode::Windowed.toggled(), FullscreenMode::Borderless);
+    assert_eq!(FullscreenMode::Borderless.toggled(), FullscreenMode::Windowed);
+    assert_eq!(FullscreenMode::Exclusive.toggled(), FullscreenMode::Windowed);
+}
+
+#[test]
+fn window_config_keeps_size_limits_as_options() {
+    let config = WindowConfig::new()
+        .with_client_size(1280, 720)
+        .with_min_client_size(640, 360)
+        .with_aspect_ratio(AspectRatioLock::Letterbox(16.0 / 9.0))
+        .with_resizable(false)
+        .with_transparency(true);
+    assert_eq!(config.client_size, Some(PhysicalSize::new(1280, 720)));
+    assert_eq!(config.options.min_client_size, Some(PhysicalSize::new(640, 360)));
+    assert_eq!(config.options.aspect_ratio, Some(AspectRatioLock::Letterbox(16.0 / 9.0)));
+    assert!(!config.resizable && config.decorations && config.transparent);
+
+    let options = WindowOptions { hidden: true, ..WindowOptions::default() };
+    assert_eq!(WindowConfig::from(options), WindowConfig::new().with_hidden(true));
+}
+
+#[test]
+fn requested_display_mode_is_used_only_if_listed() {
+    let mode = |width, height, refresh_rate_millihertz| DisplayMode {
+        size: PhysicalSize::new(width, height),
+        refresh_rate_millihertz,
+        bit_depth: 32
+    };
+    let modes = [mode(1920, 1080, 60000), mode(2560, 1440, 60000), mode(2560, 1440, 144000)];
+    assert_eq!(DisplayMode::choose(&modes, None), Some(mode(2560, 1440, 144000)));
+    assert_eq!(
+        DisplayMode::choose(&modes, Some(mode(1920, 1080, 60000))),
+        Some(mode(1920, 1080, 60000)));
+    assert_eq!(
+        DisplayMode::choose(&modes, Some(mode(1280, 720, 60000))),
+        Some(mode(2560, 1440, 144000)));
+    assert_eq!(DisplayMode::choose(&[], None), None);
+}
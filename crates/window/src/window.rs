@@ -1,30 +1,321 @@
 
 use crate::WindowEventLooper;
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, HasRawDisplayHandle, RawDisplayHandle};
-use winit::window::WindowId;
+use winit::window::{CursorGrabMode, Fullscreen, Icon, WindowId};
+use crate::{FrameTiming, WindowOptions, WindowConfig, MonitorInfo, DisplayMode};
+use crate::monitor::{describe_monitors, choose_video_mode};
+use winit::monitor::MonitorHandle;
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use error::EngineError;
+use std::cell::Cell;
 use std::fmt::Debug;
+use std::time::Duration;
+
+/// FullscreenMode enum
+/// How the window occupies its monitor. Exclusive fullscreen takes over the monitor at its
+/// largest video mode, which some platforms don't allow; borderless fullscreen is used there
+/// instead.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FullscreenMode {
+    Windowed,
+    Borderless,
+    Exclusive
+}
+
+impl FullscreenMode {
+
+    /// The mode switched to by toggling fullscreen from this one
+    pub fn toggled(self) -> Self {
+        match self {
+            FullscreenMode::Windowed => FullscreenMode::Borderless,
+            FullscreenMode::Borderless | FullscreenMode::Exclusive => FullscreenMode::Windowed
+        }
+    }
+}
+
+/// CursorMode enum
+/// How the mouse cursor behaves over the window. Locked hides the cursor and keeps it from
+/// leaving the window, so that mouse movement can steer a first-person camera; the engine then
+/// reports relative motion rather than cursor positions.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CursorMode {
+    Normal,
+    Hidden,
+    Locked
+}
 
 pub struct Window {
-    window: winit::window::Window
+    window: winit::window::Window,
+    options: WindowOptions,
+    transparent: bool,
+    cursor_mode: Cell<CursorMode>
 }
 
 impl Window {
 
     pub fn new<M: 'static + Send + Debug>(app_title: &str, looper: &WindowEventLooper<M>) -> Self {
-        let window = winit::window::WindowBuilder::new()
+        Self::new_with_config(app_title, looper, WindowConfig::default()).unwrap()
+    }
+
+    /// Create a new window, with limits on the size and shape of its client area
+    pub fn new_with_options<M: 'static + Send + Debug>(
+        app_title: &str,
+        looper: &WindowEventLooper<M>,
+        options: WindowOptions
+    ) -> Self {
+        Self::new_with_config(app_title, looper, WindowConfig::from(options)).unwrap()
+    }
+
+    /// Create a new window with the given attributes. Fails if the icon's texels don't number
+    /// its width times height, or if the platform can't create the window.
+    pub fn new_with_config<M: 'static + Send + Debug>(
+        app_title: &str,
+        looper: &WindowEventLooper<M>,
+        config: WindowConfig
+    ) -> Result<Self, EngineError> {
+        let options = config.options;
+        let mut builder = winit::window::WindowBuilder::new()
             .with_title(app_title)
+            .with_visible(!options.hidden)
+            .with_resizable(config.resizable)
+            .with_decorations(config.decorations)
+            .with_transparent(config.transparent);
+        if let Some(client_size) = config.client_size {
+            builder = builder.with_inner_size(client_size);
+        }
+        if let Some(min_client_size) = options.min_client_size {
+            builder = builder.with_min_inner_size(min_client_size);
+        }
+        if let Some(max_client_size) = options.max_client_size {
+            builder = builder.with_max_inner_size(max_client_size);
+        }
+        if let Some(icon) = config.icon {
+            let icon = Icon::from_rgba(icon.rgba, icon.width, icon.height)
+                .map_err(|e| EngineError::UserError(format!("Window icon not usable: {:?}", e)))?;
+            builder = builder.with_window_icon(Some(icon));
+        }
+        let window = builder
             .build(&looper.event_loop)
-            .unwrap();
-        Self { window }
+            .map_err(|e| EngineError::OpFailed(format!("Error creating window: {:?}", e)))?;
+        let window = Self {
+            window,
+            options,
+            transparent: config.transparent,
+            cursor_mode: Cell::new(CursorMode::Normal)
+        };
+        window.enforce_aspect_ratio(window.window.inner_size(), window.window.inner_size());
+        Ok(window)
+    }
+
+    pub fn get_options(&self) -> &WindowOptions {
+        &self.options
+    }
+
+    /// Whether the window was created transparent, so that rendering should blend with what's
+    /// behind it
+    pub fn is_transparent(&self) -> bool {
+        self.transparent
+    }
+
+    /// The ratio that rendering should currently be letterboxed to, if any
+    pub fn get_letterbox_aspect_ratio(&self) -> Option<f32> {
+        self.options.letterbox_aspect_ratio(self.window.fullscreen().is_some())
+    }
+
+    /// Aspect ratio of the area rendered into, for a client area of the given size; this is the
+    /// locked ratio if there is one
+    pub fn get_render_aspect_ratio(&self, client_size: PhysicalSize<u32>) -> f32 {
+        self.options.render_aspect_ratio(client_size)
+    }
+
+    /// Snap the client area back to the locked aspect ratio if resizing is constrained and the
+    /// new size doesn't fit it. Returns true if a resize was requested, in which case another
+    /// resize event will follow with the corrected size.
+    pub fn enforce_aspect_ratio(
+        &self,
+        size: PhysicalSize<u32>,
+        previous_size: PhysicalSize<u32>
+    ) -> bool {
+        if self.window.fullscreen().is_some() {
+            return false;
+        }
+        let constrained = self.options.constrain_client_size(size, previous_size);
+        if constrained == size {
+            return false;
+        }
+        self.window.set_inner_size(constrained);
+        true
     }
 
     pub fn get_window_id(&self) -> WindowId {
         self.window.id()
     }
 
+    /// Refresh interval of the monitor the window is currently on, if it can be determined
+    pub fn get_refresh_interval(&self) -> Option<Duration> {
+        self.window.current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .and_then(FrameTiming::refresh_interval_from_millihertz)
+    }
+
     pub fn request_redraw(&self) {
         self.window.request_redraw();
     }
+
+    /// Current size of the client area in physical pixels, which the surface is rendered at
+    pub fn get_client_size(&self) -> PhysicalSize<u32> {
+        self.window.inner_size()
+    }
+
+    /// Current size of the client area in logical pixels, which are physical pixels divided by
+    /// the scale factor; UI laid out in these stays the same apparent size on HiDPI monitors
+    pub fn get_logical_client_size(&self) -> LogicalSize<f64> {
+        self.window.inner_size().to_logical(self.get_scale_factor())
+    }
+
+    /// The number of physical pixels per logical pixel on the monitor the window is on
+    pub fn get_scale_factor(&self) -> f64 {
+        self.window.scale_factor()
+    }
+
+    /// How the window currently occupies its monitor
+    pub fn get_fullscreen_mode(&self) -> FullscreenMode {
+        match self.window.fullscreen() {
+            None => FullscreenMode::Windowed,
+            Some(Fullscreen::Borderless(_)) => FullscreenMode::Borderless,
+            Some(Fullscreen::Exclusive(_)) => FullscreenMode::Exclusive
+        }
+    }
+
+    /// Switch to the given mode on the current monitor. Returns the mode switched to, which is
+    /// borderless if exclusive was requested but the monitor lists no video modes, or None if
+    /// the window was already in that mode.
+    pub fn set_fullscreen_mode(&self, mode: FullscreenMode) -> Option<FullscreenMode> {
+        let (mode, fullscreen) = Self::fullscreen_on(mode, self.window.current_monitor(), None);
+        if mode == self.get_fullscreen_mode() {
+            return None;
+        }
+        self.window.set_fullscreen(fullscreen);
+        Some(mode)
+    }
+
+    /// Switch to the given mode on the monitor at the given index of get_monitors, using the
+    /// given display mode for exclusive fullscreen if the monitor has it, or otherwise its best
+    /// mode. Windowed mode moves the window onto the monitor. Returns the mode switched to, which
+    /// is borderless where exclusive was requested but the monitor lists no video modes, or an
+    /// error if there is no such monitor.
+    pub fn set_fullscreen_on_monitor(
+        &self,
+        mode: FullscreenMode,
+        monitor_index: usize,
+        display_mode: Option<DisplayMode>
+    ) -> Result<FullscreenMode, EngineError> {
+        let monitor = self.find_monitor(monitor_index)?;
+        let (mode, fullscreen) = Self::fullscreen_on(mode, Some(monitor.clone()), display_mode);
+        self.window.set_fullscreen(fullscreen);
+        if mode == FullscreenMode::Windowed {
+            self.center_on(&monitor);
+        }
+        Ok(mode)
+    }
+
+    /// The winit fullscreen setting for a mode on the given monitor, along with the mode it
+    /// gives, which is borderless where exclusive was requested but no video mode is available
+    fn fullscreen_on(
+        mode: FullscreenMode,
+        monitor: Option<MonitorHandle>,
+        display_mode: Option<DisplayMode>
+    ) -> (FullscreenMode, Option<Fullscreen>) {
+        match mode {
+            FullscreenMode::Windowed => (FullscreenMode::Windowed, None),
+            FullscreenMode::Borderless => {
+                (FullscreenMode::Borderless, Some(Fullscreen::Borderless(monitor)))
+            },
+            FullscreenMode::Exclusive => {
+                let video_mode = monitor.as_ref()
+                    .and_then(|monitor| choose_video_mode(monitor, display_mode));
+                match video_mode {
+                    Some(video_mode) => {
+                        (FullscreenMode::Exclusive, Some(Fullscreen::Exclusive(video_mode)))
+                    },
+                    None => (FullscreenMode::Borderless, Some(Fullscreen::Borderless(monitor)))
+                }
+            }
+        }
+    }
+
+    /// List the monitors connected to the system
+    pub fn get_monitors(&self) -> Vec<MonitorInfo> {
+        describe_monitors(self.window.available_monitors(), self.window.primary_monitor())
+    }
+
+    /// Index in get_monitors of the monitor the window is currently on, if it can be determined
+    pub fn get_current_monitor_index(&self) -> Option<usize> {
+        let current_monitor = self.window.current_monitor()?;
+        self.window.available_monitors().position(|monitor| monitor == current_monitor)
+    }
+
+    /// Move the window, if windowed, to the centre of the monitor at the given index of
+    /// get_monitors. Fails if there is no such monitor.
+    pub fn move_to_monitor(&self, monitor_index: usize) -> Result<(), EngineError> {
+        let monitor = self.find_monitor(monitor_index)?;
+        self.center_on(&monitor);
+        Ok(())
+    }
+
+    fn find_monitor(&self, monitor_index: usize) -> Result<MonitorHandle, EngineError> {
+        self.window.available_monitors()
+            .nth(monitor_index)
+            .ok_or_else(|| EngineError::UserError(format!("No monitor {}", monitor_index)))
+    }
+
+    fn center_on(&self, monitor: &MonitorHandle) {
+        let monitor_position = monitor.position();
+        let monitor_size = monitor.size();
+        let window_size = self.window.outer_size();
+        let x = monitor_position.x +
+            (monitor_size.width as i32 - window_size.width as i32).max(0) / 2;
+        let y = monitor_position.y +
+            (monitor_size.height as i32 - window_size.height as i32).max(0) / 2;
+        self.window.set_outer_position(PhysicalPosition::new(x, y));
+    }
+
+    /// The cursor mode last set
+    pub fn get_cursor_mode(&self) -> CursorMode {
+        self.cursor_mode.get()
+    }
+
+    /// Show, hide or lock the cursor. Platforms that can't lock the cursor in place confine it
+    /// to the window instead; if neither is possible, the cursor is only hidden and an error is
+    /// returned, though the mode is still recorded.
+    pub fn set_cursor_mode(&self, mode: CursorMode) -> Result<(), EngineError> {
+        let grab_result = match mode {
+            CursorMode::Normal | CursorMode::Hidden => {
+                self.window.set_cursor_grab(CursorGrabMode::None)
+            },
+            CursorMode::Locked => self.window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        };
+        self.window.set_cursor_visible(mode == CursorMode::Normal);
+        self.cursor_mode.set(mode);
+        grab_result.map_err(|e| EngineError::Compatibility(
+            format!("Could not set cursor grab for {:?} mode: {:?}", mode, e)))
+    }
+
+    /// Allow input methods, such as for composing CJK text, while a text field has focus. Their
+    /// candidate window is placed near the given position in the client area where supported.
+    pub fn set_text_input(&self, enabled: bool, position: PhysicalPosition<i32>) {
+        self.window.set_ime_allowed(enabled);
+        if enabled {
+            self.window.set_ime_position(position);
+        }
+    }
+
+    /// Switch between windowed mode and borderless fullscreen on the current monitor, returning
+    /// the mode switched to
+    pub fn toggle_fullscreen(&self) -> Option<FullscreenMode> {
+        self.set_fullscreen_mode(self.get_fullscreen_mode().toggled())
+    }
 }
 
 unsafe impl HasRawDisplayHandle for Window {
@@ -0,0 +1,16 @@
+use engine::{SimpleConfig, run_simple};
+use cgmath::{Matrix4, Rad, Vector3};
+
+/// Spin the stock model while bobbing it up and down, using a reversed-Z depth buffer
+fn main() {
+    let config = SimpleConfig {
+        app_title: "Simple App",
+        reversed_z: true,
+        ..SimpleConfig::default()
+    };
+    run_simple(config, |frame| {
+        let height = 0.25 * (2.0 * frame.total_time_secs).sin() as f32;
+        let spin = Matrix4::from_angle_y(Rad(frame.total_time_secs as f32));
+        frame.model_matrix = Matrix4::from_translation(Vector3::new(0.0, height, 0.0)) * spin;
+    });
+}
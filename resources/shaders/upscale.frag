@@ -0,0 +1,13 @@
+#version 450
+
+// Sample the image rendered at the internal resolution, filtered by the sampler
+
+layout (location = 0) in vec2 tex_coord;
+
+layout (set = 0, binding = 0) uniform sampler2D source;
+
+layout (location = 0) out vec4 color;
+
+void main() {
+    color = texture(source, tex_coord);
+}
@@ -0,0 +1,11 @@
+#version 450
+
+// Cover the render area with a single triangle generated from the vertex index, with texture
+// coordinates running from 0 to 1 across the visible part of it
+
+layout (location = 0) out vec2 tex_coord;
+
+void main() {
+    tex_coord = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(tex_coord * 2.0 - 1.0, 0.0, 1.0);
+}
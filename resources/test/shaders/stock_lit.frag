@@ -0,0 +1,62 @@
+#version 450
+
+// Textured surface lit by the point lights binned into the cluster holding the fragment. The
+// light list and cluster layouts must match ClusteredLights in the renderer.
+
+#define MAX_LIGHTS_PER_CLUSTER 31
+#define CLUSTER_STRIDE (MAX_LIGHTS_PER_CLUSTER + 1)
+
+const vec3 AMBIENT_LIGHT = vec3(0.15);
+
+struct PointLight {
+    vec4 position_radius;
+    vec4 color_intensity;
+};
+
+layout (location = 0) in vec2 v_tex_coord;
+layout (location = 1) in vec3 v_view_position;
+layout (location = 2) in vec3 v_view_normal;
+
+layout (set = 0, binding = 1) uniform sampler2D s_texture;
+
+layout (std430, set = 0, binding = 8) readonly buffer LightList {
+    vec4 projection;
+    uvec4 grid;
+    PointLight lights[];
+} light_list;
+
+layout (std430, set = 0, binding = 9) readonly buffer LightClusters {
+    uint data[];
+} clusters;
+
+layout (location = 0) out vec4 o_color;
+
+uint cluster_index(vec3 position) {
+    uvec3 grid = light_list.grid.xyz;
+    float near = light_list.projection.z;
+    float far = light_list.projection.w;
+    vec2 ndc = light_list.projection.xy * position.xy / position.z;
+    uvec2 tile = uvec2(clamp((ndc * 0.5 + 0.5) * vec2(grid.xy), vec2(0.0), vec2(grid.xy - 1)));
+    float slice = floor(log(position.z / near) / log(far / near) * float(grid.z));
+    uint depth_slice = uint(clamp(slice, 0.0, float(grid.z - 1)));
+    return tile.x + tile.y * grid.x + depth_slice * grid.x * grid.y;
+}
+
+void main() {
+    vec4 surface = texture(s_texture, v_tex_coord);
+    vec3 normal = normalize(v_view_normal);
+    vec3 lighting = AMBIENT_LIGHT;
+
+    uint base = cluster_index(v_view_position) * CLUSTER_STRIDE;
+    uint count = clusters.data[base];
+    for (uint i = 0; i < count; i++) {
+        PointLight light = light_list.lights[clusters.data[base + 1 + i]];
+        vec3 to_light = light.position_radius.xyz - v_view_position;
+        float distance = length(to_light);
+        float falloff = clamp(1.0 - distance / light.position_radius.w, 0.0, 1.0);
+        float diffuse = max(dot(normal, to_light / max(distance, 0.0001)), 0.0);
+        lighting += light.color_intensity.rgb * light.color_intensity.a *
+            falloff * falloff * diffuse;
+    }
+    o_color = vec4(surface.rgb * lighting, surface.a);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+layout (location = 0) in vec3 a_vertex;
+layout (location = 1) in vec3 a_normal;
+layout (location = 2) in vec2 a_tex_coord;
+
+layout (set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 mvp_matrix;
+    mat4 view_model_matrix;
+} ubo;
+
+layout (location = 0) out vec2 v_tex_coord;
+layout (location = 1) out vec3 v_view_position;
+layout (location = 2) out vec3 v_view_normal;
+
+void main() {
+    v_tex_coord = a_tex_coord;
+    v_view_position = (ubo.view_model_matrix * vec4(a_vertex, 1.0)).xyz;
+    v_view_normal = mat3(ubo.view_model_matrix) * a_normal;
+    gl_Position = ubo.mvp_matrix * vec4(a_vertex, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 450
+
+layout (input_attachment_index = 0, set = 0, binding = 3) uniform subpassInput i_color;
+
+layout (location = 0) out vec4 o_color;
+
+void main() {
+    o_color = subpassLoad(i_color);
+}